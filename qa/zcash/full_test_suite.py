@@ -154,12 +154,24 @@ def rust_test():
 
     rust_env = os.environ.copy()
     rust_env['RUSTC'] = os.path.join(arch_dir, 'native', 'bin', 'rustc')
+    # Also run the tests of the embedded Sapling verifying keys against the
+    # fetched parameters, if they are present.
+    test_args = []
+    if 'ZCASH_PARAMS_DIR' not in rust_env:
+        params_dir = os.path.expanduser(
+            '~/Library/Application Support/ZcashParams'
+            if sys.platform == 'darwin' else '~/.zcash-params')
+        if all(os.path.isfile(os.path.join(params_dir, f))
+               for f in ['sapling-spend.params', 'sapling-output.params']):
+            rust_env['ZCASH_PARAMS_DIR'] = params_dir
+    if 'ZCASH_PARAMS_DIR' in rust_env:
+        test_args = ['--', '--include-ignored']
     return subprocess.call([
         os.path.join(arch_dir, 'native', 'bin', 'cargo'),
         'test',
         '--manifest-path',
         os.path.join(REPOROOT, 'Cargo.toml'),
-    ], env=rust_env) == 0
+    ] + test_args, env=rust_env) == 0
 
 #
 # Tests
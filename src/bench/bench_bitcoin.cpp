@@ -39,7 +39,9 @@ main(int argc, char** argv)
         reinterpret_cast<const codeunit*>(sapling_output_str.c_str()),
         sapling_output_str.length(),
         reinterpret_cast<const codeunit*>(sprout_groth16_str.c_str()),
-        sprout_groth16_str.length()
+        sprout_groth16_str.length(),
+        nullptr,
+        false
     );
 
     benchmark::BenchRunner::RunAll();
@@ -0,0 +1,45 @@
+// Copyright (c) 2022 The Zcash developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or https://www.opensource.org/licenses/mit-license.php .
+
+#include "bench.h"
+
+#include "consensus/upgrades.h"
+#include "zcash/History.hpp"
+
+#include <array>
+#include <vector>
+
+static HistoryNode BenchLeaf(uint32_t height)
+{
+    return libzcash::NewV1Leaf(
+        uint256(), height, height, uint256(), uint256(), height, 0);
+}
+
+// Appends a leaf to a tree with a single peak, which is the common case for the
+// small trees at the start of each epoch.
+static void MMRAppendSmallTree(benchmark::State& state)
+{
+    uint32_t epochId = NetworkUpgradeInfo[Consensus::UPGRADE_HEARTWOOD].nBranchId;
+    std::vector<uint32_t> indices = {0};
+    std::vector<HistoryEntry> entries = {libzcash::LeafToEntry(BenchLeaf(1))};
+    std::vector<size_t> lengths = {libzcash::EntryLength(entries[0], epochId)};
+    HistoryNode leaf = BenchLeaf(2);
+
+    uint256 root;
+    std::array<HistoryNode, 32> appendBuf = {};
+    while (state.KeepRunning()) {
+        librustzcash_mmr_append(
+            epochId,
+            1,
+            indices.data(),
+            entries.data(),
+            lengths.data(),
+            indices.size(),
+            &leaf,
+            root.begin(),
+            appendBuf.data());
+    }
+}
+
+BENCHMARK(MMRAppendSmallTree);
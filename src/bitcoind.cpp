@@ -16,6 +16,9 @@
 #include "httpserver.h"
 #include "httprpc.h"
 
+#include <rust/address.h>
+#include <rust/test_vectors.h>
+
 #include <boost/algorithm/string/predicate.hpp>
 #include <boost/thread.hpp>
 
@@ -98,6 +101,23 @@ bool AppInit(int argc, char* argv[])
         return true;
     }
 
+    // Export test vectors for other implementations, without starting the node.
+    if (mapArgs.count("-export-test-vectors"))
+    {
+        auto kind = GetArg("-export-test-vectors", "");
+        auto params = GetArg("-export-test-vectors-params", "{}");
+        char* json = zcash_export_test_vectors(kind.c_str(), params.c_str());
+        if (json == nullptr) {
+            fprintf(stderr,
+                "Error: -export-test-vectors must be one of zip244, history_tree, and "
+                "-export-test-vectors-params a JSON object of the integers seed and count.\n");
+            return false;
+        }
+        fprintf(stdout, "%s", json);
+        zcash_address_string_free(json);
+        return true;
+    }
+
     // Handle setting of allowed-deprecated features as early as possible
     // so that it's possible for other initialization steps to respect them.
     auto deprecationError = SetAllowedDeprecatedFeaturesFromCLIArgs();
@@ -7,6 +7,7 @@
 
 #include "memusage.h"
 #include "random.h"
+#include "util.h"
 #include "version.h"
 #include "policy/fees.h"
 
@@ -389,6 +390,17 @@ static inline uint32_t altitude(uint32_t n) {
     return floor_log2(n + 1) - 1;
 }
 
+// Returns the lengths of the encodings in the history tree entries, so that
+// librustzcash decodes each from exactly its encoding.
+static std::vector<size_t> HistoryEntryLengths(uint32_t epochId, const std::vector<HistoryEntry> &entries) {
+    std::vector<size_t> lengths;
+    lengths.reserve(entries.size());
+    for (const auto &entry : entries) {
+        lengths.push_back(libzcash::EntryLength(entry, epochId));
+    }
+    return lengths;
+}
+
 uint32_t CCoinsViewCache::PreloadHistoryTree(uint32_t epochId, bool extra, std::vector<HistoryEntry> &entries, std::vector<uint32_t> &entry_indices) {
     auto treeLength = GetHistoryLength(epochId);
 
@@ -538,6 +550,7 @@ void CCoinsViewCache::PushHistoryNode(uint32_t epochId, const HistoryNode node)
     std::vector<uint32_t> entry_indices;
 
     PreloadHistoryTree(epochId, false, entries, entry_indices);
+    auto entry_lengths = HistoryEntryLengths(epochId, entries);
 
     uint256 newRoot;
     std::array<HistoryNode, 32> appendBuf = {};
@@ -547,6 +560,7 @@ void CCoinsViewCache::PushHistoryNode(uint32_t epochId, const HistoryNode node)
         historyCache.length,
         entry_indices.data(),
         entries.data(),
+        entry_lengths.data(),
         entry_indices.size(),
         &node,
         newRoot.begin(),
@@ -560,7 +574,7 @@ void CCoinsViewCache::PushHistoryNode(uint32_t epochId, const HistoryNode node)
     historyCache.root = newRoot;
 }
 
-void CCoinsViewCache::PopHistoryNode(uint32_t epochId) {
+bool CCoinsViewCache::PopHistoryNode(uint32_t epochId) {
     HistoryCache& historyCache = SelectHistoryCache(epochId);
     uint256 newRoot;
 
@@ -584,13 +598,13 @@ void CCoinsViewCache::PopHistoryNode(uint32_t epochId) {
             // Just resetting tree to empty
             historyCache.Truncate(0);
             historyCache.root = uint256();
-            return;
+            return true;
         }
         case 2:
         {
             // - A tree with one leaf has length 1.
             // - A tree with two leaves has length 3.
-            throw std::runtime_error("a history tree cannot have two nodes");
+            return error("PopHistoryNode: a history tree cannot have two nodes");
         }
         case 3:
         {
@@ -603,11 +617,11 @@ void CCoinsViewCache::PopHistoryNode(uint32_t epochId) {
                 &tmpHistoryRoot,
                 newRoot.begin()
             ) != 0) {
-                throw std::runtime_error("hashing node failed");
+                return error("PopHistoryNode: hashing node failed");
             }
             historyCache.Truncate(1);
             historyCache.root = newRoot;
-            return;
+            return true;
         }
         default:
         {
@@ -616,20 +630,27 @@ void CCoinsViewCache::PopHistoryNode(uint32_t epochId) {
             std::vector<uint32_t> entry_indices;
 
             uint32_t peak_count = PreloadHistoryTree(epochId, true, entries, entry_indices);
+            auto entry_lengths = HistoryEntryLengths(epochId, entries);
 
             uint32_t numberOfDeletes = librustzcash_mmr_delete(
                 epochId,
                 historyCache.length,
                 entry_indices.data(),
                 entries.data(),
+                entry_lengths.data(),
                 peak_count,
                 entries.size() - peak_count,
                 newRoot.begin()
             );
+            if (numberOfDeletes == MMR_DELETE_MISSING_EXTRAS) {
+                return error("PopHistoryNode: history tree delete is missing extra nodes");
+            } else if (numberOfDeletes == 0) {
+                return error("PopHistoryNode: history tree delete failed");
+            }
 
             historyCache.Truncate(historyCache.length - numberOfDeletes);
             historyCache.root = newRoot;
-            return;
+            return true;
         }
     }
 }
@@ -559,8 +559,9 @@ public:
     // Push MMR node history at the end of the history tree
     void PushHistoryNode(uint32_t epochId, const HistoryNode node);
 
-    // Pop MMR node history from the end of the history tree
-    void PopHistoryNode(uint32_t epochId);
+    // Pop MMR node history from the end of the history tree. Returns false,
+    // leaving the tree unchanged, if the tree could not be truncated.
+    bool PopHistoryNode(uint32_t epochId);
 
     /**
      * Return a pointer to CCoins in the cache, or NULL if not found. This is
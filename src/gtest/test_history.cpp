@@ -4,6 +4,9 @@
 #include "utiltest.h"
 #include "zcash/History.hpp"
 
+#include <rust/address.h>
+#include <rust/test_harness.h>
+
 // Fake an empty view
 class FakeCoinsViewDB : public CCoinsView {
 public:
@@ -107,12 +110,43 @@ TEST(History, Smoky) {
     view.PushHistoryNode(epochId, getLeafN(5));
     EXPECT_EQ(view.GetHistoryLength(epochId), 8);
 
-    view.PopHistoryNode(epochId);
+    EXPECT_TRUE(view.PopHistoryNode(epochId));
 
     EXPECT_EQ(view.GetHistoryLength(epochId), 7);
     EXPECT_EQ(h4Root, view.GetHistoryRoot(epochId));
 }
 
+TEST(History, EntryLength) {
+    const auto heartwood = NetworkUpgradeInfo[Consensus::UPGRADE_HEARTWOOD].nBranchId;
+    const auto nu5 = NetworkUpgradeInfo[Consensus::UPGRADE_NU5].nBranchId;
+
+    // 144 bytes of fixed-length fields, two 3-byte heights, and a 1-byte
+    // Sapling transaction count.
+    HistoryNode v1Leaf = libzcash::NewV1Leaf(uint256(), 1, 1, uint256(), uint256(), 1000, 3);
+    EXPECT_EQ(libzcash::EntryLength(libzcash::LeafToEntry(v1Leaf), heartwood), 1 + 151);
+    EXPECT_EQ(libzcash::EntryLength(libzcash::NodeToEntry(v1Leaf, 0, 1), heartwood), 9 + 151);
+
+    // V2 nodes add two Orchard roots, and here a 5-byte Orchard transaction
+    // count.
+    HistoryNode v2Leaf = libzcash::NewV2Leaf(
+        uint256(), 1, 1, uint256(), uint256(), uint256(), 1000, 3, 70000);
+    EXPECT_EQ(libzcash::EntryLength(libzcash::LeafToEntry(v2Leaf), nu5), 1 + 151 + 69);
+
+    // The entries are decoded from exactly these lengths.
+    std::vector<uint32_t> indices = {0};
+    std::vector<HistoryEntry> entries = {libzcash::LeafToEntry(v1Leaf)};
+    HistoryNode next = libzcash::NewV1Leaf(uint256(), 2, 1, uint256(), uint256(), 1001, 3);
+    uint256 root;
+    std::array<HistoryNode, 32> appendBuf = {};
+    auto append = [&](size_t length) {
+        return librustzcash_mmr_append(
+            heartwood, 1, indices.data(), entries.data(), &length, indices.size(),
+            &next, root.begin(), appendBuf.data());
+    };
+    EXPECT_EQ(append(152), 2);
+    EXPECT_EQ(append(151), 0);
+    EXPECT_EQ(append(153), 0);
+}
 
 TEST(History, EpochBoundaries) {
     // Fake an empty view
@@ -159,23 +193,23 @@ TEST(History, EpochBoundaries) {
     EXPECT_EQ(view.GetHistoryLength(epoch2), 4);
 
     // Rolling epoch back to 1
-    view.PopHistoryNode(epoch2);
+    EXPECT_TRUE(view.PopHistoryNode(epoch2));
     EXPECT_EQ(view.GetHistoryLength(epoch2), 3);
 
-    view.PopHistoryNode(epoch2);
+    EXPECT_TRUE(view.PopHistoryNode(epoch2));
     EXPECT_EQ(view.GetHistoryLength(epoch2), 1);
     EXPECT_EQ(view.GetHistoryLength(epoch1), 8);
 
     // And even rolling epoch 1 back a bit
-    view.PopHistoryNode(epoch1);
+    EXPECT_TRUE(view.PopHistoryNode(epoch1));
     EXPECT_EQ(view.GetHistoryLength(epoch1), 7);
 
     // And also rolling epoch 2 back to 0
-    view.PopHistoryNode(epoch2);
+    EXPECT_TRUE(view.PopHistoryNode(epoch2));
     EXPECT_EQ(view.GetHistoryLength(epoch2), 0);
 
     // Trying to truncate an empty tree is a no-op
-    view.PopHistoryNode(epoch2);
+    EXPECT_TRUE(view.PopHistoryNode(epoch2));
     EXPECT_EQ(view.GetHistoryLength(epoch2), 0);
 
 }
@@ -213,3 +247,23 @@ TEST(History, GarbageMemoryHash) {
     // Check history root and garbage history root are equal
     EXPECT_EQ(historyRoot, historyRootGarbage);
 }
+
+TEST(History, RandomOperations) {
+    for (auto upgrade : {Consensus::UPGRADE_HEARTWOOD, Consensus::UPGRADE_NU5}) {
+        const auto consensusBranchId = NetworkUpgradeInfo[upgrade].nBranchId;
+        for (unsigned char seedByte : {1, 2, 3}) {
+            unsigned char seed[32];
+            memset(seed, seedByte, sizeof(seed));
+
+            auto report = zcash_test_harness_mmr_random_ops(seed, 2000, consensusBranchId);
+            ASSERT_NE(report, nullptr);
+            uint32_t opIndex;
+            if (zcash_test_harness_mmr_random_ops_diverged(report, &opIndex)) {
+                char* log = zcash_test_harness_mmr_random_ops_log(report);
+                ADD_FAILURE() << "Diverged at operation " << opIndex << "\n" << log;
+                zcash_address_string_free(log);
+            }
+            zcash_test_harness_mmr_random_ops_free(report);
+        }
+    }
+}
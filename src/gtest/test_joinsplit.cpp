@@ -4,6 +4,7 @@
 
 
 #include "zcash/prf.h"
+#include "main.h"
 #include "util.h"
 #include "streams.h"
 #include "version.h"
@@ -20,6 +21,7 @@
 
 #include <array>
 
+#include <librustzcash.h>
 #include <rust/ed25519/types.h>
 
 using namespace libzcash;
@@ -588,6 +590,33 @@ TEST(Joinsplit, NoteClass)
     ASSERT_EQ(note.a_pk, clone.a_pk);
 }
 
+TEST(Joinsplit, RustNoteCommitmentAndNullifier)
+{
+    // The note of the `note_commitment_and_nullifier` Rust test, whose pinned
+    // commitment and nullifier are checked here against `SproutNote`.
+    uint252 a_sk(uint256(ParseHex("0e841645d916b255c0d5244caab4266aadfa8701bd16fc740f602d681687daf6")));
+    uint256 rho(ParseHex("0ddbc6c1d81a078b803040a615f91362642e985d2737a945535b86da2681952f"));
+    uint256 r(ParseHex("454349e422f05297191ead13e21d3db520e5abef52055e4964b82fb213f593a1"));
+    SproutNote note(PRF_addr_a_pk(a_sk), 1945813, rho, r);
+    EXPECT_EQ(note.a_pk, uint256(ParseHex("b091b295b63bb4e62899abe934a0d189bc7cedb51577a509962cfc2ba8853a3e")));
+    EXPECT_EQ(note.cm(), uint256(ParseHex("e7d4e72f61b00dd725d9c18c93e91388f0e2f8f70166017097552e9c947246bb")));
+    EXPECT_EQ(note.nullifier(a_sk), uint256(ParseHex("1d682c0e9ddbaf73ac88292b747312afbc05aa8918d9154caea2d0eb5cfc29cb")));
+
+    for (size_t i = 0; i < 100; i++) {
+        uint252 a_sk = libzcash::random_uint252();
+        SproutNote note(PRF_addr_a_pk(a_sk), GetRand(MAX_MONEY), random_uint256(), random_uint256());
+
+        uint256 cm;
+        librustzcash_sprout_note_commitment(
+            note.a_pk.begin(), note.value(), note.rho.begin(), note.r.begin(), cm.begin());
+        EXPECT_EQ(cm, note.cm());
+
+        uint256 nf;
+        librustzcash_sprout_nullifier(a_sk.begin(), note.rho.begin(), nf.begin());
+        EXPECT_EQ(nf, note.nullifier(a_sk));
+    }
+}
+
 TEST(Joinsplit, BasicJoinsplitVerification)
 {
     LoadProofParameters();
@@ -658,3 +687,98 @@ TEST(Joinsplit, BasicJoinsplitVerification)
     }
 
 }
+
+TEST(Joinsplit, BatchVerificationFindsInvalidJoinSplit)
+{
+    LoadProofParameters();
+
+    SproutMerkleTree merkleTree;
+    auto k = libzcash::SproutSpendingKey::random();
+    auto addr = k.address();
+    libzcash::SproutNote note(addr.a_pk, 100, uint256(), uint256());
+    merkleTree.append(note.cm());
+    uint256 rt = merkleTree.root();
+    auto witness = merkleTree.witness();
+
+    Ed25519VerificationKey joinSplitPubKey;
+    auto makeJoinSplit = [&](CAmount value1) {
+        std::array<libzcash::JSInput, ZC_NUM_JS_INPUTS> inputs = {
+            libzcash::JSInput(witness, note, k),
+            libzcash::JSInput()
+        };
+        std::array<libzcash::JSOutput, ZC_NUM_JS_OUTPUTS> outputs = {
+            libzcash::JSOutput(addr, value1),
+            libzcash::JSOutput(addr, 100 - value1)
+        };
+        return JSDescriptionInfo(joinSplitPubKey, rt, inputs, outputs, 0, 0).BuildDeterministic();
+    };
+    auto js1 = makeJoinSplit(50);
+    auto js2 = makeJoinSplit(30);
+    auto js3 = makeJoinSplit(10);
+
+    // A well-formed proof for a different statement.
+    auto invalid = js3;
+    invalid.proof = js1.proof;
+
+    auto makeTx = [&](std::vector<JSDescription> joinsplits) {
+        CMutableTransaction mtx;
+        mtx.fOverwintered = true;
+        mtx.nVersionGroupId = SAPLING_VERSION_GROUP_ID;
+        mtx.nVersion = SAPLING_TX_VERSION;
+        mtx.vJoinSplit = joinsplits;
+        mtx.joinSplitPubKey = joinSplitPubKey;
+        return CTransaction(mtx);
+    };
+
+    // Queues the JoinSplits in block order, as CheckBlock does.
+    auto queueBlock = [](ProofVerifier& verifier, const CBlock& block) {
+        for (const CTransaction& tx : block.vtx) {
+            for (const JSDescription& jsdesc : tx.vJoinSplit) {
+                ASSERT_TRUE(verifier.VerifySprout(jsdesc, tx.joinSplitPubKey));
+            }
+        }
+    };
+
+    {
+        CBlock block;
+        block.vtx.push_back(makeTx({js1, js2}));
+        block.vtx.push_back(makeTx({js3}));
+
+        auto verifier = ProofVerifier::Batch();
+        queueBlock(verifier, block);
+        EXPECT_TRUE(verifier.ValidateQueued());
+        EXPECT_FALSE(verifier.FirstFailure().has_value());
+    }
+
+    {
+        CBlock block;
+        block.vtx.push_back(makeTx({js1}));
+        block.vtx.push_back(makeTx({js2, invalid, js3}));
+
+        auto verifier = ProofVerifier::Batch();
+        queueBlock(verifier, block);
+        EXPECT_FALSE(verifier.ValidateQueued());
+        ASSERT_EQ(verifier.FirstFailure(), std::optional<size_t>(2));
+
+        auto joinsplit = FindQueuedJoinSplit(block, verifier.FirstFailure().value());
+        ASSERT_TRUE(joinsplit.has_value());
+        EXPECT_EQ(joinsplit->first, block.vtx[1].GetHash());
+        EXPECT_EQ(joinsplit->second, 1);
+
+        // The invalid proof verifies neither on its own nor in a fresh batch.
+        EXPECT_FALSE(ProofVerifier::Strict().VerifySprout(invalid, joinSplitPubKey));
+    }
+
+    {
+        // A proof that doesn't decode is rejected when it is queued, and isn't
+        // counted by FindQueuedJoinSplit's positions.
+        auto undecodable = js2;
+        auto& proof = std::get<libzcash::GrothProof>(undecodable.proof);
+        std::fill(proof.begin(), proof.end(), 0xff);
+
+        auto verifier = ProofVerifier::Batch();
+        EXPECT_FALSE(verifier.VerifySprout(undecodable, joinSplitPubKey));
+        EXPECT_TRUE(verifier.VerifySprout(js1, joinSplitPubKey));
+        EXPECT_TRUE(verifier.ValidateQueued());
+    }
+}
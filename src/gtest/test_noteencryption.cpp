@@ -10,6 +10,8 @@
 #include "zcash/prf.h"
 #include "zcash/Address.hpp"
 #include "crypto/sha256.h"
+#include "streams.h"
+#include "version.h"
 #include "librustzcash.h"
 #include "consensus/params.h"
 #include "utiltest.h"
@@ -689,6 +691,62 @@ TEST(NoteEncryption, api)
     }
 }
 
+TEST(NoteEncryption, RustSproutDecryption)
+{
+    // The notes are encrypted by ZCNoteEncryption, so that the Rust decryption is
+    // checked against the ciphertexts zcashd itself produces.
+    uint256 sk_enc = ZCNoteEncryption::generate_privkey(uint252(uint256S("21035d60bc1983e37950ce4803418a8fb33ea68d5b937ca382ecbae7564d6a07")));
+    uint256 pk_enc = ZCNoteEncryption::generate_pubkey(sk_enc);
+    uint256 h_sig = uint256S("17c04b074526fae936320a7ee4d5ceeb981061642efd85ebc4d1d2b512e782f5");
+
+    ZCNoteEncryption enc(h_sig);
+    for (unsigned char nonce_index = 0; nonce_index < ZC_NUM_JS_OUTPUTS; nonce_index++) {
+        std::array<unsigned char, ZC_MEMO_SIZE> memo = {{0xf6}};
+        memo[1] = nonce_index;
+        SproutNote note(random_uint256(), 100000000 + nonce_index, random_uint256(), random_uint256());
+        SproutNotePlaintext note_pt(note, memo);
+        auto ciphertext = note_pt.encrypt(enc, pk_enc);
+
+        CDataStream ss(SER_NETWORK, PROTOCOL_VERSION);
+        ss << note_pt;
+        ASSERT_EQ(ss.size(), ZC_NOTEPLAINTEXT_SIZE);
+
+        std::array<unsigned char, ZC_NOTEPLAINTEXT_SIZE> plaintext;
+        ASSERT_TRUE(librustzcash_sprout_try_decrypt(
+            sk_enc.begin(),
+            enc.get_epk().begin(),
+            h_sig.begin(),
+            ciphertext.begin(),
+            nonce_index,
+            plaintext.data()));
+        EXPECT_EQ(0, memcmp(plaintext.data(), &ss[0], ZC_NOTEPLAINTEXT_SIZE));
+
+        uint64_t value;
+        uint256 rho, r;
+        std::array<unsigned char, ZC_MEMO_SIZE> memo_ret;
+        ASSERT_TRUE(librustzcash_sprout_parse_plaintext(
+            plaintext.data(), &value, rho.begin(), r.begin(), memo_ret.data()));
+        EXPECT_EQ(value, note.value());
+        EXPECT_EQ(rho, note.rho);
+        EXPECT_EQ(r, note.r);
+        EXPECT_EQ(memo_ret, memo);
+
+        // The output can't be decrypted with the other output's KDF index, with
+        // another h_sig, or with another receiving key.
+        EXPECT_FALSE(librustzcash_sprout_try_decrypt(
+            sk_enc.begin(), enc.get_epk().begin(), h_sig.begin(),
+            ciphertext.begin(), 1 - nonce_index, plaintext.data()));
+        uint256 other_h_sig = uint256S("11035d60bc1983e37950ce4803418a8fb33ea68d5b937ca382ecbae7564d6a77");
+        EXPECT_FALSE(librustzcash_sprout_try_decrypt(
+            sk_enc.begin(), enc.get_epk().begin(), other_h_sig.begin(),
+            ciphertext.begin(), nonce_index, plaintext.data()));
+        uint256 other_sk_enc = ZCNoteEncryption::generate_privkey(uint252());
+        EXPECT_FALSE(librustzcash_sprout_try_decrypt(
+            other_sk_enc.begin(), enc.get_epk().begin(), h_sig.begin(),
+            ciphertext.begin(), nonce_index, plaintext.data()));
+    }
+}
+
 uint256 test_prf(
     unsigned char distinguisher,
     uint252 seed_x,
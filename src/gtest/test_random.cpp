@@ -1,8 +1,12 @@
 #include <gtest/gtest.h>
 
 #include "random.h"
+#include "uint256.h"
 #include "gtest/utils.h"
 
+#include <librustzcash.h>
+#include <rust/test_harness.h>
+
 TEST(Random, MappedShuffle) {
     std::vector<int> a {8, 4, 6, 3, 5};
     std::vector<int> m {0, 1, 2, 3, 4};
@@ -31,3 +35,31 @@ TEST(Random, MappedShuffle) {
     EXPECT_EQ(ea3, a3);
     EXPECT_EQ(em3, m3);
 }
+
+static int randFailuresReported = 0;
+
+static void CountRandFailure()
+{
+    randFailuresReported++;
+}
+
+TEST(Random, FailuresAreReported) {
+    SetRandFailureHandler(CountRandFailure);
+
+    unsigned char buf[32];
+    ASSERT_TRUE(zcash_test_harness_inject_failure("randomness", 1));
+    EXPECT_THROW(GetRandBytes(buf, sizeof(buf)), std::runtime_error);
+    EXPECT_EQ(randFailuresReported, 1);
+
+    // The failure was only injected once.
+    GetRandBytes(buf, sizeof(buf));
+    EXPECT_EQ(randFailuresReported, 1);
+
+    // The Rust functions that draw randomness return the failure to their callers.
+    uint256 r;
+    ASSERT_TRUE(zcash_test_harness_inject_failure("randomness", 1));
+    EXPECT_FALSE(librustzcash_sapling_generate_r(r.begin()));
+    EXPECT_TRUE(librustzcash_sapling_generate_r(r.begin()));
+
+    SetRandFailureHandler(nullptr);
+}
@@ -4,6 +4,8 @@
 #include "consensus/validation.h"
 #include "key_io.h"
 #include "main.h"
+#include "policy/fees.h"
+#include "proof_verifier.h"
 #include "pubkey.h"
 #include "rpc/protocol.h"
 #include "transaction_builder.h"
@@ -15,6 +17,8 @@
 
 #include <optional>
 
+#include <librustzcash.h>
+
 #include <gmock/gmock.h>
 #include <gtest/gtest.h>
 
@@ -202,6 +206,99 @@ TEST(TransactionBuilder, SproutToSproutAndSapling) {
     RegtestDeactivateSapling();
 }
 
+TEST(TransactionBuilder, SproutToSaplingMigration) {
+    LoadProofParameters();
+
+    auto consensusParams = RegtestActivateSapling();
+
+    auto sk = libzcash::SaplingSpendingKey::random();
+    auto fvk = sk.full_viewing_key();
+    auto pa = sk.default_address();
+
+    auto sproutSk = libzcash::SproutSpendingKey::random();
+    auto sproutAddr = sproutSk.address();
+
+    auto wtx1 = GetValidSproutReceive(sproutSk, 60000000, true);
+    auto sproutNote1 = GetSproutNote(sproutSk, wtx1, 0, 1);
+    auto wtx2 = GetValidSproutReceive(sproutSk, 45000000, true);
+    auto sproutNote2 = GetSproutNote(sproutSk, wtx2, 0, 1);
+
+    SproutMerkleTree sproutTree;
+    for (int i = 0; i < ZC_NUM_JS_OUTPUTS; i++) {
+        sproutTree.append(wtx1.vJoinSplit[0].commitments[i]);
+    }
+    SproutWitness sproutWitness1 = sproutTree.witness();
+    for (int i = 0; i < ZC_NUM_JS_OUTPUTS; i++) {
+        sproutTree.append(wtx2.vJoinSplit[0].commitments[i]);
+        sproutWitness1.append(wtx2.vJoinSplit[0].commitments[i]);
+    }
+    SproutWitness sproutWitness2 = sproutTree.witness();
+    // Fake a view with the Sprout notes in it
+    auto rt = sproutTree.root();
+    TransactionBuilderCoinsViewDB fakeDB;
+    fakeDB.sproutTrees.insert(std::pair<uint256, SproutMerkleTree>(rt, sproutTree));
+    CCoinsViewCache view(&fakeDB);
+
+    // Create a migration transaction as AsyncRPCOperation_saplingmigration does,
+    // with an amount chosen as specified in ZIP 308 that includes the fee.
+    CAmount available = 105000000;
+    uint64_t amount;
+    ASSERT_TRUE(librustzcash_sprout_migration_amount(available, &amount));
+    ASSERT_GE(amount, CENT);
+    ASSERT_LT(amount, available);
+
+    auto builder = TransactionBuilder(consensusParams, 2, std::nullopt, nullptr, &view);
+    builder.SetFee(DEFAULT_FEE);
+    builder.AddSproutInput(sproutSk, sproutNote1, sproutWitness1);
+    builder.AddSproutInput(sproutSk, sproutNote2, sproutWitness2);
+    builder.AddSaplingOutput(fvk.ovk, pa, amount - DEFAULT_FEE);
+    builder.SendChangeToSprout(sproutAddr);
+    auto tx = builder.Build().GetTxOrThrow();
+
+    // Both notes are spent by one JoinSplit, which pays the change back to
+    // Sprout and releases the migrated amount for the Sapling output and fee.
+    EXPECT_EQ(tx.vin.size(), 0);
+    EXPECT_EQ(tx.vout.size(), 0);
+    ASSERT_EQ(tx.vJoinSplit.size(), 1);
+    EXPECT_EQ(tx.vJoinSplit[0].anchor, rt);
+    EXPECT_EQ(tx.vJoinSplit[0].vpub_old, 0);
+    EXPECT_EQ(tx.vJoinSplit[0].vpub_new, amount);
+    EXPECT_EQ(tx.vShieldedSpend.size(), 0);
+    EXPECT_EQ(tx.vShieldedOutput.size(), 1);
+    EXPECT_EQ(tx.GetValueBalanceSapling(), -(amount - DEFAULT_FEE));
+
+    auto verifier = ProofVerifier::Strict();
+    EXPECT_TRUE(verifier.VerifySprout(tx.vJoinSplit[0], tx.joinSplitPubKey));
+
+    CValidationState state;
+    EXPECT_TRUE(ContextualCheckTransaction(tx, state, Params(), 4, true));
+    EXPECT_EQ(state.GetRejectReason(), "");
+
+    // The change can be spent from the first note's address.
+    ZCNoteDecryption decryptor(sproutSk.receiving_key());
+    auto hSig = ZCJoinSplit::h_sig(
+        tx.vJoinSplit[0].randomSeed,
+        tx.vJoinSplit[0].nullifiers,
+        tx.joinSplitPubKey);
+    CAmount change = 0;
+    for (size_t i = 0; i < ZC_NUM_JS_OUTPUTS; i++) {
+        try {
+            auto plaintext = libzcash::SproutNotePlaintext::decrypt(
+                decryptor,
+                tx.vJoinSplit[0].ciphertexts[i],
+                tx.vJoinSplit[0].ephemeralKey,
+                hSig,
+                (unsigned char)i);
+            change += plaintext.value();
+        } catch (const libzcash::note_decryption_failed&) {
+        }
+    }
+    EXPECT_EQ(change, available - amount);
+
+    // Revert to default
+    RegtestDeactivateSapling();
+}
+
 TEST(TransactionBuilder, DuplicateOrchardNullifier)
 {
     #include "data/tx-orchard-duplicate-nullifiers.h"
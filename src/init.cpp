@@ -30,6 +30,7 @@
 #include "miner.h"
 #include "net.h"
 #include "policy/policy.h"
+#include "random.h"
 #include "rpc/server.h"
 #include "rpc/register.h"
 #include "script/standard.h"
@@ -40,6 +41,7 @@
 #include "ui_interface.h"
 #include "util.h"
 #include "utilmoneystr.h"
+#include "utilstrencodings.h"
 #include "validationinterface.h"
 #ifdef ENABLE_WALLET
 #include "wallet/wallet.h"
@@ -82,6 +84,9 @@ static const bool DEFAULT_PROXYRANDOMIZE = true;
 static const bool DEFAULT_REST_ENABLE = false;
 static const bool DEFAULT_DISABLE_SAFEMODE = false;
 static const bool DEFAULT_STOPAFTERBLOCKIMPORT = false;
+static const bool DEFAULT_RAYON_LOW_PRIORITY = false;
+static const bool DEFAULT_SPROUT_PROVER_IN_MEMORY = false;
+static const unsigned int DEFAULT_SPROUT_PROVER_BUDGET = 64;
 
 // The time that the wallet will wait for the block index to load
 // during startup before timing out.
@@ -285,6 +290,19 @@ void HandleSIGHUP(int)
     fReopenDebugLog = true;
 }
 
+/**
+ * Called when no randomness could be obtained, before whatever needed it is
+ * abandoned. Nothing can be done safely without randomness, so shut down.
+ */
+static void HandleRandFailure()
+{
+    SetMiscWarning("Failed to obtain randomness from the operating system", GetTime());
+    uiInterface.ThreadSafeMessageBox(
+        _("Error: Failed to obtain randomness from the operating system. Zcash is shutting down."),
+        "", CClientUIInterface::MSG_ERROR);
+    StartShutdown();
+}
+
 bool static InitError(const std::string &str)
 {
     uiInterface.ThreadSafeMessageBox(str, "", CClientUIInterface::MSG_ERROR);
@@ -350,6 +368,9 @@ std::string HelpMessage(HelpMessageMode mode)
     }
     strUsage += HelpMessageOpt("-datadir=<dir>", _("Specify data directory (this path cannot use '~')"));
     strUsage += HelpMessageOpt("-paramsdir=<dir>", _("Specify Zcash network parameters directory"));
+    strUsage += HelpMessageOpt("-fetch-params", _("Download any missing Zcash network parameters into the parameters directory at startup (default: 0)"));
+    strUsage += HelpMessageOpt("-forcefilevks", _("Load the Sapling verifying keys from the parameter files instead of using the ones built into zcashd (default: 0)"));
+    strUsage += HelpMessageOpt("-preloadprovingparams", _("Load the Sapling proving parameters at startup, instead of when the first Sapling proof is created (default: 0)"));
     strUsage += HelpMessageOpt("-dbcache=<n>", strprintf(_("Set database cache size in megabytes (%d to %d, default: %d)"), nMinDbCache, nMaxDbCache, nDefaultDbCache));
     strUsage += HelpMessageOpt("-debuglogfile=<file>", strprintf(_("Specify location of debug log file: this can be an absolute path or a path relative to the data directory (default: %s)"), DEFAULT_DEBUGLOGFILE));
     strUsage += HelpMessageOpt("-exportdir=<dir>", _("Specify directory to be used when exporting data"));
@@ -441,12 +462,18 @@ std::string HelpMessage(HelpMessageMode mode)
     if (showDebug)
     {
         strUsage += HelpMessageOpt("-checkpoints", strprintf("Disable expensive verification for known chain history (default: %u)", DEFAULT_CHECKPOINTS_ENABLED));
+        strUsage += HelpMessageOpt("-export-test-vectors=<kind>", "Print test vectors of the given kind (zip244 or history_tree) as JSON, and exit");
+        strUsage += HelpMessageOpt("-export-test-vectors-params=<json>", "Set the integer seed and count of the test vectors printed by -export-test-vectors (default: {})");
         strUsage += HelpMessageOpt("-disablesafemode", strprintf("Disable safemode, override a real safe mode event (default: %u)", DEFAULT_DISABLE_SAFEMODE));
         strUsage += HelpMessageOpt("-testsafemode", strprintf("Force safe mode (default: %u)", DEFAULT_TESTSAFEMODE));
         strUsage += HelpMessageOpt("-dropmessagestest=<n>", "Randomly drop 1 of every <n> network messages");
         strUsage += HelpMessageOpt("-fuzzmessagestest=<n>", "Randomly fuzz 1 of every <n> network messages");
+        strUsage += HelpMessageOpt("-rayonlowpriority", strprintf("Run the Rust verification and proving threads at a lower scheduling priority (Linux only) (default: %u)", DEFAULT_RAYON_LOW_PRIORITY));
+        strUsage += HelpMessageOpt("-sproutproverinmemory", strprintf("Load all of the Sprout proving parameters into memory when creating a JoinSplit proof, rather than streaming them from disk (default: %u)", DEFAULT_SPROUT_PROVER_IN_MEMORY));
+        strUsage += HelpMessageOpt("-sproutproverbudget=<n>", strprintf("Memory in MiB to use for reading the Sprout proving parameters when streaming them (default: %u)", DEFAULT_SPROUT_PROVER_BUDGET));
         strUsage += HelpMessageOpt("-stopafterblockimport", strprintf("Stop running after importing blocks from disk (default: %u)", DEFAULT_STOPAFTERBLOCKIMPORT));
         strUsage += HelpMessageOpt("-nuparams=hexBranchId:activationHeight", "Use given activation height for specified network upgrade (regtest-only)");
+        strUsage += HelpMessageOpt("-deterministicrngseed=<hex>", "Derive randomness from the given 32-byte seed, to make tests reproducible; each node needs its own seed. The randomness inside Sapling proofs still comes from the operating system, so transactions with Sapling spends or outputs are not reproducible (regtest-only)");
         strUsage += HelpMessageOpt("-nurejectoldversions", strprintf("Reject peers that don't know about the current epoch (regtest-only) (default: %u)", DEFAULT_NU_REJECT_OLD_VERSIONS));
         strUsage += HelpMessageOpt(
                 "-fundingstream=streamId:startHeight:endHeight:comma_delimited_addresses",
@@ -828,34 +855,140 @@ bool InitSanityCheck(void)
     if (!glibc_sanity_test() || !glibcxx_sanity_test())
         return false;
 
+    if (!librustzcash_rng_selfcheck()) {
+        InitError("The operating system's source of randomness failed its self-check. Aborting.");
+        return false;
+    }
+
+    // The Sapling empty roots are hard-coded; cross-check them against the
+    // Rust note commitment tree hashing.
+    for (size_t depth = 0; depth <= SAPLING_INCREMENTAL_MERKLE_TREE_DEPTH; depth++) {
+        uint256 root;
+        if (!librustzcash_sapling_empty_root(depth, root.begin()) ||
+            libzcash::PedersenHash::EmptyRoot(depth) != root) {
+            InitError(strprintf("The Sapling empty root at depth %d failed its sanity check. Aborting.", depth));
+            return false;
+        }
+    }
+
+    // The Orchard empty roots are only computed in Rust; check that every depth
+    // is available, and that the full-depth root is the one the Orchard
+    // frontier uses and the known empty Orchard tree root.
+    uint256 orchardRoot;
+    for (size_t depth = 0; depth <= ORCHARD_INCREMENTAL_MERKLE_TREE_DEPTH; depth++) {
+        if (!orchard_merkle_tree_empty_root_at_depth(depth, orchardRoot.begin())) {
+            InitError(strprintf("The Orchard empty root at depth %d failed its sanity check. Aborting.", depth));
+            return false;
+        }
+    }
+    if (orchardRoot != OrchardMerkleFrontier::empty_root() ||
+        orchardRoot != uint256S("2fd8e51a03d9bbe2dd809831b1497aeb68a6e37ddf707ced4aa2d8dff13529ae")) {
+        InitError("The empty Orchard note commitment tree root failed its sanity check. Aborting.");
+        return false;
+    }
+
     return true;
 }
 
 
-static void ZC_LoadParams(
+static bool ZC_LoadParamsProgress(uint32_t file_index, uint64_t bytes_done, uint64_t bytes_total)
+{
+    static const char* const names[] = {"Sapling (Spend)", "Sapling (Output)", "Sprout Groth16"};
+    LogPrintf("Verifying %s parameters: %d%%\n",
+        file_index < 3 ? names[file_index] : "unknown",
+        bytes_total == 0 ? 100 : (int)(bytes_done * 100 / bytes_total));
+    return !ShutdownRequested();
+}
+
+static bool ZC_FetchParamsProgress(uint32_t file_index, uint64_t bytes_done, uint64_t bytes_total)
+{
+    static const char* const names[] = {"Sapling (Spend)", "Sapling (Output)", "Sprout Groth16"};
+    LogPrintf("Downloading %s parameters: %d of %d bytes\n",
+        file_index < 3 ? names[file_index] : "unknown", bytes_done, bytes_total);
+    return !ShutdownRequested();
+}
+
+static bool ZC_FetchParams()
+{
+    auto params_dir = ZC_GetParamsDir().native();
+    LogPrintf("Downloading any missing Zcash network parameters to %s\n", ZC_GetParamsDir().string());
+
+    switch (librustzcash_fetch_params(
+        reinterpret_cast<const codeunit*>(params_dir.c_str()),
+        params_dir.length(),
+        ZC_FetchParamsProgress))
+    {
+    case FETCH_PARAMS_OK:
+        return true;
+    case FETCH_PARAMS_CANCELLED:
+        LogPrintf("Downloading the Zcash network parameters was cancelled.\n");
+        return false;
+    case FETCH_PARAMS_NETWORK_ERROR:
+        uiInterface.ThreadSafeMessageBox(
+            _("Could not download the Zcash network parameters. Check your network connection and try again."),
+            "", CClientUIInterface::MSG_ERROR);
+        return false;
+    case FETCH_PARAMS_HASH_MISMATCH:
+        uiInterface.ThreadSafeMessageBox(
+            _("A downloaded Zcash network parameters file was corrupt, and has been discarded. Please try again."),
+            "", CClientUIInterface::MSG_ERROR);
+        return false;
+    case FETCH_PARAMS_EXISTING_INVALID:
+        uiInterface.ThreadSafeMessageBox(strprintf(
+            _("A Zcash network parameters file in the following directory is corrupt:\n"
+              "%s\n"
+              "Please remove it and restart."),
+                ZC_GetParamsDir()),
+            "", CClientUIInterface::MSG_ERROR);
+        return false;
+    default:
+        uiInterface.ThreadSafeMessageBox(strprintf(
+            _("Could not write the Zcash network parameters to the following directory:\n"
+              "%s"),
+                ZC_GetParamsDir()),
+            "", CClientUIInterface::MSG_ERROR);
+        return false;
+    }
+}
+
+/**
+ * Loads the proof system parameters. Returns false if they could not be
+ * loaded, or if loading was cancelled by a shutdown request, in which case
+ * initialization must stop.
+ */
+static bool ZC_LoadParams(
     const CChainParams& chainparams
 )
 {
     struct timeval tv_start, tv_end;
     float elapsed;
 
+    if (GetBoolArg("-fetch-params", false) && !ZC_FetchParams()) {
+        StartShutdown();
+        return false;
+    }
+
     fs::path sapling_spend = ZC_GetParamsDir() / "sapling-spend.params";
     fs::path sapling_output = ZC_GetParamsDir() / "sapling-output.params";
     fs::path sprout_groth16 = ZC_GetParamsDir() / "sprout-groth16.params";
 
+    // The Sapling verifying keys are built in, so the Sapling parameter
+    // files are only needed for proving unless -forcefilevks is set.
+    bool force_file_vks = GetBoolArg("-forcefilevks", false);
+    bool need_sapling_files = force_file_vks;
+
     if (!(
-        fs::exists(sapling_spend) &&
-        fs::exists(sapling_output) &&
+        (!need_sapling_files || (fs::exists(sapling_spend) && fs::exists(sapling_output))) &&
         fs::exists(sprout_groth16)
     )) {
         uiInterface.ThreadSafeMessageBox(strprintf(
             _("Cannot find the Zcash network parameters in the following directory:\n"
               "%s\n"
-              "Please run 'zcash-fetch-params' or './zcutil/fetch-params.sh' and then restart."),
+              "Please run 'zcash-fetch-params' or './zcutil/fetch-params.sh' and then restart, or restart with -fetch-params."),
                 ZC_GetParamsDir()),
             "", CClientUIInterface::MSG_ERROR);
         StartShutdown();
-        return;
+        return false;
     }
 
     static_assert(
@@ -870,18 +1003,38 @@ static void ZC_LoadParams(
     LogPrintf("Loading Sapling (Sprout Groth16) parameters from %s\n", sprout_groth16.string().c_str());
     gettimeofday(&tv_start, 0);
 
-    librustzcash_init_zksnark_params(
+    if (!librustzcash_init_zksnark_params(
         reinterpret_cast<const codeunit*>(sapling_spend_str.c_str()),
         sapling_spend_str.length(),
         reinterpret_cast<const codeunit*>(sapling_output_str.c_str()),
         sapling_output_str.length(),
         reinterpret_cast<const codeunit*>(sprout_groth16_str.c_str()),
-        sprout_groth16_str.length()
-    );
+        sprout_groth16_str.length(),
+        ZC_LoadParamsProgress,
+        force_file_vks
+    )) {
+        LogPrintf("Loading proof system parameters was cancelled.\n");
+        return false;
+    }
+
+    if (GetBoolArg("-preloadprovingparams", false)) {
+        LogPrintf("Preloading Sapling proving parameters\n");
+        if (!librustzcash_sapling_preload_proving_params()) {
+            uiInterface.ThreadSafeMessageBox(strprintf(
+                _("Failed to load the Sapling proving parameters from the following directory:\n"
+                  "%s\n"
+                  "Please run 'zcash-fetch-params' or './zcutil/fetch-params.sh' and then restart."),
+                    ZC_GetParamsDir()),
+                "", CClientUIInterface::MSG_ERROR);
+            StartShutdown();
+            return false;
+        }
+    }
 
     gettimeofday(&tv_end, 0);
     elapsed = float(tv_end.tv_sec-tv_start.tv_sec) + (tv_end.tv_usec-tv_start.tv_usec)/float(1000000);
     LogPrintf("Loaded proof system parameters in %fs seconds.\n", elapsed);
+    return true;
 }
 
 bool AppInitServers(boost::thread_group& threadGroup)
@@ -1087,9 +1240,6 @@ bool AppInit2(boost::thread_group& threadGroup, CScheduler& scheduler)
 
     std::set_new_handler(new_handler_terminate);
 
-    // Set up global Rayon threadpool.
-    zcashd_init_rayon_threadpool();
-
     // ********************************************************* Step 2: parameter interactions
     const CChainParams& chainparams = Params();
 
@@ -1188,6 +1338,22 @@ bool AppInit2(boost::thread_group& threadGroup, CScheduler& scheduler)
     else if (nScriptCheckThreads > MAX_SCRIPTCHECK_THREADS)
         nScriptCheckThreads = MAX_SCRIPTCHECK_THREADS;
 
+    // Set up the global Rayon threadpool used by the Rust code, with as many
+    // threads as script verification uses (including the main thread).
+    if (!librustzcash_init_rayon(
+        std::max(nScriptCheckThreads, 1),
+        "zc-rayon",
+        GetBoolArg("-rayonlowpriority", DEFAULT_RAYON_LOW_PRIORITY)))
+    {
+        return InitError(_("Failed to initialize the Rayon threadpool."));
+    }
+
+    // Orchard proofs are created on a threadpool of their own, which is the same
+    // size as the global one unless an RPC operation requests otherwise.
+    if (!librustzcash_init_orchard_proving(std::max(nScriptCheckThreads, 1))) {
+        return InitError(_("Failed to initialize the Orchard proving threadpool."));
+    }
+
     fServer = GetBoolArg("-server", false);
 
     // block pruning; get the amount of disk space (in MiB) to allot for block & undo files
@@ -1364,6 +1530,20 @@ bool AppInit2(boost::thread_group& threadGroup, CScheduler& scheduler)
         }
     }
 
+    // Pass the activation heights (which may have been overridden with -nuparams
+    // on regtest) to the Rust code.
+    std::vector<int32_t> activationHeights;
+    for (int idx = Consensus::UPGRADE_OVERWINTER; idx <= Consensus::UPGRADE_NU5; idx++) {
+        activationHeights.push_back(chainparams.GetConsensus().vUpgrades[idx].nActivationHeight);
+    }
+    if (!librustzcash_init_network_params(
+        chainparams.NetworkIDString().c_str(),
+        activationHeights.data(),
+        activationHeights.size()))
+    {
+        return InitError(_("Failed to initialize the network parameters."));
+    }
+
     // ********************************************************* Step 4: application initialization: dir lock, daemonize, pidfile, debug log
 
     // Initialize libsodium
@@ -1375,10 +1555,24 @@ bool AppInit2(boost::thread_group& threadGroup, CScheduler& scheduler)
     ECC_Start();
     globalVerifyHandle.reset(new ECCVerifyHandle());
 
+    SetRandFailureHandler(HandleRandFailure);
+
     // Sanity check
     if (!InitSanityCheck())
         return InitError(_("Initialization sanity check failed. Zcash is shutting down."));
 
+    if (mapArgs.count("-deterministicrngseed")) {
+        // Make the node's randomness reproducible, for debugging tests.
+        std::string strSeed = GetArg("-deterministicrngseed", "");
+        if (strSeed.size() != 64 || !IsHex(strSeed)) {
+            return InitError("-deterministicrngseed must be 32 bytes encoded as hex.");
+        }
+        std::vector<unsigned char> seed = ParseHex(strSeed);
+        if (!librustzcash_set_deterministic_rng(seed.data())) {
+            return InitError("-deterministicrngseed may only be set on regtest.");
+        }
+    }
+
     std::string strDataDir = GetDataDir().string();
 
     // Make sure only a single Bitcoin process is using the data directory.
@@ -1463,7 +1657,21 @@ bool AppInit2(boost::thread_group& threadGroup, CScheduler& scheduler)
     }
 
     // Initialize Zcash circuit parameters
-    ZC_LoadParams(chainparams);
+    if (!ZC_LoadParams(chainparams)) {
+        return false;
+    }
+
+    // Choose how JoinSplit proofs read the Sprout proving parameters.
+    librustzcash_sprout_prover_mode(GetBoolArg("-sproutproverinmemory", DEFAULT_SPROUT_PROVER_IN_MEMORY) ? 0 : 1);
+    int64_t nSproutProverBudget = GetArg("-sproutproverbudget", DEFAULT_SPROUT_PROVER_BUDGET);
+    if (nSproutProverBudget <= 0) {
+        return InitError(_("-sproutproverbudget must be positive."));
+    }
+    // The budget is passed in bytes, which must fit in a size_t.
+    if ((uint64_t)nSproutProverBudget > (SIZE_MAX >> 20)) {
+        return InitError(strprintf(_("-sproutproverbudget must be at most %u MiB."), (uint64_t)(SIZE_MAX >> 20)));
+    }
+    librustzcash_sprout_prover_budget((size_t)nSproutProverBudget * 1024 * 1024);
 
     /* Start the RPC server already.  It will be started in "warmup" mode
      * and not really process calls already (but it will signify connections
@@ -1651,6 +1859,14 @@ bool AppInit2(boost::thread_group& threadGroup, CScheduler& scheduler)
     LogPrintf("* Using %.1fMiB for chain state database\n", nCoinDBCache * (1.0 / 1024 / 1024));
     LogPrintf("* Using %.1fMiB for in-memory UTXO set\n", nCoinCacheUsage * (1.0 / 1024 / 1024));
 
+    // Bound the memory that a block's queued shielded proofs and signatures can
+    // take up before they are validated, in proportion to the UTXO cache.
+    size_t nBatchValidationBudget = nCoinCacheUsage / 4;
+    if (!librustzcash_init_batch_validation(nBatchValidationBudget)) {
+        return InitError(_("Failed to initialize the batch validation memory budget."));
+    }
+    LogPrintf("* Using up to %.1fMiB for queued shielded proofs and signatures\n", nBatchValidationBudget * (1.0 / 1024 / 1024));
+
     bool clearWitnessCaches = false;
 
     bool fLoaded = false;
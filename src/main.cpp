@@ -1249,7 +1249,8 @@ bool ContextualCheckShieldedInputs(
         uint32_t consensusBranchId,
         bool nu5Active,
         bool isMined,
-        bool (*isInitBlockDownload)(const Consensus::Params&))
+        bool (*isInitBlockDownload)(const Consensus::Params&),
+        BatchValidatorsPtr* blockBatch)
 {
     // This doesn't trigger the DoS code on purpose; if it did, it would make it easier
     // for an attacker to attempt to split the network.
@@ -1313,7 +1314,60 @@ bool ContextualCheckShieldedInputs(
         }
     }
 
-    if (!tx.vShieldedSpend.empty() ||
+    if (blockBatch && (!tx.vShieldedSpend.empty() || !tx.vShieldedOutput.empty())) {
+        // Queue the Sapling bundle to be validated with the rest of the block. The
+        // ZIP 216 rules were enabled when the block's batch was created.
+        auto txid = tx.GetHash();
+
+        for (const SpendDescription &spend : tx.vShieldedSpend) {
+            if (!batch_validators_add_sapling_spend(
+                blockBatch,
+                txid.begin(),
+                spend.cv.begin(),
+                spend.anchor.begin(),
+                spend.nullifier.begin(),
+                spend.rk.begin(),
+                spend.zkproof.begin(),
+                spend.spendAuthSig.begin(),
+                dataToBeSigned.begin()
+            ))
+            {
+                return state.DoS(
+                    dosLevelPotentiallyRelaxing,
+                    error("ContextualCheckShieldedInputs(): Sapling spend description invalid"),
+                    REJECT_INVALID, "bad-txns-sapling-spend-description-invalid");
+            }
+        }
+
+        for (const OutputDescription &output : tx.vShieldedOutput) {
+            if (!batch_validators_add_sapling_output(
+                blockBatch,
+                txid.begin(),
+                output.cv.begin(),
+                output.cmu.begin(),
+                output.ephemeralKey.begin(),
+                output.zkproof.begin()
+            ))
+            {
+                return state.DoS(100, error("ContextualCheckShieldedInputs(): Sapling output description invalid"),
+                                      REJECT_INVALID, "bad-txns-sapling-output-description-invalid");
+            }
+        }
+
+        if (!batch_validators_add_sapling_binding_sig(
+            blockBatch,
+            txid.begin(),
+            tx.GetValueBalanceSapling(),
+            tx.bindingSig.begin(),
+            dataToBeSigned.begin()
+        ))
+        {
+            return state.DoS(
+                dosLevelPotentiallyRelaxing,
+                error("ContextualCheckShieldedInputs(): Sapling binding signature invalid"),
+                REJECT_INVALID, "bad-txns-sapling-binding-signature-invalid");
+        }
+    } else if (!tx.vShieldedSpend.empty() ||
         !tx.vShieldedOutput.empty())
     {
         // The nu5Active flag passed in here enables the new consensus rules from ZIP 216
@@ -1378,8 +1432,14 @@ bool ContextualCheckShieldedInputs(
         librustzcash_sapling_verification_ctx_free(ctx);
     }
 
-    // Queue Orchard signatures to be batch-validated.
-    tx.GetOrchardBundle().QueueSignatureValidation(orchardAuth, dataToBeSigned);
+    if (blockBatch) {
+        // Queue the Orchard proof and signatures to be validated with the rest of the
+        // block.
+        tx.GetOrchardBundle().QueueBlockValidation(blockBatch, tx.GetHash(), dataToBeSigned);
+    } else {
+        // Queue Orchard signatures to be batch-validated.
+        tx.GetOrchardBundle().QueueSignatureValidation(orchardAuth, dataToBeSigned);
+    }
 
     return true;
 }
@@ -1409,10 +1469,10 @@ bool CheckTransaction(const CTransaction& tx, CValidationState &state,
 
         // Check bundle-specific Orchard consensus rules. Since we check encoding
         // consensus rules at parse time, and signature validation is batched, all we are
-        // checking here is proof validity. Once we implement batched proof verification,
-        // this will move into orchardAuth.
+        // checking here is proof validity. In a batch context, the proof is instead
+        // validated with the rest of the block's shielded bundles in ConnectBlock.
         auto orchardBundle = tx.GetOrchardBundle();
-        if (!orchardBundle.CheckBundleSpecificConsensusRules()) {
+        if (!verifier.IsBatch() && !orchardBundle.CheckBundleSpecificConsensusRules()) {
             return state.DoS(
                 100, error("CheckTransaction(): Orchard bundle proof does not verify"),
                 REJECT_INVALID, "bad-txns-orchard-verification-failed");
@@ -2984,7 +3044,10 @@ static DisconnectResult DisconnectBlock(const CBlock& block, CValidationState& s
     auto consensusBranchId = pindex->nCachedBranchId.value();
 
     if (chainparams.GetConsensus().NetworkUpgradeActive(pindex->nHeight, Consensus::UPGRADE_HEARTWOOD)) {
-        view.PopHistoryNode(consensusBranchId);
+        if (!view.PopHistoryNode(consensusBranchId)) {
+            AbortNode(state, "Failed to remove the block from the history tree");
+            return DISCONNECT_FAILED;
+        }
     }
 
     // move best block pointer to prevout block
@@ -3064,6 +3127,23 @@ static bool ShouldCheckTransactions(const CChainParams& chainparams, const CBloc
              && Checkpoints::IsAncestorOfLastCheckpoint(chainparams.Checkpoints(), pindex));
 }
 
+std::optional<std::pair<uint256, size_t>> FindQueuedJoinSplit(
+    const CBlock& block, size_t queued)
+{
+    for (const CTransaction& tx : block.vtx) {
+        for (size_t i = 0; i < tx.vJoinSplit.size(); i++) {
+            if (!std::holds_alternative<libzcash::GrothProof>(tx.vJoinSplit[i].proof)) {
+                continue;
+            }
+            if (queued == 0) {
+                return std::make_pair(tx.GetHash(), i);
+            }
+            queued--;
+        }
+    }
+    return std::nullopt;
+}
+
 bool ConnectBlock(const CBlock& block, CValidationState& state, CBlockIndex* pindex,
                   CCoinsViewCache& view, const CChainParams& chainparams,
                   bool fJustCheck, bool fCheckAuthDataRoot)
@@ -3077,12 +3157,20 @@ bool ConnectBlock(const CBlock& block, CValidationState& state, CBlockIndex* pin
         fExpensiveChecks = false;
     }
 
-    // proof verification is expensive, disable if possible
-    auto verifier = fExpensiveChecks ? ProofVerifier::Strict() : ProofVerifier::Disabled();
+    // proof verification is expensive, disable if possible; otherwise batch the
+    // Sprout proofs, which are validated once the block's transactions are checked.
+    auto verifier = fExpensiveChecks ? ProofVerifier::Batch() : ProofVerifier::Disabled();
 
-    // Disable Orchard batch signature validation if possible.
-    auto orchardAuth = fExpensiveChecks ?
-        orchard::AuthValidator::Batch() : orchard::AuthValidator::Disabled();
+    // Batch the Sapling and Orchard proofs and signatures if we are checking them,
+    // which are validated together once the block's transactions are checked.
+    // Orchard signatures are then never queued in orchardAuth.
+    auto orchardAuth = orchard::AuthValidator::Disabled();
+    std::unique_ptr<BatchValidatorsPtr, decltype(&batch_validators_free)> blockBatch(
+        fExpensiveChecks ?
+            batch_validators_init(chainparams.GetConsensus().NetworkUpgradeActive(
+                pindex->nHeight, Consensus::UPGRADE_NU5)) :
+            nullptr,
+        batch_validators_free);
 
     // If in initial block download, and this block is an ancestor of a checkpoint,
     // and -ibdskiptxverification is set, disable all transaction checks.
@@ -3327,7 +3415,9 @@ bool ConnectBlock(const CBlock& block, CValidationState& state, CBlockIndex* pin
             chainparams.GetConsensus(),
             consensusBranchId,
             chainparams.GetConsensus().NetworkUpgradeActive(pindex->nHeight, Consensus::UPGRADE_NU5),
-            true))
+            true,
+            IsInitialBlockDownload,
+            blockBatch.get()))
         {
             return error(
                 "ConnectBlock(): ContextualCheckShieldedInputs() on %s failed with %s",
@@ -3523,11 +3613,68 @@ bool ConnectBlock(const CBlock& block, CValidationState& state, CBlockIndex* pin
                                block.vtx[0].GetValueOut(), blockReward),
                                REJECT_INVALID, "bad-cb-amount");
 
-    // Ensure Orchard signatures are valid (if we are checking them)
-    if (!orchardAuth.Validate()) {
+    // Ensure Sapling and Orchard proofs and signatures are valid (if we are checking
+    // them). The script checks are still running on their own threads, so the
+    // shielded bundles are validated on the Rust thread pool alongside them.
+    if (!batch_validators_validate_all(blockBatch.get(), nScriptCheckThreads > 0)) {
+        uint256 txid;
+        uint32_t sigKind;
+        size_t sigIndex;
+        auto failure = batch_validators_first_failure(blockBatch.get(), txid.begin());
+        std::string sigDescription;
+        if (batch_validators_first_failure_signature(blockBatch.get(), &sigKind, &sigIndex)) {
+            switch (sigKind) {
+            case BATCH_VALIDATORS_SAPLING_SPEND_AUTH_SIG:
+            case BATCH_VALIDATORS_ORCHARD_SPEND_AUTH_SIG:
+                sigDescription = strprintf("the spend authorization signature %d", sigIndex);
+                break;
+            default:
+                sigDescription = "the binding signature";
+            }
+        }
+        switch (failure) {
+        case BATCH_VALIDATORS_SAPLING_FAILURE:
+            return state.DoS(100,
+                error("ConnectBlock(): the Sapling bundle of transaction %s is invalid",
+                      txid.ToString()),
+                REJECT_INVALID, "bad-txns-sapling-verification-failed");
+        case BATCH_VALIDATORS_SAPLING_SIGNATURE_FAILURE:
+            return state.DoS(100,
+                error("ConnectBlock(): %s of the Sapling bundle of transaction %s is invalid",
+                      sigDescription, txid.ToString()),
+                REJECT_INVALID, "bad-txns-sapling-verification-failed");
+        case BATCH_VALIDATORS_ORCHARD_PROOF_FAILURE:
+            return state.DoS(100,
+                error("ConnectBlock(): the Orchard proof of transaction %s does not verify",
+                      txid.ToString()),
+                REJECT_INVALID, "bad-txns-orchard-verification-failed");
+        case BATCH_VALIDATORS_ORCHARD_SIGNATURE_FAILURE:
+            return state.DoS(100,
+                error("ConnectBlock(): %s of the Orchard bundle of transaction %s is invalid",
+                      sigDescription, txid.ToString()),
+                REJECT_INVALID, "bad-orchard-bundle-authorization");
+        default:
+            return state.DoS(100,
+                error("ConnectBlock(): a shielded bundle within the block is invalid"),
+                REJECT_INVALID, "bad-txns-shielded-verification-failed");
+        }
+    }
+
+    // Ensure Sprout proofs are valid (if we are checking them)
+    if (!verifier.ValidateQueued()) {
+        std::optional<std::pair<uint256, size_t>> joinsplit;
+        if (auto failure = verifier.FirstFailure()) {
+            joinsplit = FindQueuedJoinSplit(block, failure.value());
+        }
+        if (joinsplit.has_value()) {
+            return state.DoS(100,
+                error("ConnectBlock(): joinsplit %d of transaction %s does not verify",
+                      joinsplit->second, joinsplit->first.ToString()),
+                REJECT_INVALID, "bad-txns-joinsplit-verification-failed");
+        }
         return state.DoS(100,
-            error("ConnectBlock(): an Orchard bundle within the block is invalid"),
-            REJECT_INVALID, "bad-orchard-bundle-authorization");
+            error("ConnectBlock(): a joinsplit within the block does not verify"),
+            REJECT_INVALID, "bad-txns-joinsplit-verification-failed");
     }
 
     if (!control.Wait())
@@ -43,6 +43,7 @@
 #include <utility>
 #include <vector>
 
+#include <rust/block_validation.h>
 #include <rust/orchard.h>
 
 #include <boost/unordered_map.hpp>
@@ -383,8 +384,10 @@ bool ContextualCheckInputs(const CTransaction& tx, CValidationState &state, cons
  *
  * This also currently checks the Sapling proofs, due to the way the Rust verification
  * code is written. Sprout and Orchard proofs are currently checked in CheckTransaction().
- * Once we have batch proof validation implemented, these will all be accumulated in
- * CheckTransaction().
+ *
+ * If `blockBatch` is provided, the Sapling proofs and signatures and the Orchard proof
+ * and signatures are instead queued in it (only their encodings are checked here), to
+ * be validated with the rest of the block's shielded bundles.
  *
  * To skip checking signatures, use `Consensus::CheckTxShieldedInputs` instead.
  *
@@ -402,7 +405,8 @@ bool ContextualCheckShieldedInputs(
         uint32_t consensusBranchId,
         bool nu5Active,
         bool isMined,
-        bool (*isInitBlockDownload)(const Consensus::Params&) = IsInitialBlockDownload);
+        bool (*isInitBlockDownload)(const Consensus::Params&) = IsInitialBlockDownload,
+        BatchValidatorsPtr* blockBatch = nullptr);
 
 /** Check a transaction contextually against a set of consensus rules */
 bool ContextualCheckTransaction(const CTransaction& tx, CValidationState &state,
@@ -544,6 +548,14 @@ bool CheckBlock(const CBlock& block, CValidationState& state,
                 bool fCheckMerkleRoot,
                 bool fCheckTransactions);
 
+/**
+ * Returns the txid and index of the JoinSplit that was queued at position
+ * `queued` by a batching ProofVerifier when CheckBlock checked `block`. Only
+ * JoinSplits with Groth16 proofs are queued.
+ */
+std::optional<std::pair<uint256, size_t>> FindQueuedJoinSplit(
+    const CBlock& block, size_t queued);
+
 /** Context-dependent validity checks.
  *  By "context", we mean only the previous block headers, but not the UTXO
  *  set; UTXO-related validity checks are done in ConnectBlock(). */
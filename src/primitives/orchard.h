@@ -8,6 +8,7 @@
 #include "streams.h"
 
 #include <amount.h>
+#include <rust/block_validation.h>
 #include <rust/orchard.h>
 #include <rust/orchard/wallet.h>
 #include "zcash/address/orchard.hpp"
@@ -105,6 +106,18 @@ public:
         batch.Queue(inner.get(), txid.begin());
     }
 
+    /// Queues this bundle's proof and signatures to be validated with the rest
+    /// of the block's shielded bundles.
+    ///
+    /// `txid` is the transaction this bundle is within, which is reported if the
+    /// bundle is invalid, and `sighash` is the hash its signatures are over.
+    void QueueBlockValidation(
+        BatchValidatorsPtr* batch, const uint256& txid, const uint256& sighash) const
+    {
+        batch_validators_add_orchard_bundle(
+            batch, txid.begin(), inner.get(), sighash.begin());
+    }
+
     const size_t GetNumActions() const {
         return orchard_bundle_actions_len(inner.get());
     }
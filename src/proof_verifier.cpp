@@ -12,16 +12,16 @@
 
 class SproutProofVerifier
 {
-    ProofVerifier& verifier;
+    SproutBatchValidatorPtr* batch;
     const Ed25519VerificationKey& joinSplitPubKey;
     const JSDescription& jsdesc;
 
 public:
     SproutProofVerifier(
-        ProofVerifier& verifier,
+        SproutBatchValidatorPtr* batch,
         const Ed25519VerificationKey& joinSplitPubKey,
         const JSDescription& jsdesc
-        ) : jsdesc(jsdesc), verifier(verifier), joinSplitPubKey(joinSplitPubKey) {}
+        ) : jsdesc(jsdesc), batch(batch), joinSplitPubKey(joinSplitPubKey) {}
 
     bool operator()(const libzcash::PHGRProof& proof) const
     {
@@ -32,6 +32,24 @@ public:
 
     bool operator()(const libzcash::GrothProof& proof) const
     {
+        if (batch != nullptr) {
+            return sprout_batch_add_joinsplit(
+                batch,
+                proof.begin(),
+                jsdesc.anchor.begin(),
+                jsdesc.randomSeed.begin(),
+                jsdesc.nullifiers[0].begin(),
+                jsdesc.nullifiers[1].begin(),
+                jsdesc.macs[0].begin(),
+                jsdesc.macs[1].begin(),
+                jsdesc.commitments[0].begin(),
+                jsdesc.commitments[1].begin(),
+                jsdesc.vpub_old,
+                jsdesc.vpub_new,
+                joinSplitPubKey.bytes
+            );
+        }
+
         uint256 h_sig = ZCJoinSplit::h_sig(jsdesc.randomSeed, jsdesc.nullifiers, joinSplitPubKey);
 
         return librustzcash_sprout_verify(
@@ -58,6 +76,12 @@ ProofVerifier ProofVerifier::Disabled() {
     return ProofVerifier(false);
 }
 
+ProofVerifier ProofVerifier::Batch() {
+    auto verifier = ProofVerifier(true);
+    verifier.batch.reset(sprout_batch_validation_init());
+    return verifier;
+}
+
 bool ProofVerifier::VerifySprout(
     const JSDescription& jsdesc,
     const Ed25519VerificationKey& joinSplitPubKey
@@ -66,6 +90,18 @@ bool ProofVerifier::VerifySprout(
         return true;
     }
 
-    auto pv = SproutProofVerifier(*this, joinSplitPubKey, jsdesc);
+    auto pv = SproutProofVerifier(batch.get(), joinSplitPubKey, jsdesc);
     return std::visit(pv, jsdesc.proof);
 }
+
+bool ProofVerifier::ValidateQueued() {
+    return sprout_batch_validate(batch.get());
+}
+
+std::optional<size_t> ProofVerifier::FirstFailure() const {
+    auto index = sprout_batch_first_failure(batch.get());
+    if (index == SPROUT_BATCH_NO_FAILURE) {
+        return std::nullopt;
+    }
+    return index;
+}
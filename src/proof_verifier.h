@@ -9,19 +9,30 @@
 #include <uint256.h>
 
 #include <rust/ed25519/types.h>
+#include <rust/sprout.h>
+
+#include <memory>
+#include <optional>
 
 class ProofVerifier {
 private:
     bool perform_verification;
 
-    ProofVerifier(bool perform_verification) : perform_verification(perform_verification) { }
+    /// An optional batch validator that Sprout proofs are queued in instead of
+    /// being verified immediately (with `nullptr` corresponding to `None`).
+    /// Memory is allocated by Rust.
+    std::unique_ptr<SproutBatchValidatorPtr, decltype(&sprout_batch_validation_free)> batch;
+
+    ProofVerifier(bool perform_verification) :
+        perform_verification(perform_verification),
+        batch(nullptr, sprout_batch_validation_free) { }
 
 public:
     // ProofVerifier should never be copied
     ProofVerifier(const ProofVerifier&) = delete;
     ProofVerifier& operator=(const ProofVerifier&) = delete;
-    ProofVerifier(ProofVerifier&&);
-    ProofVerifier& operator=(ProofVerifier&&);
+    ProofVerifier(ProofVerifier&&) = default;
+    ProofVerifier& operator=(ProofVerifier&&) = default;
 
     // Creates a verification context that strictly verifies
     // all proofs.
@@ -32,11 +43,32 @@ public:
     // such as during reindexing.
     static ProofVerifier Disabled();
 
-    // Verifies that the JoinSplit proof is correct.
+    // Creates a verification context that queues Sprout proofs
+    // to be batch-verified by `ValidateQueued`. Orchard proofs are
+    // not checked by a batch context, as `ConnectBlock` queues them
+    // in the block's `BatchValidatorsPtr` instead.
+    static ProofVerifier Batch();
+
+    // Returns `true` if this is a batch context.
+    bool IsBatch() const {
+        return batch != nullptr;
+    }
+
+    // Verifies that the JoinSplit proof is correct. In a batch
+    // context, this only checks the encoding of the proof and
+    // queues it.
     bool VerifySprout(
         const JSDescription& jsdesc,
         const Ed25519VerificationKey& joinSplitPubKey
     );
+
+    // Batch-verifies the queued Sprout proofs, returning `true` if
+    // they are all correct (or if this is not a batch context).
+    bool ValidateQueued();
+
+    // Returns the position, in the order they were queued, of the
+    // first incorrect proof found by `ValidateQueued`.
+    std::optional<size_t> FirstFailure() const;
 };
 
 #endif // ZCASH_PROOF_VERIFIER_H
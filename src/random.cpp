@@ -13,7 +13,9 @@
 #include "logging.h"  // for LogPrint()
 #include "utiltime.h" // for GetTime()
 
+#include <atomic>
 #include <limits>
+#include <stdexcept>
 
 #ifndef WIN32
 #include <fcntl.h>
@@ -35,9 +37,28 @@ static inline int64_t GetPerformanceCounter()
     return nCounter;
 }
 
+static std::atomic<void (*)()> randFailureHandler(nullptr);
+
+void SetRandFailureHandler(void (*handler)())
+{
+    randFailureHandler = handler;
+}
+
+void RandFailure()
+{
+    LogPrintf("Failed to obtain randomness from the operating system\n");
+    auto handler = randFailureHandler.load();
+    if (handler) {
+        handler();
+    }
+    throw std::runtime_error("Failed to obtain randomness from the operating system");
+}
+
 void GetRandBytes(unsigned char* buf, size_t num)
 {
-    librustzcash_getrandom(buf, num);
+    if (!librustzcash_getrandom(buf, num)) {
+        RandFailure();
+    }
 }
 
 uint64_t GetRand(uint64_t nMax)
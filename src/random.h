@@ -19,6 +19,19 @@
  * Functions to gather random data via the rand_core OsRng
  */
 void GetRandBytes(unsigned char* buf, size_t num);
+
+/**
+ * Called when no randomness could be obtained. This logs the failure, reports it
+ * through the handler set with SetRandFailureHandler, and throws
+ * std::runtime_error so that whatever needed the randomness is abandoned.
+ */
+[[noreturn]] void RandFailure();
+
+/**
+ * Sets the function that RandFailure calls to report the failure, which zcashd
+ * uses to shut down cleanly. Pass nullptr to remove the handler.
+ */
+void SetRandFailureHandler(void (*handler)());
 uint64_t GetRand(uint64_t nMax);
 int GetRandInt(int nMax);
 uint256 GetRandHash();
@@ -29,6 +29,8 @@
 #include <stdint.h>
 #include <variant>
 
+#include <rust/transaction.h>
+
 #include <boost/assign/list_of.hpp>
 
 #include <univalue.h>
@@ -151,6 +153,65 @@ UniValue TxShieldedOutputsToJSON(const CTransaction& tx) {
     return vdesc;
 }
 
+UniValue TxOrchardBundleToJSON(const CTransaction& tx) {
+    // The C++ parse keeps the Orchard bundle opaque, so its fields are read back
+    // from the encoding of the transaction.
+    CDataStream ss(SER_NETWORK, PROTOCOL_VERSION);
+    ss << tx;
+    std::vector<unsigned char> txBytes(ss.begin(), ss.end());
+    uint32_t status;
+    TxInfo* info = zcash_transaction_inspect(txBytes.data(), txBytes.size(), false, &status);
+    if (info == nullptr) {
+        throw JSONRPCError(RPC_INTERNAL_ERROR, "Could not inspect the Orchard bundle");
+    }
+
+    auto hexField = [&](uint32_t field, size_t index) {
+        char hex[65];
+        bool found = zcash_tx_info_hex(info, field, index, hex);
+        assert(found);
+        return std::string(hex);
+    };
+    auto bytesField = [&](uint32_t field, size_t index) {
+        size_t len;
+        const unsigned char* bytes = zcash_tx_info_bytes(info, field, index, &len);
+        assert(bytes != nullptr);
+        return HexStr(bytes, bytes + len);
+    };
+
+    TxSummary summary;
+    zcash_tx_info_summary(info, &summary);
+
+    UniValue orchard(UniValue::VOBJ);
+    UniValue actions(UniValue::VARR);
+    for (size_t i = 0; i < summary.orchardActions; i++) {
+        UniValue obj(UniValue::VOBJ);
+        obj.pushKV("cv", bytesField(TX_INFO_ORCHARD_CV, i));
+        obj.pushKV("nullifier", hexField(TX_INFO_ORCHARD_NULLIFIER, i));
+        obj.pushKV("rk", bytesField(TX_INFO_ORCHARD_RK, i));
+        obj.pushKV("cmx", hexField(TX_INFO_ORCHARD_CMX, i));
+        obj.pushKV("ephemeralKey", bytesField(TX_INFO_ORCHARD_EPHEMERAL_KEY, i));
+        obj.pushKV("encCiphertext", bytesField(TX_INFO_ORCHARD_ENC_CIPHERTEXT, i));
+        obj.pushKV("outCiphertext", bytesField(TX_INFO_ORCHARD_OUT_CIPHERTEXT, i));
+        obj.pushKV("spendAuthSig", bytesField(TX_INFO_ORCHARD_SPEND_AUTH_SIG, i));
+        actions.push_back(obj);
+    }
+    orchard.pushKV("actions", actions);
+    orchard.pushKV("valueBalance", ValueFromAmount(summary.orchardValueBalance));
+    orchard.pushKV("valueBalanceZat", summary.orchardValueBalance);
+    if (summary.orchardActions > 0) {
+        UniValue flags(UniValue::VOBJ);
+        flags.pushKV("enableSpends", (summary.orchardFlags & 1) != 0);
+        flags.pushKV("enableOutputs", (summary.orchardFlags & 2) != 0);
+        orchard.pushKV("flags", flags);
+        orchard.pushKV("anchor", hexField(TX_INFO_ORCHARD_ANCHOR, 0));
+        orchard.pushKV("proof", bytesField(TX_INFO_ORCHARD_PROOF, 0));
+        orchard.pushKV("bindingSig", bytesField(TX_INFO_ORCHARD_BINDING_SIG, 0));
+    }
+
+    zcash_tx_info_free(info);
+    return orchard;
+}
+
 void TxToJSON(const CTransaction& tx, const uint256 hashBlock, UniValue& entry)
 {
     const uint256 txid = tx.GetHash();
@@ -240,6 +301,10 @@ void TxToJSON(const CTransaction& tx, const uint256 hashBlock, UniValue& entry)
         }
     }
 
+    if (tx.fOverwintered && tx.nVersion >= ZIP225_TX_VERSION) {
+        entry.pushKV("orchard", TxOrchardBundleToJSON(tx));
+    }
+
     if (tx.nVersion >= 2 && tx.vJoinSplit.size() > 0) {
         // Copy joinSplitPubKey into a uint256 so that
         // it is byte-flipped in the RPC output.
@@ -360,6 +425,30 @@ UniValue getrawtransaction(const UniValue& params, bool fHelp)
             "     }\n"
             "     ,...\n"
             "  ],\n"
+            "  \"orchard\" : {           (json object, only for version >= 5)\n"
+            "     \"actions\" : [          (array of json objects)\n"
+            "       {\n"
+            "         \"cv\" : \"hex\",             (string) the value commitment\n"
+            "         \"nullifier\" : \"hex\",      (string) the nullifier of the spent note\n"
+            "         \"rk\" : \"hex\",             (string) the randomized validating key\n"
+            "         \"cmx\" : \"hex\",            (string) the x-coordinate of the output note commitment\n"
+            "         \"ephemeralKey\" : \"hex\",   (string) the ephemeral public key\n"
+            "         \"encCiphertext\" : \"hex\",  (string) the encrypted output note\n"
+            "         \"outCiphertext\" : \"hex\",  (string) the encrypted data for recovering the output note\n"
+            "         \"spendAuthSig\" : \"hex\"    (string) the spend authorization signature\n"
+            "       }\n"
+            "       ,...\n"
+            "     ],\n"
+            "     \"valueBalance\" : x.xxx,   (numeric) the net value leaving the Orchard pool in " + CURRENCY_UNIT + "\n"
+            "     \"valueBalanceZat\" : n,    (numeric) the net value leaving the Orchard pool in " + MINOR_CURRENCY_UNIT + "\n"
+            "     \"flags\" : {               (json object, only if there are actions)\n"
+            "       \"enableSpends\" : true|false,   (boolean) whether the actions may spend notes\n"
+            "       \"enableOutputs\" : true|false   (boolean) whether the actions may create notes\n"
+            "     },\n"
+            "     \"anchor\" : \"hex\",       (string, only if there are actions) the anchor of the spent notes\n"
+            "     \"proof\" : \"hex\",        (string, only if there are actions) the aggregated zero-knowledge proof\n"
+            "     \"bindingSig\" : \"hex\"    (string, only if there are actions) the binding signature\n"
+            "  },\n"
             "  \"blockhash\" : \"hash\",   (string) the block hash\n"
             "  \"confirmations\" : n,      (numeric) The confirmations\n"
             "  \"time\" : ttt,             (numeric) The transaction time in seconds since epoch (Jan 1 1970 GMT)\n"
@@ -740,6 +829,30 @@ UniValue decoderawtransaction(const UniValue& params, bool fHelp)
             "     }\n"
             "     ,...\n"
             "  ],\n"
+            "  \"orchard\" : {           (json object, only for version >= 5)\n"
+            "     \"actions\" : [          (array of json objects)\n"
+            "       {\n"
+            "         \"cv\" : \"hex\",             (string) the value commitment\n"
+            "         \"nullifier\" : \"hex\",      (string) the nullifier of the spent note\n"
+            "         \"rk\" : \"hex\",             (string) the randomized validating key\n"
+            "         \"cmx\" : \"hex\",            (string) the x-coordinate of the output note commitment\n"
+            "         \"ephemeralKey\" : \"hex\",   (string) the ephemeral public key\n"
+            "         \"encCiphertext\" : \"hex\",  (string) the encrypted output note\n"
+            "         \"outCiphertext\" : \"hex\",  (string) the encrypted data for recovering the output note\n"
+            "         \"spendAuthSig\" : \"hex\"    (string) the spend authorization signature\n"
+            "       }\n"
+            "       ,...\n"
+            "     ],\n"
+            "     \"valueBalance\" : x.xxx,   (numeric) the net value leaving the Orchard pool in " + CURRENCY_UNIT + "\n"
+            "     \"valueBalanceZat\" : n,    (numeric) the net value leaving the Orchard pool in " + MINOR_CURRENCY_UNIT + "\n"
+            "     \"flags\" : {               (json object, only if there are actions)\n"
+            "       \"enableSpends\" : true|false,   (boolean) whether the actions may spend notes\n"
+            "       \"enableOutputs\" : true|false   (boolean) whether the actions may create notes\n"
+            "     },\n"
+            "     \"anchor\" : \"hex\",       (string, only if there are actions) the anchor of the spent notes\n"
+            "     \"proof\" : \"hex\",        (string, only if there are actions) the aggregated zero-knowledge proof\n"
+            "     \"bindingSig\" : \"hex\"    (string, only if there are actions) the binding signature\n"
+            "  },\n"
             "}\n"
 
             "\nExamples:\n"
@@ -275,7 +275,7 @@ UniValue setlogfilter(const UniValue& params, bool fHelp)
         TracingInfo("main", "Reloading log filter", "new_filter", newFilter.c_str());
 
         if (!tracing_reload(pTracingHandle, newFilter.c_str())) {
-            throw JSONRPCError(RPC_INTERNAL_ERROR, "Filter reload failed; check logs");
+            throw JSONRPCError(RPC_INVALID_PARAMETER, "Invalid filter; check logs");
         }
     }
 
@@ -283,6 +283,31 @@ UniValue setlogfilter(const UniValue& params, bool fHelp)
 }
 
 
+UniValue getlogfilter(const UniValue& params, bool fHelp)
+{
+    if (fHelp || params.size() != 0) {
+        throw runtime_error(
+            "getlogfilter\n"
+            "\nReturns the filter currently used for selecting events to log.\n"
+            "\nResult:\n"
+            "\"directives\"    (string) The comma-separated list of filter directives.\n"
+            "\nExamples:\n"
+            + HelpExampleCli("getlogfilter", "")
+            + HelpExampleRpc("getlogfilter", "")
+        );
+    }
+
+    if (!pTracingHandle) {
+        throw JSONRPCError(RPC_INTERNAL_ERROR, "Logging has not been initialized");
+    }
+
+    char* directives = tracing_get_filter(pTracingHandle);
+    std::string filter(directives);
+    tracing_free_filter(directives);
+    return filter;
+}
+
+
 UniValue stop(const UniValue& params, bool fHelp)
 {
     // Accept the deprecated and ignored 'detach' boolean argument
@@ -304,6 +329,7 @@ static const CRPCCommand vRPCCommands[] =
   //  --------------------- ------------------------  -----------------------  ----------
     /* Overall control/query calls */
     { "control",            "help",                   &help,                   true  },
+    { "control",            "getlogfilter",           &getlogfilter,           true  },
     { "control",            "setlogfilter",           &setlogfilter,           true  },
     { "control",            "stop",                   &stop,                   true  },
 };
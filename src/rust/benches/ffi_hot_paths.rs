@@ -0,0 +1,681 @@
+//! Benchmarks of the bridge functions on zcashd's hot paths, called through the same
+//! `extern` declarations as the C++ side (see the headers in `src/rust/include`),
+//! rather than through the Rust functions behind them.
+//!
+//! Every fixture is generated deterministically at startup, so runs are comparable:
+//!
+//! - `mmr_append`: a V1 history tree of a million leaves, of which only the peaks are
+//!   stored, as in `CCoinsViewDB`. Their contents are made up rather than combined from
+//!   real leaves, which the append does not check.
+//! - `mmr_root_from_leaves`: V1 history trees of 2^10 and 2^16 leaves with made-up
+//!   contents, built a level at a time from their leaves. Run it with and without the
+//!   `simd` feature to compare batched and unbatched hashing.
+//! - `zip244`: a v5 transaction with 1000 transparent inputs and outputs with random
+//!   scripts, and an Orchard bundle of 50 actions from
+//!   `zcash_test_harness_generate_orchard_bundle` with placeholder proof and
+//!   signatures, which digesting never checks.
+//! - `trial_decryption`: 1000 Orchard outputs in 100 generated bundles, scanned by a
+//!   wallet with 5 spending keys, which have 10 incoming viewing keys between them.
+//!   None of the outputs are to the wallet, so every trial decryption fails, as almost
+//!   all of them do in practice.
+//! - `orchard_batch_validation`: 4 proven bundles of 2 actions from the same
+//!   generator, whose signatures are over their seeds, added to the batch repeatedly.
+//!   Proving them takes a while before the first benchmark runs. Only the RedPallas
+//!   signatures are batch-validated; proofs are checked with the verifying key, which
+//!   needs the zk-SNARK parameters.
+//!
+//! The test harness functions need the `test-dependencies` feature, which the `bench`
+//! feature enables.
+
+use std::ptr::{self, NonNull};
+use std::slice;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use libc::{c_long, c_uchar, c_void, size_t};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use zcash_history::{Entry, EntryLink, NodeData, MAX_ENTRY_SIZE, MAX_NODE_DATA_SIZE, V1};
+
+// Links the bridge functions declared below.
+extern crate rustzcash;
+
+/// The consensus branch ID of Heartwood, the first with a history tree.
+const HEARTWOOD: u32 = 0xf5b9_230b;
+
+/// The consensus branch ID of NU5.
+const NU5: u32 = 0xc2d6_d0b4;
+
+const SIGHASH_ALL: u32 = 0x01;
+
+/// The ZIP 32 coin type of mainnet.
+const COIN_TYPE: u32 = 133;
+
+type StreamObj = NonNull<c_void>;
+type ReadCb = unsafe extern "C" fn(Option<StreamObj>, *mut u8, size_t) -> c_long;
+type WriteCb = unsafe extern "C" fn(Option<StreamObj>, *const u8, size_t) -> c_long;
+
+/// Declares the opaque types that the bridge functions point to.
+macro_rules! opaque {
+    ($($name:ident),*) => {
+        $(
+            #[repr(C)]
+            struct $name {
+                _private: [u8; 0],
+            }
+        )*
+    };
+}
+
+opaque!(
+    Bundle,
+    BatchValidator,
+    IncomingViewingKey,
+    PrecomputedTxParts,
+    SpendingKey,
+    Wallet
+);
+
+/// `struct RawOrchardActionIVK` in `rust/orchard/wallet.h`.
+#[allow(dead_code)]
+#[repr(C)]
+struct RawOrchardActionIvk {
+    action_idx: u64,
+    ivk: *mut IncomingViewingKey,
+}
+
+type PushActionIvkCb = unsafe extern "C" fn(Option<NonNull<c_void>>, RawOrchardActionIvk);
+type PushSpendActionIdxCb = unsafe extern "C" fn(Option<NonNull<c_void>>, u32);
+
+/// `struct RawOrchardScanTx` in `rust/orchard/wallet.h`.
+#[allow(dead_code)]
+#[repr(C)]
+struct RawOrchardScanTx {
+    txid: [u8; 32],
+    bundle: *const Bundle,
+    callback_receiver: Option<NonNull<c_void>>,
+}
+
+/// `struct RawOrchardScanBlock` in `rust/orchard/wallet.h`.
+#[allow(dead_code)]
+#[repr(C)]
+struct RawOrchardScanBlock {
+    height: u32,
+    txs: *const RawOrchardScanTx,
+    txs_len: size_t,
+}
+
+extern "system" {
+    fn librustzcash_mmr_total_nodes(leaf_count: u32) -> u32;
+
+    fn librustzcash_mmr_append(
+        cbranch: u32,
+        t_len: u32,
+        ni_ptr: *const u32,
+        n_ptr: *const [c_uchar; MAX_ENTRY_SIZE],
+        nl_ptr: *const size_t,
+        p_len: size_t,
+        nn_ptr: *const [u8; MAX_NODE_DATA_SIZE],
+        rt_ret: *mut [u8; 32],
+        buf_ret: *mut [c_uchar; MAX_NODE_DATA_SIZE],
+    ) -> u32;
+
+    fn librustzcash_mmr_root_from_leaves(
+        cbranch: u32,
+        leaves_ptr: *const [c_uchar; MAX_NODE_DATA_SIZE],
+        leaf_count: size_t,
+        root_ret: *mut [u8; 32],
+    ) -> u32;
+}
+
+extern "C" {
+    fn zcash_transaction_digests(
+        tx_bytes: *const c_uchar,
+        tx_bytes_len: size_t,
+        txid_ret: *mut [u8; 32],
+        auth_digest_ret: *mut [u8; 32],
+    ) -> bool;
+
+    fn zcash_transaction_precomputed_init(
+        tx_bytes: *const c_uchar,
+        tx_bytes_len: size_t,
+        all_prev_outputs: *const c_uchar,
+        all_prev_outputs_len: size_t,
+    ) -> *mut PrecomputedTxParts;
+
+    fn zcash_transaction_precomputed_free(precomputed_tx: *mut PrecomputedTxParts);
+
+    fn zcash_transaction_zip244_signature_digest(
+        precomputed_tx: *const PrecomputedTxParts,
+        hash_type: u32,
+        index: size_t,
+        script_code: *const c_uchar,
+        script_code_len: size_t,
+        sighash_ret: *mut [u8; 32],
+    ) -> bool;
+
+    fn zcash_test_harness_generate_orchard_bundle(
+        seed: *const [u8; 32],
+        num_actions: size_t,
+        with_valid_proof: bool,
+        stream: Option<StreamObj>,
+        write_cb: Option<WriteCb>,
+    ) -> bool;
+
+    fn orchard_bundle_parse(
+        stream: Option<StreamObj>,
+        read_cb: Option<ReadCb>,
+        bundle_ret: *mut *mut Bundle,
+    ) -> bool;
+
+    fn orchard_bundle_free(bundle: *mut Bundle);
+
+    fn orchard_batch_validation_init() -> *mut BatchValidator;
+
+    fn orchard_batch_validation_free(batch: *mut BatchValidator);
+
+    fn orchard_batch_add_bundle(
+        batch: *mut BatchValidator,
+        bundle: *const Bundle,
+        txid: *const [u8; 32],
+    );
+
+    fn orchard_batch_validate(batch: *const BatchValidator) -> bool;
+
+    fn orchard_spending_key_for_account(
+        seed: *const u8,
+        seed_len: size_t,
+        bip44_coin_type: u32,
+        account_id: u32,
+    ) -> *mut SpendingKey;
+
+    fn orchard_spending_key_free(key: *mut SpendingKey);
+
+    fn orchard_incoming_viewing_key_free(key: *mut IncomingViewingKey);
+
+    fn orchard_wallet_new() -> *mut Wallet;
+
+    fn orchard_wallet_free(wallet: *mut Wallet);
+
+    fn orchard_wallet_add_spending_key(wallet: *mut Wallet, sk: *const SpendingKey);
+
+    fn orchard_wallet_add_notes_from_bundle(
+        wallet: *mut Wallet,
+        txid: *const [c_uchar; 32],
+        bundle: *const Bundle,
+        callback_receiver: Option<NonNull<c_void>>,
+        push_cb: Option<PushActionIvkCb>,
+        spend_cb: Option<PushSpendActionIdxCb>,
+    ) -> bool;
+
+    fn orchard_wallet_rescan_blocks(
+        wallet: *mut Wallet,
+        blocks: *const RawOrchardScanBlock,
+        blocks_len: size_t,
+        max_in_flight_blocks: size_t,
+        push_cb: Option<PushActionIvkCb>,
+        spend_cb: Option<PushSpendActionIdxCb>,
+    ) -> bool;
+}
+
+unsafe extern "C" fn write_to_vec(obj: Option<StreamObj>, pch: *const u8, size: size_t) -> c_long {
+    let buf = obj.unwrap().cast::<Vec<u8>>().as_mut();
+    buf.extend_from_slice(slice::from_raw_parts(pch, size));
+    size as c_long
+}
+
+unsafe extern "C" fn read_from_slice(obj: Option<StreamObj>, pch: *mut u8, size: size_t) -> c_long {
+    let data = obj.unwrap().cast::<&[u8]>().as_mut();
+    let size = size.min(data.len());
+    slice::from_raw_parts_mut(pch, size).copy_from_slice(&data[..size]);
+    *data = &data[size..];
+    size as c_long
+}
+
+/// Drops the decrypted notes that the wallet reports, as the fixtures have none.
+unsafe extern "C" fn drop_action_ivk(_: Option<NonNull<c_void>>, action_ivk: RawOrchardActionIvk) {
+    orchard_incoming_viewing_key_free(action_ivk.ivk);
+}
+
+unsafe extern "C" fn drop_spend_action_idx(_: Option<NonNull<c_void>>, _: u32) {}
+
+fn write_compact_size(buf: &mut Vec<u8>, n: usize) {
+    match n {
+        0..=0xfc => buf.push(n as u8),
+        0xfd..=0xffff => {
+            buf.push(0xfd);
+            buf.extend_from_slice(&(n as u16).to_le_bytes());
+        }
+        _ => {
+            buf.push(0xfe);
+            buf.extend_from_slice(&(n as u32).to_le_bytes());
+        }
+    }
+}
+
+fn random_script(rng: &mut StdRng) -> Vec<u8> {
+    (0..rng.gen_range(20..50)).map(|_| rng.gen()).collect()
+}
+
+/// Returns the encoding of an Orchard bundle from the test harness generator.
+fn generate_bundle(seed: [u8; 32], num_actions: usize, with_valid_proof: bool) -> Vec<u8> {
+    let mut bytes = vec![];
+    assert!(unsafe {
+        zcash_test_harness_generate_orchard_bundle(
+            &seed,
+            num_actions,
+            with_valid_proof,
+            Some(NonNull::from(&mut bytes).cast()),
+            Some(write_to_vec),
+        )
+    });
+    bytes
+}
+
+/// An Orchard bundle parsed by `orchard_bundle_parse`.
+struct ParsedBundle(*mut Bundle);
+
+impl ParsedBundle {
+    fn parse(mut bytes: &[u8]) -> Self {
+        let mut bundle = ptr::null_mut();
+        assert!(unsafe {
+            orchard_bundle_parse(
+                Some(NonNull::from(&mut bytes).cast()),
+                Some(read_from_slice),
+                &mut bundle,
+            )
+        });
+        assert!(!bundle.is_null());
+        ParsedBundle(bundle)
+    }
+}
+
+impl Drop for ParsedBundle {
+    fn drop(&mut self) {
+        unsafe { orchard_bundle_free(self.0) };
+    }
+}
+
+/// Returns the length of a tree of `leaves` leaves, along with the indices, entries and
+/// entry lengths of its peaks, and the data of the next leaf.
+fn simulated_tree(
+    leaves: u32,
+) -> (
+    u32,
+    Vec<u32>,
+    Vec<[u8; MAX_ENTRY_SIZE]>,
+    Vec<size_t>,
+    [u8; MAX_NODE_DATA_SIZE],
+) {
+    let node_data = |first_leaf: u32, alt: u32| NodeData {
+        consensus_branch_id: HEARTWOOD,
+        subtree_commitment: [alt as u8; 32],
+        start_time: first_leaf,
+        end_time: first_leaf + (1 << alt),
+        start_target: 190,
+        end_target: 200,
+        start_sapling_root: [0; 32],
+        end_sapling_root: [0; 32],
+        subtree_total_work: Default::default(),
+        start_height: u64::from(first_leaf),
+        end_height: u64::from(first_leaf) + (1 << alt) - 1,
+        sapling_tx: 1 << alt,
+    };
+
+    // Each peak is the root of a perfect subtree, one for each bit set in `leaves`.
+    let (mut indices, mut entries, mut lengths) = (vec![], vec![], vec![]);
+    let (mut t_len, mut first_leaf) = (0, 1);
+    for alt in (0..32).rev().filter(|alt| leaves & (1 << alt) != 0) {
+        t_len += (2 << alt) - 1;
+        let index = t_len - 1;
+        let data = node_data(first_leaf, alt);
+        let entry: Entry<V1> = if alt == 0 {
+            Entry::new_leaf(data)
+        } else {
+            Entry::new(
+                data,
+                EntryLink::Stored(index - (1 << alt)),
+                EntryLink::Stored(index - 1),
+            )
+        };
+        let mut buf = [0; MAX_ENTRY_SIZE];
+        let mut unwritten = &mut buf[..];
+        entry
+            .write(&mut unwritten)
+            .expect("Cannot fail if enough buffer length");
+        lengths.push(MAX_ENTRY_SIZE - unwritten.len());
+        indices.push(index);
+        entries.push(buf);
+        first_leaf += 1 << alt;
+    }
+    assert_eq!(t_len, unsafe { librustzcash_mmr_total_nodes(leaves) });
+
+    let mut new_leaf = [0; MAX_NODE_DATA_SIZE];
+    node_data(first_leaf, 0)
+        .write(&mut &mut new_leaf[..])
+        .expect("Cannot fail if enough buffer length");
+    (t_len, indices, entries, lengths, new_leaf)
+}
+
+fn bench_mmr_append(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mmr_append");
+
+    for leaves in [1_000, 1_000_000] {
+        let (t_len, indices, peaks, lengths, new_leaf) = simulated_tree(leaves);
+        group.bench_function(BenchmarkId::from_parameter(leaves), |b| {
+            let mut rt_ret = [0; 32];
+            let mut buf_ret = [[0; MAX_NODE_DATA_SIZE]; 32];
+            b.iter(|| {
+                let appended = unsafe {
+                    librustzcash_mmr_append(
+                        HEARTWOOD,
+                        t_len,
+                        indices.as_ptr(),
+                        peaks.as_ptr(),
+                        lengths.as_ptr(),
+                        peaks.len(),
+                        &new_leaf,
+                        &mut rt_ret,
+                        buf_ret.as_mut_ptr(),
+                    )
+                };
+                assert_ne!(appended, 0);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Returns the encodings of `leaf_count` V1 leaves, with made-up contents.
+fn history_leaves(leaf_count: u32) -> Vec<[u8; MAX_NODE_DATA_SIZE]> {
+    (1..=leaf_count)
+        .map(|height| {
+            let mut leaf = [0; MAX_NODE_DATA_SIZE];
+            NodeData {
+                consensus_branch_id: HEARTWOOD,
+                subtree_commitment: [height as u8; 32],
+                start_time: height,
+                end_time: height,
+                start_target: 200,
+                end_target: 200,
+                start_sapling_root: [0; 32],
+                end_sapling_root: [0; 32],
+                subtree_total_work: Default::default(),
+                start_height: u64::from(height),
+                end_height: u64::from(height),
+                sapling_tx: 1,
+            }
+            .write(&mut &mut leaf[..])
+            .expect("Cannot fail if enough buffer length");
+            leaf
+        })
+        .collect()
+}
+
+fn bench_mmr_root_from_leaves(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mmr_root_from_leaves");
+
+    for leaf_count in [1 << 10, 1 << 16] {
+        let leaves = history_leaves(leaf_count);
+        group.throughput(Throughput::Elements(u64::from(leaf_count)));
+        group.bench_function(BenchmarkId::from_parameter(leaf_count), |b| {
+            let mut root = [0; 32];
+            b.iter(|| {
+                let res = unsafe {
+                    librustzcash_mmr_root_from_leaves(
+                        HEARTWOOD,
+                        leaves.as_ptr(),
+                        leaves.len(),
+                        &mut root,
+                    )
+                };
+                assert_eq!(res, 0);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Returns the encoding of a v5 transaction with `io_count` transparent inputs and
+/// outputs and the encoded `orchard_bundle`, along with the serialized outputs spent by
+/// its inputs and their scripts.
+fn large_transaction(
+    rng: &mut StdRng,
+    io_count: usize,
+    orchard_bundle: &[u8],
+) -> (Vec<u8>, Vec<u8>, Vec<Vec<u8>>) {
+    let mut tx = vec![];
+    tx.extend_from_slice(&0x8000_0005u32.to_le_bytes());
+    tx.extend_from_slice(&0x26A7_270Au32.to_le_bytes());
+    tx.extend_from_slice(&NU5.to_le_bytes());
+    tx.extend_from_slice(&0u32.to_le_bytes());
+    tx.extend_from_slice(&2_000_000u32.to_le_bytes());
+
+    let mut all_prev_outputs = vec![];
+    let mut script_pubkeys = vec![];
+    write_compact_size(&mut tx, io_count);
+    write_compact_size(&mut all_prev_outputs, io_count);
+    for _ in 0..io_count {
+        let prevout: [u8; 32] = rng.gen();
+        tx.extend_from_slice(&prevout);
+        tx.extend_from_slice(&rng.gen_range(0u32..4).to_le_bytes());
+        let script_sig = random_script(rng);
+        write_compact_size(&mut tx, script_sig.len());
+        tx.extend_from_slice(&script_sig);
+        tx.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        let script_pubkey = random_script(rng);
+        all_prev_outputs.extend_from_slice(&rng.gen_range(0u64..100_000_000).to_le_bytes());
+        write_compact_size(&mut all_prev_outputs, script_pubkey.len());
+        all_prev_outputs.extend_from_slice(&script_pubkey);
+        script_pubkeys.push(script_pubkey);
+    }
+
+    write_compact_size(&mut tx, io_count);
+    for _ in 0..io_count {
+        tx.extend_from_slice(&rng.gen_range(0u64..100_000_000).to_le_bytes());
+        let script_pubkey = random_script(rng);
+        write_compact_size(&mut tx, script_pubkey.len());
+        tx.extend_from_slice(&script_pubkey);
+    }
+
+    // No Sapling spends or outputs.
+    tx.extend_from_slice(&[0, 0]);
+    tx.extend_from_slice(orchard_bundle);
+    (tx, all_prev_outputs, script_pubkeys)
+}
+
+fn bench_zip244(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let orchard_bundle = generate_bundle([1; 32], 50, false);
+    let (tx, all_prev_outputs, script_pubkeys) = large_transaction(&mut rng, 1000, &orchard_bundle);
+
+    let mut group = c.benchmark_group("zip244");
+    group.throughput(Throughput::Bytes(tx.len() as u64));
+
+    group.bench_function("txid_and_auth_digest", |b| {
+        let (mut txid, mut auth_digest) = ([0; 32], [0; 32]);
+        b.iter(|| {
+            assert!(unsafe {
+                zcash_transaction_digests(tx.as_ptr(), tx.len(), &mut txid, &mut auth_digest)
+            })
+        })
+    });
+
+    let precompute = || {
+        let precomputed = unsafe {
+            zcash_transaction_precomputed_init(
+                tx.as_ptr(),
+                tx.len(),
+                all_prev_outputs.as_ptr(),
+                all_prev_outputs.len(),
+            )
+        };
+        assert!(!precomputed.is_null());
+        precomputed
+    };
+    group.bench_function("precomputed_init", |b| {
+        b.iter(|| unsafe { zcash_transaction_precomputed_free(precompute()) })
+    });
+
+    // Every input is signed, as `CheckInputs` checks each of them.
+    let precomputed = precompute();
+    group.throughput(Throughput::Elements(script_pubkeys.len() as u64));
+    group.bench_function("signature_digest_of_every_input", |b| {
+        let mut sighash = [0; 32];
+        b.iter(|| {
+            for (index, script_code) in script_pubkeys.iter().enumerate() {
+                assert!(unsafe {
+                    zcash_transaction_zip244_signature_digest(
+                        precomputed,
+                        SIGHASH_ALL,
+                        index,
+                        script_code.as_ptr(),
+                        script_code.len(),
+                        &mut sighash,
+                    )
+                });
+            }
+        })
+    });
+    unsafe { zcash_transaction_precomputed_free(precomputed) };
+
+    group.finish();
+}
+
+/// Returns a wallet with the first `accounts` Orchard spending keys of a fixed seed.
+fn wallet_with_accounts(accounts: u32) -> *mut Wallet {
+    let wallet = unsafe { orchard_wallet_new() };
+    let seed = [7; 32];
+    for account in 0..accounts {
+        unsafe {
+            let sk =
+                orchard_spending_key_for_account(seed.as_ptr(), seed.len(), COIN_TYPE, account);
+            assert!(!sk.is_null());
+            orchard_wallet_add_spending_key(wallet, sk);
+            orchard_spending_key_free(sk);
+        }
+    }
+    wallet
+}
+
+fn bench_trial_decryption(c: &mut Criterion) {
+    const BUNDLES: usize = 100;
+    const ACTIONS_PER_BUNDLE: usize = 10;
+    const TXS_PER_BLOCK: usize = 10;
+
+    let mut rng = StdRng::seed_from_u64(1);
+    let bundles: Vec<_> = (0..BUNDLES)
+        .map(|_| (rng.gen::<[u8; 32]>(), rng.gen::<[u8; 32]>()))
+        .map(|(seed, txid)| {
+            let bundle = ParsedBundle::parse(&generate_bundle(seed, ACTIONS_PER_BUNDLE, false));
+            (txid, bundle)
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("trial_decryption");
+    group.throughput(Throughput::Elements((BUNDLES * ACTIONS_PER_BUNDLE) as u64));
+    group.sample_size(20);
+
+    // How `CWallet::AddToWalletIfInvolvingMe` scans each transaction as it arrives. No
+    // note is added, so the wallet can be reused.
+    let wallet = wallet_with_accounts(5);
+    group.bench_function("add_notes_from_bundle", |b| {
+        b.iter(|| {
+            for (txid, bundle) in &bundles {
+                assert!(!unsafe {
+                    orchard_wallet_add_notes_from_bundle(
+                        wallet,
+                        txid,
+                        bundle.0,
+                        None,
+                        Some(drop_action_ivk),
+                        Some(drop_spend_action_idx),
+                    )
+                });
+            }
+        })
+    });
+    unsafe { orchard_wallet_free(wallet) };
+
+    // How `CWallet::ScanForWalletTransactions` scans a range of blocks. Rescanning
+    // checkpoints the wallet, so each iteration starts from a new one.
+    let txs: Vec<_> = bundles
+        .iter()
+        .map(|(txid, bundle)| RawOrchardScanTx {
+            txid: *txid,
+            bundle: bundle.0,
+            callback_receiver: None,
+        })
+        .collect();
+    let blocks: Vec<_> = txs
+        .chunks(TXS_PER_BLOCK)
+        .enumerate()
+        .map(|(i, txs)| RawOrchardScanBlock {
+            height: i as u32 + 1,
+            txs: txs.as_ptr(),
+            txs_len: txs.len(),
+        })
+        .collect();
+    group.bench_function("rescan_blocks", |b| {
+        b.iter_batched(
+            || wallet_with_accounts(5),
+            |wallet| unsafe {
+                assert!(orchard_wallet_rescan_blocks(
+                    wallet,
+                    blocks.as_ptr(),
+                    blocks.len(),
+                    0,
+                    Some(drop_action_ivk),
+                    Some(drop_spend_action_idx),
+                ));
+                orchard_wallet_free(wallet);
+            },
+            BatchSize::PerIteration,
+        )
+    });
+
+    group.finish();
+}
+
+fn bench_orchard_batch_validation(c: &mut Criterion) {
+    // The bundles are signed over their seeds, which stand in for their sighashes.
+    let bundles: Vec<_> = (0..4u8)
+        .map(|i| {
+            let sighash = [i; 32];
+            (
+                sighash,
+                ParsedBundle::parse(&generate_bundle(sighash, 2, true)),
+            )
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("orchard_batch_validation");
+    group.sample_size(20);
+
+    for count in [1, 10, 100] {
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter(|| unsafe {
+                let batch = orchard_batch_validation_init();
+                for (sighash, bundle) in bundles.iter().cycle().take(count) {
+                    orchard_batch_add_bundle(batch, bundle.0, sighash);
+                }
+                assert!(orchard_batch_validate(batch));
+                orchard_batch_validation_free(batch);
+            })
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_mmr_append,
+    bench_mmr_root_from_leaves,
+    bench_zip244,
+    bench_trial_decryption,
+    bench_orchard_batch_validation
+);
+criterion_main!(benches);
@@ -0,0 +1,45 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use zcash_primitives::sapling::merkle_hash;
+
+// The library is only built as a static library for zcashd, so the module is compiled
+// into the benchmark.
+#[allow(dead_code)]
+#[path = "../src/pedersen_hash.rs"]
+mod pedersen_hash;
+
+use pedersen_hash::merkle_hash_batch;
+
+fn random_node(rng: &mut StdRng) -> [u8; 32] {
+    let mut node: [u8; 32] = rng.gen();
+    node[31] &= 0x3f;
+    node
+}
+
+fn bench_merkle_hash(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut group = c.benchmark_group("merkle_hash");
+
+    for count in [1, 64, 1024] {
+        let inputs: Vec<_> = (0..count)
+            .map(|i| (i % 32, random_node(&mut rng), random_node(&mut rng)))
+            .collect();
+        group.throughput(Throughput::Elements(count as u64));
+        group.bench_with_input(BenchmarkId::new("scalar", count), &inputs, |b, inputs| {
+            b.iter(|| {
+                inputs
+                    .iter()
+                    .map(|(depth, lhs, rhs)| merkle_hash(*depth, lhs, rhs))
+                    .collect::<Vec<_>>()
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("batch", count), &inputs, |b, inputs| {
+            b.iter(|| merkle_hash_batch(inputs))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_merkle_hash);
+criterion_main!(benches);
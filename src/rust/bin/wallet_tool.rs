@@ -21,6 +21,11 @@ use time::OffsetDateTime;
 use tracing::debug;
 use tracing_subscriber::{fmt, EnvFilter};
 
+#[path = "../src/fetch_params.rs"]
+mod fetch_params;
+
+use fetch_params::{DEFAULT_MIRRORS, PARAMS_FILES};
+
 #[derive(Debug, Options)]
 struct CliOptions {
     #[options(no_short, help = "Print this help output")]
@@ -77,6 +82,19 @@ struct CliOptions {
         meta = "SECONDS"
     )]
     rpcclienttimeout: Option<u32>,
+
+    #[options(
+        no_short,
+        help = "Download any missing Zcash network parameters, then exit"
+    )]
+    fetch_params: bool,
+
+    #[options(
+        no_short,
+        help = "Specify Zcash network parameters directory, for --fetch-params",
+        meta = "PATH"
+    )]
+    paramsdir: Option<String>,
 }
 
 impl CliOptions {
@@ -129,6 +147,9 @@ enum WalletToolError {
 
     #[error("Unexpected EOF in input")]
     UnexpectedEof,
+
+    #[error("Could not determine the Zcash network parameters directory; set --paramsdir")]
+    ParamsDirNotFound,
 }
 
 pub fn main() {
@@ -187,12 +208,58 @@ pub fn main() {
         process::exit(0);
     }
 
-    if let Err(e) = run(&opts) {
+    let res = if opts.fetch_params {
+        run_fetch_params(&opts)
+    } else {
+        run(&opts)
+    };
+    if let Err(e) = res {
         eprintln!("{}: {}", command, e);
         process::exit(1);
     }
 }
 
+/// Returns the directory that zcashd loads the parameters from if `-paramsdir` is not
+/// set, matching `ZC_GetDefaultBaseParamsDir`.
+fn default_params_dir() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("ZcashParams"))
+    } else {
+        let home = env::var_os("HOME")
+            .filter(|home| !home.is_empty())
+            .map_or_else(|| PathBuf::from("/"), PathBuf::from);
+        if cfg!(target_os = "macos") {
+            Some(home.join("Library/Application Support/ZcashParams"))
+        } else {
+            Some(home.join(".zcash-params"))
+        }
+    }
+}
+
+fn run_fetch_params(opts: &CliOptions) -> anyhow::Result<()> {
+    let params_dir = match &opts.paramsdir {
+        Some(dir) => PathBuf::from(dir),
+        None => default_params_dir().ok_or(WalletToolError::ParamsDirNotFound)?,
+    };
+    println!(
+        "Downloading any missing Zcash network parameters to {}...",
+        params_dir.display()
+    );
+
+    let mut progress = |file_index: u32, bytes_done: u64, bytes_total: u64| {
+        print!(
+            "\r{}: {} of {} bytes",
+            PARAMS_FILES[file_index as usize].name, bytes_done, bytes_total
+        );
+        let _ = io::stdout().flush();
+        true
+    };
+    fetch_params::fetch_params(&params_dir, PARAMS_FILES, DEFAULT_MIRRORS, &mut progress)?;
+
+    println!("\nThe Zcash network parameters are in place.");
+    Ok(())
+}
+
 fn run(opts: &CliOptions) -> anyhow::Result<()> {
     let cli_options: Vec<String> = opts.to_zcash_cli_options();
 
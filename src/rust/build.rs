@@ -0,0 +1,34 @@
+//! Build script for librustzcash.
+//!
+//! Records the version of `zcash_history` in `Cargo.lock`, so that it can be reported
+//! at runtime.
+
+use std::fs;
+
+/// Returns the version of the package `name` in the lockfile `lock`, if it is locked at
+/// a single version.
+fn locked_version(lock: &str, name: &str) -> Option<String> {
+    let name_line = format!("name = \"{}\"", name);
+    let mut versions = lock
+        .lines()
+        .zip(lock.lines().skip(1))
+        .filter(|(line, _)| *line == name_line)
+        .filter_map(|(_, next)| {
+            next.strip_prefix("version = \"")?
+                .strip_suffix('"')
+                .map(str::to_owned)
+        });
+    let version = versions.next()?;
+    versions.next().is_none().then(|| version)
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=src/rust/build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let lock = fs::read_to_string("Cargo.lock").unwrap_or_default();
+    println!(
+        "cargo:rustc-env=ZCASH_HISTORY_VERSION={}",
+        locked_version(&lock, "zcash_history").unwrap_or_else(|| "unknown".to_owned())
+    );
+}
@@ -11,6 +11,25 @@
   #include <stdalign.h>
 #endif
 
+#define FETCH_PARAMS_OK 0
+#define FETCH_PARAMS_NETWORK_ERROR 1
+#define FETCH_PARAMS_HASH_MISMATCH 2
+#define FETCH_PARAMS_IO_ERROR 3
+#define FETCH_PARAMS_EXISTING_INVALID 4
+#define FETCH_PARAMS_CANCELLED 5
+
+/// Diagnostic counters for the per-thread pools of proving scratch buffers.
+typedef struct ProvingScratchStats {
+    /// The number of buffers that were taken from a pool since startup.
+    uint64_t hits;
+    /// The number of buffers that were allocated since startup because no
+    /// pooled buffer was large enough.
+    uint64_t misses;
+    /// The capacity in bytes of the buffers currently held by every thread's
+    /// pool.
+    uint64_t retained_bytes;
+} ProvingScratchStats;
+
 #ifdef __cplusplus
 extern "C" {
 #endif
@@ -27,9 +46,71 @@ extern "C" {
 
     bool librustzcash_ivk_to_pkd(const unsigned char *ivk, const unsigned char *diversifier, unsigned char *result);
 
-    /// Loads the zk-SNARK parameters into memory and saves
+    /// Called while the zk-SNARK parameter files are being read,
+    /// with the index of the file (0 for Sapling Spend, 1 for
+    /// Sapling Output, and 2 for Sprout), the number of bytes of
+    /// it that have been read, and its total size.
+    ///
+    /// Return false to cancel loading the parameters.
+    typedef bool (*params_progress_callback_t)(
+        uint32_t file_index,
+        uint64_t bytes_done,
+        uint64_t bytes_total);
+
+    /// Loads the zk-SNARK verifying keys into memory and saves
     /// paths as necessary. Only called once.
-    void librustzcash_init_zksnark_params(
+    ///
+    /// The Sapling proving parameters are loaded the first time
+    /// a Sapling proof is created, or when
+    /// `librustzcash_sapling_preload_proving_params` is called.
+    ///
+    /// If `librustzcash_set_params_paths` has been called, the
+    /// paths it was given are used instead.
+    ///
+    /// If `progress_cb` is not null, it is called every 16 MiB
+    /// and at the end of each file. Returns false if `progress_cb`
+    /// cancelled loading, in which case nothing has been loaded.
+    ///
+    /// The Sapling verifying keys built into the library are used
+    /// unless `force_file_vks` is true, so the Sapling parameter
+    /// files need not exist. Aborts if the files exist but don't
+    /// contain the built-in keys.
+    bool librustzcash_init_zksnark_params(
+        const codeunit* spend_path,
+        size_t spend_path_len,
+        const codeunit* output_path,
+        size_t output_path_len,
+        const codeunit* sprout_path,
+        size_t sprout_path_len,
+        params_progress_callback_t progress_cb,
+        bool force_file_vks
+    );
+
+    /// Downloads any of the parameter files that are missing
+    /// from `dest_dir` over HTTPS, creating the directory if
+    /// necessary. Each file is checked against its published
+    /// SHA-256 hash before being moved into place, and partial
+    /// downloads are resumed. Existing files are never
+    /// overwritten.
+    ///
+    /// If `progress_cb` is not null, it is called every MiB of
+    /// each download and may return false to cancel.
+    ///
+    /// Returns one of the `FETCH_PARAMS_*` results.
+    uint32_t librustzcash_fetch_params(
+        const codeunit* dest_dir,
+        size_t dest_dir_len,
+        params_progress_callback_t progress_cb
+    );
+
+    /// Sets the paths that `librustzcash_init_zksnark_params`
+    /// loads the parameters from, instead of the paths that it
+    /// is given. The paths are not checked until the parameters
+    /// are loaded. `sprout_path` may be null.
+    ///
+    /// Returns false if either Sapling path is null, or if the
+    /// parameters have already been loaded.
+    bool librustzcash_set_params_paths(
         const codeunit* spend_path,
         size_t spend_path_len,
         const codeunit* output_path,
@@ -38,6 +119,39 @@ extern "C" {
         size_t sprout_path_len
     );
 
+    /// Returns whether the Sapling proving parameters have been
+    /// loaded into memory.
+    bool librustzcash_sapling_proving_params_loaded();
+
+    /// Returns the number of bytes that the loaded Sapling proving
+    /// parameters hold in memory, or 0 if they are not loaded.
+    size_t librustzcash_sapling_proving_params_memory_usage();
+
+    /// Drops the Sapling proving parameters from memory, along with
+    /// the pooled proving scratch buffers. The parameters are loaded
+    /// again when they are next needed to create a proof. The
+    /// verifying keys are never unloaded.
+    ///
+    /// Returns false, leaving the parameters loaded, if any Sapling
+    /// proving context has not been freed.
+    bool librustzcash_unload_proving_params();
+
+    /// Writes the diagnostic counters of the pooled proving scratch
+    /// buffers to `stats_ret`. The buffers that the streamed Sprout
+    /// prover reads its parameters through are pooled per thread, and
+    /// each thread retains a bounded number of bytes of them.
+    void librustzcash_proving_scratch_stats(ProvingScratchStats* stats_ret);
+
+    /// Returns the number of Sapling proving contexts that have
+    /// been created and not yet freed.
+    size_t librustzcash_sapling_proving_ctx_count();
+
+    /// Loads the Sapling proving parameters into memory now,
+    /// instead of when the first Sapling proof is created.
+    ///
+    /// Returns false if the parameters could not be loaded.
+    bool librustzcash_sapling_preload_proving_params();
+
     /// Validates the provided Equihash solution against
     /// the given parameters, input and nonce.
     bool librustzcash_eh_isvalid(
@@ -58,6 +172,17 @@ extern "C" {
         unsigned char *result
     );
 
+    /// Writes the root of an empty Sapling note commitment
+    /// subtree of the given depth, where depth 0 is the
+    /// "uncommitted" leaf value. `result` must be a valid
+    /// pointer to 32 bytes which will be written.
+    ///
+    /// Returns `false` if `depth` is larger than 32.
+    bool librustzcash_sapling_empty_root(
+        size_t depth,
+        unsigned char *result
+    );
+
     /// Computes a merkle tree hash for a given depth.
     /// The `depth` parameter should not be larger than
     /// 62.
@@ -74,6 +199,22 @@ extern "C" {
         unsigned char *result
     );
 
+    /// Computes `count` merkle tree hashes together, which
+    /// is faster than computing them one at a time. The hash
+    /// of `depths[i]` and the 32-byte `a[i]` and `b[i]` is
+    /// placed in the 32-byte `results[i]`, with the same
+    /// requirements on each as `librustzcash_merkle_hash`.
+    ///
+    /// `a`, `b` and `results` must each be of length
+    /// `32 * count`.
+    void librustzcash_merkle_hash_batch(
+        size_t count,
+        const size_t *depths,
+        const unsigned char *a,
+        const unsigned char *b,
+        unsigned char *results
+    );
+
     /// Computes the signature for each Spend description, given the key
     /// `ask`, the re-randomization `ar`, the 32-byte sighash `sighash`,
     /// and an output `result` buffer of 64-bytes for the signature.
@@ -221,6 +362,25 @@ extern "C" {
         unsigned char *result
     );
 
+    /// Compute [ivk] [8] epk for some 32-byte
+    /// point epk and each of the `ivks_len`
+    /// 32-byte Fs at `ivks`. Each key is only
+    /// prepared for key agreement once across
+    /// calls, so this is faster than calling
+    /// `librustzcash_sapling_ka_agree` with each
+    /// key when trial-decrypting an output. If
+    /// epk or any key is invalid, returns false.
+    /// Otherwise, the result for each key is
+    /// written to the 32-byte buffer at the same
+    /// position in `results`.
+    bool librustzcash_sapling_ka_agree_ivks(
+        bool zip216_enabled,
+        const unsigned char *epk,
+        const unsigned char *ivks,
+        size_t ivks_len,
+        unsigned char *results
+    );
+
     /// Compute g_d = GH(diversifier) and returns
     /// false if the diversifier is invalid.
     /// Computes [esk] g_d and writes the result
@@ -233,8 +393,9 @@ extern "C" {
     );
 
     /// Generate uniformly random scalar in Jubjub.
-    /// The result is of length 32.
-    void librustzcash_sapling_generate_r(
+    /// The result is of length 32. Returns false if no
+    /// randomness could be obtained.
+    bool librustzcash_sapling_generate_r(
         unsigned char *result
     );
 
@@ -270,6 +431,75 @@ extern "C" {
         uint64_t vpub_new
     );
 
+    /// Attempts to decrypt the 601-byte ciphertext of the JoinSplit output at
+    /// `nonce_index` (0 or 1) with the recipient's 32-byte `receiving_key`,
+    /// writing the 585-byte note plaintext to `plaintext_ret`.
+    ///
+    /// Returns `false` if the output was not encrypted to `receiving_key`, or
+    /// if `nonce_index` is not the index of a JoinSplit output.
+    bool librustzcash_sprout_try_decrypt(
+        const unsigned char *receiving_key,
+        const unsigned char *epk,
+        const unsigned char *h_sig,
+        const unsigned char *ciphertext,
+        uint8_t nonce_index,
+        unsigned char *plaintext_ret
+    );
+
+    /// Parses a Sprout note plaintext into its value, 32-byte rho and r, and
+    /// 512-byte memo.
+    ///
+    /// Returns `false` if the plaintext's lead byte is not recognised.
+    bool librustzcash_sprout_parse_plaintext(
+        const unsigned char *plaintext,
+        uint64_t *value_ret,
+        unsigned char *rho_ret,
+        unsigned char *r_ret,
+        unsigned char *memo_ret
+    );
+
+    /// Computes the commitment to the Sprout note with the given 32-byte
+    /// `a_pk`, `rho` and `r` and `value`, writing the 32-byte commitment to
+    /// `cm_ret`.
+    void librustzcash_sprout_note_commitment(
+        const unsigned char *a_pk,
+        uint64_t value,
+        const unsigned char *rho,
+        const unsigned char *r,
+        unsigned char *cm_ret
+    );
+
+    /// Computes the nullifier of a Sprout note from its 32-byte `rho` and the
+    /// 32-byte spending key `a_sk` of its recipient, writing the 32-byte
+    /// nullifier to `nf_ret`.
+    void librustzcash_sprout_nullifier(
+        const unsigned char *a_sk,
+        const unsigned char *rho,
+        unsigned char *nf_ret
+    );
+
+    /// Chooses the amount in zatoshis of a Sprout-to-Sapling migration
+    /// transaction, including its fee, as specified in ZIP 308, given the
+    /// `available` Sprout funds.
+    ///
+    /// Returns `false` if less than 0.01 ZEC is available, or if no randomness
+    /// could be obtained.
+    bool librustzcash_sprout_migration_amount(
+        uint64_t available,
+        uint64_t *amount_ret
+    );
+
+    /// Selects how `librustzcash_sprout_prove` reads the Sprout proving
+    /// parameters: 0 to load them into memory, or 1 (the default) to stream
+    /// them from the file.
+    ///
+    /// Returns `false` if `mode` is not recognised.
+    bool librustzcash_sprout_prover_mode(uint32_t mode);
+
+    /// Sets the total size in bytes of the buffers that streamed Sprout
+    /// proving parameters are read through.
+    void librustzcash_sprout_prover_budget(size_t budget);
+
     /// Sprout JoinSplit proof verification.
     bool librustzcash_sprout_verify(
         const unsigned char *proof,
@@ -390,10 +620,28 @@ extern "C" {
     /// be a cryptographically secure RNG; it uses Rust's `OsRng`, which
     /// is implemented in terms of the `getrandom` crate. The first call
     /// to this function may block until sufficient randomness is available.
-    void librustzcash_getrandom(
+    ///
+    /// Returns false if no randomness could be obtained.
+    bool librustzcash_getrandom(
         unsigned char *buf,
         size_t buf_len
     );
+
+    /// Checks that randomness can be obtained from the operating system,
+    /// and that it is neither all zeroes nor repeated. This should be
+    /// called at startup, so that a broken source of randomness is
+    /// detected before anything depends on it.
+    bool librustzcash_rng_selfcheck();
+
+    /// Derives all randomness drawn through `librustzcash_getrandom` and the
+    /// Rust bridges from the 32-byte `seed`, so that test failures can be
+    /// reproduced. Each thread draws from its own stream, assigned in the
+    /// order that threads first draw randomness. The randomness that the
+    /// Sapling prover draws internally still comes from the operating system.
+    ///
+    /// Returns false (and changes nothing) unless the network parameters
+    /// passed to `librustzcash_init_network_params` are for regtest.
+    bool librustzcash_set_deterministic_rng(const unsigned char *seed);
 #ifdef __cplusplus
 }
 #endif
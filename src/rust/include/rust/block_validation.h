@@ -0,0 +1,132 @@
+// Copyright (c) 2022 The Zcash developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or https://www.opensource.org/licenses/mit-license.php .
+
+#ifndef ZCASH_RUST_INCLUDE_RUST_BLOCK_VALIDATION_H
+#define ZCASH_RUST_INCLUDE_RUST_BLOCK_VALIDATION_H
+
+#include "rust/orchard.h"
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/// The Sapling and Orchard batch validators for a block, in which the proofs
+/// and signatures of each transaction's shielded bundles are queued as the
+/// block is connected, to be validated together once its last transaction
+/// has been checked.
+struct BatchValidatorsPtr;
+typedef struct BatchValidatorsPtr BatchValidatorsPtr;
+
+/// Creates the shielded batch validators for a block. `zip216_enabled`
+/// enables the ZIP 216 rules for Sapling signatures, and should be set from
+/// NU5 activation.
+///
+/// The queued bundles are kept within the memory budget set by
+/// `librustzcash_init_batch_validation`: once they exceed it, they are
+/// validated before the next transaction is queued, so a single transaction's
+/// bundles can take them over it. This doesn't change the result of
+/// `batch_validators_validate_all`, or the failure it reports.
+///
+/// Please free this with `batch_validators_free` when you are done with it.
+BatchValidatorsPtr* batch_validators_init(bool zip216_enabled);
+
+/// Frees validators returned from `batch_validators_init`.
+void batch_validators_free(BatchValidatorsPtr* batch);
+
+/// Queues a Sapling Spend description of the transaction `txid`, whose
+/// signatures are over `sighash`.
+///
+/// Returns `false` if any field is not a valid encoding, in which case nothing
+/// is queued.
+bool batch_validators_add_sapling_spend(
+    BatchValidatorsPtr* batch,
+    const unsigned char* txid,
+    const unsigned char* cv,
+    const unsigned char* anchor,
+    const unsigned char* nullifier,
+    const unsigned char* rk,
+    const unsigned char* zkproof,
+    const unsigned char* spend_auth_sig,
+    const unsigned char* sighash);
+
+/// Queues a Sapling Output description of the transaction `txid`.
+///
+/// Returns `false` if any field is not a valid encoding, in which case nothing
+/// is queued.
+bool batch_validators_add_sapling_output(
+    BatchValidatorsPtr* batch,
+    const unsigned char* txid,
+    const unsigned char* cv,
+    const unsigned char* cm,
+    const unsigned char* epk,
+    const unsigned char* zkproof);
+
+/// Queues the value balance and binding signature of the Sapling bundle of
+/// the transaction `txid`, which must be queued after its Spend and Output
+/// descriptions.
+///
+/// Returns `false` if the value balance or signature is invalid, in which case
+/// nothing is queued.
+bool batch_validators_add_sapling_binding_sig(
+    BatchValidatorsPtr* batch,
+    const unsigned char* txid,
+    int64_t value_balance,
+    const unsigned char* binding_sig,
+    const unsigned char* sighash);
+
+/// Queues the proof and signatures of the Orchard bundle of the transaction
+/// `txid`, whose signatures are over `sighash`. Does nothing if `bundle` is
+/// null.
+void batch_validators_add_orchard_bundle(
+    BatchValidatorsPtr* batch,
+    const unsigned char* txid,
+    const OrchardBundlePtr* bundle,
+    const unsigned char* sighash);
+
+/// Validates the queued bundles, on the rayon thread pool if `parallel` is
+/// set. The block's Sapling and Orchard signatures are each verified in a
+/// batch. The queue is emptied.
+///
+/// - Returns `true` if `batch` is null.
+/// - Returns `false` if any queued proof or signature is invalid.
+bool batch_validators_validate_all(BatchValidatorsPtr* batch, bool parallel);
+
+#define BATCH_VALIDATORS_NO_FAILURE 0
+#define BATCH_VALIDATORS_SAPLING_FAILURE 1
+#define BATCH_VALIDATORS_ORCHARD_PROOF_FAILURE 2
+#define BATCH_VALIDATORS_ORCHARD_SIGNATURE_FAILURE 3
+#define BATCH_VALIDATORS_SAPLING_SIGNATURE_FAILURE 4
+
+/// Writes the txid of the first transaction (in the order they were queued)
+/// with an invalid bundle found by `batch_validators_validate_all` to
+/// `txid_ret`, and returns which of its bundles is invalid, or returns
+/// `BATCH_VALIDATORS_NO_FAILURE` if none was found.
+///
+/// `txid_ret` must point to a 32-byte array.
+uint32_t batch_validators_first_failure(
+    const BatchValidatorsPtr* batch,
+    unsigned char* txid_ret);
+
+#define BATCH_VALIDATORS_SAPLING_SPEND_AUTH_SIG 0
+#define BATCH_VALIDATORS_SAPLING_BINDING_SIG 1
+#define BATCH_VALIDATORS_ORCHARD_SPEND_AUTH_SIG 2
+#define BATCH_VALIDATORS_ORCHARD_BINDING_SIG 3
+
+/// If the first failure found by `batch_validators_validate_all` is an invalid
+/// signature, writes its kind to `kind_ret` and its index among the
+/// transaction's signatures of that kind (the index of its Spend description
+/// or action, or 0 for a binding signature) to `index_ret`, and returns `true`.
+bool batch_validators_first_failure_signature(
+    const BatchValidatorsPtr* batch,
+    uint32_t* kind_ret,
+    size_t* index_ret);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // ZCASH_RUST_INCLUDE_RUST_BLOCK_VALIDATION_H
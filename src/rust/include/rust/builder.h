@@ -75,7 +75,12 @@ OrchardUnauthorizedBundlePtr* orchard_builder_build(OrchardBuilderPtr* builder);
 /// Frees an Orchard bundle returned from `orchard_bundle_build`.
 void orchard_unauthorized_bundle_free(OrchardUnauthorizedBundlePtr* bundle);
 
-/// Adds proofs and signatures to the bundle.
+/// Returns the number of threads that Orchard proofs are created with, unless
+/// a build requests otherwise.
+size_t orchard_proving_threads();
+
+/// Adds proofs and signatures to the bundle. The proof is created with
+/// `num_threads` threads, or with `orchard_proving_threads()` threads if 0.
 ///
 /// Returns `null` if an error occurs.
 ///
@@ -84,7 +89,8 @@ OrchardBundlePtr* orchard_unauthorized_bundle_prove_and_sign(
     OrchardUnauthorizedBundlePtr* bundle,
     const OrchardSpendingKeyPtr** keys,
     size_t keys_len,
-    const unsigned char* sighash);
+    const unsigned char* sighash,
+    size_t num_threads);
 
 /// Calculates a ZIP 244 shielded signature digest for the given under-construction
 /// transaction.
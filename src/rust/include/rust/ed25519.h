@@ -19,7 +19,9 @@ extern "C" {
 #endif
 
 /// Generates a new Ed25519 keypair.
-void ed25519_generate_keypair(
+///
+/// Returns false if no randomness could be obtained.
+bool ed25519_generate_keypair(
     Ed25519SigningKey* sk,
     Ed25519VerificationKey* vk);
 
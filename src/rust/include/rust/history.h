@@ -28,12 +28,33 @@ static_assert(
     "HistoryEntry struct is not the same size as the underlying byte array");
 static_assert(alignof(HistoryEntry) == 1, "HistoryEntry struct alignment is not 1");
 
+/// Diagnostic counters for the history tree functions that reconstruct a
+/// tree from the provided nodes.
+typedef struct MmrStats {
+    /// The number of trees reconstructed since startup.
+    uint64_t reconstructions;
+    /// The number of nodes decoded by all reconstructions since startup.
+    uint64_t nodes_decoded;
+    /// The number of nodes decoded by the most recent reconstruction on the
+    /// calling thread, including a reconstruction that failed partway
+    /// through.
+    uint64_t last_nodes_decoded;
+} MmrStats;
+
 #ifdef __cplusplus
 extern "C" {
 #endif
 /// Appends a leaf to the given history tree.
 ///
-/// `t_len` must be at least 1.
+/// `t_len` must be at least 1, and the provided nodes must be exactly the
+/// peaks of the tree, from left to right.
+///
+/// `nl_ptr` holds the length of the encoding in each `HistoryEntry` (see
+/// `libzcash::EntryLength`), which must then be exactly that long. If it is
+/// null, each entry is decoded from the start of its buffer.
+///
+/// Returns the number of appended nodes written to `buf_ret`, or 0 if the
+/// provided nodes are not the peaks or any input is invalid.
 ///
 /// Aborts if `cbranch` is not a valid consensus branch ID.
 uint32_t librustzcash_mmr_append(
@@ -41,25 +62,442 @@ uint32_t librustzcash_mmr_append(
     uint32_t t_len,
     const uint32_t* ni_ptr,
     const HistoryEntry* n_ptr,
+    const size_t* nl_ptr,
     size_t p_len,
     const HistoryNode* nn_ptr,
     unsigned char* rt_ret,
     HistoryNode* buf_ret);
 
+#define MMR_APPEND_OUTPUT_MISMATCH 0
+#define MMR_APPEND_OUTPUT_MATCH 1
+#define MMR_APPEND_OUTPUT_INVALID_INPUT 2
+
+/// Recomputes `librustzcash_mmr_append` for the given tree and new leaf, and
+/// checks that the `rn_len` appended nodes in `rn_ptr` and the root in
+/// `rt_ptr` are exactly what it returns.
+///
+/// Returns `MMR_APPEND_OUTPUT_MATCH` if they are, `MMR_APPEND_OUTPUT_MISMATCH`
+/// if any of them differ, or `MMR_APPEND_OUTPUT_INVALID_INPUT` if the append
+/// itself fails.
+///
+/// Aborts if `cbranch` is not a valid consensus branch ID.
+uint32_t librustzcash_mmr_verify_append_output(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t p_len,
+    const HistoryNode* nn_ptr,
+    const HistoryNode* rn_ptr,
+    size_t rn_len,
+    const unsigned char* rt_ptr);
+
+#define MMR_STAGE_OK 0
+#define MMR_STAGE_FAILED 1
+#define MMR_STAGE_SKIPPED 2
+
+#define MMR_NO_INDEX UINT32_MAX
+
+/// The outcome of each stage of `librustzcash_mmr_append_verbose`. Each
+/// status is `MMR_STAGE_OK`, `MMR_STAGE_FAILED`, or `MMR_STAGE_SKIPPED` if an
+/// earlier stage failed.
+typedef struct MmrAppendReport {
+    /// Whether `t_len` is a valid non-empty tree length and the provided
+    /// indices are exactly its peaks.
+    uint32_t reconstruct_status;
+    /// The position in `ni_ptr` of the first unexpected index. If the
+    /// provided indices are a prefix of the peaks (or vice versa), this is
+    /// the length of the shorter of the two.
+    uint32_t reconstruct_index;
+    /// Whether every provided node could be decoded.
+    uint32_t nodes_status;
+    /// The tree index of the first provided node that could not be decoded.
+    uint32_t nodes_index;
+    /// Whether the new leaf could be decoded.
+    uint32_t new_node_status;
+    /// Whether the append succeeded.
+    uint32_t append_status;
+} MmrAppendReport;
+
+/// Performs `librustzcash_mmr_append`, additionally checking that the
+/// provided nodes are exactly the peaks of the tree, and writes the outcome
+/// of each stage to `report_ret`. Indices that don't apply are set to
+/// `MMR_NO_INDEX`.
+///
+/// Returns the number of appended nodes written to `buf_ret`, or 0 if any
+/// stage failed. Also returns 0 if `report_ret` is null, or if `rt_ret` or
+/// `buf_ret` is null, in which case every stage is reported as skipped.
+///
+/// Aborts if `cbranch` is not a valid consensus branch ID.
+uint32_t librustzcash_mmr_append_verbose(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t p_len,
+    const HistoryNode* nn_ptr,
+    unsigned char* rt_ret,
+    HistoryNode* buf_ret,
+    MmrAppendReport* report_ret);
+
+/// Computes the root that the given history tree would have if the leaf at
+/// `leaf_index` were replaced with `nl_ptr`, without modifying any of the
+/// provided nodes. This is not a consensus operation; it is intended for
+/// testing alternative histories.
+///
+/// The provided nodes must include every peak of the tree, and the sibling
+/// of every node on the path from the leaf up to its peak.
+///
+/// Returns 0 on success, or 1 if the provided nodes are insufficient or any
+/// input is invalid.
+///
+/// Aborts if `cbranch` is not a valid consensus branch ID.
+uint32_t librustzcash_mmr_root_with_replaced_leaf(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t n_len,
+    uint32_t leaf_index,
+    const HistoryNode* nl_ptr,
+    unsigned char* rt_ret);
+
+#define MMR_DELETE_MISSING_EXTRAS UINT32_MAX
+
 /// Deletes the most recently-appended leaf from the given history tree.
 ///
-/// `t_len` must be at least 1.
+/// `t_len` must be at least 1. The provided nodes must be the peaks, followed
+/// by the extra nodes listed by `librustzcash_mmr_can_delete`; `e_len` may be
+/// 0 if the last peak is a leaf. Any other node is rejected.
+///
+/// Returns the number of nodes deleted, after writing the new root to
+/// `rt_ret`. Returns `MMR_DELETE_MISSING_EXTRAS` if fewer extra nodes are
+/// provided than the delete needs, or 0 on any other error, including a null
+/// `rt_ret`.
+///
+/// `nl_ptr` is the lengths of the provided entries, or null, as for
+/// `librustzcash_mmr_append`.
 ///
 /// Aborts if `cbranch` is not a valid consensus branch ID.
 uint32_t librustzcash_mmr_delete(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    const size_t* nl_ptr,
+    size_t p_len,
+    size_t e_len,
+    unsigned char* rt_ret);
+
+#define MMR_SELF_CHECK_MISMATCH 0
+#define MMR_SELF_CHECK_MATCH 1
+#define MMR_SELF_CHECK_INVALID_INPUT 2
+
+/// Reconstructs the given history tree and checks that its root is the 32-byte
+/// `stored_root`, as a startup check that a persisted tree is not corrupted.
+/// The provided nodes are the `p_len` peaks followed by `e_len` extra nodes.
+///
+/// The root only depends on the peaks, so each provided node whose children
+/// are also provided is additionally checked to be their combination.
+///
+/// Returns `MMR_SELF_CHECK_MATCH` if the tree is consistent with `stored_root`,
+/// `MMR_SELF_CHECK_MISMATCH` if it is not, or `MMR_SELF_CHECK_INVALID_INPUT` if
+/// the tree cannot be reconstructed from the provided nodes.
+///
+/// Aborts if `cbranch` is not a valid consensus branch ID.
+uint32_t librustzcash_mmr_self_check(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t p_len,
+    size_t e_len,
+    const unsigned char* stored_root);
+
+#define MMR_ROOT_V1_OK 1
+#define MMR_ROOT_V2_OK 2
+
+/// Reconstructs the given history tree from the same node encodings as both a
+/// V1 and a V2 tree, writing the 32-byte root of each to `v1_root_ret` and
+/// `v2_root_ret`. This is a debugging aid for comparing how the nodes hash
+/// under each version at a network upgrade boundary.
+///
+/// Both versions are decoded and hashed with the consensus branch ID
+/// `cbranch`, regardless of which version that branch uses. Note that a V1
+/// encoding in a zero-padded `HistoryEntry` can also be decoded as a V2 node
+/// with empty Orchard fields.
+///
+/// Returns the bitwise OR of `MMR_ROOT_V1_OK` and `MMR_ROOT_V2_OK` for the
+/// versions whose roots were written; a root is unaltered if its tree could
+/// not be reconstructed.
+uint32_t librustzcash_mmr_root_both_versions(
     uint32_t cbranch,
     uint32_t t_len,
     const uint32_t* ni_ptr,
     const HistoryEntry* n_ptr,
     size_t p_len,
     size_t e_len,
+    unsigned char* v1_root_ret,
+    unsigned char* v2_root_ret);
+
+/// Writes the ASCII name of the consensus branch `cbranch` (for example
+/// "Heartwood") to `buf` as a NUL-terminated string, if it fits in `cap`
+/// bytes. Nothing is written if the buffer is too small.
+///
+/// Returns the buffer size needed for the name, including the NUL terminator,
+/// so callers can call this with `cap == 0` to size the buffer. Returns 0 if
+/// `cbranch` is not a valid consensus branch ID.
+size_t librustzcash_branch_name(
+    uint32_t cbranch,
+    char* buf,
+    size_t cap);
+
+/// Writes the versions of the Rust library and of the `zcash_history` crate it
+/// is built with to `buf` as a NUL-terminated string of the form
+/// "librustzcash=X;zcash_history=Y", if it fits in `cap` bytes. This is
+/// intended for bug reports about history tree mismatches.
+///
+/// Returns the buffer size needed for the string, including the NUL
+/// terminator, so callers can call this with `cap == 0` to size the buffer.
+size_t librustzcash_history_crate_versions(
+    char* buf,
+    size_t cap);
+
+/// Writes 1 to `result_ret` if blocks in the consensus branch `cbranch`
+/// commit to a chain history tree (Heartwood onward), or 0 if the branch
+/// predates ZIP 221.
+///
+/// Returns 0 on success, or 1 if `cbranch` is not a valid consensus branch ID
+/// or `result_ret` is null.
+uint32_t librustzcash_mmr_history_enabled(
+    uint32_t cbranch,
+    uint32_t* result_ret);
+
+#define MMR_EMPTY_ROOT_OK 0
+#define MMR_EMPTY_ROOT_UNDEFINED 1
+#define MMR_EMPTY_ROOT_INVALID 2
+
+/// Writes the root of the empty history tree for the consensus branch
+/// `cbranch` to `rt_ret`: the root that the block at the branch's activation
+/// height commits to, before the tree has any leaves. It is the all-zero hash
+/// for both tree versions.
+///
+/// Returns MMR_EMPTY_ROOT_OK on success, MMR_EMPTY_ROOT_UNDEFINED if the
+/// branch predates ZIP 221 and so has no history tree, or
+/// MMR_EMPTY_ROOT_INVALID if `cbranch` is not a valid consensus branch ID or
+/// `rt_ret` is null. `rt_ret` is unaltered unless MMR_EMPTY_ROOT_OK is
+/// returned.
+uint32_t librustzcash_mmr_empty_root(
+    uint32_t cbranch,
     unsigned char* rt_ret);
 
+#define MMR_LEAF_HEIGHT_OK 0
+#define MMR_LEAF_HEIGHT_INVALID 1
+#define MMR_LEAF_HEIGHT_OUT_OF_BRANCH 2
+
+/// Writes the height of the block that the leaf numbered `leaf_index`
+/// (counting from zero, rather than its index in the array representation) of
+/// the history tree for the consensus branch `cbranch` commits to, on the
+/// network named by `network` ("main", "test" or "regtest"). Each branch's
+/// tree starts with the block at the branch's activation height.
+///
+/// Regtest is only supported once its parameters have been installed with
+/// `librustzcash_init_network_params`.
+///
+/// Returns `MMR_LEAF_HEIGHT_OK` on success, `MMR_LEAF_HEIGHT_OUT_OF_BRANCH` if
+/// the block would be after the branch's last block, or
+/// `MMR_LEAF_HEIGHT_INVALID` if the network or branch is invalid, the branch
+/// has no history tree or never activates, or `height_ret` is null.
+uint32_t librustzcash_mmr_leaf_to_height(
+    const char* network,
+    uint32_t cbranch,
+    uint32_t leaf_index,
+    uint32_t* height_ret);
+
+/// Writes the number of the leaf (counting from zero) of the history tree for
+/// the consensus branch `cbranch` that commits to the block at `height`. This
+/// is the inverse of `librustzcash_mmr_leaf_to_height`, and returns the same
+/// codes; `MMR_LEAF_HEIGHT_OUT_OF_BRANCH` means that the block is not in the
+/// branch.
+uint32_t librustzcash_mmr_height_to_leaf(
+    const char* network,
+    uint32_t cbranch,
+    uint32_t height,
+    uint32_t* leaf_index_ret);
+
+#define MMR_LEAF_CHECK_OK 0
+#define MMR_LEAF_CHECK_HEIGHT_MISMATCH 1
+#define MMR_LEAF_CHECK_POSITION_MISMATCH 2
+#define MMR_LEAF_CHECK_INVALID 3
+
+/// Checks that the leaf numbered `leaf_index` (counting from zero) of a tree of
+/// length `t_len`, which must be among the `n_len` provided nodes, encodes
+/// `expected_height` as both its start and end height, and that
+/// `expected_height` is the height of the block that the leaf at that position
+/// commits to on the network named by `network`. This catches leaves that were
+/// stored or loaded at the wrong position.
+///
+/// Returns `MMR_LEAF_CHECK_OK` if both hold, `MMR_LEAF_CHECK_HEIGHT_MISMATCH`
+/// if the leaf encodes a different height, `MMR_LEAF_CHECK_POSITION_MISMATCH`
+/// if `expected_height` is not the height of the leaf's position, or
+/// `MMR_LEAF_CHECK_INVALID` if the network or branch is invalid, the leaf is not
+/// in the tree or was not provided, or a pointer is null.
+uint32_t librustzcash_mmr_check_leaf_height(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const unsigned char (*n_ptr)[NODE_SERIALIZED_LENGTH],
+    size_t n_len,
+    uint32_t leaf_index,
+    uint32_t expected_height,
+    const char* network);
+
+#define MMR_COMBINE_RULE_V1 1
+#define MMR_COMBINE_RULE_V2 2
+
+/// Writes the identifier of the rule that the history tree for the consensus
+/// branch `cbranch` uses to combine two child nodes into their parent to
+/// `id_ret`: `MMR_COMBINE_RULE_V1` for branches before NU5, or
+/// `MMR_COMBINE_RULE_V2` (which also combines the Orchard fields) from NU5
+/// onward. The identifiers are stable across releases.
+///
+/// Returns 0 on success, or 1 if `cbranch` is not a valid consensus branch ID
+/// or `id_ret` is null.
+uint32_t librustzcash_mmr_combine_rule_id(
+    uint32_t cbranch,
+    uint32_t* id_ret);
+
+/// Returns the number of leaves in a history tree of length `t_len`.
+///
+/// Returns 0 if `t_len` is not the length of any history tree.
+uint32_t librustzcash_mmr_leaf_count(uint32_t t_len);
+
+/// Returns the length of the array representation of a history tree with
+/// `leaf_count` leaves, including its internal nodes. This is the inverse of
+/// `librustzcash_mmr_leaf_count`.
+///
+/// Returns 0 if the length would not fit in a `uint32_t`.
+uint32_t librustzcash_mmr_total_nodes(uint32_t leaf_count);
+
+/// Writes the number of bytes that `CCoinsViewDB` writes for the nodes of a
+/// history tree with `leaf_count` leaves, for the consensus branch `cbranch`,
+/// to `bytes_ret`. Each node is stored as a 9-byte key and a value padded to
+/// `NODE_SERIALIZED_LENGTH`, whatever the tree's version. LevelDB's own
+/// overhead and compression are not included.
+///
+/// Returns 0 on success, or 1 if `cbranch` is not a valid consensus branch ID,
+/// the tree's length would not fit in a `uint32_t`, or `bytes_ret` is null.
+uint32_t librustzcash_mmr_storage_bytes(
+    uint32_t cbranch,
+    uint32_t leaf_count,
+    uint64_t* bytes_ret);
+
+/// Writes the index of the sibling of the node at `node_index` in a history
+/// tree of length `t_len` to `sibling_ret`, and 1 to `has_sibling_ret`. A peak
+/// has no sibling within the tree, so for a peak `MMR_NO_INDEX` and 0 are
+/// written instead.
+///
+/// Returns 0 on success, or 1 if `t_len` is not the length of any history tree,
+/// `node_index` is not in the tree, or either pointer is null.
+uint32_t librustzcash_mmr_sibling_index(
+    uint32_t t_len,
+    uint32_t node_index,
+    uint32_t* sibling_ret,
+    uint32_t* has_sibling_ret);
+
+/// Writes the number of nodes that appending a leaf to a history tree of
+/// length `t_len` adds (which is the number of nodes `librustzcash_mmr_append`
+/// writes to `buf_ret`) to `exact_ret`. Writes the most nodes that an append to
+/// any tree with at most as many leaves adds to `max_ret`, which callers can
+/// use to preallocate a buffer for the appends that follow.
+///
+/// Returns 0 on success, or 1 if `t_len` is not the length of any history tree,
+/// the tree after the append would not fit in a `uint32_t`, or either pointer
+/// is null.
+uint32_t librustzcash_mmr_append_sizes(
+    uint32_t t_len,
+    uint32_t* exact_ret,
+    uint32_t* max_ret);
+
+#define MMR_WRITE_PLAN_OK 0
+#define MMR_WRITE_PLAN_INVALID 1
+#define MMR_WRITE_PLAN_BUFFER_TOO_SMALL 2
+
+/// Writes the indices of the nodes that appending a leaf to a tree of length
+/// `t_len` creates to `created_out`, and of those whose stored value it
+/// changes to `overwritten_out`, along with the length of each list.
+///
+/// An append only ever creates nodes (the new leaf, then each parent that it
+/// completes), so the overwritten list is always empty.
+///
+/// Returns `MMR_WRITE_PLAN_OK` on success, `MMR_WRITE_PLAN_BUFFER_TOO_SMALL`
+/// (after writing both lengths) if `created_cap` is too small, or
+/// `MMR_WRITE_PLAN_INVALID` if `t_len` is not a valid tree length, the tree
+/// would outgrow a `uint32_t`, or a required pointer is null.
+uint32_t librustzcash_mmr_append_write_plan(
+    uint32_t t_len,
+    uint32_t* created_out,
+    size_t created_cap,
+    size_t* created_len_ret,
+    uint32_t* overwritten_out,
+    size_t overwritten_cap,
+    size_t* overwritten_len_ret);
+
+#define MMR_STORAGE_SET_OK 0
+#define MMR_STORAGE_SET_INVALID 1
+#define MMR_STORAGE_SET_BUFFER_TOO_SMALL 2
+
+/// Writes the indices, in ascending order, of the nodes of a tree of length
+/// `t_len` that a client must retain to delete up to `reorg_depth` of its
+/// most recent leaves and then keep appending, along with the number of them.
+/// Any other node may be pruned. With a `reorg_depth` of 0 this is just the
+/// peaks; nodes appended later are not included, as they must be stored as
+/// they are created.
+///
+/// Returns `MMR_STORAGE_SET_OK` on success,
+/// `MMR_STORAGE_SET_BUFFER_TOO_SMALL` (after writing the length) if `cap` is
+/// too small, or `MMR_STORAGE_SET_INVALID` if `t_len` is not a valid tree
+/// length or a required pointer is null.
+uint32_t librustzcash_mmr_min_storage_set(
+    uint32_t t_len,
+    uint32_t reorg_depth,
+    uint32_t* out_indices,
+    size_t cap,
+    size_t* len_ret);
+
+#define MMR_DELETE_EMPTY 0
+#define MMR_DELETE_OK 1
+#define MMR_DELETE_INSUFFICIENT_EXTRAS 2
+#define MMR_DELETE_UNSUPPORTED 3
+
+/// Checks whether `librustzcash_mmr_delete` would succeed on a tree of length
+/// `t_len`, given extra nodes at the `e_len` indices in `ei_ptr`.
+///
+/// Returns `MMR_DELETE_OK` if the delete would succeed, `MMR_DELETE_EMPTY` if
+/// the tree is empty, `MMR_DELETE_INSUFFICIENT_EXTRAS` if an extra node that
+/// the delete needs is not provided, or `MMR_DELETE_UNSUPPORTED` if `t_len` is
+/// not a valid tree length or the tree is a single leaf (which callers must
+/// reset themselves).
+uint32_t librustzcash_mmr_can_delete(
+    uint32_t t_len,
+    const uint32_t* ei_ptr,
+    size_t e_len);
+
+/// Copies the `count` nodes in `ni_ptr` and `n_ptr`, which may be in any
+/// order, to `out_ni` and `out_n` in the order that the other functions
+/// expect: the peaks from left to right, followed by the extra nodes in the
+/// order zcashd loads them.
+///
+/// The tree is the one whose last peak has the largest provided index. Returns
+/// the number of peaks (`p_len`), or 0 if any peak is missing, any other node
+/// is not one of the extra nodes, or any index is repeated.
+uint32_t librustzcash_mmr_canonicalize(
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t count,
+    uint32_t* out_ni,
+    HistoryEntry* out_n);
+
 /// Returns the hash of the given history tree node.
 ///
 /// Aborts if `cbranch` is not a valid consensus branch ID.
@@ -67,6 +505,509 @@ uint32_t librustzcash_mmr_hash_node(
     uint32_t cbranch,
     const HistoryNode* n_ptr,
     unsigned char* h_ret);
+
+/// Writes 1 to `result_ret` if the `len` bytes at `n_ptr` are the canonical
+/// encoding of a history tree node for the consensus branch `cbranch`,
+/// followed by nothing but zero padding, and 0 if they are not (including if
+/// they can't be decoded). A `HistoryNode` can be passed with a `len` of
+/// `sizeof(HistoryNode)`.
+///
+/// Returns 0 on success, or 1 if `cbranch` is not a valid consensus branch ID,
+/// `len` is larger than a `HistoryNode`, or a pointer is null.
+uint32_t librustzcash_mmr_node_is_canonical(
+    uint32_t cbranch,
+    const unsigned char* n_ptr,
+    size_t len,
+    uint32_t* result_ret);
+
+#define MMR_PERSONALIZATION_OK 0
+#define MMR_PERSONALIZATION_INVALID 1
+#define MMR_PERSONALIZATION_BUFFER_TOO_SMALL 2
+
+/// Writes the BLAKE2b personalization used for hashing the nodes of the
+/// history tree for `cbranch` to `out`. As specified by ZIP 221, this is
+/// `"ZcashHistory"` followed by the little-endian consensus branch ID, for
+/// both V1 and V2 trees.
+///
+/// Returns `MMR_PERSONALIZATION_OK` on success,
+/// `MMR_PERSONALIZATION_BUFFER_TOO_SMALL` (after writing the length) if `cap`
+/// is too small, or `MMR_PERSONALIZATION_INVALID` if `cbranch` is not a valid
+/// consensus branch ID or a pointer is null.
+uint32_t librustzcash_mmr_hash_personalization(
+    uint32_t cbranch,
+    unsigned char* out,
+    size_t cap,
+    size_t* len_ret);
+
+/// Returns the hash of the history tree leaf for a block with the given fields.
+///
+/// This is equivalent to building the leaf with `libzcash::NewV1Leaf` or
+/// `libzcash::NewV2Leaf` and passing it to `librustzcash_mmr_hash_node`.
+/// `orchard_root` may be null for V1 trees, and `orchard_tx` is ignored for
+/// them.
+///
+/// Returns 0 on success, or 1 if a required pointer is null.
+///
+/// Aborts if `cbranch` is not a valid consensus branch ID.
+uint32_t librustzcash_mmr_hash_block_leaf(
+    uint32_t cbranch,
+    const unsigned char* commitment,
+    uint32_t time,
+    uint32_t target,
+    const unsigned char* sapling_root,
+    const unsigned char* orchard_root,
+    const unsigned char* total_work,
+    uint64_t height,
+    uint64_t sapling_tx,
+    uint64_t orchard_tx,
+    unsigned char* h_ret);
+
+/// Writes a commitment to the peaks of a tree of length `t_len` to
+/// `commitment_ret`, so that two tree states can be compared with a single
+/// 32-byte comparison.
+///
+/// This is not the root of the tree: it commits to the canonical encodings of
+/// the stored peaks. `ni_ptr` must list exactly the peak indices, in order.
+///
+/// Neither this nor the root can be computed from the hashes of the peaks
+/// alone. The peaks are bagged into the root by the same rule that combines
+/// any two children into their parent, which hashes the full encodings of the
+/// children and derives the other fields of the parent from theirs, so callers
+/// need to store the full encodings of the peaks.
+///
+/// Returns 0 on success, or 1 if the tree is empty, the provided nodes are not
+/// its peaks, or any input is invalid.
+uint32_t librustzcash_mmr_peaks_commitment(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t p_len,
+    unsigned char* commitment_ret);
+
+/// Appends each of the `leaf_count` leaves at `leaves_ptr` in turn to an
+/// empty tree, writing the 32-byte root after each append to `roots_out`.
+///
+/// Returns the number of roots written, which is `leaf_count` on success, or
+/// 0 if `roots_cap` is less than `leaf_count` or any input is invalid.
+uint32_t librustzcash_mmr_build_roots(
+    uint32_t cbranch,
+    const HistoryNode* leaves_ptr,
+    size_t leaf_count,
+    unsigned char* roots_out,
+    size_t roots_cap);
+
+/// The state of a chain of roots being verified a leaf at a time, which only
+/// holds the peaks of the tree.
+struct MmrChainVerifier;
+typedef struct MmrChainVerifier MmrChainVerifier;
+
+#define MMR_CHAIN_STEP_MATCH 0
+#define MMR_CHAIN_STEP_MISMATCH 1
+#define MMR_CHAIN_STEP_INVALID 2
+
+/// Returns the state for verifying the roots of a chain of leaves for the
+/// consensus branch `cbranch`, starting from an empty tree, or `nullptr` if
+/// `cbranch` is not a valid consensus branch ID. The state must be freed with
+/// `librustzcash_mmr_chain_verify_free`.
+MmrChainVerifier* librustzcash_mmr_chain_verify_init(uint32_t cbranch);
+
+/// Appends the leaf at `leaf_ptr` to the tree of `state`, and checks that the
+/// resulting root is the 32-byte `expected_root`. This is the streaming
+/// counterpart to `librustzcash_mmr_build_roots`, for chains too long to hold
+/// every leaf at once.
+///
+/// Returns `MMR_CHAIN_STEP_MATCH` if it is, `MMR_CHAIN_STEP_MISMATCH` if it
+/// isn't (in which case the leaf is still appended), or
+/// `MMR_CHAIN_STEP_INVALID` if the leaf is invalid or a pointer is null (in
+/// which case the state is unaltered).
+uint32_t librustzcash_mmr_chain_verify_step(
+    MmrChainVerifier* state,
+    const HistoryNode* leaf_ptr,
+    const unsigned char* expected_root);
+
+/// Frees the state from `librustzcash_mmr_chain_verify_init`.
+void librustzcash_mmr_chain_verify_free(MmrChainVerifier* state);
+
+/// A history tree held across calls, which keeps every node so that leaves can
+/// be deleted, and caches its root between mutations.
+struct MmrTree;
+typedef struct MmrTree MmrTree;
+
+#define MMR_TREE_OK 0
+#define MMR_TREE_INVALID 1
+#define MMR_TREE_EMPTY 2
+
+/// Returns a handle to an empty tree for the consensus branch `cbranch`, or
+/// `nullptr` if `cbranch` is not a valid consensus branch ID. The handle must be
+/// freed with `librustzcash_mmr_tree_free`.
+MmrTree* librustzcash_mmr_tree_new(uint32_t cbranch);
+
+/// Appends the leaf at `leaf_ptr` to the tree of `handle`.
+///
+/// Returns `MMR_TREE_OK` on success, or `MMR_TREE_INVALID` if the leaf is
+/// invalid or a pointer is null, in which case the tree is unaltered.
+uint32_t librustzcash_mmr_tree_append(
+    MmrTree* handle,
+    const HistoryNode* leaf_ptr);
+
+/// Deletes the most recently appended leaf from the tree of `handle`.
+///
+/// Returns `MMR_TREE_OK` on success, `MMR_TREE_EMPTY` if the tree has no
+/// leaves, or `MMR_TREE_INVALID` if `handle` is null.
+uint32_t librustzcash_mmr_tree_delete(MmrTree* handle);
+
+/// Writes the 32-byte root of the tree of `handle` to `rt_ret`. The root is
+/// only computed on the first call after the tree is mutated, and is otherwise
+/// returned from the handle's cache.
+///
+/// Returns `MMR_TREE_OK` on success, `MMR_TREE_EMPTY` if the tree has no
+/// leaves, or `MMR_TREE_INVALID` if a pointer is null.
+uint32_t librustzcash_mmr_tree_root(
+    MmrTree* handle,
+    unsigned char* rt_ret);
+
+/// Returns an estimate of the heap bytes that `handle` holds: the handle
+/// itself, which includes its cached root, and the nodes of its tree. Returns
+/// 0 if `handle` is null.
+size_t librustzcash_mmr_tree_memory_bytes(const MmrTree* handle);
+
+/// Frees the handle from `librustzcash_mmr_tree_new`.
+void librustzcash_mmr_tree_free(MmrTree* handle);
+
+/// Leaves that arrive out of order, such as during parallel block download, of
+/// which the contiguous prefix from leaf 0 is appended to a tree as soon as
+/// any gap before them is filled.
+struct MmrOutOfOrder;
+typedef struct MmrOutOfOrder MmrOutOfOrder;
+
+#define MMR_OOO_OK 0
+#define MMR_OOO_DUPLICATE 1
+#define MMR_OOO_INVALID 2
+#define MMR_OOO_EMPTY 3
+
+/// Returns the state for receiving the leaves of a tree for the consensus
+/// branch `cbranch` in any order, or `nullptr` if `cbranch` is not a valid
+/// consensus branch ID. The state must be freed with
+/// `librustzcash_mmr_ooo_free`.
+MmrOutOfOrder* librustzcash_mmr_ooo_new(uint32_t cbranch);
+
+/// Adds the leaf at `leaf_ptr` with the leaf index (not node index) `index` to
+/// `state`. If it fills the first gap, it and the leaves that follow it without
+/// a gap are appended to the contiguous prefix.
+///
+/// Returns `MMR_OOO_OK` on success, `MMR_OOO_DUPLICATE` if a leaf with the
+/// index has already been added (in which case it is not replaced), or
+/// `MMR_OOO_INVALID` if the leaf is invalid or a pointer is null.
+uint32_t librustzcash_mmr_ooo_add(
+    MmrOutOfOrder* state,
+    uint32_t index,
+    const HistoryNode* leaf_ptr);
+
+/// Writes the 32-byte root of the tree of the contiguous prefix of the leaves
+/// added to `state` to `rt_ret`, and the number of leaves in the prefix to
+/// `contiguous_len_ret`.
+///
+/// Returns `MMR_OOO_OK` on success, `MMR_OOO_EMPTY` (after writing the length
+/// of 0) if leaf 0 hasn't been added, or `MMR_OOO_INVALID` if a pointer is null.
+uint32_t librustzcash_mmr_ooo_contiguous_root(
+    const MmrOutOfOrder* state,
+    unsigned char* rt_ret,
+    uint32_t* contiguous_len_ret);
+
+/// Frees the state from `librustzcash_mmr_ooo_new`.
+void librustzcash_mmr_ooo_free(MmrOutOfOrder* state);
+
+/// Writes the root of the tree with the `leaf_count` leaves at `leaves_ptr` to
+/// `root_ret`.
+///
+/// The tree is built a level at a time, which with the `simd` feature hashes
+/// each level's parents in one batch. This is much faster than appending the
+/// leaves one at a time, but only produces the final root.
+///
+/// Returns 0 on success, or 1 if any input is invalid, in which case
+/// `root_ret` is unaltered.
+uint32_t librustzcash_mmr_root_from_leaves(
+    uint32_t cbranch,
+    const HistoryNode* leaves_ptr,
+    size_t leaf_count,
+    unsigned char* root_ret);
+
+#define MMR_DIFF_MASK_OK 0
+#define MMR_DIFF_MASK_INVALID 1
+#define MMR_DIFF_MASK_BUFFER_TOO_SMALL 2
+
+/// Writes a mask of the nodes that differ between the tree with the
+/// `a_leaf_count` leaves at `a_leaves_ptr` and the tree with the `b_leaf_count`
+/// leaves at `b_leaves_ptr` to `mask_out`, for rendering a diff of the trees.
+/// Bit `index % 8` of byte `index / 8` is set if the node at `index` (in array
+/// representation) differs, including if only one of the trees has it. The
+/// mask covers the longer tree, and any remaining bits of its last byte are
+/// clear.
+///
+/// Returns `MMR_DIFF_MASK_OK` on success, `MMR_DIFF_MASK_BUFFER_TOO_SMALL`
+/// (after writing the length in bytes) if `cap` is too small, or
+/// `MMR_DIFF_MASK_INVALID` if either tree is empty or can't be built from its
+/// leaves, or any other input is invalid.
+uint32_t librustzcash_mmr_diff_mask(
+    uint32_t cbranch,
+    const HistoryNode* a_leaves_ptr,
+    size_t a_leaf_count,
+    const HistoryNode* b_leaves_ptr,
+    size_t b_leaf_count,
+    unsigned char* mask_out,
+    size_t cap,
+    size_t* len_ret);
+
+#define MMR_LEAVES_DIVERGE 0
+#define MMR_LEAVES_PREFIX 1
+#define MMR_LEAVES_INVALID 2
+
+/// Writes the index of the first leaf that differs between the `a_count`
+/// leaves at `a_leaves` and the `b_count` leaves at `b_leaves` to `index_ret`,
+/// for finding where two chains fork during a reorg. The leaves are compared
+/// byte for byte, so any bytes past the end of their encodings must be zeroed.
+///
+/// Returns `MMR_LEAVES_DIVERGE` if a leaf differs, `MMR_LEAVES_PREFIX` (after
+/// writing the shorter leaf count) if one chain's leaves are a prefix of the
+/// other's, or `MMR_LEAVES_INVALID` if a required pointer is null.
+uint32_t librustzcash_mmr_first_leaf_divergence(
+    const HistoryNode* a_leaves,
+    size_t a_count,
+    const HistoryNode* b_leaves,
+    size_t b_count,
+    size_t* index_ret);
+
+#define MMR_EXTENSION_MISMATCH 0
+#define MMR_EXTENSION_MATCH 1
+#define MMR_EXTENSION_INVALID_INPUT 2
+
+/// Checks that appending the `leaf_count` leaves at `leaves_ptr` to the tree of
+/// length `t_len` with the given peaks yields a tree with the root at
+/// `new_root`, so that the new tree extends the old one. `ni_ptr` must list
+/// exactly the peak indices, in order.
+///
+/// Returns `MMR_EXTENSION_MATCH` if it does, `MMR_EXTENSION_MISMATCH` if it
+/// doesn't, or `MMR_EXTENSION_INVALID_INPUT` if the inputs are invalid or the
+/// provided nodes are not the peaks of the old tree.
+uint32_t librustzcash_mmr_is_extension(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t p_len,
+    const HistoryNode* leaves_ptr,
+    size_t leaf_count,
+    const unsigned char* new_root);
+
+/// Writes the encoding of the node at `index` to `out`, returning 0 on
+/// success or any other value if the node is not available.
+typedef uint32_t (*mmr_fetch_node_t)(uint32_t index, HistoryNode* out);
+
+#define MMR_VERIFY_LEAF_MISMATCH 0
+#define MMR_VERIFY_LEAF_MATCH 1
+#define MMR_VERIFY_LEAF_INVALID_INPUT 2
+#define MMR_VERIFY_LEAF_FETCH_FAILED 3
+
+/// Checks that the leaf at `leaf_index` (in array representation) is part of
+/// a tree of length `t_len` with the root at `root_ptr`, fetching only the
+/// nodes it needs with `fetch_cb`: the leaf, the siblings along its path up to
+/// its peak, and the other peaks, each of them once.
+///
+/// Returns `MMR_VERIFY_LEAF_MATCH` if it is, `MMR_VERIFY_LEAF_MISMATCH` if it
+/// isn't, `MMR_VERIFY_LEAF_FETCH_FAILED` if the callback could not provide a
+/// node, or `MMR_VERIFY_LEAF_INVALID_INPUT` if any input is invalid or the
+/// node at `leaf_index` is not a leaf of the tree.
+uint32_t librustzcash_mmr_verify_leaf_lazy(
+    uint32_t cbranch,
+    uint32_t t_len,
+    uint32_t leaf_index,
+    const unsigned char* root_ptr,
+    mmr_fetch_node_t fetch_cb);
+
+#define MMR_PATH_INDICES_OK 0
+#define MMR_PATH_INDICES_INVALID 1
+#define MMR_PATH_INDICES_BUFFER_TOO_SMALL 2
+
+/// Writes the indices, in ascending order, of the nodes that a proof for the
+/// leaf at `leaf_index` of a tree of length `t_len` is computed from, so that
+/// exactly those nodes can be loaded before calling
+/// `librustzcash_mmr_multiproof`: the leaf, each of its ancestors up to its
+/// peak, the sibling of each node on that path, and the other peaks.
+///
+/// Returns `MMR_PATH_INDICES_OK` on success,
+/// `MMR_PATH_INDICES_BUFFER_TOO_SMALL` (after writing the count to `len_ret`)
+/// if `cap` is too small, or `MMR_PATH_INDICES_INVALID` if the node is not a
+/// leaf of the tree or a pointer is null.
+uint32_t librustzcash_mmr_leaf_path_indices(
+    uint32_t t_len,
+    uint32_t leaf_index,
+    uint32_t* out,
+    size_t cap,
+    size_t* len_ret);
+
+#define MMR_MULTIPROOF_OK 0
+#define MMR_MULTIPROOF_INVALID 1
+#define MMR_MULTIPROOF_BUFFER_TOO_SMALL 2
+
+/// Writes a proof that each of the `count` leaves at `leaf_indices_ptr` (in
+/// array representation, strictly ascending) is part of a tree of length
+/// `t_len`, for `librustzcash_mmr_verify_multiproof`. Nodes shared between the
+/// leaves' paths are included once, and nodes that can be computed from the
+/// rest of the proof are left out.
+///
+/// The `n_len` provided nodes must include the leaves, the siblings along
+/// their paths that are not on another path, and the peaks that contain none
+/// of the leaves.
+///
+/// Returns `MMR_MULTIPROOF_OK` on success, `MMR_MULTIPROOF_BUFFER_TOO_SMALL`
+/// (after writing the length) if `cap` is too small, or
+/// `MMR_MULTIPROOF_INVALID` if any input is invalid or a needed node is
+/// missing.
+uint32_t librustzcash_mmr_multiproof(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryNode* n_ptr,
+    size_t n_len,
+    const uint32_t* leaf_indices_ptr,
+    size_t count,
+    unsigned char* proof_out,
+    size_t cap,
+    size_t* len_ret);
+
+#define MMR_VERIFY_MULTIPROOF_MISMATCH 0
+#define MMR_VERIFY_MULTIPROOF_MATCH 1
+#define MMR_VERIFY_MULTIPROOF_INVALID_INPUT 2
+
+/// Checks that each of the `count` leaves at `leaf_indices_ptr` is part of a
+/// tree of length `t_len` with the root at `root_ptr`, using a proof written
+/// by `librustzcash_mmr_multiproof` for the same leaves.
+///
+/// Returns `MMR_VERIFY_MULTIPROOF_MATCH` if they are,
+/// `MMR_VERIFY_MULTIPROOF_MISMATCH` if they aren't, or
+/// `MMR_VERIFY_MULTIPROOF_INVALID_INPUT` if any input is invalid or the proof
+/// does not match the leaf indices.
+uint32_t librustzcash_mmr_verify_multiproof(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* leaf_indices_ptr,
+    size_t count,
+    const unsigned char* proof_ptr,
+    size_t proof_len,
+    const unsigned char* root_ptr);
+
+#define MMR_VERIFY_BY_HASH_MISMATCH 0
+#define MMR_VERIFY_BY_HASH_MATCH 1
+#define MMR_VERIFY_BY_HASH_INVALID_INPUT 2
+#define MMR_VERIFY_BY_HASH_LEAF_MISMATCH 3
+
+/// Checks that the leaf with the 32-byte hash at `leaf_hash` is the leaf at
+/// `leaf_index` of a tree of length `t_len` with the root at `root_ptr`, using
+/// a proof written by `librustzcash_mmr_multiproof` for that leaf alone.
+///
+/// A parent node commits to the full encodings of its children, so the proof
+/// still carries the leaf encoding, which is checked to have the given hash;
+/// the caller only needs to know the hash.
+///
+/// Returns `MMR_VERIFY_BY_HASH_MATCH` if the leaf is in the tree,
+/// `MMR_VERIFY_BY_HASH_MISMATCH` if it isn't, `MMR_VERIFY_BY_HASH_LEAF_MISMATCH`
+/// if the proof's leaf does not have the given hash, or
+/// `MMR_VERIFY_BY_HASH_INVALID_INPUT` if any input is invalid or the proof is
+/// not for the leaf index.
+uint32_t librustzcash_mmr_verify_proof_by_hash(
+    uint32_t cbranch,
+    uint32_t t_len,
+    uint32_t leaf_index,
+    const unsigned char* root_ptr,
+    const unsigned char* leaf_hash,
+    const unsigned char* proof_ptr,
+    size_t proof_len);
+
+#define MMR_FRONTIER_OK 0
+#define MMR_FRONTIER_INVALID 1
+#define MMR_FRONTIER_BUFFER_TOO_SMALL 2
+
+/// Writes the frontier of the tree of length `t_len` with the `p_len` peaks at
+/// `n_ptr` to `out`: its leaf count, followed by the encodings of its peaks.
+/// This is the least state that a tree that is only appended to needs to keep,
+/// and is extended with `librustzcash_mmr_append_frontier`. The empty tree (with
+/// `t_len` and `p_len` both 0) has a frontier too.
+///
+/// Returns `MMR_FRONTIER_OK` on success, `MMR_FRONTIER_BUFFER_TOO_SMALL` (after
+/// writing the length) if `cap` is too small, or `MMR_FRONTIER_INVALID` if any
+/// input is invalid or the nodes are not exactly the peaks of the tree.
+uint32_t librustzcash_mmr_frontier(
+    uint32_t cbranch,
+    uint32_t t_len,
+    const uint32_t* ni_ptr,
+    const HistoryEntry* n_ptr,
+    size_t p_len,
+    unsigned char* out,
+    size_t cap,
+    size_t* len_ret);
+
+/// Appends the leaf at `nn_ptr` to the tree with the `frontier_len`-byte
+/// frontier at `frontier_ptr`, writing the root of the extended tree to
+/// `rt_ret` and its frontier to `out`. The root is the same as that from
+/// `librustzcash_mmr_append` given the tree's peaks.
+///
+/// Returns the same results as `librustzcash_mmr_frontier`. If `cap` is too
+/// small, only the length of the new frontier is written.
+uint32_t librustzcash_mmr_append_frontier(
+    uint32_t cbranch,
+    const unsigned char* frontier_ptr,
+    size_t frontier_len,
+    const HistoryNode* nn_ptr,
+    unsigned char* rt_ret,
+    unsigned char* out,
+    size_t cap,
+    size_t* len_ret);
+
+#define MMR_BALANCED_ROOT_OK 0
+#define MMR_BALANCED_ROOT_INVALID 1
+
+/// Writes the root of a balanced binary Merkle tree over the `count` 32-byte
+/// leaf hashes at `leaf_hashes_ptr` to `rt_ret`, hashing each pair of children
+/// with the BLAKE2b personalization of the history tree for `cbranch`. A node
+/// without a sibling is carried up a level unchanged.
+///
+/// This is not a history tree root (an MMR bags its peaks into the root, and
+/// its parents commit to their children's encodings rather than hashes); it is
+/// for other commitments over a set of hashes.
+///
+/// Returns `MMR_BALANCED_ROOT_OK` on success, or `MMR_BALANCED_ROOT_INVALID`
+/// if `cbranch` is invalid, `count` is 0 or a pointer is null, in which case
+/// `rt_ret` is unaltered.
+uint32_t librustzcash_mmr_balanced_root(
+    uint32_t cbranch,
+    const unsigned char* leaf_hashes_ptr,
+    size_t count,
+    unsigned char* rt_ret);
+
+#define MMR_DEEP_VALIDATE_OK 0xFFFFFFFF
+#define MMR_DEEP_VALIDATE_INVALID_INPUT 0xFFFFFFFE
+
+/// Checks that every internal node of the tree whose `count` nodes (in any
+/// order) are at `all_indices` and `all_nodes` is the combination of its
+/// children. Every node of the tree must be provided, and the tree's length is
+/// `count`. Unlike `librustzcash_mmr_self_check`, which only checks the nodes
+/// whose children happen to be provided, this is meant for validating an
+/// archival copy of the whole tree.
+///
+/// Returns the index of the first internal node that is not the combination
+/// of its children, `MMR_DEEP_VALIDATE_OK` if there is none, or
+/// `MMR_DEEP_VALIDATE_INVALID_INPUT` if the nodes are not exactly the nodes of
+/// a tree, a node can't be decoded, or a pointer is null.
+uint32_t librustzcash_mmr_deep_validate(
+    uint32_t cbranch,
+    const uint32_t* all_indices,
+    const HistoryEntry* all_nodes,
+    size_t count);
+
+/// Writes the history tree diagnostic counters to `stats_ret`, unless it
+/// is null.
+void librustzcash_mmr_stats(MmrStats* stats_ret);
 #ifdef __cplusplus
 }
 #endif
@@ -5,12 +5,64 @@
 #ifndef ZCASH_RUST_INCLUDE_RUST_INIT_H
 #define ZCASH_RUST_INCLUDE_RUST_INIT_H
 
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
 #ifdef __cplusplus
 extern "C" {
 #endif
 
-/// Initializes the global Rayon threadpool.
-void zcashd_init_rayon_threadpool();
+/// Initializes the global Rayon threadpool, which all parallelism in the
+/// Rust code uses.
+///
+/// `num_threads` is the number of threads, or 0 for one per core. Each thread
+/// is named `<thread_name_prefix>-<i>`. If `lower_priority` is set, the
+/// threads run at a reduced scheduling priority (on Linux only).
+///
+/// Returns false if the prefix is not valid UTF-8, or if the threadpool has
+/// already been initialized (including implicitly, by using it).
+bool librustzcash_init_rayon(
+    size_t num_threads,
+    const char* thread_name_prefix,
+    bool lower_priority);
+
+/// Sets the number of threads (or 0 for one per core) that Orchard proofs are
+/// created with, unless a build requests otherwise. The proofs are created on
+/// a dedicated threadpool, so that their parallelism is bounded separately
+/// from the global Rayon threadpool. If this is not called, the default is the
+/// size of the global threadpool.
+///
+/// This must be called before `librustzcash_init_zksnark_params`, which
+/// builds the Orchard proving key on the same threadpool. Returns false if it
+/// has already been called, or if the default has already been used.
+bool librustzcash_init_orchard_proving(size_t num_threads);
+
+/// Sets the approximate number of bytes that the shielded proofs and
+/// signatures queued in a block's batch validators may take up. Once they
+/// exceed it, the queued bundles are validated before more are queued, rather
+/// than at the end of the block. If this is not called, the budget is 64 MiB.
+/// It is a soft limit, which the queue can exceed by up to one transaction's
+/// bundles.
+///
+/// This must be called before `batch_validators_init`. Returns false if it has
+/// already been called, or if the default has already been used.
+bool librustzcash_init_batch_validation(size_t memory_budget);
+
+/// Installs the parameters of the network that zcashd is running on, for the
+/// Rust code that depends on them. Until this is called, the mainnet
+/// parameters are used.
+///
+/// `network` is "main", "test" or "regtest". `activation_heights` must have
+/// an entry for each network upgrade from Overwinter to NU5 in order, which is
+/// negative if the upgrade never activates.
+///
+/// Returns false if the arguments are invalid, or if this has already been
+/// called.
+bool librustzcash_init_network_params(
+    const char* network,
+    const int32_t* activation_heights,
+    size_t activation_heights_len);
 
 #ifdef __cplusplus
 }
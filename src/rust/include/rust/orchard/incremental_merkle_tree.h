@@ -44,6 +44,16 @@ OrchardMerkleFrontierPtr* orchard_merkle_frontier_parse(
         void* stream,
         read_callback_t read_cb);
 
+// Parses an Orchard Merkle frontier from a stream containing a tree
+// in the zcash_primitives::merkle_tree::CommitmentTree sparse encoding.
+// If parsing fails, or the legacy tree is not internally consistent,
+// this will return the null pointer.
+//
+// Memory allocated to the resulting value must be manually freed.
+OrchardMerkleFrontierPtr* orchard_merkle_frontier_parse_legacy(
+        void* stream,
+        read_callback_t read_cb);
+
 // Serializes an Orchard Merkle frontier to a stream.
 //
 // Returns `false` if an error occurs while writing to the stream.
@@ -89,6 +99,151 @@ size_t orchard_merkle_frontier_dynamic_mem_usage(
 void orchard_merkle_tree_empty_root(
         unsigned char* digest_ret);
 
+// Computes the root of an empty subtree of the given depth, where
+// depth 0 is the uncommitted leaf value.
+//
+// Returns `false` if `depth` is larger than the depth of the tree.
+bool orchard_merkle_tree_empty_root_at_depth(
+        size_t depth,
+        unsigned char* digest_ret);
+
+/// Pointer to a history of recent Orchard note commitment tree roots
+struct OrchardAnchorHistoryPtr;
+typedef struct OrchardAnchorHistoryPtr OrchardAnchorHistoryPtr;
+
+// Create an empty Orchard anchor history that retains the roots of
+// at most `max_len` blocks.
+//
+// Memory allocated to the resulting value must be manually freed.
+OrchardAnchorHistoryPtr* orchard_anchor_history_new(size_t max_len);
+
+// Clones the given Orchard anchor history. Both the original and the
+// newly allocated history need to be freed independently.
+OrchardAnchorHistoryPtr* orchard_anchor_history_clone(
+        const OrchardAnchorHistoryPtr* history_ptr);
+
+// Free the memory allocated for the given Orchard anchor history.
+void orchard_anchor_history_free(
+        OrchardAnchorHistoryPtr* history_ptr);
+
+// Parses an Orchard anchor history from a stream. If parsing fails,
+// this will return the null pointer.
+//
+// Memory allocated to the resulting value must be manually freed.
+OrchardAnchorHistoryPtr* orchard_anchor_history_parse(
+        void* stream,
+        read_callback_t read_cb);
+
+// Serializes an Orchard anchor history to a stream.
+//
+// Returns `false` if an error occurs while writing to the stream.
+bool orchard_anchor_history_serialize(
+        const OrchardAnchorHistoryPtr* history_ptr,
+        void* stream,
+        write_callback_t write_cb);
+
+// Records the Orchard note commitment tree root as of the end of the
+// block at `height`, forgetting the oldest root if the history is full.
+//
+// Returns `false` if the root is invalid, or if `height` is not greater
+// than the height of the most recently recorded root.
+bool orchard_anchor_history_push(
+        OrchardAnchorHistoryPtr* history_ptr,
+        uint32_t height,
+        const unsigned char* root);
+
+// Forgets the roots of all blocks above `height`, for use when
+// disconnecting blocks.
+void orchard_anchor_history_rewind_to(
+        OrchardAnchorHistoryPtr* history_ptr,
+        uint32_t height);
+
+// Returns `true` if `anchor` is the root as of some block within the
+// retained window, and writes the height of the most recent such block
+// to `height_ret` if it is not null.
+bool orchard_anchor_history_contains(
+        const OrchardAnchorHistoryPtr* history_ptr,
+        const unsigned char* anchor,
+        uint32_t* height_ret);
+
+/// Pointer to a checkpointed Orchard Merkle frontier
+struct OrchardDeepFrontierPtr;
+typedef struct OrchardDeepFrontierPtr OrchardDeepFrontierPtr;
+
+// Create a checkpointed Orchard Merkle frontier starting from a copy of
+// the given frontier, retaining at most `max_checkpoints` checkpoints.
+//
+// Memory allocated to the resulting value must be manually freed.
+OrchardDeepFrontierPtr* orchard_deep_frontier_new(
+        const OrchardMerkleFrontierPtr* tree_ptr,
+        size_t max_checkpoints);
+
+// Free the memory allocated for the given checkpointed Orchard Merkle
+// frontier.
+void orchard_deep_frontier_free(
+        OrchardDeepFrontierPtr* frontier_ptr);
+
+// Parses a checkpointed Orchard Merkle frontier, including its
+// checkpoints, from a stream. If parsing fails, this will return the
+// null pointer.
+//
+// Memory allocated to the resulting value must be manually freed.
+OrchardDeepFrontierPtr* orchard_deep_frontier_parse(
+        void* stream,
+        read_callback_t read_cb);
+
+// Serializes a checkpointed Orchard Merkle frontier, including its
+// checkpoints, to a stream.
+//
+// Returns `false` if an error occurs while writing to the stream.
+bool orchard_deep_frontier_serialize(
+        const OrchardDeepFrontierPtr* frontier_ptr,
+        void* stream,
+        write_callback_t write_cb);
+
+// Returns a copy of the current state of the checkpointed frontier.
+//
+// Memory allocated to the resulting value must be manually freed.
+OrchardMerkleFrontierPtr* orchard_deep_frontier_current(
+        const OrchardDeepFrontierPtr* frontier_ptr);
+
+// For each action in the provided bundle, append its commitment to
+// the checkpointed frontier.
+//
+// Returns `true` if the append succeeds, `false` if the tree is full.
+bool orchard_deep_frontier_append_bundle(
+        OrchardDeepFrontierPtr* frontier_ptr,
+        const OrchardBundlePtr* bundle);
+
+// Records the current state of the frontier as its state at the end of
+// the block at `height`, forgetting the oldest checkpoint if the
+// maximum number are retained.
+//
+// Returns `false` if `height` is not greater than the height of the
+// most recent checkpoint.
+bool orchard_deep_frontier_checkpoint(
+        OrchardDeepFrontierPtr* frontier_ptr,
+        uint32_t height);
+
+// Restores the frontier to its state at the checkpoint for the block at
+// `height`, discarding any later checkpoints.
+//
+// Returns `false` without modifying the frontier if there is no
+// checkpoint at `height`.
+bool orchard_deep_frontier_rewind(
+        OrchardDeepFrontierPtr* frontier_ptr,
+        uint32_t height);
+
+// Discards all but the `keep` most recent checkpoints.
+void orchard_deep_frontier_prune_checkpoints(
+        OrchardDeepFrontierPtr* frontier_ptr,
+        size_t keep);
+
+// Computes the root of the current state of the checkpointed frontier.
+void orchard_deep_frontier_root(
+        const OrchardDeepFrontierPtr* frontier_ptr,
+        unsigned char* digest_ret);
+
 #ifdef __cplusplus
 }
 #endif
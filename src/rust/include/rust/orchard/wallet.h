@@ -170,6 +170,56 @@ bool orchard_wallet_append_bundle_commitments(
         const OrchardBundlePtr* bundle
         );
 
+/**
+ * A C struct used to pass a transaction of a block to be rescanned to Rust. This must
+ * have the same in-memory representation as the `FFIScanTx` type in
+ * wallet_scanner.rs.
+ *
+ * `bundle` may be null if the transaction has no Orchard bundle. `callbackReceiver` is
+ * passed to the callbacks of `orchard_wallet_rescan_blocks` for this transaction.
+ */
+struct RawOrchardScanTx {
+    unsigned char txid[32];
+    const OrchardBundlePtr* bundle;
+    void* callbackReceiver;
+};
+static_assert(sizeof(RawOrchardScanTx) == 48, "RawOrchardScanTx struct should be 48 bytes.");
+
+/**
+ * A C struct used to pass a block to be rescanned to Rust. This must have the same
+ * in-memory representation as the `FFIScanBlock` type in wallet_scanner.rs.
+ */
+struct RawOrchardScanBlock {
+    uint32_t height;
+    const RawOrchardScanTx* txs;
+    size_t txsLen;
+};
+static_assert(sizeof(RawOrchardScanBlock) == 24, "RawOrchardScanBlock struct should be 24 bytes.");
+
+/**
+ * Rescans the given blocks for notes belonging to the wallet. This has the same effect
+ * as calling `orchard_wallet_add_notes_from_bundle` for each transaction of a block,
+ * then `orchard_wallet_checkpoint` and `orchard_wallet_append_bundle_commitments` for
+ * the block, block by block, except that the notes are trial-decrypted in parallel.
+ *
+ * The callbacks are called as `orchard_wallet_add_notes_from_bundle` calls them, with
+ * the `callbackReceiver` of each transaction that has an Orchard bundle.
+ *
+ * At most `maxInFlightBlocks` blocks (and at least two) are decrypted or being applied
+ * to the wallet at a time; if it is 0, a default limit is used.
+ *
+ * Returns `false` if a block could not be applied to the wallet, in which case the
+ * blocks after it are not scanned.
+ */
+bool orchard_wallet_rescan_blocks(
+        OrchardWalletPtr* wallet,
+        const RawOrchardScanBlock* blocks,
+        size_t blocksLen,
+        size_t maxInFlightBlocks,
+        push_action_ivk_callback_t push_cb,
+        push_spend_action_idx_callback_t spend_cb
+        );
+
 /**
  * Returns the root of the wallet's note commitment tree.
  */
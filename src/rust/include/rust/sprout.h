@@ -0,0 +1,142 @@
+// Copyright (c) 2022 The Zcash developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or https://www.opensource.org/licenses/mit-license.php .
+
+#ifndef ZCASH_RUST_INCLUDE_RUST_SPROUT_H
+#define ZCASH_RUST_INCLUDE_RUST_SPROUT_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+struct SproutBatchValidatorPtr;
+typedef struct SproutBatchValidatorPtr SproutBatchValidatorPtr;
+
+/// Initializes a batch validator for Sprout JoinSplit proofs.
+///
+/// Please free this with `sprout_batch_validation_free` when you are done with
+/// it.
+SproutBatchValidatorPtr* sprout_batch_validation_init();
+
+/// Frees a batch validator returned from `sprout_batch_validation_init`.
+void sprout_batch_validation_free(SproutBatchValidatorPtr* batch);
+
+/// Adds the Groth16 proof of a JoinSplit to the batch. The public inputs are
+/// computed from the given fields of the JoinSplit and the transaction's
+/// `joinSplitPubKey`.
+///
+/// Returns `false` if the proof is not a valid encoding, in which case nothing
+/// is queued. If `batch` is null, this does nothing and returns `true`.
+bool sprout_batch_add_joinsplit(
+    SproutBatchValidatorPtr* batch,
+    const unsigned char* proof,
+    const unsigned char* anchor,
+    const unsigned char* random_seed,
+    const unsigned char* nf1,
+    const unsigned char* nf2,
+    const unsigned char* mac1,
+    const unsigned char* mac2,
+    const unsigned char* cm1,
+    const unsigned char* cm2,
+    uint64_t vpub_old,
+    uint64_t vpub_new,
+    const unsigned char* joinsplit_pub_key);
+
+/// Validates the batch.
+///
+/// - Returns `true` if `batch` is null.
+/// - Returns `false` if any proof in the batch is invalid.
+bool sprout_batch_validate(SproutBatchValidatorPtr* batch);
+
+#define SPROUT_BATCH_NO_FAILURE UINT32_MAX
+
+/// Returns the position, in the order they were added, of the first invalid
+/// proof found by `sprout_batch_validate`, or `SPROUT_BATCH_NO_FAILURE` if
+/// none was found.
+uint32_t sprout_batch_first_failure(const SproutBatchValidatorPtr* batch);
+
+/// The length of the encoding of a `JSDescription` in a v4 transaction.
+#define SPROUT_JS_DESCRIPTION_SIZE 1698
+
+/// Pointer to a Rust-allocated builder for the JoinSplits of a transaction.
+struct SproutBuilderPtr;
+typedef struct SproutBuilderPtr SproutBuilderPtr;
+
+/// Pointer to the Rust-allocated JoinSplits built by a `SproutBuilderPtr`.
+struct SproutJoinSplitsPtr;
+typedef struct SproutJoinSplitsPtr SproutJoinSplitsPtr;
+
+/// Creates a builder for the JoinSplits of a transaction with the given
+/// `joinSplitPubKey`.
+///
+/// `anchor_tree` is the encoding of the `SproutMerkleTree` as of the anchor of
+/// the notes that will be spent, which chained JoinSplits extend. It may be
+/// null if no notes are spent, or if the tree is not known, in which case
+/// building fails if a JoinSplit needs to be chained.
+///
+/// Returns null if `anchor_tree` can't be parsed. Please free this with
+/// `sprout_builder_free`, or pass it to `sprout_builder_build`.
+SproutBuilderPtr* sprout_builder_new(
+    const unsigned char* joinsplit_pub_key,
+    const unsigned char* anchor_tree,
+    size_t anchor_tree_len);
+
+/// Frees a builder returned from `sprout_builder_new`.
+void sprout_builder_free(SproutBuilderPtr* builder);
+
+/// Adds the Sprout note (`value`, `rho`, `r`) to be spent with the spending
+/// key `a_sk`. `witness` is the encoding of its `SproutWitness`.
+///
+/// Returns `false` if the note can't be spent with `a_sk`, if the witness is
+/// not to the note, or if its anchor differs from that of the notes already
+/// added.
+bool sprout_builder_add_sprout_input(
+    SproutBuilderPtr* builder,
+    const unsigned char* a_sk,
+    uint64_t value,
+    const unsigned char* rho,
+    const unsigned char* r,
+    const unsigned char* witness,
+    size_t witness_len);
+
+/// Adds a payment of `value` to the Sprout address (`a_pk`, `pk_enc`).
+///
+/// `memo` is a pointer to the 512-byte memo field encoding, or `null` for "no
+/// memo".
+bool sprout_builder_add_output(
+    SproutBuilderPtr* builder,
+    const unsigned char* a_pk,
+    const unsigned char* pk_enc,
+    uint64_t value,
+    const unsigned char* memo);
+
+/// Builds and proves the JoinSplits, chaining the change of each into the next
+/// and releasing the value that is left with `vpub_new` in the last.
+///
+/// Returns null if an error occurs.
+///
+/// `builder` is always freed by this method.
+SproutJoinSplitsPtr* sprout_builder_build(SproutBuilderPtr* builder);
+
+/// Returns the number of JoinSplits built.
+size_t sprout_joinsplits_len(const SproutJoinSplitsPtr* joinsplits);
+
+/// Writes the JoinSplit at `index` to `js_ret`, which must point to
+/// `SPROUT_JS_DESCRIPTION_SIZE` bytes, in the encoding of `JSDescription` for
+/// v4 transactions.
+void sprout_joinsplits_get(
+    const SproutJoinSplitsPtr* joinsplits,
+    size_t index,
+    unsigned char* js_ret);
+
+/// Frees JoinSplits returned from `sprout_builder_build`.
+void sprout_joinsplits_free(SproutJoinSplitsPtr* joinsplits);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // ZCASH_RUST_INCLUDE_RUST_SPROUT_H
@@ -5,6 +5,10 @@
 #ifndef ZCASH_RUST_INCLUDE_RUST_TEST_HARNESS_H
 #define ZCASH_RUST_INCLUDE_RUST_TEST_HARNESS_H
 
+#include "rust/orchard/keys.h"
+#include "rust/orchard/wallet.h"
+#include "rust/streams.h"
+
 #ifdef __cplusplus
 extern "C" {
 #endif
@@ -21,6 +25,121 @@ void zcash_test_harness_random_jubjub_base(
 void zcash_test_harness_random_jubjub_point(
     unsigned char* ret);
 
+// The following functions are only available when librustzcash is built with
+// the `test-dependencies` feature, which it is when the tests are enabled.
+
+/// A pointer to a fake transaction that creates an Orchard note, of which only
+/// the txid and Orchard bundle are known. Its proof and signatures are
+/// placeholders, so it is only useful for building wallet state.
+struct FakeOrchardNotePtr;
+typedef struct FakeOrchardNotePtr FakeOrchardNotePtr;
+
+/// Creates a fake transaction with an Orchard note of `value` to the first
+/// external address of `fvk`. Everything is derived from `seed`, so the same
+/// arguments always give the same note and txid.
+///
+/// `seed` must point to a 32-byte array. Returns `nullptr` if `value` is too
+/// large.
+FakeOrchardNotePtr* zcash_test_harness_fake_orchard_note(
+    const OrchardFullViewingKeyPtr* fvk,
+    uint64_t value,
+    const unsigned char* seed);
+
+/// Frees a fake note from `zcash_test_harness_fake_orchard_note`.
+void zcash_test_harness_fake_orchard_note_free(FakeOrchardNotePtr* note);
+
+/// Writes the txid of the fake note's transaction to `txid_ret`, which must
+/// point to a 32-byte array.
+void zcash_test_harness_fake_orchard_note_txid(
+    const FakeOrchardNotePtr* note,
+    unsigned char* txid_ret);
+
+/// Adds the `notes_len` fake transactions at `notes` to `wallet`, in order, as
+/// the transactions of the block at `blockHeight`, as if the wallet had scanned
+/// the block: the notes that the wallet's keys decrypt are recorded, every
+/// commitment is appended to the note commitment tree (witnessing the wallet's
+/// notes), and the tree is checkpointed.
+///
+/// Returns `false` if the block does not follow the wallet's last checkpoint.
+bool zcash_test_harness_apply_fake_block(
+    OrchardWalletPtr* wallet,
+    const FakeOrchardNotePtr* const* notes,
+    size_t notes_len,
+    uint32_t blockHeight);
+
+/// Returns an Orchard address derived from the 32-byte `seed`.
+OrchardRawAddressPtr* zcash_test_harness_random_orchard_address(
+    const unsigned char* seed);
+
+/// A pointer to the outcome of `zcash_test_harness_mmr_random_ops`.
+struct MmrOpsReportPtr;
+typedef struct MmrOpsReportPtr MmrOpsReportPtr;
+
+/// Performs `op_count` random operations on a history tree for the consensus
+/// branch `cbranch` through `librustzcash_mmr_append` and
+/// `librustzcash_mmr_delete`, storing the nodes as zcashd does. The operations
+/// are mostly appends, with occasional reorgs of up to 8 blocks, and are
+/// derived from the 32-byte `seed`.
+///
+/// After every operation, the stored nodes and root are checked against a Rust
+/// history tree that performed the same operations, and the root is checked to
+/// be reconstructible from the stored peaks alone. The run stops at the first
+/// operation that fails a check.
+///
+/// Returns `nullptr` if `cbranch` is not a consensus branch with a history
+/// tree.
+MmrOpsReportPtr* zcash_test_harness_mmr_random_ops(
+    const unsigned char* seed,
+    uint32_t op_count,
+    uint32_t cbranch);
+
+/// Frees a report from `zcash_test_harness_mmr_random_ops`.
+void zcash_test_harness_mmr_random_ops_free(MmrOpsReportPtr* report);
+
+/// Returns `true` and writes the index of the operation to `op_index_ret` if
+/// an operation failed a check.
+bool zcash_test_harness_mmr_random_ops_diverged(
+    const MmrOpsReportPtr* report,
+    uint32_t* op_index_ret);
+
+/// Returns a log of every operation that was performed, and of the failed
+/// check if there was one. The string must be freed with
+/// `zcash_address_string_free`.
+char* zcash_test_harness_mmr_random_ops_log(const MmrOpsReportPtr* report);
+
+/// Writes an Orchard bundle with `num_actions` actions, derived from the
+/// 32-byte `seed`, to the given stream, in the format that
+/// `orchard_bundle_parse` reads. The same seed always gives the same bundle.
+///
+/// If `with_valid_proof` is false, the bundle is assembled from random but
+/// canonically encoded parts, with placeholder proofs and signatures, which is
+/// fast. Otherwise it is built by spending dummy notes, with a valid proof and
+/// with signatures over `seed` as the sighash, which is slow.
+///
+/// Returns `false` if `num_actions` is 0, or if it is 1 and `with_valid_proof`
+/// is set, as proven bundles have at least two actions.
+bool zcash_test_harness_generate_orchard_bundle(
+    const unsigned char* seed,
+    size_t num_actions,
+    bool with_valid_proof,
+    void* stream,
+    write_callback_t write_cb);
+
+/// Arms the fault site named `siteName` so that the next `count` calls through
+/// it fail as if the error it guards had occurred, or disarms it if `count` is
+/// 0. The sites are:
+///
+/// - `sapling_spend_proof` and `sapling_output_proof`: creating a Sapling proof.
+/// - `orchard_proof`: proving and signing an Orchard bundle.
+/// - `batch_validation`: validating a Sprout, Orchard or block-wide batch.
+/// - `params_loading`: reading a Sapling proving parameter file, which fails as
+///   if it were truncated.
+/// - `randomness`: drawing randomness through `librustzcash_getrandom` and the
+///   other Rust functions that need it.
+///
+/// Returns `false` if there is no site with that name.
+bool zcash_test_harness_inject_failure(const char* siteName, uint32_t count);
+
 #ifdef __cplusplus
 }
 #endif
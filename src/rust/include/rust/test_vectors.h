@@ -0,0 +1,32 @@
+// Copyright (c) 2022 The Zcash developers
+// Distributed under the MIT software license, see the accompanying
+// file COPYING or https://www.opensource.org/licenses/mit-license.php .
+
+#ifndef ZCASH_RUST_INCLUDE_RUST_TEST_VECTORS_H
+#define ZCASH_RUST_INCLUDE_RUST_TEST_VECTORS_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/// Exports the test vectors of the given kind, computed by zcashd's own code
+/// paths over inputs derived from a seed, as a JSON document.
+///
+/// `kind` is one of:
+/// - "zip244": v5 transactions with all of their ZIP 244 digests.
+/// - "history_tree": leaves, and the root of the history tree after each is
+///   appended, for both tree versions.
+///
+/// `paramsJson` may be `nullptr`, or a JSON object setting the integer `seed`
+/// (0 by default) and the `count` of vectors (20 transactions or 50 leaves by
+/// default).
+///
+/// Returns `nullptr` if the kind is unknown or the parameters are invalid. The
+/// returned string must be freed with `zcash_address_string_free`.
+char* zcash_export_test_vectors(const char* kind, const char* paramsJson);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif // ZCASH_RUST_INCLUDE_RUST_TEST_VECTORS_H
@@ -30,6 +30,338 @@ bool zcash_transaction_digests(
     unsigned char* txid_ret,
     unsigned char* authDigest_ret);
 
+#define TXID_OK 0
+#define TXID_INVALID 1
+#define TXID_UNKNOWN_VERSION 2
+#define TXID_TRAILING_BYTES 3
+#define TXID_NON_CANONICAL 4
+
+/// Calculates the txid and authorizing data commitment of the given v1-v5
+/// transaction, which must take up all of `txBytes`.
+///
+/// `txid_ret` must point to a 32-byte array. `authDigest_ret` must either be
+/// `nullptr` or point to a 32-byte array; if it is `nullptr`, the authorizing
+/// data commitment will not be returned.
+///
+/// Returns `TXID_OK` on success, `TXID_UNKNOWN_VERSION` if the transaction
+/// header is not that of a v1-v5 transaction, `TXID_TRAILING_BYTES` if
+/// `txBytes` continues past the end of the transaction, `TXID_NON_CANONICAL`
+/// if it is not the encoding that `CTransaction` would serialize to (such as
+/// when a CompactSize is not minimally encoded), and `TXID_INVALID` if the
+/// transaction is otherwise invalid.
+uint32_t zcash_transaction_calculate_wtxid(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    unsigned char* txid_ret,
+    unsigned char* authDigest_ret);
+
+#define WTXID_MATCH 0
+#define WTXID_TXID_MISMATCH 1
+#define WTXID_AUTH_DIGEST_MISMATCH 2
+#define WTXID_BOTH_MISMATCH 3
+#define WTXID_INVALID_TRANSACTION 4
+
+/// Checks that the wtxid made up of `txid` and `authDigest`, such as one that a
+/// peer announced, is that of the given v1-v5 transaction, which must take up
+/// all of `txBytes`. The authorizing data commitment of a pre-v5 transaction
+/// is all 0xFF bytes, as described in ZIP 239.
+///
+/// `txid` and `authDigest` must each point to a 32-byte array.
+///
+/// Returns `WTXID_MATCH` if both halves match, `WTXID_TXID_MISMATCH`,
+/// `WTXID_AUTH_DIGEST_MISMATCH`, or `WTXID_BOTH_MISMATCH` if they do not, or
+/// `WTXID_INVALID_TRANSACTION` if the transaction would be rejected by
+/// `zcash_transaction_calculate_wtxid`.
+uint32_t zcash_transaction_check_wtxid(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    const unsigned char* txid,
+    const unsigned char* authDigest);
+
+#define TX_SNIFF_OK 0
+#define TX_SNIFF_UNKNOWN_BRANCH 1
+#define TX_SNIFF_TRUNCATED 2
+#define TX_SNIFF_OVERSIZE 3
+#define TX_SNIFF_OVERWINTER_NOT_ACTIVE 4
+#define TX_SNIFF_NOT_OVERWINTERED 5
+#define TX_SNIFF_UNKNOWN_VERSION_GROUP 6
+#define TX_SNIFF_VERSION_GROUP_NOT_ACTIVE 7
+#define TX_SNIFF_INVALID_VERSION 8
+#define TX_SNIFF_BRANCH_ID_MISMATCH 9
+
+/// Checks the header of the given transaction against the version rules of
+/// `CheckTransaction` and `ContextualCheckTransaction` for the consensus branch
+/// `consensusBranchId`, as a cheap pre-parse of transactions from peers.
+///
+/// Only the header (at most the first 12 bytes) is read. The size of `txBytes`
+/// is also checked against the smallest transaction with that header, and the
+/// largest transaction allowed on the branch.
+///
+/// If `maxSize_ret` is not `nullptr` and the header is valid, the largest size
+/// of a transaction valid on the branch is written to it.
+///
+/// Returns `TX_SNIFF_OK` if the header is valid, or one of the other
+/// `TX_SNIFF_*` codes identifying why the transaction was rejected. For
+/// example, a v5 transaction before NU5 activation is rejected with
+/// `TX_SNIFF_VERSION_GROUP_NOT_ACTIVE`.
+uint32_t zcash_transaction_sniff(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    uint32_t consensusBranchId,
+    size_t* maxSize_ret);
+
+/// Codes identifying the rules violated by a transaction, as returned by
+/// `zcash_transaction_check_structure`. Each rule is named by the rejection
+/// code that `CheckTransaction` or `ContextualCheckTransaction` uses for it.
+#define TX_RULE_UNKNOWN_BRANCH 0
+#define TX_RULE_MALFORMED 1
+#define TX_RULE_NO_SOURCE_OF_FUNDS 2                // bad-txns-no-source-of-funds
+#define TX_RULE_NO_SINK_OF_FUNDS 3                  // bad-txns-no-sink-of-funds
+#define TX_RULE_OVERSIZE 4                          // bad-txns-oversize
+#define TX_RULE_TXOUT_TOTAL_TOO_LARGE 5             // bad-txns-txouttotal-toolarge
+#define TX_RULE_VALUE_BALANCE_NONZERO 6             // bad-txns-valuebalance-nonzero
+#define TX_RULE_TOO_MANY_SAPLING_SPENDS 7           // bad-tx-too-many-sapling-spends
+#define TX_RULE_TOO_MANY_SAPLING_OUTPUTS 8          // bad-tx-too-many-sapling-outputs
+#define TX_RULE_TOO_MANY_ORCHARD_ACTIONS 9          // bad-tx-too-many-orchard-actions
+#define TX_RULE_ORCHARD_FLAGS_DISABLE_ACTIONS 10    // bad-tx-orchard-flags-disable-actions
+#define TX_RULE_VPUBS_BOTH_NONZERO 11               // bad-txns-vpubs-both-nonzero
+#define TX_RULE_TXIN_TOTAL_TOO_LARGE 12             // bad-txns-txintotal-toolarge
+#define TX_RULE_DUPLICATE_INPUTS 13                 // bad-txns-inputs-duplicate
+#define TX_RULE_DUPLICATE_SPROUT_NULLIFIERS 14      // bad-joinsplits-nullifiers-duplicate
+#define TX_RULE_DUPLICATE_SAPLING_NULLIFIERS 15     // bad-spend-description-nullifiers-duplicate
+#define TX_RULE_DUPLICATE_ORCHARD_NULLIFIERS 16     // bad-orchard-nullifiers-duplicate
+#define TX_RULE_COINBASE_HAS_JOINSPLITS 17          // bad-cb-has-joinsplits
+#define TX_RULE_COINBASE_HAS_SPEND_DESCRIPTIONS 18  // bad-cb-has-spend-description
+#define TX_RULE_COINBASE_HAS_ORCHARD_SPEND 19       // bad-cb-has-orchard-spend
+#define TX_RULE_COINBASE_LENGTH 20                  // bad-cb-length
+#define TX_RULE_NULL_PREVOUT 21                     // bad-txns-prevout-null
+#define TX_RULE_EXPIRED 22                          // tx-overwinter-expired
+#define TX_RULE_COINBASE_HAS_OUTPUT_DESCRIPTIONS 23 // bad-cb-has-output-description
+#define TX_RULE_VPUB_OLD_NONZERO 24                 // bad-txns-vpub_old-nonzero
+#define TX_RULE_EXPIRY_HEIGHT_TOO_HIGH 25           // bad-tx-expiry-height-too-high
+
+/// Checks the given transaction, which must take up all of `txBytes`, against
+/// the rules of `CheckTransaction` and `ContextualCheckTransaction` that need
+/// no chain state, for inclusion at `height` on the consensus branch
+/// `consensusBranchId`. Proofs, signatures, coinbase note decryption, and
+/// funding streams are not checked, nor are the header rules checked by
+/// `zcash_transaction_sniff`.
+///
+/// Up to `violations_len` `TX_RULE_*` codes are written to `violations_ret`,
+/// in the order that the C++ checks the rules. A transaction that cannot be
+/// parsed is reported as `TX_RULE_MALFORMED` alone.
+///
+/// Returns the number of violated rules, which is 0 if the transaction is
+/// valid.
+size_t zcash_transaction_check_structure(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    uint32_t height,
+    uint32_t consensusBranchId,
+    uint32_t* violations_ret,
+    size_t violations_len);
+
+/// Whether a transaction's expiry height and lock time allow it to be mined in
+/// the next block.
+///
+/// A transaction is final, as in `IsFinalTx`, if its lock time is satisfied by
+/// height or by the time cutoff in use: the block time for consensus, or the
+/// median time past for the mempool and the miner.
+typedef struct TimelockStatus {
+    /// As in `IsExpiredTx` at the next block height.
+    bool expired;
+    /// As in `IsExpiringSoonTx` at the next block height.
+    bool expiringSoon;
+    /// nLockTime is a height that the next block is above.
+    bool lockTimeByHeight;
+    /// nLockTime is a time that the next block's time is after.
+    bool lockTimeByTime;
+    /// nLockTime is a time that the median time past is after.
+    bool lockTimeByMedianTimePast;
+} TimelockStatus;
+
+/// Evaluates the expiry height and lock time of the given transaction, which
+/// must take up all of `txBytes`, for inclusion in the block at
+/// `nextBlockHeight` with the time `nextBlockTime`, whose predecessor has the
+/// median time past `medianTimePast`.
+///
+/// Coinbase transactions and an nExpiryHeight of 0 never expire. An nLockTime
+/// of 0, or inputs that all have a final nSequence, satisfy every lock time
+/// condition.
+///
+/// Returns `false` if the transaction could not be parsed, in which case
+/// `status_ret` is left unchanged.
+bool zcash_transaction_evaluate_timelocks(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    uint32_t nextBlockHeight,
+    int64_t nextBlockTime,
+    int64_t medianTimePast,
+    TimelockStatus* status_ret);
+
+/// The net value, in zatoshis, that a block moves into each shielded pool,
+/// which is negative if its transactions move value out of the pool.
+///
+/// The transparent pool is not included, since the values of the outputs that
+/// a block spends are not part of the block.
+typedef struct PoolValueDeltas {
+    /// The sum of vpub_old minus the sum of vpub_new over all JoinSplits.
+    int64_t sproutValue;
+    /// The negated sum of valueBalanceSapling.
+    int64_t saplingValue;
+    /// The negated sum of valueBalanceOrchard.
+    int64_t orchardValue;
+} PoolValueDeltas;
+
+/// Calculates the net value that the given block, which must take up all of
+/// `blockBytes`, moves into each shielded pool, as `ConnectBlock` adds it to
+/// the chain value pools.
+///
+/// Returns `false` if the block could not be parsed, or if a total does not
+/// fit in an `int64_t`, in which case `deltas_ret` is left unchanged.
+bool zcash_block_pool_value_deltas(
+    const unsigned char* blockBytes,
+    size_t blockBytes_len,
+    PoolValueDeltas* deltas_ret);
+
+/// The intermediate ZIP 244 digests of a v5 transaction, from which its txid
+/// and authorizing data commitment are computed. Each is a 32-byte BLAKE2b-256
+/// digest, and an empty bundle is committed to by the digest of no data with
+/// the bundle's personalization.
+typedef struct TxDigestParts {
+    /// T.1: the digest of the header fields.
+    unsigned char headerDigest[32];
+    /// T.2: the digest of the transparent bundle.
+    unsigned char transparentDigest[32];
+    /// T.3: the digest of the Sapling bundle.
+    unsigned char saplingDigest[32];
+    /// T.4: the digest of the Orchard bundle.
+    unsigned char orchardDigest[32];
+    /// The digest of T.1 to T.4.
+    unsigned char txid[32];
+    /// The digest of the transparent scriptSigs.
+    unsigned char transparentAuthDigest[32];
+    /// The digest of the Sapling proofs and signatures.
+    unsigned char saplingAuthDigest[32];
+    /// The digest of the Orchard proof and signatures.
+    unsigned char orchardAuthDigest[32];
+    /// The digest of the authorizing data digests.
+    unsigned char authDigest[32];
+} TxDigestParts;
+
+/// Writes the intermediate ZIP 244 digests of the given transaction, which
+/// must take up all of `txBytes`, to `parts_ret`, so that a txid or wtxid that
+/// disagrees with another implementation can be narrowed down to a bundle.
+///
+/// Returns `false` if the transaction is invalid, or a v1-v4 transaction
+/// format, in which case `parts_ret` is unaltered.
+bool zcash_transaction_digest_parts(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    TxDigestParts* parts_ret);
+
+struct TxInfo;
+typedef struct TxInfo TxInfo;
+
+/// The scalar fields of a parsed transaction, as rendered by
+/// `decoderawtransaction`.
+typedef struct TxSummary {
+    /// The transaction header, including the `fOverwintered` flag.
+    uint32_t header;
+    uint32_t versionGroupId;
+    /// The consensus branch ID, or 0 for pre-v5 transactions which don't
+    /// encode one.
+    uint32_t consensusBranchId;
+    uint32_t lockTime;
+    uint32_t expiryHeight;
+    size_t transparentInputs;
+    size_t transparentOutputs;
+    size_t sproutJoinSplits;
+    size_t saplingSpends;
+    size_t saplingOutputs;
+    size_t orchardActions;
+    int64_t saplingValueBalance;
+    int64_t orchardValueBalance;
+    /// The Orchard flags byte: bit 0 enables spends and bit 1 enables outputs.
+    uint8_t orchardFlags;
+} TxSummary;
+
+/// Parses the given v1-v5 transaction, which must take up all of `txBytes`,
+/// for rendering by `decoderawtransaction` without re-parsing it in C++.
+///
+/// If `strict` is false, a transaction that is not in the canonical encoding
+/// is accepted as long as it can be parsed, so that questionable data can
+/// still be decoded.
+///
+/// Please free this with `zcash_tx_info_free` when you are done.
+///
+/// Returns `nullptr` if the transaction is rejected, setting `status_ret` to
+/// one of the `TXID_*` codes above; in particular, a transaction with a
+/// header from an unknown future version is rejected with
+/// `TXID_UNKNOWN_VERSION`. Otherwise `status_ret` is set to `TXID_OK`.
+TxInfo* zcash_transaction_inspect(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    bool strict,
+    uint32_t* status_ret);
+
+/// Frees a transaction summary from `zcash_transaction_inspect`.
+void zcash_tx_info_free(TxInfo* info);
+
+/// Writes the scalar fields of the transaction to `summary_ret`.
+void zcash_tx_info_summary(const TxInfo* info, TxSummary* summary_ret);
+
+#define TX_INFO_SAPLING_ANCHOR 0
+#define TX_INFO_SAPLING_NULLIFIER 1
+#define TX_INFO_SAPLING_CMU 2
+#define TX_INFO_ORCHARD_ANCHOR 3
+#define TX_INFO_ORCHARD_NULLIFIER 4
+#define TX_INFO_ORCHARD_CMX 5
+
+/// Writes the hex encoding of a 32-byte field of the transaction to
+/// `hex_ret`, in the byte order of `uint256::GetHex`, as a NUL-terminated
+/// string.
+///
+/// `field` is one of the `TX_INFO_*` constants, and `index` selects the Sapling
+/// spend, Sapling output, or Orchard action that the field belongs to. The
+/// Sapling anchor is given for each spend, as v4 transactions allow them to
+/// differ; the Orchard anchor is shared by the bundle, and has only the index 0.
+///
+/// `hex_ret` must point to a 65-byte array.
+///
+/// Returns `false` if the transaction has no such field; in this case,
+/// `hex_ret` will be unaltered.
+bool zcash_tx_info_hex(
+    const TxInfo* info,
+    uint32_t field,
+    size_t index,
+    char* hex_ret);
+
+#define TX_INFO_ORCHARD_CV 6
+#define TX_INFO_ORCHARD_RK 7
+#define TX_INFO_ORCHARD_EPHEMERAL_KEY 8
+#define TX_INFO_ORCHARD_ENC_CIPHERTEXT 9
+#define TX_INFO_ORCHARD_OUT_CIPHERTEXT 10
+#define TX_INFO_ORCHARD_SPEND_AUTH_SIG 11
+#define TX_INFO_ORCHARD_PROOF 12
+#define TX_INFO_ORCHARD_BINDING_SIG 13
+
+/// Returns the raw bytes of a field of the transaction's Orchard bundle, in
+/// their encoding in the transaction, writing their length to `len_ret`.
+///
+/// `field` is one of the `TX_INFO_ORCHARD_*` constants from 6 onwards, and
+/// `index` selects the Orchard action that the field belongs to. The proof and
+/// binding signature are shared by the bundle, and have only the index 0.
+///
+/// The bytes are owned by `info`, and are valid until it is freed. Returns
+/// `nullptr` if the transaction has no such field; in this case, `len_ret`
+/// will be unaltered.
+const unsigned char* zcash_tx_info_bytes(
+    const TxInfo* info,
+    uint32_t field,
+    size_t index,
+    size_t* len_ret);
+
 /// Precomputes data for calculating signature digests from the given
 /// transaction.
 ///
@@ -51,6 +383,10 @@ void zcash_transaction_precomputed_free(PrecomputedTxParts* preTx);
 /// `index` must be an index into the transaction's `vin`, or `NOT_AN_INPUT` for
 /// calculating the signature digest for shielded signatures.
 ///
+/// `scriptCode` may be empty, and must be empty if `index` is `NOT_AN_INPUT`.
+/// ZIP 244 commits to the `scriptPubKey` being spent rather than a script
+/// code, so it does not affect the digest.
+///
 /// `sighash_ret` must point to a 32-byte array.
 ///
 /// Returns `false` if any of the parameters are invalid; in this case,
@@ -59,8 +395,140 @@ bool zcash_transaction_zip244_signature_digest(
     const PrecomputedTxParts* preTx,
     uint32_t sighashType,
     size_t index,
+    const unsigned char* scriptCode,
+    size_t scriptCodeLen,
+    unsigned char* sighash_ret);
+
+/// Calculates the ZIP 244 signature digest for the transparent input at
+/// `index` of the given transaction.
+///
+/// The input must spend an output with the given `scriptPubKey` and `value`,
+/// matching the previous output it was given in `allPrevOutputs` when `preTx`
+/// was created. For P2SH inputs, `scriptPubKey` is the P2SH script rather than
+/// the redeem script, as ZIP 244 commits to the script being spent; for the
+/// same reason, `scriptCode` may be empty and does not affect the digest.
+///
+/// `sighash_ret` must point to a 32-byte array.
+///
+/// Returns `false` if any of the parameters are invalid (including a
+/// `hashType` undefined by ZIP 244, or `SIGHASH_SINGLE` without a
+/// corresponding output); in this case, `sighash_ret` will be unaltered.
+bool zcash_transaction_transparent_sig_digest(
+    const PrecomputedTxParts* preTx,
+    size_t index,
+    const unsigned char* scriptPubKey,
+    size_t scriptPubKeyLen,
+    const unsigned char* scriptCode,
+    size_t scriptCodeLen,
+    int64_t value,
+    uint32_t hashType,
     unsigned char* sighash_ret);
 
+/// Calculates the ZIP 143 or ZIP 243 signature digest for the given v3 or v4
+/// transaction, under the given consensus branch ID.
+///
+/// `index` must be an index into the transaction's `vin`, or `NOT_AN_INPUT`
+/// for calculating the signature digest for shielded signatures. For
+/// transparent inputs, the digest commits to `scriptCode` (the redeem script,
+/// for P2SH inputs), or to `scriptPubKey` if `scriptCode` is empty.
+/// `scriptCode` must be empty if `index` is `NOT_AN_INPUT`.
+///
+/// `sighash_ret` must point to a 32-byte array.
+///
+/// Returns `false` if any of the parameters are invalid; in this case,
+/// `sighash_ret` will be unaltered.
+bool zcash_transaction_legacy_signature_digest(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    uint32_t consensusBranchId,
+    uint32_t hashType,
+    size_t index,
+    const unsigned char* scriptPubKey,
+    size_t scriptPubKeyLen,
+    const unsigned char* scriptCode,
+    size_t scriptCodeLen,
+    int64_t value,
+    unsigned char* sighash_ret);
+
+/// Calculates the legacy `SignatureHash` digest of the transparent input at
+/// `index` of the given pre-Overwinter transaction, which commits to
+/// `scriptCode` in place of the input's `scriptSig`.
+///
+/// As with the original implementation, an `index` out of range, or
+/// `SIGHASH_SINGLE` on an input with no corresponding output, gives the digest
+/// `uint256S("1")`.
+///
+/// `sighash_ret` must point to a 32-byte array.
+///
+/// Returns `false` if the transaction is overwintered, or cannot be parsed; in
+/// this case, `sighash_ret` will be unaltered.
+bool zcash_transaction_sprout_signature_digest(
+    const unsigned char* txBytes,
+    size_t txBytes_len,
+    int32_t hashType,
+    size_t index,
+    const unsigned char* scriptCode,
+    size_t scriptCodeLen,
+    unsigned char* sighash_ret);
+
+struct AuthDataTree;
+typedef struct AuthDataTree AuthDataTree;
+
+/// Creates an empty incremental tree of transaction auth digests, for
+/// computing `hashAuthDataRoot` while assembling a block.
+///
+/// Please free this with `zcash_auth_data_tree_free` when you are done.
+AuthDataTree* zcash_auth_data_tree_new();
+
+/// Frees an auth data tree from `zcash_auth_data_tree_new`.
+void zcash_auth_data_tree_free(AuthDataTree* tree);
+
+/// Returns the number of auth digests in the tree.
+size_t zcash_auth_data_tree_len(const AuthDataTree* tree);
+
+/// Appends a transaction's auth digest to the tree.
+///
+/// `authDigest` must point to a 32-byte array.
+void zcash_auth_data_tree_append(
+    AuthDataTree* tree,
+    const unsigned char* authDigest);
+
+/// Replaces the auth digest at `index` in the tree.
+///
+/// `authDigest` must point to a 32-byte array.
+///
+/// Returns `false` if `index` is out of range; in this case, the tree will
+/// be unaltered.
+bool zcash_auth_data_tree_replace(
+    AuthDataTree* tree,
+    size_t index,
+    const unsigned char* authDigest);
+
+/// Removes the most recently appended auth digest from the tree.
+///
+/// Returns `false` if the tree is empty.
+bool zcash_auth_data_tree_remove_last(AuthDataTree* tree);
+
+/// Calculates the ZIP 244 `hashAuthDataRoot` of the tree, matching
+/// `CBlock::BuildAuthDataMerkleTree`.
+///
+/// `root_ret` must point to a 32-byte array.
+void zcash_auth_data_tree_root(
+    const AuthDataTree* tree,
+    unsigned char* root_ret);
+
+/// Writes the ZIP 244 `hashAuthDataRoot` of a block to `root_ret`, given the
+/// auth digests of its `authDigests_len` transactions in block order.
+///
+/// `authDigests` must point to `authDigests_len` consecutive 32-byte auth
+/// digests (it may be `nullptr` if `authDigests_len` is 0), and `root_ret`
+/// must point to a 32-byte array. This matches
+/// `CBlock::BuildAuthDataMerkleTree`.
+void zcash_block_auth_data_root(
+    const unsigned char* authDigests,
+    size_t authDigests_len,
+    unsigned char* root_ret);
+
 #ifdef __cplusplus
 }
 #endif
@@ -45,9 +45,19 @@ void tracing_free(TracingHandle* handle);
 
 /// Reloads the tracing filter.
 ///
-/// Returns `true` if the reload succeeded.
+/// Returns `true` if the reload succeeded. If any directive in `new_filter` is
+/// invalid, the active filter is left unchanged.
 bool tracing_reload(TracingHandle* handle, const char* new_filter);
 
+/// Returns the directives of the active tracing filter as a NUL-terminated
+/// string.
+///
+/// The returned string must be freed with `tracing_free_filter`.
+char* tracing_get_filter(const TracingHandle* handle);
+
+/// Frees a string returned from `tracing_get_filter`.
+void tracing_free_filter(char* filter);
+
 struct TracingCallsite;
 typedef struct TracingCallsite TracingCallsite;
 
@@ -0,0 +1,162 @@
+//! Incremental computation of the ZIP 244 block authorizing data commitment.
+
+use blake2b_simd::Params as Blake2bParams;
+
+const ZCASH_AUTH_DATA_HASH_PERSONALIZATION: &[u8; 16] = b"ZcashAuthDatHash";
+
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let hash = Blake2bParams::new()
+        .hash_length(32)
+        .personal(ZCASH_AUTH_DATA_HASH_PERSONALIZATION)
+        .to_state()
+        .update(left)
+        .update(right)
+        .finalize();
+    let mut node = [0; 32];
+    node.copy_from_slice(hash.as_bytes());
+    node
+}
+
+/// Returns the ZIP 244 `hashAuthDataRoot` of a block whose transactions have the given
+/// auth digests, in block order.
+///
+/// As in `CBlock::BuildAuthDataMerkleTree`, the leaves are padded with all-zero leaves
+/// up to the next power of two, and a block with no transactions has an all-zero root.
+pub fn block_auth_data_root(auth_digests: &[[u8; 32]]) -> [u8; 32] {
+    if auth_digests.is_empty() {
+        return [0; 32];
+    }
+
+    let mut layer = auth_digests.to_vec();
+    layer.resize(auth_digests.len().next_power_of_two(), [0; 32]);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| hash_pair(&pair[0], &pair[1]))
+            .collect();
+    }
+    layer[0]
+}
+
+/// The ZIP 244 `hashAuthDataRoot` tree over the auth digests of a block's transactions,
+/// which caches its internal nodes so that changing one leaf only rehashes the path
+/// from that leaf to the root.
+///
+/// As in `CBlock::BuildAuthDataMerkleTree`, the leaves are padded with all-zero leaves
+/// up to the next power of two.
+#[derive(Clone, Debug, Default)]
+pub struct AuthDataTree {
+    /// `layers[0]` holds the leaves, and `layers[k][i]` is the root of the subtree over
+    /// leaves `i * 2^k .. (i + 1) * 2^k`. Only subtrees containing at least one real
+    /// leaf are stored.
+    layers: Vec<Vec<[u8; 32]>>,
+    /// `empty[k]` is the root of a subtree of height `k` consisting only of padding.
+    empty: Vec<[u8; 32]>,
+}
+
+impl AuthDataTree {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the number of leaves in the tree, excluding padding.
+    pub fn len(&self) -> usize {
+        self.layers.first().map_or(0, |leaves| leaves.len())
+    }
+
+    /// Returns whether the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn empty_root(&mut self, level: usize) -> [u8; 32] {
+        if self.empty.is_empty() {
+            self.empty.push([0; 32]);
+        }
+        while self.empty.len() <= level {
+            let below = self.empty.last().expect("not empty");
+            let next = hash_pair(below, below);
+            self.empty.push(next);
+        }
+        self.empty[level]
+    }
+
+    /// Recomputes every node on the path from the leaf at `index` to the root, and
+    /// drops any layers and nodes that no longer cover a real leaf.
+    fn update_path(&mut self, mut index: usize) {
+        let leaves = self.len();
+        // The height of the padded tree.
+        let height = leaves.next_power_of_two().trailing_zeros() as usize;
+        self.layers.truncate(height + 1);
+
+        for level in 1..=height {
+            let width = (leaves + (1 << level) - 1) >> level;
+            index >>= 1;
+
+            let left = self.layers[level - 1][2 * index];
+            let right = match self.layers[level - 1].get(2 * index + 1) {
+                Some(right) => *right,
+                None => self.empty_root(level - 1),
+            };
+            let node = hash_pair(&left, &right);
+
+            if self.layers.len() == level {
+                self.layers.push(vec![]);
+            }
+            let layer = &mut self.layers[level];
+            layer.truncate(width);
+            if index < layer.len() {
+                layer[index] = node;
+            } else {
+                layer.push(node);
+            }
+        }
+    }
+
+    /// Appends the auth digest of a transaction.
+    pub fn append(&mut self, auth_digest: [u8; 32]) {
+        if self.layers.is_empty() {
+            self.layers.push(vec![]);
+        }
+        self.layers[0].push(auth_digest);
+        self.update_path(self.len() - 1);
+    }
+
+    /// Replaces the auth digest of the transaction at `index`, returning `false` if
+    /// there is no leaf at that index.
+    pub fn replace(&mut self, index: usize, auth_digest: [u8; 32]) -> bool {
+        match self
+            .layers
+            .first_mut()
+            .and_then(|leaves| leaves.get_mut(index))
+        {
+            Some(leaf) => {
+                *leaf = auth_digest;
+                self.update_path(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the most recently appended auth digest, returning it.
+    pub fn remove_last(&mut self) -> Option<[u8; 32]> {
+        let removed = self.layers.first_mut()?.pop()?;
+        if self.is_empty() {
+            self.layers.clear();
+        } else {
+            self.update_path(self.len() - 1);
+        }
+        Some(removed)
+    }
+
+    /// Returns `hashAuthDataRoot` for the current leaves, which is all zeroes for a
+    /// tree with no leaves.
+    pub fn root(&self) -> [u8; 32] {
+        self.layers
+            .last()
+            .and_then(|top| top.first())
+            .copied()
+            .unwrap_or([0; 32])
+    }
+}
@@ -0,0 +1,1150 @@
+//! Block-wide validation of shielded proofs and signatures.
+//!
+//! Rather than checking each transaction's Sapling and Orchard bundles as it is
+//! connected, `ConnectBlock` queues them in a [`BatchValidators`] as it checks each
+//! transaction, and validates them all at once after the last transaction. The pools
+//! are validated concurrently on the rayon thread pool, while the transparent script
+//! checks run on zcashd's own script check threads.
+//!
+//! zcash_proofs has no batch validator for Sapling proofs yet, so each transaction's
+//! Sapling proofs are checked on their own, with the transactions checked in parallel.
+//! Orchard proofs are likewise verified one bundle at a time in parallel. The block's
+//! signatures are accumulated in a [`BlockSignatures`] and verified together: its
+//! Sapling spend authorization signatures in one RedJubjub batch, its Sapling binding
+//! signatures in another, and all of its Orchard signatures in a single RedPallas
+//! batch.
+//!
+//! If validation fails, the first transaction (in the order they were queued) with an
+//! invalid bundle is found, so that the block can be rejected with a useful message.
+//! An invalid signature is attributed to its transaction, kind and index by bisecting
+//! the batch that failed.
+//!
+//! The queued bundles are kept within a memory budget (see
+//! [`librustzcash_init_batch_validation`]), so that a block of large bundles can't
+//! make the queue grow without bound. Once the bundles queued so far have exceeded it,
+//! they are validated in an intermediate flush before the next transaction is queued.
+//! A transaction's bundles are only validated once they are all queued, so the budget
+//! is a soft limit: the queue can exceed it by up to one transaction's bundles.
+//! The bundles of each flush are independent of the others, so this only changes how
+//! the block's bundles are split into batches: the block is still valid if and only if
+//! they all are, and the first invalid bundle is still the one reported.
+//!
+//! [`librustzcash_init_batch_validation`]: crate::init_ffi::librustzcash_init_batch_validation
+
+use bellman::{
+    gadgets::multipack,
+    groth16::{self, PreparedVerifyingKey, Proof},
+};
+use bls12_381::Bls12;
+use group::Curve;
+use std::mem;
+
+use libc::size_t;
+use once_cell::sync::OnceCell;
+use orchard::{
+    bundle::Authorized,
+    primitives::redpallas::{self, Binding, SpendAuth},
+    Action, Bundle,
+};
+use rayon::prelude::*;
+use tracing::{debug, error};
+use zcash_primitives::{
+    constants::{
+        SPENDING_KEY_GENERATOR, VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
+        VALUE_COMMITMENT_VALUE_GENERATOR,
+    },
+    sapling::redjubjub::{self, Signature},
+    transaction::{components::Amount, TxId},
+};
+use zcash_proofs::sapling::SaplingVerificationContext;
+
+use crate::fault_injection::{should_fail, FaultSite};
+use crate::rng;
+use crate::{de_ct, GROTH_PROOF_SIZE};
+
+/// The decoded fields of a Sapling Spend description.
+pub(crate) struct SaplingSpend {
+    cv: jubjub::ExtendedPoint,
+    anchor: bls12_381::Scalar,
+    nullifier: [u8; 32],
+    rk: redjubjub::PublicKey,
+    spend_auth_sig: Signature,
+    zkproof: Proof<Bls12>,
+}
+
+impl SaplingSpend {
+    /// Decodes the fields of a Spend description, or returns `None` if any of them is
+    /// not a valid encoding.
+    pub(crate) fn read(
+        cv: &[u8; 32],
+        anchor: &[u8; 32],
+        nullifier: &[u8; 32],
+        rk: &[u8; 32],
+        zkproof: &[u8; GROTH_PROOF_SIZE],
+        spend_auth_sig: &[u8; 64],
+    ) -> Option<Self> {
+        Some(SaplingSpend {
+            cv: de_ct(jubjub::ExtendedPoint::from_bytes(cv))?,
+            // The anchor should be an element of Fr.
+            anchor: de_ct(bls12_381::Scalar::from_bytes(anchor))?,
+            nullifier: *nullifier,
+            rk: redjubjub::PublicKey::read(&rk[..]).ok()?,
+            spend_auth_sig: Signature::read(&spend_auth_sig[..]).ok()?,
+            zkproof: Proof::read(&zkproof[..]).ok()?,
+        })
+    }
+
+    /// Returns the approximate number of bytes that the queued description takes up,
+    /// including its signature once it is accumulated for validation.
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>() + mem::size_of::<SaplingSignature>()
+    }
+
+    /// Checks the Spend description, accumulating its value commitment into `ctx`.
+    pub(crate) fn check(
+        self,
+        ctx: &mut SaplingVerificationContext,
+        sighash: &[u8; 32],
+        vk: &PreparedVerifyingKey<Bls12>,
+    ) -> bool {
+        ctx.check_spend(
+            self.cv,
+            self.anchor,
+            &self.nullifier,
+            self.rk,
+            sighash,
+            self.spend_auth_sig,
+            self.zkproof,
+            vk,
+        )
+    }
+
+    /// Checks the Spend description as [`SaplingSpend::check`] does, except for its
+    /// signature, which is instead verified in a [`BlockSignatures`] batch.
+    fn check_proof(&self, vk: &PreparedVerifyingKey<Bls12>) -> bool {
+        if (self.cv.is_small_order() | self.rk.0.is_small_order()).into() {
+            return false;
+        }
+
+        let rk = self.rk.0.to_affine();
+        let cv = self.cv.to_affine();
+        let nullifier: Vec<bls12_381::Scalar> =
+            multipack::compute_multipacking(&multipack::bytes_to_bits_le(&self.nullifier));
+        let public_input = [
+            rk.get_u(),
+            rk.get_v(),
+            cv.get_u(),
+            cv.get_v(),
+            self.anchor,
+            nullifier[0],
+            nullifier[1],
+        ];
+        groth16::verify_proof(vk, &self.zkproof, &public_input).is_ok()
+    }
+}
+
+/// The decoded fields of a Sapling Output description.
+pub(crate) struct SaplingOutput {
+    cv: jubjub::ExtendedPoint,
+    cm: bls12_381::Scalar,
+    epk: jubjub::ExtendedPoint,
+    zkproof: Proof<Bls12>,
+}
+
+impl SaplingOutput {
+    /// Decodes the fields of an Output description, or returns `None` if any of them
+    /// is not a valid encoding.
+    pub(crate) fn read(
+        cv: &[u8; 32],
+        cm: &[u8; 32],
+        epk: &[u8; 32],
+        zkproof: &[u8; GROTH_PROOF_SIZE],
+    ) -> Option<Self> {
+        Some(SaplingOutput {
+            cv: de_ct(jubjub::ExtendedPoint::from_bytes(cv))?,
+            // The commitment should be an element of Fr.
+            cm: de_ct(bls12_381::Scalar::from_bytes(cm))?,
+            epk: de_ct(jubjub::ExtendedPoint::from_bytes(epk))?,
+            zkproof: Proof::read(&zkproof[..]).ok()?,
+        })
+    }
+
+    /// Returns the approximate number of bytes that the queued description takes up.
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>()
+    }
+
+    /// Checks the Output description, accumulating its value commitment into `ctx`.
+    pub(crate) fn check(
+        self,
+        ctx: &mut SaplingVerificationContext,
+        vk: &PreparedVerifyingKey<Bls12>,
+    ) -> bool {
+        ctx.check_output(self.cv, self.cm, self.epk, self.zkproof, vk)
+    }
+
+    /// Checks the Output description as [`SaplingOutput::check`] does, without a
+    /// verification context.
+    fn check_proof(&self, vk: &PreparedVerifyingKey<Bls12>) -> bool {
+        if (self.cv.is_small_order() | self.epk.is_small_order()).into() {
+            return false;
+        }
+
+        let cv = self.cv.to_affine();
+        let epk = self.epk.to_affine();
+        let public_input = [cv.get_u(), cv.get_v(), epk.get_u(), epk.get_v(), self.cm];
+        groth16::verify_proof(vk, &self.zkproof, &public_input).is_ok()
+    }
+}
+
+/// The decoded value balance and binding signature of a Sapling bundle.
+pub(crate) struct SaplingBindingSig {
+    value_balance: Amount,
+    binding_sig: Signature,
+}
+
+impl SaplingBindingSig {
+    /// Decodes the value balance and binding signature, or returns `None` if either is
+    /// invalid.
+    pub(crate) fn read(value_balance: i64, binding_sig: &[u8; 64]) -> Option<Self> {
+        Some(SaplingBindingSig {
+            value_balance: Amount::from_i64(value_balance).ok()?,
+            binding_sig: Signature::read(&binding_sig[..]).ok()?,
+        })
+    }
+
+    /// Returns the approximate number of bytes that the queued signature takes up,
+    /// including once it is accumulated for validation.
+    fn footprint(&self) -> usize {
+        mem::size_of::<Self>() + mem::size_of::<SaplingSignature>()
+    }
+
+    /// Checks the binding signature against the value commitments accumulated in
+    /// `ctx`.
+    pub(crate) fn check(self, ctx: &SaplingVerificationContext, sighash: &[u8; 32]) -> bool {
+        ctx.final_check(self.value_balance, sighash, self.binding_sig)
+    }
+}
+
+/// The Sapling bundle of a queued transaction.
+#[derive(Default)]
+struct SaplingBundle {
+    spends: Vec<SaplingSpend>,
+    outputs: Vec<SaplingOutput>,
+    binding_sig: Option<SaplingBindingSig>,
+    sighash: [u8; 32],
+}
+
+impl SaplingBundle {
+    /// Checks the proofs of the bundle's descriptions. Its signatures are verified in a
+    /// [`BlockSignatures`] batch.
+    fn check_proofs(&self, keys: &VerifyingKeys) -> bool {
+        // A bundle whose binding signature was never queued is incomplete.
+        self.binding_sig.is_some()
+            && self
+                .spends
+                .iter()
+                .all(|spend| spend.check_proof(keys.sapling_spend))
+            && self
+                .outputs
+                .iter()
+                .all(|output| output.check_proof(keys.sapling_output))
+    }
+
+    /// Returns the key that the bundle's binding signature must be valid for, as
+    /// `SaplingVerificationContext` derives it from the value commitments.
+    fn binding_validating_key(&self, value_balance: Amount) -> jubjub::ExtendedPoint {
+        let spends = self
+            .spends
+            .iter()
+            .fold(jubjub::ExtendedPoint::identity(), |sum, spend| {
+                sum + spend.cv
+            });
+        let cv_sum = self
+            .outputs
+            .iter()
+            .fold(spends, |sum, output| sum - output.cv);
+
+        let abs = i64::from(value_balance).unsigned_abs();
+        let mut value_balance_point = VALUE_COMMITMENT_VALUE_GENERATOR * jubjub::Fr::from(abs);
+        if value_balance.is_negative() {
+            value_balance_point = -value_balance_point;
+        }
+        cv_sum - jubjub::ExtendedPoint::from(value_balance_point)
+    }
+}
+
+/// The Orchard bundle of a queued transaction, with the sighash its signatures are
+/// over.
+struct OrchardBundle {
+    bundle: Bundle<Authorized, Amount>,
+    sighash: [u8; 32],
+}
+
+impl OrchardBundle {
+    /// Returns the approximate number of bytes that the queued bundle takes up,
+    /// including its signatures once they are accumulated for validation. The proof
+    /// dominates this, as it grows with the number of actions.
+    fn footprint(&self) -> usize {
+        let actions = self.bundle.actions().len();
+        mem::size_of::<Self>()
+            + actions * mem::size_of::<Action<redpallas::Signature<SpendAuth>>>()
+            + self.bundle.authorization().proof().as_ref().len()
+            + (actions + 1) * mem::size_of::<OrchardSignature>()
+    }
+}
+
+/// A transaction whose shielded bundles have been queued.
+struct QueuedTransaction {
+    txid: [u8; 32],
+    sapling: Option<SaplingBundle>,
+    orchard: Option<OrchardBundle>,
+}
+
+/// The pool of the first invalid bundle found by [`BatchValidators::validate_all`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum BatchFailure {
+    /// A Sapling proof is invalid, or the bundle is incomplete.
+    Sapling,
+    /// An Orchard proof is invalid.
+    OrchardProof,
+    /// The signature of this kind with this index is invalid.
+    Signature(SignatureKind, usize),
+}
+
+/// Which of a transaction's signatures an accumulated signature is. The kinds are in
+/// the order that a transaction's bundles are queued in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum SignatureKind {
+    /// The spend authorization signature of a Sapling Spend description.
+    SaplingSpendAuth,
+    /// The binding signature of a Sapling bundle.
+    SaplingBinding,
+    /// The spend authorization signature of an Orchard action.
+    OrchardSpendAuth,
+    /// The binding signature of an Orchard bundle.
+    OrchardBinding,
+}
+
+/// Identifies an accumulated signature by the position of its transaction among those
+/// queued, its kind, and its index among the transaction's signatures of that kind
+/// (which is 0 for a binding signature). They are ordered as they were queued.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct SignatureId {
+    pub(crate) position: usize,
+    pub(crate) kind: SignatureKind,
+    pub(crate) index: usize,
+}
+
+/// A RedJubjub signature, with the key and message that it must be valid for.
+pub(crate) struct SaplingSignature {
+    id: SignatureId,
+    vk: jubjub::ExtendedPoint,
+    msg: [u8; 64],
+    sig: [u8; 64],
+}
+
+impl SaplingSignature {
+    /// Creates the signature of kind `id.kind` by `vk` over `sighash`, whose message is
+    /// (as for every Sapling signature) the encoding of `vk` followed by `sighash`.
+    pub(crate) fn new(
+        id: SignatureId,
+        vk: jubjub::ExtendedPoint,
+        sighash: &[u8; 32],
+        sig: &Signature,
+    ) -> Self {
+        let mut msg = [0; 64];
+        msg[..32].copy_from_slice(&vk.to_bytes());
+        msg[32..].copy_from_slice(sighash);
+        let mut bytes = [0; 64];
+        sig.write(&mut bytes[..])
+            .expect("Signature encodings are 64 bytes; qed");
+        SaplingSignature {
+            id,
+            vk,
+            msg,
+            sig: bytes,
+        }
+    }
+
+    fn signature(&self) -> Signature {
+        Signature::read(&self.sig[..]).expect("was written from a Signature; qed")
+    }
+
+    /// Returns whether the signature's R is canonically encoded, in which case it
+    /// decodes to the same point with or without the ZIP 216 rules.
+    fn r_is_canonical(&self) -> bool {
+        let mut r = [0; 32];
+        r.copy_from_slice(&self.sig[..32]);
+        jubjub::ExtendedPoint::from_bytes(&r).is_some().into()
+    }
+
+    /// Verifies the signature on its own.
+    fn verify(&self, zip216_enabled: bool) -> bool {
+        redjubjub::PublicKey(self.vk).verify_with_zip216(
+            &self.msg,
+            &self.signature(),
+            sapling_generator(self.id.kind),
+            zip216_enabled,
+        )
+    }
+}
+
+/// Returns the generator that Sapling signatures of `kind` are relative to.
+fn sapling_generator(kind: SignatureKind) -> jubjub::SubgroupPoint {
+    match kind {
+        SignatureKind::SaplingBinding => VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
+        _ => SPENDING_KEY_GENERATOR,
+    }
+}
+
+/// A RedPallas signature, as an item of a batch.
+pub(crate) struct OrchardSignature {
+    id: SignatureId,
+    item: redpallas::batch::Item<SpendAuth, Binding>,
+}
+
+/// The signatures of a block's shielded bundles, accumulated as the bundles are queued
+/// so that each kind of key is verified in a single batch.
+#[derive(Default)]
+pub(crate) struct BlockSignatures {
+    sapling: Vec<SaplingSignature>,
+    orchard: Vec<OrchardSignature>,
+}
+
+impl BlockSignatures {
+    pub(crate) fn add_sapling_signature(&mut self, signature: SaplingSignature) {
+        self.sapling.push(signature);
+    }
+
+    /// Adds the spend authorization signatures and (if it was queued) the binding
+    /// signature of the Sapling bundle of the transaction at `position`.
+    fn add_sapling_bundle(&mut self, position: usize, bundle: &SaplingBundle) {
+        for (index, spend) in bundle.spends.iter().enumerate() {
+            let id = SignatureId {
+                position,
+                kind: SignatureKind::SaplingSpendAuth,
+                index,
+            };
+            self.add_sapling_signature(SaplingSignature::new(
+                id,
+                // The randomized key, as the per-signature path verifies against.
+                spend.rk.0,
+                &bundle.sighash,
+                &spend.spend_auth_sig,
+            ));
+        }
+        if let Some(binding_sig) = &bundle.binding_sig {
+            let id = SignatureId {
+                position,
+                kind: SignatureKind::SaplingBinding,
+                index: 0,
+            };
+            self.add_sapling_signature(SaplingSignature::new(
+                id,
+                bundle.binding_validating_key(binding_sig.value_balance),
+                &bundle.sighash,
+                &binding_sig.binding_sig,
+            ));
+        }
+    }
+
+    /// Adds the spend authorization signatures and the binding signature of the
+    /// Orchard bundle of the transaction at `position`, which are over `sighash`.
+    pub(crate) fn add_orchard_bundle(
+        &mut self,
+        position: usize,
+        bundle: &Bundle<Authorized, Amount>,
+        sighash: &[u8; 32],
+    ) {
+        for (index, action) in bundle.actions().iter().enumerate() {
+            self.orchard.push(OrchardSignature {
+                id: SignatureId {
+                    position,
+                    kind: SignatureKind::OrchardSpendAuth,
+                    index,
+                },
+                item: action
+                    .rk()
+                    .create_batch_item(action.authorization().clone(), sighash),
+            });
+        }
+        self.orchard.push(OrchardSignature {
+            id: SignatureId {
+                position,
+                kind: SignatureKind::OrchardBinding,
+                index: 0,
+            },
+            item: bundle
+                .binding_validating_key()
+                .create_batch_item(bundle.authorization().binding_signature().clone(), sighash),
+        });
+    }
+
+    /// Verifies the accumulated signatures, verifying the Sapling and Orchard batches
+    /// concurrently on the rayon thread pool if `parallel` is set. If any is invalid,
+    /// returns the first (in the order they were queued) that could be found.
+    pub(crate) fn validate(
+        &self,
+        zip216_enabled: bool,
+        parallel: bool,
+    ) -> Result<(), Option<SignatureId>> {
+        let check_sapling = || {
+            validate_batch(&self.sapling, &|signatures| {
+                sapling_signatures_valid(signatures, zip216_enabled)
+            })
+            .map_err(|invalid| invalid.map(|i| self.sapling[i].id))
+        };
+        let check_orchard = || {
+            validate_batch(&self.orchard, &orchard_signatures_valid)
+                .map_err(|invalid| invalid.map(|i| self.orchard[i].id))
+        };
+        let (sapling, orchard) = if parallel {
+            rayon::join(check_sapling, check_orchard)
+        } else {
+            (check_sapling(), check_orchard())
+        };
+
+        match (sapling, orchard) {
+            (Ok(()), Ok(())) => Ok(()),
+            (sapling, orchard) => Err(sapling
+                .err()
+                .flatten()
+                .into_iter()
+                .chain(orchard.err().flatten())
+                .min()),
+        }
+    }
+}
+
+/// Returns whether all of `signatures` are valid, verifying those of each kind in one
+/// batch.
+///
+/// The batch decodes each R with the ZIP 216 rules. Before ZIP 216 is enabled, a
+/// signature whose R is not canonically encoded is verified on its own instead, so
+/// that it is decoded exactly as the per-signature path decodes it.
+fn sapling_signatures_valid(signatures: &[SaplingSignature], zip216_enabled: bool) -> bool {
+    if signatures.is_empty() {
+        // An empty batch is always valid, but is not free to run; skip it.
+        return true;
+    }
+
+    let mut rng = rng::rng().expect("randomness should be available");
+    [
+        SignatureKind::SaplingSpendAuth,
+        SignatureKind::SaplingBinding,
+    ]
+    .iter()
+    .all(|&kind| {
+        let (batched, alone): (Vec<_>, Vec<_>) = signatures
+            .iter()
+            .filter(|signature| signature.id.kind == kind)
+            .partition(|signature| zip216_enabled || signature.r_is_canonical());
+        let entries: Vec<_> = batched
+            .iter()
+            .map(|signature| redjubjub::BatchEntry {
+                vk: redjubjub::PublicKey(signature.vk),
+                msg: &signature.msg[..],
+                sig: signature.signature(),
+            })
+            .collect();
+        (entries.is_empty() || redjubjub::batch_verify(&mut rng, &entries, sapling_generator(kind)))
+            && alone
+                .iter()
+                .all(|signature| signature.verify(zip216_enabled))
+    })
+}
+
+/// Returns whether all of `signatures` are valid, verifying them in one batch.
+fn orchard_signatures_valid(signatures: &[OrchardSignature]) -> bool {
+    if signatures.is_empty() {
+        return true;
+    }
+
+    let mut validator = redpallas::batch::Verifier::new();
+    for signature in signatures {
+        validator.queue(signature.item.clone());
+    }
+    // As for `orchard_ffi::BatchValidator`, this aborts if no randomness can be
+    // obtained rather than treating the signatures as invalid.
+    let rng = rng::rng().expect("randomness should be available");
+    validator.verify(rng).is_ok()
+}
+
+/// Checks `signatures` with `valid`, which verifies a batch of them, bisecting them to
+/// find the index of the first invalid signature if the batch fails.
+fn validate_batch<T>(signatures: &[T], valid: &dyn Fn(&[T]) -> bool) -> Result<(), Option<usize>> {
+    if valid(signatures) {
+        Ok(())
+    } else {
+        // Invalid batches are rare (they make the whole block invalid), so the extra
+        // verifications only cost anything when there is a failure to attribute.
+        Err(first_invalid(signatures, valid))
+    }
+}
+
+/// Finds the index of the first of `signatures`, which are known to include an invalid
+/// signature, that is invalid.
+fn first_invalid<T>(signatures: &[T], valid: &dyn Fn(&[T]) -> bool) -> Option<usize> {
+    match signatures.len() {
+        0 => None,
+        1 => (!valid(signatures)).then(|| 0),
+        len => {
+            let (left, right) = signatures.split_at(len / 2);
+            if valid(left) {
+                first_invalid(right, valid).map(|i| left.len() + i)
+            } else {
+                first_invalid(left, valid)
+            }
+        }
+    }
+}
+
+/// The keys that queued proofs are verified with.
+#[derive(Clone, Copy)]
+pub(crate) struct VerifyingKeys<'a> {
+    pub(crate) sapling_spend: &'a PreparedVerifyingKey<Bls12>,
+    pub(crate) sapling_output: &'a PreparedVerifyingKey<Bls12>,
+    pub(crate) orchard: &'a orchard::circuit::VerifyingKey,
+}
+
+impl VerifyingKeys<'static> {
+    /// Returns the keys loaded by zcashd.
+    fn zcashd() -> Self {
+        VerifyingKeys {
+            sapling_spend: unsafe { crate::SAPLING_SPEND_VK.as_ref() }
+                .expect("parameters should have been initialized"),
+            sapling_output: unsafe { crate::SAPLING_OUTPUT_VK.as_ref() }
+                .expect("parameters should have been initialized"),
+            orchard: unsafe { crate::ORCHARD_VK.as_ref() }
+                .expect("parameters should have been initialized"),
+        }
+    }
+}
+
+/// The memory budget that batch validators are created with if
+/// `librustzcash_init_batch_validation` is not called.
+const FALLBACK_MEMORY_BUDGET: usize = 64 << 20;
+
+/// The memory budget, in bytes, that batch validators are created with, set by
+/// `librustzcash_init_batch_validation`.
+static DEFAULT_MEMORY_BUDGET: OnceCell<usize> = OnceCell::new();
+
+/// Sets the memory budget that batch validators are created with. Returns `false` if
+/// the default has already been set, or has been used.
+pub(crate) fn init_memory_budget(bytes: usize) -> bool {
+    DEFAULT_MEMORY_BUDGET.set(bytes).is_ok()
+}
+
+/// The Sapling and Orchard batch validators for a block.
+pub struct BatchValidators<'a> {
+    zip216_enabled: bool,
+    keys: VerifyingKeys<'a>,
+    /// The approximate number of bytes that the queued bundles may take up before they
+    /// are validated in an intermediate flush. This is only checked before each new
+    /// transaction is queued, so it may be exceeded by one transaction's bundles.
+    memory_budget: usize,
+    transactions: Vec<QueuedTransaction>,
+    /// The approximate number of bytes that the queued bundles take up.
+    queued_bytes: usize,
+    /// The number of intermediate flushes that validated bundles since the validators
+    /// were created.
+    flushes: usize,
+    /// Whether a flush of the block being validated has found an invalid bundle.
+    invalid: bool,
+    /// The txid of the first transaction with an invalid bundle found by a flush of
+    /// the block being validated, and which of its bundles is invalid.
+    flushed_failure: Option<([u8; 32], BatchFailure)>,
+    /// The txid of the first transaction with an invalid bundle, if `validate_all`
+    /// found one, and which of its bundles is invalid.
+    first_failure: Option<([u8; 32], BatchFailure)>,
+}
+
+impl<'a> BatchValidators<'a> {
+    /// Creates the validators for a block, which verify proofs with `keys`.
+    /// `zip216_enabled` enables the ZIP 216 rules for the encodings of Sapling
+    /// signatures, and the queued bundles are flushed once they take up more than
+    /// `memory_budget` bytes.
+    pub(crate) fn new(zip216_enabled: bool, keys: VerifyingKeys<'a>, memory_budget: usize) -> Self {
+        BatchValidators {
+            zip216_enabled,
+            keys,
+            memory_budget,
+            transactions: vec![],
+            queued_bytes: 0,
+            flushes: 0,
+            invalid: false,
+            flushed_failure: None,
+            first_failure: None,
+        }
+    }
+
+    /// Returns the queued transaction with the given txid, queueing it if it is not the
+    /// most recently queued transaction.
+    ///
+    /// A transaction's bundles can only be validated once they are all queued, so this
+    /// is where the queue is flushed if it has exceeded its memory budget. The budget
+    /// is therefore a soft limit, which the bundles of the most recently queued
+    /// transaction can take the queue over.
+    fn transaction(&mut self, txid: [u8; 32]) -> &mut QueuedTransaction {
+        if self.transactions.last().map(|tx| tx.txid) != Some(txid) {
+            // Intermediate flushes always use the rayon thread pool, which zcashd sizes
+            // from `-par`.
+            if self.queued_bytes > self.memory_budget && self.flush(true) {
+                self.flushes += 1;
+            }
+            self.queued_bytes += mem::size_of::<QueuedTransaction>();
+            self.transactions.push(QueuedTransaction {
+                txid,
+                sapling: None,
+                orchard: None,
+            });
+        }
+        self.transactions
+            .last_mut()
+            .expect("just pushed if empty; qed")
+    }
+
+    fn sapling(&mut self, txid: [u8; 32]) -> &mut SaplingBundle {
+        self.transaction(txid)
+            .sapling
+            .get_or_insert_with(SaplingBundle::default)
+    }
+
+    pub(crate) fn queue_sapling_spend(
+        &mut self,
+        txid: [u8; 32],
+        spend: SaplingSpend,
+        sighash: [u8; 32],
+    ) {
+        let footprint = spend.footprint();
+        let sapling = self.sapling(txid);
+        sapling.spends.push(spend);
+        sapling.sighash = sighash;
+        self.queued_bytes += footprint;
+    }
+
+    pub(crate) fn queue_sapling_output(&mut self, txid: [u8; 32], output: SaplingOutput) {
+        let footprint = output.footprint();
+        self.sapling(txid).outputs.push(output);
+        self.queued_bytes += footprint;
+    }
+
+    pub(crate) fn queue_sapling_binding_sig(
+        &mut self,
+        txid: [u8; 32],
+        binding_sig: SaplingBindingSig,
+        sighash: [u8; 32],
+    ) {
+        let footprint = binding_sig.footprint();
+        let sapling = self.sapling(txid);
+        sapling.binding_sig = Some(binding_sig);
+        sapling.sighash = sighash;
+        self.queued_bytes += footprint;
+    }
+
+    pub(crate) fn queue_orchard_bundle(
+        &mut self,
+        txid: [u8; 32],
+        bundle: Bundle<Authorized, Amount>,
+        sighash: [u8; 32],
+    ) {
+        let bundle = OrchardBundle { bundle, sighash };
+        let footprint = bundle.footprint();
+        self.transaction(txid).orchard = Some(bundle);
+        self.queued_bytes += footprint;
+    }
+
+    /// Returns the number of intermediate flushes that the queue has needed to stay
+    /// within its memory budget, since the validators were created. Flushes that were
+    /// dropped after an earlier flush found an invalid bundle are not counted.
+    pub(crate) fn flushes(&self) -> usize {
+        self.flushes
+    }
+
+    /// Validates the bundles queued since the last flush, on the rayon thread pool if
+    /// `parallel` is set, and empties the queue. Returns whether any bundles were
+    /// validated.
+    ///
+    /// Once a flush has found an invalid bundle, the block is invalid and no later
+    /// transaction can be its first, so later flushes are dropped without validating
+    /// them. A failed flush whose invalid bundle couldn't be found doesn't stop them, as
+    /// a later flush may still find one.
+    fn flush(&mut self, parallel: bool) -> bool {
+        let transactions = mem::take(&mut self.transactions);
+        self.queued_bytes = 0;
+        if self.flushed_failure.is_some() || transactions.is_empty() {
+            return false;
+        }
+        debug!(
+            "Validating a batch of {} queued transactions",
+            transactions.len()
+        );
+        if let Err(failure) =
+            validate_transactions(transactions, self.zip216_enabled, &self.keys, parallel)
+        {
+            self.invalid = true;
+            self.flushed_failure = failure;
+        }
+        true
+    }
+
+    /// Validates the queued bundles, on the rayon thread pool if `parallel` is set. If
+    /// any bundle is invalid, `first_failure` then returns the first transaction (in
+    /// the order they were queued) with an invalid bundle, including those that were
+    /// validated by intermediate flushes.
+    ///
+    /// The queue is emptied, so each block's bundles are validated once.
+    pub(crate) fn validate_all(&mut self, parallel: bool) -> bool {
+        self.first_failure = None;
+        if should_fail(FaultSite::BatchValidation) {
+            self.transactions.clear();
+            self.queued_bytes = 0;
+            self.invalid = false;
+            self.flushed_failure = None;
+            error!("Block batch validation failed: injected failure");
+            return false;
+        }
+
+        self.flush(parallel);
+        if !mem::take(&mut self.invalid) {
+            return true;
+        }
+        self.first_failure = self.flushed_failure.take();
+        match self.first_failure {
+            Some((txid, pool)) => error!(
+                "Block batch validation failed: {:?} check of transaction {} is invalid",
+                pool,
+                TxId::from_bytes(txid),
+            ),
+            // Batch verification is randomized, so a sub-batch can pass even though the
+            // full batch failed; the block is still invalid.
+            None => error!("Block batch validation failed, but no invalid bundle was found"),
+        }
+        false
+    }
+
+    /// Returns the txid of the first transaction with an invalid bundle found by the
+    /// last validation, and which of its bundles is invalid.
+    pub(crate) fn first_failure(&self) -> Option<([u8; 32], BatchFailure)> {
+        self.first_failure
+    }
+}
+
+/// Validates the bundles of `transactions` with `keys`, on the rayon thread pool if
+/// `parallel` is set. If any bundle is invalid, returns the txid of the first
+/// transaction with an invalid bundle, if it could be found, and which of its bundles
+/// is invalid.
+fn validate_transactions(
+    transactions: Vec<QueuedTransaction>,
+    zip216_enabled: bool,
+    keys: &VerifyingKeys,
+    parallel: bool,
+) -> Result<(), Option<([u8; 32], BatchFailure)>> {
+    let txids: Vec<_> = transactions.iter().map(|tx| tx.txid).collect();
+    let mut sapling = vec![];
+    let mut orchard = vec![];
+    let mut signatures = BlockSignatures::default();
+    for (position, tx) in transactions.into_iter().enumerate() {
+        if let Some(bundle) = tx.sapling {
+            signatures.add_sapling_bundle(position, &bundle);
+            sapling.push((position, bundle));
+        }
+        if let Some(bundle) = tx.orchard {
+            signatures.add_orchard_bundle(position, &bundle.bundle, &bundle.sighash);
+            orchard.push((position, bundle));
+        }
+    }
+
+    let check_sapling = || validate_sapling(sapling, keys, parallel);
+    let check_orchard_proofs = || validate_orchard_proofs(&orchard, keys.orchard, parallel);
+    let check_signatures = || signatures.validate(zip216_enabled, parallel);
+    let (sapling, (orchard_proof, signature)) = if parallel {
+        rayon::join(check_sapling, || {
+            rayon::join(check_orchard_proofs, check_signatures)
+        })
+    } else {
+        (
+            check_sapling(),
+            (check_orchard_proofs(), check_signatures()),
+        )
+    };
+    let results = [
+        sapling.map_err(|position| position.map(|p| (p, BatchFailure::Sapling))),
+        orchard_proof.map_err(|position| position.map(|p| (p, BatchFailure::OrchardProof))),
+        signature
+            .map_err(|id| id.map(|id| (id.position, BatchFailure::Signature(id.kind, id.index)))),
+    ];
+    if results.iter().all(Result::is_ok) {
+        return Ok(());
+    }
+
+    // Report the earliest failure, preferring proofs over signatures for a transaction
+    // with both.
+    Err(results
+        .iter()
+        .filter_map(|result| *result.as_ref().err()?)
+        .min_by_key(|(position, _)| *position)
+        .map(|(position, failure)| (txids[position], failure)))
+}
+
+/// The outcome of checking one pool's bundles: `Ok` if they are all valid, or the
+/// position among the queued transactions of the first with an invalid bundle, if it
+/// could be found.
+type PoolResult = Result<(), Option<usize>>;
+
+/// Checks each transaction's Sapling proofs.
+fn validate_sapling(
+    bundles: Vec<(usize, SaplingBundle)>,
+    keys: &VerifyingKeys,
+    parallel: bool,
+) -> PoolResult {
+    let (positions, bundles): (Vec<_>, Vec<_>) = bundles.into_iter().unzip();
+    let is_invalid = |bundle: SaplingBundle| !bundle.check_proofs(keys);
+    let invalid = if parallel {
+        bundles.into_par_iter().position_first(is_invalid)
+    } else {
+        bundles.into_iter().position(is_invalid)
+    };
+    match invalid {
+        Some(i) => Err(Some(positions[i])),
+        None => Ok(()),
+    }
+}
+
+/// Checks each transaction's Orchard proof.
+fn validate_orchard_proofs(
+    bundles: &[(usize, OrchardBundle)],
+    vk: &orchard::circuit::VerifyingKey,
+    parallel: bool,
+) -> PoolResult {
+    let is_invalid =
+        |(_, orchard): &(usize, OrchardBundle)| orchard.bundle.verify_proof(vk).is_err();
+    let invalid = if parallel {
+        bundles.par_iter().position_first(is_invalid)
+    } else {
+        bundles.iter().position(is_invalid)
+    };
+    match invalid {
+        Some(i) => Err(Some(bundles[i].0)),
+        None => Ok(()),
+    }
+}
+
+/// Creates the shielded batch validators for a block. `zip216_enabled` enables the ZIP
+/// 216 rules for Sapling signatures, and should be set from NU5 activation.
+///
+/// The proofs are verified with the keys loaded by zcashd, and the queue is kept within
+/// the memory budget set by `librustzcash_init_batch_validation`.
+///
+/// Please free this when you're done.
+#[no_mangle]
+pub extern "C" fn batch_validators_init(zip216_enabled: bool) -> *mut BatchValidators<'static> {
+    let memory_budget = *DEFAULT_MEMORY_BUDGET.get_or_init(|| FALLBACK_MEMORY_BUDGET);
+    Box::into_raw(Box::new(BatchValidators::new(
+        zip216_enabled,
+        VerifyingKeys::zcashd(),
+        memory_budget,
+    )))
+}
+
+/// Frees validators returned from [`batch_validators_init`].
+#[no_mangle]
+pub extern "C" fn batch_validators_free(batch: *mut BatchValidators) {
+    if !batch.is_null() {
+        drop(unsafe { Box::from_raw(batch) });
+    }
+}
+
+/// Queues a Sapling Spend description of the transaction `txid`, whose signatures are
+/// over `sighash`.
+///
+/// Returns `false` if any field is not a valid encoding, in which case nothing is
+/// queued.
+#[no_mangle]
+pub extern "C" fn batch_validators_add_sapling_spend(
+    batch: *mut BatchValidators,
+    txid: *const [u8; 32],
+    cv: *const [u8; 32],
+    anchor: *const [u8; 32],
+    nullifier: *const [u8; 32],
+    rk: *const [u8; 32],
+    zkproof: *const [u8; GROTH_PROOF_SIZE],
+    spend_auth_sig: *const [u8; 64],
+    sighash: *const [u8; 32],
+) -> bool {
+    let batch = unsafe { batch.as_mut() }.expect("batch may not be null.");
+    let spend = unsafe {
+        SaplingSpend::read(
+            &*cv,
+            &*anchor,
+            &*nullifier,
+            &*rk,
+            &*zkproof,
+            &*spend_auth_sig,
+        )
+    };
+    match spend {
+        Some(spend) => {
+            batch.queue_sapling_spend(unsafe { *txid }, spend, unsafe { *sighash });
+            true
+        }
+        None => false,
+    }
+}
+
+/// Queues a Sapling Output description of the transaction `txid`.
+///
+/// Returns `false` if any field is not a valid encoding, in which case nothing is
+/// queued.
+#[no_mangle]
+pub extern "C" fn batch_validators_add_sapling_output(
+    batch: *mut BatchValidators,
+    txid: *const [u8; 32],
+    cv: *const [u8; 32],
+    cm: *const [u8; 32],
+    epk: *const [u8; 32],
+    zkproof: *const [u8; GROTH_PROOF_SIZE],
+) -> bool {
+    let batch = unsafe { batch.as_mut() }.expect("batch may not be null.");
+    match unsafe { SaplingOutput::read(&*cv, &*cm, &*epk, &*zkproof) } {
+        Some(output) => {
+            batch.queue_sapling_output(unsafe { *txid }, output);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Queues the value balance and binding signature of the Sapling bundle of the
+/// transaction `txid`, which must be queued after its Spend and Output descriptions.
+///
+/// Returns `false` if the value balance or signature is invalid, in which case
+/// nothing is queued.
+#[no_mangle]
+pub extern "C" fn batch_validators_add_sapling_binding_sig(
+    batch: *mut BatchValidators,
+    txid: *const [u8; 32],
+    value_balance: i64,
+    binding_sig: *const [u8; 64],
+    sighash: *const [u8; 32],
+) -> bool {
+    let batch = unsafe { batch.as_mut() }.expect("batch may not be null.");
+    match SaplingBindingSig::read(value_balance, unsafe { &*binding_sig }) {
+        Some(binding_sig) => {
+            batch.queue_sapling_binding_sig(unsafe { *txid }, binding_sig, unsafe { *sighash });
+            true
+        }
+        None => false,
+    }
+}
+
+/// Queues the proof and signatures of the Orchard bundle of the transaction `txid`,
+/// whose signatures are over `sighash`. Does nothing if `bundle` is null.
+#[no_mangle]
+pub extern "C" fn batch_validators_add_orchard_bundle(
+    batch: *mut BatchValidators,
+    txid: *const [u8; 32],
+    bundle: *const Bundle<Authorized, Amount>,
+    sighash: *const [u8; 32],
+) {
+    let batch = unsafe { batch.as_mut() }.expect("batch may not be null.");
+    if let Some(bundle) = unsafe { bundle.as_ref() } {
+        batch.queue_orchard_bundle(unsafe { *txid }, bundle.clone(), unsafe { *sighash });
+    }
+}
+
+/// Validates the queued bundles, on the rayon thread pool if `parallel` is set.
+/// Bundles that were already validated by an intermediate flush count towards the
+/// result as if they were still queued.
+///
+/// - Returns `true` if `batch` is null.
+/// - Returns `false` if any queued proof or signature is invalid.
+#[no_mangle]
+pub extern "C" fn batch_validators_validate_all(
+    batch: *mut BatchValidators,
+    parallel: bool,
+) -> bool {
+    if let Some(batch) = unsafe { batch.as_mut() } {
+        batch.validate_all(parallel)
+    } else {
+        debug!("Block BatchValidators not provided, assuming disabled.");
+        true
+    }
+}
+
+/// `batch_validators_first_failure` result: no invalid bundle was found.
+const BATCH_VALIDATORS_NO_FAILURE: u32 = 0;
+/// `batch_validators_first_failure` result: a Sapling proof or signature is invalid.
+const BATCH_VALIDATORS_SAPLING_FAILURE: u32 = 1;
+/// `batch_validators_first_failure` result: an Orchard proof is invalid.
+const BATCH_VALIDATORS_ORCHARD_PROOF_FAILURE: u32 = 2;
+/// `batch_validators_first_failure` result: an Orchard signature is invalid.
+const BATCH_VALIDATORS_ORCHARD_SIGNATURE_FAILURE: u32 = 3;
+/// `batch_validators_first_failure` result: a Sapling signature is invalid.
+const BATCH_VALIDATORS_SAPLING_SIGNATURE_FAILURE: u32 = 4;
+
+/// Writes the txid of the first transaction with an invalid bundle found by
+/// [`batch_validators_validate_all`] to `txid_ret`, and returns which of its bundles is
+/// invalid.
+#[no_mangle]
+pub extern "C" fn batch_validators_first_failure(
+    batch: *const BatchValidators,
+    txid_ret: *mut [u8; 32],
+) -> u32 {
+    let failure = unsafe { batch.as_ref() }.and_then(|batch| batch.first_failure());
+    match (failure, unsafe { txid_ret.as_mut() }) {
+        (Some((txid, pool)), Some(txid_ret)) => {
+            *txid_ret = txid;
+            match pool {
+                BatchFailure::Sapling => BATCH_VALIDATORS_SAPLING_FAILURE,
+                BatchFailure::OrchardProof => BATCH_VALIDATORS_ORCHARD_PROOF_FAILURE,
+                BatchFailure::Signature(
+                    SignatureKind::SaplingSpendAuth | SignatureKind::SaplingBinding,
+                    _,
+                ) => BATCH_VALIDATORS_SAPLING_SIGNATURE_FAILURE,
+                BatchFailure::Signature(_, _) => BATCH_VALIDATORS_ORCHARD_SIGNATURE_FAILURE,
+            }
+        }
+        _ => BATCH_VALIDATORS_NO_FAILURE,
+    }
+}
+
+/// `batch_validators_first_failure_signature` kind: a Sapling spend authorization
+/// signature.
+const BATCH_VALIDATORS_SAPLING_SPEND_AUTH_SIG: u32 = 0;
+/// `batch_validators_first_failure_signature` kind: a Sapling binding signature.
+const BATCH_VALIDATORS_SAPLING_BINDING_SIG: u32 = 1;
+/// `batch_validators_first_failure_signature` kind: an Orchard spend authorization
+/// signature.
+const BATCH_VALIDATORS_ORCHARD_SPEND_AUTH_SIG: u32 = 2;
+/// `batch_validators_first_failure_signature` kind: an Orchard binding signature.
+const BATCH_VALIDATORS_ORCHARD_BINDING_SIG: u32 = 3;
+
+/// If the first failure found by [`batch_validators_validate_all`] is an invalid
+/// signature, writes its kind to `kind_ret` and its index among the transaction's
+/// signatures of that kind (the index of its Spend description or action) to
+/// `index_ret`, and returns `true`.
+#[no_mangle]
+pub extern "C" fn batch_validators_first_failure_signature(
+    batch: *const BatchValidators,
+    kind_ret: *mut u32,
+    index_ret: *mut size_t,
+) -> bool {
+    let failure = unsafe { batch.as_ref() }.and_then(|batch| batch.first_failure());
+    match (failure, unsafe { kind_ret.as_mut() }, unsafe {
+        index_ret.as_mut()
+    }) {
+        (Some((_, BatchFailure::Signature(kind, index))), Some(kind_ret), Some(index_ret)) => {
+            *kind_ret = match kind {
+                SignatureKind::SaplingSpendAuth => BATCH_VALIDATORS_SAPLING_SPEND_AUTH_SIG,
+                SignatureKind::SaplingBinding => BATCH_VALIDATORS_SAPLING_BINDING_SIG,
+                SignatureKind::OrchardSpendAuth => BATCH_VALIDATORS_ORCHARD_SPEND_AUTH_SIG,
+                SignatureKind::OrchardBinding => BATCH_VALIDATORS_ORCHARD_BINDING_SIG,
+            };
+            *index_ret = index;
+            true
+        }
+        _ => false,
+    }
+}
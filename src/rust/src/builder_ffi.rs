@@ -1,19 +1,23 @@
+use std::collections::BTreeMap;
 use std::convert::TryInto;
 use std::ptr;
 use std::slice;
+use std::sync::{Arc, Mutex};
 
 use incrementalmerkletree::Hashable;
 use libc::size_t;
-use orchard::keys::SpendingKey;
+use once_cell::sync::{Lazy, OnceCell};
+use orchard::keys::{SpendAuthorizingKey, SpendingKey};
 use orchard::{
-    builder::{Builder, InProgress, Unauthorized, Unproven},
+    builder::{Builder, Error, InProgress, Unauthorized, Unproven},
     bundle::{Authorized, Flags},
+    circuit::ProvingKey,
     keys::{FullViewingKey, OutgoingViewingKey},
     tree::{MerkleHashOrchard, MerklePath},
     value::NoteValue,
     Bundle, Note,
 };
-use rand_core::OsRng;
+use rand_core::{CryptoRng, RngCore};
 use tracing::error;
 use zcash_primitives::transaction::{
     components::{sapling, Amount},
@@ -24,6 +28,9 @@ use zcash_primitives::transaction::{
 };
 
 use crate::{
+    fault_injection::{should_fail, FaultSite},
+    init_ffi::rayon_pool_builder,
+    rng,
     transaction_ffi::{PrecomputedTxParts, TransparentAuth},
     ORCHARD_PK,
 };
@@ -128,8 +135,15 @@ pub extern "C" fn orchard_builder_build(
         return ptr::null_mut();
     }
     let builder = unsafe { Box::from_raw(builder) };
+    let rng = match rng::rng() {
+        Ok(rng) => rng,
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            return ptr::null_mut();
+        }
+    };
 
-    match builder.build(OsRng) {
+    match builder.build(rng) {
         Ok(bundle) => Box::into_raw(Box::new(bundle)),
         Err(e) => {
             error!("Failed to build Orchard bundle: {:?}", e);
@@ -147,16 +161,98 @@ pub extern "C" fn orchard_unauthorized_bundle_free(
     }
 }
 
+/// The number of threads that Orchard proofs are created with by default (or zero for
+/// one per core), set by `librustzcash_init_orchard_proving`.
+static DEFAULT_PROVING_THREADS: OnceCell<usize> = OnceCell::new();
+
+/// The threadpools that Orchard proofs are created on, by number of threads. Each pool
+/// is created on first use and kept, so that proving doesn't spawn threads each time.
+static PROVING_POOLS: Lazy<Mutex<BTreeMap<usize, Arc<rayon::ThreadPool>>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// Sets the number of threads that Orchard proofs are created with by default, or one
+/// per core if zero. Returns `false` if the default has already been set, or has been
+/// used.
+pub(crate) fn init_proving_threads(num_threads: usize) -> bool {
+    DEFAULT_PROVING_THREADS.set(num_threads).is_ok()
+}
+
+/// Returns the Orchard proving threadpool with `num_threads` threads, or the default
+/// number if zero. If the default hasn't been set, it is the size of the global Rayon
+/// threadpool, which zcashd sizes from `-par`.
+///
+/// Returns `None` if the pool can't be created.
+fn proving_pool(num_threads: usize) -> Option<Arc<rayon::ThreadPool>> {
+    let num_threads = match num_threads {
+        0 => *DEFAULT_PROVING_THREADS.get_or_init(rayon::current_num_threads),
+        n => n,
+    };
+
+    let mut pools = PROVING_POOLS.lock().unwrap();
+    if let Some(pool) = pools.get(&num_threads) {
+        return Some(pool.clone());
+    }
+    match rayon_pool_builder(num_threads, "zc-orchard".to_owned(), false).build() {
+        Ok(pool) => {
+            let pool = Arc::new(pool);
+            pools.insert(num_threads, pool.clone());
+            Some(pool)
+        }
+        Err(e) => {
+            error!("Failed to create the Orchard proving threadpool: {}", e);
+            None
+        }
+    }
+}
+
+/// Runs `f` on the Orchard proving threadpool with `num_threads` threads (or the
+/// default number if zero), which bounds the parallelism of any proving in `f`. If the
+/// pool can't be created, `f` is run on the calling thread's pool instead.
+pub(crate) fn on_proving_pool<T: Send>(num_threads: usize, f: impl FnOnce() -> T + Send) -> T {
+    match proving_pool(num_threads) {
+        Some(pool) => pool.install(f),
+        None => f(),
+    }
+}
+
+/// Returns the number of threads that Orchard proofs are created with by default.
+#[no_mangle]
+pub extern "C" fn orchard_proving_threads() -> size_t {
+    on_proving_pool(0, rayon::current_num_threads)
+}
+
+/// Proves `bundle` with `pk` on the Orchard proving threadpool with `num_threads`
+/// threads (or the default number if zero), and signs it with `signing_keys`.
+pub(crate) fn prove_and_sign<R: RngCore + CryptoRng + Send>(
+    bundle: Bundle<InProgress<Unproven, Unauthorized>, Amount>,
+    pk: &ProvingKey,
+    signing_keys: &[SpendAuthorizingKey],
+    sighash: [u8; 32],
+    num_threads: usize,
+    mut rng: R,
+) -> Result<Bundle<Authorized, Amount>, Error> {
+    on_proving_pool(num_threads, || bundle.create_proof(pk, &mut rng))?.apply_signatures(
+        &mut rng,
+        sighash,
+        signing_keys,
+    )
+}
+
 #[no_mangle]
 pub extern "C" fn orchard_unauthorized_bundle_prove_and_sign(
     bundle: *mut Bundle<InProgress<Unproven, Unauthorized>, Amount>,
     keys: *const *const SpendingKey,
     keys_len: size_t,
     sighash: *const [u8; 32],
+    num_threads: size_t,
 ) -> *mut Bundle<Authorized, Amount> {
     let bundle = unsafe { Box::from_raw(bundle) };
     let keys = unsafe { slice::from_raw_parts(keys, keys_len) };
     let sighash = unsafe { sighash.as_ref() }.expect("sighash pointer may not be null.");
+    if should_fail(FaultSite::OrchardProof) {
+        error!("An error occurred while authorizing the orchard bundle: injected failure");
+        return ptr::null_mut();
+    }
     let pk = unsafe { ORCHARD_PK.as_ref() }.unwrap();
 
     let signing_keys = keys
@@ -168,10 +264,14 @@ pub extern "C" fn orchard_unauthorized_bundle_prove_and_sign(
         })
         .collect::<Vec<_>>();
 
-    let mut rng = OsRng;
-    let res = bundle
-        .create_proof(pk, &mut rng)
-        .and_then(|b| b.apply_signatures(&mut rng, *sighash, &signing_keys));
+    let rng = match rng::rng() {
+        Ok(rng) => rng,
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            return ptr::null_mut();
+        }
+    };
+    let res = prove_and_sign(*bundle, pk, &signing_keys, *sighash, num_threads, rng);
 
     match res {
         Ok(signed) => Box::into_raw(Box::new(signed)),
@@ -4,19 +4,28 @@
 
 use ed25519_zebra::{Signature, SigningKey, VerificationKey};
 use libc::{c_uchar, size_t};
-use rand_core::OsRng;
 use std::convert::TryFrom;
 use std::slice;
+use tracing::error;
+
+use crate::rng;
 
 #[no_mangle]
-pub extern "C" fn ed25519_generate_keypair(sk: *mut [u8; 32], vk: *mut [u8; 32]) {
+pub extern "C" fn ed25519_generate_keypair(sk: *mut [u8; 32], vk: *mut [u8; 32]) -> bool {
     let sk = unsafe { sk.as_mut() }.unwrap();
     let vk = unsafe { vk.as_mut() }.unwrap();
 
-    let signing_key = SigningKey::new(OsRng);
+    let signing_key = match rng::rng() {
+        Ok(rng) => SigningKey::new(rng),
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            return false;
+        }
+    };
 
     *sk = signing_key.into();
     *vk = VerificationKey::from(&signing_key).into();
+    true
 }
 
 #[no_mangle]
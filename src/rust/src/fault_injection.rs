@@ -0,0 +1,178 @@
+//! Injected failures, for testing the error paths of proving and verification.
+//!
+//! Some errors, such as a proof failing to be created or a parameter file turning out
+//! to be truncated, can't be triggered on demand. Each such error path is guarded by a
+//! named [`FaultSite`], which tests can arm with [`test_inject_failure`] to make the
+//! next few calls through it fail as if the error had occurred.
+//!
+//! Sites can only be armed in tests and with the `test-dependencies` feature. Otherwise
+//! [`should_fail`] is always `false`, and the checks compile away.
+
+/// A point at which a failure can be injected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum FaultSite {
+    /// The start of Sapling Spend proof creation.
+    SaplingSpendProof,
+    /// The start of Sapling Output proof creation.
+    SaplingOutputProof,
+    /// The start of Orchard bundle proving.
+    OrchardProof,
+    /// The start of Sprout, Orchard and block-wide batch validation.
+    BatchValidation,
+    /// The start of reading the Sapling proving parameters.
+    ParamsLoading,
+    /// Drawing randomness for the FFI.
+    Randomness,
+}
+
+impl FaultSite {
+    /// Every fault site.
+    #[cfg(any(test, feature = "test-dependencies"))]
+    const ALL: [FaultSite; 6] = [
+        FaultSite::SaplingSpendProof,
+        FaultSite::SaplingOutputProof,
+        FaultSite::OrchardProof,
+        FaultSite::BatchValidation,
+        FaultSite::ParamsLoading,
+        FaultSite::Randomness,
+    ];
+
+    /// The name by which tests arm this site.
+    #[cfg(any(test, feature = "test-dependencies"))]
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            FaultSite::SaplingSpendProof => "sapling_spend_proof",
+            FaultSite::SaplingOutputProof => "sapling_output_proof",
+            FaultSite::OrchardProof => "orchard_proof",
+            FaultSite::BatchValidation => "batch_validation",
+            FaultSite::ParamsLoading => "params_loading",
+            FaultSite::Randomness => "randomness",
+        }
+    }
+}
+
+#[cfg(any(test, feature = "test-dependencies"))]
+mod registry {
+    use super::FaultSite;
+
+    // The unit tests run in parallel, so each test thread has its own registry, and
+    // arming a site can't fail a proof being made by another test. The C++ tests arm
+    // sites from a different thread to the one that reaches them (such as that of an
+    // async RPC operation), so they share one registry.
+    #[cfg(test)]
+    mod storage {
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+
+        use super::FaultSite;
+
+        thread_local! {
+            static ARMED: RefCell<BTreeMap<FaultSite, u32>> = RefCell::new(BTreeMap::new());
+        }
+
+        pub(super) fn with<T>(f: impl FnOnce(&mut BTreeMap<FaultSite, u32>) -> T) -> T {
+            ARMED.with(|armed| f(&mut armed.borrow_mut()))
+        }
+    }
+
+    #[cfg(not(test))]
+    mod storage {
+        use std::collections::BTreeMap;
+        use std::sync::Mutex;
+
+        use once_cell::sync::Lazy;
+
+        use super::FaultSite;
+
+        static ARMED: Lazy<Mutex<BTreeMap<FaultSite, u32>>> =
+            Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+        pub(super) fn with<T>(f: impl FnOnce(&mut BTreeMap<FaultSite, u32>) -> T) -> T {
+            f(&mut ARMED.lock().unwrap())
+        }
+    }
+
+    pub(super) fn arm(site: FaultSite, count: u32) {
+        storage::with(|armed| {
+            if count == 0 {
+                armed.remove(&site);
+            } else {
+                armed.insert(site, count);
+            }
+        })
+    }
+
+    pub(super) fn remaining(site: FaultSite) -> u32 {
+        storage::with(|armed| armed.get(&site).copied().unwrap_or(0))
+    }
+
+    pub(super) fn trigger(site: FaultSite) -> bool {
+        storage::with(|armed| match armed.get_mut(&site) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    armed.remove(&site);
+                }
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+/// Arms the site named `site_name` so that the next `count` calls through it fail, or
+/// disarms it if `count` is 0.
+///
+/// Returns `false` if there is no site with that name.
+#[cfg(any(test, feature = "test-dependencies"))]
+pub(crate) fn test_inject_failure(site_name: &str, count: u32) -> bool {
+    match FaultSite::ALL.iter().find(|site| site.name() == site_name) {
+        Some(site) => {
+            registry::arm(*site, count);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns how many more calls through `site` will fail.
+#[cfg(test)]
+pub(crate) fn injected_failures_remaining(site: FaultSite) -> u32 {
+    registry::remaining(site)
+}
+
+/// Returns whether the call through `site` should fail, consuming one of the failures
+/// it was armed with.
+#[cfg(any(test, feature = "test-dependencies"))]
+pub(crate) fn should_fail(site: FaultSite) -> bool {
+    let fail = registry::trigger(site);
+    if fail {
+        tracing::warn!("Injecting a failure at {}", site.name());
+    }
+    fail
+}
+
+/// Returns whether the call through `site` should fail, which it never does without
+/// the `test-dependencies` feature.
+#[cfg(not(any(test, feature = "test-dependencies")))]
+#[inline(always)]
+pub(crate) fn should_fail(_site: FaultSite) -> bool {
+    false
+}
+
+/// Arms the fault site named `site_name` so that the next `count` calls through it
+/// fail, or disarms it if `count` is 0.
+///
+/// Returns `false` if there is no site with that name.
+#[cfg(any(test, feature = "test-dependencies"))]
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_inject_failure(
+    site_name: *const libc::c_char,
+    count: u32,
+) -> bool {
+    let site_name = unsafe { std::ffi::CStr::from_ptr(site_name) };
+    match site_name.to_str() {
+        Ok(site_name) => test_inject_failure(site_name, count),
+        Err(_) => false,
+    }
+}
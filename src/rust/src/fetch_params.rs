@@ -0,0 +1,288 @@
+//! Downloading of the Groth16 parameter files, replacing `zcutil/fetch-params.sh`.
+//!
+//! Each file is published in two parts. The parts are downloaded next to the
+//! destination (resuming any partial download with a Range request), concatenated, and
+//! checked against the published SHA-256 hash before the file is renamed into place.
+//!
+//! This module is also built into `zcashd-wallet-tool`, so it only depends on `std`,
+//! `minreq` and `sha2`.
+
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// The servers that the parameters are downloaded from by default, in the order they
+/// are tried. The second is a public gateway to the IPFS directory that
+/// `zcutil/fetch-params.sh` fetches from, which holds the same parts.
+pub const DEFAULT_MIRRORS: &[&str] = &[
+    "https://download.z.cash/downloads",
+    "https://ipfs.io/ipfs/QmXRHVGLQBiKwvNq7c2vPxAKz1zRVmMYbmt7G5TQss7tY7",
+];
+
+/// A parameters file, and the SHA-256 hash that it is published with.
+#[derive(Clone, Copy, Debug)]
+pub struct ParamsFile {
+    pub name: &'static str,
+    pub sha256: &'static str,
+}
+
+/// The parameter files that zcashd needs, in the order of their file indices in
+/// progress reports.
+pub const PARAMS_FILES: &[ParamsFile] = &[
+    ParamsFile {
+        name: "sapling-spend.params",
+        sha256: "8e48ffd23abb3a5fd9c5589204f32d9c31285a04b78096ba40a79b75677efc13",
+    },
+    ParamsFile {
+        name: "sapling-output.params",
+        sha256: "2f0ebbcbb9bb0bcffe95a397e7eba89c29eb4dde6191c339db88570e3f3fb0e4",
+    },
+    ParamsFile {
+        name: "sprout-groth16.params",
+        sha256: "b685d700c60328498fbde589c8c7c484c722b788b265b72af448a5bf0ee55b50",
+    },
+];
+
+/// The number of parts that each file is published in.
+const PARTS: u32 = 2;
+
+/// How often progress is reported while downloading.
+const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// Errors that can occur while fetching the parameters.
+#[derive(Debug)]
+pub enum FetchError {
+    /// A local file operation failed.
+    Io(PathBuf, io::Error),
+    /// None of the mirrors could provide part of a file. Contains the last error.
+    Network(String),
+    /// The downloaded file does not have the published hash.
+    HashMismatch(PathBuf),
+    /// A file that does not have the published hash is already at the destination.
+    ExistingInvalid(PathBuf),
+    /// The download was cancelled by the progress callback.
+    Cancelled,
+}
+
+impl fmt::Display for FetchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FetchError::Io(path, e) => write!(f, "Couldn't access {}: {}", path.display(), e),
+            FetchError::Network(e) => write!(f, "Couldn't download the parameters: {}", e),
+            FetchError::HashMismatch(path) => write!(
+                f,
+                "The downloaded {} has an unexpected hash, and has been discarded",
+                path.display()
+            ),
+            FetchError::ExistingInvalid(path) => write!(
+                f,
+                "{} already exists but has an unexpected hash; remove it and try again",
+                path.display()
+            ),
+            FetchError::Cancelled => write!(f, "Downloading the parameters was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for FetchError {}
+
+fn io_error(path: &Path) -> impl FnOnce(io::Error) -> FetchError + '_ {
+    move |e| FetchError::Io(path.to_owned(), e)
+}
+
+/// Downloads each of `files` that is not already in `dest_dir`, trying each of
+/// `mirrors` in turn for every part.
+///
+/// Files that are already in `dest_dir` with the published hash are left alone, and
+/// files with any other contents are never overwritten. Partial downloads are kept so
+/// that they can be resumed by a later call.
+///
+/// `progress` is called with `(file_index, bytes_done, bytes_total)` while each file
+/// is downloaded, where `bytes_total` only counts the parts whose size is known so far.
+/// If it returns `false`, the download stops with `FetchError::Cancelled`.
+pub fn fetch_params(
+    dest_dir: &Path,
+    files: &[ParamsFile],
+    mirrors: &[&str],
+    progress: &mut dyn FnMut(u32, u64, u64) -> bool,
+) -> Result<(), FetchError> {
+    fs::create_dir_all(dest_dir).map_err(io_error(dest_dir))?;
+    for (file_index, file) in files.iter().enumerate() {
+        fetch_file(dest_dir, file, file_index as u32, mirrors, progress)?;
+    }
+    Ok(())
+}
+
+fn fetch_file(
+    dest_dir: &Path,
+    file: &ParamsFile,
+    file_index: u32,
+    mirrors: &[&str],
+    progress: &mut dyn FnMut(u32, u64, u64) -> bool,
+) -> Result<(), FetchError> {
+    let dest = dest_dir.join(file.name);
+    if dest.exists() {
+        return if sha256_files(&[dest.clone()], None)? == file.sha256 {
+            Ok(())
+        } else {
+            Err(FetchError::ExistingInvalid(dest))
+        };
+    }
+
+    let parts: Vec<_> = (1..=PARTS)
+        .map(|i| dest_dir.join(format!("{}.dl.part.{}", file.name, i)))
+        .collect();
+    let mut earlier_parts = 0;
+    for (i, part) in parts.iter().enumerate() {
+        let remote_name = format!("{}.part.{}", file.name, i + 1);
+        let mut report = |done: u64, total: u64| {
+            progress(file_index, earlier_parts + done, earlier_parts + total)
+        };
+        fetch_part(&remote_name, part, mirrors, &mut report)?;
+        earlier_parts += fs::metadata(part).map_err(io_error(part))?.len();
+    }
+
+    // Only move the file into place once the whole of it has been checked.
+    let download = dest_dir.join(format!("{}.dl", file.name));
+    let hash = sha256_files(&parts, Some(&download))?;
+    let discard_parts = || {
+        for part in &parts {
+            let _ = fs::remove_file(part);
+        }
+    };
+    if hash != file.sha256 {
+        // Start again from scratch next time.
+        discard_parts();
+        let _ = fs::remove_file(&download);
+        return Err(FetchError::HashMismatch(dest));
+    }
+    fs::rename(&download, &dest).map_err(io_error(&dest))?;
+    discard_parts();
+    Ok(())
+}
+
+/// Hashes the concatenation of `paths`, writing it to `output` if provided.
+fn sha256_files(paths: &[PathBuf], output: Option<&Path>) -> Result<String, FetchError> {
+    let mut output = match output {
+        Some(path) => Some((path, File::create(path).map_err(io_error(path))?)),
+        None => None,
+    };
+
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    for path in paths {
+        let mut input = File::open(path).map_err(io_error(path))?;
+        loop {
+            let n = input.read(&mut buf).map_err(io_error(path))?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            if let Some((path, file)) = output.as_mut() {
+                file.write_all(&buf[..n]).map_err(io_error(path))?;
+            }
+        }
+    }
+    if let Some((path, file)) = output {
+        file.sync_all().map_err(io_error(path))?;
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// Downloads one part of a file from the first mirror that can provide it.
+fn fetch_part(
+    remote_name: &str,
+    path: &Path,
+    mirrors: &[&str],
+    report: &mut dyn FnMut(u64, u64) -> bool,
+) -> Result<(), FetchError> {
+    let mut last_error = None;
+    for mirror in mirrors {
+        let url = format!("{}/{}", mirror.trim_end_matches('/'), remote_name);
+        match download(&url, path, report) {
+            Err(FetchError::Network(e)) => last_error = Some(e),
+            res => return res,
+        }
+    }
+    Err(FetchError::Network(
+        last_error.unwrap_or_else(|| "No mirrors are configured".to_owned()),
+    ))
+}
+
+/// Downloads `url` to `path`, resuming from the end of any existing file at `path`.
+fn download(
+    url: &str,
+    path: &Path,
+    report: &mut dyn FnMut(u64, u64) -> bool,
+) -> Result<(), FetchError> {
+    let network_error = |e: &dyn fmt::Display| FetchError::Network(format!("{}: {}", url, e));
+
+    let offset = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => 0,
+        Err(e) => return Err(FetchError::Io(path.to_owned(), e)),
+    };
+    let mut request = minreq::get(url);
+    if offset > 0 {
+        request = request.with_header("Range", format!("bytes={}-", offset));
+    }
+    let mut response = request.send_lazy().map_err(|e| network_error(&e))?;
+
+    let resumed = match response.status_code {
+        200 => false,
+        206 if response
+            .headers
+            .get("content-range")
+            .map_or(false, |range| {
+                range.starts_with(&format!("bytes {}-", offset))
+            }) =>
+        {
+            true
+        }
+        // The part was already completely downloaded.
+        416 if offset > 0 => return Ok(()),
+        status => return Err(network_error(&format!("HTTP status {}", status))),
+    };
+    let mut file = if resumed {
+        OpenOptions::new().append(true).open(path)
+    } else {
+        File::create(path)
+    }
+    .map_err(io_error(path))?;
+
+    let mut done = if resumed { offset } else { 0 };
+    let total = response
+        .headers
+        .get("content-length")
+        .and_then(|len| len.parse::<u64>().ok())
+        .map_or(0, |len| done + len);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = response.read(&mut buf).map_err(|e| network_error(&e))?;
+        file.write_all(&buf[..n]).map_err(io_error(path))?;
+        let before = done;
+        done += n as u64;
+
+        // Report each time another interval is crossed, and at the end.
+        if (n == 0 || done / PROGRESS_INTERVAL > before / PROGRESS_INTERVAL) && !report(done, total)
+        {
+            return Err(FetchError::Cancelled);
+        }
+        if n == 0 {
+            break;
+        }
+    }
+    if total != 0 && done != total {
+        return Err(network_error(&"The connection was closed early"));
+    }
+
+    file.sync_all().map_err(io_error(path))
+}
@@ -0,0 +1,509 @@
+//! Loading of the Groth16 parameters for Sprout and Sapling.
+//!
+//! The verifying keys are needed to validate blocks, so they're loaded when the node
+//! starts. The Sapling verifying keys are embedded in the library, so the Sapling
+//! parameter files are only needed for proving.
+//! The much larger Sapling proving parameters are only needed to create proofs, which
+//! most nodes never do, so they're loaded from disk the first time a proof is made, and
+//! can be unloaded again afterwards. (The Sprout proving parameters are read from disk
+//! for each proof, by `sprout_prover`.)
+//!
+//! Each file is streamed through the hash that checks it, rather than being read into
+//! memory first, and callers can observe and cancel that with a [`Progress`].
+//!
+//! The files are not memory-mapped. `Parameters::read` deserializes every point into a
+//! heap-allocated `Vec`, so a mapping would only be read through once and would not
+//! reduce the memory held by loaded parameters. Unloading them with
+//! [`LazyProvingParams::unload`] is what returns that memory.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use bellman::groth16::{prepare_verifying_key, Parameters, PreparedVerifyingKey, VerifyingKey};
+use bls12_381::{Bls12, G1Affine, G2Affine};
+use zcash_proofs::hashreader::HashReader;
+
+use crate::fault_injection::{should_fail, FaultSite};
+
+/// The BLAKE2b-512 hash of `sapling-spend.params`. This MUST match the hash checked by
+/// `zcash_proofs::load_parameters`.
+pub(crate) const SAPLING_SPEND_HASH: &str = "8270785a1a0d0bc77196f000ee6d221c9c9894f55307bd9357c3f0105d31ca63991ab91324160d8f53e2bbd3c2633a6eb8bdf5205d822e7f3f73edac51b2b70c";
+
+/// The BLAKE2b-512 hash of `sapling-output.params`. This MUST match the hash checked by
+/// `zcash_proofs::load_parameters`.
+pub(crate) const SAPLING_OUTPUT_HASH: &str = "657e3d38dbb5cb5e7dd2970e8b03d69b4787dd907285b5a7f0790dcc8072f60bf593b32cc2d1c030e00ff5ae64bf84c5c3beb84ddc841d48264b4a171744d028";
+
+/// The BLAKE2b-512 hash of `sprout-groth16.params`. This MUST match the hash checked by
+/// `zcash_proofs::load_parameters`.
+const SPROUT_HASH: &str = "e9b238411bd6c0ec4791e9d04245ec350c9c5744f5610dfcce4365d5ca49dfefd5054e371842b3f88fa1b9d7e8e075249b3ebabd167fa8b0f3161292d36c180a";
+
+/// Errors that can occur while loading a parameters file.
+#[derive(Debug)]
+pub enum ParamsError {
+    /// The file could not be read.
+    Io(PathBuf, io::Error),
+    /// The file could not be parsed.
+    Invalid(PathBuf, io::Error),
+    /// The file does not have the expected hash.
+    HashMismatch(PathBuf),
+    /// The file's verifying key differs from the one embedded in the library.
+    EmbeddedMismatch(PathBuf),
+    /// Loading was cancelled by the progress callback.
+    Cancelled,
+}
+
+impl fmt::Display for ParamsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParamsError::Io(path, e) => write!(f, "Couldn't read {}: {}", path.display(), e),
+            ParamsError::Invalid(path, e) => {
+                write!(f, "Couldn't deserialize {}: {}", path.display(), e)
+            }
+            ParamsError::HashMismatch(path) => write!(
+                f,
+                "{} has an unexpected hash; try re-running fetch-params",
+                path.display()
+            ),
+            ParamsError::EmbeddedMismatch(path) => write!(
+                f,
+                "{} doesn't contain the verifying key built into zcashd",
+                path.display()
+            ),
+            ParamsError::Cancelled => write!(f, "Loading parameters was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for ParamsError {}
+
+/// The index of `sapling-spend.params` in progress reports.
+pub(crate) const SAPLING_SPEND_FILE: u32 = 0;
+/// The index of `sapling-output.params` in progress reports.
+pub(crate) const SAPLING_OUTPUT_FILE: u32 = 1;
+/// The index of `sprout-groth16.params` in progress reports.
+pub(crate) const SPROUT_FILE: u32 = 2;
+
+/// Reports progress through the parameter files while they are read.
+pub(crate) struct Progress<'a> {
+    interval: u64,
+    callback: Option<&'a mut dyn FnMut(u32, u64, u64) -> bool>,
+}
+
+impl<'a> Progress<'a> {
+    /// Doesn't report progress.
+    pub(crate) fn none() -> Self {
+        Progress {
+            interval: u64::MAX,
+            callback: None,
+        }
+    }
+
+    /// Calls `callback(file_index, bytes_done, bytes_total)` each time another
+    /// `interval` bytes of a file have been read, and when the end of the file is
+    /// reached. If the callback returns `false`, loading stops with
+    /// `ParamsError::Cancelled`.
+    pub(crate) fn new(interval: u64, callback: &'a mut dyn FnMut(u32, u64, u64) -> bool) -> Self {
+        assert!(interval > 0);
+        Progress {
+            interval,
+            callback: Some(callback),
+        }
+    }
+}
+
+/// Marks the I/O error used to abort reading when progress reporting is cancelled.
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A reader that reports how far through a file it has read.
+struct ProgressReader<'p, 'a, R> {
+    inner: R,
+    file_index: u32,
+    bytes_done: u64,
+    bytes_total: u64,
+    reported: u64,
+    progress: &'p mut Progress<'a>,
+}
+
+impl<'p, 'a, R> ProgressReader<'p, 'a, R> {
+    fn report(&mut self) -> io::Result<()> {
+        self.reported = self.bytes_done;
+        if let Some(callback) = self.progress.callback.as_mut() {
+            if !callback(self.file_index, self.bytes_done, self.bytes_total) {
+                return Err(io::Error::new(io::ErrorKind::Other, Cancelled));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'p, 'a, R: Read> Read for ProgressReader<'p, 'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bytes_done += n as u64;
+
+        let interval = self.progress.interval;
+        let at_eof = n == 0 && !buf.is_empty();
+        if self.bytes_done / interval > self.reported / interval
+            || (at_eof && self.bytes_done > self.reported)
+        {
+            self.report()?;
+        }
+        Ok(n)
+    }
+}
+
+type ParamsReader<'p, 'a> = HashReader<BufReader<ProgressReader<'p, 'a, File>>>;
+
+/// Maps an error from reading a parameters file, preserving cancellation.
+fn read_error(
+    path: &Path,
+    e: io::Error,
+    kind: fn(PathBuf, io::Error) -> ParamsError,
+) -> ParamsError {
+    if e.get_ref().map_or(false, |inner| inner.is::<Cancelled>()) {
+        ParamsError::Cancelled
+    } else {
+        kind(path.to_owned(), e)
+    }
+}
+
+fn open_params_file<'p, 'a>(
+    path: &Path,
+    file_index: u32,
+    progress: &'p mut Progress<'a>,
+) -> Result<ParamsReader<'p, 'a>, ParamsError> {
+    let file = File::open(path).map_err(|e| ParamsError::Io(path.to_owned(), e))?;
+    let bytes_total = file
+        .metadata()
+        .map_err(|e| ParamsError::Io(path.to_owned(), e))?
+        .len();
+    Ok(HashReader::new(BufReader::with_capacity(
+        1024 * 1024,
+        ProgressReader {
+            inner: file,
+            file_index,
+            bytes_done: 0,
+            bytes_total,
+            reported: 0,
+            progress,
+        },
+    )))
+}
+
+/// Reads the remainder of a parameters file (for the proving parameters, the transcript
+/// that follows them) so that the hash covers the whole file, matching `b2sum`, and
+/// checks that it is `expected_hash`.
+fn check_params_hash(
+    path: &Path,
+    mut reader: ParamsReader,
+    expected_hash: &str,
+) -> Result<(), ParamsError> {
+    io::copy(&mut reader, &mut io::sink()).map_err(|e| read_error(path, e, ParamsError::Io))?;
+    if reader.into_hash() == expected_hash {
+        Ok(())
+    } else {
+        Err(ParamsError::HashMismatch(path.to_owned()))
+    }
+}
+
+/// Reads the verifying key from the front of a parameters file without keeping the
+/// proving parameters that follow it.
+pub(crate) fn read_verifying_key(
+    path: &Path,
+    expected_hash: &str,
+    file_index: u32,
+    progress: &mut Progress,
+) -> Result<VerifyingKey<Bls12>, ParamsError> {
+    let mut reader = open_params_file(path, file_index, progress)?;
+    let vk = VerifyingKey::<Bls12>::read(&mut reader)
+        .map_err(|e| read_error(path, e, ParamsError::Invalid))?;
+    check_params_hash(path, reader, expected_hash)?;
+    Ok(vk)
+}
+
+/// The verifying key at the front of `sapling-spend.params`, extracted from it by
+/// `zcutil/extract-sapling-vks.py`.
+const EMBEDDED_SAPLING_SPEND_VK: &[u8] = include_bytes!("res/sapling-spend.vk");
+/// The verifying key at the front of `sapling-output.params`, extracted from it by
+/// `zcutil/extract-sapling-vks.py`.
+const EMBEDDED_SAPLING_OUTPUT_VK: &[u8] = include_bytes!("res/sapling-output.vk");
+
+/// Returns the Sapling Spend and Output verifying keys embedded in the library.
+pub(crate) fn embedded_sapling_verifying_keys() -> (VerifyingKey<Bls12>, VerifyingKey<Bls12>) {
+    // The extraction script checked the hashes of the files these were extracted from.
+    let read = |vk: &[u8]| VerifyingKey::read(vk).expect("embedded verifying key is valid");
+    (
+        read(EMBEDDED_SAPLING_SPEND_VK),
+        read(EMBEDDED_SAPLING_OUTPUT_VK),
+    )
+}
+
+/// Checks that the parameters file at `path`, if it exists, contains `embedded_vk`.
+fn check_embedded_verifying_key(
+    path: &Path,
+    embedded_vk: &VerifyingKey<Bls12>,
+    expected_hash: &str,
+    file_index: u32,
+    progress: &mut Progress,
+) -> Result<(), ParamsError> {
+    match read_verifying_key(path, expected_hash, file_index, progress) {
+        Ok(vk) if &vk == embedded_vk => Ok(()),
+        Ok(_) => Err(ParamsError::EmbeddedMismatch(path.to_owned())),
+        // The file is only needed for proving.
+        Err(ParamsError::Io(_, e)) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the proving parameters from the parameters file at `path`.
+pub(crate) fn read_proving_params(
+    path: &Path,
+    expected_hash: &str,
+    file_index: u32,
+) -> Result<Parameters<Bls12>, ParamsError> {
+    if should_fail(FaultSite::ParamsLoading) {
+        return Err(ParamsError::Invalid(
+            path.to_owned(),
+            io::Error::new(io::ErrorKind::UnexpectedEof, "injected truncation"),
+        ));
+    }
+    let mut progress = Progress::none();
+    let mut reader = open_params_file(path, file_index, &mut progress)?;
+    let params = Parameters::read(&mut reader, false)
+        .map_err(|e| read_error(path, e, ParamsError::Invalid))?;
+    check_params_hash(path, reader, expected_hash)?;
+    Ok(params)
+}
+
+/// The locations of the parameter files.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct ParamsPaths {
+    pub(crate) spend: PathBuf,
+    pub(crate) output: PathBuf,
+    pub(crate) sprout: Option<PathBuf>,
+}
+
+/// The Groth16 verifying keys.
+pub(crate) struct VerifyingKeys {
+    pub(crate) spend_vk: PreparedVerifyingKey<Bls12>,
+    pub(crate) output_vk: PreparedVerifyingKey<Bls12>,
+    pub(crate) sprout_vk: Option<PreparedVerifyingKey<Bls12>>,
+    /// The unprepared Sprout verifying key, which batch verification needs.
+    pub(crate) sprout_batch_vk: Option<VerifyingKey<Bls12>>,
+}
+
+/// What the Sapling parameter files are checked against.
+pub(crate) struct SaplingParamsSpec<'a> {
+    pub(crate) spend_hash: &'a str,
+    pub(crate) output_hash: &'a str,
+    /// The Sapling Spend and Output verifying keys embedded in the library, if any.
+    pub(crate) embedded_vks: Option<(VerifyingKey<Bls12>, VerifyingKey<Bls12>)>,
+}
+
+impl SaplingParamsSpec<'static> {
+    /// The Zcash Sapling parameters, and the verifying keys embedded in the library.
+    pub(crate) fn zcash() -> Self {
+        SaplingParamsSpec {
+            spend_hash: SAPLING_SPEND_HASH,
+            output_hash: SAPLING_OUTPUT_HASH,
+            embedded_vks: Some(embedded_sapling_verifying_keys()),
+        }
+    }
+}
+
+/// Loads the Sapling Spend and Output verifying keys, and the Sprout verifying key if
+/// `sprout_path` is provided.
+///
+/// Unless `force_files` is `true`, the embedded Sapling verifying keys are used, and the
+/// Sapling parameter files may be absent; any that are present must contain the embedded
+/// keys. If `force_files` is `true`, the Sapling verifying keys are read from the
+/// parameter files, which must then exist.
+pub(crate) fn load_verifying_keys(
+    spend_path: &Path,
+    output_path: &Path,
+    sprout_path: Option<&Path>,
+    force_files: bool,
+    progress: &mut Progress,
+) -> Result<VerifyingKeys, ParamsError> {
+    load_verifying_keys_with(
+        &SaplingParamsSpec::zcash(),
+        spend_path,
+        output_path,
+        sprout_path,
+        force_files,
+        progress,
+    )
+}
+
+/// Like [`load_verifying_keys`], with the Sapling parameter files checked against
+/// `spec`.
+pub(crate) fn load_verifying_keys_with(
+    spec: &SaplingParamsSpec,
+    spend_path: &Path,
+    output_path: &Path,
+    sprout_path: Option<&Path>,
+    force_files: bool,
+    progress: &mut Progress,
+) -> Result<VerifyingKeys, ParamsError> {
+    let (spend_vk, output_vk) = match &spec.embedded_vks {
+        Some((spend_vk, output_vk)) if !force_files => {
+            check_embedded_verifying_key(
+                spend_path,
+                spend_vk,
+                spec.spend_hash,
+                SAPLING_SPEND_FILE,
+                progress,
+            )?;
+            check_embedded_verifying_key(
+                output_path,
+                output_vk,
+                spec.output_hash,
+                SAPLING_OUTPUT_FILE,
+                progress,
+            )?;
+            (spend_vk.clone(), output_vk.clone())
+        }
+        _ => (
+            read_verifying_key(spend_path, spec.spend_hash, SAPLING_SPEND_FILE, progress)?,
+            read_verifying_key(output_path, spec.output_hash, SAPLING_OUTPUT_FILE, progress)?,
+        ),
+    };
+
+    let sprout_vk = sprout_path
+        .map(|path| read_verifying_key(path, SPROUT_HASH, SPROUT_FILE, progress))
+        .transpose()?;
+
+    Ok(VerifyingKeys {
+        spend_vk: prepare_verifying_key(&spend_vk),
+        output_vk: prepare_verifying_key(&output_vk),
+        sprout_vk: sprout_vk.as_ref().map(prepare_verifying_key),
+        sprout_batch_vk: sprout_vk,
+    })
+}
+
+/// The Sapling Spend and Output proving parameters.
+pub(crate) struct ProvingParams {
+    pub(crate) spend: Parameters<Bls12>,
+    pub(crate) output: Parameters<Bls12>,
+}
+
+impl ProvingParams {
+    /// Returns the number of bytes of points that the parameters hold in memory.
+    pub(crate) fn memory_usage(&self) -> usize {
+        fn usage(params: &Parameters<Bls12>) -> usize {
+            let g1_len = params.vk.ic.len()
+                + params.h.len()
+                + params.l.len()
+                + params.a.len()
+                + params.b_g1.len();
+            g1_len * mem::size_of::<G1Affine>() + params.b_g2.len() * mem::size_of::<G2Affine>()
+        }
+        usage(&self.spend) + usage(&self.output)
+    }
+}
+
+/// Sapling proving parameters that are loaded from disk on first use, and can be
+/// unloaded again to reclaim their memory.
+pub(crate) struct LazyProvingParams {
+    spend_path: PathBuf,
+    spend_hash: String,
+    output_path: PathBuf,
+    output_hash: String,
+    params: Mutex<Option<Arc<ProvingParams>>>,
+    /// The memory usage of the loaded parameters, or 0 if they are not loaded.
+    memory_usage: AtomicUsize,
+}
+
+impl LazyProvingParams {
+    /// Records where the proving parameters are, without reading them.
+    pub(crate) fn new(spend_path: PathBuf, output_path: PathBuf) -> Self {
+        Self::with_hashes(
+            spend_path,
+            SAPLING_SPEND_HASH.to_owned(),
+            output_path,
+            SAPLING_OUTPUT_HASH.to_owned(),
+        )
+    }
+
+    /// Like [`LazyProvingParams::new`], for files with the given BLAKE2b-512 hashes.
+    pub(crate) fn with_hashes(
+        spend_path: PathBuf,
+        spend_hash: String,
+        output_path: PathBuf,
+        output_hash: String,
+    ) -> Self {
+        LazyProvingParams {
+            spend_path,
+            spend_hash,
+            output_path,
+            output_hash,
+            params: Mutex::new(None),
+            memory_usage: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the number of bytes that the loaded proving parameters hold in memory, or
+    /// 0 if they are not loaded.
+    ///
+    /// Parameters that have been unloaded while a proof is still using them are not
+    /// counted.
+    pub(crate) fn memory_usage(&self) -> usize {
+        self.memory_usage.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the proving parameters have been loaded.
+    ///
+    /// Returns `false` while they are being loaded.
+    pub(crate) fn is_loaded(&self) -> bool {
+        match self.params.try_lock() {
+            Ok(params) => params.is_some(),
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the proving parameters, loading them if they aren't loaded.
+    ///
+    /// If several threads call this concurrently before the parameters are loaded, one
+    /// of them loads the parameters while the others wait for it. If loading fails, the
+    /// error is returned and a later call will try again.
+    pub(crate) fn get(&self) -> Result<Arc<ProvingParams>, ParamsError> {
+        let mut params = self.params.lock().unwrap();
+        if let Some(params) = params.as_ref() {
+            return Ok(params.clone());
+        }
+
+        let loaded = Arc::new(ProvingParams {
+            spend: read_proving_params(&self.spend_path, &self.spend_hash, SAPLING_SPEND_FILE)?,
+            output: read_proving_params(&self.output_path, &self.output_hash, SAPLING_OUTPUT_FILE)?,
+        });
+        self.memory_usage
+            .store(loaded.memory_usage(), Ordering::SeqCst);
+        *params = Some(loaded.clone());
+        Ok(loaded)
+    }
+
+    /// Drops the proving parameters, so that they are loaded again by the next call to
+    /// [`LazyProvingParams::get`]. Proofs that are already being created keep the
+    /// parameters they are using in memory until they finish.
+    pub(crate) fn unload(&self) {
+        let mut params = self.params.lock().unwrap();
+        params.take();
+        self.memory_usage.store(0, Ordering::SeqCst);
+    }
+}
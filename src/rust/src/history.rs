@@ -0,0 +1,1505 @@
+//! Safe wrappers and index arithmetic for ZIP 221 history trees.
+//!
+//! Nodes are laid out in the array representation used by zcashd: every node is
+//! stored after its children, so a tree of `t_len` nodes is a sequence of perfect
+//! subtrees (the peaks) of strictly decreasing altitude.
+
+use std::cell::RefCell;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+
+use blake2b_simd::{
+    many::{hash_many, HashManyJob},
+    Params as Blake2bParams,
+};
+use byteorder::{LittleEndian, WriteBytesExt};
+use zcash_encoding::CompactSize;
+use zcash_history::{Entry, EntryLink, Tree, Version};
+
+/// The BLAKE2b-256 personalization used by ZIP 221 for hashing history tree nodes.
+pub(crate) fn hash_personalization(cbranch: u32) -> [u8; 16] {
+    let mut personalization = [0; 16];
+    personalization[..12].copy_from_slice(b"ZcashHistory");
+    personalization[12..].copy_from_slice(&cbranch.to_le_bytes());
+    personalization
+}
+
+/// Returns the altitude of the largest perfect subtree that fits into `n` nodes,
+/// which is `floor(log2(n + 1)) - 1`.
+fn altitude(n: u64) -> u32 {
+    63 - (n + 1).leading_zeros() - 1
+}
+
+/// Returns the altitude of the node at `index`, where leaves have altitude 0.
+pub(crate) fn node_altitude(index: u32) -> u32 {
+    // Convert to a 1-based position, and move left until the position is the root of
+    // a perfect tree starting at the beginning of the array.
+    let mut pos = u64::from(index) + 1;
+    loop {
+        let bits = 64 - pos.leading_zeros();
+        if pos == (1 << bits) - 1 {
+            return bits - 1;
+        }
+        pos -= (1 << (bits - 1)) - 1;
+    }
+}
+
+/// Returns the `(index, altitude)` of each peak of a tree with `t_len` nodes, from
+/// left to right, or `None` if `t_len` is not the length of any history tree.
+pub(crate) fn peaks(t_len: u32) -> Option<Vec<(u32, u32)>> {
+    let t_len = u64::from(t_len);
+    let mut peaks: Vec<(u32, u32)> = vec![];
+    let mut consumed = 0;
+    while consumed < t_len {
+        let alt = altitude(t_len - consumed);
+        if matches!(peaks.last(), Some(&(_, last_alt)) if last_alt <= alt) {
+            return None;
+        }
+        consumed += (1 << (alt + 1)) - 1;
+        peaks.push(((consumed - 1) as u32, alt));
+    }
+    Some(peaks)
+}
+
+/// Returns the number of leaves in a tree with `t_len` nodes, or `None` if `t_len` is
+/// not the length of any history tree.
+pub(crate) fn leaf_count(t_len: u32) -> Option<u32> {
+    Some(peaks(t_len)?.iter().map(|(_, alt)| 1 << alt).sum())
+}
+
+/// Returns the number of nodes in a tree with `leaf_count` leaves, or `None` if it
+/// doesn't fit in a `u32`.
+pub(crate) fn total_nodes(leaf_count: u32) -> Option<u32> {
+    // Each peak of altitude h holds 2^h leaves in 2^(h+1) - 1 nodes.
+    u32::try_from(2 * u64::from(leaf_count) - u64::from(leaf_count.count_ones())).ok()
+}
+
+/// Returns the number of nodes that appending a leaf to a tree with `t_len` nodes adds,
+/// along with the most that any append to a tree with at most as many leaves adds.
+///
+/// Returns `None` if `t_len` is not the length of any history tree, or if the tree
+/// after the append would not fit in a `u32`.
+pub(crate) fn append_sizes(t_len: u32) -> Option<(u32, u32)> {
+    let leaves = leaf_count(t_len)?;
+    let exact = total_nodes(leaves.checked_add(1)?)? - t_len;
+    // The new leaf is merged once for each peak it completes, which is at most
+    // floor(log2(leaves + 1)) times.
+    let max = 64 - (u64::from(leaves) + 1).leading_zeros();
+    Some((exact, max))
+}
+
+/// Returns the indices of the extra nodes that `librustzcash_mmr_delete` needs in
+/// addition to the peaks of a tree with `t_len` nodes: the children of every node on
+/// the right slope of the last peak, in the order zcashd loads them.
+///
+/// Returns `None` if `t_len` is not the length of any history tree.
+pub(crate) fn delete_extras(t_len: u32) -> Option<Vec<u32>> {
+    let (mut pos, mut alt) = *peaks(t_len)?.last()?;
+    let mut extras = Vec::with_capacity(2 * alt as usize);
+    while alt > 0 {
+        let right = pos - 1;
+        extras.push(pos - (1 << alt));
+        extras.push(right);
+        pos = right;
+        alt -= 1;
+    }
+    Some(extras)
+}
+
+/// Returns the indices, in ascending order, of the nodes of a tree with `t_len` nodes
+/// that must be stored to delete up to `reorg_depth` of its most recent leaves and then
+/// keep appending: the peaks and delete extras of the tree after each delete but the
+/// last, and the peaks of the tree after the last. Nodes created by later appends are
+/// stored as they are appended, so they are not included.
+///
+/// Returns `None` if `t_len` is not the length of any history tree.
+pub(crate) fn min_storage_set(t_len: u32, reorg_depth: u32) -> Option<Vec<u32>> {
+    let leaves = leaf_count(t_len)?;
+    let depth = reorg_depth.min(leaves);
+
+    let mut indices = BTreeSet::new();
+    for deleted in 0..=depth {
+        let len = total_nodes(leaves - deleted)?;
+        indices.extend(peaks(len)?.into_iter().map(|(index, _)| index));
+        if deleted < depth {
+            indices.extend(delete_extras(len)?);
+        }
+    }
+    Some(indices.into_iter().collect())
+}
+
+/// Returns the order in which the nodes at `indices`, given in any order, must be
+/// passed to the history tree FFIs, as positions in `indices`: the peaks from left to
+/// right, followed by any extras (see [`delete_extras`]) in the order zcashd loads
+/// them. Also returns the number of peaks.
+///
+/// The tree is the one whose last peak is the largest of `indices`. Returns `None` if
+/// that is not a valid tree, if any of its peaks are missing, or if any other index is
+/// repeated or is not one of its extras.
+pub(crate) fn canonical_order(indices: &[u32]) -> Option<(Vec<usize>, usize)> {
+    let t_len = indices.iter().max()?.checked_add(1)?;
+    let peaks = peaks(t_len)?;
+    let extras = delete_extras(t_len)?;
+
+    let mut positions = BTreeMap::new();
+    for (position, index) in indices.iter().enumerate() {
+        if positions.insert(*index, position).is_some() {
+            return None;
+        }
+    }
+
+    let mut order = Vec::with_capacity(indices.len());
+    for (index, _) in &peaks {
+        order.push(positions.remove(index)?);
+    }
+    order.extend(extras.iter().filter_map(|index| positions.remove(index)));
+    if !positions.is_empty() {
+        return None;
+    }
+    Some((order, peaks.len()))
+}
+
+/// Returns the position, among the peaks of a tree with `t_len` nodes, of the peak
+/// containing the leaf at `leaf_index`, along with the siblings of the nodes on the
+/// path from that leaf up to the peak, from the bottom up. Each sibling is paired
+/// with whether it is the right child of its parent.
+///
+/// Returns `None` if `t_len` is not the length of any history tree, or if the node at
+/// `leaf_index` is not a leaf of it.
+pub(crate) fn leaf_path(t_len: u32, leaf_index: u32) -> Option<(usize, Vec<(u32, bool)>)> {
+    if leaf_index >= t_len || node_altitude(leaf_index) != 0 {
+        return None;
+    }
+    let (peak, &(mut pos, mut alt)) = peaks(t_len)?
+        .iter()
+        .enumerate()
+        .find(|(_, &(index, _))| index >= leaf_index)?;
+
+    let mut siblings = Vec::with_capacity(alt as usize);
+    while alt > 0 {
+        let left = pos - (1 << alt);
+        let right = pos - 1;
+        if leaf_index <= left {
+            siblings.push((right, true));
+            pos = left;
+        } else {
+            siblings.push((left, false));
+            pos = right;
+        }
+        alt -= 1;
+    }
+    siblings.reverse();
+    Some((peak, siblings))
+}
+
+/// Returns the indices, in ascending order, of every node that a proof for the leaf at
+/// `leaf_index` of a tree with `t_len` nodes is computed from: the leaf, each of its
+/// ancestors up to its peak, the sibling of each node on that path, and the other peaks
+/// of the tree (which the root is computed from).
+///
+/// Returns `None` if `t_len` is not the length of any history tree, or if the node at
+/// `leaf_index` is not a leaf of it.
+pub(crate) fn leaf_path_indices(t_len: u32, leaf_index: u32) -> Option<Vec<u32>> {
+    let (_, siblings) = leaf_path(t_len, leaf_index)?;
+    let mut indices = vec![leaf_index];
+    let mut node = leaf_index;
+    for (sibling, _) in siblings {
+        // A parent is stored immediately after its right child.
+        node = node.max(sibling) + 1;
+        indices.extend([sibling, node]);
+    }
+    indices.extend(peaks(t_len)?.into_iter().map(|(index, _)| index));
+    indices.sort_unstable();
+    indices.dedup();
+    Some(indices)
+}
+
+/// Returns the index of the sibling of the node at `index` in a tree with `t_len` nodes,
+/// or `Some(None)` if the node is a peak, which has no sibling within the tree.
+///
+/// Returns `None` if `t_len` is not the length of any history tree, or if `index` is
+/// not the index of one of its nodes.
+pub(crate) fn sibling_index(t_len: u32, index: u32) -> Option<Option<u32>> {
+    if index >= t_len {
+        return None;
+    }
+    if peaks(t_len)?.iter().any(|&(peak, _)| peak == index) {
+        return Some(None);
+    }
+
+    // Every node below a peak has its parent, and so its sibling, within the tree. The
+    // parent immediately follows its right child.
+    let alt = node_altitude(index);
+    let offset = (1 << (alt + 1)) - 1;
+    let sibling = if node_altitude(index + 1) == alt + 1 {
+        u64::from(index) - offset
+    } else {
+        u64::from(index) + offset
+    };
+    Some(Some(sibling as u32))
+}
+
+/// Returns the index of the node that is `offset` nodes from the left among the nodes of
+/// altitude `alt`, or `None` if it doesn't fit in a `u32`.
+pub(crate) fn index_at_altitude(alt: u32, offset: u32) -> Option<u32> {
+    // The node is the peak of the perfect subtree that follows the first
+    // `offset * 2^alt` leaves.
+    let leaves = u64::from(offset) << alt;
+    let before = 2 * leaves - u64::from(leaves.count_ones());
+    u32::try_from(before + (1 << (alt + 1)) - 2).ok()
+}
+
+/// Combines each pair of sibling nodes into their parent, as `V::combine` does, by
+/// hashing all of the parents' subtree commitments in one batch.
+///
+/// `blake2b_simd` hashes a batch of inputs in parallel SIMD lanes where the CPU supports
+/// it, which is faster than hashing them one at a time once there are a few of them.
+/// Both nodes of each pair must be for the same consensus branch.
+pub(crate) fn combine_batched<V: Version>(
+    pairs: &[(V::NodeData, V::NodeData)],
+) -> Vec<V::NodeData> {
+    let params = pairs
+        .iter()
+        .map(|(left, _)| {
+            let mut params = Blake2bParams::new();
+            params
+                .hash_length(32)
+                .personal(&hash_personalization(V::consensus_branch_id(left)));
+            params
+        })
+        .collect::<Vec<_>>();
+    let inputs = pairs
+        .iter()
+        .map(|(left, right)| [V::to_bytes(left), V::to_bytes(right)].concat())
+        .collect::<Vec<_>>();
+    let mut jobs = params
+        .iter()
+        .zip(&inputs)
+        .map(|(params, input)| HashManyJob::new(params, input))
+        .collect::<Vec<_>>();
+    hash_many(jobs.iter_mut());
+
+    jobs.iter()
+        .zip(pairs)
+        .map(|(job, (left, right))| {
+            let mut subtree_commitment = [0; 32];
+            subtree_commitment.copy_from_slice(job.to_hash().as_bytes());
+            V::combine_inner(subtree_commitment, left, right)
+        })
+        .collect()
+}
+
+/// Combines each pair of sibling nodes into their parent, in a batch with the `simd`
+/// feature (see [`combine_batched`]), or one at a time otherwise.
+fn combine_all<V: Version>(pairs: &[(V::NodeData, V::NodeData)]) -> Vec<V::NodeData> {
+    if cfg!(feature = "simd") {
+        combine_batched::<V>(pairs)
+    } else {
+        pairs
+            .iter()
+            .map(|(left, right)| V::combine(left, right))
+            .collect()
+    }
+}
+
+/// Returns the root of a balanced binary Merkle tree over `leaf_hashes`, in which each
+/// parent is the hash of its children's hashes, using the BLAKE2b-256 personalization
+/// of the consensus branch `cbranch`. This is how `V::combine` commits to a pair of
+/// children, applied to their hashes rather than their node encodings.
+///
+/// A node without a sibling at the end of a level is carried up to the next level
+/// unchanged rather than paired with itself, which gives the tree the same shape as the
+/// RFC 6962 Merkle tree hash. Returns `None` if `leaf_hashes` is empty.
+pub(crate) fn balanced_root(cbranch: u32, leaf_hashes: &[[u8; 32]]) -> Option<[u8; 32]> {
+    let mut params = Blake2bParams::new();
+    params
+        .hash_length(32)
+        .personal(&hash_personalization(cbranch));
+
+    let mut level = leaf_hashes.to_vec();
+    while level.len() > 1 {
+        let inputs = level
+            .chunks_exact(2)
+            .map(|pair| [pair[0], pair[1]].concat())
+            .collect::<Vec<_>>();
+        let mut jobs = inputs
+            .iter()
+            .map(|input| HashManyJob::new(&params, input))
+            .collect::<Vec<_>>();
+        hash_many(jobs.iter_mut());
+
+        let carried = level.chunks_exact(2).remainder().first().copied();
+        level = jobs
+            .iter()
+            .map(|job| {
+                let mut parent = [0; 32];
+                parent.copy_from_slice(job.to_hash().as_bytes());
+                parent
+            })
+            .chain(carried)
+            .collect();
+    }
+    level.first().copied()
+}
+
+/// Returns the indices of the nodes that a multiproof for the leaves at `leaf_indices`
+/// (in array representation) of a tree with `t_len` nodes covers, both in ascending
+/// order: the nodes on the paths from those leaves up to their peaks, which can be
+/// computed from the proof, and the witnesses that the proof must include. These are
+/// the siblings along the paths that are not themselves on one of them, and the
+/// peaks that contain none of the leaves.
+///
+/// Returns `None` if `t_len` is not the length of any history tree, or if
+/// `leaf_indices` is empty, not strictly ascending, or includes a node that is not a
+/// leaf of the tree.
+pub(crate) fn multiproof_nodes(t_len: u32, leaf_indices: &[u32]) -> Option<(Vec<u32>, Vec<u32>)> {
+    if leaf_indices.is_empty() || leaf_indices.windows(2).any(|w| w[0] >= w[1]) {
+        return None;
+    }
+
+    let mut computed = BTreeSet::new();
+    let mut siblings = BTreeSet::new();
+    for &leaf_index in leaf_indices {
+        let (_, path) = leaf_path(t_len, leaf_index)?;
+        let mut node = leaf_index;
+        computed.insert(node);
+        for &(sibling, is_right) in &path {
+            // The parent immediately follows its right child.
+            node = if is_right { sibling } else { node } + 1;
+            siblings.insert(sibling);
+            computed.insert(node);
+        }
+    }
+
+    let witnesses = siblings
+        .into_iter()
+        .chain(peaks(t_len)?.into_iter().map(|(index, _)| index))
+        .filter(|index| !computed.contains(index))
+        .collect::<BTreeSet<_>>();
+    Some((
+        computed.into_iter().collect(),
+        witnesses.into_iter().collect(),
+    ))
+}
+
+/// Returns whether `peak_indices` are exactly the peaks of a non-empty tree with `t_len`
+/// nodes, from left to right, and every index in `extra_indices` is one of the extras
+/// that a delete from it needs (see [`delete_extras`]).
+pub(crate) fn valid_node_set(t_len: u32, peak_indices: &[u32], extra_indices: &[u32]) -> bool {
+    valid_peaks(t_len, peak_indices)
+        && (extra_indices.is_empty()
+            || delete_extras(t_len).map_or(false, |extras| {
+                extra_indices.iter().all(|i| extras.contains(i))
+            }))
+}
+
+/// Returns whether `peak_indices` are exactly the peaks of a non-empty tree with `t_len`
+/// nodes, from left to right. Unlike [`peaks`], this doesn't allocate.
+fn valid_peaks(t_len: u32, peak_indices: &[u32]) -> bool {
+    let t_len = u64::from(t_len);
+    let mut peak_indices = peak_indices.iter();
+    let mut last_alt = None;
+    let mut consumed = 0;
+    while consumed < t_len {
+        let alt = altitude(t_len - consumed);
+        if matches!(last_alt, Some(last_alt) if last_alt <= alt) {
+            return false;
+        }
+        consumed += (1 << (alt + 1)) - 1;
+        if peak_indices.next().map(|&i| u64::from(i)) != Some(consumed - 1) {
+            return false;
+        }
+        last_alt = Some(alt);
+    }
+    t_len > 0 && peak_indices.next().is_none()
+}
+
+/// The offset of `subtree_total_work` in a node encoding, which is the same for every
+/// version.
+const TOTAL_WORK_OFFSET: usize = 112;
+
+/// Decodes the node encoding `bytes`, rejecting a `subtree_total_work` of 2^224 or
+/// more.
+///
+/// `V::combine` panics if the sum of the total work of two nodes overflows. Any node
+/// that a tree of `u32` length combines is the sum of fewer than 2^32 decoded nodes, so
+/// with this bound it can't.
+pub(crate) fn decode_node<V: Version>(cbranch: u32, bytes: &[u8]) -> Option<V::NodeData> {
+    if !total_work_in_bounds(bytes) {
+        return None;
+    }
+    V::from_bytes(cbranch, bytes).ok()
+}
+
+/// Returns whether the node encoding `bytes` is long enough to hold a
+/// `subtree_total_work`, and that it is less than 2^224 (see [`decode_node`]).
+fn total_work_in_bounds(bytes: &[u8]) -> bool {
+    bytes
+        .get(TOTAL_WORK_OFFSET..TOTAL_WORK_OFFSET + 32)
+        .map_or(false, |work| work[28..].iter().all(|b| *b == 0))
+}
+
+/// Returns whether `bytes` is a node encoding that [`decode_node`] accepts, and that
+/// re-encodes to exactly the bytes it was decoded from, followed by nothing but the zero
+/// padding that fills a `MAX_NODE_DATA_SIZE` buffer.
+pub(crate) fn node_is_canonical<V: Version>(cbranch: u32, bytes: &[u8]) -> bool {
+    decode_node::<V>(cbranch, bytes).map_or(false, |node| {
+        let encoding = V::to_bytes(&node);
+        bytes.starts_with(&encoding) && bytes[encoding.len()..].iter().all(|b| *b == 0)
+    })
+}
+
+/// Decodes the entry encoding `bytes` of the node at `index`, rejecting it if its links
+/// are not to the children of that node, or if its data is rejected by
+/// [`decode_node`]. Anything in `bytes` after the encoding is ignored.
+///
+/// `zcash_history` follows the links of the entries it is given, so a link to the node
+/// itself (or to any later node) would make it recurse or loop forever.
+pub(crate) fn decode_entry<V: Version>(cbranch: u32, index: u32, bytes: &[u8]) -> Option<Entry<V>> {
+    decode_entry_prefix(cbranch, index, bytes).map(|(entry, _)| entry)
+}
+
+/// Like [`decode_entry`], but also rejects `bytes` unless it is exactly the entry
+/// encoding.
+pub(crate) fn decode_exact_entry<V: Version>(
+    cbranch: u32,
+    index: u32,
+    bytes: &[u8],
+) -> Option<Entry<V>> {
+    decode_entry_prefix(cbranch, index, bytes)
+        .and_then(|(entry, len)| (len == bytes.len()).then(|| entry))
+}
+
+/// Decodes the entry encoding `bytes` of the node at `index` as [`decode_entry`] does,
+/// or as [`decode_exact_entry`] does if `exact` is `true`, and returns just its data.
+pub(crate) fn decode_entry_data<V: Version>(
+    cbranch: u32,
+    index: u32,
+    bytes: &[u8],
+    exact: bool,
+) -> Option<V::NodeData> {
+    let (entry, len) = decode_entry_prefix::<V>(cbranch, index, bytes)?;
+    if exact && len != bytes.len() {
+        return None;
+    }
+    // `Entry` only gives up its data through a `Tree`, so the data is decoded again
+    // from after the kind byte, and the links of a non-leaf.
+    let data = if entry.is_leaf() { 1 } else { 9 };
+    V::from_bytes(cbranch, &bytes[data..len]).ok()
+}
+
+/// Decodes the entry encoding at the start of `bytes` as [`decode_entry`] does, and
+/// returns it with the length of that encoding.
+fn decode_entry_prefix<V: Version>(
+    cbranch: u32,
+    index: u32,
+    bytes: &[u8],
+) -> Option<(Entry<V>, usize)> {
+    let mut reader = bytes;
+    let entry = Entry::<V>::read(cbranch, &mut reader).ok()?;
+    let len = bytes.len() - reader.len();
+    let linked = match node_altitude(index) {
+        0 => entry.is_leaf(),
+        alt => {
+            matches!(entry.left(), Ok(EntryLink::Stored(left)) if left == index - (1 << alt))
+                && matches!(entry.right(), Ok(EntryLink::Stored(right)) if right == index - 1)
+        }
+    };
+    // The data follows the kind byte, and the links of a non-leaf. `Entry::read` has
+    // already decoded it, so only the bound on its total work is left to check.
+    let data = if entry.is_leaf() { 1 } else { 9 };
+    (linked && total_work_in_bounds(&bytes[data..len])).then(|| (entry, len))
+}
+
+/// Appends `leaf` to a tree with the given peaks, from left to right, without
+/// constructing a `Tree`. The new leaf and each parent that it completes are passed to
+/// `store`, in the order that `Tree::append_leaf` returns them, and the data of the
+/// new root is returned.
+///
+/// `Tree::append_leaf` merges the new leaf with each peak to its left that covers as
+/// many blocks as it does, by their heights. This merges it with each peak of the same
+/// altitude instead, so it returns `None` without calling `store` unless every peak
+/// covers `2^altitude` blocks, and the leaf one; otherwise the two could differ. It
+/// also returns `None` if there are no peaks.
+pub(crate) fn append_to_peaks<V: Version>(
+    peaks: &[(u32, V::NodeData)],
+    leaf: &V::NodeData,
+    mut store: impl FnMut(&V::NodeData),
+) -> Option<V::NodeData> {
+    let covers = |data: &V::NodeData, alt: u32| {
+        V::end_height(data).checked_sub(V::start_height(data)) == Some((1 << alt) - 1)
+    };
+    if peaks.is_empty()
+        || !covers(leaf, 0)
+        || !peaks
+            .iter()
+            .all(|(index, data)| covers(data, node_altitude(*index)))
+    {
+        return None;
+    }
+
+    // Merge the new leaf with the peaks of the same altitude, from right to left.
+    store(leaf);
+    let mut merged = None;
+    let mut alt = 0;
+    let mut rest = peaks;
+    while let Some(((index, peak), left)) = rest.split_last() {
+        if node_altitude(*index) != alt {
+            break;
+        }
+        let parent = V::combine(peak, merged.as_ref().unwrap_or(leaf));
+        store(&parent);
+        merged = Some(parent);
+        alt += 1;
+        rest = left;
+    }
+
+    // Bag the remaining peaks and the merged node into the root from left to right, as
+    // `Tree::new` does. If every peak was merged, the merged node is the root.
+    let mut nodes = rest
+        .iter()
+        .map(|(_, peak)| peak)
+        .chain(Some(merged.as_ref().unwrap_or(leaf)));
+    let first = nodes.next().expect("the merged node is always last; qed");
+    let mut root = None;
+    for data in nodes {
+        root = Some(V::combine(root.as_ref().unwrap_or(first), data));
+    }
+    root.or(merged)
+}
+
+thread_local! {
+    /// The buffer that [`with_node_encoding`] and [`encodings_equal`] write node
+    /// encodings into, so that they don't allocate each time.
+    static ENCODING_SCRATCH: RefCell<Vec<u8>> =
+        RefCell::new(Vec::with_capacity(2 * zcash_history::MAX_NODE_DATA_SIZE));
+}
+
+/// Calls `f` with the encoding of `data`, written into a buffer reused across calls on
+/// this thread.
+///
+/// `f` must not itself encode nodes with this or [`encodings_equal`].
+pub(crate) fn with_node_encoding<V: Version, T, F: FnOnce(&[u8]) -> T>(
+    data: &V::NodeData,
+    f: F,
+) -> T {
+    ENCODING_SCRATCH.with(|scratch| {
+        let mut scratch = scratch.borrow_mut();
+        scratch.clear();
+        V::write(data, &mut *scratch).expect("Writing to a Vec cannot fail; qed");
+        f(&scratch)
+    })
+}
+
+/// Returns whether `a` and `b` have the same encoding, without allocating either.
+pub(crate) fn encodings_equal<V: Version>(a: &V::NodeData, b: &V::NodeData) -> bool {
+    ENCODING_SCRATCH.with(|scratch| {
+        let mut scratch = scratch.borrow_mut();
+        scratch.clear();
+        V::write(a, &mut *scratch).expect("Writing to a Vec cannot fail; qed");
+        let a_len = scratch.len();
+        V::write(b, &mut *scratch).expect("Writing to a Vec cannot fail; qed");
+        let (a, b) = scratch.split_at(a_len);
+        a == b
+    })
+}
+
+/// Returns the entry for the node at `index` with the given data, linked to its
+/// children if it has any.
+fn node_entry<V: Version>(index: u32, data: V::NodeData) -> Entry<V> {
+    match node_altitude(index) {
+        0 => Entry::new_leaf(data),
+        alt => Entry::new(
+            data,
+            EntryLink::Stored(index - (1 << alt)),
+            EntryLink::Stored(index - 1),
+        ),
+    }
+}
+
+/// The version of the history tree node encoding.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LeafVersion {
+    /// The encoding used from Heartwood until Canopy.
+    V1,
+    /// The encoding used from NU5 onwards, which adds the Orchard fields.
+    V2,
+}
+
+/// Builds the encoding of a history tree leaf field by field.
+///
+/// Every field starts out zeroed. The Orchard fields are only encoded in V2 leaves, and
+/// are ignored when building a V1 leaf.
+///
+/// ```
+/// use rustzcash::history::{HistoryTree, LeafBuilder, LeafVersion};
+/// use zcash_history::V2;
+/// use zcash_primitives::consensus::BranchId;
+///
+/// let (block_hash, time, height, orchard_root) = ([7; 32], 1_654_000_000, 1_687_104, [9; 32]);
+/// let leaf = LeafBuilder::new()
+///     .subtree_commitment(block_hash)
+///     .start_time(time)
+///     .end_time(time)
+///     .start_height(height)
+///     .end_height(height)
+///     .start_orchard_root(orchard_root)
+///     .end_orchard_root(orchard_root)
+///     .orchard_tx_count(1)
+///     .build(LeafVersion::V2);
+///
+/// let mut tree = HistoryTree::<V2>::empty(BranchId::Nu5.into());
+/// tree.append(&leaf)?;
+/// assert_eq!(tree.len(), 1);
+/// # Ok::<(), rustzcash::history::HistoryError>(())
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct LeafBuilder {
+    subtree_commitment: [u8; 32],
+    start_time: u32,
+    end_time: u32,
+    start_target: u32,
+    end_target: u32,
+    start_sapling_root: [u8; 32],
+    end_sapling_root: [u8; 32],
+    subtree_total_work: [u8; 32],
+    start_height: u64,
+    end_height: u64,
+    sapling_tx_count: u64,
+    start_orchard_root: [u8; 32],
+    end_orchard_root: [u8; 32],
+    orchard_tx_count: u64,
+}
+
+impl LeafBuilder {
+    /// Constructs a builder with every field zeroed.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the commitment to the leaf's block, which is its block hash.
+    pub fn subtree_commitment(mut self, commitment: [u8; 32]) -> Self {
+        self.subtree_commitment = commitment;
+        self
+    }
+
+    /// Sets the time of the first block.
+    pub fn start_time(mut self, time: u32) -> Self {
+        self.start_time = time;
+        self
+    }
+
+    /// Sets the time of the last block.
+    pub fn end_time(mut self, time: u32) -> Self {
+        self.end_time = time;
+        self
+    }
+
+    /// Sets the difficulty target (`nBits`) of the first block.
+    pub fn start_target(mut self, target: u32) -> Self {
+        self.start_target = target;
+        self
+    }
+
+    /// Sets the difficulty target (`nBits`) of the last block.
+    pub fn end_target(mut self, target: u32) -> Self {
+        self.end_target = target;
+        self
+    }
+
+    /// Sets the Sapling note commitment tree root at the end of the first block.
+    pub fn start_sapling_root(mut self, root: [u8; 32]) -> Self {
+        self.start_sapling_root = root;
+        self
+    }
+
+    /// Sets the Sapling note commitment tree root at the end of the last block.
+    pub fn end_sapling_root(mut self, root: [u8; 32]) -> Self {
+        self.end_sapling_root = root;
+        self
+    }
+
+    /// Sets the total work of the blocks, as a little-endian 256-bit integer.
+    pub fn subtree_total_work(mut self, work: [u8; 32]) -> Self {
+        self.subtree_total_work = work;
+        self
+    }
+
+    /// Sets the height of the first block.
+    pub fn start_height(mut self, height: u64) -> Self {
+        self.start_height = height;
+        self
+    }
+
+    /// Sets the height of the last block.
+    pub fn end_height(mut self, height: u64) -> Self {
+        self.end_height = height;
+        self
+    }
+
+    /// Sets the number of transactions with Sapling components in the blocks.
+    pub fn sapling_tx_count(mut self, count: u64) -> Self {
+        self.sapling_tx_count = count;
+        self
+    }
+
+    /// Sets the Orchard note commitment tree root at the end of the first block.
+    pub fn start_orchard_root(mut self, root: [u8; 32]) -> Self {
+        self.start_orchard_root = root;
+        self
+    }
+
+    /// Sets the Orchard note commitment tree root at the end of the last block.
+    pub fn end_orchard_root(mut self, root: [u8; 32]) -> Self {
+        self.end_orchard_root = root;
+        self
+    }
+
+    /// Sets the number of transactions with Orchard components in the blocks.
+    pub fn orchard_tx_count(mut self, count: u64) -> Self {
+        self.orchard_tx_count = count;
+        self
+    }
+
+    fn write<W: Write>(&self, mut writer: W, version: LeafVersion) -> io::Result<()> {
+        writer.write_all(&self.subtree_commitment)?;
+        writer.write_u32::<LittleEndian>(self.start_time)?;
+        writer.write_u32::<LittleEndian>(self.end_time)?;
+        writer.write_u32::<LittleEndian>(self.start_target)?;
+        writer.write_u32::<LittleEndian>(self.end_target)?;
+        writer.write_all(&self.start_sapling_root)?;
+        writer.write_all(&self.end_sapling_root)?;
+        writer.write_all(&self.subtree_total_work)?;
+        CompactSize::write(&mut writer, self.start_height as usize)?;
+        CompactSize::write(&mut writer, self.end_height as usize)?;
+        CompactSize::write(&mut writer, self.sapling_tx_count as usize)?;
+        if version == LeafVersion::V2 {
+            writer.write_all(&self.start_orchard_root)?;
+            writer.write_all(&self.end_orchard_root)?;
+            CompactSize::write(&mut writer, self.orchard_tx_count as usize)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the leaf node encoding for `version`.
+    pub fn build(&self, version: LeafVersion) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(zcash_history::MAX_NODE_DATA_SIZE);
+        self.write(&mut buf, version)
+            .expect("Writing to a Vec cannot fail; qed");
+        buf
+    }
+}
+
+/// Errors that can occur when operating on a [`HistoryTree`].
+#[derive(Debug)]
+pub enum HistoryError {
+    /// The tree length is not the length of any history tree.
+    InvalidLength(u32),
+    /// The node at this index is needed, but was not provided.
+    MissingNode(u32),
+    /// The node at this index could not be decoded.
+    InvalidNode(u32),
+    /// The tree has no leaves.
+    Empty,
+    /// The tree has no leaf with this (zero-based) number.
+    NoSuchLeaf(u32),
+    /// The encoding of a leaf does not have the hash it was expected to have.
+    LeafHashMismatch,
+    /// The underlying tree implementation returned an error.
+    Tree(zcash_history::Error),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::InvalidLength(t_len) => {
+                write!(f, "{} is not a valid history tree length", t_len)
+            }
+            HistoryError::MissingNode(index) => write!(f, "Node {} was not provided", index),
+            HistoryError::InvalidNode(index) => write!(f, "Node {} has an invalid encoding", index),
+            HistoryError::Empty => write!(f, "The history tree is empty"),
+            HistoryError::NoSuchLeaf(leaf) => write!(f, "The tree has no leaf {}", leaf),
+            HistoryError::LeafHashMismatch => {
+                write!(f, "The leaf encoding does not match the leaf hash")
+            }
+            HistoryError::Tree(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {}
+
+impl From<zcash_history::Error> for HistoryError {
+    fn from(e: zcash_history::Error) -> Self {
+        HistoryError::Tree(e)
+    }
+}
+
+/// The result of appending a leaf to a [`HistoryTree`].
+#[derive(Debug)]
+pub struct AppendResult {
+    /// The root of the tree after the append.
+    pub new_root: [u8; 32],
+    /// The encodings of the nodes created by the append, in index order starting with
+    /// the new leaf.
+    pub appended_nodes: Vec<Vec<u8>>,
+    /// The length of the tree after the append.
+    pub new_len: u32,
+}
+
+/// The result of deleting the most recently appended leaf from a [`HistoryTree`].
+#[derive(Debug)]
+pub struct DeleteResult {
+    /// The root of the tree after the delete, which is all zeroes if the tree is now
+    /// empty.
+    pub new_root: [u8; 32],
+    /// The number of nodes removed by the delete.
+    pub truncated: u32,
+    /// The length of the tree after the delete.
+    pub new_len: u32,
+}
+
+/// A proof that a leaf is part of a history tree with a particular root.
+#[derive(Clone, Debug)]
+pub struct InclusionProof {
+    /// The consensus branch ID of the tree.
+    pub cbranch: u32,
+    /// The number of nodes in the tree.
+    pub t_len: u32,
+    /// The index of the leaf in the array representation.
+    pub leaf_index: u32,
+    /// The encoding of the leaf.
+    pub leaf: Vec<u8>,
+    /// The encodings of the siblings of the nodes on the path from the leaf up to its
+    /// peak, from the bottom up.
+    pub siblings: Vec<Vec<u8>>,
+    /// The encodings of the other peaks of the tree, from left to right.
+    pub other_peaks: Vec<Vec<u8>>,
+}
+
+impl InclusionProof {
+    /// Returns whether the proof shows that the leaf is part of a tree with `root`.
+    pub fn verify<V: Version>(&self, root: &[u8; 32]) -> bool {
+        self.root::<V>().map_or(false, |r| &r == root)
+    }
+
+    /// Recomputes the root of the tree from the leaf and the rest of the proof.
+    fn root<V: Version>(&self) -> Option<[u8; 32]> {
+        let (peak, path) = leaf_path(self.t_len, self.leaf_index)?;
+        let peaks = peaks(self.t_len)?;
+        if path.len() != self.siblings.len() || peaks.len() != self.other_peaks.len() + 1 {
+            return None;
+        }
+        let decode = |bytes: &[u8]| decode_node::<V>(self.cbranch, bytes);
+
+        let mut data = decode(&self.leaf)?;
+        for ((_, is_right), sibling) in path.iter().zip(&self.siblings) {
+            let sibling = decode(sibling)?;
+            data = if *is_right {
+                V::combine(&data, &sibling)
+            } else {
+                V::combine(&sibling, &data)
+            };
+        }
+
+        let mut leaf_peak = Some(data);
+        let mut other_peaks = self.other_peaks.iter();
+        let peaks = peaks
+            .iter()
+            .enumerate()
+            .map(|(i, &(index, _))| {
+                let data = if i == peak {
+                    leaf_peak.take()?
+                } else {
+                    decode(other_peaks.next()?)?
+                };
+                Some((index, node_entry(index, data)))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let tree = Tree::<V>::new(self.t_len, peaks, vec![]);
+        Some(V::hash(tree.root_node().ok()?.data()))
+    }
+}
+
+/// A proof that several leaves are part of a history tree with a particular root.
+///
+/// The inclusion proofs of leaves in the same tree overlap near the peaks, and one leaf
+/// or path node can stand in for a sibling on another's path. A multiproof holds each
+/// node it needs once, and only the nodes that can't be computed from the rest; see
+/// [`multiproof_nodes`].
+#[derive(Clone, Debug)]
+pub struct MultiProof {
+    /// The consensus branch ID of the tree.
+    pub cbranch: u32,
+    /// The number of nodes in the tree.
+    pub t_len: u32,
+    /// The indices of the leaves in the array representation, in ascending order.
+    pub leaf_indices: Vec<u32>,
+    /// The encodings of the leaves.
+    pub leaves: Vec<Vec<u8>>,
+    /// The encodings of the witnesses, in ascending order of their indices.
+    pub witnesses: Vec<Vec<u8>>,
+}
+
+impl MultiProof {
+    /// Returns whether the proof shows that every leaf is part of a tree with `root`.
+    pub fn verify<V: Version>(&self, root: &[u8; 32]) -> bool {
+        self.root::<V>().map_or(false, |r| &r == root)
+    }
+
+    /// Recomputes the root of the tree from the leaves and the witnesses.
+    fn root<V: Version>(&self) -> Option<[u8; 32]> {
+        let (computed, witness_indices) = multiproof_nodes(self.t_len, &self.leaf_indices)?;
+        if self.leaves.len() != self.leaf_indices.len()
+            || self.witnesses.len() != witness_indices.len()
+        {
+            return None;
+        }
+
+        let mut known = BTreeMap::new();
+        for (index, bytes) in self
+            .leaf_indices
+            .iter()
+            .zip(&self.leaves)
+            .chain(witness_indices.iter().zip(&self.witnesses))
+        {
+            known.insert(*index, decode_node::<V>(self.cbranch, bytes)?);
+        }
+        // Every node is stored after its children, so this computes each node after
+        // both of its children are known. The leaves are already known.
+        for index in computed {
+            let alt = node_altitude(index);
+            if alt > 0 {
+                let data = V::combine(known.get(&(index - (1 << alt)))?, known.get(&(index - 1))?);
+                known.insert(index, data);
+            }
+        }
+
+        let peaks = peaks(self.t_len)?
+            .into_iter()
+            .map(|(index, _)| Some((index, node_entry(index, known.remove(&index)?))))
+            .collect::<Option<Vec<_>>>()?;
+        let tree = Tree::<V>::new(self.t_len, peaks, vec![]);
+        Some(V::hash(tree.root_node().ok()?.data()))
+    }
+
+    /// Writes the node encodings of the proof, the leaves followed by the witnesses,
+    /// each prefixed with its length as a CompactSize. The tree and the leaf indices
+    /// are not included, as the verifier must already know them.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for node in self.leaves.iter().chain(&self.witnesses) {
+            CompactSize::write(&mut writer, node.len())?;
+            writer.write_all(node)?;
+        }
+        Ok(())
+    }
+
+    /// Parses a proof written by [`MultiProof::write`] for the leaves at
+    /// `leaf_indices` of a tree with `t_len` nodes, which must take up all of `bytes`.
+    ///
+    /// Returns `None` if the leaves are not valid for [`multiproof_nodes`], or if
+    /// `bytes` does not hold exactly the node encodings the proof needs.
+    pub fn read(cbranch: u32, t_len: u32, leaf_indices: Vec<u32>, bytes: &[u8]) -> Option<Self> {
+        let (_, witness_indices) = multiproof_nodes(t_len, &leaf_indices)?;
+        let mut reader = bytes;
+        let mut nodes = (0..leaf_indices.len() + witness_indices.len())
+            .map(|_| {
+                let len = usize::try_from(CompactSize::read(&mut reader).ok()?).ok()?;
+                if len > zcash_history::MAX_NODE_DATA_SIZE || len > reader.len() {
+                    return None;
+                }
+                let (node, rest) = reader.split_at(len);
+                reader = rest;
+                Some(node.to_vec())
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if !reader.is_empty() {
+            return None;
+        }
+
+        let witnesses = nodes.split_off(leaf_indices.len());
+        Some(MultiProof {
+            cbranch,
+            t_len,
+            leaf_indices,
+            leaves: nodes,
+            witnesses,
+        })
+    }
+}
+
+/// A source of history tree node encodings, indexed by their position in the array
+/// representation, for a [`HistoryTree`] that is loaded lazily.
+///
+/// Clones of a tree share the same fetcher.
+#[derive(Clone)]
+struct NodeFetcher(Arc<Mutex<FetchNode>>);
+
+type FetchNode = dyn FnMut(u32) -> Option<Vec<u8>> + Send;
+
+impl NodeFetcher {
+    fn fetch(&self, index: u32) -> Option<Vec<u8>> {
+        let mut fetch = self
+            .0
+            .lock()
+            .expect("the fetcher is only locked while it runs; qed");
+        (*fetch)(index)
+    }
+}
+
+impl fmt::Debug for NodeFetcher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("NodeFetcher")
+    }
+}
+
+/// A history tree for a single consensus branch that tracks its own length.
+///
+/// The tree holds the encodings of whichever of its nodes it has been given (and those
+/// it has created), indexed by their position in the array representation. Appending
+/// requires the current peaks, and deleting additionally requires the children along
+/// the right slope of the last peak; see [`delete_extras`].
+#[derive(Clone, Debug)]
+pub struct HistoryTree<V: Version> {
+    cbranch: u32,
+    len: u32,
+    nodes: BTreeMap<u32, Vec<u8>>,
+    fetcher: Option<NodeFetcher>,
+    _version: std::marker::PhantomData<V>,
+}
+
+impl<V: Version> HistoryTree<V> {
+    /// Constructs an empty tree for the given consensus branch.
+    pub fn empty(cbranch: u32) -> Self {
+        HistoryTree {
+            cbranch,
+            len: 0,
+            nodes: BTreeMap::new(),
+            fetcher: None,
+            _version: std::marker::PhantomData,
+        }
+    }
+
+    /// Constructs a tree of length `t_len` from the encodings of some of its nodes,
+    /// indexed by their position in the array representation.
+    pub fn from_nodes(
+        cbranch: u32,
+        t_len: u32,
+        nodes: impl IntoIterator<Item = (u32, Vec<u8>)>,
+    ) -> Result<Self, HistoryError> {
+        if peaks(t_len).is_none() {
+            return Err(HistoryError::InvalidLength(t_len));
+        }
+        let nodes: BTreeMap<_, _> = nodes.into_iter().collect();
+        if let Some(&index) = nodes.keys().find(|&&index| index >= t_len) {
+            return Err(HistoryError::MissingNode(index));
+        }
+
+        Ok(HistoryTree {
+            cbranch,
+            len: t_len,
+            nodes,
+            fetcher: None,
+            _version: std::marker::PhantomData,
+        })
+    }
+
+    /// Constructs a tree of length `t_len` whose nodes are provided by `fetch`, such as
+    /// from a database, rather than all up front.
+    ///
+    /// The peaks, which every operation needs, are fetched here. Other nodes are only
+    /// fetched once an operation needs them and the tree doesn't already hold them:
+    /// deletes fetch the extras along the right slope of the last peak, while proofs are
+    /// still built from the nodes the tree holds. `fetch` returns `None` for a node it
+    /// can't provide, which fails the operation with `HistoryError::MissingNode`.
+    pub fn from_fetcher(
+        cbranch: u32,
+        t_len: u32,
+        mut fetch: impl FnMut(u32) -> Option<Vec<u8>> + Send + 'static,
+    ) -> Result<Self, HistoryError> {
+        let peaks = peaks(t_len)
+            .ok_or(HistoryError::InvalidLength(t_len))?
+            .into_iter()
+            .map(|(index, _)| {
+                fetch(index)
+                    .map(|node| (index, node))
+                    .ok_or(HistoryError::MissingNode(index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut tree = Self::from_nodes(cbranch, t_len, peaks)?;
+        tree.fetcher = Some(NodeFetcher(Arc::new(Mutex::new(fetch))));
+        Ok(tree)
+    }
+
+    /// Constructs the full tree with the given leaf encodings, in order.
+    ///
+    /// The tree is built a level at a time rather than by appending each leaf, so that
+    /// with the `simd` feature each level's parents can be combined in one batch (see
+    /// [`combine_batched`]). The tree is the same as the one that appending the leaves
+    /// would build.
+    pub fn from_leaves(cbranch: u32, leaves: &[impl AsRef<[u8]>]) -> Result<Self, HistoryError> {
+        let t_len = u32::try_from(leaves.len())
+            .ok()
+            .and_then(total_nodes)
+            .ok_or(HistoryError::InvalidLength(u32::MAX))?;
+        let mut level = leaves
+            .iter()
+            .enumerate()
+            .map(|(leaf, bytes)| {
+                let index = total_nodes(leaf as u32).expect("the leaves fit in the tree; qed");
+                decode_node::<V>(cbranch, bytes.as_ref()).ok_or(HistoryError::InvalidNode(index))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut nodes = BTreeMap::new();
+        let mut alt = 0;
+        loop {
+            for (offset, data) in level.iter().enumerate() {
+                let index =
+                    index_at_altitude(alt, offset as u32).expect("the nodes fit in the tree; qed");
+                nodes.insert(index, V::to_bytes(data));
+            }
+            if level.len() < 2 {
+                break;
+            }
+            // A node without a sibling is a peak, and is left out of the next level.
+            let mut pairs = vec![];
+            let mut level_nodes = level.into_iter();
+            while let (Some(left), Some(right)) = (level_nodes.next(), level_nodes.next()) {
+                pairs.push((left, right));
+            }
+            level = combine_all::<V>(&pairs);
+            alt += 1;
+        }
+
+        Self::from_nodes(cbranch, t_len, nodes)
+    }
+
+    /// Returns the consensus branch ID of the tree.
+    pub fn consensus_branch_id(&self) -> u32 {
+        self.cbranch
+    }
+
+    /// Returns the number of nodes in the tree.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns whether the tree has no leaves.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns an estimate of the heap bytes that the tree holds: the encodings of its
+    /// nodes, and the entries of the map that indexes them. The map's own bookkeeping
+    /// is not counted.
+    pub fn heap_bytes(&self) -> usize {
+        self.nodes
+            .values()
+            .map(|node| mem::size_of::<(u32, Vec<u8>)>() + node.capacity())
+            .sum()
+    }
+
+    /// Drops every node other than the peaks, which are all that appends need.
+    pub fn retain_peaks(&mut self) {
+        let peaks: Vec<u32> = peaks(self.len)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        self.nodes.retain(|index, _| peaks.contains(index));
+    }
+
+    /// Returns the frontier of the tree: its leaf count as a little-endian `u32`,
+    /// followed by the encodings of its peaks from left to right, each prefixed with its
+    /// length as a CompactSize.
+    ///
+    /// The frontier is the least a tree that is only appended to needs to keep. The
+    /// peak hashes alone are not enough, as appending combines the full encodings of
+    /// the peaks (see [`HistoryTree::append_light_leaf`]).
+    pub fn frontier(&self) -> Result<Vec<u8>, HistoryError> {
+        let leaves = leaf_count(self.len).ok_or(HistoryError::InvalidLength(self.len))?;
+        let mut bytes = leaves.to_le_bytes().to_vec();
+        for (index, _) in peaks(self.len).ok_or(HistoryError::InvalidLength(self.len))? {
+            let node = self.node(index).ok_or(HistoryError::MissingNode(index))?;
+            CompactSize::write(&mut bytes, node.len()).expect("Writing to a Vec cannot fail; qed");
+            bytes.extend_from_slice(node);
+        }
+        Ok(bytes)
+    }
+
+    /// Constructs a tree holding only its peaks from a frontier returned by
+    /// [`HistoryTree::frontier`], which must take up all of `bytes`.
+    ///
+    /// Returns `None` if `bytes` does not hold exactly the peaks of a tree with its leaf
+    /// count, or if any of them can't be decoded.
+    pub fn from_frontier(cbranch: u32, bytes: &[u8]) -> Option<Self> {
+        let (leaves, mut reader) = (bytes.get(..4)?, &bytes[4..]);
+        let t_len = total_nodes(u32::from_le_bytes(<[u8; 4]>::try_from(leaves).ok()?))?;
+        let peaks = peaks(t_len)?
+            .into_iter()
+            .map(|(index, _)| {
+                let len = usize::try_from(CompactSize::read(&mut reader).ok()?).ok()?;
+                if len > zcash_history::MAX_NODE_DATA_SIZE || len > reader.len() {
+                    return None;
+                }
+                let (node, rest) = reader.split_at(len);
+                reader = rest;
+                decode_node::<V>(cbranch, node)?;
+                Some((index, node.to_vec()))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if !reader.is_empty() {
+            return None;
+        }
+        Self::from_nodes(cbranch, t_len, peaks).ok()
+    }
+
+    /// Returns the encoding of the node at `index`, if the tree holds it.
+    pub fn node(&self, index: u32) -> Option<&[u8]> {
+        self.nodes.get(&index).map(|node| &node[..])
+    }
+
+    /// Fetches each of the nodes at `indices` that the tree doesn't already hold, if it
+    /// was loaded with [`HistoryTree::from_fetcher`].
+    fn fetch_missing(&mut self, indices: &[u32]) -> Result<(), HistoryError> {
+        if let Some(fetcher) = &self.fetcher {
+            for &index in indices {
+                if let btree_map::Entry::Vacant(entry) = self.nodes.entry(index) {
+                    let node = fetcher
+                        .fetch(index)
+                        .ok_or(HistoryError::MissingNode(index))?;
+                    entry.insert(node);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn node_data(&self, index: u32) -> Result<V::NodeData, HistoryError> {
+        let bytes = self
+            .nodes
+            .get(&index)
+            .ok_or(HistoryError::MissingNode(index))?;
+        decode_node::<V>(self.cbranch, bytes).ok_or(HistoryError::InvalidNode(index))
+    }
+
+    fn entry(&self, index: u32) -> Result<(u32, Entry<V>), HistoryError> {
+        Ok((index, node_entry(index, self.node_data(index)?)))
+    }
+
+    /// Reconstructs the tree from its peaks, and from the extra nodes needed for a
+    /// delete if `with_extras` is set.
+    fn tree(&self, with_extras: bool) -> Result<Tree<V>, HistoryError> {
+        let peaks = peaks(self.len)
+            .ok_or(HistoryError::InvalidLength(self.len))?
+            .into_iter()
+            .map(|(index, _)| self.entry(index))
+            .collect::<Result<Vec<_>, _>>()?;
+        if peaks.is_empty() {
+            return Err(HistoryError::Empty);
+        }
+        let extra = if with_extras {
+            delete_extras(self.len)
+                .ok_or(HistoryError::InvalidLength(self.len))?
+                .into_iter()
+                .map(|index| self.entry(index))
+                .collect::<Result<Vec<_>, _>>()?
+        } else {
+            vec![]
+        };
+
+        Ok(Tree::new(self.len, peaks, extra))
+    }
+
+    /// Returns the root of the tree, or `HistoryError::Empty` if it has no leaves.
+    pub fn root(&self) -> Result<[u8; 32], HistoryError> {
+        let tree = self.tree(false)?;
+        let root = tree.root_node()?;
+        Ok(V::hash(root.data()))
+    }
+
+    /// Returns an inclusion proof for each of the leaves numbered in `range` (counting
+    /// from zero), verifiable against [`HistoryTree::root`].
+    ///
+    /// The proofs are built as the iterator is advanced. The tree must hold the peaks
+    /// and every node on the path from each leaf up to its peak (along with their
+    /// siblings); a proof that needs anything else yields `HistoryError::MissingNode`,
+    /// and a leaf past the end of the tree yields `HistoryError::NoSuchLeaf`.
+    pub fn prove_range(
+        &self,
+        range: Range<u32>,
+    ) -> impl Iterator<Item = Result<InclusionProof, HistoryError>> + '_ {
+        // The peaks are shared by every proof, so only look them up once.
+        let peaks: Vec<u32> = peaks(self.len)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        range.map(move |leaf| self.prove_leaf(&peaks, leaf))
+    }
+
+    fn prove_leaf(&self, peaks: &[u32], leaf: u32) -> Result<InclusionProof, HistoryError> {
+        let leaf_index = total_nodes(leaf)
+            .filter(|&index| index < self.len)
+            .ok_or(HistoryError::NoSuchLeaf(leaf))?;
+        let (peak, path) = leaf_path(self.len, leaf_index).ok_or(HistoryError::NoSuchLeaf(leaf))?;
+        let node = |index| {
+            self.nodes
+                .get(&index)
+                .cloned()
+                .ok_or(HistoryError::MissingNode(index))
+        };
+
+        Ok(InclusionProof {
+            cbranch: self.cbranch,
+            t_len: self.len,
+            leaf_index,
+            leaf: node(leaf_index)?,
+            siblings: path
+                .iter()
+                .map(|&(index, _)| node(index))
+                .collect::<Result<_, _>>()?,
+            other_peaks: peaks
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != peak)
+                .map(|(_, &index)| node(index))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// Returns a multiproof for the leaves numbered in `leaves` (counting from zero, in
+    /// any order), verifiable against [`HistoryTree::root`].
+    ///
+    /// The tree must hold the leaves and the witnesses of the proof (see
+    /// [`multiproof_nodes`]); the proof needs nothing else.
+    pub fn prove_leaves(&self, leaves: &[u32]) -> Result<MultiProof, HistoryError> {
+        let mut leaf_indices = leaves
+            .iter()
+            .map(|&leaf| {
+                total_nodes(leaf)
+                    .filter(|&index| index < self.len)
+                    .ok_or(HistoryError::NoSuchLeaf(leaf))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        leaf_indices.sort_unstable();
+        leaf_indices.dedup();
+        let (_, witness_indices) =
+            multiproof_nodes(self.len, &leaf_indices).ok_or(HistoryError::Empty)?;
+        let node = |index: &u32| {
+            self.nodes
+                .get(index)
+                .cloned()
+                .ok_or(HistoryError::MissingNode(*index))
+        };
+
+        Ok(MultiProof {
+            cbranch: self.cbranch,
+            t_len: self.len,
+            leaves: leaf_indices.iter().map(node).collect::<Result<_, _>>()?,
+            witnesses: witness_indices.iter().map(node).collect::<Result<_, _>>()?,
+            leaf_indices,
+        })
+    }
+
+    /// Appends the leaf with the given node encoding to the tree.
+    pub fn append(&mut self, leaf: &[u8]) -> Result<AppendResult, HistoryError> {
+        let leaf_data =
+            decode_node::<V>(self.cbranch, leaf).ok_or(HistoryError::InvalidNode(self.len))?;
+
+        if self.len == 0 {
+            let leaf = V::to_bytes(&leaf_data);
+            let new_root = V::hash(&leaf_data);
+            self.nodes.insert(0, leaf.clone());
+            self.len = 1;
+            return Ok(AppendResult {
+                new_root,
+                appended_nodes: vec![leaf],
+                new_len: self.len,
+            });
+        }
+
+        let mut tree = self.tree(false)?;
+        let appended = tree.append_leaf(leaf_data)?;
+        let new_root = V::hash(tree.root_node()?.data());
+
+        let appended_nodes = appended
+            .into_iter()
+            .map(|link| Ok(V::to_bytes(tree.resolve_link(link)?.data())))
+            .collect::<Result<Vec<_>, HistoryError>>()?;
+        for node in &appended_nodes {
+            self.nodes.insert(self.len, node.clone());
+            self.len += 1;
+        }
+
+        Ok(AppendResult {
+            new_root,
+            appended_nodes,
+            new_len: self.len,
+        })
+    }
+
+    /// Appends a leaf that the caller knows by its authoritative hash.
+    ///
+    /// A leaf can't be appended from its hash plus a subset of its fields. The
+    /// `hashSubtreeCommitment` of a parent node is the BLAKE2b hash of the full
+    /// encodings of both of its children (not of their hashes), and the remaining
+    /// fields of the parent are each derived from the corresponding fields of its
+    /// children. Every field of the leaf is therefore irreducible, so this still takes
+    /// the full leaf encoding, and rejects it with `HistoryError::LeafHashMismatch` if
+    /// it does not hash to `leaf_hash`.
+    pub fn append_light_leaf(
+        &mut self,
+        leaf_hash: &[u8; 32],
+        leaf: &[u8],
+    ) -> Result<AppendResult, HistoryError> {
+        let leaf_data =
+            decode_node::<V>(self.cbranch, leaf).ok_or(HistoryError::InvalidNode(self.len))?;
+        if &V::hash(&leaf_data) != leaf_hash {
+            return Err(HistoryError::LeafHashMismatch);
+        }
+        self.append(leaf)
+    }
+
+    /// Deletes the most recently appended leaf from the tree, along with every node
+    /// that was created when it was appended.
+    pub fn delete(&mut self) -> Result<DeleteResult, HistoryError> {
+        match self.len {
+            0 => Err(HistoryError::Empty),
+            1 => {
+                self.nodes.clear();
+                self.len = 0;
+                Ok(DeleteResult {
+                    new_root: [0; 32],
+                    truncated: 1,
+                    new_len: 0,
+                })
+            }
+            _ => {
+                let extras =
+                    delete_extras(self.len).ok_or(HistoryError::InvalidLength(self.len))?;
+                self.fetch_missing(&extras)?;
+                let mut tree = self.tree(true)?;
+                let truncated = tree.truncate_leaf()?;
+                let new_root = V::hash(tree.root_node()?.data());
+
+                self.len -= truncated;
+                self.nodes.split_off(&self.len);
+
+                Ok(DeleteResult {
+                    new_root,
+                    truncated,
+                    new_len: self.len,
+                })
+            }
+        }
+    }
+}
@@ -1,11 +1,24 @@
-use std::{convert::TryFrom, slice};
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    convert::TryFrom,
+    ptr, slice,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use libc::{c_uchar, size_t};
-use zcash_history::{Entry as MMREntry, Tree as MMRTree, Version, V1, V2};
-use zcash_primitives::consensus::BranchId;
+use blake2b_simd::Params as Blake2bParams;
+use libc::{c_char, c_uchar, size_t};
+use smallvec::SmallVec;
+use zcash_encoding::CompactSize;
+use zcash_history::{Entry as MMREntry, EntryLink, Tree as MMRTree, Version, V1, V2};
+use zcash_primitives::consensus::{BlockHeight, BranchId, NetworkUpgrade, Parameters};
+
+use crate::address_ffi::network_from_cstr;
+use crate::history::{self, LeafBuilder, LeafVersion};
+use crate::network::{self, ZcashdParams};
 
 /// Switch the tree version on the epoch it is for.
-fn dispatch<T>(cbranch: u32, v1: impl FnOnce() -> T, v2: impl FnOnce() -> T) -> T {
+pub(crate) fn dispatch<T>(cbranch: u32, v1: impl FnOnce() -> T, v2: impl FnOnce() -> T) -> T {
     match BranchId::try_from(cbranch).unwrap() {
         BranchId::Sprout
         | BranchId::Overwinter
@@ -16,6 +29,133 @@ fn dispatch<T>(cbranch: u32, v1: impl FnOnce() -> T, v2: impl FnOnce() -> T) ->
     }
 }
 
+/// Returns whether the tree for the epoch uses V2 nodes, which include Orchard fields.
+fn is_v2(cbranch: u32) -> bool {
+    dispatch(cbranch, || false, || true)
+}
+
+/// The fields of a block that make up its leaf in the history tree.
+pub(crate) struct BlockLeaf {
+    pub(crate) commitment: [u8; 32],
+    pub(crate) time: u32,
+    pub(crate) target: u32,
+    pub(crate) sapling_root: [u8; 32],
+    pub(crate) orchard_root: [u8; 32],
+    pub(crate) total_work: [u8; 32],
+    pub(crate) height: u64,
+    pub(crate) sapling_tx: u64,
+    pub(crate) orchard_tx: u64,
+}
+
+impl BlockLeaf {
+    /// Returns the leaf node encoding for the tree version used by `cbranch`, matching
+    /// `libzcash::NewV1Leaf` and `libzcash::NewV2Leaf` on the C++ side.
+    pub(crate) fn to_bytes(&self, cbranch: u32) -> Vec<u8> {
+        // A leaf spans a single block, so its start and end fields are identical.
+        LeafBuilder::new()
+            .subtree_commitment(self.commitment)
+            .start_time(self.time)
+            .end_time(self.time)
+            .start_target(self.target)
+            .end_target(self.target)
+            .start_sapling_root(self.sapling_root)
+            .end_sapling_root(self.sapling_root)
+            .subtree_total_work(self.total_work)
+            .start_height(self.height)
+            .end_height(self.height)
+            .sapling_tx_count(self.sapling_tx)
+            .start_orchard_root(self.orchard_root)
+            .end_orchard_root(self.orchard_root)
+            .orchard_tx_count(self.orchard_tx)
+            .build(if is_v2(cbranch) {
+                LeafVersion::V2
+            } else {
+                LeafVersion::V1
+            })
+    }
+}
+
+/// The number of trees reconstructed by `construct_mmr_tree` since startup.
+static RECONSTRUCTIONS: AtomicU64 = AtomicU64::new(0);
+/// The number of nodes decoded by `construct_mmr_tree` since startup.
+static NODES_DECODED: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The number of nodes decoded by the most recent reconstruction on this thread.
+    static LAST_NODES_DECODED: Cell<u64> = Cell::new(0);
+}
+
+/// Records that a reconstruction decoded `count` nodes.
+fn record_reconstruction(count: usize) {
+    RECONSTRUCTIONS.fetch_add(1, Ordering::Relaxed);
+    NODES_DECODED.fetch_add(count as u64, Ordering::Relaxed);
+    LAST_NODES_DECODED.with(|last| last.set(count as u64));
+}
+
+/// Diagnostic counters for the history tree FFIs that reconstruct a tree from the nodes
+/// passed in by zcashd.
+#[repr(C)]
+pub struct MmrStats {
+    /// The number of trees reconstructed since startup.
+    pub reconstructions: u64,
+    /// The number of nodes decoded by all reconstructions since startup.
+    pub nodes_decoded: u64,
+    /// The number of nodes decoded by the most recent reconstruction on the calling
+    /// thread, including a reconstruction that failed partway through.
+    pub last_nodes_decoded: u64,
+}
+
+/// Writes the history tree diagnostic counters to `stats_ret`, unless it is null.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_stats(stats_ret: *mut MmrStats) {
+    let stats = MmrStats {
+        reconstructions: RECONSTRUCTIONS.load(Ordering::Relaxed),
+        nodes_decoded: NODES_DECODED.load(Ordering::Relaxed),
+        last_nodes_decoded: LAST_NODES_DECODED.with(Cell::get),
+    };
+    if let Some(ret) = unsafe { stats_ret.as_mut() } {
+        *ret = stats;
+    }
+}
+
+/// The node indices, encodings, and the lengths of the encodings if they were given,
+/// passed to a history tree FFI.
+type MmrNodes<'a> = (
+    &'a [u32],
+    &'a [[c_uchar; zcash_history::MAX_ENTRY_SIZE]],
+    Option<&'a [size_t]>,
+);
+
+/// Checks that the nodes passed to a history tree FFI are the peaks and delete extras
+/// of a tree of length `t_len`, as [`construct_mmr_tree`] needs, and returns them.
+fn mmr_nodes<'a>(
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    nl_ptr: *const size_t,
+    p_len: size_t,
+    e_len: size_t,
+) -> Result<MmrNodes<'a>, &'static str> {
+    // `MMRTree::new` panics without a peak, and `zcash_history` assumes that the nodes it
+    // is given are the peaks (and delete extras) of a tree of length `t_len`.
+    if p_len == 0 || ni_ptr.is_null() || n_ptr.is_null() {
+        record_reconstruction(0);
+        return Err("No peaks provided");
+    }
+    let (indices, nodes, lens) = unsafe {
+        (
+            slice::from_raw_parts(ni_ptr, p_len + e_len),
+            slice::from_raw_parts(n_ptr, p_len + e_len),
+            (!nl_ptr.is_null()).then(|| slice::from_raw_parts(nl_ptr, p_len + e_len)),
+        )
+    };
+    if !history::valid_node_set(t_len, &indices[..p_len], &indices[p_len..]) {
+        record_reconstruction(0);
+        return Err("Invalid node indices");
+    }
+    Ok((indices, nodes, lens))
+}
+
 fn construct_mmr_tree<V: Version>(
     // Consensus branch id
     cbranch: u32,
@@ -26,30 +166,44 @@ fn construct_mmr_tree<V: Version>(
     ni_ptr: *const u32,
     // Provided tree nodes data, length of p_len+e_len
     n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Lengths of the provided tree nodes data, length of p_len+e_len, or null to decode
+    // each from the start of its buffer
+    nl_ptr: *const size_t,
 
     // Peaks count
     p_len: size_t,
     // Extra nodes loaded (for deletion) count
     e_len: size_t,
 ) -> Result<MMRTree<V>, &'static str> {
-    let (indices, nodes) = unsafe {
-        (
-            slice::from_raw_parts(ni_ptr, p_len + e_len),
-            slice::from_raw_parts(n_ptr, p_len + e_len),
-        )
-    };
+    let (indices, nodes, lens) = mmr_nodes(t_len, ni_ptr, n_ptr, nl_ptr, p_len, e_len)?;
 
-    let mut peaks: Vec<_> = indices
-        .iter()
-        .zip(nodes.iter())
-        .map(
-            |(index, node)| match MMREntry::from_bytes(cbranch, &node[..]) {
-                Ok(entry) => Ok((*index, entry)),
-                Err(_) => Err("Invalid encoding"),
-            },
-        )
-        .collect::<Result<_, _>>()?;
-    let extra = peaks.split_off(p_len);
+    // `MMRTree::new` takes ownership of both lists, so they are allocated once at their
+    // final size, and each entry is decoded straight into them from the caller's buffer
+    // rather than being copied out first. Appends pass no extra nodes, so they only
+    // allocate for the peaks.
+    let mut peaks = Vec::with_capacity(p_len);
+    let mut extra = Vec::with_capacity(e_len);
+    for (i, (index, node)) in indices.iter().zip(nodes.iter()).enumerate() {
+        let entry = match lens {
+            Some(lens) => node
+                .get(..lens[i])
+                .and_then(|bytes| history::decode_exact_entry(cbranch, *index, bytes)),
+            None => history::decode_entry(cbranch, *index, &node[..]),
+        };
+        let entry = match entry {
+            Some(entry) => entry,
+            None => {
+                record_reconstruction(i + 1);
+                return Err("Invalid encoding");
+            }
+        };
+        if i < p_len {
+            peaks.push((*index, entry));
+        } else {
+            extra.push((*index, entry));
+        }
+    }
+    record_reconstruction(indices.len());
 
     Ok(MMRTree::new(t_len, peaks, extra))
 }
@@ -64,6 +218,9 @@ pub extern "system" fn librustzcash_mmr_append(
     ni_ptr: *const u32,
     // Provided tree nodes data, length of p_len
     n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Lengths of the provided tree nodes data, length of p_len, or null to decode each
+    // from the start of its buffer
+    nl_ptr: *const size_t,
     // Peaks count
     p_len: size_t,
     // New node pointer
@@ -77,17 +234,86 @@ pub extern "system" fn librustzcash_mmr_append(
         cbranch,
         || {
             librustzcash_mmr_append_inner::<V1>(
-                cbranch, t_len, ni_ptr, n_ptr, p_len, nn_ptr, rt_ret, buf_ret,
+                cbranch, t_len, ni_ptr, n_ptr, nl_ptr, p_len, nn_ptr, rt_ret, buf_ret,
             )
         },
         || {
             librustzcash_mmr_append_inner::<V2>(
-                cbranch, t_len, ni_ptr, n_ptr, p_len, nn_ptr, rt_ret, buf_ret,
+                cbranch, t_len, ni_ptr, n_ptr, nl_ptr, p_len, nn_ptr, rt_ret, buf_ret,
             )
         },
     )
 }
 
+/// The most peaks that `librustzcash_mmr_append` decodes onto the stack, rather than
+/// into the `Vec` that `MMRTree::new` takes. A tree has as many peaks as its leaf count
+/// has bits set, so this covers every tree of fewer than 2^16 leaves, and most larger
+/// ones.
+const STACK_PEAKS: usize = 16;
+
+/// Appends to a tree of at most [`STACK_PEAKS`] peaks as `librustzcash_mmr_append` does,
+/// but with the peaks decoded onto the stack and appended to with
+/// [`history::append_to_peaks`] rather than an `MMRTree`.
+///
+/// Returns `None` without writing anything if `append_to_peaks` can't append to the
+/// peaks, so that the caller can append with an `MMRTree` instead.
+#[allow(clippy::too_many_arguments)]
+fn append_on_stack<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    nl_ptr: *const size_t,
+    p_len: size_t,
+    new_node_bytes: &[u8; zcash_history::MAX_NODE_DATA_SIZE],
+    rt_ret: *mut [u8; 32],
+    buf_ret: *mut [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+) -> Option<u32> {
+    let (indices, nodes, lens) = match mmr_nodes(t_len, ni_ptr, n_ptr, nl_ptr, p_len, 0) {
+        Ok(nodes) => nodes,
+        Err(_) => return Some(0),
+    };
+
+    let mut peaks = SmallVec::<[_; STACK_PEAKS]>::new();
+    for (i, (index, node)) in indices.iter().zip(nodes.iter()).enumerate() {
+        let data = match lens {
+            Some(lens) => node
+                .get(..lens[i])
+                .and_then(|bytes| history::decode_entry_data::<V>(cbranch, *index, bytes, true)),
+            None => history::decode_entry_data::<V>(cbranch, *index, &node[..], false),
+        };
+        match data {
+            Some(data) => peaks.push((*index, data)),
+            None => {
+                record_reconstruction(i + 1);
+                return Some(0);
+            }
+        }
+    }
+
+    let node = match history::decode_node::<V>(cbranch, &new_node_bytes[..]) {
+        Some(node) => node,
+        None => {
+            record_reconstruction(indices.len());
+            return Some(0);
+        }
+    };
+
+    let mut return_count = 0;
+    let root = history::append_to_peaks::<V>(&peaks, &node, |data| {
+        let next_buf = unsafe { &mut *buf_ret.add(return_count) };
+        V::write(data, &mut &mut next_buf[..])
+            .expect("Write using cursor with enough buffer size cannot fail; qed");
+        return_count += 1;
+    })?;
+    record_reconstruction(indices.len());
+    unsafe {
+        *rt_ret = V::hash(&root);
+    }
+
+    Some(return_count as u32)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn librustzcash_mmr_append_inner<V: Version>(
     // Consensus branch id
@@ -98,6 +324,8 @@ fn librustzcash_mmr_append_inner<V: Version>(
     ni_ptr: *const u32,
     // Provided tree nodes data, length of p_len
     n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Lengths of the provided tree nodes data, length of p_len, or null
+    nl_ptr: *const size_t,
     // Peaks count
     p_len: size_t,
     // New node pointer
@@ -116,16 +344,32 @@ fn librustzcash_mmr_append_inner<V: Version>(
         }
     };
 
-    let mut tree = match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, p_len, 0) {
+    if p_len <= STACK_PEAKS {
+        if let Some(return_count) = append_on_stack::<V>(
+            cbranch,
+            t_len,
+            ni_ptr,
+            n_ptr,
+            nl_ptr,
+            p_len,
+            new_node_bytes,
+            rt_ret,
+            buf_ret,
+        ) {
+            return return_count;
+        }
+    }
+
+    let mut tree = match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, nl_ptr, p_len, 0) {
         Ok(t) => t,
         _ => {
             return 0;
         } // error
     };
 
-    let node = match V::from_bytes(cbranch, &new_node_bytes[..]) {
-        Ok(node) => node,
-        _ => {
+    let node = match history::decode_node::<V>(cbranch, &new_node_bytes[..]) {
+        Some(node) => node,
+        None => {
             return 0;
         } // error
     };
@@ -162,104 +406,3164 @@ fn librustzcash_mmr_append_inner<V: Version>(
     return_count as u32
 }
 
+/// `librustzcash_mmr_verify_append_output` result: the reported output differs from
+/// the recomputed append.
+const MMR_APPEND_OUTPUT_MISMATCH: u32 = 0;
+/// `librustzcash_mmr_verify_append_output` result: the reported output matches.
+const MMR_APPEND_OUTPUT_MATCH: u32 = 1;
+/// `librustzcash_mmr_verify_append_output` result: the append itself fails.
+const MMR_APPEND_OUTPUT_INVALID_INPUT: u32 = 2;
+
+/// Independently recomputes `librustzcash_mmr_append` on the given inputs, and checks
+/// that the reported appended nodes and root are exactly what it produces.
 #[no_mangle]
-pub extern "system" fn librustzcash_mmr_delete(
+pub extern "system" fn librustzcash_mmr_verify_append_output(
     // Consensus branch id
     cbranch: u32,
-    // Length of tree in array representation
+    // Length of tree in array representation, before the append
     t_len: u32,
-    // Indices of provided tree nodes, length of p_len+e_len
+    // Indices of provided tree nodes, length of p_len
     ni_ptr: *const u32,
-    // Provided tree nodes data, length of p_len+e_len
+    // Provided tree nodes data, length of p_len
     n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
     // Peaks count
     p_len: size_t,
-    // Extra nodes loaded (for deletion) count
-    e_len: size_t,
-    // Return of root commitment
-    rt_ret: *mut [u8; 32],
+    // New node pointer
+    nn_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    // Appended nodes reported by librustzcash_mmr_append, length of rn_len
+    rn_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Reported appended nodes count
+    rn_len: size_t,
+    // Root commitment reported by librustzcash_mmr_append
+    rt_ptr: *const [u8; 32],
 ) -> u32 {
     dispatch(
         cbranch,
-        || librustzcash_mmr_delete_inner::<V1>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len, rt_ret),
-        || librustzcash_mmr_delete_inner::<V2>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len, rt_ret),
+        || {
+            librustzcash_mmr_verify_append_output_inner::<V1>(
+                cbranch, t_len, ni_ptr, n_ptr, p_len, nn_ptr, rn_ptr, rn_len, rt_ptr,
+            )
+        },
+        || {
+            librustzcash_mmr_verify_append_output_inner::<V2>(
+                cbranch, t_len, ni_ptr, n_ptr, p_len, nn_ptr, rn_ptr, rn_len, rt_ptr,
+            )
+        },
     )
 }
 
-fn librustzcash_mmr_delete_inner<V: Version>(
+#[allow(clippy::too_many_arguments)]
+fn librustzcash_mmr_verify_append_output_inner<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    p_len: size_t,
+    nn_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    rn_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    rn_len: size_t,
+    rt_ptr: *const [u8; 32],
+) -> u32 {
+    let (new_node_bytes, reported_root) = match unsafe { (nn_ptr.as_ref(), rt_ptr.as_ref()) } {
+        (Some(new_node_bytes), Some(reported_root)) => (new_node_bytes, reported_root),
+        _ => return MMR_APPEND_OUTPUT_INVALID_INPUT,
+    };
+    let reported_nodes = if rn_len == 0 {
+        &[][..]
+    } else if rn_ptr.is_null() {
+        return MMR_APPEND_OUTPUT_INVALID_INPUT;
+    } else {
+        unsafe { slice::from_raw_parts(rn_ptr, rn_len) }
+    };
+
+    let mut tree =
+        match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, ptr::null(), p_len, 0) {
+            Ok(t) => t,
+            _ => return MMR_APPEND_OUTPUT_INVALID_INPUT,
+        };
+    let node = match history::decode_node::<V>(cbranch, &new_node_bytes[..]) {
+        Some(node) => node,
+        None => return MMR_APPEND_OUTPUT_INVALID_INPUT,
+    };
+    let appended = match tree.append_leaf(node) {
+        Ok(appended) => appended,
+        _ => return MMR_APPEND_OUTPUT_INVALID_INPUT,
+    };
+
+    if appended.len() != reported_nodes.len() {
+        return MMR_APPEND_OUTPUT_MISMATCH;
+    }
+
+    // librustzcash_mmr_append only writes the node encoding into the front of each
+    // buffer, so the remainder of a reported buffer is not compared.
+    for (link, reported) in appended.iter().zip(reported_nodes.iter()) {
+        let data = tree
+            .resolve_link(*link)
+            .expect("This was generated by the tree and thus resolvable; qed");
+        if !history::with_node_encoding::<V, _, _>(data.data(), |encoding| {
+            reported.starts_with(encoding)
+        }) {
+            return MMR_APPEND_OUTPUT_MISMATCH;
+        }
+    }
+
+    let root_node = tree
+        .root_node()
+        .expect("Just added, should resolve always; qed");
+    if &V::hash(root_node.data()) == reported_root {
+        MMR_APPEND_OUTPUT_MATCH
+    } else {
+        MMR_APPEND_OUTPUT_MISMATCH
+    }
+}
+
+/// `MmrAppendReport` stage status: the stage succeeded.
+const MMR_STAGE_OK: u32 = 0;
+/// `MmrAppendReport` stage status: the stage failed.
+const MMR_STAGE_FAILED: u32 = 1;
+/// `MmrAppendReport` stage status: the stage was not reached because an earlier stage
+/// failed.
+const MMR_STAGE_SKIPPED: u32 = 2;
+
+/// `MmrAppendReport` offending index when a stage has no offending index.
+const MMR_NO_INDEX: u32 = u32::MAX;
+
+/// The outcome of each stage of `librustzcash_mmr_append_verbose`.
+#[repr(C)]
+pub struct MmrAppendReport {
+    /// Whether `t_len` is a valid non-empty tree length and the provided indices are
+    /// exactly its peaks.
+    pub reconstruct_status: u32,
+    /// The position in the provided arrays of the first unexpected index. If the
+    /// provided indices are a prefix of the peaks (or vice versa), this is the length of
+    /// the shorter of the two.
+    pub reconstruct_index: u32,
+    /// Whether every provided node could be decoded.
+    pub nodes_status: u32,
+    /// The tree index of the first provided node that could not be decoded.
+    pub nodes_index: u32,
+    /// Whether the new leaf could be decoded.
+    pub new_node_status: u32,
+    /// Whether the append succeeded.
+    pub append_status: u32,
+}
+
+impl MmrAppendReport {
+    fn new() -> Self {
+        MmrAppendReport {
+            reconstruct_status: MMR_STAGE_SKIPPED,
+            reconstruct_index: MMR_NO_INDEX,
+            nodes_status: MMR_STAGE_SKIPPED,
+            nodes_index: MMR_NO_INDEX,
+            new_node_status: MMR_STAGE_SKIPPED,
+            append_status: MMR_STAGE_SKIPPED,
+        }
+    }
+}
+
+/// Performs `librustzcash_mmr_append`, additionally checking that the provided nodes are
+/// exactly the peaks of the tree, and reporting which stage failed (and where) in
+/// `report_ret`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_append_verbose(
     // Consensus branch id
     cbranch: u32,
     // Length of tree in array representation
     t_len: u32,
-    // Indices of provided tree nodes, length of p_len+e_len
+    // Indices of provided tree nodes, length of p_len
     ni_ptr: *const u32,
-    // Provided tree nodes data, length of p_len+e_len
+    // Provided tree nodes data, length of p_len
     n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
     // Peaks count
     p_len: size_t,
-    // Extra nodes loaded (for deletion) count
-    e_len: size_t,
+    // New node pointer
+    nn_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
     // Return of root commitment
     rt_ret: *mut [u8; 32],
+    // Return buffer for appended leaves, should be pre-allocated of ceiling(log2(t_len)) length
+    buf_ret: *mut [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Return of the per-stage report
+    report_ret: *mut MmrAppendReport,
 ) -> u32 {
-    let mut tree = match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len) {
-        Ok(t) => t,
+    dispatch(
+        cbranch,
+        || {
+            librustzcash_mmr_append_verbose_inner::<V1>(
+                cbranch, t_len, ni_ptr, n_ptr, p_len, nn_ptr, rt_ret, buf_ret, report_ret,
+            )
+        },
+        || {
+            librustzcash_mmr_append_verbose_inner::<V2>(
+                cbranch, t_len, ni_ptr, n_ptr, p_len, nn_ptr, rt_ret, buf_ret, report_ret,
+            )
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn librustzcash_mmr_append_verbose_inner<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    p_len: size_t,
+    nn_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    rt_ret: *mut [u8; 32],
+    buf_ret: *mut [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    report_ret: *mut MmrAppendReport,
+) -> u32 {
+    let report = match unsafe { report_ret.as_mut() } {
+        Some(report) => report,
+        None => return 0,
+    };
+    *report = MmrAppendReport::new();
+    if rt_ret.is_null() || buf_ret.is_null() {
+        return 0;
+    }
+
+    // Reconstruction: the provided indices must be exactly the peaks of the tree.
+    let (indices, nodes) = if p_len == 0 {
+        (&[][..], &[][..])
+    } else {
+        unsafe {
+            (
+                slice::from_raw_parts(ni_ptr, p_len),
+                slice::from_raw_parts(n_ptr, p_len),
+            )
+        }
+    };
+    let expected = match history::peaks(t_len) {
+        Some(peaks) if !peaks.is_empty() => peaks,
         _ => {
+            report.reconstruct_status = MMR_STAGE_FAILED;
             return 0;
-        } // error
+        }
     };
+    let mismatch = expected
+        .iter()
+        .zip(indices)
+        .position(|(&(expected, _), provided)| expected != *provided)
+        .or_else(|| (indices.len() != expected.len()).then(|| indices.len().min(expected.len())));
+    if let Some(position) = mismatch {
+        report.reconstruct_status = MMR_STAGE_FAILED;
+        report.reconstruct_index = position as u32;
+        return 0;
+    }
+    report.reconstruct_status = MMR_STAGE_OK;
 
-    let truncate_len = match tree.truncate_leaf() {
-        Ok(v) => v,
-        _ => {
+    // Node validation: every provided node must decode.
+    let mut peaks = Vec::with_capacity(p_len);
+    for (index, node) in indices.iter().zip(nodes.iter()) {
+        match history::decode_entry::<V>(cbranch, *index, &node[..]) {
+            Some(entry) => peaks.push((*index, entry)),
+            None => {
+                report.nodes_status = MMR_STAGE_FAILED;
+                report.nodes_index = *index;
+                return 0;
+            }
+        }
+    }
+    report.nodes_status = MMR_STAGE_OK;
+
+    // New node validation.
+    let new_node = match unsafe { nn_ptr.as_ref() }
+        .and_then(|bytes| history::decode_node::<V>(cbranch, &bytes[..]))
+    {
+        Some(node) => node,
+        None => {
+            report.new_node_status = MMR_STAGE_FAILED;
             return 0;
-        } // Error
+        }
+    };
+    report.new_node_status = MMR_STAGE_OK;
+
+    // Append.
+    let mut tree = MMRTree::new(t_len, peaks, vec![]);
+    let appended = match tree.append_leaf(new_node) {
+        Ok(appended) => appended,
+        Err(_) => {
+            report.append_status = MMR_STAGE_FAILED;
+            return 0;
+        }
     };
+    report.append_status = MMR_STAGE_OK;
 
+    let root_node = tree
+        .root_node()
+        .expect("Just added, should resolve always; qed");
     unsafe {
-        *rt_ret = V::hash(
-            tree.root_node()
-                .expect("Just generated without errors, root should be resolving")
-                .data(),
-        );
+        *rt_ret = V::hash(root_node.data());
+
+        for (link, next_buf) in appended
+            .iter()
+            .zip(slice::from_raw_parts_mut(buf_ret, appended.len()).iter_mut())
+        {
+            V::write(
+                tree.resolve_link(*link)
+                    .expect("This was generated by the tree and thus resolvable; qed")
+                    .data(),
+                &mut &mut next_buf[..],
+            )
+            .expect("Write using cursor with enough buffer size cannot fail; qed");
+        }
     }
 
-    truncate_len
+    appended.len() as u32
 }
 
+/// Computes the root that a tree of length `t_len` would have if the leaf at
+/// `leaf_index` were replaced with `nl_ptr`, without modifying the provided nodes.
+///
+/// This is not a consensus operation. The provided nodes must include every peak of
+/// the tree, and the sibling of every node on the path from the leaf up to its peak.
+///
+/// Returns 0 on success, or 1 if the nodes are insufficient or any input is invalid.
 #[no_mangle]
-pub extern "system" fn librustzcash_mmr_hash_node(
+pub extern "system" fn librustzcash_mmr_root_with_replaced_leaf(
+    // Consensus branch id
     cbranch: u32,
-    n_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
-    h_ret: *mut [u8; 32],
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of n_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of n_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Provided nodes count
+    n_len: size_t,
+    // Index of the leaf to replace, in array representation
+    leaf_index: u32,
+    // Replacement leaf pointer
+    nl_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    // Return of root commitment
+    rt_ret: *mut [u8; 32],
 ) -> u32 {
-    dispatch(
+    let new_leaf = match unsafe { nl_ptr.as_ref() } {
+        Some(r) => r,
+        None => return 1,
+    };
+    if ni_ptr.is_null() || n_ptr.is_null() || rt_ret.is_null() {
+        return 1;
+    }
+    let (indices, nodes) = unsafe {
+        (
+            slice::from_raw_parts(ni_ptr, n_len),
+            slice::from_raw_parts(n_ptr, n_len),
+        )
+    };
+
+    let root = dispatch(
         cbranch,
-        || librustzcash_mmr_hash_node_inner::<V1>(cbranch, n_ptr, h_ret),
-        || librustzcash_mmr_hash_node_inner::<V2>(cbranch, n_ptr, h_ret),
-    )
+        || root_with_replaced_leaf::<V1>(cbranch, t_len, indices, nodes, leaf_index, new_leaf),
+        || root_with_replaced_leaf::<V2>(cbranch, t_len, indices, nodes, leaf_index, new_leaf),
+    );
+
+    match root {
+        Some(root) => {
+            unsafe { *rt_ret = root };
+            0
+        }
+        None => 1,
+    }
 }
 
-fn librustzcash_mmr_hash_node_inner<V: Version>(
+fn root_with_replaced_leaf<V: Version>(
     cbranch: u32,
-    n_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
-    h_ret: *mut [u8; 32],
-) -> u32 {
-    let node_bytes: &[u8; zcash_history::MAX_NODE_DATA_SIZE] = unsafe {
-        match n_ptr.as_ref() {
-            Some(r) => r,
-            None => return 1,
-        }
-    };
+    t_len: u32,
+    indices: &[u32],
+    nodes: &[[c_uchar; zcash_history::MAX_ENTRY_SIZE]],
+    leaf_index: u32,
+    new_leaf: &[u8; zcash_history::MAX_NODE_DATA_SIZE],
+) -> Option<[u8; 32]> {
+    let (peak, path) = history::leaf_path(t_len, leaf_index)?;
+    let peak_indices: Vec<_> = history::peaks(t_len)?
+        .into_iter()
+        .map(|(index, _)| index)
+        .collect();
+    let sibling_indices: Vec<_> = path.iter().map(|(index, _)| *index).collect();
 
-    let node = match V::from_bytes(cbranch, &node_bytes[..]) {
-        Ok(n) => n,
-        _ => return 1, // error
+    let load = |wanted: &[u32]| {
+        wanted
+            .iter()
+            .map(|&index| {
+                let pos = indices.iter().position(|&i| i == index)?;
+                let entry = history::decode_entry::<V>(cbranch, index, &nodes[pos][..])?;
+                Some((index, entry))
+            })
+            .collect::<Option<Vec<_>>>()
     };
 
-    unsafe {
-        *h_ret = V::hash(&node);
+    // Recompute the nodes on the path from the replaced leaf up to its peak.
+    let original = MMRTree::new(t_len, load(&peak_indices)?, load(&sibling_indices)?);
+    let mut data = history::decode_node::<V>(cbranch, &new_leaf[..])?;
+    for &(sibling, is_right) in &path {
+        let sibling = original.resolve_link(EntryLink::Stored(sibling)).ok()?;
+        data = if is_right {
+            V::combine(&data, sibling.data())
+        } else {
+            V::combine(sibling.data(), &data)
+        };
     }
 
-    0
+    // Rebuild the tree with the recomputed peak to find the new root.
+    let mut peaks = load(&peak_indices)?;
+    let peak_index = peak_indices[peak];
+    peaks[peak].1 = match history::node_altitude(peak_index) {
+        0 => MMREntry::new_leaf(data),
+        alt => MMREntry::new(
+            data,
+            EntryLink::Stored(peak_index - (1 << alt)),
+            EntryLink::Stored(peak_index - 1),
+        ),
+    };
+    let tree = MMRTree::new(t_len, peaks, vec![]);
+    let root = tree.root_node().ok()?;
+    Some(V::hash(root.data()))
+}
+
+/// `librustzcash_mmr_delete` result: fewer extra nodes were provided than the delete
+/// needs. This can't be confused with a count of deleted nodes.
+const MMR_DELETE_MISSING_EXTRAS: u32 = u32::MAX;
+
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_delete(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of p_len+e_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of p_len+e_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Lengths of the provided tree nodes data, length of p_len+e_len, or null to decode
+    // each from the start of its buffer
+    nl_ptr: *const size_t,
+    // Peaks count
+    p_len: size_t,
+    // Extra nodes loaded (for deletion) count
+    e_len: size_t,
+    // Return of root commitment
+    rt_ret: *mut [u8; 32],
+) -> u32 {
+    let rt_ret = match unsafe { rt_ret.as_mut() } {
+        Some(rt_ret) => rt_ret,
+        None => return 0,
+    };
+    dispatch(
+        cbranch,
+        || {
+            librustzcash_mmr_delete_inner::<V1>(
+                cbranch, t_len, ni_ptr, n_ptr, nl_ptr, p_len, e_len, rt_ret,
+            )
+        },
+        || {
+            librustzcash_mmr_delete_inner::<V2>(
+                cbranch, t_len, ni_ptr, n_ptr, nl_ptr, p_len, e_len, rt_ret,
+            )
+        },
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+fn librustzcash_mmr_delete_inner<V: Version>(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of p_len+e_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of p_len+e_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Lengths of the provided tree nodes data, length of p_len+e_len, or null
+    nl_ptr: *const size_t,
+    // Peaks count
+    p_len: size_t,
+    // Extra nodes loaded (for deletion) count
+    e_len: size_t,
+    // Return of root commitment
+    rt_ret: &mut [u8; 32],
+) -> u32 {
+    // Without this check, a caller that didn't load the extras would only get a generic
+    // failure from `truncate_leaf`.
+    match history::delete_extras(t_len) {
+        Some(required) if e_len < required.len() => return MMR_DELETE_MISSING_EXTRAS,
+        _ => (),
+    }
+
+    let mut tree =
+        match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, nl_ptr, p_len, e_len) {
+            Ok(t) => t,
+            _ => {
+                return 0;
+            } // error
+        };
+
+    let truncate_len = match tree.truncate_leaf() {
+        Ok(v) => v,
+        _ => {
+            return 0;
+        } // Error
+    };
+
+    // The new root is the left child of the last peak when that is the only peak, which
+    // `truncate_leaf` doesn't resolve, so it is missing if that extra wasn't provided.
+    let root_node = match tree.root_node() {
+        Ok(root_node) => root_node,
+        _ => {
+            return 0;
+        } // Error
+    };
+    *rt_ret = V::hash(root_node.data());
+
+    truncate_len
+}
+
+/// `librustzcash_mmr_self_check` result: the tree does not match the stored root.
+const MMR_SELF_CHECK_MISMATCH: u32 = 0;
+/// `librustzcash_mmr_self_check` result: the tree matches the stored root.
+const MMR_SELF_CHECK_MATCH: u32 = 1;
+/// `librustzcash_mmr_self_check` result: the tree could not be reconstructed.
+const MMR_SELF_CHECK_INVALID_INPUT: u32 = 2;
+
+/// Reconstructs the given tree and checks that its root is `stored_root`, so that a
+/// persisted tree can be checked for corruption at startup.
+///
+/// The root only depends on the peaks, so each provided node whose children are also
+/// provided (such as the extra nodes loaded for a delete) is additionally checked to be
+/// the combination of its children.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_self_check(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of p_len+e_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of p_len+e_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Peaks count
+    p_len: size_t,
+    // Extra nodes loaded (for deletion) count
+    e_len: size_t,
+    // Root commitment that the tree is expected to have
+    stored_root: *const [u8; 32],
+) -> u32 {
+    let stored_root = match unsafe { stored_root.as_ref() } {
+        Some(root) => root,
+        None => return MMR_SELF_CHECK_INVALID_INPUT,
+    };
+    if ni_ptr.is_null() || n_ptr.is_null() || p_len == 0 {
+        return MMR_SELF_CHECK_INVALID_INPUT;
+    }
+
+    dispatch(
+        cbranch,
+        || self_check::<V1>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len, stored_root),
+        || self_check::<V2>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len, stored_root),
+    )
+}
+
+fn self_check<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    p_len: size_t,
+    e_len: size_t,
+    stored_root: &[u8; 32],
+) -> u32 {
+    let tree =
+        match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, ptr::null(), p_len, e_len) {
+            Ok(tree) => tree,
+            Err(_) => return MMR_SELF_CHECK_INVALID_INPUT,
+        };
+    let root = match tree.root_node() {
+        Ok(root) => V::hash(root.data()),
+        Err(_) => return MMR_SELF_CHECK_INVALID_INPUT,
+    };
+    if &root != stored_root {
+        return MMR_SELF_CHECK_MISMATCH;
+    }
+
+    let indices = unsafe { slice::from_raw_parts(ni_ptr, p_len + e_len) };
+    let node = |index| tree.resolve_link(EntryLink::Stored(index)).ok();
+    for &index in indices {
+        let alt = history::node_altitude(index);
+        if alt == 0 {
+            continue;
+        }
+        if let (Some(parent), Some(left), Some(right)) =
+            (node(index), node(index - (1 << alt)), node(index - 1))
+        {
+            let combined = V::combine(left.data(), right.data());
+            if !history::encodings_equal::<V>(&combined, parent.data()) {
+                return MMR_SELF_CHECK_MISMATCH;
+            }
+        }
+    }
+
+    MMR_SELF_CHECK_MATCH
+}
+
+/// `librustzcash_mmr_root_both_versions` flag: the V1 root was written.
+const MMR_ROOT_V1_OK: u32 = 1;
+/// `librustzcash_mmr_root_both_versions` flag: the V2 root was written.
+const MMR_ROOT_V2_OK: u32 = 2;
+
+/// Reconstructs the given tree from the same node encodings as both a V1 and a V2 tree,
+/// writing the root of each to `v1_root_ret` and `v2_root_ret`, to compare how the
+/// nodes hash under each version at a network upgrade boundary.
+///
+/// Both versions are decoded (and the nodes hashed) with the consensus branch ID
+/// `cbranch`, regardless of which version that branch actually uses. Returns the flags
+/// of the versions whose roots were written; a root is unaltered if its tree could not
+/// be reconstructed.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_root_both_versions(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of p_len+e_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of p_len+e_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Peaks count
+    p_len: size_t,
+    // Extra nodes loaded (for deletion) count
+    e_len: size_t,
+    // Return of the root commitment of the V1 tree
+    v1_root_ret: *mut [u8; 32],
+    // Return of the root commitment of the V2 tree
+    v2_root_ret: *mut [u8; 32],
+) -> u32 {
+    if ni_ptr.is_null() || n_ptr.is_null() || p_len == 0 {
+        return 0;
+    }
+
+    let mut flags = 0;
+    if version_root::<V1>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len, v1_root_ret) {
+        flags |= MMR_ROOT_V1_OK;
+    }
+    if version_root::<V2>(cbranch, t_len, ni_ptr, n_ptr, p_len, e_len, v2_root_ret) {
+        flags |= MMR_ROOT_V2_OK;
+    }
+    flags
+}
+
+/// Writes the root of the given tree, reconstructed as a tree of version `V`, to
+/// `root_ret`, returning whether it could be reconstructed.
+fn version_root<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    p_len: size_t,
+    e_len: size_t,
+    root_ret: *mut [u8; 32],
+) -> bool {
+    let root_ret = match unsafe { root_ret.as_mut() } {
+        Some(r) => r,
+        None => return false,
+    };
+    let tree =
+        match construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, ptr::null(), p_len, e_len) {
+            Ok(tree) => tree,
+            Err(_) => return false,
+        };
+    match tree.root_node() {
+        Ok(root) => {
+            *root_ret = V::hash(root.data());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Returns the name of the network upgrade that activates the given branch, matching
+/// the names used by zcashd.
+fn branch_name(branch: BranchId) -> &'static str {
+    match branch {
+        BranchId::Sprout => "Sprout",
+        BranchId::Overwinter => "Overwinter",
+        BranchId::Sapling => "Sapling",
+        BranchId::Blossom => "Blossom",
+        BranchId::Heartwood => "Heartwood",
+        BranchId::Canopy => "Canopy",
+        BranchId::Nu5 => "NU5",
+    }
+}
+
+/// Writes the ASCII name of the consensus branch `cbranch` to `buf` as a
+/// NUL-terminated string, if `buf` has room for it.
+///
+/// Returns the buffer size the name needs, including the NUL terminator; the name was
+/// written if and only if this is at most `cap`. Returns 0 if `cbranch` is not a valid
+/// consensus branch ID.
+#[no_mangle]
+pub extern "system" fn librustzcash_branch_name(
+    // Consensus branch id
+    cbranch: u32,
+    // Return buffer for the name, may be null if cap is 0
+    buf: *mut c_char,
+    // Capacity of buf
+    cap: size_t,
+) -> size_t {
+    match BranchId::try_from(cbranch) {
+        Ok(branch) => write_c_str(branch_name(branch), buf, cap),
+        Err(_) => 0,
+    }
+}
+
+/// Writes `s` to `buf` as a NUL-terminated string if `buf` has room for it, and returns
+/// the buffer size it needs.
+fn write_c_str(s: &str, buf: *mut c_char, cap: size_t) -> size_t {
+    debug_assert!(s.is_ascii());
+
+    let required = s.len() + 1;
+    if cap >= required && !buf.is_null() {
+        let out = unsafe { slice::from_raw_parts_mut(buf as *mut u8, required) };
+        out[..s.len()].copy_from_slice(s.as_bytes());
+        out[s.len()] = 0;
+    }
+    required
+}
+
+/// Returns the versions of this library and of the `zcash_history` crate it is built
+/// with, as `librustzcash=X;zcash_history=Y`.
+pub(crate) fn crate_versions() -> String {
+    format!(
+        "librustzcash={};zcash_history={}",
+        env!("CARGO_PKG_VERSION"),
+        env!("ZCASH_HISTORY_VERSION"),
+    )
+}
+
+/// Writes the versions of this library and of the `zcash_history` crate it is built
+/// with to `buf`, as a NUL-terminated string of the form `librustzcash=X;zcash_history=Y`,
+/// if `buf` has room for it.
+///
+/// Returns the buffer size the string needs, including the NUL terminator; the string
+/// was written if and only if this is at most `cap`.
+#[no_mangle]
+pub extern "system" fn librustzcash_history_crate_versions(
+    // Return buffer for the versions, may be null if cap is 0
+    buf: *mut c_char,
+    // Capacity of buf
+    cap: size_t,
+) -> size_t {
+    write_c_str(&crate_versions(), buf, cap)
+}
+
+/// Returns whether blocks in the given branch commit to a ZIP 221 chain history tree.
+pub(crate) fn history_enabled(branch: BranchId) -> bool {
+    match branch {
+        BranchId::Sprout | BranchId::Overwinter | BranchId::Sapling | BranchId::Blossom => false,
+        BranchId::Heartwood | BranchId::Canopy | BranchId::Nu5 => true,
+    }
+}
+
+/// Writes 1 to `result_ret` if the consensus branch `cbranch` maintains a chain
+/// history tree, and 0 if it predates them.
+///
+/// Returns 0 on success, or 1 if `cbranch` is not a valid consensus branch ID or
+/// `result_ret` is null, in which case `result_ret` is unaltered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_history_enabled(
+    // Consensus branch id
+    cbranch: u32,
+    // Return of whether the branch maintains a history tree
+    result_ret: *mut u32,
+) -> u32 {
+    let result_ret = match unsafe { result_ret.as_mut() } {
+        Some(r) => r,
+        None => return 1,
+    };
+    match BranchId::try_from(cbranch) {
+        Ok(branch) => {
+            *result_ret = u32::from(history_enabled(branch));
+            0
+        }
+        Err(_) => 1,
+    }
+}
+
+/// The root that block headers commit to while a branch's history tree is empty, as in
+/// the block at the branch's activation height. Both tree versions define it as the
+/// all-zero hash, which is also what zcashd stores for an empty history cache.
+const EMPTY_HISTORY_ROOT: [u8; 32] = [0; 32];
+
+/// `librustzcash_mmr_empty_root` result: the empty tree's root was written.
+const MMR_EMPTY_ROOT_OK: u32 = 0;
+/// `librustzcash_mmr_empty_root` result: the branch predates history trees, so there is
+/// no empty tree root.
+const MMR_EMPTY_ROOT_UNDEFINED: u32 = 1;
+/// `librustzcash_mmr_empty_root` result: the consensus branch is invalid or `rt_ret` is
+/// null.
+const MMR_EMPTY_ROOT_INVALID: u32 = 2;
+
+/// Writes the root of the empty history tree for the consensus branch `cbranch` to
+/// `rt_ret`.
+///
+/// The other tree functions reject an empty tree, as it has no nodes to hash; this is
+/// the root committed to before the tree has any leaves. `rt_ret` is unaltered unless
+/// `MMR_EMPTY_ROOT_OK` is returned.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_empty_root(
+    // Consensus branch id
+    cbranch: u32,
+    // Return of the root commitment
+    rt_ret: *mut [u8; 32],
+) -> u32 {
+    let rt_ret = match unsafe { rt_ret.as_mut() } {
+        Some(rt_ret) => rt_ret,
+        None => return MMR_EMPTY_ROOT_INVALID,
+    };
+    match BranchId::try_from(cbranch) {
+        Ok(branch) if history_enabled(branch) => {
+            *rt_ret = EMPTY_HISTORY_ROOT;
+            MMR_EMPTY_ROOT_OK
+        }
+        Ok(_) => MMR_EMPTY_ROOT_UNDEFINED,
+        Err(_) => MMR_EMPTY_ROOT_INVALID,
+    }
+}
+
+/// `librustzcash_mmr_leaf_to_height` and `librustzcash_mmr_height_to_leaf` result: the
+/// conversion succeeded.
+const MMR_LEAF_HEIGHT_OK: u32 = 0;
+/// `librustzcash_mmr_leaf_to_height` and `librustzcash_mmr_height_to_leaf` result: the
+/// network or consensus branch is invalid, the branch has no history tree or is never
+/// active on the network, or a pointer is null.
+const MMR_LEAF_HEIGHT_INVALID: u32 = 1;
+/// `librustzcash_mmr_leaf_to_height` and `librustzcash_mmr_height_to_leaf` result: the
+/// block is not in the branch.
+const MMR_LEAF_HEIGHT_OUT_OF_BRANCH: u32 = 2;
+
+/// Returns the parameters of the given network, along with the branch and the height
+/// of its first block, whose leaf is the first in its history tree.
+fn history_start(
+    network: *const c_char,
+    cbranch: u32,
+) -> Option<(ZcashdParams, BranchId, BlockHeight)> {
+    if network.is_null() {
+        return None;
+    }
+    let params = network::params_for(network_from_cstr(network)?)?;
+    let branch = BranchId::try_from(cbranch).ok()?;
+    let nu = match branch {
+        BranchId::Heartwood => NetworkUpgrade::Heartwood,
+        BranchId::Canopy => NetworkUpgrade::Canopy,
+        BranchId::Nu5 => NetworkUpgrade::Nu5,
+        _ => return None,
+    };
+    Some((params, branch, params.activation_height(nu)?))
+}
+
+/// Writes the height of the block that the leaf numbered `leaf_index` (counting from
+/// zero, rather than its index in the array representation) of the history tree for
+/// the consensus branch `cbranch` commits to, on the given network.
+///
+/// Each branch's tree starts with the block at the branch's activation height.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_leaf_to_height(
+    // Network name: "main", "test" or "regtest"
+    network: *const c_char,
+    // Consensus branch id
+    cbranch: u32,
+    // Index of the leaf among the tree's leaves
+    leaf_index: u32,
+    // Return of the block height
+    height_ret: *mut u32,
+) -> u32 {
+    let height_ret = match unsafe { height_ret.as_mut() } {
+        Some(r) => r,
+        None => return MMR_LEAF_HEIGHT_INVALID,
+    };
+    let (params, branch, start) = match history_start(network, cbranch) {
+        Some(start) => start,
+        None => return MMR_LEAF_HEIGHT_INVALID,
+    };
+    match leaf_height(&params, branch, start, leaf_index) {
+        Some(height) => {
+            *height_ret = u32::from(height);
+            MMR_LEAF_HEIGHT_OK
+        }
+        None => MMR_LEAF_HEIGHT_OUT_OF_BRANCH,
+    }
+}
+
+/// Returns the height of the block that the leaf numbered `leaf_index` of the history
+/// tree for `branch`, which starts at `start`, commits to, or `None` if that block
+/// would not be in the branch.
+fn leaf_height(
+    params: &ZcashdParams,
+    branch: BranchId,
+    start: BlockHeight,
+    leaf_index: u32,
+) -> Option<BlockHeight> {
+    let height = BlockHeight::from_u32(u32::from(start).checked_add(leaf_index)?);
+    if BranchId::for_height(params, height) == branch {
+        Some(height)
+    } else {
+        None
+    }
+}
+
+/// Writes the number of the leaf (counting from zero) of the history tree for the
+/// consensus branch `cbranch` that commits to the block at `height`, on the given
+/// network. This is the inverse of `librustzcash_mmr_leaf_to_height`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_height_to_leaf(
+    // Network name: "main", "test" or "regtest"
+    network: *const c_char,
+    // Consensus branch id
+    cbranch: u32,
+    // Block height
+    height: u32,
+    // Return of the index of the leaf among the tree's leaves
+    leaf_index_ret: *mut u32,
+) -> u32 {
+    let leaf_index_ret = match unsafe { leaf_index_ret.as_mut() } {
+        Some(r) => r,
+        None => return MMR_LEAF_HEIGHT_INVALID,
+    };
+    let (params, branch, start) = match history_start(network, cbranch) {
+        Some(start) => start,
+        None => return MMR_LEAF_HEIGHT_INVALID,
+    };
+    let height = BlockHeight::from_u32(height);
+    if height < start || BranchId::for_height(&params, height) != branch {
+        return MMR_LEAF_HEIGHT_OUT_OF_BRANCH;
+    }
+
+    *leaf_index_ret = u32::from(height) - u32::from(start);
+    MMR_LEAF_HEIGHT_OK
+}
+
+/// `librustzcash_mmr_check_leaf_height` result: the leaf encodes the expected height,
+/// which is that of the block at its position.
+const MMR_LEAF_CHECK_OK: u32 = 0;
+/// `librustzcash_mmr_check_leaf_height` result: the leaf's start or end height is not
+/// the expected height.
+const MMR_LEAF_CHECK_HEIGHT_MISMATCH: u32 = 1;
+/// `librustzcash_mmr_check_leaf_height` result: the expected height is not that of the
+/// block whose leaf is at the given position on the network.
+const MMR_LEAF_CHECK_POSITION_MISMATCH: u32 = 2;
+/// `librustzcash_mmr_check_leaf_height` result: the network or consensus branch is
+/// invalid, the leaf is not in the tree or was not provided, or a pointer is null.
+const MMR_LEAF_CHECK_INVALID: u32 = 3;
+
+/// Checks that the leaf numbered `leaf_index` (counting from zero) of a tree of length
+/// `t_len`, which must be among the provided nodes, encodes `expected_height` as both
+/// its start and end height, and that `expected_height` is the height of the block
+/// that the leaf at that position commits to on the given network.
+///
+/// This is not a consensus operation. It catches a leaf being stored or loaded at the
+/// wrong position, which would otherwise only show up as an unexpected root.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_check_leaf_height(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of n_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of n_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Provided nodes count
+    n_len: size_t,
+    // Index of the leaf among the tree's leaves
+    leaf_index: u32,
+    // Height of the block that the leaf is expected to commit to
+    expected_height: u32,
+    // Network name: "main", "test" or "regtest"
+    network: *const c_char,
+) -> u32 {
+    if ni_ptr.is_null() || n_ptr.is_null() {
+        return MMR_LEAF_CHECK_INVALID;
+    }
+    let (indices, nodes) = unsafe {
+        (
+            slice::from_raw_parts(ni_ptr, n_len),
+            slice::from_raw_parts(n_ptr, n_len),
+        )
+    };
+    let (params, branch, start) = match history_start(network, cbranch) {
+        Some(start) => start,
+        None => return MMR_LEAF_CHECK_INVALID,
+    };
+
+    let heights = dispatch(
+        cbranch,
+        || leaf_heights::<V1>(cbranch, t_len, indices, nodes, leaf_index),
+        || leaf_heights::<V2>(cbranch, t_len, indices, nodes, leaf_index),
+    );
+    let (start_height, end_height) = match heights {
+        Some(heights) => heights,
+        None => return MMR_LEAF_CHECK_INVALID,
+    };
+
+    match leaf_height(&params, branch, start, leaf_index) {
+        Some(height) if u32::from(height) == expected_height => (),
+        _ => return MMR_LEAF_CHECK_POSITION_MISMATCH,
+    }
+    let expected_height = u64::from(expected_height);
+    if start_height == expected_height && end_height == expected_height {
+        MMR_LEAF_CHECK_OK
+    } else {
+        MMR_LEAF_CHECK_HEIGHT_MISMATCH
+    }
+}
+
+/// Returns the start and end heights encoded by the leaf numbered `leaf_index` of a
+/// tree of length `t_len`, from among the provided nodes.
+fn leaf_heights<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    indices: &[u32],
+    nodes: &[[c_uchar; zcash_history::MAX_ENTRY_SIZE]],
+    leaf_index: u32,
+) -> Option<(u64, u64)> {
+    if leaf_index >= history::leaf_count(t_len)? {
+        return None;
+    }
+    let index = history::index_at_altitude(0, leaf_index)?;
+    let pos = indices.iter().position(|&i| i == index)?;
+    let entry = history::decode_entry::<V>(cbranch, index, &nodes[pos][..])?;
+
+    // Entries don't expose their node data, so read it through a tree holding only
+    // the leaf.
+    let tree = MMRTree::new(t_len, vec![(index, entry)], vec![]);
+    let leaf = tree.resolve_link(EntryLink::Stored(index)).ok()?;
+    Some((V::start_height(leaf.data()), V::end_height(leaf.data())))
+}
+
+/// `librustzcash_mmr_combine_rule_id` result: the ZIP 221 rule for V1 nodes, which
+/// take the subtree commitment from the hash of the children, take the start fields
+/// from the left child and the end fields from the right child, and sum the total work
+/// and Sapling transaction counts.
+const MMR_COMBINE_RULE_V1: u32 = 1;
+/// `librustzcash_mmr_combine_rule_id` result: the V1 rule, extended to V2 nodes by
+/// taking the start and end Orchard roots in the same way and summing the Orchard
+/// transaction counts.
+const MMR_COMBINE_RULE_V2: u32 = 2;
+
+/// Writes the identifier of the rule that the tree for the consensus branch `cbranch`
+/// uses to combine two child nodes into their parent to `id_ret`.
+///
+/// The identifiers are stable, so that other implementations can check that they are
+/// using the same rule. Returns 0 on success, or 1 if `cbranch` is not a valid consensus
+/// branch ID or `id_ret` is null, in which case `id_ret` is unaltered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_combine_rule_id(
+    // Consensus branch id
+    cbranch: u32,
+    // Return of the combine rule identifier
+    id_ret: *mut u32,
+) -> u32 {
+    let id_ret = match unsafe { id_ret.as_mut() } {
+        Some(r) => r,
+        None => return 1,
+    };
+    if BranchId::try_from(cbranch).is_err() {
+        return 1;
+    }
+    *id_ret = dispatch(cbranch, || MMR_COMBINE_RULE_V1, || MMR_COMBINE_RULE_V2);
+    0
+}
+
+/// Returns the number of leaves in a tree of length `t_len`, or 0 if `t_len` is not a
+/// valid tree length.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_leaf_count(
+    // Length of tree in array representation
+    t_len: u32,
+) -> u32 {
+    history::leaf_count(t_len).unwrap_or(0)
+}
+
+/// Returns the length of the array representation of a tree with `leaf_count` leaves,
+/// or 0 if it would not fit in a `u32`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_total_nodes(
+    // Number of leaves
+    leaf_count: u32,
+) -> u32 {
+    history::total_nodes(leaf_count).unwrap_or(0)
+}
+
+/// The size of each history node entry that `CCoinsViewDB` writes: the key of
+/// `DB_MMR_NODE`, the epoch and the node index, and the node padded to
+/// `NODE_SERIALIZED_LENGTH` bytes.
+const HISTORY_NODE_DB_ENTRY_SIZE: u64 = 1 + 4 + 4 + zcash_history::MAX_NODE_DATA_SIZE as u64;
+
+/// Writes the number of bytes that `CCoinsViewDB` writes for the nodes of a history
+/// tree with `leaf_count` leaves, for the consensus branch `cbranch`, to `bytes_ret`.
+///
+/// Every node is stored padded to the size of the largest V2 node, so V1 and V2 trees
+/// of the same length take up the same space. LevelDB's own overhead and compression
+/// are not included. Returns 0 on success, or 1 if `cbranch` is not a valid consensus
+/// branch ID, the tree would outgrow a `u32`, or `bytes_ret` is null, in which case
+/// `bytes_ret` is unaltered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_storage_bytes(
+    // Consensus branch id
+    cbranch: u32,
+    // Number of leaves
+    leaf_count: u32,
+    // Return of the number of bytes
+    bytes_ret: *mut u64,
+) -> u32 {
+    let bytes_ret = match unsafe { bytes_ret.as_mut() } {
+        Some(r) => r,
+        None => return 1,
+    };
+    if BranchId::try_from(cbranch).is_err() {
+        return 1;
+    }
+    match history::total_nodes(leaf_count) {
+        Some(t_len) => {
+            *bytes_ret = u64::from(t_len) * HISTORY_NODE_DB_ENTRY_SIZE;
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Writes the index of the sibling of the node at `node_index` in a tree of length
+/// `t_len` to `sibling_ret`, and whether it has one to `has_sibling_ret` (1 if so, or 0
+/// if the node is a peak). A peak has no sibling within the tree, so `MMR_NO_INDEX` is
+/// written to `sibling_ret` instead.
+///
+/// Returns 0 on success, or 1 if `t_len` is not a valid tree length, `node_index` is
+/// not in the tree, or either pointer is null, in which case neither is altered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_sibling_index(
+    // Length of tree in array representation
+    t_len: u32,
+    // Index of the node in array representation
+    node_index: u32,
+    // Return of the index of the node's sibling
+    sibling_ret: *mut u32,
+    // Return of whether the node has a sibling
+    has_sibling_ret: *mut u32,
+) -> u32 {
+    let (sibling_ret, has_sibling_ret) =
+        match unsafe { (sibling_ret.as_mut(), has_sibling_ret.as_mut()) } {
+            (Some(sibling_ret), Some(has_sibling_ret)) => (sibling_ret, has_sibling_ret),
+            _ => return 1,
+        };
+    match history::sibling_index(t_len, node_index) {
+        Some(sibling) => {
+            *sibling_ret = sibling.unwrap_or(MMR_NO_INDEX);
+            *has_sibling_ret = sibling.is_some() as u32;
+            0
+        }
+        None => 1,
+    }
+}
+
+/// Writes the number of nodes that appending a leaf to a tree of length `t_len` adds
+/// (and so that `librustzcash_mmr_append` writes to `buf_ret`) to `exact_ret`, and the
+/// most that an append to a tree with at most as many leaves adds to `max_ret`.
+///
+/// Returns 0 on success, or 1 if `t_len` is not a valid tree length, the tree would
+/// outgrow a `u32`, or either pointer is null, in which case neither is altered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_append_sizes(
+    // Length of tree in array representation
+    t_len: u32,
+    // Return of the number of nodes this append adds
+    exact_ret: *mut u32,
+    // Return of the most nodes an append to a tree this size adds
+    max_ret: *mut u32,
+) -> u32 {
+    let (exact_ret, max_ret) = match unsafe { (exact_ret.as_mut(), max_ret.as_mut()) } {
+        (Some(exact_ret), Some(max_ret)) => (exact_ret, max_ret),
+        _ => return 1,
+    };
+    match history::append_sizes(t_len) {
+        Some((exact, max)) => {
+            *exact_ret = exact;
+            *max_ret = max;
+            0
+        }
+        None => 1,
+    }
+}
+
+/// `librustzcash_mmr_append_write_plan` result: the plan was written.
+const MMR_WRITE_PLAN_OK: u32 = 0;
+/// `librustzcash_mmr_append_write_plan` result: `t_len` is not a valid tree length, the
+/// tree would outgrow a `u32`, or a required pointer is null.
+const MMR_WRITE_PLAN_INVALID: u32 = 1;
+/// `librustzcash_mmr_append_write_plan` result: `created_out` is too small for the plan.
+/// The lengths of both parts have still been written.
+const MMR_WRITE_PLAN_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes the indices of the nodes that appending a leaf to a tree of length `t_len`
+/// creates to `created_out`, and those whose stored value it changes to
+/// `overwritten_out`, so that a storage layer can tell inserts from updates.
+///
+/// Every node is stored after its children and is never recomputed once they are all
+/// present, so an append only ever creates nodes: the new leaf, then each parent it
+/// completes. The overwritten list is therefore always empty and `overwritten_out` is
+/// never written to, but its length is reported so that callers don't need to rely on
+/// that.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_append_write_plan(
+    // Length of tree in array representation
+    t_len: u32,
+    // Return buffer for the indices of created nodes, length of created_cap
+    created_out: *mut u32,
+    // Capacity of created_out
+    created_cap: size_t,
+    // Return of the number of created nodes
+    created_len_ret: *mut size_t,
+    // Return buffer for the indices of overwritten nodes, length of overwritten_cap
+    _overwritten_out: *mut u32,
+    // Capacity of overwritten_out
+    _overwritten_cap: size_t,
+    // Return of the number of overwritten nodes
+    overwritten_len_ret: *mut size_t,
+) -> u32 {
+    let (created_len_ret, overwritten_len_ret) =
+        match unsafe { (created_len_ret.as_mut(), overwritten_len_ret.as_mut()) } {
+            (Some(created), Some(overwritten)) => (created, overwritten),
+            _ => return MMR_WRITE_PLAN_INVALID,
+        };
+    let created = match history::append_sizes(t_len) {
+        Some((exact, _)) => t_len..t_len + exact,
+        None => return MMR_WRITE_PLAN_INVALID,
+    };
+    // See above: no append overwrites a node.
+    *created_len_ret = created.len();
+    *overwritten_len_ret = 0;
+    if created_cap < created.len() {
+        return MMR_WRITE_PLAN_BUFFER_TOO_SMALL;
+    }
+    if created_out.is_null() {
+        return MMR_WRITE_PLAN_INVALID;
+    }
+
+    let created_out = unsafe { slice::from_raw_parts_mut(created_out, created.len()) };
+    for (out, index) in created_out.iter_mut().zip(created) {
+        *out = index;
+    }
+    MMR_WRITE_PLAN_OK
+}
+
+/// `librustzcash_mmr_min_storage_set` result: the indices were written.
+const MMR_STORAGE_SET_OK: u32 = 0;
+/// `librustzcash_mmr_min_storage_set` result: `t_len` is not a valid tree length, or a
+/// required pointer is null.
+const MMR_STORAGE_SET_INVALID: u32 = 1;
+/// `librustzcash_mmr_min_storage_set` result: `out_indices` is too small for the set.
+/// Its length has still been written.
+const MMR_STORAGE_SET_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes the indices, in ascending order, of the nodes of a tree of length `t_len`
+/// that a client must retain to delete up to `reorg_depth` of its most recent leaves
+/// and then keep appending. Any other node may be pruned.
+///
+/// With a `reorg_depth` of 0 this is just the peaks. Nodes appended later are not
+/// included, as they must be stored as they are created.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_min_storage_set(
+    // Length of tree in array representation
+    t_len: u32,
+    // Number of leaves that may be deleted
+    reorg_depth: u32,
+    // Return buffer for the indices of retained nodes, length of cap
+    out_indices: *mut u32,
+    // Capacity of out_indices
+    cap: size_t,
+    // Return of the number of retained nodes
+    len_ret: *mut size_t,
+) -> u32 {
+    let len_ret = match unsafe { len_ret.as_mut() } {
+        Some(len_ret) => len_ret,
+        None => return MMR_STORAGE_SET_INVALID,
+    };
+    let indices = match history::min_storage_set(t_len, reorg_depth) {
+        Some(indices) => indices,
+        None => return MMR_STORAGE_SET_INVALID,
+    };
+    *len_ret = indices.len();
+    if cap < indices.len() {
+        return MMR_STORAGE_SET_BUFFER_TOO_SMALL;
+    }
+    if indices.is_empty() {
+        return MMR_STORAGE_SET_OK;
+    }
+    if out_indices.is_null() {
+        return MMR_STORAGE_SET_INVALID;
+    }
+
+    let out_indices = unsafe { slice::from_raw_parts_mut(out_indices, indices.len()) };
+    out_indices.copy_from_slice(&indices);
+    MMR_STORAGE_SET_OK
+}
+
+/// `librustzcash_mmr_can_delete` result: the tree is empty.
+const MMR_DELETE_EMPTY: u32 = 0;
+/// `librustzcash_mmr_can_delete` result: the delete would succeed.
+const MMR_DELETE_OK: u32 = 1;
+/// `librustzcash_mmr_can_delete` result: extra nodes needed for the delete are missing.
+const MMR_DELETE_INSUFFICIENT_EXTRAS: u32 = 2;
+/// `librustzcash_mmr_can_delete` result: the delete can't be performed at this length.
+const MMR_DELETE_UNSUPPORTED: u32 = 3;
+
+/// Checks whether `librustzcash_mmr_delete` would succeed on a tree of length `t_len`
+/// given extra nodes at the provided indices, without decoding any nodes.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_can_delete(
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of the extra nodes that would be provided, length of e_len
+    ei_ptr: *const u32,
+    // Extra nodes count
+    e_len: size_t,
+) -> u32 {
+    if t_len == 0 {
+        return MMR_DELETE_EMPTY;
+    }
+
+    // librustzcash_mmr_delete can't truncate a tree down to nothing.
+    let required = match history::delete_extras(t_len) {
+        Some(extras) if t_len > 1 => extras,
+        _ => return MMR_DELETE_UNSUPPORTED,
+    };
+
+    let provided = if e_len == 0 {
+        &[][..]
+    } else if ei_ptr.is_null() {
+        return MMR_DELETE_INSUFFICIENT_EXTRAS;
+    } else {
+        unsafe { slice::from_raw_parts(ei_ptr, e_len) }
+    };
+
+    if required.iter().all(|index| provided.contains(index)) {
+        MMR_DELETE_OK
+    } else {
+        MMR_DELETE_INSUFFICIENT_EXTRAS
+    }
+}
+
+/// Copies the `count` nodes at `ni_ptr` and `n_ptr`, which may be in any order, to
+/// `out_ni` and `out_n` in the order that the other functions expect: the peaks from
+/// left to right, followed by the extra nodes in the order zcashd loads them.
+///
+/// Returns the number of peaks, or 0 if the nodes are not the peaks (and optionally
+/// some of the extras) of the tree whose last peak has the largest index.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_canonicalize(
+    // Indices of provided tree nodes, length of count
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of count
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Provided nodes count
+    count: size_t,
+    // Return buffer for the reordered indices, length of count
+    out_ni: *mut u32,
+    // Return buffer for the reordered nodes data, length of count
+    out_n: *mut [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+) -> u32 {
+    if count == 0 || ni_ptr.is_null() || n_ptr.is_null() || out_ni.is_null() || out_n.is_null() {
+        return 0;
+    }
+
+    let (indices, nodes, out_indices, out_nodes) = unsafe {
+        (
+            slice::from_raw_parts(ni_ptr, count),
+            slice::from_raw_parts(n_ptr, count),
+            slice::from_raw_parts_mut(out_ni, count),
+            slice::from_raw_parts_mut(out_n, count),
+        )
+    };
+
+    let (order, peak_count) = match history::canonical_order(indices) {
+        Some(v) => v,
+        None => return 0,
+    };
+    for (out, position) in order.into_iter().enumerate() {
+        out_indices[out] = indices[position];
+        out_nodes[out] = nodes[position];
+    }
+    peak_count as u32
+}
+
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_hash_node(
+    cbranch: u32,
+    n_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    h_ret: *mut [u8; 32],
+) -> u32 {
+    dispatch(
+        cbranch,
+        || librustzcash_mmr_hash_node_inner::<V1>(cbranch, n_ptr, h_ret),
+        || librustzcash_mmr_hash_node_inner::<V2>(cbranch, n_ptr, h_ret),
+    )
+}
+
+fn librustzcash_mmr_hash_node_inner<V: Version>(
+    cbranch: u32,
+    n_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    h_ret: *mut [u8; 32],
+) -> u32 {
+    let node_bytes: &[u8; zcash_history::MAX_NODE_DATA_SIZE] = unsafe {
+        match n_ptr.as_ref() {
+            Some(r) => r,
+            None => return 1,
+        }
+    };
+
+    let node = match V::from_bytes(cbranch, &node_bytes[..]) {
+        Ok(n) => n,
+        _ => return 1, // error
+    };
+
+    unsafe {
+        *h_ret = V::hash(&node);
+    }
+
+    0
+}
+
+/// Writes 1 to `result_ret` if the `len` bytes at `n_ptr` are the canonical encoding of
+/// a node for the consensus branch `cbranch`, and 0 if not.
+///
+/// Only the prefix of a node buffer that holds the encoding is decoded, so anything
+/// other than zero padding after it, or an encoding that the node would not re-encode
+/// to (such as a non-minimal CompactSize), would give different bytes the same node.
+/// An encoding that can't be decoded at all is not canonical.
+///
+/// Returns 0 on success, or 1 if `cbranch` is not a valid consensus branch ID, `len` is
+/// more than `MAX_NODE_DATA_SIZE`, or a pointer is null, in which case `result_ret` is
+/// unaltered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_node_is_canonical(
+    // Consensus branch id
+    cbranch: u32,
+    // Node data, length of len
+    n_ptr: *const c_uchar,
+    // Length of the node data
+    len: size_t,
+    // Return of whether the encoding is canonical
+    result_ret: *mut u32,
+) -> u32 {
+    let result_ret = match unsafe { result_ret.as_mut() } {
+        Some(r) => r,
+        None => return 1,
+    };
+    if n_ptr.is_null() || len > zcash_history::MAX_NODE_DATA_SIZE {
+        return 1;
+    }
+    if BranchId::try_from(cbranch).is_err() {
+        return 1;
+    }
+    let bytes = unsafe { slice::from_raw_parts(n_ptr, len) };
+
+    *result_ret = u32::from(dispatch(
+        cbranch,
+        || history::node_is_canonical::<V1>(cbranch, bytes),
+        || history::node_is_canonical::<V2>(cbranch, bytes),
+    ));
+    0
+}
+
+/// `librustzcash_mmr_hash_personalization` result: the personalization was written.
+const MMR_PERSONALIZATION_OK: u32 = 0;
+/// `librustzcash_mmr_hash_personalization` result: `cbranch` is not a valid consensus
+/// branch ID, or a required pointer is null.
+const MMR_PERSONALIZATION_INVALID: u32 = 1;
+/// `librustzcash_mmr_hash_personalization` result: `out` is too small for the
+/// personalization. Its length has still been written.
+const MMR_PERSONALIZATION_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes the BLAKE2b personalization that node hashes and parent commitments in the
+/// history tree for `cbranch` use to `out`.
+///
+/// Both tree versions use the same construction from ZIP 221, `"ZcashHistory"`
+/// followed by the little-endian consensus branch ID, so the hashes of each epoch are
+/// domain-separated by its branch ID rather than by the version.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_hash_personalization(
+    // Consensus branch id
+    cbranch: u32,
+    // Return buffer for the personalization, length of cap
+    out: *mut u8,
+    // Capacity of out
+    cap: size_t,
+    // Return of the length of the personalization
+    len_ret: *mut size_t,
+) -> u32 {
+    let len_ret = match unsafe { len_ret.as_mut() } {
+        Some(len_ret) => len_ret,
+        None => return MMR_PERSONALIZATION_INVALID,
+    };
+    if BranchId::try_from(cbranch).is_err() {
+        return MMR_PERSONALIZATION_INVALID;
+    }
+    let personalization = history::hash_personalization(cbranch);
+    *len_ret = personalization.len();
+    if cap < personalization.len() {
+        return MMR_PERSONALIZATION_BUFFER_TOO_SMALL;
+    }
+    if out.is_null() {
+        return MMR_PERSONALIZATION_INVALID;
+    }
+
+    unsafe { slice::from_raw_parts_mut(out, personalization.len()) }
+        .copy_from_slice(&personalization);
+    MMR_PERSONALIZATION_OK
+}
+
+/// Hashes the history tree leaf for a block directly from its fields.
+///
+/// This is equivalent to building the leaf node and passing it to
+/// `librustzcash_mmr_hash_node`, without the intermediate node buffer.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_hash_block_leaf(
+    // Consensus branch id
+    cbranch: u32,
+    // Block hash
+    commitment: *const [u8; 32],
+    // Block time
+    time: u32,
+    // Block difficulty target (nBits)
+    target: u32,
+    // Sapling note commitment tree root at the end of the block
+    sapling_root: *const [u8; 32],
+    // Orchard note commitment tree root at the end of the block, may be null for V1 trees
+    orchard_root: *const [u8; 32],
+    // Work of the block, little-endian
+    total_work: *const [u8; 32],
+    // Block height
+    height: u64,
+    // Number of Sapling transactions in the block
+    sapling_tx: u64,
+    // Number of Orchard transactions in the block, ignored for V1 trees
+    orchard_tx: u64,
+    // Return of the leaf hash
+    h_ret: *mut [u8; 32],
+) -> u32 {
+    let (commitment, sapling_root, total_work, h_ret) = match unsafe {
+        (
+            commitment.as_ref(),
+            sapling_root.as_ref(),
+            total_work.as_ref(),
+            h_ret.as_mut(),
+        )
+    } {
+        (Some(c), Some(s), Some(w), Some(h)) => (c, s, w, h),
+        _ => return 1, // Null pointer passed, error
+    };
+
+    let orchard_root = match unsafe { orchard_root.as_ref() } {
+        Some(r) => *r,
+        // V2 leaves must commit to an Orchard root.
+        None if is_v2(cbranch) => return 1,
+        None => [0; 32],
+    };
+
+    let leaf = BlockLeaf {
+        commitment: *commitment,
+        time,
+        target,
+        sapling_root: *sapling_root,
+        orchard_root,
+        total_work: *total_work,
+        height,
+        sapling_tx,
+        orchard_tx,
+    };
+
+    let hash = Blake2bParams::new()
+        .hash_length(32)
+        .personal(&history::hash_personalization(cbranch))
+        .hash(&leaf.to_bytes(cbranch));
+    h_ret.copy_from_slice(hash.as_bytes());
+
+    0
+}
+
+/// Writes a commitment to the peaks of a tree of length `t_len` to `commitment_ret`.
+///
+/// This is not the root of the tree: it commits to the stored encodings of the peaks
+/// (in canonical form, without the links to their children), so two sets of peaks can
+/// be compared with a single 32-byte comparison. `ni_ptr` must list exactly the peak
+/// indices, in order.
+///
+/// Neither this nor the root can be computed from the hashes of the peaks alone: the
+/// peaks are bagged into the root with `V::combine`, which needs their full node data.
+///
+/// Returns 0 on success, or 1 if the tree is empty, the provided nodes are not its
+/// peaks, or any input is invalid.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_peaks_commitment(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of p_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of p_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Peaks count
+    p_len: size_t,
+    // Return of the peaks commitment
+    commitment_ret: *mut [u8; 32],
+) -> u32 {
+    let commitment_ret = match unsafe { commitment_ret.as_mut() } {
+        Some(r) => r,
+        None => return 1,
+    };
+    if BranchId::try_from(cbranch).is_err() || (p_len > 0 && (ni_ptr.is_null() || n_ptr.is_null()))
+    {
+        return 1;
+    }
+    let (indices, nodes) = if p_len == 0 {
+        (&[][..], &[][..])
+    } else {
+        unsafe {
+            (
+                slice::from_raw_parts(ni_ptr, p_len),
+                slice::from_raw_parts(n_ptr, p_len),
+            )
+        }
+    };
+
+    let commitment = dispatch(
+        cbranch,
+        || peaks_commitment::<V1>(cbranch, t_len, indices, nodes),
+        || peaks_commitment::<V2>(cbranch, t_len, indices, nodes),
+    );
+
+    match commitment {
+        Some(commitment) => {
+            *commitment_ret = commitment;
+            0
+        }
+        None => 1,
+    }
+}
+
+fn peaks_commitment<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    indices: &[u32],
+    nodes: &[[c_uchar; zcash_history::MAX_ENTRY_SIZE]],
+) -> Option<[u8; 32]> {
+    let peaks = history::peaks(t_len)?;
+    if peaks.is_empty()
+        || peaks.len() != indices.len()
+        || peaks.iter().zip(indices).any(|((i, _), j)| i != j)
+    {
+        return None;
+    }
+
+    let entries = indices
+        .iter()
+        .zip(nodes)
+        .map(|(index, node)| {
+            Some((
+                *index,
+                history::decode_entry::<V>(cbranch, *index, &node[..])?,
+            ))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    let tree = MMRTree::new(t_len, entries, vec![]);
+
+    let mut state = Blake2bParams::new()
+        .hash_length(32)
+        .personal(b"ZcashHistoryPeak")
+        .to_state();
+    state.update(&cbranch.to_le_bytes());
+    state.update(&t_len.to_le_bytes());
+    for index in indices {
+        // Node encodings vary in length, so each is prefixed with its length.
+        let node = tree.resolve_link(EntryLink::Stored(*index)).ok()?;
+        history::with_node_encoding::<V, _, _>(node.data(), |data| {
+            let mut len = [0; 9];
+            let mut writer = &mut len[..];
+            CompactSize::write(&mut writer, data.len())
+                .expect("A CompactSize is at most 9 bytes; qed");
+            let written = 9 - writer.len();
+            state.update(&index.to_le_bytes());
+            state.update(&len[..written]);
+            state.update(data);
+        });
+    }
+
+    let mut commitment = [0; 32];
+    commitment.copy_from_slice(state.finalize().as_bytes());
+    Some(commitment)
+}
+
+/// Appends each of the `leaf_count` leaves at `leaves_ptr` in turn to an empty tree,
+/// writing the root after each append to `roots_out`.
+///
+/// Only the peaks are kept between appends, so a whole chain's worth of leaves can be
+/// checked in one call.
+///
+/// Returns the number of roots written, which is `leaf_count` on success, or 0 if
+/// `roots_cap` is less than `leaf_count` or any input is invalid.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_build_roots(
+    // Consensus branch id
+    cbranch: u32,
+    // Leaves to append, in order, length of leaf_count
+    leaves_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count
+    leaf_count: size_t,
+    // Return buffer for the root after each append, length of roots_cap
+    roots_out: *mut [u8; 32],
+    // Capacity of roots_out
+    roots_cap: size_t,
+) -> u32 {
+    if BranchId::try_from(cbranch).is_err()
+        || leaf_count == 0
+        || leaf_count > u32::MAX as usize
+        || roots_cap < leaf_count
+        || leaves_ptr.is_null()
+        || roots_out.is_null()
+    {
+        return 0;
+    }
+    let leaves = unsafe { slice::from_raw_parts(leaves_ptr, leaf_count) };
+
+    let built = dispatch(
+        cbranch,
+        || build_roots::<V1>(cbranch, leaves),
+        || build_roots::<V2>(cbranch, leaves),
+    );
+    match built {
+        Ok(built) => {
+            unsafe { slice::from_raw_parts_mut(roots_out, leaf_count) }.copy_from_slice(&built);
+            leaf_count as u32
+        }
+        Err(_) => 0,
+    }
+}
+
+fn build_roots<V: Version>(
+    cbranch: u32,
+    leaves: &[[c_uchar; zcash_history::MAX_NODE_DATA_SIZE]],
+) -> Result<Vec<[u8; 32]>, history::HistoryError> {
+    let mut tree = history::HistoryTree::<V>::empty(cbranch);
+    leaves
+        .iter()
+        .map(|leaf| {
+            let root = tree.append(&leaf[..])?.new_root;
+            tree.retain_peaks();
+            Ok(root)
+        })
+        .collect()
+}
+
+/// A history tree of the version used by its consensus branch, as held by the stateful
+/// FFIs.
+enum VersionedTree {
+    V1(history::HistoryTree<V1>),
+    V2(history::HistoryTree<V2>),
+}
+
+/// The state of a chain of roots being verified a leaf at a time.
+///
+/// Like `librustzcash_mmr_build_roots`, this only keeps the peaks of the tree between
+/// leaves, so it needs memory proportional to the number of peaks rather than leaves.
+pub struct MmrChainVerifier {
+    tree: VersionedTree,
+}
+
+/// `librustzcash_mmr_chain_verify_step` result: the root after appending the leaf is
+/// the expected root.
+const MMR_CHAIN_STEP_MATCH: u32 = 0;
+/// `librustzcash_mmr_chain_verify_step` result: the root after appending the leaf is
+/// not the expected root. The leaf has still been appended.
+const MMR_CHAIN_STEP_MISMATCH: u32 = 1;
+/// `librustzcash_mmr_chain_verify_step` result: the leaf is invalid, or a pointer is
+/// null. The state is unaltered.
+const MMR_CHAIN_STEP_INVALID: u32 = 2;
+
+/// Returns the state for verifying the roots of a chain of leaves for the consensus
+/// branch `cbranch`, starting from an empty tree, or null if `cbranch` is not a valid
+/// consensus branch ID.
+///
+/// The state must be freed with `librustzcash_mmr_chain_verify_free`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_chain_verify_init(
+    // Consensus branch id
+    cbranch: u32,
+) -> *mut MmrChainVerifier {
+    if BranchId::try_from(cbranch).is_err() {
+        return std::ptr::null_mut();
+    }
+    let tree = dispatch(
+        cbranch,
+        || VersionedTree::V1(history::HistoryTree::empty(cbranch)),
+        || VersionedTree::V2(history::HistoryTree::empty(cbranch)),
+    );
+    Box::into_raw(Box::new(MmrChainVerifier { tree }))
+}
+
+/// Appends the leaf at `leaf_ptr` to the tree of `state`, and checks that the resulting
+/// root is `expected_root`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_chain_verify_step(
+    // State from librustzcash_mmr_chain_verify_init
+    state: *mut MmrChainVerifier,
+    // Leaf to append
+    leaf_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Root commitment expected after the append
+    expected_root: *const [u8; 32],
+) -> u32 {
+    let (state, leaf, expected_root) =
+        match unsafe { (state.as_mut(), leaf_ptr.as_ref(), expected_root.as_ref()) } {
+            (Some(state), Some(leaf), Some(expected_root)) => (state, leaf, expected_root),
+            _ => return MMR_CHAIN_STEP_INVALID,
+        };
+
+    // A failed append leaves the tree as it was.
+    let root = match &mut state.tree {
+        VersionedTree::V1(tree) => chain_step(tree, &leaf[..]),
+        VersionedTree::V2(tree) => chain_step(tree, &leaf[..]),
+    };
+    match root {
+        Ok(root) if &root == expected_root => MMR_CHAIN_STEP_MATCH,
+        Ok(_) => MMR_CHAIN_STEP_MISMATCH,
+        Err(_) => MMR_CHAIN_STEP_INVALID,
+    }
+}
+
+fn chain_step<V: Version>(
+    tree: &mut history::HistoryTree<V>,
+    leaf: &[u8],
+) -> Result<[u8; 32], history::HistoryError> {
+    let root = tree.append(leaf)?.new_root;
+    tree.retain_peaks();
+    Ok(root)
+}
+
+/// Frees the state from `librustzcash_mmr_chain_verify_init`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_chain_verify_free(state: *mut MmrChainVerifier) {
+    if !state.is_null() {
+        drop(unsafe { Box::from_raw(state) });
+    }
+}
+
+/// A history tree held across FFI calls, which keeps every node so that leaves can be
+/// deleted, and caches its root between mutations.
+pub struct MmrTree {
+    tree: VersionedTree,
+    /// The root of `tree`, if it has been computed since the tree was last mutated.
+    root: Option<[u8; 32]>,
+}
+
+impl MmrTree {
+    fn root(&mut self) -> Result<[u8; 32], history::HistoryError> {
+        if let Some(root) = self.root {
+            return Ok(root);
+        }
+        let root = match &self.tree {
+            VersionedTree::V1(tree) => tree.root(),
+            VersionedTree::V2(tree) => tree.root(),
+        }?;
+        self.root = Some(root);
+        Ok(root)
+    }
+}
+
+/// `librustzcash_mmr_tree_*` result: success.
+const MMR_TREE_OK: u32 = 0;
+/// `librustzcash_mmr_tree_*` result: the leaf is invalid, or a pointer is null. The
+/// tree is unaltered.
+const MMR_TREE_INVALID: u32 = 1;
+/// `librustzcash_mmr_tree_*` result: the tree has no leaves, so it has no root and
+/// nothing can be deleted.
+const MMR_TREE_EMPTY: u32 = 2;
+
+fn tree_result(result: Result<(), history::HistoryError>) -> u32 {
+    match result {
+        Ok(()) => MMR_TREE_OK,
+        Err(history::HistoryError::Empty) => MMR_TREE_EMPTY,
+        Err(_) => MMR_TREE_INVALID,
+    }
+}
+
+/// Returns a handle to an empty tree for the consensus branch `cbranch`, or null if
+/// `cbranch` is not a valid consensus branch ID.
+///
+/// The handle must be freed with `librustzcash_mmr_tree_free`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_tree_new(
+    // Consensus branch id
+    cbranch: u32,
+) -> *mut MmrTree {
+    if BranchId::try_from(cbranch).is_err() {
+        return std::ptr::null_mut();
+    }
+    let tree = dispatch(
+        cbranch,
+        || VersionedTree::V1(history::HistoryTree::empty(cbranch)),
+        || VersionedTree::V2(history::HistoryTree::empty(cbranch)),
+    );
+    Box::into_raw(Box::new(MmrTree { tree, root: None }))
+}
+
+/// Appends the leaf at `leaf_ptr` to the tree of `handle`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_tree_append(
+    // Handle from librustzcash_mmr_tree_new
+    handle: *mut MmrTree,
+    // Leaf to append
+    leaf_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+) -> u32 {
+    let (handle, leaf) = match unsafe { (handle.as_mut(), leaf_ptr.as_ref()) } {
+        (Some(handle), Some(leaf)) => (handle, leaf),
+        _ => return MMR_TREE_INVALID,
+    };
+
+    // A failed append leaves the tree, and so its cached root, as it was.
+    let appended = match &mut handle.tree {
+        VersionedTree::V1(tree) => tree.append(&leaf[..]).map(|_| ()),
+        VersionedTree::V2(tree) => tree.append(&leaf[..]).map(|_| ()),
+    };
+    if appended.is_ok() {
+        handle.root = None;
+    }
+    tree_result(appended)
+}
+
+/// Deletes the most recently appended leaf from the tree of `handle`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_tree_delete(
+    // Handle from librustzcash_mmr_tree_new
+    handle: *mut MmrTree,
+) -> u32 {
+    let handle = match unsafe { handle.as_mut() } {
+        Some(handle) => handle,
+        None => return MMR_TREE_INVALID,
+    };
+
+    let deleted = match &mut handle.tree {
+        VersionedTree::V1(tree) => tree.delete().map(|_| ()),
+        VersionedTree::V2(tree) => tree.delete().map(|_| ()),
+    };
+    if deleted.is_ok() {
+        handle.root = None;
+    }
+    tree_result(deleted)
+}
+
+/// Writes the root of the tree of `handle` to `rt_ret`.
+///
+/// The root is only computed on the first query after the tree is mutated, and is
+/// otherwise returned from the handle's cache.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_tree_root(
+    // Handle from librustzcash_mmr_tree_new
+    handle: *mut MmrTree,
+    // Return of the root commitment
+    rt_ret: *mut [u8; 32],
+) -> u32 {
+    let (handle, rt_ret) = match unsafe { (handle.as_mut(), rt_ret.as_mut()) } {
+        (Some(handle), Some(rt_ret)) => (handle, rt_ret),
+        _ => return MMR_TREE_INVALID,
+    };
+
+    tree_result(handle.root().map(|root| *rt_ret = root))
+}
+
+/// Returns an estimate of the heap bytes that `handle` holds: the handle itself, which
+/// includes its cached root, and the nodes of its tree. Returns 0 if `handle` is null.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_tree_memory_bytes(
+    // Handle from librustzcash_mmr_tree_new
+    handle: *const MmrTree,
+) -> size_t {
+    let handle = match unsafe { handle.as_ref() } {
+        Some(handle) => handle,
+        None => return 0,
+    };
+    let tree_bytes = match &handle.tree {
+        VersionedTree::V1(tree) => tree.heap_bytes(),
+        VersionedTree::V2(tree) => tree.heap_bytes(),
+    };
+    std::mem::size_of::<MmrTree>() + tree_bytes
+}
+
+/// Frees the handle from `librustzcash_mmr_tree_new`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_tree_free(handle: *mut MmrTree) {
+    if !handle.is_null() {
+        drop(unsafe { Box::from_raw(handle) });
+    }
+}
+
+/// Leaves that arrive out of order, such as during parallel block download, of which
+/// the contiguous prefix from leaf 0 is appended to a tree as soon as any gap before
+/// them is filled.
+pub struct MmrOutOfOrder {
+    /// The tree of the contiguous prefix, of which only the peaks are kept.
+    tree: VersionedTree,
+    /// The number of leaves in `tree`.
+    contiguous: u32,
+    /// The leaves after the first gap, by leaf index.
+    pending: BTreeMap<u32, [c_uchar; zcash_history::MAX_NODE_DATA_SIZE]>,
+}
+
+/// `librustzcash_mmr_ooo_*` result: success.
+const MMR_OOO_OK: u32 = 0;
+/// `librustzcash_mmr_ooo_add` result: a leaf with the index has already been added. It
+/// is not replaced.
+const MMR_OOO_DUPLICATE: u32 = 1;
+/// `librustzcash_mmr_ooo_*` result: the leaf is invalid, or a pointer is null. The
+/// state is unaltered.
+const MMR_OOO_INVALID: u32 = 2;
+/// `librustzcash_mmr_ooo_contiguous_root` result: leaf 0 hasn't been added, so there
+/// is no root. The contiguous length (of 0) has still been written.
+const MMR_OOO_EMPTY: u32 = 3;
+
+/// Returns the state for receiving the leaves of a tree for the consensus branch
+/// `cbranch` in any order, or null if `cbranch` is not a valid consensus branch ID.
+///
+/// The state must be freed with `librustzcash_mmr_ooo_free`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_ooo_new(
+    // Consensus branch id
+    cbranch: u32,
+) -> *mut MmrOutOfOrder {
+    if BranchId::try_from(cbranch).is_err() {
+        return std::ptr::null_mut();
+    }
+    let tree = dispatch(
+        cbranch,
+        || VersionedTree::V1(history::HistoryTree::empty(cbranch)),
+        || VersionedTree::V2(history::HistoryTree::empty(cbranch)),
+    );
+    Box::into_raw(Box::new(MmrOutOfOrder {
+        tree,
+        contiguous: 0,
+        pending: BTreeMap::new(),
+    }))
+}
+
+/// Adds the leaf at `leaf_ptr` with the leaf index `index` to `state`. If it fills the
+/// first gap, it and the pending leaves that follow it without a gap are appended to
+/// the contiguous prefix.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_ooo_add(
+    // State from librustzcash_mmr_ooo_new
+    state: *mut MmrOutOfOrder,
+    // Leaf index (not the node index) of the leaf
+    index: u32,
+    // Leaf to add
+    leaf_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+) -> u32 {
+    let (state, leaf) = match unsafe { (state.as_mut(), leaf_ptr.as_ref()) } {
+        (Some(state), Some(leaf)) => (state, leaf),
+        _ => return MMR_OOO_INVALID,
+    };
+    if index < state.contiguous || state.pending.contains_key(&index) {
+        return MMR_OOO_DUPLICATE;
+    }
+
+    // Pending leaves are checked as they are added, so that appending them can't fail
+    // on their encodings once the gap before them is filled.
+    let valid = match &state.tree {
+        VersionedTree::V1(tree) => {
+            history::decode_node::<V1>(tree.consensus_branch_id(), &leaf[..]).is_some()
+        }
+        VersionedTree::V2(tree) => {
+            history::decode_node::<V2>(tree.consensus_branch_id(), &leaf[..]).is_some()
+        }
+    };
+    // A tree with the leaf must also fit in a u32.
+    if !valid
+        || index
+            .checked_add(1)
+            .and_then(history::total_nodes)
+            .is_none()
+    {
+        return MMR_OOO_INVALID;
+    }
+    state.pending.insert(index, *leaf);
+
+    let appended = match &mut state.tree {
+        VersionedTree::V1(tree) => append_contiguous(tree, state.contiguous, &mut state.pending),
+        VersionedTree::V2(tree) => append_contiguous(tree, state.contiguous, &mut state.pending),
+    };
+    state.contiguous += appended;
+    MMR_OOO_OK
+}
+
+/// Appends the pending leaves that follow the `contiguous` leaves of `tree` without a
+/// gap, returning how many were appended.
+fn append_contiguous<V: Version>(
+    tree: &mut history::HistoryTree<V>,
+    contiguous: u32,
+    pending: &mut BTreeMap<u32, [c_uchar; zcash_history::MAX_NODE_DATA_SIZE]>,
+) -> u32 {
+    let mut next = contiguous;
+    while let Some(leaf) = pending.get(&next) {
+        if tree.append(&leaf[..]).is_err() {
+            break;
+        }
+        tree.retain_peaks();
+        pending.remove(&next);
+        next += 1;
+    }
+    next - contiguous
+}
+
+/// Writes the root of the tree of the contiguous prefix of the leaves added to `state`
+/// to `rt_ret`, and the number of leaves in the prefix to `contiguous_len_ret`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_ooo_contiguous_root(
+    // State from librustzcash_mmr_ooo_new
+    state: *const MmrOutOfOrder,
+    // Return of the root commitment
+    rt_ret: *mut [u8; 32],
+    // Return of the number of leaves in the contiguous prefix
+    contiguous_len_ret: *mut u32,
+) -> u32 {
+    let (state, rt_ret, contiguous_len_ret) =
+        match unsafe { (state.as_ref(), rt_ret.as_mut(), contiguous_len_ret.as_mut()) } {
+            (Some(state), Some(rt_ret), Some(contiguous_len_ret)) => {
+                (state, rt_ret, contiguous_len_ret)
+            }
+            _ => return MMR_OOO_INVALID,
+        };
+
+    *contiguous_len_ret = state.contiguous;
+    if state.contiguous == 0 {
+        return MMR_OOO_EMPTY;
+    }
+    let root = match &state.tree {
+        VersionedTree::V1(tree) => tree.root(),
+        VersionedTree::V2(tree) => tree.root(),
+    };
+    match root {
+        Ok(root) => {
+            *rt_ret = root;
+            MMR_OOO_OK
+        }
+        Err(_) => MMR_OOO_INVALID,
+    }
+}
+
+/// Frees the state from `librustzcash_mmr_ooo_new`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_ooo_free(state: *mut MmrOutOfOrder) {
+    if !state.is_null() {
+        drop(unsafe { Box::from_raw(state) });
+    }
+}
+
+/// Writes the root of the tree with the `leaf_count` leaves at `leaves_ptr` to
+/// `root_ret`.
+///
+/// The tree is built a level at a time, which with the `simd` feature hashes each
+/// level's parents in one batch. This is much faster than appending the leaves one at
+/// a time, but only produces the final root.
+///
+/// Returns 0 on success, or 1 if any input is invalid, in which case `root_ret` is
+/// unaltered.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_root_from_leaves(
+    // Consensus branch id
+    cbranch: u32,
+    // Leaves, in order, length of leaf_count
+    leaves_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count
+    leaf_count: size_t,
+    // Return of the root commitment
+    root_ret: *mut [u8; 32],
+) -> u32 {
+    let root_ret = match unsafe { root_ret.as_mut() } {
+        Some(root_ret) => root_ret,
+        None => return 1,
+    };
+    if BranchId::try_from(cbranch).is_err() || leaf_count == 0 || leaves_ptr.is_null() {
+        return 1;
+    }
+    let leaves = unsafe { slice::from_raw_parts(leaves_ptr, leaf_count) };
+    let leaves = leaves.iter().map(|leaf| &leaf[..]).collect::<Vec<_>>();
+
+    let root = dispatch(
+        cbranch,
+        || history::HistoryTree::<V1>::from_leaves(cbranch, &leaves).and_then(|t| t.root()),
+        || history::HistoryTree::<V2>::from_leaves(cbranch, &leaves).and_then(|t| t.root()),
+    );
+    match root {
+        Ok(root) => {
+            *root_ret = root;
+            0
+        }
+        Err(_) => 1,
+    }
+}
+
+/// `librustzcash_mmr_diff_mask` result: the mask was written.
+const MMR_DIFF_MASK_OK: u32 = 0;
+/// `librustzcash_mmr_diff_mask` result: either tree has no leaves or can't be built
+/// from its leaves, or a required pointer is null.
+const MMR_DIFF_MASK_INVALID: u32 = 1;
+/// `librustzcash_mmr_diff_mask` result: `mask_out` is too small for the mask. Its
+/// length has still been written.
+const MMR_DIFF_MASK_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes a mask of the nodes that differ between the trees with the leaves at
+/// `a_leaves_ptr` and `b_leaves_ptr` to `mask_out`, for rendering a diff of the trees.
+///
+/// Bit `index % 8` of byte `index / 8` is set if the node at `index` (in array
+/// representation) differs between the trees, including if only one of them has it.
+/// The mask covers the longer of the two trees, and any remaining bits of its last
+/// byte are clear.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_diff_mask(
+    // Consensus branch id
+    cbranch: u32,
+    // Leaves of the first tree, in order, length of a_leaf_count
+    a_leaves_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count of the first tree
+    a_leaf_count: size_t,
+    // Leaves of the second tree, in order, length of b_leaf_count
+    b_leaves_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count of the second tree
+    b_leaf_count: size_t,
+    // Return buffer for the mask, length of cap
+    mask_out: *mut u8,
+    // Capacity of mask_out
+    cap: size_t,
+    // Return of the length of the mask in bytes
+    len_ret: *mut size_t,
+) -> u32 {
+    let len_ret = match unsafe { len_ret.as_mut() } {
+        Some(len_ret) => len_ret,
+        None => return MMR_DIFF_MASK_INVALID,
+    };
+    if BranchId::try_from(cbranch).is_err()
+        || a_leaf_count == 0
+        || b_leaf_count == 0
+        || a_leaves_ptr.is_null()
+        || b_leaves_ptr.is_null()
+    {
+        return MMR_DIFF_MASK_INVALID;
+    }
+    let a_leaves = unsafe { slice::from_raw_parts(a_leaves_ptr, a_leaf_count) };
+    let b_leaves = unsafe { slice::from_raw_parts(b_leaves_ptr, b_leaf_count) };
+
+    let mask = dispatch(
+        cbranch,
+        || diff_mask::<V1>(cbranch, a_leaves, b_leaves),
+        || diff_mask::<V2>(cbranch, a_leaves, b_leaves),
+    );
+    let mask = match mask {
+        Ok(mask) => mask,
+        Err(_) => return MMR_DIFF_MASK_INVALID,
+    };
+    *len_ret = mask.len();
+    if cap < mask.len() {
+        return MMR_DIFF_MASK_BUFFER_TOO_SMALL;
+    }
+    if mask_out.is_null() {
+        return MMR_DIFF_MASK_INVALID;
+    }
+
+    unsafe { slice::from_raw_parts_mut(mask_out, mask.len()) }.copy_from_slice(&mask);
+    MMR_DIFF_MASK_OK
+}
+
+fn diff_mask<V: Version>(
+    cbranch: u32,
+    a_leaves: &[[c_uchar; zcash_history::MAX_NODE_DATA_SIZE]],
+    b_leaves: &[[c_uchar; zcash_history::MAX_NODE_DATA_SIZE]],
+) -> Result<Vec<u8>, history::HistoryError> {
+    let a = history::HistoryTree::<V>::from_leaves(cbranch, a_leaves)?;
+    let b = history::HistoryTree::<V>::from_leaves(cbranch, b_leaves)?;
+
+    // Both trees are built from their leaves, so they hold every node and their
+    // encodings are canonical.
+    let t_len = a.len().max(b.len());
+    let mut mask = vec![0; (t_len as usize + 7) / 8];
+    for index in 0..t_len {
+        if a.node(index) != b.node(index) {
+            mask[index as usize / 8] |= 1 << (index % 8);
+        }
+    }
+    Ok(mask)
+}
+
+/// `librustzcash_mmr_first_leaf_divergence` result: the chains' leaves differ, and the
+/// index of the first that differs was written.
+const MMR_LEAVES_DIVERGE: u32 = 0;
+/// `librustzcash_mmr_first_leaf_divergence` result: one chain's leaves are a prefix of
+/// the other's (or they are the same), and the shorter leaf count was written.
+const MMR_LEAVES_PREFIX: u32 = 1;
+/// `librustzcash_mmr_first_leaf_divergence` result: a required pointer is null.
+const MMR_LEAVES_INVALID: u32 = 2;
+
+/// Writes the index of the first leaf that differs between the `a_count` leaves at
+/// `a_leaves` and the `b_count` leaves at `b_leaves` to `index_ret`, for finding where
+/// two chains fork during a reorg.
+///
+/// The leaves are compared as they are encoded, including any bytes past the end of
+/// their encodings, which zcashd always leaves zeroed. If every leaf of the shorter
+/// chain matches, the shorter leaf count is written instead: it is the index of the
+/// first leaf that only the longer chain has.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_first_leaf_divergence(
+    // Leaves of the first chain, in order, length of a_count
+    a_leaves: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count of the first chain
+    a_count: size_t,
+    // Leaves of the second chain, in order, length of b_count
+    b_leaves: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count of the second chain
+    b_count: size_t,
+    // Return of the leaf index
+    index_ret: *mut size_t,
+) -> u32 {
+    let index_ret = match unsafe { index_ret.as_mut() } {
+        Some(index_ret) => index_ret,
+        None => return MMR_LEAVES_INVALID,
+    };
+    if (a_count > 0 && a_leaves.is_null()) || (b_count > 0 && b_leaves.is_null()) {
+        return MMR_LEAVES_INVALID;
+    }
+    let a = if a_count == 0 {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(a_leaves, a_count) }
+    };
+    let b = if b_count == 0 {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(b_leaves, b_count) }
+    };
+
+    match a.iter().zip(b).position(|(a, b)| a[..] != b[..]) {
+        Some(index) => {
+            *index_ret = index;
+            MMR_LEAVES_DIVERGE
+        }
+        None => {
+            *index_ret = a.len().min(b.len());
+            MMR_LEAVES_PREFIX
+        }
+    }
+}
+
+/// `librustzcash_mmr_is_extension` result: the appends do not yield the new root.
+const MMR_EXTENSION_MISMATCH: u32 = 0;
+/// `librustzcash_mmr_is_extension` result: the appends yield the new root.
+const MMR_EXTENSION_MATCH: u32 = 1;
+/// `librustzcash_mmr_is_extension` result: the inputs are invalid, or the provided
+/// nodes are not the peaks of the old tree.
+const MMR_EXTENSION_INVALID_INPUT: u32 = 2;
+
+/// Checks that appending the `leaf_count` leaves at `leaves_ptr` to the tree of length
+/// `t_len` with the given peaks yields a tree with the root at `new_root`, so that the
+/// new tree extends the old one.
+///
+/// Only the peaks of the old tree are needed, and only the peaks are kept between
+/// appends. `ni_ptr` must list exactly the peak indices, in order; an empty old tree
+/// has no peaks, and must be extended by at least one leaf.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_is_extension(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of the old tree in array representation
+    t_len: u32,
+    // Indices of the old tree's peaks, length of p_len
+    ni_ptr: *const u32,
+    // Old tree's peaks data, length of p_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Peaks count
+    p_len: size_t,
+    // Leaves appended to the old tree, in order, length of leaf_count
+    leaves_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Leaves count
+    leaf_count: size_t,
+    // Claimed root of the new tree
+    new_root: *const [u8; 32],
+) -> u32 {
+    let new_root = match unsafe { new_root.as_ref() } {
+        Some(new_root) => new_root,
+        None => return MMR_EXTENSION_INVALID_INPUT,
+    };
+    if BranchId::try_from(cbranch).is_err()
+        || (p_len > 0 && (ni_ptr.is_null() || n_ptr.is_null()))
+        || (leaf_count > 0 && leaves_ptr.is_null())
+    {
+        return MMR_EXTENSION_INVALID_INPUT;
+    }
+    let (indices, nodes) = if p_len == 0 {
+        (&[][..], &[][..])
+    } else {
+        unsafe {
+            (
+                slice::from_raw_parts(ni_ptr, p_len),
+                slice::from_raw_parts(n_ptr, p_len),
+            )
+        }
+    };
+    let leaves = if leaf_count == 0 {
+        &[][..]
+    } else {
+        unsafe { slice::from_raw_parts(leaves_ptr, leaf_count) }
+    };
+
+    let extended = dispatch(
+        cbranch,
+        || extended_root::<V1>(cbranch, t_len, indices, nodes, leaves),
+        || extended_root::<V2>(cbranch, t_len, indices, nodes, leaves),
+    );
+    match extended {
+        Some(root) if &root == new_root => MMR_EXTENSION_MATCH,
+        Some(_) => MMR_EXTENSION_MISMATCH,
+        None => MMR_EXTENSION_INVALID_INPUT,
+    }
+}
+
+/// Returns the root of the tree with the given peaks after appending `leaves`.
+fn extended_root<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    indices: &[u32],
+    nodes: &[[c_uchar; zcash_history::MAX_ENTRY_SIZE]],
+    leaves: &[[c_uchar; zcash_history::MAX_NODE_DATA_SIZE]],
+) -> Option<[u8; 32]> {
+    let peaks = history::peaks(t_len)?;
+    if peaks.len() != indices.len() || peaks.iter().zip(indices).any(|((i, _), j)| i != j) {
+        return None;
+    }
+
+    let mut tree = if t_len == 0 {
+        history::HistoryTree::<V>::empty(cbranch)
+    } else {
+        let entries = indices
+            .iter()
+            .zip(nodes)
+            .map(|(index, node)| {
+                Some((
+                    *index,
+                    history::decode_entry::<V>(cbranch, *index, &node[..])?,
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let old = MMRTree::new(t_len, entries, vec![]);
+        let peaks = indices
+            .iter()
+            .map(|index| {
+                let peak = old.resolve_link(EntryLink::Stored(*index)).ok()?;
+                Some((*index, V::to_bytes(peak.data())))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        history::HistoryTree::<V>::from_nodes(cbranch, t_len, peaks).ok()?
+    };
+    for leaf in leaves {
+        tree.append(&leaf[..]).ok()?;
+        tree.retain_peaks();
+    }
+    tree.root().ok()
+}
+
+/// Writes the encoding of the node at `index` to `out`, returning 0 on success or
+/// any other value if the node is not available.
+pub type FetchNodeCb =
+    unsafe extern "C" fn(index: u32, out: *mut [c_uchar; zcash_history::MAX_NODE_DATA_SIZE]) -> u32;
+
+/// `librustzcash_mmr_verify_leaf_lazy` result: the leaf is not in a tree with the root.
+const MMR_VERIFY_LEAF_MISMATCH: u32 = 0;
+/// `librustzcash_mmr_verify_leaf_lazy` result: the leaf is in a tree with the root.
+const MMR_VERIFY_LEAF_MATCH: u32 = 1;
+/// `librustzcash_mmr_verify_leaf_lazy` result: the inputs are invalid, or the node at
+/// `leaf_index` is not a leaf of the tree.
+const MMR_VERIFY_LEAF_INVALID_INPUT: u32 = 2;
+/// `librustzcash_mmr_verify_leaf_lazy` result: the callback could not provide a node.
+const MMR_VERIFY_LEAF_FETCH_FAILED: u32 = 3;
+
+/// Checks that the leaf at `leaf_index` (in array representation) is part of a tree of
+/// length `t_len` with the root at `root_ptr`, fetching the nodes it needs with
+/// `fetch_cb` instead of having them all provided up front.
+///
+/// Only the leaf, the siblings of the nodes on its path up to its peak, and the other
+/// peaks are fetched, each of them once.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_verify_leaf_lazy(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Index of the leaf to verify, in array representation
+    leaf_index: u32,
+    // Root commitment to verify against
+    root_ptr: *const [u8; 32],
+    // Callback that provides tree nodes by index
+    fetch_cb: Option<FetchNodeCb>,
+) -> u32 {
+    let (root, fetch_cb) = match (unsafe { root_ptr.as_ref() }, fetch_cb) {
+        (Some(root), Some(fetch_cb)) => (root, fetch_cb),
+        _ => return MMR_VERIFY_LEAF_INVALID_INPUT,
+    };
+    if BranchId::try_from(cbranch).is_err() {
+        return MMR_VERIFY_LEAF_INVALID_INPUT;
+    }
+    let (peak, path) = match history::leaf_path(t_len, leaf_index) {
+        Some(leaf_path) => leaf_path,
+        None => return MMR_VERIFY_LEAF_INVALID_INPUT,
+    };
+    let peaks = history::peaks(t_len).expect("t_len was checked by leaf_path; qed");
+
+    let fetch = |index| {
+        let mut node = [0; zcash_history::MAX_NODE_DATA_SIZE];
+        match unsafe { fetch_cb(index, &mut node) } {
+            0 => Some(node.to_vec()),
+            _ => None,
+        }
+    };
+    let fetched = || {
+        Some(history::InclusionProof {
+            cbranch,
+            t_len,
+            leaf_index,
+            leaf: fetch(leaf_index)?,
+            siblings: path
+                .iter()
+                .map(|&(index, _)| fetch(index))
+                .collect::<Option<_>>()?,
+            other_peaks: peaks
+                .iter()
+                .enumerate()
+                .filter(|&(i, _)| i != peak)
+                .map(|(_, &(index, _))| fetch(index))
+                .collect::<Option<_>>()?,
+        })
+    };
+    let proof = match fetched() {
+        Some(proof) => proof,
+        None => return MMR_VERIFY_LEAF_FETCH_FAILED,
+    };
+
+    let verified = dispatch(
+        cbranch,
+        || proof.verify::<V1>(root),
+        || proof.verify::<V2>(root),
+    );
+    if verified {
+        MMR_VERIFY_LEAF_MATCH
+    } else {
+        MMR_VERIFY_LEAF_MISMATCH
+    }
+}
+
+/// `librustzcash_mmr_leaf_path_indices` result: the indices were written.
+const MMR_PATH_INDICES_OK: u32 = 0;
+/// `librustzcash_mmr_leaf_path_indices` result: the node at `leaf_index` is not a leaf
+/// of a tree of length `t_len`, or a pointer is null.
+const MMR_PATH_INDICES_INVALID: u32 = 1;
+/// `librustzcash_mmr_leaf_path_indices` result: `out` is too small for the indices.
+/// Their count has still been written.
+const MMR_PATH_INDICES_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes the indices, in ascending order, of the nodes that a proof for the leaf at
+/// `leaf_index` of a tree of length `t_len` is computed from, so that they can be
+/// loaded before calling `librustzcash_mmr_multiproof`: the leaf, each of its
+/// ancestors up to its peak, the sibling of each node on that path, and the other
+/// peaks of the tree.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_leaf_path_indices(
+    // Length of tree in array representation
+    t_len: u32,
+    // Index of the leaf in array representation
+    leaf_index: u32,
+    // Return buffer for the indices, length of cap
+    out: *mut u32,
+    // Capacity of out
+    cap: size_t,
+    // Return of the number of indices
+    len_ret: *mut size_t,
+) -> u32 {
+    let len_ret = match unsafe { len_ret.as_mut() } {
+        Some(len_ret) => len_ret,
+        None => return MMR_PATH_INDICES_INVALID,
+    };
+    let indices = match history::leaf_path_indices(t_len, leaf_index) {
+        Some(indices) => indices,
+        None => return MMR_PATH_INDICES_INVALID,
+    };
+    *len_ret = indices.len();
+    if cap < indices.len() {
+        return MMR_PATH_INDICES_BUFFER_TOO_SMALL;
+    }
+    if out.is_null() {
+        return MMR_PATH_INDICES_INVALID;
+    }
+
+    unsafe { slice::from_raw_parts_mut(out, indices.len()) }.copy_from_slice(&indices);
+    MMR_PATH_INDICES_OK
+}
+
+/// `librustzcash_mmr_multiproof` result: the proof was written.
+const MMR_MULTIPROOF_OK: u32 = 0;
+/// `librustzcash_mmr_multiproof` result: the inputs are invalid, the leaf indices are
+/// not strictly ascending leaves of the tree, or a node the proof needs is missing.
+const MMR_MULTIPROOF_INVALID: u32 = 1;
+/// `librustzcash_mmr_multiproof` result: `proof_out` is too small for the proof. Its
+/// length has still been written.
+const MMR_MULTIPROOF_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes a proof that each of the leaves at `leaf_indices_ptr` is part of a tree of
+/// length `t_len`, for `librustzcash_mmr_verify_multiproof`.
+///
+/// Nodes shared between the paths of the leaves are only included once, and nodes
+/// that can be computed from the rest of the proof are left out, so the proof is
+/// never larger than separate proofs for each leaf. The provided nodes must include
+/// the leaves and the siblings along their paths that are not on another path, along
+/// with the peaks that contain none of the leaves; any others are ignored.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_multiproof(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of n_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of n_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_NODE_DATA_SIZE],
+    // Provided nodes count
+    n_len: size_t,
+    // Indices of the leaves to prove in array representation, in ascending order,
+    // length of count
+    leaf_indices_ptr: *const u32,
+    // Leaves count
+    count: size_t,
+    // Return buffer for the proof, length of cap
+    proof_out: *mut c_uchar,
+    // Capacity of proof_out
+    cap: size_t,
+    // Return of the length of the proof
+    len_ret: *mut size_t,
+) -> u32 {
+    let len_ret = match unsafe { len_ret.as_mut() } {
+        Some(len_ret) => len_ret,
+        None => return MMR_MULTIPROOF_INVALID,
+    };
+    if BranchId::try_from(cbranch).is_err()
+        || ni_ptr.is_null()
+        || n_ptr.is_null()
+        || n_len == 0
+        || leaf_indices_ptr.is_null()
+        || count == 0
+    {
+        return MMR_MULTIPROOF_INVALID;
+    }
+    let indices = unsafe { slice::from_raw_parts(ni_ptr, n_len) };
+    let nodes = unsafe { slice::from_raw_parts(n_ptr, n_len) };
+    let leaf_indices = unsafe { slice::from_raw_parts(leaf_indices_ptr, count) };
+
+    let proof = dispatch(
+        cbranch,
+        || multiproof::<V1>(cbranch, t_len, indices, nodes, leaf_indices),
+        || multiproof::<V2>(cbranch, t_len, indices, nodes, leaf_indices),
+    );
+    let proof = match proof {
+        Some(proof) => proof,
+        None => return MMR_MULTIPROOF_INVALID,
+    };
+    *len_ret = proof.len();
+    if cap < proof.len() {
+        return MMR_MULTIPROOF_BUFFER_TOO_SMALL;
+    }
+    if proof_out.is_null() {
+        return MMR_MULTIPROOF_INVALID;
+    }
+
+    unsafe { slice::from_raw_parts_mut(proof_out, proof.len()) }.copy_from_slice(&proof);
+    MMR_MULTIPROOF_OK
+}
+
+fn multiproof<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    indices: &[u32],
+    nodes: &[[c_uchar; zcash_history::MAX_NODE_DATA_SIZE]],
+    leaf_indices: &[u32],
+) -> Option<Vec<u8>> {
+    if leaf_indices.windows(2).any(|w| w[0] >= w[1]) {
+        return None;
+    }
+    // The tree counts leaves from zero rather than by their array index.
+    let leaves = leaf_indices
+        .iter()
+        .map(|&index| {
+            if index >= t_len || history::node_altitude(index) != 0 {
+                return None;
+            }
+            history::leaf_count(index)
+        })
+        .collect::<Option<Vec<_>>>()?;
+    // The node buffers are padded, so only keep the bytes of each node's encoding.
+    let nodes = indices
+        .iter()
+        .zip(nodes)
+        .map(|(&index, node)| {
+            let data = history::decode_node::<V>(cbranch, &node[..])?;
+            Some((index, V::to_bytes(&data)))
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let tree = history::HistoryTree::<V>::from_nodes(cbranch, t_len, nodes).ok()?;
+    let proof = tree.prove_leaves(&leaves).ok()?;
+    let mut bytes = vec![];
+    proof.write(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+/// `librustzcash_mmr_verify_multiproof` result: the leaves are not all in a tree with
+/// the root.
+const MMR_VERIFY_MULTIPROOF_MISMATCH: u32 = 0;
+/// `librustzcash_mmr_verify_multiproof` result: the leaves are all in a tree with the
+/// root.
+const MMR_VERIFY_MULTIPROOF_MATCH: u32 = 1;
+/// `librustzcash_mmr_verify_multiproof` result: the inputs are invalid, or the proof
+/// is not a multiproof for the leaf indices.
+const MMR_VERIFY_MULTIPROOF_INVALID_INPUT: u32 = 2;
+
+/// Checks that each of the leaves at `leaf_indices_ptr` is part of a tree of length
+/// `t_len` with the root at `root_ptr`, using a proof written by
+/// `librustzcash_mmr_multiproof` for the same leaves.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_verify_multiproof(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of the proven leaves in array representation, in ascending order,
+    // length of count
+    leaf_indices_ptr: *const u32,
+    // Leaves count
+    count: size_t,
+    // Proof, length of proof_len
+    proof_ptr: *const c_uchar,
+    // Length of the proof
+    proof_len: size_t,
+    // Root commitment to verify against
+    root_ptr: *const [u8; 32],
+) -> u32 {
+    let root = match unsafe { root_ptr.as_ref() } {
+        Some(root) => root,
+        None => return MMR_VERIFY_MULTIPROOF_INVALID_INPUT,
+    };
+    if BranchId::try_from(cbranch).is_err()
+        || leaf_indices_ptr.is_null()
+        || count == 0
+        || proof_ptr.is_null()
+    {
+        return MMR_VERIFY_MULTIPROOF_INVALID_INPUT;
+    }
+    let leaf_indices = unsafe { slice::from_raw_parts(leaf_indices_ptr, count) };
+    let proof = unsafe { slice::from_raw_parts(proof_ptr, proof_len) };
+
+    let proof = match history::MultiProof::read(cbranch, t_len, leaf_indices.to_vec(), proof) {
+        Some(proof) => proof,
+        None => return MMR_VERIFY_MULTIPROOF_INVALID_INPUT,
+    };
+    let verified = dispatch(
+        cbranch,
+        || proof.verify::<V1>(root),
+        || proof.verify::<V2>(root),
+    );
+    if verified {
+        MMR_VERIFY_MULTIPROOF_MATCH
+    } else {
+        MMR_VERIFY_MULTIPROOF_MISMATCH
+    }
+}
+
+/// `librustzcash_mmr_verify_proof_by_hash` result: the leaf is not in a tree with the
+/// root.
+const MMR_VERIFY_BY_HASH_MISMATCH: u32 = 0;
+/// `librustzcash_mmr_verify_proof_by_hash` result: the leaf is in a tree with the root.
+const MMR_VERIFY_BY_HASH_MATCH: u32 = 1;
+/// `librustzcash_mmr_verify_proof_by_hash` result: the inputs are invalid, or the proof
+/// is not a multiproof for the leaf index.
+const MMR_VERIFY_BY_HASH_INVALID_INPUT: u32 = 2;
+/// `librustzcash_mmr_verify_proof_by_hash` result: the leaf encoding in the proof does
+/// not have the given hash.
+const MMR_VERIFY_BY_HASH_LEAF_MISMATCH: u32 = 3;
+
+/// Checks that the leaf with the hash at `leaf_hash` is the leaf at `leaf_index` (in
+/// array representation) of a tree of length `t_len` with the root at `root_ptr`, using
+/// a proof written by `librustzcash_mmr_multiproof` for that leaf alone.
+///
+/// A parent node commits to the full encodings of its children, so the proof can't be
+/// folded up from the leaf hash. Instead the proof's own copy of the leaf encoding is
+/// checked to have the given hash, so that the caller doesn't need the encoding.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_verify_proof_by_hash(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Index of the proven leaf in array representation
+    leaf_index: u32,
+    // Root commitment to verify against
+    root_ptr: *const [u8; 32],
+    // Hash of the proven leaf
+    leaf_hash: *const [u8; 32],
+    // Proof, length of proof_len
+    proof_ptr: *const c_uchar,
+    // Length of the proof
+    proof_len: size_t,
+) -> u32 {
+    let (root, leaf_hash) = match unsafe { (root_ptr.as_ref(), leaf_hash.as_ref()) } {
+        (Some(root), Some(leaf_hash)) => (root, leaf_hash),
+        _ => return MMR_VERIFY_BY_HASH_INVALID_INPUT,
+    };
+    if BranchId::try_from(cbranch).is_err() || proof_ptr.is_null() {
+        return MMR_VERIFY_BY_HASH_INVALID_INPUT;
+    }
+    let proof = unsafe { slice::from_raw_parts(proof_ptr, proof_len) };
+
+    let proof = match history::MultiProof::read(cbranch, t_len, vec![leaf_index], proof) {
+        Some(proof) => proof,
+        None => return MMR_VERIFY_BY_HASH_INVALID_INPUT,
+    };
+    dispatch(
+        cbranch,
+        || verify_proof_by_hash::<V1>(&proof, root, leaf_hash),
+        || verify_proof_by_hash::<V2>(&proof, root, leaf_hash),
+    )
+}
+
+fn verify_proof_by_hash<V: Version>(
+    proof: &history::MultiProof,
+    root: &[u8; 32],
+    leaf_hash: &[u8; 32],
+) -> u32 {
+    match history::decode_node::<V>(proof.cbranch, &proof.leaves[0]) {
+        None => MMR_VERIFY_BY_HASH_INVALID_INPUT,
+        Some(leaf) if &V::hash(&leaf) != leaf_hash => MMR_VERIFY_BY_HASH_LEAF_MISMATCH,
+        Some(_) if proof.verify::<V>(root) => MMR_VERIFY_BY_HASH_MATCH,
+        Some(_) => MMR_VERIFY_BY_HASH_MISMATCH,
+    }
+}
+
+/// `librustzcash_mmr_frontier` and `librustzcash_mmr_append_frontier` result: the
+/// frontier was written.
+const MMR_FRONTIER_OK: u32 = 0;
+/// `librustzcash_mmr_frontier` and `librustzcash_mmr_append_frontier` result: the inputs
+/// are invalid, or a pointer is null.
+const MMR_FRONTIER_INVALID: u32 = 1;
+/// `librustzcash_mmr_frontier` and `librustzcash_mmr_append_frontier` result: `out` is
+/// too small for the frontier. Its length has still been written, and nothing else has.
+const MMR_FRONTIER_BUFFER_TOO_SMALL: u32 = 2;
+
+/// Writes `frontier` to `out` if it fits in `cap` bytes, after writing its length to
+/// `len_ret`.
+fn write_frontier(frontier: &[u8], out: *mut c_uchar, cap: size_t, len_ret: &mut size_t) -> u32 {
+    *len_ret = frontier.len();
+    if cap < frontier.len() {
+        return MMR_FRONTIER_BUFFER_TOO_SMALL;
+    }
+    if out.is_null() {
+        return MMR_FRONTIER_INVALID;
+    }
+    unsafe { slice::from_raw_parts_mut(out, frontier.len()) }.copy_from_slice(frontier);
+    MMR_FRONTIER_OK
+}
+
+/// Writes the frontier of the tree of length `t_len` with the given peaks to `out`: its
+/// leaf count, followed by the encodings of its peaks. A tree that is only appended to
+/// can be kept as its frontier, and extended with `librustzcash_mmr_append_frontier`.
+///
+/// The provided nodes must be exactly the peaks of the tree, as for
+/// `librustzcash_mmr_append`. An empty tree (with `t_len` and `p_len` both 0) has a
+/// frontier too, from which its first leaf can be appended.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_frontier(
+    // Consensus branch id
+    cbranch: u32,
+    // Length of tree in array representation
+    t_len: u32,
+    // Indices of provided tree nodes, length of p_len
+    ni_ptr: *const u32,
+    // Provided tree nodes data, length of p_len
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Peaks count
+    p_len: size_t,
+    // Return buffer for the frontier, length of cap
+    out: *mut c_uchar,
+    // Capacity of out
+    cap: size_t,
+    // Return of the length of the frontier
+    len_ret: *mut size_t,
+) -> u32 {
+    let len_ret = match unsafe { len_ret.as_mut() } {
+        Some(len_ret) => len_ret,
+        None => return MMR_FRONTIER_INVALID,
+    };
+    if BranchId::try_from(cbranch).is_err() {
+        return MMR_FRONTIER_INVALID;
+    }
+
+    let frontier = dispatch(
+        cbranch,
+        || frontier::<V1>(cbranch, t_len, ni_ptr, n_ptr, p_len),
+        || frontier::<V2>(cbranch, t_len, ni_ptr, n_ptr, p_len),
+    );
+    match frontier {
+        Some(frontier) => write_frontier(&frontier, out, cap, len_ret),
+        None => MMR_FRONTIER_INVALID,
+    }
+}
+
+fn frontier<V: Version>(
+    cbranch: u32,
+    t_len: u32,
+    ni_ptr: *const u32,
+    n_ptr: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    p_len: size_t,
+) -> Option<Vec<u8>> {
+    if t_len == 0 && p_len == 0 {
+        return history::HistoryTree::<V>::empty(cbranch).frontier().ok();
+    }
+    let tree =
+        construct_mmr_tree::<V>(cbranch, t_len, ni_ptr, n_ptr, ptr::null(), p_len, 0).ok()?;
+    // `construct_mmr_tree` checked that the nodes are the peaks, so the frontier holds
+    // all of them.
+    let peaks = unsafe { slice::from_raw_parts(ni_ptr, p_len) }
+        .iter()
+        .map(|&index| {
+            let data = tree.resolve_link(EntryLink::Stored(index)).ok()?;
+            Some((index, V::to_bytes(data.data())))
+        })
+        .collect::<Option<Vec<_>>>()?;
+    history::HistoryTree::<V>::from_nodes(cbranch, t_len, peaks)
+        .and_then(|tree| tree.frontier())
+        .ok()
+}
+
+/// Appends the leaf at `nn_ptr` to the tree with the frontier at `frontier_ptr`,
+/// written by `librustzcash_mmr_frontier` or by an earlier append, writing the root of
+/// the extended tree to `rt_ret` and its frontier to `out`.
+///
+/// This gives the same root as `librustzcash_mmr_append` given the tree's peaks. If
+/// `out` is too small, neither the root nor the frontier is written.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_append_frontier(
+    // Consensus branch id
+    cbranch: u32,
+    // Frontier of the tree, length of frontier_len
+    frontier_ptr: *const c_uchar,
+    // Length of the frontier
+    frontier_len: size_t,
+    // New node pointer
+    nn_ptr: *const [u8; zcash_history::MAX_NODE_DATA_SIZE],
+    // Return of root commitment
+    rt_ret: *mut [u8; 32],
+    // Return buffer for the frontier of the extended tree, length of cap
+    out: *mut c_uchar,
+    // Capacity of out
+    cap: size_t,
+    // Return of the length of the frontier of the extended tree
+    len_ret: *mut size_t,
+) -> u32 {
+    let (new_node, rt_ret, len_ret) =
+        match unsafe { (nn_ptr.as_ref(), rt_ret.as_mut(), len_ret.as_mut()) } {
+            (Some(new_node), Some(rt_ret), Some(len_ret)) => (new_node, rt_ret, len_ret),
+            _ => return MMR_FRONTIER_INVALID,
+        };
+    if BranchId::try_from(cbranch).is_err() || frontier_ptr.is_null() {
+        return MMR_FRONTIER_INVALID;
+    }
+    let frontier = unsafe { slice::from_raw_parts(frontier_ptr, frontier_len) };
+
+    let appended = dispatch(
+        cbranch,
+        || append_frontier::<V1>(cbranch, frontier, &new_node[..]),
+        || append_frontier::<V2>(cbranch, frontier, &new_node[..]),
+    );
+    let (root, frontier) = match appended {
+        Some(appended) => appended,
+        None => return MMR_FRONTIER_INVALID,
+    };
+    let result = write_frontier(&frontier, out, cap, len_ret);
+    if result == MMR_FRONTIER_OK {
+        *rt_ret = root;
+    }
+    result
+}
+
+/// Returns the root and frontier of the tree with the given frontier, after appending
+/// `leaf` to it.
+fn append_frontier<V: Version>(
+    cbranch: u32,
+    frontier: &[u8],
+    leaf: &[u8],
+) -> Option<([u8; 32], Vec<u8>)> {
+    let mut tree = history::HistoryTree::<V>::from_frontier(cbranch, frontier)?;
+    let appended = tree.append(leaf).ok()?;
+    Some((appended.new_root, tree.frontier().ok()?))
+}
+
+/// `librustzcash_mmr_balanced_root` result: the root was written.
+const MMR_BALANCED_ROOT_OK: u32 = 0;
+/// `librustzcash_mmr_balanced_root` result: the consensus branch is invalid, there are
+/// no leaf hashes, or a pointer is null.
+const MMR_BALANCED_ROOT_INVALID: u32 = 1;
+
+/// Writes the root of a balanced binary Merkle tree over the `count` 32-byte leaf
+/// hashes at `leaf_hashes_ptr` to `rt_ret`, hashing each pair of children with the
+/// personalization that the tree for the consensus branch `cbranch` combines nodes
+/// with.
+///
+/// This is not a history tree root: an MMR's peaks are bagged into its root instead,
+/// and its parents commit to the full encodings of their children. It is for other
+/// commitments over a fixed set of hashes. `rt_ret` is unaltered unless
+/// `MMR_BALANCED_ROOT_OK` is returned.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_balanced_root(
+    // Consensus branch id
+    cbranch: u32,
+    // Leaf hashes, length of count
+    leaf_hashes_ptr: *const [u8; 32],
+    // Number of leaf hashes
+    count: size_t,
+    // Return of the root
+    rt_ret: *mut [u8; 32],
+) -> u32 {
+    let rt_ret = match unsafe { rt_ret.as_mut() } {
+        Some(r) => r,
+        None => return MMR_BALANCED_ROOT_INVALID,
+    };
+    if BranchId::try_from(cbranch).is_err() || count == 0 || leaf_hashes_ptr.is_null() {
+        return MMR_BALANCED_ROOT_INVALID;
+    }
+    let leaf_hashes = unsafe { slice::from_raw_parts(leaf_hashes_ptr, count) };
+
+    match history::balanced_root(cbranch, leaf_hashes) {
+        Some(root) => {
+            *rt_ret = root;
+            MMR_BALANCED_ROOT_OK
+        }
+        None => MMR_BALANCED_ROOT_INVALID,
+    }
+}
+
+/// `librustzcash_mmr_deep_validate` result: every internal node is the combination of
+/// its children. This can't be confused with a node index, as no tree is this long.
+const MMR_DEEP_VALIDATE_OK: u32 = u32::MAX;
+/// `librustzcash_mmr_deep_validate` result: the nodes are not exactly the nodes of a
+/// tree, a node could not be decoded, or a pointer is null.
+const MMR_DEEP_VALIDATE_INVALID_INPUT: u32 = u32::MAX - 1;
+
+/// Checks that each internal node of the tree whose `count` nodes (in array
+/// representation, in any order) are at `all_indices` and `all_nodes` is the
+/// combination of its children.
+///
+/// Unlike `librustzcash_mmr_self_check`, this recomputes every internal node rather
+/// than only those whose children happen to be provided, so it needs the whole tree;
+/// the tree's length is `count`. It is meant for validating an archival copy of the
+/// tree, rather than for use on the hot path.
+///
+/// Returns the index of the first internal node that is not the combination of its
+/// children, `MMR_DEEP_VALIDATE_OK` if there is none, or
+/// `MMR_DEEP_VALIDATE_INVALID_INPUT`.
+#[no_mangle]
+pub extern "system" fn librustzcash_mmr_deep_validate(
+    // Consensus branch id
+    cbranch: u32,
+    // Indices of all tree nodes, length of count
+    all_indices: *const u32,
+    // All tree nodes data, length of count
+    all_nodes: *const [c_uchar; zcash_history::MAX_ENTRY_SIZE],
+    // Nodes count, which is the length of the tree
+    count: size_t,
+) -> u32 {
+    if BranchId::try_from(cbranch).is_err()
+        || count == 0
+        || all_indices.is_null()
+        || all_nodes.is_null()
+    {
+        return MMR_DEEP_VALIDATE_INVALID_INPUT;
+    }
+    let (indices, nodes) = unsafe {
+        (
+            slice::from_raw_parts(all_indices, count),
+            slice::from_raw_parts(all_nodes, count),
+        )
+    };
+
+    dispatch(
+        cbranch,
+        || deep_validate::<V1>(cbranch, indices, nodes),
+        || deep_validate::<V2>(cbranch, indices, nodes),
+    )
+}
+
+fn deep_validate<V: Version>(
+    cbranch: u32,
+    indices: &[u32],
+    nodes: &[[c_uchar; zcash_history::MAX_ENTRY_SIZE]],
+) -> u32 {
+    let t_len = match u32::try_from(indices.len()) {
+        Ok(t_len) if t_len < MMR_DEEP_VALIDATE_INVALID_INPUT => t_len,
+        _ => return MMR_DEEP_VALIDATE_INVALID_INPUT,
+    };
+    let peaks = match history::peaks(t_len) {
+        Some(peaks) if !peaks.is_empty() => peaks,
+        _ => return MMR_DEEP_VALIDATE_INVALID_INPUT,
+    };
+
+    // Every node of the tree must be provided exactly once. As there are as many nodes
+    // as the tree's length, this is the case if none is provided twice.
+    let mut positions = vec![None; indices.len()];
+    for (position, &index) in indices.iter().enumerate() {
+        match positions.get_mut(index as usize) {
+            Some(slot @ None) => *slot = Some(position),
+            _ => return MMR_DEEP_VALIDATE_INVALID_INPUT,
+        }
+    }
+
+    let mut peak_entries = Vec::with_capacity(peaks.len());
+    let mut extra = Vec::with_capacity(indices.len() - peaks.len());
+    let mut next_peak = peaks.iter().map(|(index, _)| *index).peekable();
+    for (index, position) in (0..t_len).zip(positions) {
+        let node = &nodes[position.expect("every index was provided; qed")];
+        let entry = match history::decode_entry::<V>(cbranch, index, &node[..]) {
+            Some(entry) => entry,
+            None => return MMR_DEEP_VALIDATE_INVALID_INPUT,
+        };
+        if next_peak.next_if_eq(&index).is_some() {
+            peak_entries.push((index, entry));
+        } else {
+            extra.push((index, entry));
+        }
+    }
+    let tree = MMRTree::new(t_len, peak_entries, extra);
+
+    let node = |index| tree.resolve_link(EntryLink::Stored(index)).ok();
+    for index in 0..t_len {
+        let alt = history::node_altitude(index);
+        if alt == 0 {
+            continue;
+        }
+        let (parent, left, right) = match (node(index), node(index - (1 << alt)), node(index - 1)) {
+            (Some(parent), Some(left), Some(right)) => (parent, left, right),
+            _ => return MMR_DEEP_VALIDATE_INVALID_INPUT,
+        };
+        let combined = V::combine(left.data(), right.data());
+        if !history::encodings_equal::<V>(&combined, parent.data()) {
+            return index;
+        }
+    }
+
+    MMR_DEEP_VALIDATE_OK
 }
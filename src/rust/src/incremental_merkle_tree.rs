@@ -1,20 +1,27 @@
-use byteorder::{ReadBytesExt, WriteBytesExt};
-use std::collections::{BTreeMap, BTreeSet};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::io::{self, Read, Write};
 
 use incrementalmerkletree::{
-    bridgetree::{BridgeTree, Checkpoint},
-    Hashable, Position,
+    bridgetree::{self, BridgeTree, Checkpoint, Leaf},
+    Altitude, Hashable, Position,
 };
+use orchard::tree::MerkleHashOrchard;
 use zcash_encoding::{Optional, Vector};
-use zcash_primitives::merkle_tree::{
-    incremental::{
-        read_bridge_v1, read_leu64_usize, read_position, write_bridge_v1, write_position,
-        write_usize_leu64, SER_V1, SER_V2,
+use zcash_primitives::{
+    merkle_tree::{
+        incremental::{
+            read_bridge_v1, read_frontier_v1, read_leu64_usize, read_position, write_bridge_v1,
+            write_frontier_v1, write_position, write_usize_leu64, SER_V1, SER_V2,
+        },
+        HashSer,
     },
-    HashSer,
+    sapling,
 };
 
+pub type SaplingFrontier = bridgetree::Frontier<sapling::Node, 32>;
+pub type OrchardFrontier = bridgetree::Frontier<MerkleHashOrchard, 32>;
+
 pub fn write_checkpoint_v2<W: Write>(mut writer: W, checkpoint: &Checkpoint) -> io::Result<()> {
     write_usize_leu64(&mut writer, checkpoint.bridges_len())?;
     writer.write_u8(if checkpoint.is_witnessed() { 1 } else { 0 })?;
@@ -139,3 +146,387 @@ pub fn read_tree<H: Hashable + HashSer + Ord + Clone, R: Read>(
         )
     })
 }
+
+/// Returns the encoding of the root of an empty subtree of the given depth, or `None`
+/// if `depth` is greater than the depth of the note commitment tree.
+///
+/// The note commitment types cache their empty roots, so this is cheap to call.
+fn empty_root<H: Hashable + HashSer>(depth: u8) -> Option<[u8; 32]> {
+    if depth > 32 {
+        return None;
+    }
+    let mut root = [0; 32];
+    H::empty_root(Altitude::from(depth))
+        .write(&mut root[..])
+        .expect("Node encodings are 32 bytes; qed");
+    Some(root)
+}
+
+/// Returns the root of an empty Sapling note commitment subtree of depth `depth`, where
+/// the empty root at depth 0 is the uncommitted leaf value. Returns `None` if `depth`
+/// is greater than 32.
+pub fn sapling_empty_root(depth: u8) -> Option<[u8; 32]> {
+    empty_root::<sapling::Node>(depth)
+}
+
+/// Returns the root of an empty Orchard note commitment subtree of depth `depth`, where
+/// the empty root at depth 0 is the uncommitted leaf value. Returns `None` if `depth`
+/// is greater than 32.
+pub fn orchard_empty_root(depth: u8) -> Option<[u8; 32]> {
+    empty_root::<MerkleHashOrchard>(depth)
+}
+
+/// Computes the root of a tree in the legacy `IncrementalMerkleTree` representation, in
+/// the same way as zcashd's C++ implementation.
+fn legacy_root<H: Hashable>(left: Option<&H>, right: Option<&H>, parents: &[Option<H>]) -> H {
+    let empty_leaf = H::empty_leaf();
+    let mut root = H::combine(
+        Altitude::from(0),
+        left.unwrap_or(&empty_leaf),
+        right.unwrap_or(&empty_leaf),
+    );
+    for (i, parent) in parents.iter().enumerate() {
+        let altitude = Altitude::from((i + 1) as u8);
+        root = match parent {
+            Some(parent) => H::combine(altitude, parent, &root),
+            None => H::combine(altitude, &root, &H::empty_root(altitude)),
+        };
+    }
+    for level in (parents.len() + 1)..32 {
+        let altitude = Altitude::from(level as u8);
+        root = H::combine(altitude, &root, &H::empty_root(altitude));
+    }
+    root
+}
+
+/// Reads a note commitment tree in the legacy `IncrementalMerkleTree` encoding (the
+/// `zcash_primitives::merkle_tree::CommitmentTree` sparse encoding) and converts it to
+/// the equivalent frontier.
+///
+/// Returns an error if the legacy tree has a gap that a frontier can't represent, or if
+/// the root of the resulting frontier doesn't match the root of the legacy tree.
+#[allow(clippy::redundant_closure)]
+pub fn read_legacy_frontier<H: Hashable + HashSer + Clone + PartialEq, R: Read>(
+    mut reader: R,
+) -> io::Result<bridgetree::Frontier<H, 32>> {
+    let left = Optional::read(&mut reader, |r| H::read(r))?;
+    let right = Optional::read(&mut reader, |r| H::read(r))?;
+    let parents = Vector::read(&mut reader, |r| Optional::read(r, |r| H::read(r)))?;
+
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    if parents.len() >= 32 {
+        return Err(invalid(format!(
+            "Legacy tree has {} parents, but a tree of depth 32 has at most 31.",
+            parents.len()
+        )));
+    }
+
+    let leaf = match (left.clone(), right.clone()) {
+        (None, None) if parents.is_empty() => return Ok(bridgetree::Frontier::empty()),
+        (None, None) => {
+            return Err(invalid(
+                "Legacy tree has parents but no leaves.".to_string(),
+            ))
+        }
+        (None, Some(_)) => {
+            return Err(invalid(
+                "Legacy tree has a right leaf without a left leaf.".to_string(),
+            ))
+        }
+        (Some(a), None) => Leaf::Left(a),
+        (Some(a), Some(b)) => Leaf::Right(a, b),
+    };
+
+    let size = parents
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| p.is_some())
+        .fold(if right.is_some() { 2 } else { 1 }, |acc, (i, _)| {
+            acc + (1usize << (i + 1))
+        });
+    let ommers = parents.iter().flatten().cloned().collect();
+
+    let frontier = bridgetree::Frontier::from_parts(Position::from(size - 1), leaf, ommers)
+        .map_err(|e| {
+            invalid(format!(
+                "Legacy tree of size {} could not be converted to a frontier: {:?}",
+                size, e
+            ))
+        })?;
+
+    if frontier.root() != legacy_root(left.as_ref(), right.as_ref(), &parents) {
+        return Err(invalid(format!(
+            "Root of the frontier converted from a legacy tree of size {} does not match the legacy root.",
+            size
+        )));
+    }
+
+    Ok(frontier)
+}
+
+/// Converts the legacy `IncrementalMerkleTree` encoding of a tree into a frontier,
+/// requiring that the whole of `legacy_bytes` is consumed.
+fn legacy_tree_to_frontier<H: Hashable + HashSer + Clone + PartialEq>(
+    mut legacy_bytes: &[u8],
+) -> io::Result<bridgetree::Frontier<H, 32>> {
+    let frontier = read_legacy_frontier(&mut legacy_bytes)?;
+    if !legacy_bytes.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} unexpected trailing bytes after legacy tree.",
+                legacy_bytes.len()
+            ),
+        ));
+    }
+    Ok(frontier)
+}
+
+/// Converts a serialized legacy Sapling note commitment tree into a frontier with the
+/// same root.
+pub fn sapling_tree_to_frontier(legacy_bytes: &[u8]) -> io::Result<SaplingFrontier> {
+    legacy_tree_to_frontier(legacy_bytes)
+}
+
+/// Converts a serialized legacy Orchard note commitment tree into a frontier with the
+/// same root.
+pub fn orchard_tree_to_frontier(legacy_bytes: &[u8]) -> io::Result<OrchardFrontier> {
+    legacy_tree_to_frontier(legacy_bytes)
+}
+
+/// The note commitment tree roots as of the most recent blocks, used to check that an
+/// anchor refers to a recent state of the tree.
+///
+/// Each block has its own entry, so a root that is shared by several blocks (because
+/// the blocks between them added no note commitments) stays valid until every block
+/// with that root has been rewound or has left the window.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AnchorHistory<H> {
+    max_len: usize,
+    roots: VecDeque<(u32, H)>,
+}
+
+impl<H: HashSer + Clone + PartialEq> AnchorHistory<H> {
+    /// Constructs an empty history retaining the roots of at most `max_len` blocks.
+    pub fn new(max_len: usize) -> Self {
+        AnchorHistory {
+            max_len,
+            roots: VecDeque::with_capacity(max_len),
+        }
+    }
+
+    /// Records the tree root as of the end of the block at `height`, forgetting the
+    /// oldest root if the history is full.
+    ///
+    /// Returns `false` without modifying the history if `height` is not greater than
+    /// the height of the most recently pushed root.
+    pub fn push(&mut self, height: u32, root: H) -> bool {
+        if matches!(self.roots.back(), Some((last, _)) if *last >= height) {
+            return false;
+        }
+        if self.max_len == 0 {
+            return true;
+        }
+        if self.roots.len() == self.max_len {
+            self.roots.pop_front();
+        }
+        self.roots.push_back((height, root));
+        true
+    }
+
+    /// Forgets the roots of all blocks above `height`.
+    pub fn rewind_to(&mut self, height: u32) {
+        while matches!(self.roots.back(), Some((last, _)) if *last > height) {
+            self.roots.pop_back();
+        }
+    }
+
+    /// Returns the height of the most recent block whose root is `anchor`, if any
+    /// block within the retained window has that root.
+    pub fn contains(&self, anchor: &H) -> Option<u32> {
+        self.roots
+            .iter()
+            .rev()
+            .find(|(_, root)| root == anchor)
+            .map(|(height, _)| *height)
+    }
+
+    /// Returns the height of the most recently pushed root.
+    pub fn tip_height(&self) -> Option<u32> {
+        self.roots.back().map(|(height, _)| *height)
+    }
+
+    /// Returns the number of retained roots.
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Returns whether the history has no retained roots.
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_usize_leu64(&mut writer, self.max_len)?;
+        Vector::write_sized(&mut writer, self.roots.iter(), |mut w, (height, root)| {
+            w.write_u32::<LittleEndian>(*height)?;
+            root.write(&mut w)
+        })
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let max_len = read_leu64_usize(&mut reader)?;
+        let roots: Vec<(u32, H)> = Vector::read(&mut reader, |mut r| {
+            Ok((r.read_u32::<LittleEndian>()?, H::read(&mut r)?))
+        })?;
+
+        if roots.len() > max_len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Anchor history has {} roots, but retains at most {}.",
+                    roots.len(),
+                    max_len
+                ),
+            ));
+        }
+        if roots.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Anchor history heights are not strictly increasing.",
+            ));
+        }
+
+        Ok(AnchorHistory {
+            max_len,
+            roots: roots.into(),
+        })
+    }
+}
+
+/// A frontier that can be rewound to the state it had at the end of any of its most
+/// recent checkpointed blocks.
+///
+/// Frontiers are small (at most one node per level of the tree), so each checkpoint
+/// stores a copy of the frontier as it was when the checkpoint was made.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeepFrontier<H> {
+    frontier: bridgetree::Frontier<H, 32>,
+    max_checkpoints: usize,
+    checkpoints: VecDeque<(u32, bridgetree::Frontier<H, 32>)>,
+}
+
+impl<H: Hashable + HashSer + Clone + PartialEq> DeepFrontier<H> {
+    /// Wraps `frontier`, retaining at most `max_checkpoints` checkpoints.
+    pub fn new(frontier: bridgetree::Frontier<H, 32>, max_checkpoints: usize) -> Self {
+        DeepFrontier {
+            frontier,
+            max_checkpoints,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    /// Returns the current frontier.
+    pub fn frontier(&self) -> &bridgetree::Frontier<H, 32> {
+        &self.frontier
+    }
+
+    /// Appends a note commitment to the frontier, returning `false` if the tree is full.
+    pub fn append(&mut self, value: &H) -> bool {
+        self.frontier.append(value)
+    }
+
+    /// Returns the root of the current frontier.
+    pub fn root(&self) -> H {
+        self.frontier.root()
+    }
+
+    /// Records the current state of the frontier as the state at the end of the block at
+    /// `height`, forgetting the oldest checkpoint if the maximum number are retained.
+    ///
+    /// Returns `false` without making a checkpoint if `height` is not greater than the
+    /// height of the most recent checkpoint.
+    pub fn checkpoint(&mut self, height: u32) -> bool {
+        if matches!(self.checkpoints.back(), Some((last, _)) if *last >= height) {
+            return false;
+        }
+        self.checkpoints.push_back((height, self.frontier.clone()));
+        self.prune_checkpoints(self.max_checkpoints);
+        true
+    }
+
+    /// Returns the height of the most recent checkpoint.
+    pub fn last_checkpoint_height(&self) -> Option<u32> {
+        self.checkpoints.back().map(|(height, _)| *height)
+    }
+
+    /// Restores the frontier to its state at the checkpoint for the block at `height`,
+    /// discarding any later checkpoints. That checkpoint is kept, so that the frontier
+    /// can be rewound to it again.
+    ///
+    /// Returns `false` without modifying the frontier if there is no checkpoint at
+    /// `height`.
+    pub fn rewind(&mut self, height: u32) -> bool {
+        match self.checkpoints.iter().rposition(|(h, _)| *h == height) {
+            Some(pos) => {
+                self.checkpoints.truncate(pos + 1);
+                self.frontier = self.checkpoints[pos].1.clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Discards all but the `keep` most recent checkpoints.
+    pub fn prune_checkpoints(&mut self, keep: usize) {
+        while self.checkpoints.len() > keep {
+            self.checkpoints.pop_front();
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        write_frontier_v1(&mut writer, &self.frontier)?;
+        write_usize_leu64(&mut writer, self.max_checkpoints)?;
+        Vector::write_sized(
+            &mut writer,
+            self.checkpoints.iter(),
+            |mut w, (height, frontier)| {
+                w.write_u32::<LittleEndian>(*height)?;
+                write_frontier_v1(&mut w, frontier)
+            },
+        )
+    }
+
+    pub fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let frontier = read_frontier_v1(&mut reader)?;
+        let max_checkpoints = read_leu64_usize(&mut reader)?;
+        let checkpoints: Vec<(u32, bridgetree::Frontier<H, 32>)> =
+            Vector::read(&mut reader, |mut r| {
+                Ok((r.read_u32::<LittleEndian>()?, read_frontier_v1(&mut r)?))
+            })?;
+
+        if checkpoints.len() > max_checkpoints {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Frontier has {} checkpoints, but retains at most {}.",
+                    checkpoints.len(),
+                    max_checkpoints
+                ),
+            ));
+        }
+        if checkpoints.windows(2).any(|w| w[0].0 >= w[1].0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Frontier checkpoint heights are not strictly increasing.",
+            ));
+        }
+
+        Ok(DeepFrontier {
+            frontier,
+            max_checkpoints,
+            checkpoints: checkpoints.into(),
+        })
+    }
+}
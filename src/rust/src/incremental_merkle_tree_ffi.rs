@@ -1,4 +1,5 @@
 use incrementalmerkletree::{bridgetree, Altitude, Frontier, Hashable};
+use std::convert::TryFrom;
 use std::mem::size_of_val;
 use std::ptr;
 
@@ -12,6 +13,9 @@ use zcash_primitives::{
     transaction::components::Amount,
 };
 
+use crate::incremental_merkle_tree::{
+    orchard_empty_root, read_legacy_frontier, AnchorHistory, DeepFrontier,
+};
 use crate::streams_ffi::{CppStreamReader, CppStreamWriter, ReadCb, StreamObj, WriteCb};
 
 pub const MERKLE_DEPTH: u8 = 32;
@@ -61,6 +65,22 @@ pub extern "C" fn orchard_merkle_frontier_parse(
     }
 }
 
+#[no_mangle]
+pub extern "C" fn orchard_merkle_frontier_parse_legacy(
+    stream: Option<StreamObj>,
+    read_cb: Option<ReadCb>,
+) -> *mut bridgetree::Frontier<MerkleHashOrchard, MERKLE_DEPTH> {
+    let reader = CppStreamReader::from_raw_parts(stream, read_cb.unwrap());
+
+    match read_legacy_frontier(reader) {
+        Ok(parsed) => Box::into_raw(Box::new(parsed)),
+        Err(e) => {
+            error!("Failed to parse legacy Orchard note commitment tree: {}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn orchard_merkle_frontier_serialize(
     frontier: *const bridgetree::Frontier<MerkleHashOrchard, MERKLE_DEPTH>,
@@ -184,3 +204,312 @@ pub extern "C" fn orchard_merkle_tree_empty_root(root_ret: *mut [u8; 32]) {
 
     *root_ret = digest;
 }
+
+#[no_mangle]
+pub extern "C" fn orchard_merkle_tree_empty_root_at_depth(
+    depth: usize,
+    root_ret: *mut [u8; 32],
+) -> bool {
+    let root_ret = unsafe {
+        root_ret
+            .as_mut()
+            .expect("Cannot return to the null pointer.")
+    };
+
+    match u8::try_from(depth).ok().and_then(orchard_empty_root) {
+        Some(root) => {
+            *root_ret = root;
+            true
+        }
+        None => false,
+    }
+}
+
+//
+// Operations on the history of recent Orchard anchors.
+//
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_new(
+    max_len: usize,
+) -> *mut AnchorHistory<MerkleHashOrchard> {
+    Box::into_raw(Box::new(AnchorHistory::new(max_len)))
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_clone(
+    history: *const AnchorHistory<MerkleHashOrchard>,
+) -> *mut AnchorHistory<MerkleHashOrchard> {
+    unsafe { history.as_ref() }
+        .map(|history| Box::into_raw(Box::new(history.clone())))
+        .unwrap_or(std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_free(history: *mut AnchorHistory<MerkleHashOrchard>) {
+    if !history.is_null() {
+        drop(unsafe { Box::from_raw(history) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_parse(
+    stream: Option<StreamObj>,
+    read_cb: Option<ReadCb>,
+) -> *mut AnchorHistory<MerkleHashOrchard> {
+    let reader = CppStreamReader::from_raw_parts(stream, read_cb.unwrap());
+
+    match AnchorHistory::read(reader) {
+        Ok(parsed) => Box::into_raw(Box::new(parsed)),
+        Err(e) => {
+            error!("Failed to parse Orchard anchor history: {}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_serialize(
+    history: *const AnchorHistory<MerkleHashOrchard>,
+    stream: Option<StreamObj>,
+    write_cb: Option<WriteCb>,
+) -> bool {
+    let history = unsafe {
+        history
+            .as_ref()
+            .expect("Orchard anchor history pointer may not be null.")
+    };
+
+    let writer = CppStreamWriter::from_raw_parts(stream, write_cb.unwrap());
+    match history.write(writer) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_push(
+    history: *mut AnchorHistory<MerkleHashOrchard>,
+    height: u32,
+    root: *const [u8; 32],
+) -> bool {
+    let history = unsafe {
+        history
+            .as_mut()
+            .expect("Orchard anchor history pointer may not be null.")
+    };
+    let root = unsafe { root.as_ref() }.expect("Root pointer may not be null.");
+
+    match Option::<MerkleHashOrchard>::from(MerkleHashOrchard::from_bytes(root)) {
+        Some(root) => history.push(height, root),
+        None => {
+            error!("Invalid Orchard note commitment tree root.");
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_rewind_to(
+    history: *mut AnchorHistory<MerkleHashOrchard>,
+    height: u32,
+) {
+    let history = unsafe {
+        history
+            .as_mut()
+            .expect("Orchard anchor history pointer may not be null.")
+    };
+
+    history.rewind_to(height);
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_anchor_history_contains(
+    history: *const AnchorHistory<MerkleHashOrchard>,
+    anchor: *const [u8; 32],
+    height_ret: *mut u32,
+) -> bool {
+    let history = unsafe {
+        history
+            .as_ref()
+            .expect("Orchard anchor history pointer may not be null.")
+    };
+    let anchor = unsafe { anchor.as_ref() }.expect("Anchor pointer may not be null.");
+
+    let height = Option::<MerkleHashOrchard>::from(MerkleHashOrchard::from_bytes(anchor))
+        .and_then(|anchor| history.contains(&anchor));
+    match (height, unsafe { height_ret.as_mut() }) {
+        (Some(height), Some(height_ret)) => {
+            *height_ret = height;
+            true
+        }
+        (Some(_), None) => true,
+        (None, _) => false,
+    }
+}
+
+//
+// Operations on checkpointed Orchard Merkle frontiers.
+//
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_new(
+    frontier: *const bridgetree::Frontier<MerkleHashOrchard, MERKLE_DEPTH>,
+    max_checkpoints: usize,
+) -> *mut DeepFrontier<MerkleHashOrchard> {
+    let frontier = unsafe {
+        frontier
+            .as_ref()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    Box::into_raw(Box::new(DeepFrontier::new(
+        frontier.clone(),
+        max_checkpoints,
+    )))
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_free(frontier: *mut DeepFrontier<MerkleHashOrchard>) {
+    if !frontier.is_null() {
+        drop(unsafe { Box::from_raw(frontier) });
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_parse(
+    stream: Option<StreamObj>,
+    read_cb: Option<ReadCb>,
+) -> *mut DeepFrontier<MerkleHashOrchard> {
+    let reader = CppStreamReader::from_raw_parts(stream, read_cb.unwrap());
+
+    match DeepFrontier::read(reader) {
+        Ok(parsed) => Box::into_raw(Box::new(parsed)),
+        Err(e) => {
+            error!("Failed to parse checkpointed Orchard frontier: {}", e);
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_serialize(
+    frontier: *const DeepFrontier<MerkleHashOrchard>,
+    stream: Option<StreamObj>,
+    write_cb: Option<WriteCb>,
+) -> bool {
+    let frontier = unsafe {
+        frontier
+            .as_ref()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    let writer = CppStreamWriter::from_raw_parts(stream, write_cb.unwrap());
+    match frontier.write(writer) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_current(
+    frontier: *const DeepFrontier<MerkleHashOrchard>,
+) -> *mut bridgetree::Frontier<MerkleHashOrchard, MERKLE_DEPTH> {
+    let frontier = unsafe {
+        frontier
+            .as_ref()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    Box::into_raw(Box::new(frontier.frontier().clone()))
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_append_bundle(
+    frontier: *mut DeepFrontier<MerkleHashOrchard>,
+    bundle: *const orchard::Bundle<Authorized, Amount>,
+) -> bool {
+    let frontier = unsafe {
+        frontier
+            .as_mut()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+    if let Some(bundle) = unsafe { bundle.as_ref() } {
+        for action in bundle.actions().iter() {
+            if !frontier.append(&MerkleHashOrchard::from_cmx(action.cmx())) {
+                error!("Orchard note commitment tree is full.");
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_checkpoint(
+    frontier: *mut DeepFrontier<MerkleHashOrchard>,
+    height: u32,
+) -> bool {
+    let frontier = unsafe {
+        frontier
+            .as_mut()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    frontier.checkpoint(height)
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_rewind(
+    frontier: *mut DeepFrontier<MerkleHashOrchard>,
+    height: u32,
+) -> bool {
+    let frontier = unsafe {
+        frontier
+            .as_mut()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    frontier.rewind(height)
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_prune_checkpoints(
+    frontier: *mut DeepFrontier<MerkleHashOrchard>,
+    keep: usize,
+) {
+    let frontier = unsafe {
+        frontier
+            .as_mut()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    frontier.prune_checkpoints(keep);
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_deep_frontier_root(
+    frontier: *const DeepFrontier<MerkleHashOrchard>,
+    root_ret: *mut [u8; 32],
+) {
+    let frontier = unsafe {
+        frontier
+            .as_ref()
+            .expect("Orchard note commitment tree pointer may not be null.")
+    };
+
+    let root_ret = unsafe {
+        root_ret
+            .as_mut()
+            .expect("Cannot return to the null pointer.")
+    };
+
+    *root_ret = frontier.root().to_bytes();
+}
@@ -1,7 +1,139 @@
-#[no_mangle]
-pub extern "C" fn zcashd_init_rayon_threadpool() {
+use std::{ffi::CStr, slice};
+
+use libc::c_char;
+use tracing::error;
+
+use crate::{
+    address_ffi::network_from_cstr,
+    block_validation_ffi, builder_ffi,
+    network::{self, ZcashdParams},
+};
+
+/// Returns a builder for a Rayon threadpool with `num_threads` threads (or one per
+/// core if zero), named `{thread_name_prefix}-{i}`.
+///
+/// If `lower_priority` is set, the threads lower their scheduling priority when they
+/// start, so that they yield to zcashd's own threads. This is only supported on Linux,
+/// where the priority of each thread can be set independently; elsewhere it would
+/// lower the priority of the whole process, so it is ignored.
+pub(crate) fn rayon_pool_builder(
+    num_threads: usize,
+    thread_name_prefix: String,
+    lower_priority: bool,
+) -> rayon::ThreadPoolBuilder {
     rayon::ThreadPoolBuilder::new()
-        .thread_name(|i| format!("zc-rayon-{}", i))
-        .build_global()
-        .expect("Only initialized once");
+        .num_threads(num_threads)
+        .thread_name(move |i| format!("{}-{}", thread_name_prefix, i))
+        .start_handler(move |_| {
+            #[cfg(target_os = "linux")]
+            if lower_priority {
+                // A positive niceness lowers the priority of only the calling thread.
+                unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10) };
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = lower_priority;
+        })
+}
+
+/// Initializes the global Rayon threadpool, which all parallelism in the Rust code
+/// uses.
+///
+/// `num_threads` is the number of threads (or zero for one per core), and each thread
+/// is named `{thread_name_prefix}-{i}`. Returns `false` if the prefix is not valid
+/// UTF-8, or if the global threadpool already exists.
+#[no_mangle]
+pub extern "C" fn librustzcash_init_rayon(
+    num_threads: usize,
+    thread_name_prefix: *const c_char,
+    lower_priority: bool,
+) -> bool {
+    if thread_name_prefix.is_null() {
+        return false;
+    }
+    let prefix = match unsafe { CStr::from_ptr(thread_name_prefix) }.to_str() {
+        Ok(prefix) => prefix.to_owned(),
+        Err(_) => return false,
+    };
+
+    match rayon_pool_builder(num_threads, prefix, lower_priority).build_global() {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to initialize the Rayon threadpool: {}", e);
+            false
+        }
+    }
+}
+
+/// Sets the number of threads (or zero for one per core) that Orchard proofs are
+/// created with, unless a build requests otherwise. The proofs are created on a
+/// dedicated threadpool, so that their parallelism is bounded separately from the
+/// global one.
+///
+/// This must be called before the Orchard parameters are loaded, as the proving key is
+/// built on the same threadpool. Returns `false` if it has already been called, or if
+/// the default has already been used.
+#[no_mangle]
+pub extern "C" fn librustzcash_init_orchard_proving(num_threads: usize) -> bool {
+    if builder_ffi::init_proving_threads(num_threads) {
+        true
+    } else {
+        error!("The number of Orchard proving threads cannot be changed once it is used");
+        false
+    }
+}
+
+/// Sets the approximate number of bytes that the shielded bundles queued in a block's
+/// batch validators may take up, beyond which they are validated in an intermediate
+/// flush rather than waiting for the end of the block. zcashd derives this from its
+/// `-dbcache` setting.
+///
+/// The budget is a soft limit: it is checked before each transaction's bundles are
+/// queued, so the queue can exceed it by up to one transaction's bundles.
+///
+/// This must be called before any batch validators are created. Returns `false` if it
+/// has already been called, or if the default has already been used.
+#[no_mangle]
+pub extern "C" fn librustzcash_init_batch_validation(memory_budget: usize) -> bool {
+    if block_validation_ffi::init_memory_budget(memory_budget) {
+        true
+    } else {
+        error!("The batch validation memory budget cannot be changed once it is used");
+        false
+    }
+}
+
+/// Installs the parameters of the network that zcashd is running on, for the Rust code
+/// that depends on them.
+///
+/// `network` is "main", "test" or "regtest". `activation_heights` has an entry for each
+/// network upgrade from Overwinter to NU5 in order, which is negative if the upgrade
+/// never activates. Returns `false` if the arguments are invalid, or if the parameters
+/// have already been installed.
+#[no_mangle]
+pub extern "C" fn librustzcash_init_network_params(
+    network: *const c_char,
+    activation_heights: *const i32,
+    activation_heights_len: usize,
+) -> bool {
+    if network.is_null() {
+        return false;
+    }
+    let network = match network_from_cstr(network) {
+        Some(network) => network,
+        None => return false,
+    };
+    if activation_heights.is_null() {
+        return false;
+    }
+    let heights = unsafe { slice::from_raw_parts(activation_heights, activation_heights_len) };
+    let params = match ZcashdParams::from_zcashd_heights(network, heights) {
+        Some(params) => params,
+        None => return false,
+    };
+
+    if network::init_network_params(params).is_err() {
+        tracing::error!("Network parameters cannot be changed once they are set");
+        return false;
+    }
+    true
 }
@@ -0,0 +1,193 @@
+//! The consensus parameters of the network that zcashd is running on.
+//!
+//! zcashd passes its network and activation heights (which `-nuparams` can override on
+//! regtest) in with `librustzcash_init_network_params` at startup, so that Rust code
+//! that depends on them agrees with the C++ side. Until then, the mainnet parameters
+//! are used.
+
+use std::convert::TryFrom;
+
+use once_cell::sync::OnceCell;
+use zcash_address::Network;
+use zcash_primitives::{
+    consensus::{self, BlockHeight, NetworkUpgrade, MAIN_NETWORK, TEST_NETWORK},
+    constants,
+};
+
+/// The number of network upgrades that activation heights are given for.
+pub(crate) const NETWORK_UPGRADE_COUNT: usize = 6;
+
+/// The network upgrades that activation heights are given for, in order.
+pub(crate) const NETWORK_UPGRADES: [NetworkUpgrade; NETWORK_UPGRADE_COUNT] = [
+    NetworkUpgrade::Overwinter,
+    NetworkUpgrade::Sapling,
+    NetworkUpgrade::Blossom,
+    NetworkUpgrade::Heartwood,
+    NetworkUpgrade::Canopy,
+    NetworkUpgrade::Nu5,
+];
+
+/// The consensus parameters of a network, with its activation heights as configured in
+/// zcashd.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct ZcashdParams {
+    network: Network,
+    activation_heights: [Option<BlockHeight>; NETWORK_UPGRADE_COUNT],
+}
+
+impl ZcashdParams {
+    /// Returns the parameters for `network`, where `activation_heights` gives the
+    /// activation height of each of [`NETWORK_UPGRADES`] (or `None` if it never
+    /// activates).
+    pub(crate) fn new(
+        network: Network,
+        activation_heights: [Option<BlockHeight>; NETWORK_UPGRADE_COUNT],
+    ) -> Self {
+        ZcashdParams {
+            network,
+            activation_heights,
+        }
+    }
+
+    /// Returns the parameters for `network` with the activation heights that zcashd
+    /// passes in: one for each of [`NETWORK_UPGRADES`], which is negative if the upgrade
+    /// never activates. Returns `None` if there isn't exactly one height per upgrade.
+    pub(crate) fn from_zcashd_heights(network: Network, heights: &[i32]) -> Option<Self> {
+        if heights.len() != NETWORK_UPGRADE_COUNT {
+            return None;
+        }
+
+        let mut activation_heights = [None; NETWORK_UPGRADE_COUNT];
+        for (parsed, height) in activation_heights.iter_mut().zip(heights) {
+            // A negative height is Consensus::NetworkUpgrade::NO_ACTIVATION_HEIGHT.
+            *parsed = u32::try_from(*height).ok().map(BlockHeight::from_u32);
+        }
+        Some(ZcashdParams::new(network, activation_heights))
+    }
+
+    /// Returns the built-in mainnet parameters.
+    pub(crate) fn mainnet() -> Self {
+        ZcashdParams::built_in(Network::Main, &MAIN_NETWORK)
+    }
+
+    /// Returns the built-in testnet parameters.
+    pub(crate) fn testnet() -> Self {
+        ZcashdParams::built_in(Network::Test, &TEST_NETWORK)
+    }
+
+    fn built_in(network: Network, params: &impl consensus::Parameters) -> Self {
+        let mut activation_heights = [None; NETWORK_UPGRADE_COUNT];
+        for (height, nu) in activation_heights.iter_mut().zip(NETWORK_UPGRADES) {
+            *height = params.activation_height(nu);
+        }
+        ZcashdParams::new(network, activation_heights)
+    }
+
+    /// Returns the network that these parameters are for.
+    pub(crate) fn network(&self) -> Network {
+        self.network
+    }
+}
+
+impl consensus::Parameters for ZcashdParams {
+    fn activation_height(&self, nu: NetworkUpgrade) -> Option<BlockHeight> {
+        NETWORK_UPGRADES
+            .iter()
+            .position(|upgrade| *upgrade == nu)
+            .and_then(|i| self.activation_heights[i])
+    }
+
+    fn coin_type(&self) -> u32 {
+        match self.network {
+            Network::Main => constants::mainnet::COIN_TYPE,
+            Network::Test => constants::testnet::COIN_TYPE,
+            Network::Regtest => constants::regtest::COIN_TYPE,
+        }
+    }
+
+    fn hrp_sapling_extended_spending_key(&self) -> &str {
+        match self.network {
+            Network::Main => constants::mainnet::HRP_SAPLING_EXTENDED_SPENDING_KEY,
+            Network::Test => constants::testnet::HRP_SAPLING_EXTENDED_SPENDING_KEY,
+            Network::Regtest => constants::regtest::HRP_SAPLING_EXTENDED_SPENDING_KEY,
+        }
+    }
+
+    fn hrp_sapling_extended_full_viewing_key(&self) -> &str {
+        match self.network {
+            Network::Main => constants::mainnet::HRP_SAPLING_EXTENDED_FULL_VIEWING_KEY,
+            Network::Test => constants::testnet::HRP_SAPLING_EXTENDED_FULL_VIEWING_KEY,
+            Network::Regtest => constants::regtest::HRP_SAPLING_EXTENDED_FULL_VIEWING_KEY,
+        }
+    }
+
+    fn hrp_sapling_payment_address(&self) -> &str {
+        match self.network {
+            Network::Main => constants::mainnet::HRP_SAPLING_PAYMENT_ADDRESS,
+            Network::Test => constants::testnet::HRP_SAPLING_PAYMENT_ADDRESS,
+            Network::Regtest => constants::regtest::HRP_SAPLING_PAYMENT_ADDRESS,
+        }
+    }
+
+    fn b58_pubkey_address_prefix(&self) -> [u8; 2] {
+        match self.network {
+            Network::Main => constants::mainnet::B58_PUBKEY_ADDRESS_PREFIX,
+            Network::Test => constants::testnet::B58_PUBKEY_ADDRESS_PREFIX,
+            Network::Regtest => constants::regtest::B58_PUBKEY_ADDRESS_PREFIX,
+        }
+    }
+
+    fn b58_script_address_prefix(&self) -> [u8; 2] {
+        match self.network {
+            Network::Main => constants::mainnet::B58_SCRIPT_ADDRESS_PREFIX,
+            Network::Test => constants::testnet::B58_SCRIPT_ADDRESS_PREFIX,
+            Network::Regtest => constants::regtest::B58_SCRIPT_ADDRESS_PREFIX,
+        }
+    }
+}
+
+static NETWORK_PARAMS: OnceCell<ZcashdParams> = OnceCell::new();
+
+/// Installs the parameters of the network that zcashd is running on.
+///
+/// Returns an error if parameters have already been installed.
+pub(crate) fn init_network_params(params: ZcashdParams) -> Result<(), ZcashdParams> {
+    NETWORK_PARAMS.set(params)
+}
+
+/// Returns the parameters of the network that zcashd is running on, or the mainnet
+/// parameters if none have been installed.
+///
+/// Rust code that depends on the network must use these rather than the
+/// zcash_primitives constants.
+pub(crate) fn network_params() -> ZcashdParams {
+    NETWORK_PARAMS
+        .get()
+        .copied()
+        .unwrap_or_else(ZcashdParams::mainnet)
+}
+
+/// Returns the parameters of `network`: those installed by zcashd if they are for
+/// `network`, and otherwise the built-in mainnet or testnet parameters.
+///
+/// Returns `None` for regtest unless zcashd has installed its parameters, as its
+/// activation heights are only known at runtime.
+pub(crate) fn params_for(network: Network) -> Option<ZcashdParams> {
+    select_params(NETWORK_PARAMS.get(), network)
+}
+
+/// Returns the parameters of `network`, given the parameters that zcashd has
+/// `installed` (if any).
+pub(crate) fn select_params(
+    installed: Option<&ZcashdParams>,
+    network: Network,
+) -> Option<ZcashdParams> {
+    match installed {
+        Some(params) if params.network == network => Some(*params),
+        _ => match network {
+            Network::Main => Some(ZcashdParams::mainnet()),
+            Network::Test => Some(ZcashdParams::testnet()),
+            Network::Regtest => None,
+        },
+    }
+}
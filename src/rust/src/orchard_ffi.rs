@@ -9,7 +9,6 @@ use orchard::{
     primitives::redpallas::{self, Binding, SpendAuth},
     Bundle,
 };
-use rand_core::OsRng;
 use tracing::{debug, error};
 use zcash_note_encryption::try_output_recovery_with_ovk;
 use zcash_primitives::transaction::{
@@ -17,6 +16,8 @@ use zcash_primitives::transaction::{
     TxId,
 };
 
+use crate::fault_injection::{should_fail, FaultSite};
+use crate::rng;
 use crate::streams_ffi::{CppStreamReader, CppStreamWriter, ReadCb, StreamObj, WriteCb};
 
 #[no_mangle]
@@ -195,11 +196,11 @@ pub struct BatchValidator {
 }
 
 impl BatchValidator {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         BatchValidator { signatures: vec![] }
     }
 
-    fn add_bundle(&mut self, bundle: &Bundle<Authorized, Amount>, txid: TxId) {
+    pub(crate) fn add_bundle(&mut self, bundle: &Bundle<Authorized, Amount>, txid: TxId) {
         for action in bundle.actions().iter() {
             self.signatures.push(BundleSignature {
                 signature: action
@@ -217,29 +218,42 @@ impl BatchValidator {
     }
 
     fn validate(&self) -> bool {
-        if self.signatures.is_empty() {
-            // An empty batch is always valid, but is not free to run; skip it.
-            return true;
-        }
-
-        let mut validator = redpallas::batch::Verifier::new();
-        for sig in self.signatures.iter() {
-            validator.queue(sig.signature.clone());
+        if should_fail(FaultSite::BatchValidation) {
+            error!("RedPallas batch validation failed: injected failure");
+            return false;
         }
-
-        match validator.verify(OsRng) {
+        match self.verify() {
             Ok(()) => true,
             Err(e) => {
                 error!("RedPallas batch validation failed: {}", e);
-                // TODO: Try sub-batches to figure out which signatures are invalid. We can
-                // postpone this for now:
+                // We don't try sub-batches to figure out which signatures are invalid:
                 // - For per-transaction batching (when adding to the mempool), we don't care
                 //   which signature within the transaction failed.
-                // - For per-block batching, we currently don't care which transaction failed.
+                // - Per-block batching is done by `BatchValidators`, which finds the
+                //   transaction that failed itself.
                 false
             }
         }
     }
+
+    /// Verifies the queued signatures, without logging a failure.
+    pub(crate) fn verify(&self) -> Result<(), impl std::fmt::Display> {
+        if self.signatures.is_empty() {
+            // An empty batch is always valid, but is not free to run; skip it.
+            return Ok(());
+        }
+
+        let mut validator = redpallas::batch::Verifier::new();
+        for sig in self.signatures.iter() {
+            validator.queue(sig.signature.clone());
+        }
+
+        // Failing here would treat a valid bundle (and so possibly a valid block) as
+        // invalid, so this still aborts if no randomness can be obtained. The startup
+        // self-check makes that unlikely to first happen here.
+        let rng = rng::rng().expect("randomness should be available");
+        validator.verify(rng)
+    }
 }
 
 /// Creates a RedPallas batch validation context.
@@ -0,0 +1,109 @@
+//! Batched Sapling Pedersen hashing.
+//!
+//! Each input is hashed by `zcash_primitives`' scalar implementation, so that results
+//! are bit-identical to it, and its windowed generator tables are computed once and
+//! shared by every input. What batching saves is the conversion of each hash's point to
+//! affine coordinates, which costs a field inversion: the points of a chunk of inputs
+//! are normalized together with a single inversion. Chunks are hashed in parallel on
+//! the rayon thread pool.
+
+use group::Curve;
+use rayon::prelude::*;
+use zcash_primitives::{
+    merkle_tree::{HashSer, MerklePath},
+    sapling::{
+        self,
+        pedersen_hash::{pedersen_hash, Personalization},
+    },
+};
+
+/// The number of inputs whose points are normalized together.
+const CHUNK_SIZE: usize = 64;
+
+/// The number of bits of each child that a Merkle tree hash takes.
+const MERKLE_CHILD_BITS: usize = 255;
+
+/// Returns the Pedersen hash of each `(personalization, bits)` input, as the encoding of
+/// the u-coordinate of the resulting point, in the same order as the inputs.
+pub fn pedersen_hash_batch<B: AsRef<[bool]> + Sync>(
+    inputs: &[(Personalization, B)],
+) -> Vec<[u8; 32]> {
+    inputs
+        .par_chunks(CHUNK_SIZE)
+        .flat_map_iter(|chunk| {
+            let points: Vec<jubjub::ExtendedPoint> = chunk
+                .iter()
+                .map(|(personalization, bits)| {
+                    pedersen_hash(*personalization, bits.as_ref().iter().copied()).into()
+                })
+                .collect();
+            let mut affine = vec![jubjub::AffinePoint::identity(); points.len()];
+            jubjub::ExtendedPoint::batch_normalize(&points, &mut affine);
+            affine.into_iter().map(|p| p.get_u().to_bytes())
+        })
+        .collect()
+}
+
+/// The bits of a Merkle tree hash of `lhs` and `rhs`, as `sapling::merkle_hash` takes
+/// them.
+fn merkle_hash_bits(lhs: &[u8; 32], rhs: &[u8; 32]) -> Vec<bool> {
+    fn child_bits(bytes: &[u8; 32]) -> impl Iterator<Item = bool> + '_ {
+        (0..MERKLE_CHILD_BITS).map(move |i| (bytes[i / 8] >> (i % 8)) & 1 == 1)
+    }
+    child_bits(lhs).chain(child_bits(rhs)).collect()
+}
+
+/// Returns the Merkle tree hash of each `(depth, lhs, rhs)` input, as
+/// `sapling::merkle_hash` computes it, in the same order as the inputs.
+pub fn merkle_hash_batch(inputs: &[(usize, [u8; 32], [u8; 32])]) -> Vec<[u8; 32]> {
+    let inputs: Vec<_> = inputs
+        .par_iter()
+        .map(|(depth, lhs, rhs)| {
+            (
+                Personalization::MerkleTree(*depth),
+                merkle_hash_bits(lhs, rhs),
+            )
+        })
+        .collect();
+    pedersen_hash_batch(&inputs)
+}
+
+/// Returns the root of the Sapling note commitment tree that each `(leaf, path)` input
+/// leads to, as `MerklePath::root` computes it, in the same order as the inputs.
+///
+/// The paths are walked together, so that the hashes of every path at each level are
+/// computed as one batch.
+pub fn merkle_path_roots(inputs: &[([u8; 32], MerklePath<sapling::Node>)]) -> Vec<[u8; 32]> {
+    let mut nodes: Vec<[u8; 32]> = inputs.iter().map(|(leaf, _)| *leaf).collect();
+    let depth = inputs
+        .iter()
+        .map(|(_, path)| path.auth_path.len())
+        .max()
+        .unwrap_or(0);
+
+    for level in 0..depth {
+        // The paths that are still being walked at this level.
+        let (walking, hashes): (Vec<usize>, Vec<_>) = inputs
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (_, path))| {
+                let (sibling, node_is_right) = path.auth_path.get(level)?;
+                let mut sibling_bytes = [0; 32];
+                sibling
+                    .write(&mut sibling_bytes[..])
+                    .expect("Node encodings are 32 bytes; qed");
+                Some(if *node_is_right {
+                    (i, (level, sibling_bytes, nodes[i]))
+                } else {
+                    (i, (level, nodes[i], sibling_bytes))
+                })
+            })
+            .unzip();
+
+        for (i, node) in walking.into_iter().zip(merkle_hash_batch(&hashes)) {
+            nodes[i] = node;
+        }
+    }
+
+    nodes
+}
@@ -0,0 +1,177 @@
+//! Scratch buffers for proving, pooled per thread.
+//!
+//! The streamed Sprout prover reads its parameters through a buffer of the same size
+//! for every multi-exponentiation of every proof. Rather than allocating (and zeroing)
+//! each one afresh, buffers are taken from a pool on the thread that uses them, and
+//! returned to it when dropped. Buffers are zeroized when they are returned, so that no
+//! data from one proof is visible to the next.
+//!
+//! The Groth16 witness and assignment vectors of Sapling proofs are allocated inside
+//! `bellman`, which doesn't accept caller-provided buffers, so they can't be pooled
+//! from here.
+//!
+//! Each thread retains at most [`MAX_BUFFERS_PER_THREAD`] buffers totalling at most
+//! [`MAX_RETAINED_BYTES_PER_THREAD`] bytes. [`release`], which is called when the
+//! proving parameters are unloaded, frees the calling thread's pool immediately, and
+//! every other thread's pool the next time that thread uses it.
+
+use std::cell::RefCell;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use zeroize::Zeroize;
+
+/// The most buffers that each thread's pool retains.
+const MAX_BUFFERS_PER_THREAD: usize = 4;
+/// The most bytes of buffer capacity that each thread's pool retains.
+const MAX_RETAINED_BYTES_PER_THREAD: usize = 16 * 1024 * 1024;
+
+/// The number of buffers taken from a pool since startup.
+static HITS: AtomicU64 = AtomicU64::new(0);
+/// The number of buffers allocated because no pooled buffer was large enough.
+static MISSES: AtomicU64 = AtomicU64::new(0);
+/// The capacity of the buffers held by every thread's pool.
+static RETAINED_BYTES: AtomicUsize = AtomicUsize::new(0);
+/// Incremented by [`release`], so that each thread frees its pool when it next uses it.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// A thread's pooled buffers, and the generation they are from.
+struct Pool {
+    generation: u64,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Pool {
+    fn retained(&self) -> usize {
+        self.buffers.iter().map(Vec::capacity).sum()
+    }
+
+    /// Frees the buffers if a release has happened since they were pooled.
+    fn check_generation(&mut self) {
+        let generation = GENERATION.load(Ordering::Relaxed);
+        if self.generation != generation {
+            self.clear();
+            self.generation = generation;
+        }
+    }
+
+    fn clear(&mut self) {
+        RETAINED_BYTES.fetch_sub(self.retained(), Ordering::Relaxed);
+        self.buffers.clear();
+    }
+}
+
+impl Drop for Pool {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+thread_local! {
+    static POOL: RefCell<Pool> = RefCell::new(Pool {
+        generation: GENERATION.load(Ordering::Relaxed),
+        buffers: vec![],
+    });
+}
+
+/// An empty buffer with capacity for at least some number of bytes, which is returned
+/// to the pool of the thread that drops it.
+pub(crate) struct ScratchBuf(Vec<u8>);
+
+impl Deref for ScratchBuf {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for ScratchBuf {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl Drop for ScratchBuf {
+    fn drop(&mut self) {
+        let mut buf = std::mem::take(&mut self.0);
+        buf.zeroize();
+        if buf.capacity() == 0 {
+            return;
+        }
+
+        // The pool is gone if the thread is exiting, in which case the buffer is freed.
+        let _ = POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            pool.check_generation();
+            if pool.buffers.len() < MAX_BUFFERS_PER_THREAD
+                && pool.retained() + buf.capacity() <= MAX_RETAINED_BYTES_PER_THREAD
+            {
+                RETAINED_BYTES.fetch_add(buf.capacity(), Ordering::Relaxed);
+                pool.buffers.push(buf);
+            }
+        });
+    }
+}
+
+/// Returns an empty buffer with capacity for at least `len` bytes, from the calling
+/// thread's pool if it has one that is large enough.
+pub(crate) fn take(len: usize) -> ScratchBuf {
+    let pooled = POOL
+        .try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            pool.check_generation();
+            // Take the smallest buffer that fits, leaving larger ones for larger uses.
+            let (i, _) = pool
+                .buffers
+                .iter()
+                .enumerate()
+                .filter(|(_, buf)| buf.capacity() >= len)
+                .min_by_key(|(_, buf)| buf.capacity())?;
+            let buf = pool.buffers.swap_remove(i);
+            RETAINED_BYTES.fetch_sub(buf.capacity(), Ordering::Relaxed);
+            Some(buf)
+        })
+        .ok()
+        .flatten();
+
+    match pooled {
+        Some(buf) => {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            ScratchBuf(buf)
+        }
+        None => {
+            MISSES.fetch_add(1, Ordering::Relaxed);
+            ScratchBuf(Vec::with_capacity(len))
+        }
+    }
+}
+
+/// Frees the pooled buffers of every thread: those of the calling thread now, and those
+/// of other threads when they next take or return a buffer.
+pub(crate) fn release() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+    let _ = POOL.try_with(|pool| pool.borrow_mut().check_generation());
+}
+
+/// Diagnostic counters for the pooled proving scratch buffers.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProvingScratchStats {
+    /// The number of buffers that were taken from a pool since startup.
+    pub hits: u64,
+    /// The number of buffers that were allocated since startup because no pooled
+    /// buffer was large enough.
+    pub misses: u64,
+    /// The capacity in bytes of the buffers currently held by every thread's pool.
+    pub retained_bytes: u64,
+}
+
+/// Returns the pool's diagnostic counters.
+pub(crate) fn stats() -> ProvingScratchStats {
+    ProvingScratchStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        retained_bytes: RETAINED_BYTES.load(Ordering::Relaxed) as u64,
+    }
+}
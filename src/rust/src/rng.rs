@@ -0,0 +1,193 @@
+//! Randomness for the FFI, drawn from the operating system.
+//!
+//! `OsRng` panics if the operating system can't provide randomness, which aborts
+//! zcashd. The bridges instead obtain their randomness through [`fill_random`] or
+//! [`rng`], which return the error so that it can be reported to the C++ side.
+//!
+//! On regtest, [`set_deterministic_rng`] replaces the operating system's randomness
+//! with ChaCha20 streams derived from a seed, so that failing functional tests can be
+//! reproduced. Randomness drawn inside our dependencies (such as the value commitment
+//! randomness in Sapling proofs) still comes from the operating system.
+
+use std::cell::RefCell;
+use std::num::NonZeroU32;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Mutex,
+};
+
+use once_cell::sync::Lazy;
+use rand::{rngs::StdRng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{Error, OsRng, RngCore};
+use zcash_address::Network;
+
+use crate::fault_injection::{should_fail, FaultSite};
+
+/// The seed that deterministic randomness is derived from, if it is enabled.
+#[derive(Clone, Copy)]
+struct DeterministicSeed {
+    seed: [u8; 32],
+    /// Incremented each time the seed is set, so that threads restart their streams.
+    generation: u64,
+    /// The stream that the next thread to draw randomness will use.
+    next_stream: u64,
+}
+
+static DETERMINISTIC_ENABLED: AtomicBool = AtomicBool::new(false);
+static DETERMINISTIC_SEED: Lazy<Mutex<Option<DeterministicSeed>>> = Lazy::new(|| Mutex::new(None));
+
+thread_local! {
+    /// This thread's deterministic stream, and the generation of the seed it is from.
+    static DETERMINISTIC_RNG: RefCell<Option<(u64, ChaCha20Rng)>> = RefCell::new(None);
+}
+
+fn deterministic_stream(seed: [u8; 32], stream: u64) -> ChaCha20Rng {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    rng.set_stream(stream);
+    rng
+}
+
+/// Replaces the operating system's randomness with streams derived from `seed`, as
+/// long as `network` is regtest.
+///
+/// The calling thread is given the first stream, and every other thread is given the
+/// next unused stream when it first draws randomness. Setting the seed again restarts
+/// every thread's stream.
+///
+/// In tests, only the calling thread draws from the seed, so that the tests running in
+/// parallel with it keep the operating system's randomness.
+pub(crate) fn set_deterministic_rng(network: Network, seed: [u8; 32]) -> bool {
+    if network != Network::Regtest {
+        return false;
+    }
+
+    let mut state = DETERMINISTIC_SEED.lock().unwrap();
+    let generation = state.map_or(0, |state| state.generation + 1);
+    *state = Some(DeterministicSeed {
+        seed,
+        generation,
+        next_stream: 1,
+    });
+    DETERMINISTIC_RNG.with(|rng| {
+        *rng.borrow_mut() = Some((generation, deterministic_stream(seed, 0)));
+    });
+    DETERMINISTIC_ENABLED.store(true, Ordering::Release);
+    true
+}
+
+/// Restores the operating system's randomness, so that tests leave no deterministic
+/// seed behind for the tests that run after them.
+#[cfg(test)]
+pub(crate) fn clear_deterministic_rng() {
+    let mut state = DETERMINISTIC_SEED.lock().unwrap();
+    DETERMINISTIC_ENABLED.store(false, Ordering::Release);
+    *state = None;
+    DETERMINISTIC_RNG.with(|rng| *rng.borrow_mut() = None);
+}
+
+/// Fills `buf` from this thread's deterministic stream, returning `false` if
+/// deterministic randomness is not enabled.
+fn fill_deterministic(buf: &mut [u8]) -> bool {
+    if !DETERMINISTIC_ENABLED.load(Ordering::Acquire) {
+        return false;
+    }
+
+    let mut state = DETERMINISTIC_SEED.lock().unwrap();
+    let state = match state.as_mut() {
+        Some(state) => state,
+        None => return false,
+    };
+    DETERMINISTIC_RNG.with(|rng| {
+        let mut rng = rng.borrow_mut();
+        if !matches!(&*rng, Some((generation, _)) if *generation == state.generation) {
+            if cfg!(test) {
+                return false;
+            }
+            *rng = Some((
+                state.generation,
+                deterministic_stream(state.seed, state.next_stream),
+            ));
+            state.next_stream += 1;
+        }
+        rng.as_mut().unwrap().1.fill_bytes(buf);
+        true
+    })
+}
+
+/// A source of randomness that can fail.
+#[cfg(test)]
+pub(crate) type RandomSource = fn(&mut [u8]) -> Result<(), Error>;
+
+#[cfg(test)]
+thread_local! {
+    /// Replaces the operating system's randomness on this thread, so that tests can
+    /// simulate it failing.
+    pub(crate) static RANDOM_SOURCE: std::cell::Cell<Option<RandomSource>> =
+        std::cell::Cell::new(None);
+}
+
+/// Fills `buf` with randomness from the operating system (or from the deterministic
+/// stream, if enabled).
+pub(crate) fn fill_random(buf: &mut [u8]) -> Result<(), Error> {
+    if should_fail(FaultSite::Randomness) {
+        return Err(NonZeroU32::new(Error::CUSTOM_START).unwrap().into());
+    }
+
+    #[cfg(test)]
+    if let Some(source) = RANDOM_SOURCE.with(|source| source.get()) {
+        return source(buf);
+    }
+
+    if fill_deterministic(buf) {
+        return Ok(());
+    }
+    OsRng.try_fill_bytes(buf)
+}
+
+/// Returns a CSPRNG seeded from the operating system's randomness.
+///
+/// Once seeded the returned RNG can't fail, so it can be passed to APIs that don't
+/// expect their RNG to.
+pub(crate) fn rng() -> Result<StdRng, Error> {
+    let mut seed = <StdRng as SeedableRng>::Seed::default();
+    fill_random(&mut seed)?;
+    Ok(StdRng::from_seed(seed))
+}
+
+/// Errors that the startup self-test of the operating system's randomness can detect.
+#[derive(Debug)]
+pub(crate) enum SelfCheckError {
+    /// No randomness could be drawn.
+    Source(Error),
+    /// A sample was all zeroes.
+    Zero,
+    /// Two samples were the same.
+    Repeated,
+}
+
+impl std::fmt::Display for SelfCheckError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SelfCheckError::Source(e) => write!(f, "Couldn't obtain randomness: {}", e),
+            SelfCheckError::Zero => write!(f, "The randomness was all zeroes"),
+            SelfCheckError::Repeated => write!(f, "The randomness repeated itself"),
+        }
+    }
+}
+
+/// Draws two samples from the operating system's randomness, and checks that neither
+/// is all zeroes and that they differ.
+pub(crate) fn selfcheck() -> Result<(), SelfCheckError> {
+    let mut samples = [[0; 32]; 2];
+    for sample in samples.iter_mut() {
+        fill_random(sample).map_err(SelfCheckError::Source)?;
+        if sample.iter().all(|b| *b == 0) {
+            return Err(SelfCheckError::Zero);
+        }
+    }
+    if samples[0] == samples[1] {
+        return Err(SelfCheckError::Repeated);
+    }
+    Ok(())
+}
@@ -19,17 +19,19 @@
 // See https://github.com/rust-lang/rfcs/pull/2585 for more background.
 #![allow(clippy::not_unsafe_ptr_arg_deref)]
 
-use bellman::groth16::{Parameters, PreparedVerifyingKey, Proof};
+use bellman::groth16::{PreparedVerifyingKey, VerifyingKey};
 use blake2s_simd::Params as Blake2sParams;
 use bls12_381::Bls12;
 use group::{cofactor::CofactorGroup, GroupEncoding};
 use libc::{c_uchar, size_t};
-use rand_core::{OsRng, RngCore};
-use std::fs::File;
-use std::io::BufReader;
+use once_cell::sync::{Lazy, OnceCell};
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 use std::slice;
-use std::sync::Once;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
 use subtle::CtOption;
 use tracing::{error, info};
 
@@ -48,11 +50,8 @@ use zcash_primitives::{
     constants::{CRH_IVK_PERSONALIZATION, PROOF_GENERATION_KEY_GENERATOR, SPENDING_KEY_GENERATOR},
     merkle_tree::MerklePath,
     sapling::{
-        self,
-        keys::FullViewingKey,
-        note_encryption::sapling_ka_agree,
-        redjubjub::{self, Signature},
-        Diversifier, Note, PaymentAddress, ProofGenerationKey, Rseed, ViewingKey,
+        self, keys::FullViewingKey, note_encryption::sapling_ka_agree, redjubjub, Diversifier,
+        Note, PaymentAddress, ProofGenerationKey, Rseed, ViewingKey,
     },
     sapling::{merkle_hash, spend_sig},
     transaction::components::Amount,
@@ -60,43 +59,77 @@ use zcash_primitives::{
 };
 use zcash_proofs::{
     circuit::sapling::TREE_DEPTH as SAPLING_TREE_DEPTH,
-    load_parameters,
     sapling::{SaplingProvingContext, SaplingVerificationContext},
     sprout,
 };
 
+use crate::block_validation_ffi::{SaplingBindingSig, SaplingOutput, SaplingSpend};
+use crate::fault_injection::{should_fail, FaultSite};
+use crate::fetch_params::FetchError;
+use crate::groth16_params::{LazyProvingParams, ParamsError, ParamsPaths, Progress, ProvingParams};
+use crate::proving_scratch::ProvingScratchStats;
+
 mod blake2b;
 mod ed25519;
+mod fault_injection;
+mod fetch_params;
+mod groth16_params;
 mod metrics_ffi;
 mod streams_ffi;
 mod tracing_ffi;
 mod zcashd_orchard;
 
 mod address_ffi;
+mod auth_data_tree;
+mod block_validation_ffi;
 mod builder_ffi;
+pub mod history;
 mod history_ffi;
-mod incremental_merkle_tree;
+pub mod incremental_merkle_tree;
 mod incremental_merkle_tree_ffi;
 mod init_ffi;
+mod network;
 mod orchard_ffi;
 mod orchard_keys_ffi;
+pub mod pedersen_hash;
+mod proving_scratch;
+mod rng;
+mod sighash_legacy;
+mod sprout_builder;
+mod sprout_ffi;
+mod sprout_prover;
+mod sprout_tree;
+mod test_vectors;
+mod transaction_encoding;
 mod transaction_ffi;
 mod unified_keys_ffi;
 mod wallet;
+mod wallet_scanner;
 mod zip339_ffi;
 
 mod test_harness_ffi;
+#[cfg(any(test, feature = "test-dependencies"))]
+mod test_history_ffi;
+#[cfg(any(test, feature = "test-dependencies"))]
+mod test_orchard_ffi;
+#[cfg(any(test, feature = "test-dependencies"))]
+mod test_wallet_ffi;
 
 #[cfg(test)]
 mod tests;
 
-static PROOF_PARAMETERS_LOADED: Once = Once::new();
+static PROOF_PARAMETERS_LOADED: OnceCell<()> = OnceCell::new();
+/// Paths set with `librustzcash_set_params_paths`, which take precedence over the paths
+/// passed to `librustzcash_init_zksnark_params`.
+static PARAMS_PATHS: Lazy<Mutex<Option<ParamsPaths>>> = Lazy::new(|| Mutex::new(None));
 static mut SAPLING_SPEND_VK: Option<PreparedVerifyingKey<Bls12>> = None;
 static mut SAPLING_OUTPUT_VK: Option<PreparedVerifyingKey<Bls12>> = None;
 static mut SPROUT_GROTH16_VK: Option<PreparedVerifyingKey<Bls12>> = None;
+static mut SPROUT_GROTH16_BATCH_VK: Option<VerifyingKey<Bls12>> = None;
 
-static mut SAPLING_SPEND_PARAMS: Option<Parameters<Bls12>> = None;
-static mut SAPLING_OUTPUT_PARAMS: Option<Parameters<Bls12>> = None;
+static mut SAPLING_PROVING_PARAMS: Option<LazyProvingParams> = None;
+/// The number of Sapling proving contexts that have not been freed.
+static SAPLING_PROVING_CONTEXTS: AtomicUsize = AtomicUsize::new(0);
 static mut SPROUT_GROTH16_PARAMS_PATH: Option<PathBuf> = None;
 
 static mut ORCHARD_PK: Option<orchard::circuit::ProvingKey> = None;
@@ -120,8 +153,91 @@ fn fixed_scalar_mult(from: &[u8; 32], p_g: &jubjub::SubgroupPoint) -> jubjub::Su
     p_g * f
 }
 
-/// Loads the zk-SNARK parameters into memory and saves paths as necessary.
+/// Parses a path from the caller, which is bytes on Unix and UTF-16 on Windows. Returns
+/// `None` if `path` is null.
+#[cfg(not(target_os = "windows"))]
+fn native_path(path: *const u8, path_len: usize) -> Option<PathBuf> {
+    if path.is_null() {
+        return None;
+    }
+    let path = unsafe { slice::from_raw_parts(path, path_len) };
+    Some(PathBuf::from(OsStr::from_bytes(path)))
+}
+
+/// Parses a path from the caller, which is bytes on Unix and UTF-16 on Windows. Returns
+/// `None` if `path` is null.
+#[cfg(target_os = "windows")]
+fn native_path(path: *const u16, path_len: usize) -> Option<PathBuf> {
+    if path.is_null() {
+        return None;
+    }
+    let path = unsafe { slice::from_raw_parts(path, path_len) };
+    Some(PathBuf::from(OsString::from_wide(path)))
+}
+
+/// Sets the paths that `librustzcash_init_zksnark_params` will load the parameters
+/// from, instead of the paths that it is given. The paths are in the platform's native
+/// encoding, as for `librustzcash_init_zksnark_params`, and are not checked until the
+/// parameters are loaded. `sprout_path` may be null.
+///
+/// Returns `false` if either Sapling path is null, or if the parameters have already
+/// been loaded.
+#[no_mangle]
+pub extern "C" fn librustzcash_set_params_paths(
+    #[cfg(not(target_os = "windows"))] spend_path: *const u8,
+    #[cfg(target_os = "windows")] spend_path: *const u16,
+    spend_path_len: usize,
+    #[cfg(not(target_os = "windows"))] output_path: *const u8,
+    #[cfg(target_os = "windows")] output_path: *const u16,
+    output_path_len: usize,
+    #[cfg(not(target_os = "windows"))] sprout_path: *const u8,
+    #[cfg(target_os = "windows")] sprout_path: *const u16,
+    sprout_path_len: usize,
+) -> bool {
+    let paths = match (
+        native_path(spend_path, spend_path_len),
+        native_path(output_path, output_path_len),
+    ) {
+        (Some(spend), Some(output)) => ParamsPaths {
+            spend,
+            output,
+            sprout: native_path(sprout_path, sprout_path_len),
+        },
+        _ => {
+            error!("Sapling parameters paths must not be null");
+            return false;
+        }
+    };
+
+    // Holding the lock excludes a concurrent librustzcash_init_zksnark_params.
+    let mut params_paths = PARAMS_PATHS.lock().unwrap();
+    if PROOF_PARAMETERS_LOADED.get().is_some() {
+        error!("Parameter paths cannot be changed after the parameters are loaded");
+        return false;
+    }
+    *params_paths = Some(paths);
+    true
+}
+
+/// How often `librustzcash_init_zksnark_params` reports progress through each file.
+const PARAMS_PROGRESS_INTERVAL: u64 = 16 * 1024 * 1024;
+
+/// Loads the zk-SNARK verifying keys into memory and saves paths as necessary.
 /// Only called once.
+///
+/// The Sapling proving parameters are not loaded until they are first needed to create
+/// a proof, or `librustzcash_sapling_preload_proving_params` is called.
+///
+/// If `librustzcash_set_params_paths` has been called, the paths it was given are used
+/// instead of the paths passed here.
+///
+/// If `progress_cb` is provided, it is called with `(file_index, bytes_done,
+/// bytes_total)` as the files are read and hashed. Returns `false` if `progress_cb`
+/// returned `false` to cancel loading, in which case nothing is loaded and this may be
+/// called again.
+///
+/// The Sapling verifying keys embedded in the library are used unless `force_file_vks`
+/// is `true`, and the Sapling parameter files are only checked against them if present.
 #[no_mangle]
 pub extern "C" fn librustzcash_init_zksnark_params(
     #[cfg(not(target_os = "windows"))] spend_path: *const u8,
@@ -133,8 +249,11 @@ pub extern "C" fn librustzcash_init_zksnark_params(
     #[cfg(not(target_os = "windows"))] sprout_path: *const u8,
     #[cfg(target_os = "windows")] sprout_path: *const u16,
     sprout_path_len: usize,
-) {
-    PROOF_PARAMETERS_LOADED.call_once(|| {
+    progress_cb: Option<unsafe extern "C" fn(u32, u64, u64) -> bool>,
+    force_file_vks: bool,
+) -> bool {
+    let params_paths = PARAMS_PATHS.lock().unwrap();
+    let res = PROOF_PARAMETERS_LOADED.get_or_try_init(|| {
         #[cfg(not(target_os = "windows"))]
         let (spend_path, output_path, sprout_path) = {
             (
@@ -165,35 +284,201 @@ pub extern "C" fn librustzcash_init_zksnark_params(
             )
         };
 
-        let (spend_path, output_path, sprout_path) = (
-            Path::new(&spend_path),
-            Path::new(&output_path),
-            sprout_path.as_ref().map(Path::new),
-        );
+        let (spend_path, output_path, sprout_path) = match params_paths.as_ref() {
+            Some(paths) => (
+                paths.spend.as_path(),
+                paths.output.as_path(),
+                paths.sprout.as_deref(),
+            ),
+            None => (
+                Path::new(&spend_path),
+                Path::new(&output_path),
+                sprout_path.as_ref().map(Path::new),
+            ),
+        };
 
-        // Load params
-        let params = load_parameters(spend_path, output_path, sprout_path);
+        // Load verifying keys
+        let mut report = |file_index: u32, bytes_done: u64, bytes_total: u64| match progress_cb {
+            Some(cb) => unsafe { cb(file_index, bytes_done, bytes_total) },
+            None => true,
+        };
+        let mut progress = Progress::new(PARAMS_PROGRESS_INTERVAL, &mut report);
+        let vks = match groth16_params::load_verifying_keys(
+            spend_path,
+            output_path,
+            sprout_path,
+            force_file_vks,
+            &mut progress,
+        ) {
+            Ok(vks) => vks,
+            Err(ParamsError::Cancelled) => return Err(ParamsError::Cancelled),
+            Err(e) => panic!("Couldn't load zk-SNARK verifying keys: {}", e),
+        };
+
+        if !force_file_vks {
+            info!(target: "main", "Using the Sapling verifying keys built into zcashd");
+        }
 
         // Generate Orchard parameters.
         info!(target: "main", "Loading Orchard parameters");
-        let orchard_pk = orchard::circuit::ProvingKey::build();
+        let orchard_pk = builder_ffi::on_proving_pool(0, orchard::circuit::ProvingKey::build);
         let orchard_vk = orchard::circuit::VerifyingKey::build();
 
         // Caller is responsible for calling this function once, so
         // these global mutations are safe.
         unsafe {
-            SAPLING_SPEND_PARAMS = Some(params.spend_params);
-            SAPLING_OUTPUT_PARAMS = Some(params.output_params);
+            SAPLING_PROVING_PARAMS = Some(LazyProvingParams::new(
+                spend_path.to_owned(),
+                output_path.to_owned(),
+            ));
             SPROUT_GROTH16_PARAMS_PATH = sprout_path.map(|p| p.to_owned());
 
-            SAPLING_SPEND_VK = Some(params.spend_vk);
-            SAPLING_OUTPUT_VK = Some(params.output_vk);
-            SPROUT_GROTH16_VK = params.sprout_vk;
+            SAPLING_SPEND_VK = Some(vks.spend_vk);
+            SAPLING_OUTPUT_VK = Some(vks.output_vk);
+            SPROUT_GROTH16_VK = vks.sprout_vk;
+            SPROUT_GROTH16_BATCH_VK = vks.sprout_batch_vk;
 
             ORCHARD_PK = Some(orchard_pk);
             ORCHARD_VK = Some(orchard_vk);
         }
+
+        Ok(())
     });
+
+    match res {
+        Ok(()) => true,
+        Err(e) => {
+            info!(target: "main", "{}", e);
+            false
+        }
+    }
+}
+
+/// `librustzcash_fetch_params` result: every file is present with the published hash.
+const FETCH_PARAMS_OK: u32 = 0;
+/// `librustzcash_fetch_params` result: no mirror could provide a file.
+const FETCH_PARAMS_NETWORK_ERROR: u32 = 1;
+/// `librustzcash_fetch_params` result: a downloaded file had the wrong hash.
+const FETCH_PARAMS_HASH_MISMATCH: u32 = 2;
+/// `librustzcash_fetch_params` result: a local file operation failed.
+const FETCH_PARAMS_IO_ERROR: u32 = 3;
+/// `librustzcash_fetch_params` result: an existing file has the wrong hash.
+const FETCH_PARAMS_EXISTING_INVALID: u32 = 4;
+/// `librustzcash_fetch_params` result: `progress_cb` cancelled the download.
+const FETCH_PARAMS_CANCELLED: u32 = 5;
+
+/// Downloads any of the parameter files that are missing from `dest_dir` over HTTPS,
+/// checking them against their published hashes before moving them into place. The
+/// directory is created if necessary.
+///
+/// If `progress_cb` is provided, it is called with `(file_index, bytes_done,
+/// bytes_total)` during each download, and may return `false` to cancel.
+#[no_mangle]
+pub extern "C" fn librustzcash_fetch_params(
+    #[cfg(not(target_os = "windows"))] dest_dir: *const u8,
+    #[cfg(target_os = "windows")] dest_dir: *const u16,
+    dest_dir_len: usize,
+    progress_cb: Option<unsafe extern "C" fn(u32, u64, u64) -> bool>,
+) -> u32 {
+    #[cfg(not(target_os = "windows"))]
+    let dest_dir = OsStr::from_bytes(unsafe { slice::from_raw_parts(dest_dir, dest_dir_len) });
+
+    #[cfg(target_os = "windows")]
+    let dest_dir = OsString::from_wide(unsafe { slice::from_raw_parts(dest_dir, dest_dir_len) });
+
+    let mut report = |file_index: u32, bytes_done: u64, bytes_total: u64| match progress_cb {
+        Some(cb) => unsafe { cb(file_index, bytes_done, bytes_total) },
+        None => true,
+    };
+    match fetch_params::fetch_params(
+        Path::new(&dest_dir),
+        fetch_params::PARAMS_FILES,
+        fetch_params::DEFAULT_MIRRORS,
+        &mut report,
+    ) {
+        Ok(()) => FETCH_PARAMS_OK,
+        Err(e) => {
+            error!("{}", e);
+            match e {
+                FetchError::Network(_) => FETCH_PARAMS_NETWORK_ERROR,
+                FetchError::HashMismatch(_) => FETCH_PARAMS_HASH_MISMATCH,
+                FetchError::Io(..) => FETCH_PARAMS_IO_ERROR,
+                FetchError::ExistingInvalid(_) => FETCH_PARAMS_EXISTING_INVALID,
+                FetchError::Cancelled => FETCH_PARAMS_CANCELLED,
+            }
+        }
+    }
+}
+
+/// Returns the Sapling proving parameters, loading them from disk if necessary.
+fn sapling_proving_params() -> Result<Arc<ProvingParams>, ParamsError> {
+    unsafe { SAPLING_PROVING_PARAMS.as_ref() }
+        .expect("parameters should have been initialized")
+        .get()
+}
+
+/// Returns whether the Sapling proving parameters have been loaded into memory.
+#[no_mangle]
+pub extern "C" fn librustzcash_sapling_proving_params_loaded() -> bool {
+    unsafe { SAPLING_PROVING_PARAMS.as_ref() }.map_or(false, |params| params.is_loaded())
+}
+
+/// Returns the number of bytes that the loaded Sapling proving parameters hold in
+/// memory, or 0 if they are not loaded.
+#[no_mangle]
+pub extern "C" fn librustzcash_sapling_proving_params_memory_usage() -> usize {
+    unsafe { SAPLING_PROVING_PARAMS.as_ref() }.map_or(0, |params| params.memory_usage())
+}
+
+/// Drops the Sapling proving parameters from memory, along with the pooled proving
+/// scratch buffers. The parameters are loaded again when they are next needed to
+/// create a proof. The verifying keys are never unloaded.
+///
+/// Returns `false`, leaving the parameters loaded, if any Sapling proving context has
+/// not been freed.
+#[no_mangle]
+pub extern "C" fn librustzcash_unload_proving_params() -> bool {
+    if SAPLING_PROVING_CONTEXTS.load(Ordering::SeqCst) != 0 {
+        error!("Can't unload the Sapling proving parameters while they are in use");
+        return false;
+    }
+    // Proofs hold their own reference to the parameters, so a context created after the
+    // check still works, and reloads the parameters if it needs them.
+    if let Some(params) = unsafe { SAPLING_PROVING_PARAMS.as_ref() } {
+        params.unload();
+    }
+    proving_scratch::release();
+    true
+}
+
+/// Writes the diagnostic counters of the pooled proving scratch buffers to `stats_ret`.
+#[no_mangle]
+pub extern "C" fn librustzcash_proving_scratch_stats(stats_ret: *mut ProvingScratchStats) {
+    if let Some(stats_ret) = unsafe { stats_ret.as_mut() } {
+        *stats_ret = proving_scratch::stats();
+    }
+}
+
+/// Returns the number of Sapling proving contexts that have been created and not yet
+/// freed.
+#[no_mangle]
+pub extern "C" fn librustzcash_sapling_proving_ctx_count() -> usize {
+    SAPLING_PROVING_CONTEXTS.load(Ordering::SeqCst)
+}
+
+/// Loads the Sapling proving parameters into memory now, instead of when the first
+/// Sapling proof is created.
+///
+/// Returns `false` if the parameters could not be loaded.
+#[no_mangle]
+pub extern "C" fn librustzcash_sapling_preload_proving_params() -> bool {
+    match sapling_proving_params() {
+        Ok(_) => true,
+        Err(e) => {
+            error!("Failed to load Sapling proving parameters: {}", e);
+            false
+        }
+    }
 }
 
 /// Writes the "uncommitted" note value for empty leaves of the Merkle tree.
@@ -209,6 +494,30 @@ pub extern "C" fn librustzcash_tree_uncommitted(result: *mut [c_uchar; 32]) {
     *result = tmp;
 }
 
+/// Writes the root of an empty Sapling note commitment subtree of the given depth,
+/// where depth 0 is the "uncommitted" leaf value.
+///
+/// Returns `false` without writing to `result` if `depth` is larger than 32.
+#[no_mangle]
+pub extern "C" fn librustzcash_sapling_empty_root(
+    depth: size_t,
+    result: *mut [c_uchar; 32],
+) -> bool {
+    match u8::try_from(depth)
+        .ok()
+        .and_then(incremental_merkle_tree::sapling_empty_root)
+    {
+        Some(root) => {
+            // Should be okay, caller is responsible for ensuring the pointer
+            // is a valid pointer to 32 bytes that can be mutated.
+            let result = unsafe { &mut *result };
+            *result = root;
+            true
+        }
+        None => false,
+    }
+}
+
 /// Computes a merkle tree hash for a given depth. The `depth` parameter should
 /// not be larger than 62.
 ///
@@ -233,6 +542,38 @@ pub extern "C" fn librustzcash_merkle_hash(
     *result = tmp;
 }
 
+/// Computes `count` merkle tree hashes together, which is faster than computing them
+/// one at a time. The hash of `depths[i]`, `a[i]` and `b[i]` is placed in `results[i]`,
+/// with the same requirements on each as `librustzcash_merkle_hash` has.
+///
+/// `depths`, `a`, `b` and `results` must each point to `count` elements.
+#[no_mangle]
+pub extern "C" fn librustzcash_merkle_hash_batch(
+    count: size_t,
+    depths: *const size_t,
+    a: *const [c_uchar; 32],
+    b: *const [c_uchar; 32],
+    results: *mut [c_uchar; 32],
+) {
+    if count == 0 {
+        return;
+    }
+
+    // Should be okay, because caller is responsible for ensuring the pointers are valid
+    // pointers to `count` elements.
+    let depths = unsafe { slice::from_raw_parts(depths, count) };
+    let a = unsafe { slice::from_raw_parts(a, count) };
+    let b = unsafe { slice::from_raw_parts(b, count) };
+    let results = unsafe { slice::from_raw_parts_mut(results, count) };
+
+    let inputs: Vec<_> = depths
+        .iter()
+        .zip(a.iter().zip(b.iter()))
+        .map(|(depth, (a, b))| (*depth, *a, *b))
+        .collect();
+    results.copy_from_slice(&pedersen_hash::merkle_hash_batch(&inputs));
+}
+
 #[no_mangle] // ToScalar
 pub extern "C" fn librustzcash_to_scalar(input: *const [c_uchar; 64], result: *mut [c_uchar; 32]) {
     // Should be okay, because caller is responsible for ensuring
@@ -334,17 +675,22 @@ fn test_gen_r() {
 }
 
 /// Generate uniformly random scalar in Jubjub. The result is of length 32.
+///
+/// Returns false if no randomness could be obtained.
 #[no_mangle]
-pub extern "C" fn librustzcash_sapling_generate_r(result: *mut [c_uchar; 32]) {
+pub extern "C" fn librustzcash_sapling_generate_r(result: *mut [c_uchar; 32]) -> bool {
     // create random 64 byte buffer
-    let mut rng = OsRng;
     let mut buffer = [0u8; 64];
-    rng.fill_bytes(&mut buffer);
+    if let Err(e) = rng::fill_random(&mut buffer) {
+        error!("Couldn't obtain randomness: {}", e);
+        return false;
+    }
 
     // reduce to uniform value
     let r = jubjub::Scalar::from_bytes_wide(&buffer);
     let result = unsafe { &mut *result };
     *result = r.to_bytes();
+    true
 }
 
 // Private utility function to get Note from C parameters
@@ -497,6 +843,47 @@ pub extern "C" fn librustzcash_sapling_ka_agree(
     true
 }
 
+/// Computes \[ivk\] \[8\] epk for some 32-byte point epk and each of the `ivks_len`
+/// 32-byte Fs at `ivks`, as `librustzcash_sapling_ka_agree` does for each of them.
+///
+/// This is faster for the many keys that trial decryption agrees each output's
+/// ephemeral key with, as it prepares each key once across calls. If epk or any key
+/// is invalid, returns false. Otherwise, the result for each key is written to the
+/// 32-byte buffer at the same position in `results`.
+#[no_mangle]
+pub extern "C" fn librustzcash_sapling_ka_agree_ivks(
+    zip216_enabled: bool,
+    epk: *const [c_uchar; 32],
+    ivks: *const [c_uchar; 32],
+    ivks_len: size_t,
+    results: *mut [c_uchar; 32],
+) -> bool {
+    // Deserialize epk
+    let epk = match de_ct(if zip216_enabled {
+        jubjub::ExtendedPoint::from_bytes(unsafe { &*epk })
+    } else {
+        jubjub::AffinePoint::from_bytes_pre_zip216_compatibility(unsafe { *epk }).map(|p| p.into())
+    }) {
+        Some(p) => p,
+        None => return false,
+    };
+    if ivks_len == 0 {
+        return true;
+    }
+    let ivks = unsafe { slice::from_raw_parts(ivks, ivks_len) };
+
+    match wallet_scanner::sapling_ka_agree_ivks(&wallet_scanner::SAPLING_KEYS, &epk, ivks) {
+        Some(kas) => {
+            let results = unsafe { slice::from_raw_parts_mut(results, ivks.len()) };
+            for (result, ka) in results.iter_mut().zip(kas) {
+                *result = ka.to_bytes();
+            }
+            true
+        }
+        None => false,
+    }
+}
+
 /// Compute g_d = GH(diversifier) and returns false if the diversifier is
 /// invalid. Computes \[esk\] g_d and writes the result to the 32-byte `result`
 /// buffer. Returns false if `esk` is not a valid scalar.
@@ -594,47 +981,24 @@ pub extern "C" fn librustzcash_sapling_check_spend(
     spend_auth_sig: *const [c_uchar; 64],
     sighash_value: *const [c_uchar; 32],
 ) -> bool {
-    // Deserialize the value commitment
-    let cv = match de_ct(jubjub::ExtendedPoint::from_bytes(unsafe { &*cv })) {
-        Some(p) => p,
-        None => return false,
-    };
-
-    // Deserialize the anchor, which should be an element
-    // of Fr.
-    let anchor = match de_ct(bls12_381::Scalar::from_bytes(unsafe { &*anchor })) {
-        Some(a) => a,
-        None => return false,
-    };
-
-    // Deserialize rk
-    let rk = match redjubjub::PublicKey::read(&(unsafe { &*rk })[..]) {
-        Ok(p) => p,
-        Err(_) => return false,
-    };
-
-    // Deserialize the signature
-    let spend_auth_sig = match Signature::read(&(unsafe { &*spend_auth_sig })[..]) {
-        Ok(sig) => sig,
-        Err(_) => return false,
-    };
-
-    // Deserialize the proof
-    let zkproof = match Proof::read(&(unsafe { &*zkproof })[..]) {
-        Ok(p) => p,
-        Err(_) => return false,
+    let spend = unsafe {
+        SaplingSpend::read(
+            &*cv,
+            &*anchor,
+            &*nullifier,
+            &*rk,
+            &*zkproof,
+            &*spend_auth_sig,
+        )
     };
-
-    unsafe { &mut *ctx }.check_spend(
-        cv,
-        anchor,
-        unsafe { &*nullifier },
-        rk,
-        unsafe { &*sighash_value },
-        spend_auth_sig,
-        zkproof,
-        unsafe { SAPLING_SPEND_VK.as_ref() }.unwrap(),
-    )
+    match spend {
+        Some(spend) => spend.check(
+            unsafe { &mut *ctx },
+            unsafe { &*sighash_value },
+            unsafe { SAPLING_SPEND_VK.as_ref() }.unwrap(),
+        ),
+        None => false,
+    }
 }
 
 /// Check the validity of a Sapling Output description, accumulating the value
@@ -647,38 +1011,13 @@ pub extern "C" fn librustzcash_sapling_check_output(
     epk: *const [c_uchar; 32],
     zkproof: *const [c_uchar; GROTH_PROOF_SIZE],
 ) -> bool {
-    // Deserialize the value commitment
-    let cv = match de_ct(jubjub::ExtendedPoint::from_bytes(unsafe { &*cv })) {
-        Some(p) => p,
-        None => return false,
-    };
-
-    // Deserialize the commitment, which should be an element
-    // of Fr.
-    let cm = match de_ct(bls12_381::Scalar::from_bytes(unsafe { &*cm })) {
-        Some(a) => a,
-        None => return false,
-    };
-
-    // Deserialize the ephemeral key
-    let epk = match de_ct(jubjub::ExtendedPoint::from_bytes(unsafe { &*epk })) {
-        Some(p) => p,
-        None => return false,
-    };
-
-    // Deserialize the proof
-    let zkproof = match Proof::read(&(unsafe { &*zkproof })[..]) {
-        Ok(p) => p,
-        Err(_) => return false,
-    };
-
-    unsafe { &mut *ctx }.check_output(
-        cv,
-        cm,
-        epk,
-        zkproof,
-        unsafe { SAPLING_OUTPUT_VK.as_ref() }.unwrap(),
-    )
+    match unsafe { SaplingOutput::read(&*cv, &*cm, &*epk, &*zkproof) } {
+        Some(output) => output.check(
+            unsafe { &mut *ctx },
+            unsafe { SAPLING_OUTPUT_VK.as_ref() }.unwrap(),
+        ),
+        None => false,
+    }
 }
 
 /// Finally checks the validity of the entire Sapling transaction given
@@ -690,18 +1029,10 @@ pub extern "C" fn librustzcash_sapling_final_check(
     binding_sig: *const [c_uchar; 64],
     sighash_value: *const [c_uchar; 32],
 ) -> bool {
-    let value_balance = match Amount::from_i64(value_balance) {
-        Ok(vb) => vb,
-        Err(()) => return false,
-    };
-
-    // Deserialize the signature
-    let binding_sig = match Signature::read(&(unsafe { &*binding_sig })[..]) {
-        Ok(sig) => sig,
-        Err(_) => return false,
-    };
-
-    unsafe { &*ctx }.final_check(value_balance, unsafe { &*sighash_value }, binding_sig)
+    match SaplingBindingSig::read(value_balance, unsafe { &*binding_sig }) {
+        Some(binding_sig) => binding_sig.check(unsafe { &*ctx }, unsafe { &*sighash_value }),
+        None => false,
+    }
 }
 
 /// Sprout JoinSplit proof generation.
@@ -741,22 +1072,11 @@ pub extern "C" fn librustzcash_sprout_prove(
     vpub_old: u64,
     vpub_new: u64,
 ) {
-    // Load parameters from disk
-    let sprout_fs = File::open(
-        unsafe { &SPROUT_GROTH16_PARAMS_PATH }
-            .as_ref()
-            .expect("parameters should have been initialized"),
-    )
-    .expect("couldn't load Sprout groth16 parameters file");
-
-    let mut sprout_fs = BufReader::with_capacity(1024 * 1024, sprout_fs);
-
-    let params = Parameters::read(&mut sprout_fs, false)
-        .expect("couldn't deserialize Sprout JoinSplit parameters file");
-
-    drop(sprout_fs);
+    let params_path = unsafe { &SPROUT_GROTH16_PARAMS_PATH }
+        .as_ref()
+        .expect("parameters should have been initialized");
 
-    let proof = sprout::create_proof(
+    let circuit = sprout_prover::joinsplit(
         unsafe { *phi },
         unsafe { *rt },
         unsafe { *h_sig },
@@ -778,14 +1098,44 @@ pub extern "C" fn librustzcash_sprout_prove(
         unsafe { *out_r2 },
         vpub_old,
         vpub_new,
-        &params,
     );
+    let mut rng = rng::rng().expect("couldn't obtain randomness for the Sprout proof");
+    let proof = sprout_prover::prove(
+        circuit,
+        params_path,
+        sprout_prover::mode(),
+        sprout_prover::budget(),
+        &mut rng,
+    )
+    .expect("couldn't create the Sprout proof; check the Sprout parameters file");
 
     proof
         .write(&mut (unsafe { &mut *proof_out })[..])
         .expect("should be able to serialize a proof");
 }
 
+/// Selects how `librustzcash_sprout_prove` reads the Sprout proving parameters: 0 to
+/// load them into memory, or 1 (the default) to stream them from the file.
+///
+/// Returns `false` if `mode` is not recognised.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_prover_mode(mode: u32) -> bool {
+    match sprout_prover::ProverMode::from_u32(mode) {
+        Some(mode) => {
+            sprout_prover::set_mode(mode);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Sets the total size in bytes of the buffers that streamed Sprout proving parameters
+/// are read through.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_prover_budget(budget: usize) {
+    sprout_prover::set_budget(budget);
+}
+
 /// Sprout JoinSplit proof verification.
 #[no_mangle]
 pub extern "C" fn librustzcash_sprout_verify(
@@ -829,6 +1179,11 @@ pub extern "C" fn librustzcash_sapling_output_proof(
     cv: *mut [c_uchar; 32],
     zkproof: *mut [c_uchar; GROTH_PROOF_SIZE],
 ) -> bool {
+    if should_fail(FaultSite::SaplingOutputProof) {
+        error!("Failed to create Sapling Output proof");
+        return false;
+    }
+
     // Grab `esk`, which the caller should have constructed for the DH key exchange.
     let esk = match de_ct(jubjub::Scalar::from_bytes(unsafe { &*esk })) {
         Some(p) => p,
@@ -847,14 +1202,17 @@ pub extern "C" fn librustzcash_sapling_output_proof(
         None => return false,
     };
 
+    let params = match sapling_proving_params() {
+        Ok(params) => params,
+        Err(e) => {
+            error!("Failed to load Sapling proving parameters: {}", e);
+            return false;
+        }
+    };
+
     // Create proof
-    let (proof, value_commitment) = unsafe { &mut *ctx }.output_proof(
-        esk,
-        payment_address,
-        rcm,
-        value,
-        unsafe { SAPLING_OUTPUT_PARAMS.as_ref() }.unwrap(),
-    );
+    let (proof, value_commitment) =
+        unsafe { &mut *ctx }.output_proof(esk, payment_address, rcm, value, &params.output);
 
     // Write the proof out to the caller
     proof
@@ -892,7 +1250,13 @@ pub extern "C" fn librustzcash_sapling_spend_sig(
     };
 
     // Initialize secure RNG
-    let mut rng = OsRng;
+    let mut rng = match rng::rng() {
+        Ok(rng) => rng,
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            return false;
+        }
+    };
 
     // Do the signing
     let sig = spend_sig(ask, ar, unsafe { &*sighash }, &mut rng);
@@ -951,6 +1315,11 @@ pub extern "C" fn librustzcash_sapling_spend_proof(
     rk_out: *mut [c_uchar; 32],
     zkproof: *mut [c_uchar; GROTH_PROOF_SIZE],
 ) -> bool {
+    if should_fail(FaultSite::SaplingSpendProof) {
+        error!("Failed to create Sapling Spend proof");
+        return false;
+    }
+
     // Grab `ak` from the caller, which should be a point.
     let ak = match de_ct(jubjub::ExtendedPoint::from_bytes(unsafe { &*ak })) {
         Some(p) => p,
@@ -1001,6 +1370,14 @@ pub extern "C" fn librustzcash_sapling_spend_proof(
         Err(_) => return false,
     };
 
+    let params = match sapling_proving_params() {
+        Ok(params) => params,
+        Err(e) => {
+            error!("Failed to load Sapling proving parameters: {}", e);
+            return false;
+        }
+    };
+
     // Create proof
     let (proof, value_commitment, rk) = unsafe { &mut *ctx }
         .spend_proof(
@@ -1011,7 +1388,7 @@ pub extern "C" fn librustzcash_sapling_spend_proof(
             value,
             anchor,
             merkle_path,
-            unsafe { SAPLING_SPEND_PARAMS.as_ref() }.unwrap(),
+            &params.spend,
             unsafe { SAPLING_SPEND_VK.as_ref() }.unwrap(),
         )
         .expect("proving should not fail");
@@ -1035,6 +1412,7 @@ pub extern "C" fn librustzcash_sapling_spend_proof(
 #[no_mangle]
 pub extern "C" fn librustzcash_sapling_proving_ctx_init() -> *mut SaplingProvingContext {
     let ctx = Box::new(SaplingProvingContext::new());
+    SAPLING_PROVING_CONTEXTS.fetch_add(1, Ordering::SeqCst);
 
     Box::into_raw(ctx)
 }
@@ -1044,6 +1422,7 @@ pub extern "C" fn librustzcash_sapling_proving_ctx_init() -> *mut SaplingProving
 #[no_mangle]
 pub extern "C" fn librustzcash_sapling_proving_ctx_free(ctx: *mut SaplingProvingContext) {
     drop(unsafe { Box::from_raw(ctx) });
+    SAPLING_PROVING_CONTEXTS.fetch_sub(1, Ordering::SeqCst);
 }
 
 /// Derive the master ExtendedSpendingKey from a seed.
@@ -1200,8 +1579,41 @@ pub extern "C" fn librustzcash_sapling_diversifier_index(
     j_ret.copy_from_slice(&j.0);
 }
 
+/// Fills `buf` with randomness from the operating system.
+///
+/// Returns false if no randomness could be obtained.
 #[no_mangle]
-pub extern "C" fn librustzcash_getrandom(buf: *mut u8, buf_len: usize) {
+pub extern "C" fn librustzcash_getrandom(buf: *mut u8, buf_len: usize) -> bool {
     let buf = unsafe { slice::from_raw_parts_mut(buf, buf_len) };
-    OsRng.fill_bytes(buf);
+    match rng::fill_random(buf) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            false
+        }
+    }
+}
+
+/// Derives all randomness from `seed` rather than the operating system, so that
+/// failures can be reproduced. This is refused unless zcashd is running on regtest.
+#[no_mangle]
+pub extern "C" fn librustzcash_set_deterministic_rng(seed: *const [c_uchar; 32]) -> bool {
+    let seed = match unsafe { seed.as_ref() } {
+        Some(seed) => *seed,
+        None => return false,
+    };
+    rng::set_deterministic_rng(network::network_params().network(), seed)
+}
+
+/// Checks that randomness can be obtained from the operating system, and that it is
+/// not obviously broken.
+#[no_mangle]
+pub extern "C" fn librustzcash_rng_selfcheck() -> bool {
+    match rng::selfcheck() {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Randomness self-check failed: {}", e);
+            false
+        }
+    }
 }
@@ -0,0 +1,264 @@
+//! The signature digest of pre-Overwinter transactions.
+//!
+//! Transactions without the overwintered flag are signed with the original Bitcoin
+//! `SignatureHash`, as extended by Sprout to commit to JoinSplits: the double-SHA-256
+//! hash of a copy of the transaction, modified according to the hash type, followed by
+//! the hash type. `zcashd` no longer produces these digests, since it only validates them
+//! for blocks below the Overwinter activation height; this reproduces them for signing
+//! legacy transactions in tests, and for cross-checking the C++ implementation.
+
+use std::convert::TryInto;
+use std::fmt;
+
+use sha2::{Digest, Sha256};
+use zcash_primitives::transaction::sighash::{SIGHASH_ANYONECANPAY, SIGHASH_NONE, SIGHASH_SINGLE};
+
+use crate::transaction_encoding::{canonical_length, EncodingError};
+
+/// The digest that legacy `SignatureHash` returns, instead of an error, for an input
+/// index that is out of range, or for `SIGHASH_SINGLE` on an input with no
+/// corresponding output. Since it is not a hash, signatures over it are valid for any
+/// transaction.
+pub(crate) const SIGHASH_ONE: [u8; 32] = {
+    let mut one = [0; 32];
+    one[0] = 1;
+    one
+};
+
+/// The size of a pre-Overwinter JoinSplit description, which has a BCTV14 proof.
+const JOINSPLIT_SIZE: usize = 8 + 8 + 32 + 2 * 32 + 2 * 32 + 32 + 32 + 2 * 32 + 296 + 2 * 601;
+
+/// Errors that can occur when calculating a pre-Overwinter signature digest.
+#[derive(Debug, PartialEq)]
+pub(crate) enum LegacySighashError {
+    /// The transaction has the overwintered flag set, and so is not signed with the
+    /// legacy digest.
+    Overwintered,
+    /// The transaction is truncated, or not in its canonical encoding.
+    NonCanonical(EncodingError),
+    /// This many bytes follow the end of the transaction.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for LegacySighashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LegacySighashError::Overwintered => write!(
+                f,
+                "Cannot calculate legacy digest for an overwintered transaction"
+            ),
+            LegacySighashError::NonCanonical(e) => write!(f, "Invalid transaction: {}", e),
+            LegacySighashError::TrailingBytes(n) => {
+                write!(f, "Transaction is followed by {} trailing bytes", n)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LegacySighashError {}
+
+struct TxIn<'a> {
+    prevout: &'a [u8],
+    sequence: &'a [u8],
+}
+
+struct TxOut<'a> {
+    value: &'a [u8],
+    script_pubkey: &'a [u8],
+}
+
+/// The parts of a pre-Overwinter transaction that the legacy digest commits to.
+struct LegacyTransaction<'a> {
+    version: u32,
+    vin: Vec<TxIn<'a>>,
+    vout: Vec<TxOut<'a>>,
+    lock_time: &'a [u8],
+    /// The JoinSplit count, descriptions, and `joinSplitPubKey` of a v2+ transaction.
+    joinsplits: Option<(usize, &'a [u8], &'a [u8])>,
+}
+
+/// Reads fields from a transaction whose encoding `canonical_length` has accepted, and
+/// which therefore cannot end early.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn take(&mut self, len: usize) -> &'a [u8] {
+        let (field, rest) = self.0.split_at(len);
+        self.0 = rest;
+        field
+    }
+
+    fn compact_size(&mut self) -> usize {
+        match self.take(1)[0] {
+            0xfd => u16::from_le_bytes(self.take(2).try_into().unwrap()) as usize,
+            0xfe => u32::from_le_bytes(self.take(4).try_into().unwrap()) as usize,
+            // `canonical_length` rejects anything larger than `MAX_SIZE`.
+            0xff => unreachable!(),
+            size => size as usize,
+        }
+    }
+
+    fn bytes(&mut self) -> &'a [u8] {
+        let len = self.compact_size();
+        self.take(len)
+    }
+}
+
+impl<'a> LegacyTransaction<'a> {
+    fn parse(tx_bytes: &'a [u8]) -> Result<Self, LegacySighashError> {
+        if tx_bytes.len() >= 4 && tx_bytes[3] >> 7 == 1 {
+            return Err(LegacySighashError::Overwintered);
+        }
+        match canonical_length(tx_bytes) {
+            Ok(len) if len < tx_bytes.len() => {
+                return Err(LegacySighashError::TrailingBytes(tx_bytes.len() - len))
+            }
+            Ok(_) => (),
+            Err(e) => return Err(LegacySighashError::NonCanonical(e)),
+        }
+
+        let mut fields = Fields(tx_bytes);
+        let version = u32::from_le_bytes(fields.take(4).try_into().unwrap());
+        let vin = (0..fields.compact_size())
+            .map(|_| {
+                let prevout = fields.take(36);
+                fields.bytes();
+                TxIn {
+                    prevout,
+                    sequence: fields.take(4),
+                }
+            })
+            .collect();
+        let vout = (0..fields.compact_size())
+            .map(|_| TxOut {
+                value: fields.take(8),
+                script_pubkey: fields.bytes(),
+            })
+            .collect();
+        let lock_time = fields.take(4);
+        let joinsplits = if version >= 2 {
+            let count = fields.compact_size();
+            let descriptions = fields.take(count * JOINSPLIT_SIZE);
+            let pubkey = if count > 0 { fields.take(32) } else { &[] };
+            Some((count, descriptions, pubkey))
+        } else {
+            None
+        };
+
+        Ok(LegacyTransaction {
+            version,
+            vin,
+            vout,
+            lock_time,
+            joinsplits,
+        })
+    }
+}
+
+fn write_compact_size(preimage: &mut Vec<u8>, size: usize) {
+    match size {
+        0..=0xfc => preimage.push(size as u8),
+        0xfd..=0xffff => {
+            preimage.push(0xfd);
+            preimage.extend_from_slice(&(size as u16).to_le_bytes());
+        }
+        _ => {
+            preimage.push(0xfe);
+            preimage.extend_from_slice(&(size as u32).to_le_bytes());
+        }
+    }
+}
+
+fn write_bytes(preimage: &mut Vec<u8>, bytes: &[u8]) {
+    write_compact_size(preimage, bytes.len());
+    preimage.extend_from_slice(bytes);
+}
+
+/// Calculates the legacy signature digest of the transparent input at `input_index` of
+/// a serialized pre-Overwinter transaction, as `SignatureHash` did before Overwinter.
+///
+/// The digest commits to `script_code` in place of the input's `scriptSig`, and to
+/// empty scripts for the other inputs. The low five bits of `hash_type` select
+/// `SIGHASH_NONE`, `SIGHASH_SINGLE`, or otherwise `SIGHASH_ALL`, and the whole of
+/// `hash_type` is committed to. As with the original implementation, an `input_index`
+/// out of range, or `SIGHASH_SINGLE` without a corresponding output, gives
+/// [`SIGHASH_ONE`] rather than an error.
+pub(crate) fn sighash_legacy(
+    tx_bytes: &[u8],
+    hash_type: i32,
+    input_index: usize,
+    script_code: &[u8],
+) -> Result<[u8; 32], LegacySighashError> {
+    let tx = LegacyTransaction::parse(tx_bytes)?;
+    let anyone_can_pay = hash_type & i32::from(SIGHASH_ANYONECANPAY) != 0;
+    let hash_single = hash_type & 0x1f == i32::from(SIGHASH_SINGLE);
+    let hash_none = hash_type & 0x1f == i32::from(SIGHASH_NONE);
+
+    if input_index >= tx.vin.len() || (hash_single && input_index >= tx.vout.len()) {
+        return Ok(SIGHASH_ONE);
+    }
+
+    let mut preimage = Vec::with_capacity(tx_bytes.len());
+    preimage.extend_from_slice(&tx.version.to_le_bytes());
+
+    let inputs = if anyone_can_pay {
+        input_index..input_index + 1
+    } else {
+        0..tx.vin.len()
+    };
+    write_compact_size(&mut preimage, inputs.len());
+    for i in inputs {
+        let txin = &tx.vin[i];
+        preimage.extend_from_slice(txin.prevout);
+        if i == input_index {
+            write_bytes(&mut preimage, script_code);
+            preimage.extend_from_slice(txin.sequence);
+        } else {
+            // Blank out the other inputs' signatures, and let them update at will
+            // unless every output is signed.
+            write_bytes(&mut preimage, &[]);
+            if hash_single || hash_none {
+                preimage.extend_from_slice(&[0; 4]);
+            } else {
+                preimage.extend_from_slice(txin.sequence);
+            }
+        }
+    }
+
+    let outputs = if hash_none {
+        0
+    } else if hash_single {
+        input_index + 1
+    } else {
+        tx.vout.len()
+    };
+    write_compact_size(&mut preimage, outputs);
+    for (i, txout) in tx.vout.iter().take(outputs).enumerate() {
+        if hash_single && i != input_index {
+            // A null output, with a value of -1 and an empty script.
+            preimage.extend_from_slice(&(-1i64).to_le_bytes());
+            write_bytes(&mut preimage, &[]);
+        } else {
+            preimage.extend_from_slice(txout.value);
+            write_bytes(&mut preimage, txout.script_pubkey);
+        }
+    }
+
+    preimage.extend_from_slice(tx.lock_time);
+    if let Some((count, descriptions, pubkey)) = tx.joinsplits {
+        write_compact_size(&mut preimage, count);
+        preimage.extend_from_slice(descriptions);
+        if count > 0 {
+            // The JoinSplit signature is blanked out, since it signs this digest.
+            preimage.extend_from_slice(pubkey);
+            preimage.extend_from_slice(&[0; 64]);
+        }
+    }
+    preimage.extend_from_slice(&hash_type.to_le_bytes());
+
+    let mut hasher = Sha256::new();
+    hasher.update(&preimage);
+    let mut second = Sha256::new();
+    second.update(hasher.finalize());
+    Ok(second.finalize().into())
+}
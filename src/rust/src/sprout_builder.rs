@@ -0,0 +1,941 @@
+//! Construction of the JoinSplits of a transaction that spends Sprout notes, as used by
+//! the Sprout-to-Sapling migration.
+//!
+//! `TransactionBuilder::CreateJSDescriptions` delegates to this, which keeps the chaining
+//! of the C++ implementation it replaced and proves as `JoinSplit::prove` does. Notes are
+//! spent in pairs, and the change of each JoinSplit is
+//! spent as the first input of the next, anchored to the interstitial tree that
+//! includes the previous JoinSplit's commitments. Any value left over once every note
+//! has been spent and every Sprout output paid is released with `vpub_new` in the last
+//! JoinSplit, which is how the funds of a migration transaction reach its Sapling
+//! output.
+
+use std::collections::VecDeque;
+use std::convert::TryInto;
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::slice;
+
+use libc::size_t;
+use rand_core::RngCore;
+use tracing::error;
+use zcash_primitives::transaction::components::amount::MAX_MONEY;
+use zcash_proofs::sprout::{GROTH_PROOF_SIZE, WITNESS_PATH_SIZE};
+
+use crate::rng;
+use crate::sprout_ffi::{
+    encrypt, h_sig, mac, note_commitment, nullifier, output_rho, parse_plaintext, paying_key,
+    receiving_key, transmission_key, try_decrypt, SproutNotePlaintext, SPROUT_CIPHERTEXT_SIZE,
+    SPROUT_MEMO_SIZE,
+};
+use crate::sprout_prover;
+use crate::sprout_tree::{MerklePath, Node, SproutTree, SproutWitness, SPROUT_TREE_DEPTH};
+
+/// The length of a JoinSplit description with a Groth16 proof.
+pub(crate) const JS_DESCRIPTION_SIZE: usize = 8
+    + 8
+    + 32
+    + (2 * 32)
+    + (2 * 32)
+    + 32
+    + 32
+    + (2 * 32)
+    + GROTH_PROOF_SIZE
+    + (2 * SPROUT_CIPHERTEXT_SIZE);
+
+/// The memo of outputs that don't have one: 0xF6 followed by zeroes.
+const NO_MEMO: [u8; SPROUT_MEMO_SIZE] = {
+    let mut memo = [0; SPROUT_MEMO_SIZE];
+    memo[0] = 0xf6;
+    memo
+};
+
+/// Errors that can occur while building JoinSplits.
+#[derive(Debug)]
+pub(crate) enum Error {
+    /// A note's witness is to a different tree than the notes already added.
+    AnchorMismatch,
+    /// A witness does not witness the note it was given with.
+    WrongWitness,
+    /// A witness is to the empty tree.
+    EmptyWitness,
+    /// A note is not spendable with the key it was given with.
+    WrongKey,
+    /// A value, or a sum of values, is larger than `MAX_MONEY`.
+    InvalidValue,
+    /// The inputs of the last JoinSplit don't cover `vpub_new`.
+    InsufficientFunds(u64),
+    /// The values of a JoinSplit's inputs and `vpub_old` don't add up to those of its
+    /// outputs and `vpub_new`.
+    Unbalanced,
+    /// The change of a JoinSplit is chained, but the tree as of its anchor is unknown.
+    MissingAnchorTree,
+    /// The change of the previous JoinSplit couldn't be decrypted.
+    ChangeDecryption,
+    /// An output's transmission key is of small order.
+    InvalidRecipient,
+    /// The witness of a note that is spent after chained change does not have the
+    /// anchor of the change.
+    ChainedAnchorMismatch,
+    /// The proof couldn't be created.
+    Proof(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::AnchorMismatch => {
+                write!(f, "Anchor does not match previously-added Sprout spends")
+            }
+            Error::WrongWitness => write!(f, "witness of wrong element for joinsplit input"),
+            Error::EmptyWitness => write!(f, "witness is to the empty tree"),
+            Error::WrongKey => write!(f, "input note not authorized to spend with given key"),
+            Error::InvalidValue => write!(f, "nonsensical joinsplit value"),
+            Error::InsufficientFunds(vpub_new) => {
+                write!(f, "Insufficient funds for vpub_new {}", vpub_new)
+            }
+            Error::Unbalanced => write!(f, "invalid joinsplit balance"),
+            Error::MissingAnchorTree => write!(f, "Could not find previous JoinSplit anchor"),
+            Error::ChangeDecryption => {
+                write!(f, "Error decrypting output note of previous JoinSplit")
+            }
+            Error::InvalidRecipient => write!(f, "Could not create DH secret"),
+            Error::ChainedAnchorMismatch => write!(
+                f,
+                "Witness for spendable note does not have same anchor as change input"
+            ),
+            Error::Proof(e) => write!(f, "Couldn't create the Sprout proof: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// A Sprout note.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SproutNote {
+    pub(crate) a_pk: [u8; 32],
+    pub(crate) value: u64,
+    pub(crate) rho: [u8; 32],
+    pub(crate) r: [u8; 32],
+}
+
+impl SproutNote {
+    pub(crate) fn cm(&self) -> Node {
+        note_commitment(&self.a_pk, self.value, &self.rho, &self.r)
+    }
+}
+
+/// Returns a random 252-bit value, as `random_uint252` does.
+fn random_u252<R: RngCore>(rng: &mut R) -> [u8; 32] {
+    let mut value = [0; 32];
+    rng.fill_bytes(&mut value);
+    value[0] &= 0x0f;
+    value
+}
+
+fn random_u256<R: RngCore>(rng: &mut R) -> [u8; 32] {
+    let mut value = [0; 32];
+    rng.fill_bytes(&mut value);
+    value
+}
+
+/// A note to be spent by a JoinSplit.
+#[derive(Clone)]
+struct JsInput {
+    a_sk: [u8; 32],
+    note: SproutNote,
+    witness: SproutWitness<SPROUT_TREE_DEPTH>,
+}
+
+impl JsInput {
+    /// Returns a zero-valued note in a tree of its own, for an unused input slot
+    /// (`JSInput()` in zcashd).
+    fn dummy<R: RngCore>(rng: &mut R) -> Self {
+        let a_sk = random_u252(rng);
+        let note = SproutNote {
+            a_pk: paying_key(&a_sk),
+            value: 0,
+            rho: random_u256(rng),
+            r: random_u256(rng),
+        };
+        let mut tree = SproutTree::default();
+        tree.append(note.cm());
+        JsInput {
+            a_sk,
+            note,
+            witness: tree.witness(),
+        }
+    }
+}
+
+/// A payment to a Sprout address.
+#[derive(Clone)]
+struct JsOutput {
+    a_pk: [u8; 32],
+    pk_enc: [u8; 32],
+    value: u64,
+    memo: [u8; SPROUT_MEMO_SIZE],
+}
+
+impl JsOutput {
+    /// Returns a zero-valued payment to a random address, for an unused output slot
+    /// (`JSOutput()` in zcashd).
+    fn dummy<R: RngCore>(rng: &mut R) -> Self {
+        let a_sk = random_u252(rng);
+        JsOutput {
+            a_pk: paying_key(&a_sk),
+            pk_enc: transmission_key(&receiving_key(&a_sk)),
+            value: 0,
+            memo: NO_MEMO,
+        }
+    }
+}
+
+/// An input of the JoinSplit circuit.
+#[derive(Clone)]
+pub(crate) struct ProofInput {
+    pub(crate) a_sk: [u8; 32],
+    pub(crate) note: SproutNote,
+    pub(crate) path: MerklePath<SPROUT_TREE_DEPTH>,
+}
+
+/// The witness of the JoinSplit circuit.
+#[derive(Clone)]
+pub(crate) struct ProofWitness {
+    pub(crate) phi: [u8; 32],
+    pub(crate) rt: [u8; 32],
+    pub(crate) h_sig: [u8; 32],
+    pub(crate) inputs: [ProofInput; 2],
+    pub(crate) outputs: [SproutNote; 2],
+    pub(crate) vpub_old: u64,
+    pub(crate) vpub_new: u64,
+}
+
+/// Creates JoinSplit proofs.
+pub(crate) trait JoinSplitProver {
+    fn prove(
+        &self,
+        witness: &ProofWitness,
+        rng: &mut dyn RngCore,
+    ) -> Result<[u8; GROTH_PROOF_SIZE], Error>;
+}
+
+/// Proves JoinSplits with the Sprout parameters in the file at the given path, in the
+/// way that `librustzcash_sprout_prove` does.
+pub(crate) struct ParamsProver<'a>(pub(crate) &'a Path);
+
+impl<'a> JoinSplitProver for ParamsProver<'a> {
+    fn prove(
+        &self,
+        witness: &ProofWitness,
+        mut rng: &mut dyn RngCore,
+    ) -> Result<[u8; GROTH_PROOF_SIZE], Error> {
+        let auth = |input: &ProofInput| {
+            let mut auth = [0; WITNESS_PATH_SIZE];
+            input
+                .path
+                .write(&mut auth[..])
+                .expect("the encoding of a path has a fixed length");
+            auth
+        };
+        let [in1, in2] = &witness.inputs;
+        let [out1, out2] = &witness.outputs;
+        let circuit = sprout_prover::joinsplit(
+            witness.phi,
+            witness.rt,
+            witness.h_sig,
+            in1.a_sk,
+            in1.note.value,
+            in1.note.rho,
+            in1.note.r,
+            &auth(in1),
+            in2.a_sk,
+            in2.note.value,
+            in2.note.rho,
+            in2.note.r,
+            &auth(in2),
+            out1.a_pk,
+            out1.value,
+            out1.r,
+            out2.a_pk,
+            out2.value,
+            out2.r,
+            witness.vpub_old,
+            witness.vpub_new,
+        );
+        let proof = sprout_prover::prove(
+            circuit,
+            self.0,
+            sprout_prover::mode(),
+            sprout_prover::budget(),
+            &mut rng,
+        )
+        .map_err(|e| Error::Proof(e.to_string()))?;
+
+        let mut encoded = [0; GROTH_PROOF_SIZE];
+        proof
+            .write(&mut encoded[..])
+            .expect("should be able to serialize a proof");
+        Ok(encoded)
+    }
+}
+
+/// A JoinSplit description with a Groth16 proof.
+#[derive(Clone)]
+pub(crate) struct JsDescription {
+    pub(crate) vpub_old: u64,
+    pub(crate) vpub_new: u64,
+    pub(crate) anchor: [u8; 32],
+    pub(crate) nullifiers: [[u8; 32]; 2],
+    pub(crate) commitments: [[u8; 32]; 2],
+    pub(crate) ephemeral_key: [u8; 32],
+    pub(crate) random_seed: [u8; 32],
+    pub(crate) macs: [[u8; 32]; 2],
+    pub(crate) proof: [u8; GROTH_PROOF_SIZE],
+    pub(crate) ciphertexts: [[u8; SPROUT_CIPHERTEXT_SIZE]; 2],
+}
+
+impl JsDescription {
+    /// Writes the description in the encoding of zcashd's `JSDescription` for v4
+    /// transactions.
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(&self.vpub_old.to_le_bytes())?;
+        writer.write_all(&self.vpub_new.to_le_bytes())?;
+        writer.write_all(&self.anchor)?;
+        for nf in &self.nullifiers {
+            writer.write_all(nf)?;
+        }
+        for cm in &self.commitments {
+            writer.write_all(cm)?;
+        }
+        writer.write_all(&self.ephemeral_key)?;
+        writer.write_all(&self.random_seed)?;
+        for mac in &self.macs {
+            writer.write_all(mac)?;
+        }
+        writer.write_all(&self.proof)?;
+        for ciphertext in &self.ciphertexts {
+            writer.write_all(ciphertext)?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds `value` to `total`, checking that both stay within `MAX_MONEY`.
+fn add_value(total: u64, value: u64) -> Result<u64, Error> {
+    let max = MAX_MONEY as u64;
+    if value > max {
+        return Err(Error::InvalidValue);
+    }
+    match total.checked_add(value) {
+        Some(total) if total <= max => Ok(total),
+        _ => Err(Error::InvalidValue),
+    }
+}
+
+/// Shuffles `items`, returning the original index of each item in its new position,
+/// as `MappedShuffle` does.
+fn mapped_shuffle<T, R: RngCore>(items: &mut [T; 2], rng: &mut R) -> [usize; 2] {
+    let mut map = [0, 1];
+    for i in (1..items.len()).rev() {
+        let j = (rng.next_u32() as usize) % (i + 1);
+        items.swap(i, j);
+        map.swap(i, j);
+    }
+    map
+}
+
+/// Creates a single JoinSplit, with its inputs and outputs in a random order, as
+/// `JSDescriptionInfo::BuildRandomized` does. Returns the JoinSplit and the position
+/// that each output was moved to.
+fn create_joinsplit<P: JoinSplitProver, R: RngCore>(
+    joinsplit_pub_key: &[u8; 32],
+    mut inputs: [JsInput; 2],
+    mut outputs: [JsOutput; 2],
+    vpub_old: u64,
+    vpub_new: u64,
+    prover: &P,
+    rng: &mut R,
+) -> Result<(JsDescription, [usize; 2]), Error> {
+    // The anchor is chosen before the inputs are shuffled.
+    let rt = inputs[0].witness.root();
+    mapped_shuffle(&mut inputs, rng);
+    let output_map = mapped_shuffle(&mut outputs, rng);
+
+    let mut lhs_value = add_value(0, vpub_old)?;
+    let mut rhs_value = add_value(0, vpub_new)?;
+    for input in &inputs {
+        if input.note.value != 0 {
+            if input.witness.root() != rt {
+                return Err(Error::AnchorMismatch);
+            }
+            if input.witness.element() != Some(input.note.cm()) {
+                return Err(Error::WrongWitness);
+            }
+        }
+        if input.note.a_pk != paying_key(&input.a_sk) {
+            return Err(Error::WrongKey);
+        }
+        lhs_value = add_value(lhs_value, input.note.value)?;
+    }
+    let nullifiers = [
+        nullifier(&inputs[0].a_sk, &inputs[0].note.rho),
+        nullifier(&inputs[1].a_sk, &inputs[1].note.rho),
+    ];
+
+    let random_seed = random_u256(rng);
+    let h_sig = h_sig(
+        &random_seed,
+        [&nullifiers[0], &nullifiers[1]],
+        joinsplit_pub_key,
+    );
+    let phi = random_u252(rng);
+
+    let mut notes = vec![];
+    for (i, output) in outputs.iter().enumerate() {
+        rhs_value = add_value(rhs_value, output.value)?;
+        notes.push(SproutNote {
+            a_pk: output.a_pk,
+            value: output.value,
+            rho: output_rho(&phi, i as u8, &h_sig),
+            r: random_u256(rng),
+        });
+    }
+    if lhs_value != rhs_value {
+        return Err(Error::Unbalanced);
+    }
+    let notes: [SproutNote; 2] = notes.try_into().unwrap();
+    let commitments = [notes[0].cm(), notes[1].cm()];
+
+    // Both outputs are encrypted under the same ephemeral key, with their index bound
+    // into the KDF.
+    let esk = random_u256(rng);
+    let ephemeral_key = transmission_key(&esk);
+    let mut ciphertexts = [[0; SPROUT_CIPHERTEXT_SIZE]; 2];
+    for (i, (output, note)) in outputs.iter().zip(notes.iter()).enumerate() {
+        let plaintext = SproutNotePlaintext {
+            value: note.value,
+            rho: note.rho,
+            r: note.r,
+            memo: output.memo,
+        };
+        ciphertexts[i] = encrypt(&esk, &output.pk_enc, &h_sig, &plaintext.to_bytes(), i as u8)
+            .ok_or(Error::InvalidRecipient)?;
+    }
+
+    let macs = [
+        mac(&inputs[0].a_sk, 0, &h_sig),
+        mac(&inputs[1].a_sk, 1, &h_sig),
+    ];
+
+    let path = |input: &JsInput| input.witness.path().ok_or(Error::EmptyWitness);
+    let witness = ProofWitness {
+        phi,
+        rt,
+        h_sig,
+        inputs: [
+            ProofInput {
+                a_sk: inputs[0].a_sk,
+                note: inputs[0].note.clone(),
+                path: path(&inputs[0])?,
+            },
+            ProofInput {
+                a_sk: inputs[1].a_sk,
+                note: inputs[1].note.clone(),
+                path: path(&inputs[1])?,
+            },
+        ],
+        outputs: notes,
+        vpub_old,
+        vpub_new,
+    };
+    let proof = prover.prove(&witness, rng)?;
+
+    Ok((
+        JsDescription {
+            vpub_old,
+            vpub_new,
+            anchor: rt,
+            nullifiers,
+            commitments,
+            ephemeral_key,
+            random_seed,
+            macs,
+            proof,
+            ciphertexts,
+        },
+        output_map,
+    ))
+}
+
+/// Builds the JoinSplits of a transaction from the Sprout notes it spends and the
+/// Sprout addresses it pays.
+pub struct SproutBuilder {
+    joinsplit_pub_key: [u8; 32],
+    /// The tree as of the anchor of the inputs, which chained JoinSplits extend.
+    anchor_tree: Option<SproutTree<SPROUT_TREE_DEPTH>>,
+    inputs: Vec<JsInput>,
+    outputs: Vec<JsOutput>,
+}
+
+impl SproutBuilder {
+    pub(crate) fn new(
+        joinsplit_pub_key: [u8; 32],
+        anchor_tree: Option<SproutTree<SPROUT_TREE_DEPTH>>,
+    ) -> Self {
+        SproutBuilder {
+            joinsplit_pub_key,
+            anchor_tree,
+            inputs: vec![],
+            outputs: vec![],
+        }
+    }
+
+    /// Adds a note to be spent with the spending key `a_sk`. All of the notes must be
+    /// witnessed to the same tree.
+    pub(crate) fn add_sprout_input(
+        &mut self,
+        a_sk: [u8; 32],
+        note: SproutNote,
+        witness: SproutWitness<SPROUT_TREE_DEPTH>,
+    ) -> Result<(), Error> {
+        if note.a_pk != paying_key(&a_sk) {
+            return Err(Error::WrongKey);
+        }
+        if note.value > MAX_MONEY as u64 {
+            return Err(Error::InvalidValue);
+        }
+        match witness.element() {
+            None => return Err(Error::EmptyWitness),
+            Some(cm) if cm != note.cm() => return Err(Error::WrongWitness),
+            Some(_) => (),
+        }
+        if let Some(first) = self.inputs.first() {
+            if first.witness.root() != witness.root() {
+                return Err(Error::AnchorMismatch);
+            }
+        }
+
+        self.inputs.push(JsInput {
+            a_sk,
+            note,
+            witness,
+        });
+        Ok(())
+    }
+
+    /// Adds a payment of `value` to the Sprout address (`a_pk`, `pk_enc`).
+    pub(crate) fn add_output(
+        &mut self,
+        a_pk: [u8; 32],
+        pk_enc: [u8; 32],
+        value: u64,
+        memo: Option<[u8; SPROUT_MEMO_SIZE]>,
+    ) -> Result<(), Error> {
+        if value > MAX_MONEY as u64 {
+            return Err(Error::InvalidValue);
+        }
+        self.outputs.push(JsOutput {
+            a_pk,
+            pk_enc,
+            value,
+            memo: memo.unwrap_or(NO_MEMO),
+        });
+        Ok(())
+    }
+
+    /// Builds the JoinSplits. The difference between the values of the inputs and the
+    /// outputs is released with `vpub_new` in the last JoinSplit if positive, or taken
+    /// in with `vpub_old` in the first JoinSplit if negative.
+    pub(crate) fn build<P: JoinSplitProver, R: RngCore>(
+        self,
+        prover: &P,
+        rng: &mut R,
+    ) -> Result<Vec<JsDescription>, Error> {
+        let mut joinsplits = vec![];
+        let mut inputs: VecDeque<_> = self.inputs.into_iter().collect();
+        let mut outputs: VecDeque<_> = self.outputs.into_iter().collect();
+
+        // Without inputs there is nothing to chain, so each JoinSplit just shields the
+        // value of a pair of outputs.
+        if inputs.is_empty() {
+            while !outputs.is_empty() {
+                let mut vjsout = [JsOutput::dummy(rng), JsOutput::dummy(rng)];
+                let mut vpub_old = 0;
+                for slot in vjsout.iter_mut() {
+                    if let Some(output) = outputs.pop_front() {
+                        vpub_old = add_value(vpub_old, output.value)?;
+                        *slot = output;
+                    }
+                }
+                let vjsin = [JsInput::dummy(rng), JsInput::dummy(rng)];
+                let (joinsplit, _) = create_joinsplit(
+                    &self.joinsplit_pub_key,
+                    vjsin,
+                    vjsout,
+                    vpub_old,
+                    0,
+                    prover,
+                    rng,
+                )?;
+                joinsplits.push(joinsplit);
+            }
+            return Ok(joinsplits);
+        }
+
+        // Change is sent to the address of the first input.
+        let change_key = inputs[0].a_sk;
+        let change_a_pk = paying_key(&change_key);
+        let change_sk_enc = receiving_key(&change_key);
+        let change_pk_enc = transmission_key(&change_sk_enc);
+
+        let value_in = inputs
+            .iter()
+            .try_fold(0, |total, input| add_value(total, input.note.value))?;
+        let value_out = outputs
+            .iter()
+            .try_fold(0, |total, output| add_value(total, output.value))?;
+        let vpub_old_target = value_out.saturating_sub(value_in);
+        let vpub_new_target = value_in.saturating_sub(value_out);
+
+        let mut js_change = 0;
+        let mut change_output_index = 0;
+        let mut vpub_old_processed = false;
+        let mut vpub_new_processed = false;
+
+        // The tree as of the anchor of the previous JoinSplit (if known), and the
+        // commitments of the chain of JoinSplits so far.
+        let mut prev_anchor_tree: Option<SproutTree<SPROUT_TREE_DEPTH>> = None;
+        let mut previous_commitments: Vec<Node> = vec![];
+
+        while !vpub_new_processed {
+            let mut vjsin = [JsInput::dummy(rng), JsInput::dummy(rng)];
+            let mut vjsout = [JsOutput::dummy(rng), JsOutput::dummy(rng)];
+            let mut vpub_old = 0;
+            let mut vpub_new = 0;
+
+            if !vpub_old_processed {
+                vpub_old = vpub_old_target;
+                vpub_old_processed = true;
+            }
+
+            let mut js_input_value = 0;
+            let mut js_anchor: Option<Node> = None;
+            let mut anchor_tree = None;
+
+            // Without change, the chain has ended.
+            if js_change == 0 && !joinsplits.is_empty() {
+                previous_commitments.clear();
+            }
+
+            // Spend the change of the previous JoinSplit first.
+            if js_change > 0 {
+                let prev: &JsDescription = joinsplits.last().unwrap();
+                let mut tree = prev_anchor_tree.clone().ok_or(Error::MissingAnchorTree)?;
+                let mut change_witness = None;
+                for (n, cm) in prev.commitments.iter().enumerate() {
+                    tree.append(*cm);
+                    previous_commitments.push(*cm);
+                    if change_witness.is_none() && n == change_output_index {
+                        change_witness = Some(tree.witness());
+                    } else if let Some(witness) = &mut change_witness {
+                        witness.append(*cm);
+                    }
+                }
+                js_anchor = Some(tree.root());
+
+                let prev_h_sig = h_sig(
+                    &prev.random_seed,
+                    [&prev.nullifiers[0], &prev.nullifiers[1]],
+                    &self.joinsplit_pub_key,
+                );
+                let plaintext = try_decrypt(
+                    &change_sk_enc,
+                    &prev.ephemeral_key,
+                    &prev_h_sig,
+                    &prev.ciphertexts[change_output_index],
+                    change_output_index as u8,
+                )
+                .and_then(|plaintext| parse_plaintext(&plaintext))
+                .ok_or(Error::ChangeDecryption)?;
+
+                vjsin[0] = JsInput {
+                    a_sk: change_key,
+                    note: SproutNote {
+                        a_pk: change_a_pk,
+                        value: plaintext.value,
+                        rho: plaintext.rho,
+                        r: plaintext.r,
+                    },
+                    witness: change_witness.expect("the change is one of the commitments"),
+                };
+                js_input_value = add_value(js_input_value, plaintext.value)?;
+                anchor_tree = Some(tree);
+            }
+
+            // Then spend the notes that were added.
+            let first = if js_change > 0 { 1 } else { 0 };
+            for slot in vjsin.iter_mut().skip(first) {
+                let mut input = match inputs.pop_front() {
+                    Some(input) => input,
+                    None => break,
+                };
+
+                if js_change > 0 {
+                    for cm in &previous_commitments {
+                        input.witness.append(*cm);
+                    }
+                    if js_anchor != Some(input.witness.root()) {
+                        return Err(Error::ChainedAnchorMismatch);
+                    }
+                }
+
+                // This JoinSplit starts a new chain at the anchor of the notes.
+                if js_anchor.is_none() {
+                    js_anchor = Some(input.witness.root());
+                    anchor_tree = self.anchor_tree.clone();
+                }
+
+                js_input_value = add_value(js_input_value, input.note.value)?;
+                *slot = input;
+            }
+
+            let mut recipient = outputs.pop_front().unwrap_or_else(|| JsOutput::dummy(rng));
+
+            js_change = 0;
+            let mut out_amount = recipient.value;
+
+            // Release the remaining value in the last JoinSplit.
+            if outputs.is_empty() && inputs.is_empty() {
+                if js_input_value < vpub_new_target {
+                    return Err(Error::InsufficientFunds(vpub_new_target));
+                }
+                out_amount = add_value(out_amount, vpub_new_target)?;
+                vpub_new = vpub_new_target;
+                vpub_new_processed = true;
+                js_change = js_input_value
+                    .checked_sub(out_amount)
+                    .ok_or(Error::InsufficientFunds(vpub_new_target))?;
+            } else if js_input_value > out_amount {
+                js_change = js_input_value - out_amount;
+            } else if out_amount > js_input_value {
+                // Pay what is covered now, and the rest from the next JoinSplit.
+                let mut due = recipient.clone();
+                due.value = out_amount - js_input_value;
+                outputs.push_front(due);
+                recipient.value = js_input_value;
+            }
+
+            vjsout[0] = recipient;
+            if js_change > 0 {
+                vjsout[1] = JsOutput {
+                    a_pk: change_a_pk,
+                    pk_enc: change_pk_enc,
+                    value: js_change,
+                    memo: NO_MEMO,
+                };
+            }
+
+            let (joinsplit, output_map) = create_joinsplit(
+                &self.joinsplit_pub_key,
+                vjsin,
+                vjsout,
+                vpub_old,
+                vpub_new,
+                prover,
+                rng,
+            )?;
+            joinsplits.push(joinsplit);
+            prev_anchor_tree = anchor_tree;
+
+            if js_change > 0 {
+                change_output_index = output_map
+                    .iter()
+                    .position(|&i| i == 1)
+                    .expect("the change is one of the outputs");
+            }
+        }
+
+        Ok(joinsplits)
+    }
+}
+
+/// The JoinSplits built by a [`SproutBuilder`].
+pub struct SproutJoinSplits(Vec<JsDescription>);
+
+/// Creates a builder for the JoinSplits of a transaction with the given
+/// `joinSplitPubKey`.
+///
+/// `anchor_tree` is the encoding of the `SproutMerkleTree` as of the anchor of the
+/// notes that will be spent, which chained JoinSplits extend. It may be null if no
+/// notes are spent, or if the tree is not known, in which case building fails if a
+/// JoinSplit needs to be chained.
+///
+/// Returns null if `anchor_tree` can't be parsed.
+#[no_mangle]
+pub extern "C" fn sprout_builder_new(
+    joinsplit_pub_key: *const [u8; 32],
+    anchor_tree: *const u8,
+    anchor_tree_len: size_t,
+) -> *mut SproutBuilder {
+    let anchor_tree = if anchor_tree.is_null() {
+        None
+    } else {
+        let encoded = unsafe { slice::from_raw_parts(anchor_tree, anchor_tree_len) };
+        match SproutTree::read(encoded) {
+            Ok(tree) => Some(tree),
+            Err(e) => {
+                error!("Failed to parse Sprout anchor tree: {}", e);
+                return std::ptr::null_mut();
+            }
+        }
+    };
+
+    Box::into_raw(Box::new(SproutBuilder::new(
+        unsafe { *joinsplit_pub_key },
+        anchor_tree,
+    )))
+}
+
+/// Frees a builder returned from [`sprout_builder_new`].
+#[no_mangle]
+pub extern "C" fn sprout_builder_free(builder: *mut SproutBuilder) {
+    if !builder.is_null() {
+        drop(unsafe { Box::from_raw(builder) });
+    }
+}
+
+/// Adds the Sprout note (`value`, `rho`, `r`) to be spent with the spending key
+/// `a_sk`. `witness` is the encoding of its `SproutWitness`.
+///
+/// Returns `false` if the witness can't be parsed or is not to this note, if the note
+/// is not spendable with `a_sk`, or if the witness has a different anchor than the
+/// notes already added.
+#[no_mangle]
+pub extern "C" fn sprout_builder_add_sprout_input(
+    builder: *mut SproutBuilder,
+    a_sk: *const [u8; 32],
+    value: u64,
+    rho: *const [u8; 32],
+    r: *const [u8; 32],
+    witness: *const u8,
+    witness_len: size_t,
+) -> bool {
+    let builder = unsafe { builder.as_mut() }.expect("Builder may not be null.");
+    let witness = unsafe { slice::from_raw_parts(witness, witness_len) };
+    let witness = match SproutWitness::read(witness) {
+        Ok(witness) => witness,
+        Err(e) => {
+            error!("Failed to parse Sprout witness: {}", e);
+            return false;
+        }
+    };
+
+    let a_sk = unsafe { *a_sk };
+    let note = SproutNote {
+        a_pk: paying_key(&a_sk),
+        value,
+        rho: unsafe { *rho },
+        r: unsafe { *r },
+    };
+    match builder.add_sprout_input(a_sk, note, witness) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to add Sprout input: {}", e);
+            false
+        }
+    }
+}
+
+/// Adds a payment of `value` to the Sprout address (`a_pk`, `pk_enc`).
+///
+/// `memo` is a pointer to the 512-byte memo field encoding, or `null` for "no memo".
+#[no_mangle]
+pub extern "C" fn sprout_builder_add_output(
+    builder: *mut SproutBuilder,
+    a_pk: *const [u8; 32],
+    pk_enc: *const [u8; 32],
+    value: u64,
+    memo: *const [u8; SPROUT_MEMO_SIZE],
+) -> bool {
+    let builder = unsafe { builder.as_mut() }.expect("Builder may not be null.");
+    let memo = unsafe { memo.as_ref() }.copied();
+
+    match builder.add_output(unsafe { *a_pk }, unsafe { *pk_enc }, value, memo) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to add Sprout output: {}", e);
+            false
+        }
+    }
+}
+
+/// Builds and proves the JoinSplits, chaining the change of each into the next and
+/// releasing the value that is left with `vpub_new` in the last.
+///
+/// Returns null if an error occurs.
+///
+/// `builder` is always freed by this method.
+#[no_mangle]
+pub extern "C" fn sprout_builder_build(builder: *mut SproutBuilder) -> *mut SproutJoinSplits {
+    if builder.is_null() {
+        error!("Called with null builder");
+        return std::ptr::null_mut();
+    }
+    let builder = unsafe { Box::from_raw(builder) };
+    let params_path = match unsafe { &crate::SPROUT_GROTH16_PARAMS_PATH }.as_ref() {
+        Some(path) => path,
+        None => {
+            error!("Failed to build Sprout JoinSplits: the Sprout parameters are not loaded");
+            return std::ptr::null_mut();
+        }
+    };
+    let mut rng = match rng::rng() {
+        Ok(rng) => rng,
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match builder.build(&ParamsProver(params_path), &mut rng) {
+        Ok(joinsplits) => Box::into_raw(Box::new(SproutJoinSplits(joinsplits))),
+        Err(e) => {
+            error!("Failed to build Sprout JoinSplits: {}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the number of JoinSplits built.
+#[no_mangle]
+pub extern "C" fn sprout_joinsplits_len(joinsplits: *const SproutJoinSplits) -> size_t {
+    unsafe { joinsplits.as_ref() }
+        .expect("JoinSplits may not be null.")
+        .0
+        .len()
+}
+
+/// Writes the JoinSplit at `index` to `js_ret`, in the encoding of `JSDescription` for
+/// v4 transactions.
+#[no_mangle]
+pub extern "C" fn sprout_joinsplits_get(
+    joinsplits: *const SproutJoinSplits,
+    index: size_t,
+    js_ret: *mut [u8; JS_DESCRIPTION_SIZE],
+) {
+    let joinsplits = unsafe { joinsplits.as_ref() }.expect("JoinSplits may not be null.");
+    joinsplits.0[index]
+        .write(&mut (unsafe { &mut *js_ret })[..])
+        .expect("the encoding of a JoinSplit has a fixed length");
+}
+
+/// Frees JoinSplits returned from [`sprout_builder_build`].
+#[no_mangle]
+pub extern "C" fn sprout_joinsplits_free(joinsplits: *mut SproutJoinSplits) {
+    if !joinsplits.is_null() {
+        drop(unsafe { Box::from_raw(joinsplits) });
+    }
+}
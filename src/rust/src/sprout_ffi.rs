@@ -0,0 +1,652 @@
+//! Encryption and decryption of Sprout notes, their commitments and nullifiers, batch
+//! verification of JoinSplit proofs, and the amounts of Sprout-to-Sapling migration
+//! transactions.
+//!
+//! Each output of a JoinSplit is encrypted to the recipient's transmission key with the
+//! scheme in section 4.17 of the protocol spec: a Curve25519 key agreement between the
+//! recipient's receiving key and the JoinSplit's ephemeral key, a KDF that binds `h_sig`
+//! and the index of the output within the JoinSplit, and ChaCha20-Poly1305 with an
+//! all-zero nonce (the key is never reused).
+
+use bellman::{
+    gadgets::multipack,
+    groth16::{batch, prepare_verifying_key, PreparedVerifyingKey, Proof, VerifyingKey},
+};
+use blake2b_simd::Params as Blake2bParams;
+use bls12_381::Bls12;
+use byteorder::{LittleEndian, WriteBytesExt};
+use chacha20poly1305::{
+    aead::{AeadInPlace, NewAead},
+    ChaCha20Poly1305, Key, Nonce, Tag,
+};
+use curve25519_dalek::{constants::X25519_BASEPOINT, montgomery::MontgomeryPoint, scalar::Scalar};
+use rand::Rng;
+use sha2::{digest::generic_array::GenericArray, Digest, Sha256};
+use tracing::{debug, error};
+use zcash_proofs::sprout::GROTH_PROOF_SIZE;
+
+use crate::fault_injection::{should_fail, FaultSite};
+use crate::rng;
+
+/// The length of a Sprout memo.
+pub(crate) const SPROUT_MEMO_SIZE: usize = 512;
+/// The length of a Sprout note plaintext: the lead byte, value, rho, r and memo.
+pub(crate) const SPROUT_PLAINTEXT_SIZE: usize = 1 + 8 + 32 + 32 + SPROUT_MEMO_SIZE;
+/// The length of a Sprout note ciphertext, including its authentication tag.
+pub(crate) const SPROUT_CIPHERTEXT_SIZE: usize = SPROUT_PLAINTEXT_SIZE + 16;
+
+/// The number of outputs in a JoinSplit. Each is encrypted under its own KDF index.
+const JOINSPLIT_OUTPUTS: u8 = 2;
+
+/// Computes Curve25519 scalar multiplication of `point` by `sk`, clamping `sk` as
+/// libsodium's `crypto_scalarmult` does.
+pub(crate) fn x25519(sk: &[u8; 32], point: &[u8; 32]) -> [u8; 32] {
+    let mut clamped = *sk;
+    clamped[0] &= 248;
+    clamped[31] &= 127;
+    clamped[31] |= 64;
+    (MontgomeryPoint(*point) * Scalar::from_bits(clamped)).to_bytes()
+}
+
+/// Derives the symmetric key for the output at `nonce_index` (`KDF^Sprout` in the
+/// protocol spec, which zcashd implements as `KDF` in `NoteEncryption.cpp`).
+fn kdf(
+    dhsecret: &[u8; 32],
+    epk: &[u8; 32],
+    pk_enc: &[u8; 32],
+    h_sig: &[u8; 32],
+    nonce_index: u8,
+) -> [u8; 32] {
+    let mut personalization = [0; 16];
+    personalization[..8].copy_from_slice(b"ZcashKDF");
+    personalization[8] = nonce_index;
+
+    let mut state = Blake2bParams::new()
+        .hash_length(32)
+        .personal(&personalization)
+        .to_state();
+    state.update(h_sig);
+    state.update(dhsecret);
+    state.update(epk);
+    state.update(pk_enc);
+
+    let mut key = [0; 32];
+    key.copy_from_slice(state.finalize().as_bytes());
+    key
+}
+
+/// Encrypts the plaintext of the JoinSplit output at `nonce_index` to the recipient's
+/// transmission key `pk_enc` under the JoinSplit's ephemeral key `esk`, matching
+/// `ZCNoteEncryption::encrypt`.
+///
+/// Returns `None` if `pk_enc` is of small order.
+pub(crate) fn encrypt(
+    esk: &[u8; 32],
+    pk_enc: &[u8; 32],
+    h_sig: &[u8; 32],
+    plaintext: &[u8; SPROUT_PLAINTEXT_SIZE],
+    nonce_index: u8,
+) -> Option<[u8; SPROUT_CIPHERTEXT_SIZE]> {
+    let epk = x25519(esk, &X25519_BASEPOINT.to_bytes());
+    let dhsecret = x25519(esk, pk_enc);
+    if dhsecret == [0; 32] {
+        return None;
+    }
+    let key = kdf(&dhsecret, &epk, pk_enc, h_sig, nonce_index);
+
+    let mut ciphertext = [0; SPROUT_CIPHERTEXT_SIZE];
+    let (encrypted, tag) = ciphertext.split_at_mut(SPROUT_PLAINTEXT_SIZE);
+    encrypted.copy_from_slice(plaintext);
+    let computed = ChaCha20Poly1305::new(Key::from_slice(&key))
+        .encrypt_in_place_detached(Nonce::from_slice(&[0; 12]), &[], encrypted)
+        .ok()?;
+    tag.copy_from_slice(&computed);
+    Some(ciphertext)
+}
+
+/// Decrypts the ciphertext of the JoinSplit output at `nonce_index` (0 or 1) with the
+/// recipient's receiving key `sk_enc`, returning `None` if it was not encrypted to
+/// them.
+pub(crate) fn try_decrypt(
+    sk_enc: &[u8; 32],
+    epk: &[u8; 32],
+    h_sig: &[u8; 32],
+    ciphertext: &[u8; SPROUT_CIPHERTEXT_SIZE],
+    nonce_index: u8,
+) -> Option<[u8; SPROUT_PLAINTEXT_SIZE]> {
+    if nonce_index >= JOINSPLIT_OUTPUTS {
+        return None;
+    }
+
+    let pk_enc = transmission_key(sk_enc);
+    let dhsecret = x25519(sk_enc, epk);
+    // Like `crypto_scalarmult`, reject ephemeral keys of small order.
+    if dhsecret == [0; 32] {
+        return None;
+    }
+    let key = kdf(&dhsecret, epk, &pk_enc, h_sig, nonce_index);
+
+    let (encrypted, tag) = ciphertext.split_at(SPROUT_PLAINTEXT_SIZE);
+    let mut plaintext = [0; SPROUT_PLAINTEXT_SIZE];
+    plaintext.copy_from_slice(encrypted);
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+        .decrypt_in_place_detached(
+            Nonce::from_slice(&[0; 12]),
+            &[],
+            &mut plaintext,
+            Tag::from_slice(tag),
+        )
+        .ok()?;
+    Some(plaintext)
+}
+
+/// The fields of a decrypted Sprout note.
+pub(crate) struct SproutNotePlaintext {
+    pub(crate) value: u64,
+    pub(crate) rho: [u8; 32],
+    pub(crate) r: [u8; 32],
+    pub(crate) memo: [u8; SPROUT_MEMO_SIZE],
+}
+
+impl SproutNotePlaintext {
+    /// Encodes the plaintext as `SproutNotePlaintext` does.
+    pub(crate) fn to_bytes(&self) -> [u8; SPROUT_PLAINTEXT_SIZE] {
+        let mut plaintext = [0; SPROUT_PLAINTEXT_SIZE];
+        plaintext[1..9].copy_from_slice(&self.value.to_le_bytes());
+        plaintext[9..41].copy_from_slice(&self.rho);
+        plaintext[41..73].copy_from_slice(&self.r);
+        plaintext[73..].copy_from_slice(&self.memo);
+        plaintext
+    }
+}
+
+/// Parses a Sprout note plaintext, returning `None` if its lead byte is not 0x00.
+pub(crate) fn parse_plaintext(
+    plaintext: &[u8; SPROUT_PLAINTEXT_SIZE],
+) -> Option<SproutNotePlaintext> {
+    if plaintext[0] != 0x00 {
+        return None;
+    }
+
+    let mut value = [0; 8];
+    value.copy_from_slice(&plaintext[1..9]);
+    let mut note = SproutNotePlaintext {
+        value: u64::from_le_bytes(value),
+        rho: [0; 32],
+        r: [0; 32],
+        memo: [0; SPROUT_MEMO_SIZE],
+    };
+    note.rho.copy_from_slice(&plaintext[9..41]);
+    note.r.copy_from_slice(&plaintext[41..73]);
+    note.memo.copy_from_slice(&plaintext[73..]);
+    Some(note)
+}
+
+/// Attempts to decrypt the ciphertext of the JoinSplit output at `nonce_index` (0 or
+/// 1) with `receiving_key`, writing the note plaintext to `plaintext_ret`.
+///
+/// Returns `false` if the output was not encrypted to `receiving_key`, or if
+/// `nonce_index` is not the index of a JoinSplit output.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_try_decrypt(
+    receiving_key: *const [u8; 32],
+    epk: *const [u8; 32],
+    h_sig: *const [u8; 32],
+    ciphertext: *const [u8; SPROUT_CIPHERTEXT_SIZE],
+    nonce_index: u8,
+    plaintext_ret: *mut [u8; SPROUT_PLAINTEXT_SIZE],
+) -> bool {
+    match try_decrypt(
+        unsafe { &*receiving_key },
+        unsafe { &*epk },
+        unsafe { &*h_sig },
+        unsafe { &*ciphertext },
+        nonce_index,
+    ) {
+        Some(plaintext) => {
+            unsafe { *plaintext_ret = plaintext };
+            true
+        }
+        None => false,
+    }
+}
+
+/// Parses a Sprout note plaintext into its value, rho, r and memo.
+///
+/// Returns `false` if the plaintext's lead byte is not recognised.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_parse_plaintext(
+    plaintext: *const [u8; SPROUT_PLAINTEXT_SIZE],
+    value_ret: *mut u64,
+    rho_ret: *mut [u8; 32],
+    r_ret: *mut [u8; 32],
+    memo_ret: *mut [u8; SPROUT_MEMO_SIZE],
+) -> bool {
+    match parse_plaintext(unsafe { &*plaintext }) {
+        Some(note) => {
+            unsafe {
+                *value_ret = note.value;
+                *rho_ret = note.rho;
+                *r_ret = note.r;
+                *memo_ret = note.memo;
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+/// The SHA-256 compression function applied to a single block, without padding, as
+/// used by the Sprout PRFs.
+pub(crate) fn sha256_compress(block: &[u8; 64]) -> [u8; 32] {
+    // The initial state of SHA-256.
+    let mut state = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    sha2::compress256(&mut state, &[GenericArray::clone_from_slice(block)]);
+
+    let mut out = [0; 32];
+    for (chunk, word) in out.chunks_exact_mut(4).zip(state.iter()) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    out
+}
+
+/// Computes the commitment to a Sprout note, matching `SproutNote::cm`.
+pub(crate) fn note_commitment(
+    a_pk: &[u8; 32],
+    value: u64,
+    rho: &[u8; 32],
+    r: &[u8; 32],
+) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(&[0xb0u8]);
+    hasher.update(a_pk);
+    hasher.update(&value.to_le_bytes());
+    hasher.update(rho);
+    hasher.update(r);
+
+    let mut cm = [0; 32];
+    cm.copy_from_slice(&hasher.finalize());
+    cm
+}
+
+/// The Sprout PRFs, matching `PRF` in zcashd's `prf.cpp`: `SHA256Compress` of `x` and
+/// `y`, with the four high bits of `x` replaced by the domain separator `tag`.
+///
+/// A key that is not a valid 252-bit spending key is therefore treated as if those
+/// bits were zero.
+fn prf(tag: u8, x: &[u8; 32], y: &[u8; 32]) -> [u8; 32] {
+    let mut block = [0; 64];
+    block[..32].copy_from_slice(x);
+    block[0] = (block[0] & 0x0f) | (tag << 4);
+    block[32..].copy_from_slice(y);
+    sha256_compress(&block)
+}
+
+/// Computes the nullifier of a Sprout note with `PRF^nf`, matching `PRF_nf`.
+pub(crate) fn nullifier(a_sk: &[u8; 32], rho: &[u8; 32]) -> [u8; 32] {
+    prf(0b1110, a_sk, rho)
+}
+
+/// Computes the paying key of the Sprout spending key `a_sk`, matching
+/// `PRF_addr_a_pk`.
+pub(crate) fn paying_key(a_sk: &[u8; 32]) -> [u8; 32] {
+    prf(0b1100, a_sk, &[0; 32])
+}
+
+/// Computes the receiving key of the Sprout spending key `a_sk`, matching
+/// `ZCNoteEncryption::generate_privkey`.
+pub(crate) fn receiving_key(a_sk: &[u8; 32]) -> [u8; 32] {
+    let mut y = [0; 32];
+    y[0] = 1;
+    let mut sk_enc = prf(0b1100, a_sk, &y);
+    sk_enc[0] &= 248;
+    sk_enc[31] &= 127;
+    sk_enc[31] |= 64;
+    sk_enc
+}
+
+/// Computes the transmission key for the receiving key `sk_enc`.
+pub(crate) fn transmission_key(sk_enc: &[u8; 32]) -> [u8; 32] {
+    x25519(sk_enc, &X25519_BASEPOINT.to_bytes())
+}
+
+/// Computes the MAC binding `h_sig` to the JoinSplit input at `index` with `PRF^pk`,
+/// matching `PRF_pk`.
+pub(crate) fn mac(a_sk: &[u8; 32], index: u8, h_sig: &[u8; 32]) -> [u8; 32] {
+    prf(index << 2, a_sk, h_sig)
+}
+
+/// Computes the rho of the JoinSplit output at `index` with `PRF^rho`, matching
+/// `PRF_rho`.
+pub(crate) fn output_rho(phi: &[u8; 32], index: u8, h_sig: &[u8; 32]) -> [u8; 32] {
+    prf((index << 2) | 0b0010, phi, h_sig)
+}
+
+/// Computes the commitment to the Sprout note with the given `a_pk`, `value`, `rho`
+/// and `r`, writing it to `cm_ret`.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_note_commitment(
+    a_pk: *const [u8; 32],
+    value: u64,
+    rho: *const [u8; 32],
+    r: *const [u8; 32],
+    cm_ret: *mut [u8; 32],
+) {
+    let cm = note_commitment(unsafe { &*a_pk }, value, unsafe { &*rho }, unsafe { &*r });
+    unsafe { *cm_ret = cm };
+}
+
+/// Computes the nullifier of a Sprout note from its `rho` and the spending key `a_sk`
+/// of its recipient, writing it to `nf_ret`.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_nullifier(
+    a_sk: *const [u8; 32],
+    rho: *const [u8; 32],
+    nf_ret: *mut [u8; 32],
+) {
+    let nf = nullifier(unsafe { &*a_sk }, unsafe { &*rho });
+    unsafe { *nf_ret = nf };
+}
+
+/// Computes `h_sig` for a JoinSplit, matching `ZCJoinSplit::h_sig`.
+pub(crate) fn h_sig(
+    random_seed: &[u8; 32],
+    nullifiers: [&[u8; 32]; 2],
+    joinsplit_pub_key: &[u8; 32],
+) -> [u8; 32] {
+    let mut state = Blake2bParams::new()
+        .hash_length(32)
+        .personal(b"ZcashComputehSig")
+        .to_state();
+    state.update(random_seed);
+    state.update(nullifiers[0]);
+    state.update(nullifiers[1]);
+    state.update(joinsplit_pub_key);
+
+    let mut h_sig = [0; 32];
+    h_sig.copy_from_slice(state.finalize().as_bytes());
+    h_sig
+}
+
+/// The fields of a JoinSplit that its proof is verified against.
+pub(crate) struct JoinSplitInputs {
+    pub(crate) rt: [u8; 32],
+    pub(crate) h_sig: [u8; 32],
+    pub(crate) nullifiers: [[u8; 32]; 2],
+    pub(crate) macs: [[u8; 32]; 2],
+    pub(crate) commitments: [[u8; 32]; 2],
+    pub(crate) vpub_old: u64,
+    pub(crate) vpub_new: u64,
+}
+
+impl JoinSplitInputs {
+    /// Returns the public inputs of the JoinSplit circuit, packed into 9 field elements
+    /// in the same way as `zcash_proofs::sprout::verify_proof`.
+    pub(crate) fn public_inputs(&self) -> Vec<bls12_381::Scalar> {
+        let mut bytes = Vec::with_capacity((32 * 8) + (8 * 2));
+        bytes.extend_from_slice(&self.rt);
+        bytes.extend_from_slice(&self.h_sig);
+        bytes.extend_from_slice(&self.nullifiers[0]);
+        bytes.extend_from_slice(&self.macs[0]);
+        bytes.extend_from_slice(&self.nullifiers[1]);
+        bytes.extend_from_slice(&self.macs[1]);
+        bytes.extend_from_slice(&self.commitments[0]);
+        bytes.extend_from_slice(&self.commitments[1]);
+        bytes.write_u64::<LittleEndian>(self.vpub_old).unwrap();
+        bytes.write_u64::<LittleEndian>(self.vpub_new).unwrap();
+
+        multipack::compute_multipacking(&multipack::bytes_to_bits(&bytes))
+    }
+}
+
+/// `sprout_batch_first_failure` result when no queued proof is known to be invalid.
+const SPROUT_BATCH_NO_FAILURE: u32 = u32::MAX;
+
+/// Batch validation context for Sprout JoinSplit proofs.
+pub struct SproutBatchValidator {
+    items: Vec<batch::Item<Bls12>>,
+    /// The queue position of the first invalid proof, if `validate` found one.
+    first_failure: Option<usize>,
+}
+
+impl SproutBatchValidator {
+    pub(crate) fn new() -> Self {
+        SproutBatchValidator {
+            items: vec![],
+            first_failure: None,
+        }
+    }
+
+    /// Queues a proof to be verified against the given public inputs.
+    pub(crate) fn queue(&mut self, proof: Proof<Bls12>, public_inputs: Vec<bls12_381::Scalar>) {
+        self.items.push((proof, public_inputs).into());
+    }
+
+    /// Verifies the queued proofs with the Sprout verifying key.
+    fn validate(&mut self) -> bool {
+        self.validate_with(
+            unsafe { crate::SPROUT_GROTH16_BATCH_VK.as_ref() }
+                .expect("parameters should have been initialized"),
+        )
+    }
+
+    /// Verifies the queued proofs with `vk`. If the batch fails, the proofs are checked
+    /// in sub-batches to find the first invalid one, which `first_failure` then returns.
+    pub(crate) fn validate_with(&mut self, vk: &VerifyingKey<Bls12>) -> bool {
+        self.first_failure = None;
+        if should_fail(FaultSite::BatchValidation) {
+            error!("Sprout batch validation failed: injected failure");
+            return false;
+        }
+        if self.items.is_empty() {
+            // An empty batch is always valid, but is not free to run; skip it.
+            return true;
+        }
+
+        if batch_verifies(&self.items, vk) {
+            return true;
+        }
+
+        // Invalid batches are rare (they make the whole block invalid), so the extra
+        // verifications only cost anything when there is a failure to attribute.
+        let pvk = prepare_verifying_key(vk);
+        self.first_failure = first_failure(&self.items, vk, &pvk);
+        match self.first_failure {
+            Some(index) => error!("Sprout proof {} in the batch is invalid", index),
+            // Batch verification is randomized, so a sub-batch can pass even though the
+            // full batch failed; the batch is still invalid.
+            None => error!("Sprout batch validation failed, but no invalid proof was found"),
+        }
+        false
+    }
+
+    /// Returns the queue position of the first invalid proof found by the last
+    /// validation.
+    pub(crate) fn first_failure(&self) -> Option<usize> {
+        self.first_failure
+    }
+}
+
+/// Returns whether every proof in `items` is valid.
+fn batch_verifies(items: &[batch::Item<Bls12>], vk: &VerifyingKey<Bls12>) -> bool {
+    let mut verifier = batch::Verifier::new();
+    for item in items {
+        verifier.queue(item.clone());
+    }
+
+    // Failing here would treat a valid block as invalid, so this still aborts if no
+    // randomness can be obtained, as Orchard batch validation does.
+    let rng = rng::rng().expect("randomness should be available");
+    verifier.verify(rng, vk).is_ok()
+}
+
+/// Finds the first invalid proof in `items`, which are known to contain at least one, by
+/// bisecting them.
+fn first_failure(
+    items: &[batch::Item<Bls12>],
+    vk: &VerifyingKey<Bls12>,
+    pvk: &PreparedVerifyingKey<Bls12>,
+) -> Option<usize> {
+    match items {
+        [] => None,
+        [item] => item.clone().verify_single(pvk).is_err().then(|| 0),
+        _ => {
+            let (left, right) = items.split_at(items.len() / 2);
+            if batch_verifies(left, vk) {
+                first_failure(right, vk, pvk).map(|index| left.len() + index)
+            } else {
+                first_failure(left, vk, pvk)
+            }
+        }
+    }
+}
+
+/// Creates a Sprout proof batch validation context.
+///
+/// Please free this when you're done.
+#[no_mangle]
+pub extern "C" fn sprout_batch_validation_init() -> *mut SproutBatchValidator {
+    let ctx = Box::new(SproutBatchValidator::new());
+    Box::into_raw(ctx)
+}
+
+/// Frees a Sprout proof batch validation context returned from
+/// [`sprout_batch_validation_init`].
+#[no_mangle]
+pub extern "C" fn sprout_batch_validation_free(ctx: *mut SproutBatchValidator) {
+    if !ctx.is_null() {
+        drop(unsafe { Box::from_raw(ctx) });
+    }
+}
+
+/// Adds the Groth16 proof of a JoinSplit to this batch, computing its public inputs from
+/// the fields of the JoinSplit and the transaction's `joinSplitPubKey`.
+///
+/// Returns `false` if the proof is not a valid encoding, in which case nothing is
+/// queued. If `batch` is null, this does nothing and returns `true`.
+#[no_mangle]
+pub extern "C" fn sprout_batch_add_joinsplit(
+    batch: *mut SproutBatchValidator,
+    proof: *const [u8; GROTH_PROOF_SIZE],
+    anchor: *const [u8; 32],
+    random_seed: *const [u8; 32],
+    nf1: *const [u8; 32],
+    nf2: *const [u8; 32],
+    mac1: *const [u8; 32],
+    mac2: *const [u8; 32],
+    cm1: *const [u8; 32],
+    cm2: *const [u8; 32],
+    vpub_old: u64,
+    vpub_new: u64,
+    joinsplit_pub_key: *const [u8; 32],
+) -> bool {
+    let batch = match unsafe { batch.as_mut() } {
+        Some(batch) => batch,
+        None => {
+            debug!("Sprout BatchValidator not provided, assuming disabled.");
+            return true;
+        }
+    };
+
+    let proof = match Proof::read(&unsafe { &*proof }[..]) {
+        Ok(proof) => proof,
+        Err(e) => {
+            error!("Invalid Sprout proof: {}", e);
+            return false;
+        }
+    };
+
+    let (nf1, nf2) = unsafe { (*nf1, *nf2) };
+    let h_sig = h_sig(unsafe { &*random_seed }, [&nf1, &nf2], unsafe {
+        &*joinsplit_pub_key
+    });
+    let inputs = JoinSplitInputs {
+        rt: unsafe { *anchor },
+        h_sig,
+        nullifiers: [nf1, nf2],
+        macs: unsafe { [*mac1, *mac2] },
+        commitments: unsafe { [*cm1, *cm2] },
+        vpub_old,
+        vpub_new,
+    };
+    batch.queue(proof, inputs.public_inputs());
+    true
+}
+
+/// Validates this batch.
+///
+/// - Returns `true` if `batch` is null.
+/// - Returns `false` if any proof in the batch is invalid.
+#[no_mangle]
+pub extern "C" fn sprout_batch_validate(batch: *mut SproutBatchValidator) -> bool {
+    if let Some(batch) = unsafe { batch.as_mut() } {
+        batch.validate()
+    } else {
+        // The ProofVerifier C++ class uses null to represent a disabled batch validator.
+        debug!("Sprout BatchValidator not provided, assuming disabled.");
+        true
+    }
+}
+
+/// Returns the position in the order they were queued of the first invalid proof found
+/// by `sprout_batch_validate`, or `u32::MAX` if none was found.
+#[no_mangle]
+pub extern "C" fn sprout_batch_first_failure(batch: *const SproutBatchValidator) -> u32 {
+    unsafe { batch.as_ref() }
+        .and_then(|batch| batch.first_failure())
+        .map_or(SPROUT_BATCH_NO_FAILURE, |index| index as u32)
+}
+
+/// The smallest amount that ZIP 308 can choose for a migration transaction, in zatoshis.
+const MIGRATION_MIN_AMOUNT: u64 = 1_000_000;
+
+/// Chooses the amount of a Sprout-to-Sapling migration transaction, including its fee,
+/// with the algorithm specified in ZIP 308.
+///
+/// Returns `None` if less than [`MIGRATION_MIN_AMOUNT`] is `available`, in which case
+/// no amount can be chosen.
+pub(crate) fn migration_amount<R: Rng>(available: u64, rng: &mut R) -> Option<u64> {
+    if available < MIGRATION_MIN_AMOUNT {
+        return None;
+    }
+
+    loop {
+        let exponent = rng.gen_range(6..=8);
+        let mantissa: u64 = rng.gen_range(1..=99);
+        let amount = mantissa * 10u64.pow(exponent);
+        // Amounts larger than the remaining funds are discarded and chosen again.
+        if amount <= available {
+            return Some(amount);
+        }
+    }
+}
+
+/// Chooses the amount of a Sprout-to-Sapling migration transaction, including its fee,
+/// as specified in ZIP 308, given the `available` Sprout funds.
+///
+/// Returns `false` if less than 0.01 ZEC is available, or if no randomness could be
+/// obtained.
+#[no_mangle]
+pub extern "C" fn librustzcash_sprout_migration_amount(
+    available: u64,
+    amount_ret: *mut u64,
+) -> bool {
+    let mut rng = match rng::rng() {
+        Ok(rng) => rng,
+        Err(e) => {
+            error!("Couldn't obtain randomness: {}", e);
+            return false;
+        }
+    };
+
+    match migration_amount(available, &mut rng) {
+        Some(amount) => {
+            unsafe { *amount_ret = amount };
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,492 @@
+//! Creation of Sprout JoinSplit proofs.
+//!
+//! The Sprout proving parameters are read from `sprout-groth16.params` for each proof.
+//! By default they're streamed: each multi-exponentiation reads its base points from
+//! the file as it reaches them, through buffers that together stay within a
+//! configurable budget, instead of the whole file being deserialized into memory
+//! before proving starts. The parameters can instead be loaded into memory, which is
+//! faster but holds all of them for the duration of the proof.
+//!
+//! Both modes prove the same circuit against the same parameters, so their proofs are
+//! interchangeable.
+
+use std::cmp;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufReader, Seek};
+use std::marker::PhantomData;
+use std::ops::AddAssign;
+use std::path::Path;
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc,
+};
+use std::thread;
+
+use bellman::{
+    groth16::{create_random_proof, ParameterSource, Parameters, Proof, VerifyingKey},
+    multiexp::{Source, SourceBuilder},
+    Circuit, SynthesisError,
+};
+use bls12_381::{Bls12, G1Affine, G2Affine};
+use group::prime::PrimeCurveAffine;
+use rand_core::RngCore;
+use zcash_proofs::{
+    circuit::sprout::{
+        CommitmentRandomness, JoinSplit, JsInput, JsOutput, PayingKey, SpendingKey,
+        UniqueRandomness, TREE_DEPTH,
+    },
+    sprout::WITNESS_PATH_SIZE,
+};
+
+use crate::proving_scratch::{self, ScratchBuf};
+
+/// How the Sprout proving parameters are read.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum ProverMode {
+    /// Deserialize the whole parameters file before proving.
+    InMemory,
+    /// Read the parameters from the file as the prover needs them.
+    Streamed,
+}
+
+impl ProverMode {
+    /// Returns the mode with the given `librustzcash_sprout_prover_mode` value.
+    pub(crate) fn from_u32(mode: u32) -> Option<Self> {
+        match mode {
+            0 => Some(ProverMode::InMemory),
+            1 => Some(ProverMode::Streamed),
+            _ => None,
+        }
+    }
+}
+
+/// The default total size of the buffers that streamed parameters are read through.
+pub(crate) const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+static MODE: AtomicU32 = AtomicU32::new(1);
+static BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_BUDGET);
+
+/// Sets how the parameters are read for subsequent proofs.
+pub(crate) fn set_mode(mode: ProverMode) {
+    MODE.store(mode as u32, Ordering::Relaxed);
+}
+
+/// Returns how the parameters are read.
+pub(crate) fn mode() -> ProverMode {
+    ProverMode::from_u32(MODE.load(Ordering::Relaxed)).expect("only valid modes are stored")
+}
+
+/// Sets the total size in bytes of the buffers that streamed parameters are read
+/// through.
+pub(crate) fn set_budget(budget: usize) {
+    BUDGET.store(budget, Ordering::Relaxed);
+}
+
+/// Returns the total size in bytes of the buffers that streamed parameters are read
+/// through.
+pub(crate) fn budget() -> usize {
+    BUDGET.load(Ordering::Relaxed)
+}
+
+/// A curve point that the parameters file stores uncompressed.
+pub(crate) trait StreamedPoint: PrimeCurveAffine {
+    /// The length of the uncompressed encoding.
+    const SIZE: usize;
+
+    /// Decodes the point, without checking that it is in the prime-order subgroup
+    /// (matching `Parameters::read(_, false)`).
+    fn from_uncompressed(bytes: &[u8]) -> Option<Self>;
+}
+
+impl StreamedPoint for G1Affine {
+    const SIZE: usize = 96;
+
+    fn from_uncompressed(bytes: &[u8]) -> Option<Self> {
+        Option::from(G1Affine::from_uncompressed_unchecked(
+            bytes.try_into().ok()?,
+        ))
+    }
+}
+
+impl StreamedPoint for G2Affine {
+    const SIZE: usize = 192;
+
+    fn from_uncompressed(bytes: &[u8]) -> Option<Self> {
+        Option::from(G2Affine::from_uncompressed_unchecked(
+            bytes.try_into().ok()?,
+        ))
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn read_exact_at(file: &File, buf: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buf, offset)
+}
+
+#[cfg(target_os = "windows")]
+fn read_exact_at(file: &File, mut buf: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !buf.is_empty() {
+        match file.seek_read(buf, offset) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => {
+                let rest = buf;
+                buf = &mut rest[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The position of a list of points in the parameters file.
+#[derive(Clone, Copy, Debug)]
+struct Section {
+    /// The offset of the first point.
+    start: u64,
+    /// The number of points.
+    len: usize,
+}
+
+/// Reads the length of the section at `offset`, and advances `offset` past it.
+fn read_section(file: &File, offset: &mut u64, point_size: usize) -> io::Result<Section> {
+    let mut len = [0; 4];
+    read_exact_at(file, &mut len, *offset)?;
+    let section = Section {
+        start: *offset + 4,
+        len: u32::from_be_bytes(len) as usize,
+    };
+    *offset = section.start + (section.len * point_size) as u64;
+    Ok(section)
+}
+
+fn expected_more_bases() -> SynthesisError {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "expected more bases from source",
+    )
+    .into()
+}
+
+/// The bases for a multi-exponentiation, starting at point `first` of a section.
+pub(crate) struct StreamedBases<G> {
+    file: Arc<File>,
+    section: Section,
+    first: usize,
+    /// How many points each reader buffers at a time.
+    buffer_points: usize,
+    _point: PhantomData<fn() -> G>,
+}
+
+impl<G> Clone for StreamedBases<G> {
+    fn clone(&self) -> Self {
+        StreamedBases {
+            file: self.file.clone(),
+            section: self.section,
+            first: self.first,
+            buffer_points: self.buffer_points,
+            _point: PhantomData,
+        }
+    }
+}
+
+impl<G> SourceBuilder<G> for StreamedBases<G>
+where
+    G: StreamedPoint,
+    G::Curve: for<'a> AddAssign<&'a G>,
+{
+    type Source = BasesReader<G>;
+
+    fn new(self) -> BasesReader<G> {
+        let points = cmp::min(
+            self.buffer_points,
+            self.section.len.saturating_sub(self.first),
+        );
+        BasesReader {
+            next: self.first,
+            buf_first: self.first,
+            buf: proving_scratch::take(points * G::SIZE),
+            bases: self,
+        }
+    }
+}
+
+/// Reads the bases of a multi-exponentiation in order.
+pub(crate) struct BasesReader<G> {
+    bases: StreamedBases<G>,
+    /// The index of the next point in the section.
+    next: usize,
+    /// The index in the section of the first point in `buf`.
+    buf_first: usize,
+    buf: ScratchBuf,
+}
+
+impl<G: StreamedPoint> BasesReader<G> {
+    fn read_next(&mut self) -> Result<G, SynthesisError> {
+        // Points are only ever read in order, so refill the buffer once it is used up.
+        if self.next >= self.buf_first + self.buf.len() / G::SIZE {
+            let count = cmp::min(self.bases.buffer_points, self.bases.section.len - self.next);
+            self.buf.resize(count * G::SIZE, 0);
+            read_exact_at(
+                &self.bases.file,
+                &mut self.buf,
+                self.bases.section.start + (self.next * G::SIZE) as u64,
+            )?;
+            self.buf_first = self.next;
+        }
+
+        let at = (self.next - self.buf_first) * G::SIZE;
+        G::from_uncompressed(&self.buf[at..at + G::SIZE]).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "invalid point in parameters").into()
+        })
+    }
+}
+
+impl<G> Source<G> for BasesReader<G>
+where
+    G: StreamedPoint,
+    G::Curve: for<'a> AddAssign<&'a G>,
+{
+    fn add_assign_mixed(&mut self, to: &mut G::Curve) -> Result<(), SynthesisError> {
+        if self.next >= self.bases.section.len {
+            return Err(expected_more_bases());
+        }
+        let point = self.read_next()?;
+        if point.is_identity().into() {
+            return Err(SynthesisError::UnexpectedIdentity);
+        }
+        to.add_assign(&point);
+        self.next += 1;
+        Ok(())
+    }
+
+    fn skip(&mut self, amt: usize) -> Result<(), SynthesisError> {
+        if self.next >= self.bases.section.len {
+            return Err(expected_more_bases());
+        }
+        self.next += amt;
+        Ok(())
+    }
+}
+
+/// Groth16 proving parameters that are read from their file as the prover needs them.
+pub(crate) struct StreamedParameters {
+    file: Arc<File>,
+    vk: VerifyingKey<Bls12>,
+    h: Section,
+    l: Section,
+    a: Section,
+    b_g1: Section,
+    b_g2: Section,
+    /// The size of each reader's buffer.
+    reader_budget: usize,
+}
+
+impl StreamedParameters {
+    /// Opens the parameters file at `path`, reading only its verifying key and the
+    /// lengths of its sections.
+    ///
+    /// `budget` is shared between the readers that the prover's threads have open at
+    /// once, so it bounds the memory used to read the file.
+    pub(crate) fn open(path: &Path, budget: usize) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let mut reader = BufReader::new(&file);
+        let vk = VerifyingKey::read(&mut reader)?;
+        let mut offset = reader.stream_position()?;
+
+        let h = read_section(&file, &mut offset, G1Affine::SIZE)?;
+        let l = read_section(&file, &mut offset, G1Affine::SIZE)?;
+        let a = read_section(&file, &mut offset, G1Affine::SIZE)?;
+        let b_g1 = read_section(&file, &mut offset, G1Affine::SIZE)?;
+        let b_g2 = read_section(&file, &mut offset, G2Affine::SIZE)?;
+        if offset > file.metadata()?.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "parameters file is truncated",
+            ));
+        }
+
+        let threads = thread::available_parallelism().map_or(1, |threads| threads.get());
+        Ok(StreamedParameters {
+            file: Arc::new(file),
+            vk,
+            h,
+            l,
+            a,
+            b_g1,
+            b_g2,
+            reader_budget: budget / threads,
+        })
+    }
+
+    fn bases<G: StreamedPoint>(&self, section: Section, first: usize) -> StreamedBases<G> {
+        StreamedBases {
+            file: self.file.clone(),
+            section,
+            first,
+            buffer_points: cmp::max(self.reader_budget / G::SIZE, 1),
+            _point: PhantomData,
+        }
+    }
+}
+
+impl<'a> ParameterSource<Bls12> for &'a StreamedParameters {
+    type G1Builder = StreamedBases<G1Affine>;
+    type G2Builder = StreamedBases<G2Affine>;
+
+    fn get_vk(&mut self, _: usize) -> Result<VerifyingKey<Bls12>, SynthesisError> {
+        Ok(self.vk.clone())
+    }
+
+    fn get_h(&mut self, _: usize) -> Result<Self::G1Builder, SynthesisError> {
+        Ok(self.bases(self.h, 0))
+    }
+
+    fn get_l(&mut self, _: usize) -> Result<Self::G1Builder, SynthesisError> {
+        Ok(self.bases(self.l, 0))
+    }
+
+    fn get_a(
+        &mut self,
+        num_inputs: usize,
+        _: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        Ok((self.bases(self.a, 0), self.bases(self.a, num_inputs)))
+    }
+
+    fn get_b_g1(
+        &mut self,
+        num_inputs: usize,
+        _: usize,
+    ) -> Result<(Self::G1Builder, Self::G1Builder), SynthesisError> {
+        Ok((self.bases(self.b_g1, 0), self.bases(self.b_g1, num_inputs)))
+    }
+
+    fn get_b_g2(
+        &mut self,
+        num_inputs: usize,
+        _: usize,
+    ) -> Result<(Self::G2Builder, Self::G2Builder), SynthesisError> {
+        Ok((self.bases(self.b_g2, 0), self.bases(self.b_g2, num_inputs)))
+    }
+}
+
+/// Proves `circuit` with the parameters in the file at `params_path`, read according
+/// to `mode`. `budget` bounds the buffers used by [`ProverMode::Streamed`].
+pub(crate) fn prove<C, R>(
+    circuit: C,
+    params_path: &Path,
+    mode: ProverMode,
+    budget: usize,
+    rng: &mut R,
+) -> Result<Proof<Bls12>, SynthesisError>
+where
+    C: Circuit<bls12_381::Scalar>,
+    R: RngCore,
+{
+    match mode {
+        ProverMode::InMemory => {
+            let mut file = BufReader::with_capacity(1024 * 1024, File::open(params_path)?);
+            let params = Parameters::<Bls12>::read(&mut file, false)?;
+            drop(file);
+            create_random_proof(circuit, &params, rng)
+        }
+        ProverMode::Streamed => {
+            let params = StreamedParameters::open(params_path, budget)?;
+            create_random_proof(circuit, &params, rng)
+        }
+    }
+}
+
+/// Returns the JoinSplit circuit for the given witness, decoding each input's
+/// authentication path from its serialized `libzcash::SproutWitness` form.
+///
+/// This is the circuit that `zcash_proofs::sprout::create_proof` proves, which only
+/// accepts in-memory parameters.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn joinsplit(
+    phi: [u8; 32],
+    rt: [u8; 32],
+    h_sig: [u8; 32],
+
+    // First input
+    in_sk1: [u8; 32],
+    in_value1: u64,
+    in_rho1: [u8; 32],
+    in_r1: [u8; 32],
+    in_auth1: &[u8; WITNESS_PATH_SIZE],
+
+    // Second input
+    in_sk2: [u8; 32],
+    in_value2: u64,
+    in_rho2: [u8; 32],
+    in_r2: [u8; 32],
+    in_auth2: &[u8; WITNESS_PATH_SIZE],
+
+    // First output
+    out_pk1: [u8; 32],
+    out_value1: u64,
+    out_r1: [u8; 32],
+
+    // Second output
+    out_pk2: [u8; 32],
+    out_value2: u64,
+    out_r2: [u8; 32],
+
+    // Public value
+    vpub_old: u64,
+    vpub_new: u64,
+) -> JoinSplit {
+    let input = |sk, value, rho, r, auth: &[u8; WITNESS_PATH_SIZE]| {
+        // The path is serialized as its depth, then each sibling from the root down
+        // (prefixed with its length), then the position of the note.
+        assert_eq!(auth[0], TREE_DEPTH as u8);
+        let mut auth = &auth[1..];
+        let mut auth_path = [None; TREE_DEPTH];
+        for entry in auth_path.iter_mut().rev() {
+            assert_eq!(auth[0], 32);
+            let mut sibling = [0; 32];
+            sibling.copy_from_slice(&auth[1..33]);
+            auth = &auth[33..];
+            *entry = Some((sibling, false));
+        }
+        let mut position = u64::from_le_bytes(auth[..8].try_into().unwrap());
+        for (_, is_right) in auth_path.iter_mut().flatten() {
+            *is_right = position & 1 == 1;
+            position >>= 1;
+        }
+
+        JsInput {
+            value: Some(value),
+            a_sk: Some(SpendingKey(sk)),
+            rho: Some(UniqueRandomness(rho)),
+            r: Some(CommitmentRandomness(r)),
+            auth_path,
+        }
+    };
+    let output = |pk, value, r| JsOutput {
+        value: Some(value),
+        a_pk: Some(PayingKey(pk)),
+        r: Some(CommitmentRandomness(r)),
+    };
+
+    JoinSplit {
+        vpub_old: Some(vpub_old),
+        vpub_new: Some(vpub_new),
+        h_sig: Some(h_sig),
+        phi: Some(phi),
+        inputs: vec![
+            input(in_sk1, in_value1, in_rho1, in_r1, in_auth1),
+            input(in_sk2, in_value2, in_rho2, in_r2, in_auth2),
+        ],
+        outputs: vec![
+            output(out_pk1, out_value1, out_r1),
+            output(out_pk2, out_value2, out_r2),
+        ],
+        rt: Some(rt),
+    }
+}
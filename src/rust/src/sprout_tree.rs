@@ -0,0 +1,406 @@
+//! The Sprout note commitment tree, and witnesses to the notes in it.
+//!
+//! These are ports of `libzcash::IncrementalMerkleTree` and `libzcash::IncrementalWitness`
+//! for `SHA256Compress`, with the same encodings, so that the witnesses the wallet stores
+//! can be read here and extended as JoinSplits within a transaction are chained.
+
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+use zcash_encoding::{Optional, Vector};
+
+use crate::sprout_ffi::sha256_compress;
+
+/// The depth of the Sprout note commitment tree.
+pub(crate) const SPROUT_TREE_DEPTH: usize = 29;
+
+/// A node of the Sprout note commitment tree.
+pub(crate) type Node = [u8; 32];
+
+/// Combines two nodes with `SHA256Compress`. Unlike the Sapling and Orchard trees, the
+/// hash does not depend on the height of the nodes.
+pub(crate) fn combine(left: &Node, right: &Node) -> Node {
+    let mut block = [0; 64];
+    block[..32].copy_from_slice(left);
+    block[32..].copy_from_slice(right);
+    sha256_compress(&block)
+}
+
+/// Returns the root of an empty subtree of the given height. The empty leaf is all
+/// zeroes.
+pub(crate) fn empty_root(height: usize) -> Node {
+    (0..height).fold([0; 32], |root, _| combine(&root, &root))
+}
+
+fn read_node<R: Read>(mut reader: R) -> io::Result<Node> {
+    let mut node = [0; 32];
+    reader.read_exact(&mut node)?;
+    Ok(node)
+}
+
+#[cfg(test)]
+fn write_node<W: Write>(mut writer: W, node: &Node) -> io::Result<()> {
+    writer.write_all(node)
+}
+
+/// Supplies the roots of the subtrees that a tree doesn't contain, in order of height,
+/// falling back to empty subtrees (`PathFiller` in zcashd).
+struct PathFiller {
+    queue: VecDeque<Node>,
+}
+
+impl PathFiller {
+    fn new(queue: VecDeque<Node>) -> Self {
+        PathFiller { queue }
+    }
+
+    fn next(&mut self, height: usize) -> Node {
+        self.queue.pop_front().unwrap_or_else(|| empty_root(height))
+    }
+}
+
+/// An authentication path from a leaf to the root of a tree of depth `DEPTH`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct MerklePath<const DEPTH: usize> {
+    /// The siblings of the path, from the leaf's sibling up to the child of the root.
+    pub(crate) auth_path: [Node; DEPTH],
+    pub(crate) position: u64,
+}
+
+impl<const DEPTH: usize> MerklePath<DEPTH> {
+    /// Returns the root that this path authenticates `leaf` to.
+    #[cfg(test)]
+    pub(crate) fn root(&self, leaf: &Node) -> Node {
+        self.auth_path
+            .iter()
+            .enumerate()
+            .fold(*leaf, |node, (height, sibling)| {
+                if (self.position >> height) & 1 == 1 {
+                    combine(sibling, &node)
+                } else {
+                    combine(&node, sibling)
+                }
+            })
+    }
+
+    /// Writes this path in the encoding of `libzcash::MerklePath`, which is what the
+    /// JoinSplit prover reads: the siblings from the root down, each as a byte vector,
+    /// then the position.
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let siblings: Vec<_> = self.auth_path.iter().rev().collect();
+        Vector::write(&mut writer, &siblings, |w, sibling| {
+            Vector::write(w, &sibling[..], |w, b| w.write_all(&[*b]))
+        })?;
+        writer.write_all(&self.position.to_le_bytes())
+    }
+}
+
+/// An append-only Merkle tree of depth `DEPTH` that stores only what is needed to
+/// append leaves and compute its root.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SproutTree<const DEPTH: usize> {
+    left: Option<Node>,
+    right: Option<Node>,
+    /// The roots of the completed left subtrees, ordered toward the root of the tree.
+    parents: Vec<Option<Node>>,
+}
+
+impl<const DEPTH: usize> Default for SproutTree<DEPTH> {
+    fn default() -> Self {
+        SproutTree {
+            left: None,
+            right: None,
+            parents: vec![],
+        }
+    }
+}
+
+impl<const DEPTH: usize> SproutTree<DEPTH> {
+    /// Returns the number of leaves in the tree.
+    #[cfg(test)]
+    pub(crate) fn size(&self) -> u64 {
+        self.parents
+            .iter()
+            .enumerate()
+            .filter(|(_, parent)| parent.is_some())
+            .fold(
+                self.left.iter().count() as u64 + self.right.iter().count() as u64,
+                |size, (i, _)| size + (1 << (i + 1)),
+            )
+    }
+
+    /// Returns the most recently appended leaf.
+    pub(crate) fn last(&self) -> Option<Node> {
+        self.right.or(self.left)
+    }
+
+    /// Returns whether the subtree of height `height` at the start of this tree is
+    /// complete.
+    fn is_complete(&self, height: usize) -> bool {
+        self.left.is_some()
+            && self.right.is_some()
+            && self.parents.len() == height - 1
+            && self.parents.iter().all(|parent| parent.is_some())
+    }
+
+    /// Appends a leaf to the tree, returning `false` if it is full.
+    pub(crate) fn append(&mut self, node: Node) -> bool {
+        if self.is_complete(DEPTH) {
+            return false;
+        }
+
+        match (self.left, self.right) {
+            (None, _) => self.left = Some(node),
+            (Some(_), None) => self.right = Some(node),
+            (Some(left), Some(right)) => {
+                let mut combined = combine(&left, &right);
+                self.left = Some(node);
+                self.right = None;
+
+                for i in 0..DEPTH {
+                    match self.parents.get_mut(i) {
+                        Some(slot) => match slot.take() {
+                            Some(parent) => combined = combine(&parent, &combined),
+                            None => {
+                                *slot = Some(combined);
+                                break;
+                            }
+                        },
+                        None => {
+                            self.parents.push(Some(combined));
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Returns the height of the next incomplete subtree after the first `skip` that a
+    /// witness to the last leaf has been given roots for.
+    fn next_height(&self, mut skip: usize) -> usize {
+        let mut take = |present: bool| {
+            if present {
+                false
+            } else if skip > 0 {
+                skip -= 1;
+                false
+            } else {
+                true
+            }
+        };
+
+        if take(self.left.is_some()) || take(self.right.is_some()) {
+            return 0;
+        }
+        for (i, parent) in self.parents.iter().enumerate() {
+            if take(parent.is_some()) {
+                return i + 1;
+            }
+        }
+        self.parents.len() + 1 + skip
+    }
+
+    /// Returns the root of the subtree of height `height` at the start of this tree,
+    /// taking the roots of the subtrees it doesn't contain from `filler`.
+    fn root_with(&self, height: usize, filler: &mut PathFiller) -> Node {
+        let left = self.left.unwrap_or_else(|| filler.next(0));
+        let right = self.right.unwrap_or_else(|| filler.next(0));
+        let mut root = combine(&left, &right);
+
+        let mut d = 1;
+        for parent in &self.parents {
+            root = match parent {
+                Some(parent) => combine(parent, &root),
+                None => combine(&root, &filler.next(d)),
+            };
+            d += 1;
+        }
+        while d < height {
+            root = combine(&root, &filler.next(d));
+            d += 1;
+        }
+        root
+    }
+
+    /// Returns the root of the tree.
+    pub(crate) fn root(&self) -> Node {
+        self.root_with(DEPTH, &mut PathFiller::new(VecDeque::new()))
+    }
+
+    /// Returns the authentication path of the last leaf, taking the siblings that this
+    /// tree doesn't contain from `filler`.
+    fn path_with(&self, filler: &mut PathFiller) -> Option<MerklePath<DEPTH>> {
+        let left = self.left?;
+
+        let mut auth_path = [[0; 32]; DEPTH];
+        let mut position = 0;
+        auth_path[0] = match self.right {
+            Some(_) => {
+                position |= 1;
+                left
+            }
+            None => filler.next(0),
+        };
+        for (d, sibling) in auth_path.iter_mut().enumerate().skip(1) {
+            *sibling = match self.parents.get(d - 1) {
+                Some(Some(parent)) => {
+                    position |= 1 << d;
+                    *parent
+                }
+                _ => filler.next(d),
+            };
+        }
+
+        Some(MerklePath {
+            auth_path,
+            position,
+        })
+    }
+
+    /// Returns a witness to the last leaf appended to the tree.
+    pub(crate) fn witness(&self) -> SproutWitness<DEPTH> {
+        SproutWitness {
+            tree: self.clone(),
+            filled: vec![],
+            cursor: None,
+            cursor_height: 0,
+        }
+    }
+
+    /// Reads a tree in the encoding of zcashd's `SproutMerkleTree`.
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let left = Optional::read(&mut reader, read_node)?;
+        let right = Optional::read(&mut reader, read_node)?;
+        let parents = Vector::read(&mut reader, |r| Optional::read(r, read_node))?;
+        let tree = SproutTree {
+            left,
+            right,
+            parents,
+        };
+
+        // The same checks as `IncrementalMerkleTree::wfcheck`.
+        let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        if tree.parents.len() >= DEPTH {
+            invalid("tree has too many parents")
+        } else if matches!(tree.parents.last(), Some(None)) {
+            invalid("tree has non-canonical representation of parent")
+        } else if tree.left.is_none() && (tree.right.is_some() || !tree.parents.is_empty()) {
+            invalid("tree has non-canonical representation of its leaves")
+        } else {
+            Ok(tree)
+        }
+    }
+
+    /// Writes the tree in the encoding of zcashd's `SproutMerkleTree`.
+    #[cfg(test)]
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        Optional::write(&mut writer, self.left.as_ref(), write_node)?;
+        Optional::write(&mut writer, self.right.as_ref(), write_node)?;
+        Vector::write(&mut writer, &self.parents, |w, parent| {
+            Optional::write(w, parent.as_ref(), write_node)
+        })
+    }
+}
+
+/// A witness to a leaf of a tree of depth `DEPTH`, which is kept up to date as leaves
+/// are appended to the tree.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct SproutWitness<const DEPTH: usize> {
+    /// The tree as of the witnessed leaf.
+    tree: SproutTree<DEPTH>,
+    /// The roots of the subtrees appended since then that have been completed.
+    filled: Vec<Node>,
+    /// The subtree that is being filled.
+    cursor: Option<SproutTree<DEPTH>>,
+    cursor_height: usize,
+}
+
+impl<const DEPTH: usize> SproutWitness<DEPTH> {
+    fn filler(&self) -> PathFiller {
+        let mut uncles: VecDeque<_> = self.filled.iter().cloned().collect();
+        if let Some(cursor) = &self.cursor {
+            uncles.push_back(
+                cursor.root_with(self.cursor_height, &mut PathFiller::new(VecDeque::new())),
+            );
+        }
+        PathFiller::new(uncles)
+    }
+
+    /// Returns the witnessed leaf.
+    pub(crate) fn element(&self) -> Option<Node> {
+        self.tree.last()
+    }
+
+    /// Returns the position of the witnessed leaf.
+    #[cfg(test)]
+    pub(crate) fn position(&self) -> Option<u64> {
+        self.tree.size().checked_sub(1)
+    }
+
+    /// Returns the root of the tree that this witness is up to date with.
+    pub(crate) fn root(&self) -> Node {
+        self.tree.root_with(DEPTH, &mut self.filler())
+    }
+
+    /// Returns the authentication path of the witnessed leaf, or `None` if this is a
+    /// witness to an empty tree.
+    pub(crate) fn path(&self) -> Option<MerklePath<DEPTH>> {
+        self.tree.path_with(&mut self.filler())
+    }
+
+    /// Updates the witness with a leaf appended to the tree, returning `false` if the
+    /// tree is full.
+    pub(crate) fn append(&mut self, node: Node) -> bool {
+        match &mut self.cursor {
+            Some(cursor) => {
+                cursor.append(node);
+                if cursor.is_complete(self.cursor_height) {
+                    let root =
+                        cursor.root_with(self.cursor_height, &mut PathFiller::new(VecDeque::new()));
+                    self.filled.push(root);
+                    self.cursor = None;
+                }
+            }
+            None => {
+                self.cursor_height = self.tree.next_height(self.filled.len());
+                if self.cursor_height >= DEPTH {
+                    return false;
+                }
+                if self.cursor_height == 0 {
+                    self.filled.push(node);
+                } else {
+                    let mut cursor = SproutTree::default();
+                    cursor.append(node);
+                    self.cursor = Some(cursor);
+                }
+            }
+        }
+        true
+    }
+
+    /// Reads a witness in the encoding of zcashd's `SproutWitness`.
+    pub(crate) fn read<R: Read>(mut reader: R) -> io::Result<Self> {
+        let tree = SproutTree::read(&mut reader)?;
+        let filled = Vector::read(&mut reader, |r| read_node(r))?;
+        let cursor = Optional::read(&mut reader, SproutTree::read)?;
+        let cursor_height = tree.next_height(filled.len());
+        Ok(SproutWitness {
+            tree,
+            filled,
+            cursor,
+            cursor_height,
+        })
+    }
+
+    /// Writes the witness in the encoding of zcashd's `SproutWitness`.
+    #[cfg(test)]
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        self.tree.write(&mut writer)?;
+        Vector::write(&mut writer, &self.filled, |w, node| write_node(w, node))?;
+        Optional::write(&mut writer, self.cursor.as_ref(), |w, cursor| {
+            cursor.write(w)
+        })
+    }
+}
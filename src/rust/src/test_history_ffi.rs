@@ -0,0 +1,378 @@
+//! Randomized consistency checks of the history tree FFIs.
+//!
+//! zcashd persists the nodes of the history tree itself, and only passes the peaks (and
+//! for deletes, the extras) to `librustzcash_mmr_append` and `librustzcash_mmr_delete`.
+//! This drives both FFIs through long random sequences of appends and reorgs the way
+//! zcashd does, keeping the nodes in a store of its own, and checks after every
+//! operation that the store and roots agree with a [`HistoryTree`] that performed the
+//! same operations.
+//!
+//! This module is only built for tests, and with the `test-dependencies` feature.
+
+use std::convert::TryFrom;
+use std::ffi::CString;
+use std::fmt;
+use std::ptr;
+
+use libc::c_char;
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+use zcash_history::{Version, V1, V2};
+use zcash_primitives::consensus::BranchId;
+
+use crate::history::{self, HistoryTree};
+use crate::history_ffi::{
+    dispatch, history_enabled, librustzcash_mmr_append, librustzcash_mmr_delete,
+    librustzcash_mmr_hash_node,
+};
+use crate::test_vectors::random_leaf;
+
+type Node = [u8; zcash_history::MAX_NODE_DATA_SIZE];
+type Entry = [u8; zcash_history::MAX_ENTRY_SIZE];
+
+/// The longest reorg that the random sequences contain.
+const MAX_REORG_DEPTH: u32 = 8;
+
+/// An operation on the history tree.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum MmrOp {
+    /// Append the leaf for a block at this height.
+    Append(u64),
+    /// Delete the most recently appended leaf.
+    Delete,
+}
+
+/// The outcome of [`random_ops`].
+#[derive(Debug)]
+pub struct MmrOpsReport {
+    cbranch: u32,
+    seed: [u8; 32],
+    /// The operations that were performed, ending with the one that diverged if any did.
+    pub(crate) ops: Vec<MmrOp>,
+    /// The index in `ops` of the first operation after which the FFIs and the tree
+    /// disagreed, and how.
+    pub(crate) divergence: Option<(usize, String)>,
+}
+
+impl fmt::Display for MmrOpsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "History tree operations for branch {:08x}, seed ",
+            self.cbranch
+        )?;
+        for byte in &self.seed {
+            write!(f, "{:02x}", byte)?;
+        }
+        writeln!(f)?;
+        for (i, op) in self.ops.iter().enumerate() {
+            match op {
+                MmrOp::Append(height) => writeln!(f, "{}: append {}", i, height)?,
+                MmrOp::Delete => writeln!(f, "{}: delete", i)?,
+            }
+        }
+        match &self.divergence {
+            Some((i, reason)) => write!(f, "Diverged after operation {}: {}", i, reason),
+            None => write!(f, "No divergence after {} operations", self.ops.len()),
+        }
+    }
+}
+
+fn pad(node: &[u8]) -> Node {
+    let mut padded = [0; zcash_history::MAX_NODE_DATA_SIZE];
+    padded[..node.len()].copy_from_slice(node);
+    padded
+}
+
+/// Encodes the stored node at `index` as an entry, linked to its children as zcashd
+/// links them.
+fn entry(store: &[Node], index: u32) -> Entry {
+    let mut entry = [0; zcash_history::MAX_ENTRY_SIZE];
+    let data = match history::node_altitude(index) {
+        0 => {
+            entry[0] = 1;
+            &mut entry[1..]
+        }
+        alt => {
+            entry[1..5].copy_from_slice(&(index - (1 << alt)).to_le_bytes());
+            entry[5..9].copy_from_slice(&(index - 1).to_le_bytes());
+            &mut entry[9..]
+        }
+    };
+    data[..zcash_history::MAX_NODE_DATA_SIZE].copy_from_slice(&store[index as usize]);
+    entry
+}
+
+/// Loads the entries at `indices` from the store.
+fn load(store: &[Node], indices: &[u32]) -> Vec<Entry> {
+    indices.iter().map(|&index| entry(store, index)).collect()
+}
+
+/// The history tree as zcashd persists it: every node, and the current root.
+struct Store {
+    nodes: Vec<Node>,
+    root: [u8; 32],
+}
+
+impl Store {
+    fn len(&self) -> u32 {
+        self.nodes.len() as u32
+    }
+
+    fn peak_indices(&self) -> Vec<u32> {
+        history::peaks(self.len())
+            .expect("the store only holds whole trees; qed")
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    /// Appends `leaf` with `librustzcash_mmr_append`, returning the number of nodes it
+    /// created.
+    fn append(&mut self, cbranch: u32, leaf: &[u8]) -> Result<u32, String> {
+        let leaf = pad(leaf);
+        if self.nodes.is_empty() {
+            // zcashd stores the first leaf directly, as `MMRTree` needs a peak.
+            if librustzcash_mmr_hash_node(cbranch, &leaf, &mut self.root) != 0 {
+                return Err("librustzcash_mmr_hash_node failed".into());
+            }
+            self.nodes.push(leaf);
+            return Ok(1);
+        }
+
+        let indices = self.peak_indices();
+        let entries = load(&self.nodes, &indices);
+        let mut appended = [[0; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+        let count = librustzcash_mmr_append(
+            cbranch,
+            self.len(),
+            indices.as_ptr(),
+            entries.as_ptr(),
+            ptr::null(),
+            indices.len(),
+            &leaf,
+            &mut self.root,
+            appended.as_mut_ptr(),
+        );
+        if count == 0 {
+            return Err("librustzcash_mmr_append failed".into());
+        }
+        self.nodes.extend_from_slice(&appended[..count as usize]);
+        Ok(count)
+    }
+
+    /// Deletes the last leaf with `librustzcash_mmr_delete`, returning the number of
+    /// nodes it removed.
+    fn delete(&mut self, cbranch: u32) -> Result<u32, String> {
+        if self.nodes.len() == 1 {
+            // zcashd resets a tree with a single leaf rather than truncating it.
+            self.nodes.clear();
+            self.root = [0; 32];
+            return Ok(1);
+        }
+
+        let mut indices = self.peak_indices();
+        let p_len = indices.len();
+        indices.extend(
+            history::delete_extras(self.len()).expect("the store only holds whole trees; qed"),
+        );
+        let entries = load(&self.nodes, &indices);
+        let truncated = librustzcash_mmr_delete(
+            cbranch,
+            self.len(),
+            indices.as_ptr(),
+            entries.as_ptr(),
+            ptr::null(),
+            p_len,
+            indices.len() - p_len,
+            &mut self.root,
+        );
+        if truncated == 0 || truncated >= self.len() {
+            return Err(format!("librustzcash_mmr_delete returned {}", truncated));
+        }
+        self.nodes.truncate((self.len() - truncated) as usize);
+        Ok(truncated)
+    }
+}
+
+/// Checks that the store holds exactly the nodes of `tree` at and after `from`, that
+/// their roots agree, and that the root reconstructed from the stored peaks alone is
+/// the stored root.
+fn check<V: Version>(store: &Store, tree: &HistoryTree<V>, from: u32) -> Result<(), String> {
+    if store.len() != tree.len() {
+        return Err(format!(
+            "store has {} nodes, tree has {}",
+            store.len(),
+            tree.len()
+        ));
+    }
+    for index in from..tree.len() {
+        let node = tree.node(index).expect("the tree holds every node; qed");
+        if store.nodes[index as usize] != pad(node) {
+            return Err(format!("node {} differs", index));
+        }
+    }
+
+    let root = if tree.is_empty() {
+        [0; 32]
+    } else {
+        tree.root().map_err(|e| format!("tree root: {}", e))?
+    };
+    if store.root != root {
+        return Err("roots differ".into());
+    }
+    if !tree.is_empty() {
+        let peaks = store
+            .peak_indices()
+            .into_iter()
+            .map(|index| (index, store.nodes[index as usize].to_vec()));
+        let reconstructed =
+            HistoryTree::<V>::from_nodes(tree.consensus_branch_id(), store.len(), peaks)
+                .and_then(|peaks| peaks.root())
+                .map_err(|e| format!("reconstruction from peaks: {}", e))?;
+        if reconstructed != store.root {
+            return Err("root reconstructed from the stored peaks differs".into());
+        }
+    }
+    Ok(())
+}
+
+fn run<V: Version>(cbranch: u32, seed: [u8; 32], op_count: u32) -> MmrOpsReport {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let mut tree = HistoryTree::<V>::empty(cbranch);
+    let mut store = Store {
+        nodes: vec![],
+        root: [0; 32],
+    };
+    let mut report = MmrOpsReport {
+        cbranch,
+        seed,
+        ops: vec![],
+        divergence: None,
+    };
+
+    // Most operations append the next block. Occasionally a reorg deletes a few of the
+    // most recent blocks, which are then replaced by different ones.
+    let mut next_height = 1;
+    let mut pending_deletes = 0;
+    for i in 0..op_count as usize {
+        if pending_deletes == 0 && next_height > 1 && rng.next_u32() % 16 == 0 {
+            pending_deletes = 1 + rng.next_u32() % MAX_REORG_DEPTH;
+        }
+        let result = if pending_deletes > 0 && next_height > 1 {
+            pending_deletes -= 1;
+            next_height -= 1;
+            report.ops.push(MmrOp::Delete);
+            store.delete(cbranch).and_then(|truncated| {
+                let deleted = tree.delete().map_err(|e| format!("tree delete: {}", e))?;
+                if truncated != deleted.truncated {
+                    return Err(format!(
+                        "deleted {} nodes, tree deleted {}",
+                        truncated, deleted.truncated
+                    ));
+                }
+                check(&store, &tree, tree.len())
+            })
+        } else {
+            pending_deletes = 0;
+            let leaf = random_leaf(&mut rng, cbranch, next_height);
+            report.ops.push(MmrOp::Append(next_height));
+            next_height += 1;
+            let from = store.len();
+            store.append(cbranch, &leaf).and_then(|count| {
+                let appended = tree
+                    .append(&leaf)
+                    .map_err(|e| format!("tree append: {}", e))?;
+                if count as usize != appended.appended_nodes.len() {
+                    return Err(format!(
+                        "appended {} nodes, tree appended {}",
+                        count,
+                        appended.appended_nodes.len()
+                    ));
+                }
+                check(&store, &tree, from)
+            })
+        };
+        if let Err(reason) = result {
+            report.divergence = Some((i, reason));
+            return report;
+        }
+    }
+
+    // Only the nodes that each operation touched were compared as it went.
+    if let Err(reason) = check(&store, &tree, 0) {
+        report.divergence = Some((report.ops.len().saturating_sub(1), reason));
+    }
+    report
+}
+
+/// Performs `op_count` random operations, derived from `seed`, on a history tree for
+/// the consensus branch `cbranch` through the history tree FFIs, checking them against
+/// [`HistoryTree`] after each one.
+///
+/// Returns `None` if `cbranch` is not a consensus branch with a history tree.
+pub(crate) fn random_ops(seed: [u8; 32], op_count: u32, cbranch: u32) -> Option<MmrOpsReport> {
+    match BranchId::try_from(cbranch) {
+        Ok(branch) if history_enabled(branch) => Some(dispatch(
+            cbranch,
+            || run::<V1>(cbranch, seed, op_count),
+            || run::<V2>(cbranch, seed, op_count),
+        )),
+        _ => None,
+    }
+}
+
+/// Performs `op_count` random operations, derived from the 32-byte `seed`, on a history
+/// tree for the consensus branch `cbranch` through `librustzcash_mmr_append` and
+/// `librustzcash_mmr_delete`, stopping at the first one after which they disagree with
+/// the Rust history tree.
+///
+/// Returns `nullptr` if `cbranch` is not a consensus branch with a history tree.
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_mmr_random_ops(
+    seed: *const [u8; 32],
+    op_count: u32,
+    cbranch: u32,
+) -> *mut MmrOpsReport {
+    let seed = unsafe { seed.as_ref() }.expect("Seed may not be null.");
+    match random_ops(*seed, op_count, cbranch) {
+        Some(report) => Box::into_raw(Box::new(report)),
+        None => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_mmr_random_ops_free(report: *mut MmrOpsReport) {
+    if !report.is_null() {
+        drop(unsafe { Box::from_raw(report) });
+    }
+}
+
+/// Returns `true` and writes the index of the operation to `op_index_ret` if the FFIs
+/// diverged from the Rust history tree.
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_mmr_random_ops_diverged(
+    report: *const MmrOpsReport,
+    op_index_ret: *mut u32,
+) -> bool {
+    let report = unsafe { report.as_ref() }.expect("Report may not be null.");
+    match &report.divergence {
+        Some((i, _)) => {
+            *unsafe { &mut *op_index_ret } = *i as u32;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Returns the log of every operation that was performed, and of the divergence if
+/// there was one. The string must be freed with `zcash_address_string_free`.
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_mmr_random_ops_log(
+    report: *const MmrOpsReport,
+) -> *mut c_char {
+    let report = unsafe { report.as_ref() }.expect("Report may not be null.");
+    CString::new(report.to_string())
+        .expect("The log has no interior nul bytes; qed")
+        .into_raw()
+}
@@ -0,0 +1,203 @@
+//! Generated Orchard bundles for benchmarks and fuzzing corpora.
+//!
+//! Bundles are either assembled directly from random (but canonically encoded) parts,
+//! with placeholder proofs and signatures, or built and proven with the builder from
+//! dummy notes. Everything is derived from a seed, so the same seed always gives the
+//! same bundle.
+//!
+//! This module is only built for tests, and with the `test-dependencies` feature.
+
+use std::convert::TryFrom;
+
+use incrementalmerkletree::Hashable;
+use nonempty::NonEmpty;
+use once_cell::sync::Lazy;
+use orchard::{
+    builder::Builder,
+    bundle::{Authorized, Flags},
+    circuit::ProvingKey,
+    note::{ExtractedNoteCommitment, Nullifier, TransmittedNoteCiphertext},
+    primitives::redpallas::{self, Signature, SpendAuth},
+    tree::{Anchor, MerkleHashOrchard},
+    value::{NoteValue, ValueCommitment},
+    Action, Bundle, Proof,
+};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+use tracing::error;
+use zcash_primitives::transaction::components::{
+    amount::MAX_MONEY, orchard as orchard_serialization, Amount,
+};
+
+use crate::{
+    de_ct,
+    streams_ffi::{CppStreamWriter, StreamObj, WriteCb},
+    test_wallet_ffi::random_address,
+};
+
+/// The proving key used for generated bundles, which is built on first use so that
+/// the Orchard parameters don't need to be loaded.
+pub(crate) static PROVING_KEY: Lazy<ProvingKey> = Lazy::new(ProvingKey::build);
+
+/// Returns the size of the proof of a bundle with `num_actions` actions.
+fn proof_size(num_actions: usize) -> usize {
+    2720 + 2272 * num_actions
+}
+
+/// Returns 32 random bytes that are the canonical encoding of a Pallas base field
+/// element, as both fields are just under 2^254.
+fn random_field_bytes(rng: &mut ChaCha20Rng) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    rng.fill_bytes(&mut bytes);
+    bytes[31] &= 0b0011_1111;
+    bytes
+}
+
+/// Returns the encoding of a random Pallas point, as that of a spend validating key.
+fn random_point_bytes(rng: &mut ChaCha20Rng) -> [u8; 32] {
+    loop {
+        if let Ok(sk) = redpallas::SigningKey::<SpendAuth>::try_from(random_field_bytes(rng)) {
+            return <[u8; 32]>::from(&redpallas::VerificationKey::from(&sk));
+        }
+    }
+}
+
+fn random_signature<T: redpallas::SigType>(rng: &mut ChaCha20Rng) -> Signature<T> {
+    let mut bytes = [0; 64];
+    rng.fill_bytes(&mut bytes);
+    Signature::from(bytes)
+}
+
+fn random_action(rng: &mut ChaCha20Rng) -> Action<Signature<SpendAuth>> {
+    let nf = de_ct(Nullifier::from_bytes(&random_field_bytes(rng)))
+        .expect("the bytes are canonical; qed");
+    let rk = redpallas::VerificationKey::try_from(random_point_bytes(rng))
+        .expect("the bytes encode a point; qed");
+    let cmx = de_ct(ExtractedNoteCommitment::from_bytes(&random_field_bytes(
+        rng,
+    )))
+    .expect("the bytes are canonical; qed");
+    let mut encrypted_note = TransmittedNoteCiphertext {
+        epk_bytes: random_point_bytes(rng),
+        enc_ciphertext: [0; 580],
+        out_ciphertext: [0; 80],
+    };
+    rng.fill_bytes(&mut encrypted_note.enc_ciphertext);
+    rng.fill_bytes(&mut encrypted_note.out_ciphertext);
+    let cv_net = de_ct(ValueCommitment::from_bytes(&random_point_bytes(rng)))
+        .expect("the bytes encode a point; qed");
+
+    Action::from_parts(nf, rk, cmx, encrypted_note, cv_net, random_signature(rng))
+}
+
+/// Assembles a bundle of `num_actions` random actions, with a placeholder proof and
+/// signatures.
+fn random_bundle(rng: &mut ChaCha20Rng, num_actions: usize) -> Bundle<Authorized, Amount> {
+    let actions = (0..num_actions).map(|_| random_action(rng)).collect();
+    let flags = Flags::from_parts(rng.next_u32() & 1 == 1, rng.next_u32() & 1 == 1);
+    let value_balance = (rng.next_u64() % (2 * MAX_MONEY as u64 + 1)) as i64 - MAX_MONEY;
+    let anchor =
+        de_ct(Anchor::from_bytes(random_field_bytes(rng))).expect("the bytes are canonical; qed");
+
+    Bundle::from_parts(
+        NonEmpty::from_vec(actions).expect("num_actions is nonzero; qed"),
+        flags,
+        Amount::from_i64(value_balance).expect("the value balance is in range; qed"),
+        anchor,
+        Authorized::from_parts(
+            Proof::new(vec![0; proof_size(num_actions)]),
+            random_signature(rng),
+        ),
+    )
+}
+
+/// Builds and proves a bundle with an output to a random address for each of its
+/// `num_actions` actions, each spending a dummy note.
+fn proven_bundle(
+    rng: &mut ChaCha20Rng,
+    seed: [u8; 32],
+    num_actions: usize,
+) -> Option<Bundle<Authorized, Amount>> {
+    let mut builder = Builder::new(
+        Flags::from_parts(true, true),
+        MerkleHashOrchard::empty_root(32.into()).into(),
+    );
+    for _ in 0..num_actions {
+        let mut address_seed = [0; 32];
+        rng.fill_bytes(&mut address_seed);
+        builder
+            .add_recipient(
+                None,
+                random_address(address_seed),
+                NoteValue::from_raw(u64::from(rng.next_u32() % 1_000_000)),
+                None,
+            )
+            .ok()?;
+    }
+
+    builder
+        .build(&mut *rng)
+        .and_then(|bundle| bundle.create_proof(&PROVING_KEY, &mut *rng))
+        .and_then(|bundle| bundle.apply_signatures(&mut *rng, seed, &[]))
+        .map_err(|e| error!("Failed to build Orchard bundle: {:?}", e))
+        .ok()
+}
+
+/// Returns the encoding of an Orchard bundle with `num_actions` actions, derived from
+/// `seed`, or `None` if `num_actions` is 0, or 1 with `with_valid_proof`.
+///
+/// Without `with_valid_proof`, the bundle is assembled from random parts, which are
+/// canonically encoded so that it parses, but its proof and signatures are
+/// placeholders. With it, the bundle is built by spending dummy notes, and has a valid
+/// proof and signatures over the sighash `seed`. Proving is slow, and the builder pads
+/// bundles to at least two actions.
+pub(crate) fn generate_orchard_bundle(
+    seed: [u8; 32],
+    num_actions: usize,
+    with_valid_proof: bool,
+) -> Option<Vec<u8>> {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let bundle = match (num_actions, with_valid_proof) {
+        (0, _) | (1, true) => return None,
+        (_, false) => random_bundle(&mut rng, num_actions),
+        (_, true) => proven_bundle(&mut rng, seed, num_actions)?,
+    };
+
+    let mut bytes = vec![];
+    orchard_serialization::write_v5_bundle(Some(&bundle), &mut bytes)
+        .expect("Writing to a Vec cannot fail; qed");
+    Some(bytes)
+}
+
+/// Writes an Orchard bundle with `num_actions` actions, derived from the 32-byte
+/// `seed`, to the given stream, in the format that `orchard_bundle_parse` reads.
+///
+/// Returns `false` if `num_actions` is 0, or 1 with `with_valid_proof`.
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_generate_orchard_bundle(
+    seed: *const [u8; 32],
+    num_actions: usize,
+    with_valid_proof: bool,
+    stream: Option<StreamObj>,
+    write_cb: Option<WriteCb>,
+) -> bool {
+    let seed = unsafe { seed.as_ref() }.expect("Seed may not be null.");
+    let mut writer = CppStreamWriter::from_raw_parts(stream, write_cb.unwrap());
+
+    match generate_orchard_bundle(*seed, num_actions, with_valid_proof) {
+        Some(bytes) => match std::io::Write::write_all(&mut writer, &bytes) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        },
+        None => {
+            error!(
+                "Cannot generate an Orchard bundle with {} actions",
+                num_actions
+            );
+            false
+        }
+    }
+}
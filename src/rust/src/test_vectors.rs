@@ -0,0 +1,339 @@
+//! Exports of test vectors computed by zcashd's own code paths.
+//!
+//! Other implementations check themselves against vectors generated here, so every
+//! output is computed by the same functions that zcashd's FFIs use, over inputs derived
+//! from a seed. Each export is a JSON document of the inputs and outputs: transactions
+//! and leaves are hex-encoded as serialized, and digests are hex-encoded in the reversed
+//! byte order of `uint256::GetHex`, as in `zip0244.json` and the RPC methods.
+
+use std::ffi::{CStr, CString};
+use std::fmt::{self, Write};
+use std::ptr;
+
+use libc::c_char;
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+use tracing::error;
+use zcash_encoding::CompactSize;
+use zcash_history::{Version, V1, V2};
+use zcash_primitives::{consensus::BranchId, transaction::components::amount::MAX_MONEY};
+
+use crate::history::HistoryTree;
+use crate::history_ffi::BlockLeaf;
+use crate::transaction_ffi::{transaction_digest_parts, TxDigestParts};
+
+/// The most vectors that a single export may contain.
+const MAX_COUNT: u64 = 10_000;
+
+/// The kinds of test vectors that can be exported.
+const KINDS: [&str; 2] = ["zip244", "history_tree"];
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum ExportError {
+    /// The requested kind of test vectors is not one of [`KINDS`].
+    UnknownKind(String),
+    /// The parameters are not a JSON object of the integer `seed` and `count`.
+    InvalidParams(String),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExportError::UnknownKind(kind) => write!(
+                f,
+                "Unknown test vector kind {:?}; expected one of {}",
+                kind,
+                KINDS.join(", ")
+            ),
+            ExportError::InvalidParams(reason) => {
+                write!(f, "Invalid test vector parameters: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+/// The parameters of an export.
+#[derive(Debug, PartialEq)]
+struct Params {
+    /// The seed from which the inputs are derived.
+    seed: u64,
+    /// The number of vectors to export, if not the default for the kind.
+    count: Option<u64>,
+}
+
+impl Params {
+    /// Parses a JSON object with the optional integer members `seed` and `count`. An
+    /// empty string is treated as an empty object.
+    fn parse(json: &str) -> Result<Self, ExportError> {
+        let invalid = |reason: &str| ExportError::InvalidParams(reason.to_owned());
+
+        let json = json.trim();
+        let members = if json.is_empty() {
+            ""
+        } else {
+            json.strip_prefix('{')
+                .and_then(|json| json.strip_suffix('}'))
+                .ok_or_else(|| invalid("expected a JSON object"))?
+                .trim()
+        };
+
+        let mut params = Params {
+            seed: 0,
+            count: None,
+        };
+        if members.is_empty() {
+            return Ok(params);
+        }
+        // Every value is an integer, so members can be split at commas.
+        for member in members.split(',') {
+            let (key, value) = member
+                .split_once(':')
+                .ok_or_else(|| invalid("expected a \"key\": value member"))?;
+            let value = value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| invalid("values must be non-negative integers"))?;
+            match key.trim() {
+                "\"seed\"" => params.seed = value,
+                "\"count\"" if value <= MAX_COUNT => params.count = Some(value),
+                "\"count\"" => return Err(invalid("count is too large")),
+                _ => return Err(invalid("the only parameters are seed and count")),
+            }
+        }
+        Ok(params)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hex-encodes a digest in the reversed byte order of `uint256::GetHex`.
+fn digest_hex(digest: &[u8; 32]) -> String {
+    let mut reversed = *digest;
+    reversed.reverse();
+    hex(&reversed)
+}
+
+/// Returns the encoding of a random v5 transaction for NU5, with transparent inputs
+/// and outputs but no shielded bundles, since those can only be generated with the
+/// `test-dependencies` helpers.
+///
+/// Script contents are random, as parsing and digesting never interpret them.
+fn random_v5_transaction(rng: &mut ChaCha20Rng) -> Vec<u8> {
+    fn random_script(rng: &mut ChaCha20Rng) -> Vec<u8> {
+        let mut script = vec![0; (rng.next_u32() % 50) as usize];
+        rng.fill_bytes(&mut script);
+        script
+    }
+
+    let mut tx = vec![];
+    tx.extend_from_slice(&0x8000_0005u32.to_le_bytes());
+    tx.extend_from_slice(&0x26A7_270Au32.to_le_bytes());
+    tx.extend_from_slice(&u32::from(BranchId::Nu5).to_le_bytes());
+    tx.extend_from_slice(&rng.next_u32().to_le_bytes());
+    tx.extend_from_slice(&(rng.next_u32() % 500_000_000).to_le_bytes());
+
+    let vin_count = rng.next_u32() % 4;
+    CompactSize::write(&mut tx, vin_count as usize).expect("Writing to a Vec cannot fail; qed");
+    for _ in 0..vin_count {
+        let mut prevout = [0; 36];
+        rng.fill_bytes(&mut prevout);
+        tx.extend_from_slice(&prevout);
+        let script_sig = random_script(rng);
+        CompactSize::write(&mut tx, script_sig.len()).expect("Writing to a Vec cannot fail; qed");
+        tx.extend_from_slice(&script_sig);
+        tx.extend_from_slice(&rng.next_u32().to_le_bytes());
+    }
+
+    let vout_count = rng.next_u32() % 4;
+    CompactSize::write(&mut tx, vout_count as usize).expect("Writing to a Vec cannot fail; qed");
+    for _ in 0..vout_count {
+        let value = rng.next_u64() % (MAX_MONEY as u64 + 1);
+        tx.extend_from_slice(&value.to_le_bytes());
+        let script_pubkey = random_script(rng);
+        CompactSize::write(&mut tx, script_pubkey.len())
+            .expect("Writing to a Vec cannot fail; qed");
+        tx.extend_from_slice(&script_pubkey);
+    }
+
+    // No Sapling spends or outputs, and no Orchard actions.
+    tx.extend_from_slice(&[0, 0, 0]);
+    tx
+}
+
+/// Returns the JSON object of a v5 transaction and its ZIP 244 digests, indented for
+/// an element of the `vectors` array, or `None` if it is not a valid v5 transaction.
+pub(crate) fn zip244_vector(tx_bytes: &[u8]) -> Option<String> {
+    let parts: TxDigestParts = transaction_digest_parts(tx_bytes).ok()??;
+    let digests = [
+        ("header_digest", &parts.header_digest),
+        ("transparent_digest", &parts.transparent_digest),
+        ("sapling_digest", &parts.sapling_digest),
+        ("orchard_digest", &parts.orchard_digest),
+        ("txid", &parts.txid),
+        ("transparent_auth_digest", &parts.transparent_auth_digest),
+        ("sapling_auth_digest", &parts.sapling_auth_digest),
+        ("orchard_auth_digest", &parts.orchard_auth_digest),
+        ("auth_digest", &parts.auth_digest),
+    ];
+
+    let mut vector = format!("    {{\n      \"tx\": \"{}\"", hex(tx_bytes));
+    for (name, digest) in digests.iter() {
+        write!(vector, ",\n      \"{}\": \"{}\"", name, digest_hex(digest))
+            .expect("Writing to a String cannot fail; qed");
+    }
+    vector.push_str("\n    }");
+    Some(vector)
+}
+
+fn export_zip244(rng: &mut ChaCha20Rng, count: u64) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            zip244_vector(&random_v5_transaction(rng))
+                .expect("generated transactions are canonical v5 transactions; qed")
+        })
+        .collect()
+}
+
+/// Returns the leaf of a random block at `height`, for the tree version used by
+/// `cbranch`.
+pub(crate) fn random_leaf<R: RngCore>(rng: &mut R, cbranch: u32, height: u64) -> Vec<u8> {
+    let mut random_bytes = || {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        bytes
+    };
+    let commitment = random_bytes();
+    let sapling_root = random_bytes();
+    let orchard_root = random_bytes();
+    let mut total_work = [0; 32];
+    total_work[..4].copy_from_slice(&rng.next_u32().to_le_bytes());
+
+    BlockLeaf {
+        commitment,
+        time: 1_600_000_000 + 75 * height as u32,
+        target: 0x1f07ffff,
+        sapling_root,
+        orchard_root,
+        total_work,
+        height,
+        sapling_tx: u64::from(rng.next_u32() % 4),
+        orchard_tx: u64::from(rng.next_u32() % 4),
+    }
+    .to_bytes(cbranch)
+}
+
+/// Returns the JSON object of `count` random leaves and the root of the history tree
+/// after each is appended, indented for an element of the `trees` array.
+fn history_tree_vector<V: Version>(
+    rng: &mut ChaCha20Rng,
+    version: u32,
+    branch: BranchId,
+    count: u64,
+) -> String {
+    let cbranch = u32::from(branch);
+    let leaves: Vec<_> = (1..=count)
+        .map(|height| random_leaf(rng, cbranch, height))
+        .collect();
+    let mut tree = HistoryTree::<V>::empty(cbranch);
+    let roots: Vec<_> = leaves
+        .iter()
+        .map(|leaf| {
+            let root = tree
+                .append(leaf)
+                .expect("generated leaves are valid for the tree version; qed")
+                .new_root;
+            tree.retain_peaks();
+            root
+        })
+        .collect();
+
+    let list = |items: Vec<String>| {
+        items
+            .iter()
+            .map(|item| format!("\n        \"{}\"", item))
+            .collect::<Vec<_>>()
+            .join(",")
+    };
+    format!(
+        "    {{\n      \"version\": {},\n      \"cbranch\": \"{:08x}\",\n      \
+         \"leaves\": [{}\n      ],\n      \"roots\": [{}\n      ]\n    }}",
+        version,
+        cbranch,
+        list(leaves.iter().map(|leaf| hex(leaf)).collect()),
+        list(roots.iter().map(digest_hex).collect()),
+    )
+}
+
+/// Exports the test vectors of the given kind as a JSON document.
+///
+/// `params_json` is a JSON object that may set the integer `seed` from which the
+/// inputs are derived (0 by default), and the `count` of vectors:
+///
+/// - `zip244`: `count` v5 transactions (20 by default), with all of their ZIP 244
+///   digests.
+/// - `history_tree`: for each of the V1 (Heartwood) and V2 (NU5) tree versions, `count`
+///   leaves (50 by default), and the root after each is appended.
+pub(crate) fn export_test_vectors(kind: &str, params_json: &str) -> Result<String, ExportError> {
+    let params = Params::parse(params_json)?;
+    let mut rng = ChaCha20Rng::seed_from_u64(params.seed);
+
+    let (array, items) = match kind {
+        "zip244" => (
+            "vectors",
+            export_zip244(&mut rng, params.count.unwrap_or(20)),
+        ),
+        "history_tree" => {
+            let count = params.count.unwrap_or(50);
+            (
+                "trees",
+                vec![
+                    history_tree_vector::<V1>(&mut rng, 1, BranchId::Heartwood, count),
+                    history_tree_vector::<V2>(&mut rng, 2, BranchId::Nu5, count),
+                ],
+            )
+        }
+        _ => return Err(ExportError::UnknownKind(kind.to_owned())),
+    };
+
+    let items = if items.is_empty() {
+        String::new()
+    } else {
+        format!("\n{}\n  ", items.join(",\n"))
+    };
+    Ok(format!(
+        "{{\n  \"kind\": \"{}\",\n  \"seed\": {},\n  \"{}\": [{}]\n}}\n",
+        kind, params.seed, array, items
+    ))
+}
+
+/// Exports the test vectors of the given kind as a JSON document, with the parameters
+/// in the JSON object `params_json`, which may be `nullptr` for the defaults.
+///
+/// Returns `nullptr` if the kind is unknown or the parameters are invalid. The returned
+/// string must be freed with `zcash_address_string_free`.
+#[no_mangle]
+pub extern "C" fn zcash_export_test_vectors(
+    kind: *const c_char,
+    params_json: *const c_char,
+) -> *mut c_char {
+    let kind = unsafe { CStr::from_ptr(kind) }.to_string_lossy();
+    let params_json = if params_json.is_null() {
+        "".into()
+    } else {
+        unsafe { CStr::from_ptr(params_json) }.to_string_lossy()
+    };
+
+    match export_test_vectors(&kind, &params_json) {
+        Ok(json) => CString::new(json)
+            .expect("JSON documents contain no NUL bytes; qed")
+            .into_raw(),
+        Err(e) => {
+            error!("{}", e);
+            ptr::null_mut()
+        }
+    }
+}
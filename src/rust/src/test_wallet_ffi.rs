@@ -0,0 +1,204 @@
+//! Fake Orchard notes and blocks for the wallet tests.
+//!
+//! A wallet only needs the note encryptions and commitments of a bundle to track the
+//! notes it receives, so these build bundles with placeholder proofs and signatures
+//! instead of proving them. Everything is derived from a seed, so that the C++ tests can
+//! reproduce the wallet state that they create.
+//!
+//! This module is only built for tests, and with the `test-dependencies` feature.
+
+use std::ptr;
+use std::slice;
+
+use incrementalmerkletree::Hashable;
+use libc::size_t;
+use nonempty::NonEmpty;
+use orchard::{
+    builder::Builder,
+    bundle::{Authorized, Flags},
+    keys::{FullViewingKey, Scope, SpendingKey},
+    primitives::redpallas::Signature,
+    tree::MerkleHashOrchard,
+    value::NoteValue,
+    Action, Address, Bundle, Proof,
+};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{RngCore, SeedableRng};
+use tracing::error;
+use zcash_primitives::transaction::{components::Amount, TxId};
+
+use crate::{de_ct, wallet::Wallet};
+
+/// A transaction that creates an Orchard note, of which only its txid and Orchard
+/// bundle are known.
+pub struct FakeNote {
+    txid: TxId,
+    bundle: Bundle<Authorized, Amount>,
+}
+
+impl FakeNote {
+    /// Builds a bundle that sends `value` to the first external address of `fvk`, using
+    /// randomness derived from `seed`, or returns `None` if `value` is too large.
+    ///
+    /// The bundle is padded with a dummy spend, as the builder does, so it has two
+    /// actions.
+    pub(crate) fn new(fvk: &FullViewingKey, value: u64, seed: [u8; 32]) -> Option<Self> {
+        Amount::from_u64(value).ok()?;
+        let mut rng = ChaCha20Rng::from_seed(seed);
+        let mut txid = [0; 32];
+        rng.fill_bytes(&mut txid);
+
+        let mut builder = Builder::new(
+            Flags::from_parts(true, true),
+            MerkleHashOrchard::empty_root(32.into()).into(),
+        );
+        builder
+            .add_recipient(
+                None,
+                fvk.address_at(0u32, Scope::External),
+                NoteValue::from_raw(value),
+                None,
+            )
+            .ok()?;
+        let unauthorized: Bundle<_, Amount> = builder.build(&mut rng).ok()?;
+
+        let actions: Vec<_> = unauthorized
+            .actions()
+            .iter()
+            .map(|action| {
+                Action::from_parts(
+                    *action.nullifier(),
+                    action.rk().clone(),
+                    *action.cmx(),
+                    action.encrypted_note().clone(),
+                    action.cv_net().clone(),
+                    Signature::from([0; 64]),
+                )
+            })
+            .collect();
+        let bundle = Bundle::from_parts(
+            NonEmpty::from_vec(actions).expect("the builder creates at least two actions; qed"),
+            *unauthorized.flags(),
+            *unauthorized.value_balance(),
+            *unauthorized.anchor(),
+            Authorized::from_parts(Proof::new(vec![]), Signature::from([0; 64])),
+        );
+
+        Some(FakeNote {
+            txid: TxId::from_bytes(txid),
+            bundle,
+        })
+    }
+
+    pub(crate) fn txid(&self) -> TxId {
+        self.txid
+    }
+
+    pub(crate) fn bundle(&self) -> &Bundle<Authorized, Amount> {
+        &self.bundle
+    }
+}
+
+/// Adds the given transactions to `wallet` as those of the block at `block_height`, as
+/// if the wallet had scanned it: the notes that it can decrypt are recorded, their
+/// commitments are appended and witnessed, and the tree is checkpointed.
+///
+/// Returns `false` if the block does not follow the wallet's last checkpoint.
+pub(crate) fn apply_fake_block(
+    wallet: &mut Wallet,
+    notes: &[&FakeNote],
+    block_height: u32,
+) -> bool {
+    for (block_tx_idx, note) in notes.iter().enumerate() {
+        wallet.add_notes_from_bundle(&note.txid, &note.bundle);
+        if let Err(e) = wallet.append_bundle_commitments(
+            block_height.into(),
+            block_tx_idx,
+            &note.txid,
+            &note.bundle,
+        ) {
+            error!("Failed to append fake block: {:?}", e);
+            return false;
+        }
+    }
+    wallet.checkpoint(block_height.into())
+}
+
+/// Returns an Orchard address derived from `seed`, which the wallet has no keys for
+/// unless they were also derived from it.
+pub(crate) fn random_address(seed: [u8; 32]) -> Address {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    let sk = loop {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        if let Some(sk) = de_ct(SpendingKey::from_bytes(bytes)) {
+            break sk;
+        }
+    };
+    FullViewingKey::from(&sk).address_at(0u32, Scope::External)
+}
+
+/// Creates a transaction with an Orchard note of `value` to the first external address
+/// of `fvk`, derived from the 32-byte `seed`.
+///
+/// Returns `nullptr` if `value` is too large.
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_fake_orchard_note(
+    fvk: *const FullViewingKey,
+    value: u64,
+    seed: *const [u8; 32],
+) -> *mut FakeNote {
+    let fvk = unsafe { fvk.as_ref() }.expect("Full viewing key pointer may not be null.");
+    let seed = unsafe { seed.as_ref() }.expect("Seed may not be null.");
+
+    match FakeNote::new(fvk, value, *seed) {
+        Some(note) => Box::into_raw(Box::new(note)),
+        None => {
+            error!("Failed to build fake Orchard note");
+            ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_fake_orchard_note_free(note: *mut FakeNote) {
+    if !note.is_null() {
+        drop(unsafe { Box::from_raw(note) });
+    }
+}
+
+/// Writes the txid of the fake transaction to `txid_ret`.
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_fake_orchard_note_txid(
+    note: *const FakeNote,
+    txid_ret: *mut [u8; 32],
+) {
+    let note = unsafe { note.as_ref() }.expect("Fake note may not be null.");
+    *unsafe { &mut *txid_ret } = *note.txid().as_ref();
+}
+
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_apply_fake_block(
+    wallet: *mut Wallet,
+    notes: *const *const FakeNote,
+    notes_len: size_t,
+    block_height: u32,
+) -> bool {
+    let wallet = unsafe { wallet.as_mut() }.expect("Wallet pointer may not be null");
+    let notes: Vec<_> = if notes_len == 0 {
+        vec![]
+    } else {
+        unsafe { slice::from_raw_parts(notes, notes_len) }
+            .iter()
+            .map(|note| unsafe { note.as_ref() }.expect("Fake notes may not be null."))
+            .collect()
+    };
+
+    apply_fake_block(wallet, &notes, block_height)
+}
+
+#[no_mangle]
+pub extern "C" fn zcash_test_harness_random_orchard_address(seed: *const [u8; 32]) -> *mut Address {
+    let seed = unsafe { seed.as_ref() }.expect("Seed may not be null.");
+    Box::into_raw(Box::new(random_address(*seed)))
+}
@@ -0,0 +1,110 @@
+use blake2b_simd::Params as Blake2bParams;
+
+use crate::auth_data_tree::{block_auth_data_root, AuthDataTree};
+
+/// A port of `CBlock::BuildAuthDataMerkleTree`.
+fn build_auth_data_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+
+    let mut layer = leaves.to_vec();
+    layer.resize(leaves.len().next_power_of_two(), [0; 32]);
+    while layer.len() > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| {
+                let hash = Blake2bParams::new()
+                    .hash_length(32)
+                    .personal(b"ZcashAuthDatHash")
+                    .to_state()
+                    .update(&pair[0])
+                    .update(&pair[1])
+                    .finalize();
+                let mut node = [0; 32];
+                node.copy_from_slice(hash.as_bytes());
+                node
+            })
+            .collect();
+    }
+    layer[0]
+}
+
+/// A small deterministic PRNG, so that failures are reproducible.
+struct Lcg(u64);
+
+impl Lcg {
+    fn next(&mut self) -> u64 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        self.0 >> 33
+    }
+
+    fn digest(&mut self) -> [u8; 32] {
+        let mut digest = [0; 32];
+        for chunk in digest.chunks_mut(4) {
+            chunk.copy_from_slice(&(self.next() as u32).to_le_bytes());
+        }
+        digest
+    }
+}
+
+#[test]
+fn empty_and_coinbase_only() {
+    let mut tree = AuthDataTree::new();
+    assert!(tree.is_empty());
+    assert_eq!(tree.root(), [0; 32]);
+    assert_eq!(tree.remove_last(), None);
+    assert!(!tree.replace(0, [1; 32]));
+
+    // A block containing only a pre-v5 coinbase transaction.
+    tree.append([0xff; 32]);
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree.root(), [0xff; 32]);
+
+    assert!(tree.replace(0, [7; 32]));
+    assert_eq!(tree.root(), [7; 32]);
+
+    assert_eq!(tree.remove_last(), Some([7; 32]));
+    assert!(tree.is_empty());
+    assert_eq!(tree.root(), [0; 32]);
+}
+
+#[test]
+fn matches_from_scratch_computation() {
+    let mut rng = Lcg(0x5eed);
+    let mut tree = AuthDataTree::new();
+    let mut leaves = vec![];
+
+    for _ in 0..2000 {
+        match rng.next() % 4 {
+            0 | 1 => {
+                let digest = rng.digest();
+                tree.append(digest);
+                leaves.push(digest);
+            }
+            2 if !leaves.is_empty() => {
+                let index = rng.next() as usize % leaves.len();
+                let digest = rng.digest();
+                assert!(tree.replace(index, digest));
+                leaves[index] = digest;
+            }
+            _ => {
+                assert_eq!(tree.remove_last(), leaves.pop());
+            }
+        }
+
+        assert_eq!(tree.len(), leaves.len());
+        assert_eq!(tree.root(), build_auth_data_root(&leaves));
+        assert_eq!(block_auth_data_root(&leaves), tree.root());
+    }
+
+    // Shrink back down through every power-of-two boundary.
+    while !leaves.is_empty() {
+        assert_eq!(tree.remove_last(), leaves.pop());
+        assert_eq!(tree.root(), build_auth_data_root(&leaves));
+    }
+    assert_eq!(tree.root(), [0; 32]);
+}
@@ -0,0 +1,439 @@
+use bellman::{
+    groth16::{create_random_proof, generate_random_parameters, prepare_verifying_key},
+    Circuit, ConstraintSystem, SynthesisError,
+};
+use bls12_381::{Bls12, Scalar};
+use group::{ff::Field, Group, GroupEncoding};
+use nonempty::NonEmpty;
+use orchard::{bundle::Authorized, circuit::VerifyingKey, Action, Bundle, Proof};
+use rand_core::OsRng;
+use zcash_primitives::{
+    constants::{SPENDING_KEY_GENERATOR, VALUE_COMMITMENT_RANDOMNESS_GENERATOR},
+    sapling::redjubjub::{PrivateKey, PublicKey},
+    transaction::components::{orchard as orchard_serialization, Amount},
+};
+
+use crate::block_validation_ffi::{
+    BatchFailure, BatchValidators, BlockSignatures, SaplingBindingSig, SaplingOutput,
+    SaplingSignature, SignatureId, SignatureKind, VerifyingKeys,
+};
+use crate::test_orchard_ffi::generate_orchard_bundle;
+
+/// Proves knowledge of a square root of the public input, standing in for the Sapling
+/// circuits.
+struct Square {
+    x: Option<Scalar>,
+}
+
+impl Circuit<Scalar> for Square {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let x = cs.alloc(|| "x", || self.x.ok_or(SynthesisError::AssignmentMissing))?;
+        let y = cs.alloc_input(
+            || "y",
+            || {
+                self.x
+                    .map(|x| x.square())
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?;
+        cs.enforce(|| "x * x = y", |lc| lc + x, |lc| lc + x, |lc| lc + y);
+        Ok(())
+    }
+}
+
+/// The sighash that generated bundles' signatures are over.
+const SIGHASH: [u8; 32] = [7; 32];
+
+fn txid(position: usize) -> [u8; 32] {
+    let mut txid = [0; 32];
+    txid[..8].copy_from_slice(&(position as u64).to_le_bytes());
+    txid
+}
+
+fn proven_bundle() -> Bundle<Authorized, Amount> {
+    let bytes = generate_orchard_bundle(SIGHASH, 2, true).unwrap();
+    orchard_serialization::read_v5_bundle(&bytes[..])
+        .unwrap()
+        .unwrap()
+}
+
+/// Returns `bundle` with one bit of its proof flipped.
+fn with_corrupted_proof(bundle: &Bundle<Authorized, Amount>) -> Bundle<Authorized, Amount> {
+    let mut proof = bundle.authorization().proof().as_ref().to_vec();
+    proof[100] ^= 1;
+    Bundle::from_parts(
+        bundle.actions().clone(),
+        *bundle.flags(),
+        *bundle.value_balance(),
+        *bundle.anchor(),
+        Authorized::from_parts(
+            Proof::new(proof),
+            bundle.authorization().binding_signature().clone(),
+        ),
+    )
+}
+
+/// Returns `bundle` with the spend authorization signature of its first action in place
+/// of that of the action at `index`, which is then invalid.
+fn with_swapped_signature(
+    bundle: &Bundle<Authorized, Amount>,
+    index: usize,
+) -> Bundle<Authorized, Amount> {
+    let first = bundle.actions().first().authorization().clone();
+    let actions = bundle
+        .actions()
+        .iter()
+        .enumerate()
+        .map(|(i, action)| {
+            let authorization = if i == index {
+                first.clone()
+            } else {
+                action.authorization().clone()
+            };
+            Action::from_parts(
+                *action.nullifier(),
+                action.rk().clone(),
+                *action.cmx(),
+                action.encrypted_note().clone(),
+                action.cv_net().clone(),
+                authorization,
+            )
+        })
+        .collect();
+    Bundle::from_parts(
+        NonEmpty::from_vec(actions).unwrap(),
+        *bundle.flags(),
+        *bundle.value_balance(),
+        *bundle.anchor(),
+        bundle.authorization().clone(),
+    )
+}
+
+/// Returns a Sapling signature of `id.kind` by a random key over `SIGHASH`, which is
+/// invalid if `valid` is not set.
+fn sapling_signature(id: SignatureId, valid: bool) -> SaplingSignature {
+    let generator = match id.kind {
+        SignatureKind::SaplingBinding => VALUE_COMMITMENT_RANDOMNESS_GENERATOR,
+        _ => SPENDING_KEY_GENERATOR,
+    };
+    let sk = PrivateKey(jubjub::Fr::random(OsRng));
+    let vk = PublicKey::from_private(&sk, generator);
+    let mut msg = [0; 64];
+    msg[..32].copy_from_slice(&vk.0.to_bytes());
+    msg[32..].copy_from_slice(if valid { &SIGHASH } else { &[8; 32] });
+    let sig = sk.sign(&msg, &mut OsRng, generator);
+    SaplingSignature::new(id, vk.0, &SIGHASH, &sig)
+}
+
+fn signature_id(position: usize, kind: SignatureKind, index: usize) -> SignatureId {
+    SignatureId {
+        position,
+        kind,
+        index,
+    }
+}
+
+/// A memory budget that the tests' blocks never exceed.
+const UNLIMITED: usize = usize::MAX;
+
+/// Queues a block of `count` transactions that each have a copy of `bundle`, except
+/// for the transaction at the position given by `invalid`, which instead has the given
+/// bundle and sighash, in validators with the given memory budget.
+fn orchard_block<'a>(
+    keys: VerifyingKeys<'a>,
+    memory_budget: usize,
+    count: usize,
+    bundle: &Bundle<Authorized, Amount>,
+    invalid: Option<(usize, Bundle<Authorized, Amount>, [u8; 32])>,
+) -> BatchValidators<'a> {
+    let mut batch = BatchValidators::new(true, keys, memory_budget);
+    for position in 0..count {
+        let (bundle, sighash) = match &invalid {
+            Some((p, bundle, sighash)) if *p == position => (bundle.clone(), *sighash),
+            _ => (bundle.clone(), SIGHASH),
+        };
+        batch.queue_orchard_bundle(txid(position), bundle, sighash);
+    }
+    batch
+}
+
+#[test]
+fn block_validation_attributes_failures() {
+    let params = generate_random_parameters::<Bls12, _, _>(Square { x: None }, &mut OsRng).unwrap();
+    let sapling_vk = prepare_verifying_key(&params.vk);
+    let orchard_vk = VerifyingKey::build();
+    let keys = VerifyingKeys {
+        sapling_spend: &sapling_vk,
+        sapling_output: &sapling_vk,
+        orchard: &orchard_vk,
+    };
+    let bundle = proven_bundle();
+
+    // An empty block is valid.
+    let mut batch = BatchValidators::new(true, keys, UNLIMITED);
+    assert!(batch.validate_all(true));
+
+    // A block of 50 valid transactions is valid, whether or not it is validated in
+    // parallel.
+    for parallel in [true, false] {
+        let mut batch = orchard_block(keys, UNLIMITED, 50, &bundle, None);
+        assert!(batch.validate_all(parallel));
+        assert_eq!(batch.first_failure(), None);
+    }
+
+    // A single corrupted proof is attributed to its transaction.
+    let mut batch = orchard_block(
+        keys,
+        UNLIMITED,
+        50,
+        &bundle,
+        Some((23, with_corrupted_proof(&bundle), SIGHASH)),
+    );
+    assert!(!batch.validate_all(true));
+    assert_eq!(
+        batch.first_failure(),
+        Some((txid(23), BatchFailure::OrchardProof))
+    );
+
+    // Signatures over the wrong sighash are found by bisecting the block's batch.
+    for parallel in [true, false] {
+        let mut batch = orchard_block(
+            keys,
+            UNLIMITED,
+            12,
+            &bundle,
+            Some((9, bundle.clone(), [8; 32])),
+        );
+        assert!(!batch.validate_all(parallel));
+        assert_eq!(
+            batch.first_failure(),
+            Some((
+                txid(9),
+                BatchFailure::Signature(SignatureKind::OrchardSpendAuth, 0)
+            ))
+        );
+    }
+
+    // A single invalid spend authorization signature is attributed to its action.
+    let mut batch = orchard_block(
+        keys,
+        UNLIMITED,
+        12,
+        &bundle,
+        Some((5, with_swapped_signature(&bundle, 1), SIGHASH)),
+    );
+    assert!(!batch.validate_all(true));
+    assert_eq!(
+        batch.first_failure(),
+        Some((
+            txid(5),
+            BatchFailure::Signature(SignatureKind::OrchardSpendAuth, 1)
+        ))
+    );
+
+    // The earliest failure is reported, whichever pool it is in. The Output's proof is
+    // well-formed, but is not a proof of a Sapling output.
+    let mut zkproof = [0; 192];
+    create_random_proof(
+        Square {
+            x: Some(Scalar::from(3)),
+        },
+        &params,
+        &mut OsRng,
+    )
+    .unwrap()
+    .write(&mut zkproof[..])
+    .unwrap();
+    let point = jubjub::ExtendedPoint::generator().to_bytes();
+    let mut batch = BatchValidators::new(true, keys, UNLIMITED);
+    for position in 0..12 {
+        if position == 4 {
+            let output = SaplingOutput::read(&point, &[0; 32], &point, &zkproof).unwrap();
+            let binding_sig = SaplingBindingSig::read(0, &[0; 64]).unwrap();
+            batch.queue_sapling_output(txid(position), output);
+            batch.queue_sapling_binding_sig(txid(position), binding_sig, SIGHASH);
+        }
+        let orchard = if position == 10 {
+            with_corrupted_proof(&bundle)
+        } else {
+            bundle.clone()
+        };
+        batch.queue_orchard_bundle(txid(position), orchard, SIGHASH);
+    }
+    assert!(!batch.validate_all(true));
+    assert_eq!(
+        batch.first_failure(),
+        Some((txid(4), BatchFailure::Sapling))
+    );
+
+    // Validation empties the queue.
+    assert!(batch.validate_all(true));
+    assert_eq!(batch.first_failure(), None);
+}
+
+#[test]
+fn block_validation_flushes_within_memory_budget() {
+    let params = generate_random_parameters::<Bls12, _, _>(Square { x: None }, &mut OsRng).unwrap();
+    let sapling_vk = prepare_verifying_key(&params.vk);
+    let orchard_vk = VerifyingKey::build();
+    let keys = VerifyingKeys {
+        sapling_spend: &sapling_vk,
+        sapling_output: &sapling_vk,
+        orchard: &orchard_vk,
+    };
+    let bundle = proven_bundle();
+
+    // Blocks of 12 transactions, with an invalid bundle in some of them.
+    let blocks = || {
+        vec![
+            None,
+            Some((9, with_corrupted_proof(&bundle), SIGHASH)),
+            Some((5, with_swapped_signature(&bundle, 1), SIGHASH)),
+            Some((0, bundle.clone(), [8; 32])),
+            Some((11, bundle.clone(), [8; 32])),
+        ]
+    };
+    let results = |memory_budget| {
+        blocks()
+            .into_iter()
+            .map(|invalid| {
+                let mut batch = orchard_block(keys, memory_budget, 12, &bundle, invalid);
+                let result = (batch.validate_all(true), batch.first_failure());
+                (result, batch.flushes())
+            })
+            .collect::<Vec<_>>()
+    };
+    let unlimited = results(UNLIMITED);
+    assert!(unlimited.iter().all(|(_, flushes)| *flushes == 0));
+    assert_eq!(unlimited[0].0, (true, None));
+    assert_eq!(
+        unlimited[1].0,
+        (false, Some((txid(9), BatchFailure::OrchardProof)))
+    );
+
+    // A budget of a byte flushes before every transaction after the first, until a
+    // flush finds the invalid bundle; the flushes after that validate nothing, and
+    // aren't counted. A budget of a few bundles flushes every few transactions. Either
+    // way, the results are those of validating each block at once.
+    let flushed = results(1);
+    for (((result, flushes), (unflushed, _)), expected) in
+        flushed.iter().zip(&unlimited).zip([11, 10, 6, 1, 11])
+    {
+        assert_eq!(result, unflushed);
+        assert_eq!(*flushes, expected);
+    }
+    for ((result, flushes), (unflushed, _)) in results(20_000).iter().zip(&unlimited) {
+        assert_eq!(result, unflushed);
+        assert!((1..11).contains(flushes));
+    }
+
+    // Once a flush finds an invalid bundle, later failures are not attributed, and the
+    // validators are ready for the next block.
+    let mut batch = BatchValidators::new(true, keys, 1);
+    for position in 0..6 {
+        let sighash = if position == 2 || position == 4 {
+            [8; 32]
+        } else {
+            SIGHASH
+        };
+        batch.queue_orchard_bundle(txid(position), bundle.clone(), sighash);
+    }
+    assert!(!batch.validate_all(true));
+    assert_eq!(
+        batch.first_failure(),
+        Some((
+            txid(2),
+            BatchFailure::Signature(SignatureKind::OrchardSpendAuth, 0)
+        ))
+    );
+    for position in 0..3 {
+        batch.queue_orchard_bundle(txid(position), bundle.clone(), SIGHASH);
+    }
+    assert!(batch.validate_all(true));
+    assert_eq!(batch.first_failure(), None);
+    assert_eq!(batch.flushes(), 5);
+}
+
+#[test]
+fn block_signatures_attribute_failures() {
+    use SignatureKind::{SaplingBinding, SaplingSpendAuth};
+
+    // A block's worth of valid Sapling signatures is valid, with or without ZIP 216.
+    let block = |invalid: Option<SignatureId>| {
+        let mut signatures = BlockSignatures::default();
+        for position in 0..20 {
+            for index in 0..3 {
+                let id = signature_id(position, SaplingSpendAuth, index);
+                signatures.add_sapling_signature(sapling_signature(id, Some(id) != invalid));
+            }
+            let id = signature_id(position, SaplingBinding, 0);
+            signatures.add_sapling_signature(sapling_signature(id, Some(id) != invalid));
+        }
+        signatures
+    };
+    for (zip216_enabled, parallel) in [(true, true), (false, false)] {
+        assert_eq!(block(None).validate(zip216_enabled, parallel), Ok(()));
+    }
+
+    // A single invalid signature of either kind is attributed to its index.
+    for invalid in [
+        signature_id(13, SaplingSpendAuth, 2),
+        signature_id(0, SaplingSpendAuth, 0),
+        signature_id(19, SaplingBinding, 0),
+    ] {
+        for (zip216_enabled, parallel) in [(true, true), (false, false)] {
+            assert_eq!(
+                block(Some(invalid)).validate(zip216_enabled, parallel),
+                Err(Some(invalid))
+            );
+        }
+    }
+}
+
+#[test]
+fn block_signatures_keep_pools_apart() {
+    let bundle = proven_bundle();
+    let orchard_block = |invalid: Option<usize>| {
+        let mut signatures = BlockSignatures::default();
+        for position in 0..4 {
+            let orchard = if Some(position) == invalid {
+                with_swapped_signature(&bundle, 1)
+            } else {
+                bundle.clone()
+            };
+            signatures.add_orchard_bundle(position, &orchard, &SIGHASH);
+        }
+        signatures
+    };
+
+    // An invalid Sapling signature among valid Orchard signatures is attributed to
+    // Sapling, even in a later transaction than the Orchard bundles.
+    let invalid = signature_id(6, SignatureKind::SaplingSpendAuth, 0);
+    let mut signatures = orchard_block(None);
+    signatures.add_sapling_signature(sapling_signature(
+        signature_id(5, SignatureKind::SaplingSpendAuth, 0),
+        true,
+    ));
+    signatures.add_sapling_signature(sapling_signature(invalid, false));
+    assert_eq!(signatures.validate(true, true), Err(Some(invalid)));
+
+    // An invalid Orchard signature among valid Sapling signatures is attributed to
+    // Orchard, and the earliest failure of either pool is reported.
+    let mut signatures = orchard_block(Some(2));
+    signatures.add_sapling_signature(sapling_signature(
+        signature_id(1, SignatureKind::SaplingBinding, 0),
+        true,
+    ));
+    assert_eq!(
+        signatures.validate(true, false),
+        Err(Some(signature_id(2, SignatureKind::OrchardSpendAuth, 1)))
+    );
+    signatures.add_sapling_signature(sapling_signature(
+        signature_id(3, SignatureKind::SaplingBinding, 0),
+        false,
+    ));
+    assert_eq!(
+        signatures.validate(true, true),
+        Err(Some(signature_id(2, SignatureKind::OrchardSpendAuth, 1)))
+    );
+}
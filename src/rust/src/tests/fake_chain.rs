@@ -0,0 +1,122 @@
+use std::ptr;
+
+use orchard::keys::{FullViewingKey, Scope, SpendingKey};
+use zcash_primitives::transaction::components::Amount;
+
+use crate::{
+    builder_ffi::{
+        orchard_builder_add_recipient, orchard_builder_add_spend, orchard_builder_build,
+        orchard_builder_free, orchard_builder_new,
+    },
+    test_wallet_ffi::{
+        apply_fake_block, random_address, zcash_test_harness_apply_fake_block,
+        zcash_test_harness_fake_orchard_note, zcash_test_harness_fake_orchard_note_free,
+        zcash_test_harness_fake_orchard_note_txid, FakeNote,
+    },
+    wallet::{orchard_wallet_add_spending_key, orchard_wallet_free, orchard_wallet_new},
+};
+
+fn spending_key() -> SpendingKey {
+    SpendingKey::from_bytes([7; 32]).unwrap()
+}
+
+#[test]
+fn fake_notes_are_deterministic() {
+    let fvk = FullViewingKey::from(&spending_key());
+    let note = |value, seed| {
+        let note = zcash_test_harness_fake_orchard_note(&fvk, value, &seed);
+        assert!(!note.is_null());
+        let mut txid = [0; 32];
+        zcash_test_harness_fake_orchard_note_txid(note, &mut txid);
+        zcash_test_harness_fake_orchard_note_free(note);
+        txid
+    };
+
+    assert_eq!(note(5000, [1; 32]), note(5000, [1; 32]));
+    assert_ne!(note(5000, [1; 32]), note(5000, [2; 32]));
+    assert!(zcash_test_harness_fake_orchard_note(&fvk, u64::MAX, &[1; 32]).is_null());
+
+    assert_eq!(random_address([3; 32]), random_address([3; 32]));
+    assert_ne!(random_address([3; 32]), random_address([4; 32]));
+    assert_ne!(
+        random_address([3; 32]),
+        fvk.address_at(0u32, Scope::External)
+    );
+}
+
+#[test]
+fn fake_notes_are_selectable_and_witnessed() {
+    let sk = spending_key();
+    let fvk = FullViewingKey::from(&sk);
+    let other_fvk = FullViewingKey::from(&SpendingKey::from_bytes([8; 32]).unwrap());
+
+    let wallet = orchard_wallet_new();
+    orchard_wallet_add_spending_key(wallet, &sk);
+
+    let first = FakeNote::new(&fvk, 10_000, [1; 32]).unwrap();
+    let other = FakeNote::new(&other_fvk, 20_000, [2; 32]).unwrap();
+    let second = FakeNote::new(&fvk, 30_000, [3; 32]).unwrap();
+    assert!(apply_fake_block(
+        unsafe { &mut *wallet },
+        &[&first, &other],
+        1
+    ));
+    let block: [*const FakeNote; 1] = [&second];
+    assert!(zcash_test_harness_apply_fake_block(
+        wallet,
+        block.as_ptr(),
+        block.len(),
+        2
+    ));
+    // Blocks must be applied in order.
+    assert!(!apply_fake_block(unsafe { &mut *wallet }, &[], 1));
+
+    let wallet_ref = unsafe { &*wallet };
+    assert!(wallet_ref.tx_involves_my_notes(&first.txid()));
+    assert!(!wallet_ref.tx_involves_my_notes(&other.txid()));
+    let notes = wallet_ref.get_filtered_notes(None, true, true);
+    assert_eq!(notes.len(), 2);
+
+    // Each note can be spent in a bundle anchored at the wallet's tree, which the
+    // builder only accepts if its witness leads to the anchor.
+    let root = wallet_ref.note_commitment_tree_root(0).unwrap();
+    let recipient = random_address([9; 32]);
+    let mut spent = 0;
+    for (outpoint, _) in notes {
+        for value in [10_000, 30_000] {
+            let builder = orchard_builder_new(true, true, &root.to_bytes());
+            let spend_info = wallet_ref.get_spend_info(outpoint, root).unwrap();
+            assert!(orchard_builder_add_spend(
+                builder,
+                Box::into_raw(Box::new(spend_info))
+            ));
+            assert!(orchard_builder_add_recipient(
+                builder,
+                ptr::null(),
+                &recipient,
+                value,
+                ptr::null(),
+            ));
+            let bundle = orchard_builder_build(builder);
+            assert!(!bundle.is_null());
+            let bundle = unsafe { Box::from_raw(bundle) };
+            // The spend balances the output only for the note's own value.
+            if *bundle.value_balance() == Amount::zero() {
+                spent += value;
+            }
+        }
+    }
+    assert_eq!(spent, 40_000);
+
+    // A spend against a different anchor is rejected.
+    let builder = orchard_builder_new(true, true, ptr::null());
+    let (outpoint, _) = wallet_ref.get_filtered_notes(None, true, true).remove(0);
+    let spend_info = wallet_ref.get_spend_info(outpoint, root).unwrap();
+    assert!(!orchard_builder_add_spend(
+        builder,
+        Box::into_raw(Box::new(spend_info))
+    ));
+    orchard_builder_free(builder);
+
+    orchard_wallet_free(wallet);
+}
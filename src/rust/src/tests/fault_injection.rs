@@ -0,0 +1,121 @@
+use std::ffi::CString;
+use std::io;
+use std::ptr;
+
+use crate::{
+    builder_ffi::{
+        orchard_builder_add_recipient, orchard_builder_build, orchard_builder_new,
+        orchard_unauthorized_bundle_prove_and_sign,
+    },
+    fault_injection::{
+        injected_failures_remaining, test_inject_failure, zcash_test_harness_inject_failure,
+        FaultSite,
+    },
+    groth16_params::{LazyProvingParams, ParamsError},
+    librustzcash_sapling_output_proof, librustzcash_sapling_spend_proof,
+    orchard_ffi::{
+        orchard_batch_validate, orchard_batch_validation_free, orchard_batch_validation_init,
+    },
+    test_wallet_ffi::random_address,
+};
+
+#[test]
+fn sites_disarm_after_count_failures() {
+    assert!(test_inject_failure("params_loading", 2));
+    assert_eq!(injected_failures_remaining(FaultSite::ParamsLoading), 2);
+
+    // A missing parameter file fails to load as if it were truncated, until the site
+    // is disarmed.
+    let spend_path = std::env::temp_dir().join("zcashd-fault-injection-spend.params");
+    let params = LazyProvingParams::new(
+        spend_path.clone(),
+        std::env::temp_dir().join("zcashd-fault-injection-output.params"),
+    );
+    for remaining in [1, 0].iter() {
+        assert!(matches!(
+            params.get(),
+            Err(ParamsError::Invalid(path, e))
+                if path == spend_path && e.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        assert_eq!(
+            injected_failures_remaining(FaultSite::ParamsLoading),
+            *remaining
+        );
+    }
+    assert!(matches!(params.get(), Err(ParamsError::Io(path, _)) if path == spend_path));
+
+    // An empty batch is valid, unless the site is armed.
+    let batch = orchard_batch_validation_init();
+    assert!(test_inject_failure("batch_validation", 1));
+    assert!(!orchard_batch_validate(batch));
+    assert!(orchard_batch_validate(batch));
+    orchard_batch_validation_free(batch);
+
+    // Unknown sites can't be armed, and a count of 0 disarms a site.
+    assert!(!test_inject_failure("sprout_proof", 1));
+    assert!(test_inject_failure("batch_validation", 3));
+    assert!(test_inject_failure("batch_validation", 0));
+    assert_eq!(injected_failures_remaining(FaultSite::BatchValidation), 0);
+
+    let site = CString::new("orchard_proof").unwrap();
+    assert!(zcash_test_harness_inject_failure(site.as_ptr(), 1));
+    assert_eq!(injected_failures_remaining(FaultSite::OrchardProof), 1);
+    let site = CString::new("orchard").unwrap();
+    assert!(!zcash_test_harness_inject_failure(site.as_ptr(), 1));
+    assert!(test_inject_failure("orchard_proof", 0));
+}
+
+#[test]
+fn armed_proofs_fail_before_reading_inputs() {
+    // The Sapling proofs fail without touching their arguments, or needing the
+    // proving parameters.
+    assert!(test_inject_failure("sapling_spend_proof", 1));
+    assert!(!librustzcash_sapling_spend_proof(
+        ptr::null_mut(),
+        ptr::null(),
+        ptr::null(),
+        ptr::null(),
+        ptr::null(),
+        ptr::null(),
+        0,
+        ptr::null(),
+        ptr::null(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+        ptr::null_mut(),
+    ));
+    assert_eq!(injected_failures_remaining(FaultSite::SaplingSpendProof), 0);
+
+    assert!(test_inject_failure("sapling_output_proof", 1));
+    assert!(!librustzcash_sapling_output_proof(
+        ptr::null_mut(),
+        ptr::null(),
+        ptr::null(),
+        ptr::null(),
+        0,
+        ptr::null_mut(),
+        ptr::null_mut(),
+    ));
+    assert_eq!(
+        injected_failures_remaining(FaultSite::SaplingOutputProof),
+        0
+    );
+
+    // The unproven bundle is freed, as it is when proving fails.
+    let builder = orchard_builder_new(true, true, ptr::null());
+    assert!(orchard_builder_add_recipient(
+        builder,
+        ptr::null(),
+        &random_address([1; 32]),
+        5000,
+        ptr::null(),
+    ));
+    let bundle = orchard_builder_build(builder);
+    assert!(!bundle.is_null());
+    let keys: [*const orchard::keys::SpendingKey; 0] = [];
+    assert!(test_inject_failure("orchard_proof", 1));
+    assert!(
+        orchard_unauthorized_bundle_prove_and_sign(bundle, keys.as_ptr(), 0, &[0; 32]).is_null()
+    );
+    assert_eq!(injected_failures_remaining(FaultSite::OrchardProof), 0);
+}
@@ -0,0 +1,225 @@
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::temp_path;
+use crate::fetch_params::{fetch_params, FetchError, ParamsFile};
+
+const FIXTURE: &[u8] = include_bytes!("./res/fetch_params_fixture.txt");
+
+const FIXTURE_FILE: ParamsFile = ParamsFile {
+    name: "fixture.params",
+    sha256: "5aa4f1745d4e515c0c9b5dfc68fb7c1dc67affbe31a02de983cb8c39a9abda96",
+};
+
+/// The requests that a [`MockServer`] has received, as `(path, range)` pairs.
+type RequestLog = Arc<Mutex<Vec<(String, Option<String>)>>>;
+
+/// A local HTTP server that serves the fixture in two parts, like the real mirrors do.
+struct MockServer {
+    url: String,
+    requests: RequestLog,
+}
+
+impl MockServer {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/downloads", listener.local_addr().unwrap());
+        let requests = RequestLog::default();
+
+        let log = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                serve(stream.unwrap(), &log);
+            }
+        });
+
+        MockServer { url, requests }
+    }
+
+    fn requests(&self) -> Vec<(String, Option<String>)> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+fn serve(mut stream: TcpStream, log: &RequestLog) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).unwrap();
+    let path = request_line.split_whitespace().nth(1).unwrap().to_owned();
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Range: ") {
+            range = Some(value.to_owned());
+        }
+    }
+    log.lock().unwrap().push((path.clone(), range.clone()));
+
+    let (part_1, part_2) = FIXTURE.split_at(FIXTURE.len() / 2);
+    let body = match path.as_str() {
+        "/downloads/fixture.params.part.1" => part_1,
+        "/downloads/fixture.params.part.2" => part_2,
+        _ => {
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+            )
+            .unwrap();
+            return;
+        }
+    };
+    let start = range
+        .map(|range| {
+            range["bytes=".len()..range.len() - 1]
+                .parse::<usize>()
+                .unwrap()
+        })
+        .unwrap_or(0);
+    if start >= body.len() {
+        write!(
+            stream,
+            "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Length: 0\r\n\r\n"
+        )
+        .unwrap();
+    } else if start > 0 {
+        write!(
+            stream,
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\n\r\n",
+            start,
+            body.len() - 1,
+            body.len(),
+            body.len() - start,
+        )
+        .unwrap();
+        stream.write_all(&body[start..]).unwrap();
+    } else {
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+    }
+}
+
+/// Returns the URL of a port that nothing is listening on.
+fn dead_mirror() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}/downloads", listener.local_addr().unwrap())
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = temp_path(name);
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+fn no_progress() -> impl FnMut(u32, u64, u64) -> bool {
+    |_, _, _| true
+}
+
+#[test]
+fn fetches_and_checks_params() {
+    let server = MockServer::start();
+    let dir = temp_dir("fetch-params");
+
+    // The first mirror is down, so the second is used.
+    let dead = dead_mirror();
+    let mut reports = vec![];
+    let mut progress = |file_index: u32, bytes_done: u64, bytes_total: u64| {
+        reports.push((file_index, bytes_done, bytes_total));
+        true
+    };
+    fetch_params(&dir, &[FIXTURE_FILE], &[&dead, &server.url], &mut progress).unwrap();
+
+    assert_eq!(fs::read(dir.join("fixture.params")).unwrap(), FIXTURE);
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    let len = FIXTURE.len() as u64;
+    assert_eq!(reports.last(), Some(&(0, len, len)));
+
+    // A valid file is left alone, without contacting the mirrors.
+    let requests = server.requests().len();
+    fetch_params(&dir, &[FIXTURE_FILE], &[&server.url], &mut no_progress()).unwrap();
+    assert_eq!(server.requests().len(), requests);
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn resumes_partial_downloads() {
+    let server = MockServer::start();
+    let dir = temp_dir("fetch-params-resume");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("fixture.params.dl.part.1"), &FIXTURE[..100]).unwrap();
+
+    fetch_params(&dir, &[FIXTURE_FILE], &[&server.url], &mut no_progress()).unwrap();
+    assert_eq!(fs::read(dir.join("fixture.params")).unwrap(), FIXTURE);
+    assert_eq!(
+        server.requests(),
+        vec![
+            (
+                "/downloads/fixture.params.part.1".to_owned(),
+                Some("bytes=100-".to_owned())
+            ),
+            ("/downloads/fixture.params.part.2".to_owned(), None),
+        ]
+    );
+
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn fetch_errors_are_distinguished() {
+    let server = MockServer::start();
+    let dir = temp_dir("fetch-params-errors");
+
+    // No mirror is reachable.
+    let res = fetch_params(&dir, &[FIXTURE_FILE], &[&dead_mirror()], &mut no_progress());
+    assert!(matches!(res, Err(FetchError::Network(_))));
+
+    // The mirror doesn't have the file.
+    let missing = ParamsFile {
+        name: "missing.params",
+        ..FIXTURE_FILE
+    };
+    let res = fetch_params(&dir, &[missing], &[&server.url], &mut no_progress());
+    assert!(matches!(res, Err(FetchError::Network(_))));
+
+    // The file doesn't have the published hash, so it is discarded.
+    let corrupt = ParamsFile {
+        sha256: "0000000000000000000000000000000000000000000000000000000000000000",
+        ..FIXTURE_FILE
+    };
+    let res = fetch_params(&dir, &[corrupt], &[&server.url], &mut no_progress());
+    assert!(
+        matches!(res, Err(FetchError::HashMismatch(path)) if path == dir.join("fixture.params"))
+    );
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    // An existing file with the wrong contents is never overwritten.
+    fs::write(dir.join("fixture.params"), b"not the params").unwrap();
+    let res = fetch_params(&dir, &[FIXTURE_FILE], &[&server.url], &mut no_progress());
+    assert!(matches!(res, Err(FetchError::ExistingInvalid(_))));
+    assert_eq!(
+        fs::read(dir.join("fixture.params")).unwrap(),
+        b"not the params"
+    );
+    fs::remove_file(dir.join("fixture.params")).unwrap();
+
+    // The progress callback can cancel the download.
+    let res = fetch_params(&dir, &[FIXTURE_FILE], &[&server.url], &mut |_, _, _| false);
+    assert!(matches!(res, Err(FetchError::Cancelled)));
+    assert!(!dir.join("fixture.params").exists());
+
+    fs::remove_dir_all(dir).unwrap();
+}
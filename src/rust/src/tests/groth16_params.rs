@@ -0,0 +1,622 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use bellman::{
+    groth16::{
+        create_random_proof, generate_random_parameters, prepare_verifying_key, verify_proof,
+        Parameters, VerifyingKey,
+    },
+    Circuit, ConstraintSystem, SynthesisError,
+};
+use blake2b_simd::Params as Blake2bParams;
+use bls12_381::{Bls12, G1Affine, G2Affine, Scalar};
+use rand_chacha::ChaCha8Rng;
+use rand_core::SeedableRng;
+
+use super::temp_path;
+use crate::{
+    groth16_params::{
+        load_verifying_keys, load_verifying_keys_with, read_proving_params, LazyProvingParams,
+        ParamsError, ParamsPaths, Progress, SaplingParamsSpec,
+    },
+    librustzcash_sapling_proving_ctx_count, librustzcash_sapling_proving_ctx_free,
+    librustzcash_sapling_proving_ctx_init, librustzcash_set_params_paths,
+    librustzcash_unload_proving_params, PARAMS_PATHS,
+};
+
+#[test]
+fn proving_params_are_not_read_until_needed() {
+    // A verification-only session records the paths, but never touches the files.
+    let params = LazyProvingParams::new(
+        temp_path("missing-spend.params"),
+        temp_path("missing-output.params"),
+    );
+    assert!(!params.is_loaded());
+}
+
+#[test]
+fn missing_proving_params_fail_on_first_use() {
+    let spend_path = temp_path("absent-spend.params");
+    let params = Arc::new(LazyProvingParams::new(
+        spend_path.clone(),
+        temp_path("absent-output.params"),
+    ));
+
+    // Concurrent first uses all see the error, and none of them poison the cell.
+    let handles: Vec<_> = (0..4)
+        .map(|_| {
+            let params = params.clone();
+            thread::spawn(move || params.get().err().map(|e| e.to_string()))
+        })
+        .collect();
+    for handle in handles {
+        let err = handle.join().unwrap().expect("loading should fail");
+        assert!(err.contains(&spend_path.display().to_string()));
+    }
+    assert!(!params.is_loaded());
+
+    // A later attempt tries again.
+    assert!(matches!(params.get(), Err(ParamsError::Io(path, _)) if path == spend_path));
+}
+
+#[test]
+fn corrupt_params_files_are_rejected() {
+    let spend_path = temp_path("corrupt-spend.params");
+    let output_path = temp_path("corrupt-output.params");
+    fs::write(&spend_path, [0xa5; 4096]).unwrap();
+    fs::write(&output_path, [0x5a; 4096]).unwrap();
+
+    assert!(
+        load_verifying_keys(&spend_path, &output_path, None, true, &mut Progress::none()).is_err()
+    );
+
+    let params = LazyProvingParams::new(spend_path.clone(), output_path.clone());
+    assert!(params.get().is_err());
+    assert!(!params.is_loaded());
+
+    fs::remove_file(spend_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+}
+
+/// Returns a well-formed verifying key with `ic_len` elements in `ic`.
+fn test_vk(ic_len: usize) -> VerifyingKey<Bls12> {
+    VerifyingKey::<Bls12> {
+        alpha_g1: G1Affine::generator(),
+        beta_g1: G1Affine::generator(),
+        beta_g2: G2Affine::generator(),
+        gamma_g2: G2Affine::generator(),
+        delta_g1: G1Affine::generator(),
+        delta_g2: G2Affine::generator(),
+        ic: vec![G1Affine::generator(); ic_len],
+    }
+}
+
+/// Writes a file that starts with `vk`, padded with zeroes to `len` bytes.
+fn write_vk_file(name: &str, vk: &VerifyingKey<Bls12>, len: usize) -> PathBuf {
+    let mut data = vec![];
+    vk.write(&mut data).unwrap();
+    data.resize(len, 0);
+
+    let path = temp_path(name);
+    File::create(&path).unwrap().write_all(&data).unwrap();
+    path
+}
+
+/// Writes a file that starts with a well-formed verifying key, so that it is read to the
+/// end (and then fails the hash check).
+fn write_params_file(name: &str, len: usize) -> PathBuf {
+    write_vk_file(name, &test_vk(2), len)
+}
+
+fn file_hash(path: &Path) -> String {
+    Blake2bParams::new()
+        .hash_length(64)
+        .hash(&fs::read(path).unwrap())
+        .to_hex()
+        .to_string()
+}
+
+#[test]
+fn embedded_sapling_vks_make_params_files_optional() {
+    let spend_path = write_vk_file("embedded-spend.params", &test_vk(2), 4096);
+    let output_path = write_vk_file("embedded-output.params", &test_vk(3), 4096);
+    let (spend_hash, output_hash) = (file_hash(&spend_path), file_hash(&output_path));
+    let spec = SaplingParamsSpec {
+        spend_hash: &spend_hash,
+        output_hash: &output_hash,
+        embedded_vks: Some((test_vk(2), test_vk(3))),
+    };
+    let load = |spend: &Path, output: &Path, force_files| {
+        load_verifying_keys_with(
+            &spec,
+            spend,
+            output,
+            None,
+            force_files,
+            &mut Progress::none(),
+        )
+    };
+
+    // Present files are checked against the embedded keys.
+    assert!(load(&spend_path, &output_path, false).is_ok());
+
+    // Absent files are only needed if the keys must be read from them.
+    let absent_spend = temp_path("embedded-absent-spend.params");
+    let absent_output = temp_path("embedded-absent-output.params");
+    assert!(load(&absent_spend, &output_path, false).is_ok());
+    assert!(load(&spend_path, &absent_output, false).is_ok());
+    let res = load(&absent_spend, &absent_output, true);
+    assert!(matches!(res, Err(ParamsError::Io(path, _)) if path == absent_spend));
+
+    // Without embedded keys, the files are always needed.
+    let spec = SaplingParamsSpec {
+        embedded_vks: None,
+        ..spec
+    };
+    let res = load_verifying_keys_with(
+        &spec,
+        &spend_path,
+        &absent_output,
+        None,
+        false,
+        &mut Progress::none(),
+    );
+    assert!(matches!(res, Err(ParamsError::Io(path, _)) if path == absent_output));
+
+    fs::remove_file(spend_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn params_files_must_contain_embedded_vks() {
+    // The files have the expected hashes, but the library embeds other keys.
+    let spend_path = write_vk_file("mismatch-spend.params", &test_vk(2), 4096);
+    let output_path = write_vk_file("mismatch-output.params", &test_vk(2), 4096);
+    let (spend_hash, output_hash) = (file_hash(&spend_path), file_hash(&output_path));
+    let load = |embedded_vks, force_files| {
+        let spec = SaplingParamsSpec {
+            spend_hash: &spend_hash,
+            output_hash: &output_hash,
+            embedded_vks: Some(embedded_vks),
+        };
+        load_verifying_keys_with(
+            &spec,
+            &spend_path,
+            &output_path,
+            None,
+            force_files,
+            &mut Progress::none(),
+        )
+    };
+
+    let res = load((test_vk(3), test_vk(2)), false);
+    assert!(matches!(res, Err(ParamsError::EmbeddedMismatch(path)) if path == spend_path));
+    let res = load((test_vk(2), test_vk(3)), false);
+    assert!(matches!(res, Err(ParamsError::EmbeddedMismatch(path)) if path == output_path));
+
+    // Forcing the keys to be read from the files skips the comparison.
+    assert!(load((test_vk(3), test_vk(3)), true).is_ok());
+
+    // A file with another hash is rejected before its key is compared.
+    let res = load_verifying_keys_with(
+        &SaplingParamsSpec {
+            spend_hash: &output_hash,
+            output_hash: &output_hash,
+            embedded_vks: Some((test_vk(3), test_vk(2))),
+        },
+        &spend_path,
+        &output_path,
+        None,
+        false,
+        &mut Progress::none(),
+    );
+    assert!(matches!(res, Err(ParamsError::HashMismatch(path)) if path == spend_path));
+
+    fs::remove_file(spend_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn progress_is_reported_while_hashing() {
+    const MIB: u64 = 1024 * 1024;
+    let len = 5 * MIB + 100;
+    let spend_path = write_params_file("progress-spend.params", len as usize);
+    let output_path = temp_path("progress-output.params");
+
+    let mut reports = vec![];
+    let mut callback = |file_index: u32, bytes_done: u64, bytes_total: u64| {
+        reports.push((file_index, bytes_done, bytes_total));
+        true
+    };
+    let res = load_verifying_keys(
+        &spend_path,
+        &output_path,
+        None,
+        true,
+        &mut Progress::new(MIB, &mut callback),
+    );
+    assert!(matches!(res, Err(ParamsError::HashMismatch(path)) if path == spend_path));
+
+    // One report per MiB, and one at the end of the file.
+    assert_eq!(reports.len(), 6);
+    for (i, &(file_index, bytes_done, bytes_total)) in reports.iter().enumerate().take(5) {
+        assert_eq!(file_index, 0);
+        assert_eq!(bytes_done / MIB, i as u64 + 1);
+        assert_eq!(bytes_total, len);
+    }
+    assert_eq!(reports[5], (0, len, len));
+
+    fs::remove_file(spend_path).unwrap();
+}
+
+#[test]
+fn progress_callback_can_cancel_loading() {
+    const MIB: u64 = 1024 * 1024;
+    let spend_path = write_params_file("cancel-spend.params", 4 * MIB as usize);
+    let output_path = temp_path("cancel-output.params");
+
+    let mut calls = 0;
+    let mut callback = |_: u32, _: u64, _: u64| {
+        calls += 1;
+        calls < 2
+    };
+    let res = load_verifying_keys(
+        &spend_path,
+        &output_path,
+        None,
+        true,
+        &mut Progress::new(MIB, &mut callback),
+    );
+    assert!(matches!(res, Err(ParamsError::Cancelled)));
+    assert_eq!(calls, 2);
+
+    fs::remove_file(spend_path).unwrap();
+}
+
+/// Changes the last byte of the file at `path`.
+fn modify_params_file(path: &Path) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.seek(SeekFrom::End(-1))?;
+    file.write_all(&[1])
+}
+
+#[test]
+fn proving_params_are_hash_checked() {
+    // A verifying key followed by zeroes parses as parameters with no query elements.
+    let path = write_params_file("hashed.params", 4096);
+    let hash = file_hash(&path);
+    assert!(read_proving_params(&path, &hash, 0).is_ok());
+
+    // The trailing bytes aren't part of the parameters, but are still covered by the hash.
+    modify_params_file(&path).unwrap();
+    let res = read_proving_params(&path, &hash, 0);
+    assert!(matches!(res, Err(ParamsError::HashMismatch(p)) if p == path));
+
+    fs::remove_file(path).unwrap();
+}
+
+/// Proves knowledge of a square root of the public input.
+struct SquareRoot(Option<Scalar>);
+
+impl Circuit<Scalar> for SquareRoot {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let root = cs.alloc(
+            || "root",
+            || self.0.ok_or(SynthesisError::AssignmentMissing),
+        )?;
+        let square = cs.alloc_input(
+            || "square",
+            || {
+                self.0
+                    .map(|r| r.square())
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?;
+        cs.enforce(
+            || "root * root = square",
+            |lc| lc + root,
+            |lc| lc + root,
+            |lc| lc + square,
+        );
+        Ok(())
+    }
+}
+
+/// Writes parameters for [`SquareRoot`] generated from `rng`, and returns their path and
+/// hash along with the parameters.
+fn write_square_root_params(
+    name: &str,
+    rng: &mut ChaCha8Rng,
+) -> (PathBuf, String, Parameters<Bls12>) {
+    let params = generate_random_parameters::<Bls12, _, _>(SquareRoot(None), rng).unwrap();
+    let path = temp_path(name);
+    let mut data = vec![];
+    params.write(&mut data).unwrap();
+    fs::write(&path, data).unwrap();
+    let hash = file_hash(&path);
+    (path, hash, params)
+}
+
+/// Checks that `params` create a valid proof of the square root of 49.
+fn square_root_proof_verifies(params: &Parameters<Bls12>, rng: &mut ChaCha8Rng) {
+    let root = Scalar::from(7u64);
+    let proof = create_random_proof(SquareRoot(Some(root)), params, rng).unwrap();
+    let pvk = prepare_verifying_key(&params.vk);
+    assert!(verify_proof(&pvk, &proof, &[root.square()]).is_ok());
+    assert!(verify_proof(&pvk, &proof, &[root]).is_err());
+}
+
+#[test]
+fn read_proving_params_create_valid_proofs() {
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let (path, hash, params) = write_square_root_params("read-proof.params", &mut rng);
+
+    let read = read_proving_params(&path, &hash, 0).unwrap();
+    assert!(read == params);
+    square_root_proof_verifies(&read, &mut rng);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unloaded_proving_params_are_reloaded() {
+    let mut rng = ChaCha8Rng::seed_from_u64(1);
+    let (spend_path, spend_hash, _) = write_square_root_params("reload-spend.params", &mut rng);
+    let (output_path, output_hash, _) = write_square_root_params("reload-output.params", &mut rng);
+    let params = LazyProvingParams::with_hashes(
+        spend_path.clone(),
+        spend_hash,
+        output_path.clone(),
+        output_hash,
+    );
+    assert_eq!(params.memory_usage(), 0);
+
+    let loaded = params.get().unwrap();
+    square_root_proof_verifies(&loaded.spend, &mut rng);
+    square_root_proof_verifies(&loaded.output, &mut rng);
+    assert!(params.is_loaded());
+    let usage = params.memory_usage();
+    assert_eq!(usage, loaded.memory_usage());
+    assert!(usage > 0);
+
+    // A proof that is still running keeps its parameters, but they are no longer
+    // counted or cached.
+    params.unload();
+    assert!(!params.is_loaded());
+    assert_eq!(params.memory_usage(), 0);
+    square_root_proof_verifies(&loaded.spend, &mut rng);
+    drop(loaded);
+
+    // The next use reloads them.
+    let reloaded = params.get().unwrap();
+    square_root_proof_verifies(&reloaded.spend, &mut rng);
+    assert!(params.is_loaded());
+    assert_eq!(params.memory_usage(), usage);
+
+    fs::remove_file(spend_path).unwrap();
+    fs::remove_file(output_path).unwrap();
+}
+
+#[test]
+fn unloading_is_refused_while_a_proving_context_is_open() {
+    let ctx = librustzcash_sapling_proving_ctx_init();
+    assert_eq!(librustzcash_sapling_proving_ctx_count(), 1);
+    assert!(!librustzcash_unload_proving_params());
+
+    librustzcash_sapling_proving_ctx_free(ctx);
+    assert_eq!(librustzcash_sapling_proving_ctx_count(), 0);
+    assert!(librustzcash_unload_proving_params());
+}
+
+#[test]
+#[cfg(not(target_os = "windows"))]
+fn params_paths_can_be_set_to_native_paths() {
+    use std::os::unix::ffi::OsStrExt;
+
+    // Unix paths need not be UTF-8.
+    let mut name = format!("zcashd-{}-ünïcödé-spend-", std::process::id()).into_bytes();
+    name.extend_from_slice(b"\xff.params");
+    let spend_path = std::env::temp_dir().join(std::ffi::OsStr::from_bytes(&name));
+    fs::rename(
+        write_params_file("non-utf8-spend.params", 4096),
+        &spend_path,
+    )
+    .unwrap();
+    let output_path = temp_path("ünïcödé-output.params");
+    let spend = spend_path.as_os_str().as_bytes();
+    let output = output_path.as_os_str().as_bytes();
+
+    // The Sapling paths are required.
+    assert!(!librustzcash_set_params_paths(
+        std::ptr::null(),
+        0,
+        output.as_ptr(),
+        output.len(),
+        std::ptr::null(),
+        0,
+    ));
+    assert!(PARAMS_PATHS.lock().unwrap().is_none());
+
+    assert!(librustzcash_set_params_paths(
+        spend.as_ptr(),
+        spend.len(),
+        output.as_ptr(),
+        output.len(),
+        std::ptr::null(),
+        0,
+    ));
+    let paths = PARAMS_PATHS.lock().unwrap().clone().unwrap();
+    assert_eq!(
+        paths,
+        ParamsPaths {
+            spend: spend_path.clone(),
+            output: output_path.clone(),
+            sprout: None,
+        }
+    );
+
+    // The file at the non-UTF-8 path is found, and its parameters are read.
+    let hash = Blake2bParams::new()
+        .hash_length(64)
+        .hash(&fs::read(&spend_path).unwrap())
+        .to_hex();
+    assert!(read_proving_params(&paths.spend, &hash, 0).is_ok());
+
+    *PARAMS_PATHS.lock().unwrap() = None;
+    fs::remove_file(spend_path).unwrap();
+}
+
+/// Tests of the Sapling verifying keys embedded in the library. The ones that compare
+/// them against the real parameter files, or make proofs with those files, are ignored
+/// unless they are run with `--ignored` and `ZCASH_PARAMS_DIR` set to the directory
+/// containing the files (see `doc/book/src/dev/rust.md`).
+mod embedded {
+    use std::path::{Path, PathBuf};
+
+    use zcash_primitives::{
+        sapling::{
+            keys::{ExpandedSpendingKey, FullViewingKey},
+            Rseed,
+        },
+        zip32::{sapling_find_address, DiversifierIndex, DiversifierKey},
+    };
+    use zcash_proofs::sapling::{SaplingProvingContext, SaplingVerificationContext};
+
+    use super::temp_path;
+    use crate::groth16_params::{
+        embedded_sapling_verifying_keys, load_verifying_keys, read_verifying_key,
+        LazyProvingParams, ParamsError, Progress, SAPLING_OUTPUT_FILE, SAPLING_OUTPUT_HASH,
+        SAPLING_SPEND_FILE, SAPLING_SPEND_HASH,
+    };
+
+    fn params_path(name: &str) -> PathBuf {
+        let dir = std::env::var_os("ZCASH_PARAMS_DIR")
+            .expect("ZCASH_PARAMS_DIR must be set to run the tests of the parameter files");
+        Path::new(&dir).join(name)
+    }
+
+    #[test]
+    fn embedded_vks_are_for_the_sapling_circuits() {
+        let (spend_vk, output_vk) = embedded_sapling_verifying_keys();
+        // The Spend circuit has seven public inputs and the Output circuit five, plus
+        // the constant input of each.
+        assert_eq!(spend_vk.ic.len(), 8);
+        assert_eq!(output_vk.ic.len(), 6);
+    }
+
+    #[test]
+    fn sapling_params_files_are_optional() {
+        let spend_path = temp_path("embedded-absent-spend.params");
+        let output_path = temp_path("embedded-absent-output.params");
+
+        assert!(load_verifying_keys(
+            &spend_path,
+            &output_path,
+            None,
+            false,
+            &mut Progress::none()
+        )
+        .is_ok());
+
+        // Operators can insist on reading the keys from the files.
+        let res = load_verifying_keys(&spend_path, &output_path, None, true, &mut Progress::none());
+        assert!(matches!(res, Err(ParamsError::Io(path, _)) if path == spend_path));
+    }
+
+    #[test]
+    #[ignore]
+    fn embedded_vks_match_params_files() {
+        let (spend_vk, output_vk) = embedded_sapling_verifying_keys();
+
+        // `read_verifying_key` checks the BLAKE2b-512 hash of the whole file.
+        let spend_path = params_path("sapling-spend.params");
+        let output_path = params_path("sapling-output.params");
+        let mut progress = Progress::none();
+        let file_spend_vk = read_verifying_key(
+            &spend_path,
+            SAPLING_SPEND_HASH,
+            SAPLING_SPEND_FILE,
+            &mut progress,
+        )
+        .unwrap();
+        let file_output_vk = read_verifying_key(
+            &output_path,
+            SAPLING_OUTPUT_HASH,
+            SAPLING_OUTPUT_FILE,
+            &mut progress,
+        )
+        .unwrap();
+
+        // `VerifyingKey` doesn't implement `Debug`.
+        assert!(file_spend_vk == spend_vk);
+        assert!(file_output_vk == output_vk);
+    }
+
+    #[test]
+    #[ignore]
+    fn present_sapling_params_files_match_embedded_vks() {
+        let spend_path = params_path("sapling-spend.params");
+        let output_path = params_path("sapling-output.params");
+        assert!(load_verifying_keys(
+            &spend_path,
+            &output_path,
+            None,
+            false,
+            &mut Progress::none()
+        )
+        .is_ok());
+
+        // The self-check is made against each file that is present.
+        let res = load_verifying_keys(
+            &output_path,
+            &spend_path,
+            None,
+            false,
+            &mut Progress::none(),
+        );
+        assert!(matches!(res, Err(ParamsError::HashMismatch(path)) if path == output_path));
+    }
+
+    #[test]
+    #[ignore]
+    fn output_proof_verifies_against_embedded_vk() {
+        let params = LazyProvingParams::new(
+            params_path("sapling-spend.params"),
+            params_path("sapling-output.params"),
+        );
+        let params = params.get().unwrap();
+
+        // Load the verifying keys with the files absent from where they're expected.
+        let vks = load_verifying_keys(
+            &temp_path("proof-absent-spend.params"),
+            &temp_path("proof-absent-output.params"),
+            None,
+            false,
+            &mut Progress::none(),
+        )
+        .unwrap();
+
+        let fvk = FullViewingKey::from_expanded_spending_key(
+            &ExpandedSpendingKey::from_spending_key(&[7; 32]),
+        );
+        let (_, address) =
+            sapling_find_address(&fvk, &DiversifierKey([0; 32]), DiversifierIndex::new()).unwrap();
+        let esk = jubjub::Scalar::from(3u64);
+        let rcm = jubjub::Scalar::from(5u64);
+        let value = 1000;
+
+        let mut ctx = SaplingProvingContext::new();
+        let (proof, cv) = ctx.output_proof(esk, address.clone(), rcm, value, &params.output);
+
+        let note = address
+            .create_note(value, Rseed::BeforeZip212(rcm))
+            .unwrap();
+        let epk = jubjub::ExtendedPoint::from(address.g_d().unwrap() * esk);
+
+        let mut ctx = SaplingVerificationContext::new(true);
+        assert!(ctx.check_output(cv, note.cmu(), epk, proof, &vks.output_vk));
+    }
+}
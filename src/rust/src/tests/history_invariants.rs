@@ -0,0 +1,402 @@
+//! Adversarial inputs for the history tree FFIs, checking that each is rejected with an
+//! error before it can reach one of the `expect`s (or panics) inside `zcash_history`.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use zcash_history::{Version, V1};
+use zcash_primitives::consensus::BranchId;
+
+use super::history_tree::v1_leaf;
+use crate::history::{node_altitude, HistoryTree, InclusionProof};
+use crate::history_ffi::{
+    librustzcash_mmr_append, librustzcash_mmr_append_verbose, librustzcash_mmr_delete,
+    librustzcash_mmr_peaks_commitment, librustzcash_mmr_root_with_replaced_leaf,
+    librustzcash_mmr_self_check, MmrAppendReport,
+};
+
+type Entry = [u8; zcash_history::MAX_ENTRY_SIZE];
+type Node = [u8; zcash_history::MAX_NODE_DATA_SIZE];
+
+/// Runs `f`, failing the test with `case` if it panics.
+fn no_panic<T>(case: &str, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| panic!("{} reached a panic", case))
+}
+
+/// A tree of 4 leaves, which has a single peak, and a tree of 13 leaves, which has
+/// three.
+fn trees() -> (HistoryTree<V1>, HistoryTree<V1>) {
+    let cbranch = BranchId::Heartwood.into();
+    let mut four = HistoryTree::empty(cbranch);
+    let mut thirteen = HistoryTree::empty(cbranch);
+    for height in 1..=13 {
+        if height <= 4 {
+            four.append(&v1_leaf(height)).unwrap();
+        }
+        thirteen.append(&v1_leaf(height)).unwrap();
+    }
+    (four, thirteen)
+}
+
+/// Encodes the node at `index` of `tree` as an entry, with the given links if it is
+/// not a leaf.
+fn entry_with_links(tree: &HistoryTree<V1>, index: u32, links: (u32, u32)) -> Entry {
+    let mut entry = [0; zcash_history::MAX_ENTRY_SIZE];
+    let data = if node_altitude(index) == 0 {
+        entry[0] = 1;
+        &mut entry[1..]
+    } else {
+        entry[1..5].copy_from_slice(&links.0.to_le_bytes());
+        entry[5..9].copy_from_slice(&links.1.to_le_bytes());
+        &mut entry[9..]
+    };
+    let node = tree.node(index).unwrap();
+    data[..node.len()].copy_from_slice(node);
+    entry
+}
+
+fn entry(tree: &HistoryTree<V1>, index: u32) -> Entry {
+    match node_altitude(index) {
+        0 => entry_with_links(tree, index, (0, 0)),
+        alt => entry_with_links(tree, index, (index - (1 << alt), index - 1)),
+    }
+}
+
+fn node(bytes: &[u8]) -> Node {
+    let mut node = [0; zcash_history::MAX_NODE_DATA_SIZE];
+    node[..bytes.len()].copy_from_slice(bytes);
+    node
+}
+
+/// Sets the top byte of `subtree_total_work` in a node encoding, which overflows
+/// `V::combine` in any tree that combines two such nodes.
+fn overflow_work(node: &mut [u8]) {
+    node[143] = 0x80;
+}
+
+fn append(t_len: u32, indices: &[u32], entries: &[Entry], new_node: &Node) -> u32 {
+    let mut rt_ret = [0; 32];
+    let mut buf_ret = [[0; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+    librustzcash_mmr_append(
+        BranchId::Heartwood.into(),
+        t_len,
+        indices.as_ptr(),
+        entries.as_ptr(),
+        std::ptr::null(),
+        indices.len(),
+        new_node,
+        &mut rt_ret,
+        buf_ret.as_mut_ptr(),
+    )
+}
+
+fn delete(t_len: u32, indices: &[u32], entries: &[Entry], p_len: usize) -> u32 {
+    let mut rt_ret = [0; 32];
+    librustzcash_mmr_delete(
+        BranchId::Heartwood.into(),
+        t_len,
+        indices.as_ptr(),
+        entries.as_ptr(),
+        std::ptr::null(),
+        p_len,
+        indices.len() - p_len,
+        &mut rt_ret,
+    )
+}
+
+#[test]
+fn append_rejects_malformed_peak_sets() {
+    let (_, tree) = trees();
+    let t_len = tree.len();
+    assert_eq!(t_len, 23);
+    let peaks = [14, 21, 22];
+    let entries: Vec<_> = peaks.iter().map(|&index| entry(&tree, index)).collect();
+    let new_leaf = node(&v1_leaf(14));
+    assert_eq!(append(t_len, &peaks, &entries, &new_leaf), 2);
+
+    let cases: Vec<(&str, u32, Vec<u32>, Vec<Entry>)> = vec![
+        ("no peaks", t_len, vec![], vec![]),
+        (
+            "an empty tree",
+            0,
+            peaks[..1].to_vec(),
+            entries[..1].to_vec(),
+        ),
+        (
+            "an impossible length",
+            2,
+            peaks[..1].to_vec(),
+            entries[..1].to_vec(),
+        ),
+        (
+            "a missing peak",
+            t_len,
+            peaks[..2].to_vec(),
+            entries[..2].to_vec(),
+        ),
+        (
+            "swapped peaks",
+            t_len,
+            vec![21, 14, 22],
+            vec![entries[1], entries[0], entries[2]],
+        ),
+        ("a wrong peak", t_len, vec![13, 21, 22], entries.clone()),
+        (
+            "a self-linked peak",
+            t_len,
+            peaks.to_vec(),
+            vec![
+                entry_with_links(&tree, 14, (14, 14)),
+                entries[1],
+                entries[2],
+            ],
+        ),
+        (
+            "a peak linked to a later node",
+            t_len,
+            peaks.to_vec(),
+            vec![entry_with_links(&tree, 14, (6, 22)), entries[1], entries[2]],
+        ),
+        (
+            "the peaks of a shorter tree",
+            25,
+            peaks.to_vec(),
+            entries.clone(),
+        ),
+    ];
+    for (case, t_len, indices, entries) in cases {
+        assert_eq!(
+            no_panic(case, || append(t_len, &indices, &entries, &new_leaf)),
+            0,
+            "{}",
+            case
+        );
+    }
+
+    // Bagging the peaks into the root would overflow the total work.
+    let mut heavy = entries.clone();
+    overflow_work(&mut heavy[0][9..]);
+    overflow_work(&mut heavy[1][9..]);
+    assert_eq!(
+        no_panic("overflowing peaks", || append(
+            t_len, &peaks, &heavy, &new_leaf
+        )),
+        0
+    );
+    let mut heavy_leaf = new_leaf;
+    overflow_work(&mut heavy_leaf);
+    let mut heavy = entries.clone();
+    overflow_work(&mut heavy[2][1..]);
+    assert_eq!(
+        no_panic("an overflowing leaf", || append(
+            t_len,
+            &peaks,
+            &heavy,
+            &heavy_leaf
+        )),
+        0
+    );
+
+    // The verbose append reports a self-linked peak as a node that can't be decoded.
+    let mut report = MmrAppendReport {
+        reconstruct_status: 0,
+        reconstruct_index: 0,
+        nodes_status: 0,
+        nodes_index: 0,
+        new_node_status: 0,
+        append_status: 0,
+    };
+    let self_linked = [
+        entry_with_links(&tree, 14, (14, 14)),
+        entries[1],
+        entries[2],
+    ];
+    let result = no_panic("a self-linked peak", || {
+        librustzcash_mmr_append_verbose(
+            BranchId::Heartwood.into(),
+            t_len,
+            peaks.as_ptr(),
+            self_linked.as_ptr(),
+            peaks.len(),
+            &new_leaf,
+            &mut [0; 32],
+            [[0; zcash_history::MAX_NODE_DATA_SIZE]; 32].as_mut_ptr(),
+            &mut report,
+        )
+    });
+    assert_eq!(result, 0);
+    assert_eq!((report.nodes_status, report.nodes_index), (1, 14));
+}
+
+#[test]
+fn delete_rejects_malformed_node_sets() {
+    let (four, _) = trees();
+    assert_eq!(four.len(), 7);
+    let indices = [6, 2, 5, 3, 4];
+    let entries: Vec<_> = indices.iter().map(|&index| entry(&four, index)).collect();
+    assert_eq!(delete(7, &indices, &entries, 1), 3);
+
+    let cases: Vec<(&str, u32, Vec<u32>, Vec<Entry>)> = vec![
+        ("no peaks", 1, vec![], vec![]),
+        ("an empty tree", 0, vec![0], vec![entry(&four, 0)]),
+        ("an impossible length", 5, indices.to_vec(), entries.clone()),
+        // Only the peak and its right child are provided, so the left child that
+        // becomes the root is missing.
+        (
+            "a repeated extra",
+            3,
+            vec![2, 1, 1],
+            vec![entry(&four, 2), entry(&four, 1), entry(&four, 1)],
+        ),
+        (
+            "an extra that isn't needed",
+            7,
+            vec![6, 2, 5, 3, 0],
+            vec![
+                entries[0],
+                entries[1],
+                entries[2],
+                entries[3],
+                entry(&four, 0),
+            ],
+        ),
+        (
+            "a self-linked extra",
+            7,
+            indices.to_vec(),
+            vec![
+                entries[0],
+                entries[1],
+                entry_with_links(&four, 5, (3, 5)),
+                entries[3],
+                entries[4],
+            ],
+        ),
+    ];
+    // Every tree here has a single peak.
+    for (case, t_len, indices, entries) in cases {
+        let p_len = indices.len().min(1);
+        assert_eq!(
+            no_panic(case, || delete(t_len, &indices, &entries, p_len)),
+            0,
+            "{}",
+            case
+        );
+    }
+}
+
+#[test]
+fn other_reconstructions_reject_malformed_nodes() {
+    let cbranch = BranchId::Heartwood.into();
+    let (four, tree) = trees();
+    let t_len = tree.len();
+    let peaks = [14, 21, 22];
+    let entries: Vec<_> = peaks.iter().map(|&index| entry(&tree, index)).collect();
+    let root = tree.root().unwrap();
+
+    let self_check = |t_len, indices: &[u32], entries: &[Entry], p_len| {
+        librustzcash_mmr_self_check(
+            cbranch,
+            t_len,
+            indices.as_ptr(),
+            entries.as_ptr(),
+            p_len,
+            indices.len() - p_len,
+            &root,
+        )
+    };
+    assert_eq!(self_check(t_len, &peaks, &entries, 3), 1);
+    // The peaks passed off as extras, and a peak linked to itself.
+    assert_eq!(
+        no_panic("peaks as extras", || self_check(t_len, &peaks, &entries, 1)),
+        2
+    );
+    let self_linked = [
+        entry_with_links(&tree, 14, (14, 14)),
+        entries[1],
+        entries[2],
+    ];
+    assert_eq!(
+        no_panic("a self-linked peak", || self_check(
+            t_len,
+            &peaks,
+            &self_linked,
+            3
+        )),
+        2
+    );
+
+    let mut commitment = [0; 32];
+    assert_eq!(
+        no_panic("a self-linked peak", || librustzcash_mmr_peaks_commitment(
+            cbranch,
+            t_len,
+            peaks.as_ptr(),
+            self_linked.as_ptr(),
+            3,
+            &mut commitment,
+        )),
+        1
+    );
+
+    // Replacing a leaf with one of overflowing work, next to a sibling that also has it.
+    let indices = [6, 0, 1, 5];
+    let mut entries: Vec<_> = indices.iter().map(|&index| entry(&four, index)).collect();
+    let mut leaf = node(four.node(0).unwrap());
+    overflow_work(&mut entries[2][1..]);
+    overflow_work(&mut leaf);
+    assert_eq!(
+        no_panic("an overflowing leaf", || {
+            librustzcash_mmr_root_with_replaced_leaf(
+                cbranch,
+                7,
+                indices.as_ptr(),
+                entries.as_ptr(),
+                indices.len(),
+                0,
+                &leaf,
+                &mut [0; 32],
+            )
+        }),
+        1
+    );
+}
+
+#[test]
+fn history_tree_rejects_overflowing_work() {
+    let cbranch = BranchId::Heartwood.into();
+    let (_, tree) = trees();
+    let root = tree.root().unwrap();
+
+    let mut peaks: Vec<_> = [14, 21, 22]
+        .iter()
+        .map(|&index| (index, tree.node(index).unwrap().to_vec()))
+        .collect();
+    overflow_work(&mut peaks[0].1);
+    overflow_work(&mut peaks[1].1);
+    let mut heavy = HistoryTree::<V1>::from_nodes(cbranch, tree.len(), peaks).unwrap();
+    assert!(no_panic("overflowing peaks", || heavy.root()).is_err());
+    let mut leaf = v1_leaf(14);
+    overflow_work(&mut leaf);
+    assert!(no_panic("an overflowing leaf", || heavy.append(&leaf)).is_err());
+
+    let proof = tree.prove_range(12..13).next().unwrap().unwrap();
+    assert!(proof.verify::<V1>(&root));
+    let mut heavy_proof = InclusionProof {
+        leaf: leaf.clone(),
+        ..proof
+    };
+    overflow_work(&mut heavy_proof.other_peaks[0]);
+    assert!(!no_panic("an overflowing proof", || heavy_proof.verify::<V1>(&root)));
+
+    // Work just below the bound is still accepted.
+    let mut leaf = v1_leaf(14);
+    leaf[139] = 0xff;
+    let node = V1::from_bytes(cbranch, &leaf).unwrap();
+    assert_eq!(
+        HistoryTree::<V1>::empty(cbranch)
+            .append(&leaf)
+            .unwrap()
+            .new_root,
+        V1::hash(&node)
+    );
+}
@@ -0,0 +1,553 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use zcash_history::{NodeData, Version, V1, V2};
+use zcash_primitives::consensus::BranchId;
+
+use crate::history::{
+    combine_batched, HistoryError, HistoryTree, LeafBuilder, LeafVersion, MultiProof,
+};
+use crate::history_ffi::librustzcash_mmr_min_storage_set;
+
+pub(super) fn v1_leaf(height: u64) -> Vec<u8> {
+    let mut sapling_root = [0u8; 32];
+    sapling_root[..8].copy_from_slice(&height.to_le_bytes());
+    V1::to_bytes(&NodeData {
+        consensus_branch_id: BranchId::Heartwood.into(),
+        subtree_commitment: [height as u8; 32],
+        start_time: 1_600_000_000 + height as u32,
+        end_time: 1_600_000_000 + height as u32,
+        start_target: 0x1f07ffff,
+        end_target: 0x1f07ffff,
+        start_sapling_root: sapling_root,
+        end_sapling_root: sapling_root,
+        subtree_total_work: Default::default(),
+        start_height: height,
+        end_height: height,
+        sapling_tx: height % 3,
+    })
+}
+
+#[test]
+fn tracks_length_across_appends_and_deletes() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut tree = HistoryTree::<V1>::empty(cbranch);
+    let mut roots = vec![];
+
+    for height in 1..=20u64 {
+        let old_len = tree.len();
+        let result = tree.append(&v1_leaf(height)).unwrap();
+        // A tree with n leaves has 2n - popcount(n) nodes.
+        assert_eq!(result.new_len, 2 * height as u32 - height.count_ones());
+        assert_eq!(result.new_len, tree.len());
+        assert_eq!(result.new_len - old_len, result.appended_nodes.len() as u32);
+        assert_eq!(result.appended_nodes[0], v1_leaf(height));
+        assert_eq!(tree.root().unwrap(), result.new_root);
+        roots.push(result.new_root);
+    }
+
+    for leaves in (1..20u32).rev() {
+        let result = tree.delete().unwrap();
+        assert_eq!(result.new_len, 2 * leaves - leaves.count_ones());
+        assert_eq!(result.new_len, tree.len());
+        assert_eq!(result.new_root, roots[leaves as usize - 1]);
+    }
+
+    let result = tree.delete().unwrap();
+    assert_eq!(result.new_len, 0);
+    assert!(tree.is_empty());
+    assert!(tree.delete().is_err());
+
+    // The tree can be rebuilt after being emptied.
+    let result = tree.append(&v1_leaf(1)).unwrap();
+    assert_eq!(result.new_root, roots[0]);
+}
+
+#[test]
+fn requires_the_nodes_it_needs() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut full = HistoryTree::<V1>::empty(cbranch);
+    for height in 1..=4 {
+        full.append(&v1_leaf(height)).unwrap();
+    }
+    assert_eq!(full.len(), 7);
+
+    // Only the peak is needed to append, but its children are needed to delete.
+    let mut partial =
+        HistoryTree::<V1>::from_nodes(cbranch, 7, vec![(6, full.node(6).unwrap().to_vec())])
+            .unwrap();
+    assert_eq!(partial.root().unwrap(), full.root().unwrap());
+    assert!(partial.delete().is_err());
+    assert_eq!(partial.len(), 7);
+    assert_eq!(
+        partial.append(&v1_leaf(5)).unwrap().new_root,
+        full.append(&v1_leaf(5)).unwrap().new_root
+    );
+    assert_eq!(partial.len(), full.len());
+
+    assert!(HistoryTree::<V1>::from_nodes(cbranch, 2, vec![]).is_err());
+}
+
+#[test]
+fn fetches_only_the_nodes_it_needs() {
+    let cbranch = BranchId::Heartwood.into();
+    let leaves = (1..=11).map(v1_leaf).collect::<Vec<_>>();
+    let mut full = HistoryTree::<V1>::from_leaves(cbranch, &leaves).unwrap();
+    // Eleven leaves make peaks of 8, 2, and 1 leaves.
+    assert_eq!(full.len(), 19);
+
+    let db: HashMap<u32, Vec<u8>> = (0..full.len())
+        .map(|index| (index, full.node(index).unwrap().to_vec()))
+        .collect();
+    let fetched = Arc::new(Mutex::new(vec![]));
+    let fetch = {
+        let fetched = fetched.clone();
+        move |index| {
+            fetched.lock().unwrap().push(index);
+            db.get(&index).cloned()
+        }
+    };
+    let take_fetched = || std::mem::take(&mut *fetched.lock().unwrap());
+
+    let mut lazy = HistoryTree::<V1>::from_fetcher(cbranch, 19, fetch).unwrap();
+    assert_eq!(take_fetched(), vec![14, 17, 18]);
+    assert_eq!(lazy.root().unwrap(), full.root().unwrap());
+
+    // An append only needs the peaks, which the tree already holds.
+    let appended = lazy.append(&v1_leaf(12)).unwrap();
+    assert_eq!(
+        appended.appended_nodes,
+        full.append(&v1_leaf(12)).unwrap().appended_nodes
+    );
+    assert!(take_fetched().is_empty());
+
+    // Deleting the leaf again only needs nodes the append created.
+    assert_eq!(
+        lazy.delete().unwrap().new_root,
+        full.delete().unwrap().new_root
+    );
+    assert!(take_fetched().is_empty());
+
+    // Deleting the eleventh leaf just removes its peak, but the next delete needs the
+    // children of the peak that is then last.
+    assert_eq!(
+        lazy.delete().unwrap().new_root,
+        full.delete().unwrap().new_root
+    );
+    assert!(take_fetched().is_empty());
+    assert_eq!(
+        lazy.delete().unwrap().new_root,
+        full.delete().unwrap().new_root
+    );
+    assert_eq!(take_fetched(), vec![15, 16]);
+    assert_eq!(lazy.len(), 16);
+
+    // A node the fetcher can't provide fails the operation that needs it.
+    let db: HashMap<u32, Vec<u8>> = (0..16)
+        .filter(|&index| index != 13)
+        .map(|index| (index, full.node(index).unwrap().to_vec()))
+        .collect();
+    let fetch = move |index| db.get(&index).cloned();
+    let mut partial = HistoryTree::<V1>::from_fetcher(cbranch, 16, fetch).unwrap();
+    partial.delete().unwrap();
+    assert!(matches!(
+        partial.delete(),
+        Err(HistoryError::MissingNode(13))
+    ));
+    assert_eq!(partial.len(), 15);
+    assert!(matches!(
+        HistoryTree::<V1>::from_fetcher(cbranch, 19, |_| None),
+        Err(HistoryError::MissingNode(14))
+    ));
+}
+
+#[test]
+fn light_leaf_append_matches_full_leaf_append() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut full = HistoryTree::<V1>::empty(cbranch);
+    let mut light = HistoryTree::<V1>::empty(cbranch);
+
+    for height in 1..=6 {
+        let leaf = v1_leaf(height);
+        let leaf_hash = V1::hash(&V1::from_bytes(cbranch, &leaf).unwrap());
+        assert_eq!(
+            light.append_light_leaf(&leaf_hash, &leaf).unwrap().new_root,
+            full.append(&leaf).unwrap().new_root
+        );
+    }
+
+    // A leaf that doesn't hash to the authoritative hash is rejected.
+    let leaf = v1_leaf(7);
+    let mut leaf_hash = V1::hash(&V1::from_bytes(cbranch, &leaf).unwrap());
+    leaf_hash[0] ^= 1;
+    assert!(matches!(
+        light.append_light_leaf(&leaf_hash, &leaf),
+        Err(HistoryError::LeafHashMismatch)
+    ));
+    assert_eq!(light.len(), full.len());
+    assert_eq!(light.root().unwrap(), full.root().unwrap());
+}
+
+#[test]
+fn range_proofs_verify_against_the_root() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut tree = HistoryTree::<V1>::empty(cbranch);
+    assert!(matches!(
+        tree.prove_range(0..1).next(),
+        Some(Err(HistoryError::NoSuchLeaf(0)))
+    ));
+
+    for height in 1..=13 {
+        tree.append(&v1_leaf(height)).unwrap();
+    }
+    let root = tree.root().unwrap();
+
+    let proofs = tree
+        .prove_range(0..13)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(proofs.len(), 13);
+    for (leaf, proof) in proofs.iter().enumerate() {
+        assert_eq!(proof.leaf, v1_leaf(leaf as u64 + 1));
+        assert!(proof.verify::<V1>(&root));
+    }
+
+    // A proof doesn't verify against another root, or with a tampered sibling.
+    let other = tree.append(&v1_leaf(14)).unwrap().new_root;
+    assert!(!proofs[0].verify::<V1>(&other));
+    tree.delete().unwrap();
+    let mut tampered = proofs[0].clone();
+    tampered.siblings[0] = v1_leaf(99);
+    assert!(!tampered.verify::<V1>(&root));
+
+    // Proofs are built lazily, so leaves past the end only fail when reached.
+    let mut proofs = tree.prove_range(12..14);
+    assert!(proofs.next().unwrap().unwrap().verify::<V1>(&root));
+    assert!(matches!(
+        proofs.next(),
+        Some(Err(HistoryError::NoSuchLeaf(13)))
+    ));
+
+    // Only the peaks are known here, so only a leaf that is a peak can be proven.
+    let partial = HistoryTree::<V1>::from_nodes(
+        cbranch,
+        tree.len(),
+        [14, 21, 22].map(|index| (index, tree.node(index).unwrap().to_vec())),
+    )
+    .unwrap();
+    assert!(matches!(
+        partial.prove_range(0..1).next(),
+        Some(Err(HistoryError::MissingNode(0)))
+    ));
+    assert!(partial
+        .prove_range(12..13)
+        .next()
+        .unwrap()
+        .unwrap()
+        .verify::<V1>(&root));
+}
+
+#[test]
+fn multiproofs_verify_against_the_root() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut tree = HistoryTree::<V1>::empty(cbranch);
+    for height in 1..=13 {
+        tree.append(&v1_leaf(height)).unwrap();
+    }
+    let root = tree.root().unwrap();
+
+    // The leaves share most of their paths, and the last one is a peak.
+    let leaves = [5, 0, 1, 2, 12, 1];
+    let proof = tree.prove_leaves(&leaves).unwrap();
+    assert_eq!(proof.leaf_indices, [0, 1, 3, 8, 22]);
+    assert!(proof.verify::<V1>(&root));
+
+    let separate = tree
+        .prove_range(0..13)
+        .enumerate()
+        .filter(|(leaf, _)| leaves.contains(&(*leaf as u32)))
+        .map(|(_, proof)| proof.unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(separate.len(), proof.leaf_indices.len());
+    let separate_nodes = separate
+        .iter()
+        .map(|proof| 1 + proof.siblings.len() + proof.other_peaks.len())
+        .sum::<usize>();
+    let separate_bytes = separate
+        .iter()
+        .flat_map(|proof| {
+            Some(&proof.leaf)
+                .into_iter()
+                .chain(&proof.siblings)
+                .chain(&proof.other_peaks)
+        })
+        .map(|node| node.len())
+        .sum::<usize>();
+    let mut bytes = vec![];
+    proof.write(&mut bytes).unwrap();
+    assert!(proof.leaves.len() + proof.witnesses.len() < separate_nodes / 2);
+    assert!(bytes.len() < separate_bytes / 2);
+
+    // The proof survives a round trip, but only for the same leaves.
+    let read = MultiProof::read(cbranch, tree.len(), proof.leaf_indices.clone(), &bytes).unwrap();
+    assert!(read.verify::<V1>(&root));
+    assert!(
+        MultiProof::read(cbranch, tree.len(), vec![0, 1, 3, 7, 22], &bytes)
+            .map_or(true, |read| !read.verify::<V1>(&root))
+    );
+    assert!(MultiProof::read(cbranch, tree.len(), vec![0, 1, 3], &bytes).is_none());
+    assert!(MultiProof::read(cbranch, tree.len(), vec![0, 1, 2, 8, 22], &bytes).is_none());
+    assert!(MultiProof::read(cbranch, tree.len(), vec![3, 1, 0, 8, 22], &bytes).is_none());
+    assert!(
+        MultiProof::read(cbranch, tree.len(), proof.leaf_indices.clone(), &bytes[1..]).is_none()
+    );
+
+    // A proof doesn't verify against another root, or with a tampered node.
+    let other = tree.append(&v1_leaf(14)).unwrap().new_root;
+    assert!(!proof.verify::<V1>(&other));
+    tree.delete().unwrap();
+    let mut tampered = proof.clone();
+    tampered.witnesses[0] = v1_leaf(99);
+    assert!(!tampered.verify::<V1>(&root));
+    let mut tampered = proof.clone();
+    tampered.leaves[0] = v1_leaf(99);
+    assert!(!tampered.verify::<V1>(&root));
+
+    assert!(matches!(
+        tree.prove_leaves(&[3, 13]),
+        Err(HistoryError::NoSuchLeaf(13))
+    ));
+    assert!(matches!(tree.prove_leaves(&[]), Err(HistoryError::Empty)));
+}
+
+fn v2_leaf(height: u64) -> Vec<u8> {
+    LeafBuilder::new()
+        .subtree_commitment([height as u8; 32])
+        .start_time(1_650_000_000 + height as u32)
+        .end_time(1_650_000_000 + height as u32)
+        .start_height(height)
+        .end_height(height)
+        .start_orchard_root([height as u8 ^ 0xff; 32])
+        .end_orchard_root([height as u8 ^ 0xff; 32])
+        .orchard_tx_count(height % 5)
+        .build(LeafVersion::V2)
+}
+
+#[test]
+fn batched_combine_matches_combine() {
+    fn check<V: Version>(cbranch: u32, leaf: fn(u64) -> Vec<u8>) {
+        let node = |height| V::from_bytes(cbranch, leaf(height)).unwrap();
+        let pairs = (1..=9)
+            .map(|i| (node(2 * i), node(2 * i + 1)))
+            .collect::<Vec<_>>();
+        // Parents are combined as well as leaves.
+        let parents = pairs
+            .iter()
+            .map(|(left, right)| V::combine(left, right))
+            .collect::<Vec<_>>();
+        let grandparents = parents
+            .chunks_exact(4)
+            .map(|quad| {
+                (
+                    V::combine(&quad[0], &quad[1]),
+                    V::combine(&quad[2], &quad[3]),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        for pairs in [&pairs[..], &grandparents, &pairs[..1], &[]] {
+            let batched = combine_batched::<V>(pairs);
+            assert_eq!(batched.len(), pairs.len());
+            for ((left, right), parent) in pairs.iter().zip(&batched) {
+                assert_eq!(V::to_bytes(parent), V::to_bytes(&V::combine(left, right)));
+            }
+        }
+    }
+
+    check::<V1>(BranchId::Heartwood.into(), v1_leaf);
+    check::<V2>(BranchId::Nu5.into(), v2_leaf);
+}
+
+#[test]
+fn from_leaves_matches_appends() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut appended = HistoryTree::<V1>::empty(cbranch);
+    let mut leaves = vec![];
+    for height in 1..=70 {
+        leaves.push(v1_leaf(height));
+        appended.append(leaves.last().unwrap()).unwrap();
+
+        let built = HistoryTree::<V1>::from_leaves(cbranch, &leaves).unwrap();
+        assert_eq!(built.len(), appended.len());
+        for index in 0..built.len() {
+            assert_eq!(built.node(index), appended.node(index), "node {}", index);
+        }
+        assert_eq!(built.root().unwrap(), appended.root().unwrap());
+    }
+
+    let cbranch = BranchId::Nu5.into();
+    let leaves = (1..=21).map(v2_leaf).collect::<Vec<_>>();
+    let mut appended = HistoryTree::<V2>::empty(cbranch);
+    for leaf in &leaves {
+        appended.append(leaf).unwrap();
+    }
+    let built = HistoryTree::<V2>::from_leaves(cbranch, &leaves).unwrap();
+    assert_eq!(built.root().unwrap(), appended.root().unwrap());
+
+    let empty: &[Vec<u8>] = &[];
+    assert!(HistoryTree::<V1>::from_leaves(cbranch, empty)
+        .unwrap()
+        .is_empty());
+    assert!(matches!(
+        HistoryTree::<V1>::from_leaves(cbranch, &[v1_leaf(1), vec![0; 10]]),
+        Err(HistoryError::InvalidNode(1))
+    ));
+}
+
+#[test]
+fn combine_batched_matches_combine() {
+    let cbranch = BranchId::Heartwood.into();
+    let leaves = (1..=64).map(v1_leaf).collect::<Vec<_>>();
+    let mut level = leaves
+        .iter()
+        .map(|leaf| V1::from_bytes(cbranch, leaf).unwrap())
+        .collect::<Vec<_>>();
+
+    // Combine each level in a batch and one pair at a time, up to the single peak.
+    while level.len() > 1 {
+        let pairs = level
+            .chunks_exact(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect::<Vec<_>>();
+        let batched = combine_batched::<V1>(&pairs);
+        level = pairs
+            .iter()
+            .map(|(left, right)| V1::combine(left, right))
+            .collect();
+        assert!(level
+            .iter()
+            .zip(&batched)
+            .all(|(a, b)| V1::to_bytes(a) == V1::to_bytes(b)));
+    }
+
+    // `from_leaves` only combines in batches with the `simd` feature, so this checks
+    // whichever path the tests were built with against the portable combine.
+    let tree = HistoryTree::<V1>::from_leaves(cbranch, &leaves).unwrap();
+    assert_eq!(tree.root().unwrap(), V1::hash(&level[0]));
+}
+
+#[test]
+fn leaf_builder_matches_node_encoding() {
+    for height in 1..=3u64 {
+        let mut sapling_root = [0u8; 32];
+        sapling_root[..8].copy_from_slice(&height.to_le_bytes());
+        let builder = LeafBuilder::new()
+            .subtree_commitment([height as u8; 32])
+            .start_time(1_600_000_000 + height as u32)
+            .end_time(1_600_000_000 + height as u32)
+            .start_target(0x1f07ffff)
+            .end_target(0x1f07ffff)
+            .start_sapling_root(sapling_root)
+            .end_sapling_root(sapling_root)
+            .start_height(height)
+            .end_height(height)
+            .sapling_tx_count(height % 3)
+            // Ignored by V1 leaves.
+            .orchard_tx_count(7);
+        assert_eq!(builder.build(LeafVersion::V1), v1_leaf(height));
+    }
+
+    let cbranch = BranchId::Nu5.into();
+    let leaf = LeafBuilder::new()
+        .subtree_commitment([1; 32])
+        .start_time(1_650_000_000)
+        .end_time(1_650_000_000)
+        .start_height(1_687_104)
+        .end_height(1_687_104)
+        .start_orchard_root([2; 32])
+        .end_orchard_root([3; 32])
+        .orchard_tx_count(4)
+        .build(LeafVersion::V2);
+    let node = V2::from_bytes(cbranch, &leaf).unwrap();
+    assert_eq!(node.start_orchard_root, [2; 32]);
+    assert_eq!(node.end_orchard_root, [3; 32]);
+    assert_eq!(node.orchard_tx, 4);
+    assert_eq!(V2::to_bytes(&node), leaf);
+
+    let mut tree = HistoryTree::<V2>::empty(cbranch);
+    assert_eq!(tree.append(&leaf).unwrap().new_root, V2::hash(&node));
+}
+
+#[test]
+fn min_storage_set_supports_reorgs() {
+    let cbranch = BranchId::Heartwood.into();
+    let mut full = HistoryTree::<V1>::empty(cbranch);
+    let mut roots = vec![[0; 32]];
+    for height in 1..=37 {
+        roots.push(full.append(&v1_leaf(height)).unwrap().new_root);
+    }
+
+    for reorg_depth in [0, 1, 3, 10, 37, 50] {
+        let mut len = 0;
+        let mut indices = vec![0; full.len() as usize];
+        assert_eq!(
+            librustzcash_mmr_min_storage_set(
+                full.len(),
+                reorg_depth,
+                indices.as_mut_ptr(),
+                indices.len(),
+                &mut len,
+            ),
+            0
+        );
+        indices.truncate(len);
+        if reorg_depth == 0 {
+            // Only the peaks are needed to append.
+            assert_eq!(indices, [62, 69, 70]);
+        }
+
+        let mut partial = HistoryTree::<V1>::from_nodes(
+            cbranch,
+            full.len(),
+            indices
+                .iter()
+                .map(|&index| (index, full.node(index).unwrap().to_vec())),
+        )
+        .unwrap();
+
+        let depth = reorg_depth.min(37) as usize;
+        for leaves in (37 - depth..37).rev() {
+            assert_eq!(partial.delete().unwrap().new_root, roots[leaves]);
+        }
+        // Re-appending different blocks at the same heights, and then reorging them out
+        // again.
+        let mut reorged_roots = vec![roots[37 - depth]];
+        for height in 37 - depth as u64 + 1..=37 {
+            let mut leaf = v1_leaf(height);
+            leaf[0] ^= 1;
+            reorged_roots.push(partial.append(&leaf).unwrap().new_root);
+        }
+        for leaves in (0..depth).rev() {
+            assert_eq!(partial.delete().unwrap().new_root, reorged_roots[leaves]);
+        }
+        // And re-appending the original blocks.
+        for height in 37 - depth as u64 + 1..=37 {
+            assert_eq!(
+                partial.append(&v1_leaf(height)).unwrap().new_root,
+                roots[height as usize]
+            );
+        }
+    }
+
+    // An undersized buffer is reported along with the required length.
+    let mut len = 0;
+    assert_eq!(
+        librustzcash_mmr_min_storage_set(full.len(), 3, std::ptr::null_mut(), 0, &mut len),
+        2
+    );
+    assert!(len > 3);
+    assert_eq!(
+        librustzcash_mmr_min_storage_set(5, 0, std::ptr::null_mut(), 0, &mut len),
+        1
+    );
+}
@@ -0,0 +1,76 @@
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+use tracing_subscriber::{filter::EnvFilter, layer::SubscriberExt, reload};
+
+use crate::tracing_ffi::{tracing_free_filter, tracing_get_filter, tracing_reload, TracingHandle};
+
+/// Collects the formatted events so that the test can inspect them.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Captured {
+    fn contains(&self, message: &str) -> bool {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).contains(message)
+    }
+}
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn get_filter(handle: &TracingHandle) -> String {
+    let directives = tracing_get_filter(handle);
+    let filter = unsafe { CStr::from_ptr(directives) }
+        .to_str()
+        .unwrap()
+        .to_owned();
+    tracing_free_filter(directives);
+    filter
+}
+
+#[test]
+fn log_filter_can_be_reloaded() {
+    let captured = Captured::default();
+    let writer = captured.clone();
+    let (filter, reload_handle) = reload::Layer::new(EnvFilter::new("info"));
+    let subscriber = tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .without_time()
+                .with_writer(move || writer.clone()),
+        )
+        .with(filter);
+    let mut handle = TracingHandle::new(None, reload_handle, "info");
+    assert_eq!(get_filter(&handle), "info");
+
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::trace!(target: "wallet", "before the reload");
+        assert!(!captured.contains("before the reload"));
+
+        assert!(tracing_reload(
+            &mut handle,
+            b"info,wallet=trace\0".as_ptr() as *const _,
+        ));
+        assert_eq!(get_filter(&handle), "info,wallet=trace");
+        tracing::trace!(target: "wallet", "after the reload");
+        assert!(captured.contains("after the reload"));
+
+        // An invalid directive leaves the active filter in place.
+        assert!(!tracing_reload(
+            &mut handle,
+            b"wallet=verbose\0".as_ptr() as *const _,
+        ));
+        assert_eq!(get_filter(&handle), "info,wallet=trace");
+        tracing::trace!(target: "wallet", "after the rejected reload");
+        assert!(captured.contains("after the rejected reload"));
+    });
+}
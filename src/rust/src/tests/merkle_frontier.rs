@@ -0,0 +1,333 @@
+use incrementalmerkletree::{bridgetree::Frontier, Altitude, Hashable, Position};
+use orchard::tree::MerkleHashOrchard;
+use zcash_primitives::{
+    merkle_tree::{CommitmentTree, HashSer},
+    sapling::Node,
+};
+
+use super::json_hex_strings;
+use crate::incremental_merkle_tree::{
+    orchard_empty_root, orchard_tree_to_frontier, sapling_empty_root, sapling_tree_to_frontier,
+    AnchorHistory, DeepFrontier,
+};
+
+const TREE_SIZES: &[usize] = &[0, 1, 2, 3, 4, 7, 8, 31, 100, 1025];
+
+/// The legacy trees serialized by zcashd's `SaplingTestingMerkleTree`, after each of the
+/// note commitments in `merkle_commitments_sapling.json` is appended.
+const ZCASHD_SAPLING_TREES: &str =
+    include_str!("../../../test/data/merkle_serialization_sapling.json");
+/// The roots of those depth-4 trees, as computed by zcashd.
+const ZCASHD_SAPLING_ROOTS: &str = include_str!("../../../test/data/merkle_roots_sapling.json");
+
+fn sapling_leaf(i: usize) -> Node {
+    let mut repr = [0u8; 32];
+    repr[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    Node::new(repr)
+}
+
+fn orchard_leaf(i: usize) -> MerkleHashOrchard {
+    let mut repr = [0u8; 32];
+    repr[..8].copy_from_slice(&(i as u64).to_le_bytes());
+    MerkleHashOrchard::from_bytes(&repr).unwrap()
+}
+
+#[test]
+fn sapling_empty_roots() {
+    // Uncommitted^Sapling, the little-endian encoding of 1.
+    let mut uncommitted = [0; 32];
+    uncommitted[0] = 1;
+    assert_eq!(sapling_empty_root(0), Some(uncommitted));
+    assert_eq!(
+        sapling_empty_root(16),
+        Some([
+            0x1e, 0xa6, 0x67, 0x5f, 0x95, 0x51, 0xee, 0xb9, 0xdf, 0xaa, 0xa9, 0x24, 0x7b, 0xc9,
+            0x85, 0x82, 0x70, 0xd3, 0xd3, 0xa4, 0xc5, 0xaf, 0xa7, 0x17, 0x7a, 0x98, 0x4d, 0x5e,
+            0xd1, 0xbe, 0x24, 0x51,
+        ])
+    );
+    assert_eq!(
+        sapling_empty_root(32),
+        Some([
+            0xfb, 0xc2, 0xf4, 0x30, 0x0c, 0x01, 0xf0, 0xb7, 0x82, 0x0d, 0x00, 0xe3, 0x34, 0x7c,
+            0x8d, 0xa4, 0xee, 0x61, 0x46, 0x74, 0x37, 0x6c, 0xbc, 0x45, 0x35, 0x9d, 0xaa, 0x54,
+            0xf9, 0xb5, 0x49, 0x3e,
+        ])
+    );
+    assert_eq!(sapling_empty_root(33), None);
+
+    // The root of the empty tree is the empty root of the full depth.
+    let mut empty_tree_root = [0; 32];
+    Frontier::<Node, 32>::empty()
+        .root()
+        .write(&mut empty_tree_root[..])
+        .unwrap();
+    assert_eq!(sapling_empty_root(32), Some(empty_tree_root));
+}
+
+#[test]
+fn orchard_empty_roots() {
+    // Uncommitted^Orchard, the little-endian encoding of 2.
+    let mut uncommitted = [0; 32];
+    uncommitted[0] = 2;
+    assert_eq!(orchard_empty_root(0), Some(uncommitted));
+    assert_eq!(
+        orchard_empty_root(32),
+        Some([
+            0xae, 0x29, 0x35, 0xf1, 0xdf, 0xd8, 0xa2, 0x4a, 0xed, 0x7c, 0x70, 0xdf, 0x7d, 0xe3,
+            0xa6, 0x68, 0xeb, 0x7a, 0x49, 0xb1, 0x31, 0x98, 0x80, 0xdd, 0xe2, 0xbb, 0xd9, 0x03,
+            0x1a, 0xe5, 0xd8, 0x2f,
+        ])
+    );
+    assert_eq!(orchard_empty_root(33), None);
+
+    // Each empty root is the combination of two empty roots of the depth below.
+    for depth in 1..=32u8 {
+        let child = MerkleHashOrchard::from_bytes(&orchard_empty_root(depth - 1).unwrap()).unwrap();
+        assert_eq!(
+            orchard_empty_root(depth),
+            Some(MerkleHashOrchard::combine(Altitude::from(depth - 1), &child, &child).to_bytes())
+        );
+    }
+}
+
+#[test]
+fn sapling_legacy_tree_to_frontier() {
+    for &size in TREE_SIZES {
+        let mut legacy = CommitmentTree::empty();
+        let mut expected = Frontier::<Node, 32>::empty();
+        for i in 0..size {
+            legacy.append(sapling_leaf(i)).unwrap();
+            assert!(expected.append(&sapling_leaf(i)));
+        }
+        let mut legacy_bytes = vec![];
+        legacy.write(&mut legacy_bytes).unwrap();
+
+        let frontier = sapling_tree_to_frontier(&legacy_bytes).unwrap();
+        assert_eq!(frontier.root(), legacy.root());
+        assert_eq!(frontier.root(), expected.root());
+        assert_eq!(frontier.position(), size.checked_sub(1).map(Position::from));
+    }
+}
+
+#[test]
+fn sapling_zcashd_tree_to_frontier() {
+    let trees = json_hex_strings(ZCASHD_SAPLING_TREES);
+    let roots = json_hex_strings(ZCASHD_SAPLING_ROOTS);
+    assert_eq!(trees.len(), 16);
+    assert_eq!(roots.len(), trees.len());
+
+    for (i, (legacy_bytes, root)) in trees.iter().zip(roots.iter()).enumerate() {
+        let frontier = sapling_tree_to_frontier(legacy_bytes).unwrap();
+        assert_eq!(frontier.position(), Some(Position::from(i)));
+
+        // zcashd's trees have depth 4, so the root of the same leaves in a depth-32 tree
+        // is its root combined with the empty subtrees above it.
+        let mut expected = Node::read(&root[..]).unwrap();
+        for depth in 4..32u8 {
+            let empty = Node::new(sapling_empty_root(depth).unwrap());
+            expected = Node::combine(Altitude::from(depth), &expected, &empty);
+        }
+        assert_eq!(frontier.root(), expected);
+
+        // The frontier is also the one the legacy tree itself reads as.
+        let legacy = CommitmentTree::<Node>::read(&legacy_bytes[..]).unwrap();
+        assert_eq!(frontier.root(), legacy.root());
+    }
+}
+
+#[test]
+fn orchard_legacy_tree_to_frontier() {
+    for &size in TREE_SIZES {
+        let mut legacy = CommitmentTree::empty();
+        let mut expected = Frontier::<MerkleHashOrchard, 32>::empty();
+        for i in 0..size {
+            legacy.append(orchard_leaf(i)).unwrap();
+            assert!(expected.append(&orchard_leaf(i)));
+        }
+        let mut legacy_bytes = vec![];
+        legacy.write(&mut legacy_bytes).unwrap();
+
+        let frontier = orchard_tree_to_frontier(&legacy_bytes).unwrap();
+        assert_eq!(frontier.root(), legacy.root());
+        assert_eq!(frontier.root(), expected.root());
+        assert_eq!(frontier.position(), size.checked_sub(1).map(Position::from));
+    }
+}
+
+#[test]
+fn invalid_legacy_trees() {
+    let node = [0u8; 32];
+
+    // A right leaf without a left leaf.
+    let mut right_only = vec![0x00, 0x01];
+    right_only.extend_from_slice(&node);
+    right_only.push(0x00);
+    assert!(sapling_tree_to_frontier(&right_only).is_err());
+
+    // Parents without any leaves.
+    let mut parents_only = vec![0x00, 0x00, 0x01, 0x01];
+    parents_only.extend_from_slice(&node);
+    assert!(sapling_tree_to_frontier(&parents_only).is_err());
+
+    // More parents than a depth-32 tree can have.
+    let mut too_deep = vec![0x01];
+    too_deep.extend_from_slice(&node);
+    too_deep.extend_from_slice(&[0x00, 32]);
+    too_deep.extend(std::iter::repeat(0x00).take(32));
+    assert!(sapling_tree_to_frontier(&too_deep).is_err());
+
+    // Truncated and over-long encodings of a valid tree.
+    let mut legacy = CommitmentTree::empty();
+    for i in 0..5 {
+        legacy.append(sapling_leaf(i)).unwrap();
+    }
+    let mut legacy_bytes = vec![];
+    legacy.write(&mut legacy_bytes).unwrap();
+    assert!(sapling_tree_to_frontier(&legacy_bytes[..legacy_bytes.len() - 1]).is_err());
+    legacy_bytes.push(0x00);
+    assert!(sapling_tree_to_frontier(&legacy_bytes).is_err());
+}
+
+#[test]
+fn anchor_history_window() {
+    let mut history = AnchorHistory::new(3);
+    let mut tree = Frontier::<Node, 32>::empty();
+    let mut roots = vec![];
+
+    // Connect blocks 1..=5, where block 3 adds no note commitments.
+    for height in 1..=5u32 {
+        if height != 3 {
+            assert!(tree.append(&sapling_leaf(height as usize)));
+        }
+        roots.push(tree.root());
+        assert!(history.push(height, tree.root()));
+    }
+    assert_eq!(history.len(), 3);
+    assert_eq!(history.tip_height(), Some(5));
+
+    // Blocks 1 and 2 have left the window, but the root of block 2 is shared by block 3.
+    assert_eq!(history.contains(&roots[0]), None);
+    assert_eq!(history.contains(&roots[1]), Some(3));
+    assert_eq!(history.contains(&roots[3]), Some(4));
+    assert_eq!(history.contains(&roots[4]), Some(5));
+
+    // Heights must increase.
+    assert!(!history.push(5, roots[4]));
+    assert!(!history.push(2, roots[1]));
+
+    // Disconnect blocks 4 and 5.
+    history.rewind_to(3);
+    assert_eq!(history.tip_height(), Some(3));
+    assert_eq!(history.contains(&roots[3]), None);
+    assert_eq!(history.contains(&roots[4]), None);
+    assert_eq!(history.contains(&roots[1]), Some(3));
+
+    // Disconnecting block 3 leaves nothing with its root inside the window.
+    history.rewind_to(2);
+    assert!(history.is_empty());
+    assert_eq!(history.contains(&roots[1]), None);
+
+    // Connect a replacement block 3.
+    assert!(history.push(3, roots[2]));
+    assert_eq!(history.contains(&roots[2]), Some(3));
+}
+
+#[test]
+fn anchor_history_serialization() {
+    let mut history = AnchorHistory::new(4);
+    for height in 10..16 {
+        assert!(history.push(height, sapling_leaf(height as usize)));
+    }
+
+    let mut bytes = vec![];
+    history.write(&mut bytes).unwrap();
+    let parsed = AnchorHistory::<Node>::read(&bytes[..]).unwrap();
+    assert_eq!(parsed, history);
+
+    // More roots than the history retains.
+    let mut over_full = AnchorHistory::new(5);
+    for height in 10..15 {
+        assert!(over_full.push(height, sapling_leaf(height as usize)));
+    }
+    let mut bytes = vec![];
+    over_full.write(&mut bytes).unwrap();
+    bytes[0] = 4;
+    assert!(AnchorHistory::<Node>::read(&bytes[..]).is_err());
+}
+
+/// The note commitments of a block on one of two forks.
+fn block_leaves(height: u32, fork: usize) -> Vec<Node> {
+    (0..(height as usize % 3 + 1))
+        .map(|i| sapling_leaf(fork * 1_000_000 + height as usize * 100 + i))
+        .collect()
+}
+
+fn frontier_for_chain(chain: &[(u32, usize)]) -> Frontier<Node, 32> {
+    let mut frontier = Frontier::empty();
+    for (height, fork) in chain {
+        for leaf in block_leaves(*height, *fork) {
+            assert!(frontier.append(&leaf));
+        }
+    }
+    frontier
+}
+
+#[test]
+fn deep_frontier_reorg() {
+    let mut deep = DeepFrontier::new(Frontier::<Node, 32>::empty(), 10);
+
+    // Connect blocks A..F at heights 1..=6.
+    for height in 1..=6 {
+        for leaf in block_leaves(height, 0) {
+            assert!(deep.append(&leaf));
+        }
+        assert!(deep.checkpoint(height));
+    }
+    assert!(!deep.checkpoint(6));
+    let chain: Vec<_> = (1..=6).map(|h| (h, 0)).collect();
+    assert_eq!(deep.root(), frontier_for_chain(&chain).root());
+
+    // Disconnect D..F, and connect alternative blocks at heights 4 and 5.
+    assert!(deep.rewind(3));
+    assert_eq!(deep.last_checkpoint_height(), Some(3));
+    assert_eq!(deep.root(), frontier_for_chain(&chain[..3]).root());
+    for height in 4..=5 {
+        for leaf in block_leaves(height, 1) {
+            assert!(deep.append(&leaf));
+        }
+        assert!(deep.checkpoint(height));
+    }
+    let new_chain = [(1, 0), (2, 0), (3, 0), (4, 1), (5, 1)];
+    assert_eq!(deep.frontier(), &frontier_for_chain(&new_chain));
+
+    // Checkpoints beyond the retention depth can't be rewound to.
+    deep.prune_checkpoints(2);
+    assert!(!deep.rewind(3));
+    assert!(deep.rewind(4));
+    assert_eq!(deep.frontier(), &frontier_for_chain(&new_chain[..4]));
+}
+
+#[test]
+fn deep_frontier_serialization() {
+    let mut deep = DeepFrontier::new(Frontier::<Node, 32>::empty(), 3);
+    for height in 1..=5 {
+        for leaf in block_leaves(height, 0) {
+            assert!(deep.append(&leaf));
+        }
+        assert!(deep.checkpoint(height));
+    }
+    // Uncheckpointed changes are also serialized.
+    assert!(deep.append(&sapling_leaf(42)));
+
+    let mut bytes = vec![];
+    deep.write(&mut bytes).unwrap();
+    let mut parsed = DeepFrontier::<Node>::read(&bytes[..]).unwrap();
+    assert_eq!(parsed, deep);
+
+    // A restart in the middle of a reorg can still rewind.
+    assert!(!parsed.rewind(2));
+    assert!(parsed.rewind(3));
+    let chain: Vec<_> = (1..=3).map(|h| (h, 0)).collect();
+    assert_eq!(parsed.frontier(), &frontier_for_chain(&chain));
+}
@@ -1,6 +1,39 @@
-use zcash_history::{Entry, EntryLink, NodeData, V1};
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
 
-use crate::history_ffi::{librustzcash_mmr_append, librustzcash_mmr_delete};
+use zcash_history::{Entry, EntryLink, NodeData, Tree, Version, V1, V2};
+use zcash_primitives::consensus::BranchId;
+
+use crate::history::{
+    append_to_peaks, decode_entry_data, leaf_path, multiproof_nodes, node_altitude, peaks,
+    HistoryTree,
+};
+
+use crate::history_ffi::{
+    librustzcash_branch_name, librustzcash_history_crate_versions, librustzcash_mmr_append,
+    librustzcash_mmr_append_frontier, librustzcash_mmr_append_sizes,
+    librustzcash_mmr_append_verbose, librustzcash_mmr_append_write_plan,
+    librustzcash_mmr_balanced_root, librustzcash_mmr_build_roots, librustzcash_mmr_can_delete,
+    librustzcash_mmr_canonicalize, librustzcash_mmr_chain_verify_free,
+    librustzcash_mmr_chain_verify_init, librustzcash_mmr_chain_verify_step,
+    librustzcash_mmr_check_leaf_height, librustzcash_mmr_combine_rule_id,
+    librustzcash_mmr_deep_validate, librustzcash_mmr_delete, librustzcash_mmr_diff_mask,
+    librustzcash_mmr_empty_root, librustzcash_mmr_first_leaf_divergence, librustzcash_mmr_frontier,
+    librustzcash_mmr_hash_block_leaf, librustzcash_mmr_hash_node,
+    librustzcash_mmr_hash_personalization, librustzcash_mmr_height_to_leaf,
+    librustzcash_mmr_history_enabled, librustzcash_mmr_is_extension, librustzcash_mmr_leaf_count,
+    librustzcash_mmr_leaf_path_indices, librustzcash_mmr_leaf_to_height,
+    librustzcash_mmr_multiproof, librustzcash_mmr_node_is_canonical, librustzcash_mmr_ooo_add,
+    librustzcash_mmr_ooo_contiguous_root, librustzcash_mmr_ooo_free, librustzcash_mmr_ooo_new,
+    librustzcash_mmr_peaks_commitment, librustzcash_mmr_root_both_versions,
+    librustzcash_mmr_root_from_leaves, librustzcash_mmr_root_with_replaced_leaf,
+    librustzcash_mmr_self_check, librustzcash_mmr_sibling_index, librustzcash_mmr_stats,
+    librustzcash_mmr_storage_bytes, librustzcash_mmr_total_nodes, librustzcash_mmr_tree_append,
+    librustzcash_mmr_tree_delete, librustzcash_mmr_tree_free, librustzcash_mmr_tree_memory_bytes,
+    librustzcash_mmr_tree_new, librustzcash_mmr_tree_root, librustzcash_mmr_verify_append_output,
+    librustzcash_mmr_verify_leaf_lazy, librustzcash_mmr_verify_multiproof,
+    librustzcash_mmr_verify_proof_by_hash, MmrAppendReport, MmrStats,
+};
 
 const NODE_DATA_16L: &[u8] = include_bytes!("./res/tree16.dat");
 const NODE_DATA_1023L: &[u8] = include_bytes!("./res/tree1023.dat");
@@ -176,6 +209,7 @@ fn append() {
         nodes.len() as u32,
         indices.as_ptr(),
         peaks.as_ptr(),
+        std::ptr::null(),
         peaks.len(),
         &new_node_data,
         &mut rt_ret,
@@ -204,22 +238,2890 @@ fn append() {
 }
 
 #[test]
-fn delete() {
+fn append_every_tree_size() {
+    // Appending the next leaf of a stored tree must reproduce the stored nodes, whether
+    // the tree has a single peak or many.
     let nodes = load_nodes(NODE_DATA_1023L);
-    let (indices, nodes, peak_count) = preload_tree_delete(&nodes);
 
-    let mut rt_ret = [0u8; 32];
+    for leaves in 1.. {
+        let t_len = librustzcash_mmr_total_nodes(leaves);
+        let next_len = librustzcash_mmr_total_nodes(leaves + 1);
+        if next_len as usize > nodes.len() {
+            break;
+        }
+        let (indices, peaks) = preload_tree_append(&nodes[..t_len as usize]);
 
-    let result = librustzcash_mmr_delete(
+        let mut rt_ret = [0u8; 32];
+        let mut buf_ret = vec![[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+        let mut new_node_data = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        nodes[t_len as usize]
+            .write(&mut &mut new_node_data[..])
+            .expect("Failed to write node data");
+
+        let result = librustzcash_mmr_append(
+            0,
+            t_len,
+            indices.as_ptr(),
+            peaks.as_ptr(),
+            std::ptr::null(),
+            peaks.len(),
+            &new_node_data,
+            &mut rt_ret,
+            buf_ret.as_mut_ptr(),
+        );
+        buf_ret.truncate(result as usize);
+
+        assert_eq!(result, next_len - t_len, "{} leaves", leaves);
+        let (mut exact, mut max) = (0, 0);
+        assert_eq!(
+            librustzcash_mmr_append_sizes(t_len, &mut exact, &mut max),
+            0
+        );
+        assert_eq!(result, exact, "{} leaves", leaves);
+        for (appended, expected) in buf_ret.iter().zip(&nodes[t_len as usize..]) {
+            let mut encoded = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            expected
+                .write(&mut &mut encoded[..])
+                .expect("Failed to write node data");
+            assert_eq!(&appended[..], &encoded[..], "{} leaves", leaves);
+        }
+
+        let tree_view = prepare_tree(&nodes[..next_len as usize]);
+        let tree = Tree::<V1>::new(next_len, tree_view.peaks, vec![]);
+        assert_eq!(rt_ret, V1::hash(tree.root_node().unwrap().data()));
+    }
+}
+
+#[test]
+fn build_roots() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..40)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+
+    let mut roots = vec![[0u8; 32]; leaves.len()];
+    assert_eq!(
+        librustzcash_mmr_build_roots(
+            0,
+            leaves.as_ptr(),
+            leaves.len(),
+            roots.as_mut_ptr(),
+            roots.len(),
+        ),
+        leaves.len() as u32
+    );
+
+    // Each root must match appending that leaf to the stored tree on its own.
+    assert_eq!(roots[0], V1::hash(&nodes[0]));
+    for (i, (leaf, root)) in leaves.iter().zip(&roots).enumerate().skip(1) {
+        let t_len = librustzcash_mmr_total_nodes(i as u32);
+        let (indices, peaks) = preload_tree_append(&nodes[..t_len as usize]);
+        let mut rt_ret = [0u8; 32];
+        let mut buf_ret = vec![[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+        assert_ne!(
+            librustzcash_mmr_append(
+                0,
+                t_len,
+                indices.as_ptr(),
+                peaks.as_ptr(),
+                std::ptr::null(),
+                peaks.len(),
+                leaf,
+                &mut rt_ret,
+                buf_ret.as_mut_ptr(),
+            ),
+            0
+        );
+        assert_eq!(root, &rt_ret, "{} leaves", i + 1);
+    }
+
+    // Every root must fit.
+    assert_eq!(
+        librustzcash_mmr_build_roots(
+            0,
+            leaves.as_ptr(),
+            leaves.len(),
+            roots.as_mut_ptr(),
+            roots.len() - 1,
+        ),
+        0
+    );
+}
+
+#[test]
+fn chain_verify() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..512)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+    let mut roots = vec![[0u8; 32]; leaves.len()];
+    librustzcash_mmr_build_roots(
+        0,
+        leaves.as_ptr(),
+        leaves.len(),
+        roots.as_mut_ptr(),
+        roots.len(),
+    );
+
+    // A wrong root is caught at its step, and the leaf is still appended so that the
+    // rest of the chain verifies.
+    let mut wrong_root = roots[300];
+    wrong_root[0] ^= 1;
+    let state = librustzcash_mmr_chain_verify_init(0);
+    assert!(!state.is_null());
+    for (i, (leaf, root)) in leaves.iter().zip(&roots).enumerate() {
+        let (expected_root, result) = if i == 300 {
+            (&wrong_root, 1)
+        } else {
+            (root, 0)
+        };
+        assert_eq!(
+            librustzcash_mmr_chain_verify_step(state, leaf, expected_root),
+            result,
+            "step {}",
+            i
+        );
+    }
+
+    // A step without a leaf leaves the state as it was.
+    assert_eq!(
+        librustzcash_mmr_chain_verify_step(state, std::ptr::null(), &roots[0]),
+        2
+    );
+    let mut tree = HistoryTree::<V1>::from_leaves(0, &leaves).unwrap();
+    let root = tree.append(&leaves[0]).unwrap().new_root;
+    assert_eq!(
+        librustzcash_mmr_chain_verify_step(state, &leaves[0], &root),
+        0
+    );
+    librustzcash_mmr_chain_verify_free(state);
+
+    assert!(librustzcash_mmr_chain_verify_init(0x1234_5678).is_null());
+}
+
+#[test]
+fn tree_handle_caches_root() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..3)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+    let root_of = |count| {
+        HistoryTree::<V1>::from_leaves(0, &leaves[..count])
+            .unwrap()
+            .root()
+            .unwrap()
+    };
+
+    let handle = librustzcash_mmr_tree_new(0);
+    assert!(!handle.is_null());
+    let mut root = [0u8; 32];
+    // An empty tree has no root.
+    assert_eq!(librustzcash_mmr_tree_root(handle, &mut root), 2);
+    assert_eq!(librustzcash_mmr_tree_delete(handle), 2);
+
+    assert_eq!(librustzcash_mmr_tree_append(handle, &leaves[0]), 0);
+    assert_eq!(librustzcash_mmr_tree_append(handle, &leaves[1]), 0);
+    assert_eq!(librustzcash_mmr_tree_root(handle, &mut root), 0);
+    let mut again = [0u8; 32];
+    assert_eq!(librustzcash_mmr_tree_root(handle, &mut again), 0);
+    assert_eq!(root, again);
+    assert_eq!(root, root_of(2));
+
+    // Mutations invalidate the cached root.
+    assert_eq!(librustzcash_mmr_tree_append(handle, &leaves[2]), 0);
+    assert_eq!(librustzcash_mmr_tree_root(handle, &mut again), 0);
+    assert_ne!(root, again);
+    assert_eq!(again, root_of(3));
+    assert_eq!(librustzcash_mmr_tree_delete(handle), 0);
+    assert_eq!(librustzcash_mmr_tree_root(handle, &mut again), 0);
+    assert_eq!(root, again);
+
+    // A failed append leaves the tree and its cached root as they were.
+    assert_eq!(
+        librustzcash_mmr_tree_append(handle, &[0xff; zcash_history::MAX_NODE_DATA_SIZE]),
+        1
+    );
+    assert_eq!(librustzcash_mmr_tree_append(handle, std::ptr::null()), 1);
+    assert_eq!(librustzcash_mmr_tree_root(handle, &mut again), 0);
+    assert_eq!(root, again);
+    assert_eq!(librustzcash_mmr_tree_root(handle, std::ptr::null_mut()), 1);
+    librustzcash_mmr_tree_free(handle);
+
+    assert!(librustzcash_mmr_tree_new(0x1234_5678).is_null());
+}
+
+#[test]
+fn tree_handle_memory_bytes() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..64)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+    let handle_with = |count| {
+        let handle = librustzcash_mmr_tree_new(0);
+        for leaf in &leaves[..count] {
+            assert_eq!(librustzcash_mmr_tree_append(handle, leaf), 0);
+        }
+        handle
+    };
+
+    let empty = handle_with(0);
+    let small = handle_with(4);
+    let large = handle_with(64);
+    let empty_bytes = librustzcash_mmr_tree_memory_bytes(empty);
+    let small_bytes = librustzcash_mmr_tree_memory_bytes(small);
+    let large_bytes = librustzcash_mmr_tree_memory_bytes(large);
+    assert!(empty_bytes > 0);
+    assert!(small_bytes > empty_bytes);
+    assert!(large_bytes > small_bytes);
+
+    // Each node holds at least its encoding, and the handle keeps all 127 nodes.
+    let node_len = V1::to_bytes(&nodes[0]).len();
+    assert!(large_bytes - empty_bytes >= 127 * node_len);
+
+    // Querying the root caches it inline, so the footprint doesn't change.
+    let mut root = [0u8; 32];
+    assert_eq!(librustzcash_mmr_tree_root(large, &mut root), 0);
+    assert_eq!(librustzcash_mmr_tree_memory_bytes(large), large_bytes);
+
+    // Deleting leaves shrinks it again.
+    for _ in 0..60 {
+        assert_eq!(librustzcash_mmr_tree_delete(large), 0);
+    }
+    assert_eq!(librustzcash_mmr_tree_memory_bytes(large), small_bytes);
+
+    for handle in [empty, small, large] {
+        librustzcash_mmr_tree_free(handle);
+    }
+    assert_eq!(librustzcash_mmr_tree_memory_bytes(std::ptr::null()), 0);
+}
+
+#[test]
+fn out_of_order_leaves() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..10)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+    let root_of = |count| {
+        HistoryTree::<V1>::from_leaves(0, &leaves[..count])
+            .unwrap()
+            .root()
+            .unwrap()
+    };
+
+    let state = librustzcash_mmr_ooo_new(0);
+    assert!(!state.is_null());
+    let contiguous_root = || {
+        let mut root = [0u8; 32];
+        let mut len = u32::MAX;
+        let result = librustzcash_mmr_ooo_contiguous_root(state, &mut root, &mut len);
+        (result, len, root)
+    };
+    assert_eq!(contiguous_root(), (3, 0, [0; 32]));
+
+    // Nothing is contiguous until leaf 0 arrives.
+    for index in [3, 1, 5] {
+        assert_eq!(
+            librustzcash_mmr_ooo_add(state, index, &leaves[index as usize]),
+            0
+        );
+        assert_eq!(contiguous_root(), (3, 0, [0; 32]));
+    }
+
+    // Each leaf that fills the first gap advances the prefix past the buffered leaves
+    // that follow it.
+    for (index, contiguous) in [(0, 2), (2, 4), (4, 6), (9, 6), (7, 6), (6, 8), (8, 10)] {
+        assert_eq!(
+            librustzcash_mmr_ooo_add(state, index, &leaves[index as usize]),
+            0
+        );
+        assert_eq!(
+            contiguous_root(),
+            (0, contiguous, root_of(contiguous as usize))
+        );
+    }
+
+    // Leaves can't be added twice, whether or not they have been appended.
+    assert_eq!(librustzcash_mmr_ooo_add(state, 4, &leaves[4]), 1);
+    assert_eq!(librustzcash_mmr_ooo_add(state, 12, &leaves[0]), 0);
+    assert_eq!(librustzcash_mmr_ooo_add(state, 12, &leaves[1]), 1);
+    assert_eq!(contiguous_root(), (0, 10, root_of(10)));
+
+    assert_eq!(
+        librustzcash_mmr_ooo_add(state, 10, &[0xff; zcash_history::MAX_NODE_DATA_SIZE]),
+        2
+    );
+    assert_eq!(librustzcash_mmr_ooo_add(state, 10, std::ptr::null()), 2);
+    assert_eq!(librustzcash_mmr_ooo_add(state, u32::MAX, &leaves[0]), 2);
+    assert_eq!(contiguous_root(), (0, 10, root_of(10)));
+    assert_eq!(
+        librustzcash_mmr_ooo_contiguous_root(state, std::ptr::null_mut(), &mut 0),
+        2
+    );
+    librustzcash_mmr_ooo_free(state);
+
+    assert!(librustzcash_mmr_ooo_new(0x1234_5678).is_null());
+}
+
+#[test]
+fn root_from_leaves() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..512)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+
+    // The leaves make up a single perfect tree, so the root is the hash of its peak.
+    let mut root = [0u8; 32];
+    assert_eq!(
+        librustzcash_mmr_root_from_leaves(0, leaves.as_ptr(), leaves.len(), &mut root),
+        0
+    );
+    assert_eq!(root, V1::hash(&nodes[1022]));
+    let tree = HistoryTree::<V1>::from_leaves(0, &leaves).unwrap();
+    for (index, node) in nodes.iter().enumerate() {
+        assert_eq!(tree.node(index as u32), Some(&V1::to_bytes(node)[..]));
+    }
+
+    assert_eq!(
+        librustzcash_mmr_root_from_leaves(0, leaves.as_ptr(), 0, &mut root),
+        1
+    );
+    assert_eq!(
+        librustzcash_mmr_root_from_leaves(0, leaves.as_ptr(), 1, std::ptr::null_mut()),
+        1
+    );
+}
+
+#[test]
+fn diff_mask() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaf = |i| {
+        let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        nodes[librustzcash_mmr_total_nodes(i) as usize]
+            .write(&mut &mut leaf[..])
+            .expect("Failed to write node data");
+        leaf
+    };
+    let a: Vec<_> = (0..7).map(leaf).collect();
+    let diff = |b: &[[u8; zcash_history::MAX_NODE_DATA_SIZE]], cap| {
+        let mut mask = vec![0xff; cap];
+        let mut len = 0;
+        let result = librustzcash_mmr_diff_mask(
+            0,
+            a.as_ptr(),
+            a.len(),
+            b.as_ptr(),
+            b.len(),
+            mask.as_mut_ptr(),
+            cap,
+            &mut len,
+        );
+        (result, mask, len)
+    };
+
+    assert_eq!(diff(&a, 2), (0, vec![0, 0], 2));
+
+    // Replacing the sixth leaf (at index 8) changes it and its parent (at index 9),
+    // which is a peak.
+    let mut b = a.clone();
+    b[5] = leaf(20);
+    assert_eq!(diff(&b, 2), (0, vec![0, 0b11], 2));
+
+    // Appending a leaf adds it (at index 11) and its three new ancestors.
+    let mut b = a.clone();
+    b.push(leaf(7));
+    assert_eq!(diff(&b, 2), (0, vec![0, 0b0111_1000], 2));
+    assert_eq!(diff(&b, 1), (2, vec![0xff], 2));
+
+    let mut len = 0;
+    assert_eq!(
+        librustzcash_mmr_diff_mask(
+            0,
+            a.as_ptr(),
+            a.len(),
+            a.as_ptr(),
+            0,
+            std::ptr::null_mut(),
+            0,
+            &mut len,
+        ),
+        1
+    );
+}
+
+#[test]
+fn first_leaf_divergence() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaf = |i| {
+        let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        nodes[librustzcash_mmr_total_nodes(i) as usize]
+            .write(&mut &mut leaf[..])
+            .expect("Failed to write node data");
+        leaf
+    };
+    let a: Vec<_> = (0..30).map(leaf).collect();
+    let divergence = |a: &[[u8; zcash_history::MAX_NODE_DATA_SIZE]],
+                      b: &[[u8; zcash_history::MAX_NODE_DATA_SIZE]]| {
+        let mut index = usize::MAX;
+        let result = librustzcash_mmr_first_leaf_divergence(
+            a.as_ptr(),
+            a.len(),
+            b.as_ptr(),
+            b.len(),
+            &mut index,
+        );
+        (result, index)
+    };
+
+    // A chain that reorgs away the last 13 blocks of the other and replaces them with
+    // 15 blocks of its own.
+    let mut b = a[..17].to_vec();
+    b.extend((100..115).map(leaf));
+    assert_eq!(divergence(&a, &b), (0, 17));
+    assert_eq!(divergence(&b, &a), (0, 17));
+
+    // The leaves differ from the first.
+    assert_eq!(divergence(&a[..5], &b[17..]), (0, 0));
+
+    // A chain that extends the other diverges from it nowhere.
+    assert_eq!(divergence(&a, &a[..17]), (1, 17));
+    assert_eq!(divergence(&a[..17], &a), (1, 17));
+    assert_eq!(divergence(&a, &a), (1, 30));
+    assert_eq!(divergence(&a, &[]), (1, 0));
+    assert_eq!(
+        librustzcash_mmr_first_leaf_divergence(std::ptr::null(), 0, std::ptr::null(), 0, &mut 7),
+        1
+    );
+
+    let mut index = 0;
+    assert_eq!(
+        librustzcash_mmr_first_leaf_divergence(std::ptr::null(), 1, a.as_ptr(), 1, &mut index),
+        2
+    );
+    assert_eq!(
+        librustzcash_mmr_first_leaf_divergence(a.as_ptr(), 1, a.as_ptr(), 1, std::ptr::null_mut()),
+        2
+    );
+}
+
+#[test]
+fn is_extension() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let leaves: Vec<_> = (0..40)
+        .map(|i| {
+            let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            nodes[librustzcash_mmr_total_nodes(i) as usize]
+                .write(&mut &mut leaf[..])
+                .expect("Failed to write node data");
+            leaf
+        })
+        .collect();
+    let new_root = HistoryTree::<V1>::from_leaves(0, &leaves)
+        .unwrap()
+        .root()
+        .unwrap();
+
+    // A tree of 7 leaves, extended to 40.
+    let t_len = librustzcash_mmr_total_nodes(7);
+    let (indices, peaks) = preload_tree_append(&nodes[..t_len as usize]);
+    let check = |indices: &[u32],
+                 peaks: &[[u8; zcash_history::MAX_ENTRY_SIZE]],
+                 leaves: &[[u8; zcash_history::MAX_NODE_DATA_SIZE]],
+                 root: &[u8; 32]| {
+        librustzcash_mmr_is_extension(
+            0,
+            t_len,
+            indices.as_ptr(),
+            peaks.as_ptr(),
+            indices.len(),
+            leaves.as_ptr(),
+            leaves.len(),
+            root,
+        )
+    };
+    assert_eq!(check(&indices, &peaks, &leaves[7..], &new_root), 1);
+
+    // Tampering with the claimed root, an appended leaf, or an old peak breaks the
+    // extension.
+    let mut tampered_root = new_root;
+    tampered_root[0] ^= 1;
+    assert_eq!(check(&indices, &peaks, &leaves[7..], &tampered_root), 0);
+    let mut tampered_leaves = leaves[7..].to_vec();
+    tampered_leaves[20][5] ^= 1;
+    assert_eq!(check(&indices, &peaks, &tampered_leaves, &new_root), 0);
+    let mut tampered_peaks = peaks.clone();
+    // Skip the entry header to reach the subtree commitment.
+    tampered_peaks[0][9] ^= 1;
+    assert_eq!(check(&indices, &tampered_peaks, &leaves[7..], &new_root), 0);
+
+    // With no leaves, the old tree extends itself.
+    let old_root = HistoryTree::<V1>::from_leaves(0, &leaves[..7])
+        .unwrap()
+        .root()
+        .unwrap();
+    assert_eq!(check(&indices, &peaks, &[], &old_root), 1);
+
+    // An empty tree is extended by any leaves that yield the root.
+    assert_eq!(
+        librustzcash_mmr_is_extension(
+            0,
+            0,
+            std::ptr::null(),
+            std::ptr::null(),
+            0,
+            leaves.as_ptr(),
+            leaves.len(),
+            &new_root,
+        ),
+        1
+    );
+
+    // The old nodes must be exactly the peaks of the tree, in order.
+    assert_eq!(
+        check(&indices[..2], &peaks[..2], &leaves[7..], &new_root),
+        2
+    );
+    assert_eq!(check(&[6, 10, 9], &peaks, &leaves[7..], &new_root), 2);
+    assert_eq!(
+        librustzcash_mmr_is_extension(
+            0,
+            t_len,
+            indices.as_ptr(),
+            peaks.as_ptr(),
+            indices.len(),
+            leaves.as_ptr(),
+            leaves.len(),
+            std::ptr::null(),
+        ),
+        2
+    );
+}
+
+thread_local! {
+    /// The nodes that `fetch_node` serves, and how many it has served.
+    static NODE_STORE: RefCell<BTreeMap<u32, [u8; zcash_history::MAX_NODE_DATA_SIZE]>> =
+        RefCell::new(BTreeMap::new());
+    static NODE_FETCHES: Cell<usize> = Cell::new(0);
+}
+
+unsafe extern "C" fn fetch_node(
+    index: u32,
+    out: *mut [u8; zcash_history::MAX_NODE_DATA_SIZE],
+) -> u32 {
+    NODE_FETCHES.with(|fetches| fetches.set(fetches.get() + 1));
+    NODE_STORE.with(|store| match store.borrow().get(&index) {
+        Some(node) => {
+            *out = *node;
+            0
+        }
+        None => 1,
+    })
+}
+
+#[test]
+fn verify_leaf_lazy() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = librustzcash_mmr_total_nodes(37);
+    let encoded: BTreeMap<_, _> = nodes[..t_len as usize]
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let mut encoded = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            node.write(&mut &mut encoded[..])
+                .expect("Failed to write node data");
+            (index as u32, encoded)
+        })
+        .collect();
+    let root = HistoryTree::<V1>::from_nodes(
+        0,
+        t_len,
+        encoded.iter().map(|(index, node)| (*index, node.to_vec())),
+    )
+    .unwrap()
+    .root()
+    .unwrap();
+    NODE_STORE.with(|store| *store.borrow_mut() = encoded);
+    let fetches = || NODE_FETCHES.with(|fetches| fetches.replace(0));
+
+    let peak_count = peaks(t_len).unwrap().len();
+    for leaf_index in (0..t_len).filter(|&index| node_altitude(index) == 0) {
+        assert_eq!(
+            librustzcash_mmr_verify_leaf_lazy(0, t_len, leaf_index, &root, Some(fetch_node)),
+            1,
+            "leaf {}",
+            leaf_index
+        );
+        // Only the leaf, its path and the other peaks are fetched.
+        let (_, path) = leaf_path(t_len, leaf_index).unwrap();
+        assert_eq!(fetches(), 1 + path.len() + peak_count - 1);
+    }
+
+    let mut wrong_root = root;
+    wrong_root[0] ^= 1;
+    assert_eq!(
+        librustzcash_mmr_verify_leaf_lazy(0, t_len, 0, &wrong_root, Some(fetch_node)),
+        0
+    );
+    // Not a leaf.
+    assert_eq!(
+        librustzcash_mmr_verify_leaf_lazy(0, t_len, 2, &root, Some(fetch_node)),
+        2
+    );
+    assert_eq!(
+        librustzcash_mmr_verify_leaf_lazy(0, t_len, 0, &root, None),
+        2
+    );
+
+    // A node that the store can't provide stops the verification.
+    NODE_STORE.with(|store| store.borrow_mut().remove(&1));
+    assert_eq!(
+        librustzcash_mmr_verify_leaf_lazy(0, t_len, 0, &root, Some(fetch_node)),
+        3
+    );
+}
+
+#[test]
+fn multiproof() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = librustzcash_mmr_total_nodes(37);
+    let (indices, encoded): (Vec<_>, Vec<_>) = nodes[..t_len as usize]
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let mut encoded = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            node.write(&mut &mut encoded[..])
+                .expect("Failed to write node data");
+            (index as u32, encoded)
+        })
+        .unzip();
+    let root = HistoryTree::<V1>::from_nodes(
+        0,
+        t_len,
+        indices
+            .iter()
+            .zip(&encoded)
+            .map(|(index, node)| (*index, node.to_vec())),
+    )
+    .unwrap()
+    .root()
+    .unwrap();
+
+    // The first eight leaves fill a subtree, and the last leaf is a peak.
+    let mut leaf_indices = (0..t_len)
+        .filter(|&index| node_altitude(index) == 0)
+        .collect::<Vec<_>>();
+    leaf_indices.drain(8..leaf_indices.len() - 1);
+    let multiproof = |nodes: &[u32], leaf_indices: &[u32], proof: &mut [u8], len: &mut usize| {
+        let encoded = nodes
+            .iter()
+            .map(|&index| encoded[index as usize])
+            .collect::<Vec<_>>();
+        librustzcash_mmr_multiproof(
+            0,
+            t_len,
+            nodes.as_ptr(),
+            encoded.as_ptr(),
+            nodes.len(),
+            leaf_indices.as_ptr(),
+            leaf_indices.len(),
+            proof.as_mut_ptr(),
+            proof.len(),
+            len,
+        )
+    };
+    let verify = |leaf_indices: &[u32], proof: &[u8], root: &[u8; 32]| {
+        librustzcash_mmr_verify_multiproof(
+            0,
+            t_len,
+            leaf_indices.as_ptr(),
+            leaf_indices.len(),
+            proof.as_ptr(),
+            proof.len(),
+            root,
+        )
+    };
+
+    let mut len = 0;
+    assert_eq!(multiproof(&indices, &leaf_indices, &mut [], &mut len), 2);
+    let mut proof = vec![0; len];
+    assert_eq!(multiproof(&indices, &leaf_indices, &mut proof, &mut len), 0);
+    assert_eq!(len, proof.len());
+    assert_eq!(verify(&leaf_indices, &proof, &root), 1);
+
+    // Separate proofs would each hold a leaf, its path and the other peaks.
+    let peak_count = peaks(t_len).unwrap().len();
+    let separate_nodes = leaf_indices
+        .iter()
+        .map(|&index| 1 + leaf_path(t_len, index).unwrap().1.len() + peak_count - 1)
+        .sum::<usize>();
+    let node_len = 1 + V1::to_bytes(&nodes[0]).len();
+    assert!(proof.len() < separate_nodes * node_len / 4);
+
+    // Only the leaves and the witnesses are needed to build the proof.
+    let (_, witnesses) = multiproof_nodes(t_len, &leaf_indices).unwrap();
+    let mut needed = leaf_indices
+        .iter()
+        .chain(&witnesses)
+        .copied()
+        .collect::<Vec<_>>();
+    let mut needed_proof = vec![0; len];
+    assert_eq!(
+        multiproof(&needed, &leaf_indices, &mut needed_proof, &mut len),
+        0
+    );
+    assert_eq!(needed_proof, proof);
+    needed.pop();
+    assert_eq!(
+        multiproof(&needed, &leaf_indices, &mut needed_proof, &mut len),
+        1
+    );
+
+    let mut wrong_root = root;
+    wrong_root[0] ^= 1;
+    assert_eq!(verify(&leaf_indices, &proof, &wrong_root), 0);
+    let mut tampered = proof.clone();
+    *tampered.last_mut().unwrap() ^= 1;
+    assert_eq!(verify(&leaf_indices, &tampered, &root), 0);
+    // The proof must be for exactly the given leaves.
+    assert_ne!(verify(&leaf_indices[1..], &proof, &root), 1);
+    assert_eq!(verify(&leaf_indices, &proof[..proof.len() - 1], &root), 2);
+
+    // The leaf indices must be strictly ascending leaves of the tree.
+    for leaf_indices in [&[1, 0][..], &[0, 0], &[2], &[t_len], &[]] {
+        assert_eq!(multiproof(&indices, leaf_indices, &mut proof, &mut len), 1);
+        assert_eq!(verify(leaf_indices, &proof, &root), 2);
+    }
+}
+
+#[test]
+fn leaf_path_indices() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = librustzcash_mmr_total_nodes(37);
+    let encoded: Vec<_> = nodes[..t_len as usize]
+        .iter()
+        .map(|node| {
+            let mut encoded = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            node.write(&mut &mut encoded[..])
+                .expect("Failed to write node data");
+            encoded
+        })
+        .collect();
+    let peak_count = peaks(t_len).unwrap().len();
+
+    for leaf_index in (0..t_len).filter(|&index| node_altitude(index) == 0) {
+        let mut len = 0;
+        assert_eq!(
+            librustzcash_mmr_leaf_path_indices(
+                t_len,
+                leaf_index,
+                std::ptr::null_mut(),
+                0,
+                &mut len
+            ),
+            2
+        );
+        let mut indices = vec![0; len];
+        assert_eq!(
+            librustzcash_mmr_leaf_path_indices(
+                t_len,
+                leaf_index,
+                indices.as_mut_ptr(),
+                indices.len(),
+                &mut len
+            ),
+            0
+        );
+        assert!(indices.windows(2).all(|w| w[0] < w[1]));
+        assert!(indices.contains(&leaf_index));
+        // The leaf, and an ancestor and a sibling per level, plus the other peaks.
+        let path_len = leaf_path(t_len, leaf_index).unwrap().1.len();
+        assert_eq!(len, 1 + 2 * path_len + peak_count - 1);
+
+        // The indexed nodes are enough to prove the leaf.
+        let supplied: Vec<_> = indices.iter().map(|&i| encoded[i as usize]).collect();
+        let mut proof = vec![0; 4096];
+        assert_eq!(
+            librustzcash_mmr_multiproof(
+                0,
+                t_len,
+                indices.as_ptr(),
+                supplied.as_ptr(),
+                indices.len(),
+                &leaf_index,
+                1,
+                proof.as_mut_ptr(),
+                proof.len(),
+                &mut len,
+            ),
+            0
+        );
+    }
+
+    // Only leaves of the tree have paths.
+    let mut len = 0;
+    for (t_len, leaf_index) in [(t_len, 2), (t_len, t_len), (2, 0), (0, 0)] {
+        assert_eq!(
+            librustzcash_mmr_leaf_path_indices(
+                t_len,
+                leaf_index,
+                std::ptr::null_mut(),
+                0,
+                &mut len
+            ),
+            1
+        );
+    }
+}
+
+#[test]
+fn verify_proof_by_hash() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = librustzcash_mmr_total_nodes(37);
+    let (indices, encoded): (Vec<_>, Vec<_>) = nodes[..t_len as usize]
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let mut encoded = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+            node.write(&mut &mut encoded[..])
+                .expect("Failed to write node data");
+            (index as u32, encoded)
+        })
+        .unzip();
+    let root = HistoryTree::<V1>::from_nodes(
+        0,
+        t_len,
+        indices
+            .iter()
+            .zip(&encoded)
+            .map(|(index, node)| (*index, node.to_vec())),
+    )
+    .unwrap()
+    .root()
+    .unwrap();
+
+    for leaf_index in (0..t_len).filter(|&index| node_altitude(index) == 0) {
+        let mut proof = vec![0; 4096];
+        let mut len = 0;
+        assert_eq!(
+            librustzcash_mmr_multiproof(
+                0,
+                t_len,
+                indices.as_ptr(),
+                encoded.as_ptr(),
+                indices.len(),
+                &leaf_index,
+                1,
+                proof.as_mut_ptr(),
+                proof.len(),
+                &mut len,
+            ),
+            0
+        );
+        proof.truncate(len);
+        let leaf_hash = V1::hash(&nodes[leaf_index as usize]);
+        let by_hash = |root: &[u8; 32], leaf_hash: &[u8; 32], proof: &[u8]| {
+            librustzcash_mmr_verify_proof_by_hash(
+                0,
+                t_len,
+                leaf_index,
+                root,
+                leaf_hash,
+                proof.as_ptr(),
+                proof.len(),
+            )
+        };
+        let full = |root: &[u8; 32], proof: &[u8]| {
+            librustzcash_mmr_verify_multiproof(
+                0,
+                t_len,
+                &leaf_index,
+                1,
+                proof.as_ptr(),
+                proof.len(),
+                root,
+            )
+        };
+
+        // Verifying by the leaf hash agrees with verifying the full proof.
+        let mut wrong_root = root;
+        wrong_root[0] ^= 1;
+        let mut tampered = proof.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        for (root, proof) in [(&root, &proof), (&wrong_root, &proof), (&root, &tampered)] {
+            assert_eq!(by_hash(root, &leaf_hash, proof), full(root, proof));
+        }
+        assert_eq!(by_hash(&root, &leaf_hash, &proof), 1);
+
+        // The proof's leaf must have the given hash.
+        let mut wrong_hash = leaf_hash;
+        wrong_hash[0] ^= 1;
+        assert_eq!(by_hash(&root, &wrong_hash, &proof), 3);
+        assert_eq!(by_hash(&root, &leaf_hash, &proof[..proof.len() - 1]), 2);
+    }
+}
+
+/// Returns the length of a tree with `leaves` leaves, the indices and entries of its
+/// peaks, and the next leaf. The peaks are made up rather than combined from real
+/// leaves, but each covers as many blocks as it has leaves, from height 1.
+fn simulated_tree(
+    leaves: u32,
+) -> (
+    u32,
+    Vec<u32>,
+    Vec<[u8; zcash_history::MAX_ENTRY_SIZE]>,
+    NodeData,
+) {
+    let node_data = |first_leaf: u32, alt: u32| NodeData {
+        consensus_branch_id: 0,
+        subtree_commitment: [alt as u8; 32],
+        start_time: first_leaf,
+        end_time: first_leaf + (1 << alt),
+        start_target: 190,
+        end_target: 200,
+        start_sapling_root: [0u8; 32],
+        end_sapling_root: [0u8; 32],
+        subtree_total_work: Default::default(),
+        start_height: u64::from(first_leaf),
+        end_height: u64::from(first_leaf) + (1 << alt) - 1,
+        sapling_tx: 1 << alt,
+    };
+
+    let t_len = librustzcash_mmr_total_nodes(leaves);
+    let mut first_leaf = 1;
+    let (indices, entries) = peaks(t_len)
+        .unwrap()
+        .into_iter()
+        .map(|(index, alt)| {
+            let data = node_data(first_leaf, alt);
+            first_leaf += 1 << alt;
+            let entry: Entry<V1> = if alt == 0 {
+                Entry::new_leaf(data)
+            } else {
+                Entry::new(
+                    data,
+                    EntryLink::Stored(index - (1 << alt)),
+                    EntryLink::Stored(index - 1),
+                )
+            };
+            let mut buf = [0u8; zcash_history::MAX_ENTRY_SIZE];
+            entry
+                .write(&mut &mut buf[..])
+                .expect("Cannot fail if enough buffer length");
+            (index, buf)
+        })
+        .unzip();
+    (t_len, indices, entries, node_data(first_leaf, 0))
+}
+
+#[test]
+fn append_on_stack_matches_tree() {
+    // `librustzcash_mmr_append` appends to trees of at most 16 peaks without an
+    // `MMRTree`, and to larger trees with one. Either way, it must append the same
+    // nodes and give the same root as `zcash_history`, and as `append_to_peaks`.
+    let check = |t_len: u32,
+                 indices: &[u32],
+                 entries: &[[u8; zcash_history::MAX_ENTRY_SIZE]],
+                 leaf: &NodeData,
+                 heights_match: bool| {
+        let mut leaf_bytes = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        leaf.write(&mut &mut leaf_bytes[..])
+            .expect("Failed to write node data");
+        let mut rt_ret = [0u8; 32];
+        let mut buf_ret = vec![[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 33];
+        let result = librustzcash_mmr_append(
+            0,
+            t_len,
+            indices.as_ptr(),
+            entries.as_ptr(),
+            std::ptr::null(),
+            indices.len(),
+            &leaf_bytes,
+            &mut rt_ret,
+            buf_ret.as_mut_ptr(),
+        );
+        buf_ret.truncate(result as usize);
+        let appended = buf_ret
+            .iter()
+            .map(|buf| V1::to_bytes(&V1::from_bytes(0, &buf[..]).unwrap()))
+            .collect::<Vec<_>>();
+
+        let mut tree = Tree::<V1>::new(
+            t_len,
+            indices
+                .iter()
+                .zip(entries)
+                .map(|(index, entry)| (*index, Entry::from_bytes(0, &entry[..]).unwrap()))
+                .collect(),
+            vec![],
+        );
+        let tree_appended = tree
+            .append_leaf(leaf.clone())
+            .unwrap()
+            .into_iter()
+            .map(|link| V1::to_bytes(tree.resolve_link(link).unwrap().data()))
+            .collect::<Vec<_>>();
+        assert_eq!(appended, tree_appended);
+        assert_eq!(rt_ret, V1::hash(tree.root_node().unwrap().data()));
+
+        let peaks = indices
+            .iter()
+            .zip(entries)
+            .map(|(index, entry)| {
+                (
+                    *index,
+                    decode_entry_data::<V1>(0, *index, &entry[..], false).unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut stored = vec![];
+        let root = append_to_peaks::<V1>(&peaks, leaf, |data| stored.push(V1::to_bytes(data)));
+        if heights_match {
+            assert_eq!(stored, appended);
+            assert_eq!(rt_ret, V1::hash(&root.unwrap()));
+        } else {
+            assert!(root.is_none());
+        }
+    };
+
+    for leaves in [
+        1,
+        2,
+        3,
+        0b1111_1110,
+        1_000_000,
+        (1 << 16) - 1,
+        (1 << 17) - 1,
+        (1 << 17) - 2,
+        (1 << 31) - 1,
+    ] {
+        let (t_len, indices, entries, leaf) = simulated_tree(leaves);
+        check(t_len, &indices, &entries, &leaf, true);
+    }
+
+    // A leaf that covers more than one block can't be appended to on the stack, so
+    // the append falls back to an `MMRTree`.
+    let (t_len, indices, entries, mut leaf) = simulated_tree(6);
+    leaf.end_height += 1;
+    check(t_len, &indices, &entries, &leaf, false);
+}
+
+#[test]
+fn verify_append_output() {
+    let nodes = load_nodes(NODE_DATA_16L);
+    let (indices, peaks) = preload_tree_append(&nodes);
+
+    let mut new_node_data = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+    NodeData {
+        consensus_branch_id: 0,
+        subtree_commitment: [0u8; 32],
+        start_time: 101,
+        end_time: 110,
+        start_target: 190,
+        end_target: 200,
+        start_sapling_root: [0u8; 32],
+        end_sapling_root: [0u8; 32],
+        subtree_total_work: Default::default(),
+        start_height: 10,
+        end_height: 10,
+        sapling_tx: 13,
+    }
+    .write(&mut &mut new_node_data[..])
+    .expect("Failed to write node data");
+
+    let mut rt_ret = [0u8; 32];
+    let mut buf_ret = [[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+    let appended = librustzcash_mmr_append(
         0,
         nodes.len() as u32,
         indices.as_ptr(),
-        nodes.as_ptr(),
-        peak_count,
-        indices.len() - peak_count,
+        peaks.as_ptr(),
+        std::ptr::null(),
+        peaks.len(),
+        &new_node_data,
         &mut rt_ret,
+        buf_ret.as_mut_ptr(),
+    ) as usize;
+    assert_eq!(appended, 2);
+
+    let verify = |reported: &[[u8; zcash_history::MAX_NODE_DATA_SIZE]], root: &[u8; 32]| {
+        librustzcash_mmr_verify_append_output(
+            0,
+            nodes.len() as u32,
+            indices.as_ptr(),
+            peaks.as_ptr(),
+            peaks.len(),
+            &new_node_data,
+            reported.as_ptr(),
+            reported.len(),
+            root,
+        )
+    };
+
+    // The actual output of the append.
+    assert_eq!(verify(&buf_ret[..appended], &rt_ret), 1);
+
+    // A tampered root.
+    let mut bad_root = rt_ret;
+    bad_root[0] ^= 1;
+    assert_eq!(verify(&buf_ret[..appended], &bad_root), 0);
+
+    // A tampered appended node.
+    let mut bad_nodes = buf_ret;
+    bad_nodes[1][40] ^= 1;
+    assert_eq!(verify(&bad_nodes[..appended], &rt_ret), 0);
+
+    // Missing and additional appended nodes.
+    assert_eq!(verify(&buf_ret[..1], &rt_ret), 0);
+    assert_eq!(verify(&buf_ret[..appended + 1], &rt_ret), 0);
+
+    // An append that can't be performed at all.
+    let invalid = librustzcash_mmr_verify_append_output(
+        0,
+        nodes.len() as u32,
+        indices.as_ptr(),
+        peaks.as_ptr(),
+        peaks.len(),
+        std::ptr::null(),
+        buf_ret.as_ptr(),
+        appended,
+        &rt_ret,
     );
+    assert_eq!(invalid, 2);
+}
 
-    // Deleting from full tree of 9 height would result in cascade deleting of 10 nodes
-    assert_eq!(result, 10);
+#[test]
+fn append_verbose() {
+    let nodes = load_nodes(NODE_DATA_16L);
+    let (indices, peaks) = preload_tree_append(&nodes);
+    assert!(peaks.len() > 1);
+
+    let mut new_node_data = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+    NodeData {
+        consensus_branch_id: 0,
+        subtree_commitment: [0u8; 32],
+        start_time: 101,
+        end_time: 110,
+        start_target: 190,
+        end_target: 200,
+        start_sapling_root: [0u8; 32],
+        end_sapling_root: [0u8; 32],
+        subtree_total_work: Default::default(),
+        start_height: 10,
+        end_height: 10,
+        sapling_tx: 13,
+    }
+    .write(&mut &mut new_node_data[..])
+    .expect("Failed to write node data");
+
+    let append = |t_len: u32,
+                  indices: &[u32],
+                  peaks: &[[u8; zcash_history::MAX_ENTRY_SIZE]],
+                  new_node: *const [u8; zcash_history::MAX_NODE_DATA_SIZE]| {
+        let mut rt_ret = [0u8; 32];
+        let mut buf_ret = [[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+        let mut report = MmrAppendReport {
+            reconstruct_status: 9,
+            reconstruct_index: 9,
+            nodes_status: 9,
+            nodes_index: 9,
+            new_node_status: 9,
+            append_status: 9,
+        };
+        let result = librustzcash_mmr_append_verbose(
+            0,
+            t_len,
+            indices.as_ptr(),
+            peaks.as_ptr(),
+            peaks.len(),
+            new_node,
+            &mut rt_ret,
+            buf_ret.as_mut_ptr(),
+            &mut report,
+        );
+        (result, rt_ret, report)
+    };
+    let stages = |report: &MmrAppendReport| {
+        [
+            report.reconstruct_status,
+            report.nodes_status,
+            report.new_node_status,
+            report.append_status,
+        ]
+    };
+    let t_len = nodes.len() as u32;
+
+    // Success matches librustzcash_mmr_append.
+    let (result, root, report) = append(t_len, &indices, &peaks, &new_node_data);
+    assert_eq!(result, 2);
+    assert_eq!(stages(&report), [0, 0, 0, 0]);
+    assert_eq!(
+        (report.reconstruct_index, report.nodes_index),
+        (u32::MAX, u32::MAX)
+    );
+    let mut expected_root = [0u8; 32];
+    let mut buf_ret = [[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+    librustzcash_mmr_append(
+        0,
+        t_len,
+        indices.as_ptr(),
+        peaks.as_ptr(),
+        std::ptr::null(),
+        peaks.len(),
+        &new_node_data,
+        &mut expected_root,
+        buf_ret.as_mut_ptr(),
+    );
+    assert_eq!(root, expected_root);
+
+    // Reconstruction fails for an invalid length, the wrong peaks, or too few peaks.
+    let (result, _, report) = append(t_len + 1, &indices, &peaks, &new_node_data);
+    assert_eq!(result, 0);
+    assert_eq!(stages(&report), [1, 2, 2, 2]);
+
+    let mut swapped = indices.clone();
+    swapped.swap(0, 1);
+    let (_, _, report) = append(t_len, &swapped, &peaks, &new_node_data);
+    assert_eq!(stages(&report), [1, 2, 2, 2]);
+    assert_eq!(report.reconstruct_index, 0);
+
+    let last = indices.len() - 1;
+    let (_, _, report) = append(t_len, &indices[..last], &peaks[..last], &new_node_data);
+    assert_eq!(stages(&report), [1, 2, 2, 2]);
+    assert_eq!(report.reconstruct_index, last as u32);
+
+    // Node validation reports the tree index of the undecodable node.
+    let mut corrupt = peaks.clone();
+    corrupt[1] = [0xff; zcash_history::MAX_ENTRY_SIZE];
+    let (_, _, report) = append(t_len, &indices, &corrupt, &new_node_data);
+    assert_eq!(stages(&report), [0, 1, 2, 2]);
+    assert_eq!(report.nodes_index, indices[1]);
+
+    // The new node must be provided.
+    let (_, _, report) = append(t_len, &indices, &peaks, std::ptr::null());
+    assert_eq!(stages(&report), [0, 0, 1, 2]);
+
+    // A peak that claims to span a non-power-of-two number of leaves can be decoded,
+    // but the append then needs its children, which weren't provided.
+    let mut inconsistent = nodes;
+    inconsistent[indices[0] as usize].end_height += 1;
+    let (_, inconsistent_peaks) = preload_tree_append(&inconsistent);
+    let (result, _, report) = append(t_len, &indices, &inconsistent_peaks, &new_node_data);
+    assert_eq!(result, 0);
+    assert_eq!(stages(&report), [0, 0, 0, 1]);
+
+    // Null out-params are rejected before anything is done.
+    let mut rt_ret = [0u8; 32];
+    let mut buf_ret = [[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+    let result = librustzcash_mmr_append_verbose(
+        0,
+        t_len,
+        indices.as_ptr(),
+        peaks.as_ptr(),
+        peaks.len(),
+        &new_node_data,
+        &mut rt_ret,
+        buf_ret.as_mut_ptr(),
+        std::ptr::null_mut(),
+    );
+    assert_eq!(result, 0);
+    let mut report = MmrAppendReport {
+        reconstruct_status: 9,
+        reconstruct_index: 9,
+        nodes_status: 9,
+        nodes_index: 9,
+        new_node_status: 9,
+        append_status: 9,
+    };
+    let result = librustzcash_mmr_append_verbose(
+        0,
+        t_len,
+        indices.as_ptr(),
+        peaks.as_ptr(),
+        peaks.len(),
+        &new_node_data,
+        std::ptr::null_mut(),
+        buf_ret.as_mut_ptr(),
+        &mut report,
+    );
+    assert_eq!(result, 0);
+    assert_eq!(stages(&report), [2, 2, 2, 2]);
+}
+
+#[test]
+fn root_with_replaced_leaf() {
+    let nodes = load_nodes(NODE_DATA_16L);
+    let t_len = nodes.len() as u32;
+
+    let mut drafted = vec![];
+    for pos in 1..=nodes.len() {
+        draft(&mut drafted, &nodes, pos, node_altitude(pos as u32 - 1));
+    }
+    let entries: Vec<(u32, [u8; zcash_history::MAX_ENTRY_SIZE])> = drafted
+        .into_iter()
+        .map(|(index, entry)| {
+            let mut buf = [0u8; zcash_history::MAX_ENTRY_SIZE];
+            entry
+                .write(&mut &mut buf[..])
+                .expect("Cannot fail if enough buffer length");
+            (index, buf)
+        })
+        .collect();
+
+    let root_with = |provided: &[u32], leaf_index: u32, leaf: &NodeData| {
+        let (indices, nodes): (Vec<_>, Vec<_>) = entries
+            .iter()
+            .filter(|(index, _)| provided.contains(index))
+            .cloned()
+            .unzip();
+        let mut leaf_buf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        leaf.write(&mut &mut leaf_buf[..])
+            .expect("Failed to write node data");
+        let mut rt_ret = [0u8; 32];
+        let result = librustzcash_mmr_root_with_replaced_leaf(
+            0,
+            t_len,
+            indices.as_ptr(),
+            nodes.as_ptr(),
+            indices.len(),
+            leaf_index,
+            &leaf_buf,
+            &mut rt_ret,
+        );
+        (result == 0).then(|| rt_ret)
+    };
+
+    let tree = Tree::<V1>::new(t_len, prepare_tree(&nodes).peaks, vec![]);
+    let root = V1::hash(tree.root_node().unwrap().data());
+    let all: Vec<u32> = (0..t_len).collect();
+
+    for leaf_index in (0..t_len).filter(|&index| node_altitude(index) == 0) {
+        let leaf = &nodes[leaf_index as usize];
+        assert_eq!(root_with(&all, leaf_index, leaf), Some(root));
+
+        let mut other = leaf.clone();
+        other.sapling_tx += 1;
+        let other_root = root_with(&all, leaf_index, &other).unwrap();
+        assert_ne!(other_root, root);
+
+        // The peaks and the siblings along the path are sufficient.
+        let (_, path) = crate::history::leaf_path(t_len, leaf_index).unwrap();
+        let mut needed: Vec<_> = crate::history::peaks(t_len)
+            .unwrap()
+            .into_iter()
+            .map(|(index, _)| index)
+            .collect();
+        needed.extend(path.iter().map(|(index, _)| *index));
+        assert_eq!(root_with(&needed, leaf_index, &other), Some(other_root));
+
+        // Without a sibling, the path can't be resolved.
+        if let Some(&(sibling, _)) = path.first() {
+            needed.retain(|&index| index != sibling);
+            assert_eq!(root_with(&needed, leaf_index, &other), None);
+        }
+    }
+
+    // Only leaves can be replaced.
+    let internal = (0..t_len).find(|&index| node_altitude(index) > 0).unwrap();
+    assert_eq!(root_with(&all, internal, &nodes[0]), None);
+    assert_eq!(root_with(&all, t_len, &nodes[0]), None);
+}
+
+#[test]
+fn delete() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = nodes.len() as u32;
+    let (indices, nodes, peak_count) = preload_tree_delete(&nodes);
+
+    let mut rt_ret = [0u8; 32];
+
+    let result = librustzcash_mmr_delete(
+        0,
+        t_len,
+        indices.as_ptr(),
+        nodes.as_ptr(),
+        std::ptr::null(),
+        peak_count,
+        indices.len() - peak_count,
+        &mut rt_ret,
+    );
+
+    // Deleting from full tree of 9 height would result in cascade deleting of 10 nodes
+    assert_eq!(result, 10);
+
+    // A null root pointer is rejected.
+    let result = librustzcash_mmr_delete(
+        0,
+        t_len,
+        indices.as_ptr(),
+        nodes.as_ptr(),
+        std::ptr::null(),
+        peak_count,
+        indices.len() - peak_count,
+        std::ptr::null_mut(),
+    );
+    assert_eq!(result, 0);
+}
+
+/// Returns the length of the entry encoding at the start of each buffer.
+fn entry_lengths(entries: &[[u8; zcash_history::MAX_ENTRY_SIZE]]) -> Vec<usize> {
+    entries
+        .iter()
+        .map(|buf| {
+            let mut encoding = vec![];
+            Entry::<V1>::from_bytes(0, &buf[..])
+                .unwrap()
+                .write(&mut encoding)
+                .unwrap();
+            encoding.len()
+        })
+        .collect()
+}
+
+#[test]
+fn entries_with_lengths() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = nodes.len() as u32;
+
+    let delete = |indices: &[u32], entries: &[_], lengths: *const usize, peak_count| {
+        let mut rt_ret = [0u8; 32];
+        let result = librustzcash_mmr_delete(
+            0,
+            t_len,
+            indices.as_ptr(),
+            entries.as_ptr(),
+            lengths,
+            peak_count,
+            indices.len() - peak_count,
+            &mut rt_ret,
+        );
+        (result, rt_ret)
+    };
+    let (indices, entries, peak_count) = preload_tree_delete(&nodes);
+    let mut lengths = entry_lengths(&entries);
+    let expected = delete(&indices, &entries, std::ptr::null(), peak_count);
+    assert_eq!(expected.0, 10);
+    assert_eq!(
+        delete(&indices, &entries, lengths.as_ptr(), peak_count),
+        expected
+    );
+
+    // Each entry must be exactly its length, which must fit in its buffer.
+    let last = lengths.len() - 1;
+    for length in [lengths[last] - 1, lengths[last] + 1, usize::MAX] {
+        lengths[last] = length;
+        assert_eq!(
+            delete(&indices, &entries, lengths.as_ptr(), peak_count).0,
+            0
+        );
+    }
+
+    let append = |indices: &[u32], entries: &[_], lengths: *const usize| {
+        let mut new_node_data = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        nodes[0]
+            .write(&mut &mut new_node_data[..])
+            .expect("Failed to write node data");
+        let mut rt_ret = [0u8; 32];
+        let mut buf_ret = [[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+        let result = librustzcash_mmr_append(
+            0,
+            t_len,
+            indices.as_ptr(),
+            entries.as_ptr(),
+            lengths,
+            indices.len(),
+            &new_node_data,
+            &mut rt_ret,
+            buf_ret.as_mut_ptr(),
+        );
+        (result, rt_ret, buf_ret)
+    };
+    let (indices, entries) = preload_tree_append(&nodes);
+    let mut lengths = entry_lengths(&entries);
+    let expected = append(&indices, &entries, std::ptr::null());
+    assert_ne!(expected.0, 0);
+    assert!(append(&indices, &entries, lengths.as_ptr()) == expected);
+
+    lengths[0] += 1;
+    assert_eq!(append(&indices, &entries, lengths.as_ptr()).0, 0);
+}
+
+#[test]
+fn self_check() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = nodes.len() as u32;
+    let tree = Tree::<V1>::new(t_len, prepare_tree(&nodes).peaks, vec![]);
+    let root = V1::hash(tree.root_node().unwrap().data());
+
+    let check = |nodes: &[NodeData], root: &[u8; 32]| {
+        let (indices, encoded, peak_count) = preload_tree_delete(nodes);
+        librustzcash_mmr_self_check(
+            0,
+            t_len,
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            peak_count,
+            indices.len() - peak_count,
+            root,
+        )
+    };
+
+    assert_eq!(check(&nodes, &root), 1);
+
+    let mut corrupted_root = root;
+    corrupted_root[31] ^= 1;
+    assert_eq!(check(&nodes, &corrupted_root), 0);
+
+    // Corrupting the left child of the last peak doesn't change the root, but it no
+    // longer combines into its parent.
+    let (indices, _, peak_count) = preload_tree_delete(&nodes);
+    let mut corrupted = nodes.clone();
+    corrupted[indices[peak_count] as usize].subtree_commitment[0] ^= 1;
+    assert_eq!(check(&corrupted, &root), 0);
+
+    // Nodes that can't be decoded are an error.
+    let (indices, mut encoded, peak_count) = preload_tree_delete(&nodes);
+    encoded[0] = [0xff; zcash_history::MAX_ENTRY_SIZE];
+    assert_eq!(
+        librustzcash_mmr_self_check(
+            0,
+            t_len,
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            peak_count,
+            indices.len() - peak_count,
+            &root,
+        ),
+        2
+    );
+}
+
+/// Encodes every node of a tree as an entry, linked to its children.
+fn encode_all_entries(nodes: &[NodeData]) -> Vec<[u8; zcash_history::MAX_ENTRY_SIZE]> {
+    nodes
+        .iter()
+        .enumerate()
+        .map(|(index, node)| {
+            let index = index as u32;
+            let entry = match node_altitude(index) {
+                0 => Entry::<V1>::new_leaf(node.clone()),
+                alt => Entry::new(
+                    node.clone(),
+                    EntryLink::Stored(index - (1 << alt)),
+                    EntryLink::Stored(index - 1),
+                ),
+            };
+            let mut buf = [0u8; zcash_history::MAX_ENTRY_SIZE];
+            entry
+                .write(&mut &mut buf[..])
+                .expect("Cannot fail if enough buffer length");
+            buf
+        })
+        .collect()
+}
+
+#[test]
+fn deep_validate() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let validate = |indices: &[u32], encoded: &[[u8; zcash_history::MAX_ENTRY_SIZE]]| {
+        librustzcash_mmr_deep_validate(0, indices.as_ptr(), encoded.as_ptr(), indices.len())
+    };
+    let indices: Vec<u32> = (0..nodes.len() as u32).collect();
+    let encoded = encode_all_entries(&nodes);
+    assert_eq!(validate(&indices, &encoded), u32::MAX);
+
+    // The nodes may be in any order.
+    let reversed: Vec<_> = encoded.iter().rev().cloned().collect();
+    let reversed_indices: Vec<_> = indices.iter().rev().cloned().collect();
+    assert_eq!(validate(&reversed_indices, &reversed), u32::MAX);
+
+    // A tampered internal node is reported, rather than its ancestors, which no longer
+    // combine from it either.
+    let tampered = (500..).find(|i| node_altitude(*i) > 1).unwrap();
+    let mut corrupted = nodes.clone();
+    corrupted[tampered as usize].subtree_commitment[0] ^= 1;
+    assert_eq!(
+        validate(&indices, &encode_all_entries(&corrupted)),
+        tampered
+    );
+
+    // A tampered leaf is reported as its parent.
+    let mut corrupted = nodes;
+    corrupted[0].end_time += 1;
+    assert_eq!(validate(&indices, &encode_all_entries(&corrupted)), 2);
+
+    // Every node must be provided exactly once, in a tree of the given length.
+    let mut duplicated = indices.clone();
+    duplicated[7] = 6;
+    assert_eq!(validate(&duplicated, &encoded), u32::MAX - 1);
+    assert_eq!(validate(&indices[..5], &encoded[..5]), u32::MAX - 1);
+    assert_eq!(validate(&indices[..4], &encoded[..4]), u32::MAX);
+    assert_eq!(
+        librustzcash_mmr_deep_validate(0, indices.as_ptr(), encoded.as_ptr(), 0),
+        u32::MAX - 1
+    );
+}
+
+#[test]
+fn root_both_versions() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = nodes.len() as u32;
+    let tree = Tree::<V1>::new(t_len, prepare_tree(&nodes).peaks, vec![]);
+    let v1_root = V1::hash(tree.root_node().unwrap().data());
+
+    let (indices, mut encoded, peak_count) = preload_tree_delete(&nodes);
+    let mut roots = ([0; 32], [0; 32]);
+    let flags = librustzcash_mmr_root_both_versions(
+        0,
+        t_len,
+        indices.as_ptr(),
+        encoded.as_ptr(),
+        peak_count,
+        indices.len() - peak_count,
+        &mut roots.0,
+        &mut roots.1,
+    );
+    // Both versions decode the nodes.
+    assert_eq!(flags, 3);
+    assert_eq!(roots.0, v1_root);
+    // The zero padding after each V1 encoding is read as empty Orchard fields, which
+    // the V2 root then commits to as well.
+    assert_ne!(roots.1, v1_root);
+
+    // When neither version can decode the nodes, the outputs are left untouched.
+    encoded[0] = [0xff; zcash_history::MAX_ENTRY_SIZE];
+    let mut roots = ([1; 32], [2; 32]);
+    assert_eq!(
+        librustzcash_mmr_root_both_versions(
+            0,
+            t_len,
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            peak_count,
+            indices.len() - peak_count,
+            &mut roots.0,
+            &mut roots.1,
+        ),
+        0
+    );
+    assert_eq!(roots, ([1; 32], [2; 32]));
+}
+
+#[test]
+fn canonicalize() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let (indices, encoded, peak_count) = preload_tree_delete(&nodes);
+    let t_len = nodes.len() as u32;
+
+    let delete = |indices: &[u32], encoded: &[[u8; zcash_history::MAX_ENTRY_SIZE]]| {
+        let mut rt_ret = [0u8; 32];
+        let result = librustzcash_mmr_delete(
+            0,
+            t_len,
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            std::ptr::null(),
+            peak_count,
+            indices.len() - peak_count,
+            &mut rt_ret,
+        );
+        (result, rt_ret)
+    };
+    let canonicalize = |indices: &[u32], encoded: &[[u8; zcash_history::MAX_ENTRY_SIZE]]| {
+        let mut out_ni = vec![0; indices.len()];
+        let mut out_n = vec![[0; zcash_history::MAX_ENTRY_SIZE]; indices.len()];
+        let result = librustzcash_mmr_canonicalize(
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            indices.len(),
+            out_ni.as_mut_ptr(),
+            out_n.as_mut_ptr(),
+        );
+        (result, out_ni, out_n)
+    };
+
+    // Take every other node from the end, and then the rest, so that the extras are
+    // interleaved with the peaks in reverse.
+    let shuffled: Vec<_> = (0..indices.len())
+        .rev()
+        .step_by(2)
+        .chain((0..indices.len()).rev().skip(1).step_by(2))
+        .collect();
+    let shuffled_ni: Vec<_> = shuffled.iter().map(|i| indices[*i]).collect();
+    let shuffled_n: Vec<_> = shuffled.iter().map(|i| encoded[*i]).collect();
+    assert_ne!(shuffled_ni, indices);
+
+    let (result, out_ni, out_n) = canonicalize(&shuffled_ni, &shuffled_n);
+    assert_eq!(result as usize, peak_count);
+    assert_eq!(out_ni[..peak_count], indices[..peak_count]);
+    assert_eq!(delete(&out_ni, &out_n), delete(&indices, &encoded));
+
+    // The canonical order is stable.
+    let (result, again_ni, again_n) = canonicalize(&out_ni, &out_n);
+    assert_eq!(result as usize, peak_count);
+    assert_eq!(again_ni, out_ni);
+    assert!(again_n == out_n);
+
+    // A missing peak, a repeated node, and a node that is neither a peak nor an extra
+    // are all rejected.
+    let without_peak: Vec<_> = (1..indices.len()).collect();
+    let repeated: Vec<_> = (0..indices.len()).chain([indices.len() - 1]).collect();
+    let internal = (0..t_len)
+        .find(|index| !indices.contains(index) && node_altitude(*index) > 0)
+        .unwrap();
+    for positions in [without_peak, repeated] {
+        let ni: Vec<_> = positions.iter().map(|i| indices[*i]).collect();
+        let n: Vec<_> = positions.iter().map(|i| encoded[*i]).collect();
+        assert_eq!(canonicalize(&ni, &n).0, 0);
+    }
+    let mut ni = indices;
+    ni[peak_count] = internal;
+    assert_eq!(canonicalize(&ni, &encoded).0, 0);
+    assert_eq!(canonicalize(&[], &[]).0, 0);
+}
+
+fn mmr_stats() -> MmrStats {
+    let mut stats = MmrStats {
+        reconstructions: 0,
+        nodes_decoded: 0,
+        last_nodes_decoded: 0,
+    };
+    librustzcash_mmr_stats(&mut stats);
+    stats
+}
+
+#[test]
+fn mmr_stats_ignores_null() {
+    librustzcash_mmr_stats(std::ptr::null_mut());
+}
+
+#[test]
+fn delete_reports_nodes_decoded() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let t_len = nodes.len() as u32;
+    let (indices, nodes, peak_count) = preload_tree_delete(&nodes);
+    let extra_count = indices.len() - peak_count;
+    assert!(extra_count > 0);
+
+    let before = mmr_stats();
+    let mut rt_ret = [0u8; 32];
+    librustzcash_mmr_delete(
+        0,
+        t_len,
+        indices.as_ptr(),
+        nodes.as_ptr(),
+        std::ptr::null(),
+        peak_count,
+        extra_count,
+        &mut rt_ret,
+    );
+    let after = mmr_stats();
+
+    // The gauge is per thread, so other tests can't change it, but they can add to the
+    // totals concurrently.
+    assert_eq!(after.last_nodes_decoded, (peak_count + extra_count) as u64);
+    assert!(after.reconstructions > before.reconstructions);
+    assert!(after.nodes_decoded >= before.nodes_decoded + after.last_nodes_decoded);
+}
+
+#[test]
+fn delete_checks_extra_count() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let mut rt_ret = [0u8; 32];
+
+    // The last peak of a full tree has children, so extras are needed.
+    let (indices, encoded, peak_count) = preload_tree_delete(&nodes);
+    assert!(indices.len() > peak_count);
+    let result = librustzcash_mmr_delete(
+        0,
+        nodes.len() as u32,
+        indices.as_ptr(),
+        encoded.as_ptr(),
+        std::ptr::null(),
+        peak_count,
+        0,
+        &mut rt_ret,
+    );
+    assert_eq!(result, u32::MAX);
+    assert_eq!(rt_ret, [0u8; 32]);
+
+    // The last peak of a tree with three leaves is a leaf, so none are needed.
+    let (indices, encoded, peak_count) = preload_tree_delete(&nodes[..4]);
+    assert_eq!(indices.len(), peak_count);
+    let result = librustzcash_mmr_delete(
+        0,
+        4,
+        indices.as_ptr(),
+        encoded.as_ptr(),
+        std::ptr::null(),
+        peak_count,
+        0,
+        &mut rt_ret,
+    );
+    assert_eq!(result, 1);
+    let expected = Tree::<V1>::new(3, prepare_tree(&nodes[..3]).peaks, vec![]);
+    assert_eq!(rt_ret, V1::hash(expected.root_node().unwrap().data()));
+}
+
+#[test]
+fn branch_name() {
+    let cbranch = BranchId::Heartwood.into();
+
+    // Ask for the size first, without a buffer.
+    let required = librustzcash_branch_name(cbranch, std::ptr::null_mut(), 0);
+    assert_eq!(required, "Heartwood".len() + 1);
+
+    // A buffer that is too small is left untouched.
+    let mut buf: Vec<libc::c_char> = vec![0x7f; required - 1];
+    assert_eq!(
+        librustzcash_branch_name(cbranch, buf.as_mut_ptr(), buf.len()),
+        required
+    );
+    assert!(buf.iter().all(|&c| c == 0x7f));
+
+    let mut buf: Vec<libc::c_char> = vec![0; required];
+    assert_eq!(
+        librustzcash_branch_name(cbranch, buf.as_mut_ptr(), buf.len()),
+        required
+    );
+    let name = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+    assert_eq!(name.to_str(), Ok("Heartwood"));
+
+    assert_eq!(
+        librustzcash_branch_name(BranchId::Nu5.into(), std::ptr::null_mut(), 0),
+        4
+    );
+    assert_eq!(
+        librustzcash_branch_name(0xdeadbeef, std::ptr::null_mut(), 0),
+        0
+    );
+}
+
+#[test]
+fn history_enabled() {
+    let enabled = |branch: BranchId| {
+        let mut result = 2;
+        assert_eq!(
+            librustzcash_mmr_history_enabled(branch.into(), &mut result),
+            0
+        );
+        result
+    };
+    assert_eq!(enabled(BranchId::Sprout), 0);
+    assert_eq!(enabled(BranchId::Sapling), 0);
+    assert_eq!(enabled(BranchId::Blossom), 0);
+    assert_eq!(enabled(BranchId::Heartwood), 1);
+    assert_eq!(enabled(BranchId::Canopy), 1);
+    assert_eq!(enabled(BranchId::Nu5), 1);
+
+    let mut result = 2;
+    assert_eq!(librustzcash_mmr_history_enabled(0xdeadbeef, &mut result), 1);
+    assert_eq!(result, 2);
+    assert_eq!(
+        librustzcash_mmr_history_enabled(BranchId::Heartwood.into(), std::ptr::null_mut()),
+        1
+    );
+}
+
+#[test]
+fn empty_root() {
+    let empty_root = |cbranch: u32| {
+        let mut root = [0xff; 32];
+        (librustzcash_mmr_empty_root(cbranch, &mut root), root)
+    };
+
+    // The V1 and V2 trees both commit to the all-zero hash until their first leaf is
+    // appended.
+    for branch in [BranchId::Heartwood, BranchId::Canopy, BranchId::Nu5] {
+        assert_eq!(empty_root(branch.into()), (0, [0; 32]));
+    }
+
+    // Earlier branches have no history tree, so no empty root either.
+    for branch in [BranchId::Sprout, BranchId::Sapling, BranchId::Blossom] {
+        assert_eq!(empty_root(branch.into()), (1, [0xff; 32]));
+    }
+
+    assert_eq!(empty_root(0xdeadbeef), (2, [0xff; 32]));
+    assert_eq!(
+        librustzcash_mmr_empty_root(BranchId::Nu5.into(), std::ptr::null_mut()),
+        2
+    );
+
+    // The functions that take a tree's nodes have none to hash for an empty tree.
+    let mut root = [0xff; 32];
+    assert_eq!(
+        librustzcash_mmr_root_from_leaves(
+            BranchId::Heartwood.into(),
+            std::ptr::null(),
+            0,
+            &mut root
+        ),
+        1
+    );
+    assert_eq!(root, [0xff; 32]);
+}
+
+#[test]
+fn leaf_heights() {
+    let main = b"main\0".as_ptr() as *const _;
+    let test = b"test\0".as_ptr() as *const _;
+    let to_height = |network, branch: BranchId, leaf_index| {
+        let mut height = 0;
+        let res = librustzcash_mmr_leaf_to_height(network, branch.into(), leaf_index, &mut height);
+        (res, height)
+    };
+    let to_leaf = |network, branch: BranchId, height| {
+        let mut leaf_index = 0;
+        let res = librustzcash_mmr_height_to_leaf(network, branch.into(), height, &mut leaf_index);
+        (res, leaf_index)
+    };
+
+    // The first leaf of each tree is its branch's activation block.
+    assert_eq!(to_height(main, BranchId::Heartwood, 0), (0, 903_000));
+    assert_eq!(to_height(main, BranchId::Canopy, 0), (0, 1_046_400));
+    assert_eq!(to_height(main, BranchId::Nu5, 0), (0, 1_687_104));
+    assert_eq!(to_height(test, BranchId::Heartwood, 0), (0, 903_800));
+    assert_eq!(to_leaf(main, BranchId::Nu5, 1_687_104), (0, 0));
+
+    // The last leaf of the Heartwood tree is the block before Canopy activation.
+    assert_eq!(
+        to_height(main, BranchId::Heartwood, 143_399),
+        (0, 1_046_399)
+    );
+    assert_eq!(to_leaf(main, BranchId::Heartwood, 1_046_399), (0, 143_399));
+    assert_eq!(to_height(main, BranchId::Heartwood, 143_400).0, 2);
+    assert_eq!(to_leaf(main, BranchId::Canopy, 1_046_399).0, 2);
+    assert_eq!(to_leaf(main, BranchId::Heartwood, 1_046_400).0, 2);
+    assert_eq!(to_height(main, BranchId::Nu5, u32::MAX).0, 2);
+
+    // Branches without history trees can't be converted.
+    assert_eq!(to_height(main, BranchId::Blossom, 0).0, 1);
+    assert_eq!(to_leaf(main, BranchId::Sapling, 500_000).0, 1);
+    let mut height = 0;
+    assert_eq!(
+        librustzcash_mmr_leaf_to_height(std::ptr::null(), BranchId::Nu5.into(), 0, &mut height),
+        1
+    );
+}
+
+#[test]
+fn check_leaf_height() {
+    let main = b"main\0".as_ptr() as *const _;
+    let leaf = |height| {
+        let mut buf = [0u8; zcash_history::MAX_ENTRY_SIZE];
+        Entry::<V1>::new_leaf(NodeData {
+            consensus_branch_id: 0,
+            subtree_commitment: [0u8; 32],
+            start_time: 101,
+            end_time: 110,
+            start_target: 190,
+            end_target: 200,
+            start_sapling_root: [0u8; 32],
+            end_sapling_root: [0u8; 32],
+            subtree_total_work: Default::default(),
+            start_height: height,
+            end_height: height,
+            sapling_tx: 13,
+        })
+        .write(&mut &mut buf[..])
+        .expect("Cannot fail if enough buffer length");
+        buf
+    };
+
+    // A tree of three leaves starting at Heartwood activation, of which the second
+    // was stored with the wrong height.
+    let t_len = 4;
+    let indices = [0, 1, 3];
+    let nodes = [leaf(903_000), leaf(903_005), leaf(903_002)];
+    let check = |leaf_index, expected_height, network| {
+        librustzcash_mmr_check_leaf_height(
+            BranchId::Heartwood.into(),
+            t_len,
+            indices.as_ptr(),
+            nodes.as_ptr(),
+            nodes.len(),
+            leaf_index,
+            expected_height,
+            network,
+        )
+    };
+
+    assert_eq!(check(0, 903_000, main), 0);
+    assert_eq!(check(2, 903_002, main), 0);
+    assert_eq!(check(1, 903_001, main), 1);
+    // The expected height must be that of the leaf's position, even if the leaf
+    // encodes it.
+    assert_eq!(check(1, 903_005, main), 2);
+    assert_eq!(check(0, 903_002, main), 2);
+
+    // The leaf must be in the tree, and provided.
+    assert_eq!(check(3, 903_003, main), 3);
+    assert_eq!(
+        librustzcash_mmr_check_leaf_height(
+            BranchId::Heartwood.into(),
+            t_len,
+            indices.as_ptr(),
+            nodes.as_ptr(),
+            2,
+            2,
+            903_002,
+            main,
+        ),
+        3
+    );
+    assert_eq!(check(0, 903_000, std::ptr::null()), 3);
+    assert_eq!(
+        librustzcash_mmr_check_leaf_height(
+            BranchId::Blossom.into(),
+            t_len,
+            indices.as_ptr(),
+            nodes.as_ptr(),
+            nodes.len(),
+            0,
+            903_000,
+            main,
+        ),
+        3
+    );
+}
+
+#[test]
+fn combine_rule_id() {
+    let rule = |branch: BranchId| {
+        let mut id = u32::MAX;
+        assert_eq!(librustzcash_mmr_combine_rule_id(branch.into(), &mut id), 0);
+        id
+    };
+
+    // These are part of the FFI, and must not change.
+    assert_eq!(rule(BranchId::Heartwood), 1);
+    assert_eq!(rule(BranchId::Canopy), 1);
+    assert_eq!(rule(BranchId::Nu5), 2);
+
+    let mut id = u32::MAX;
+    assert_eq!(librustzcash_mmr_combine_rule_id(0xdeadbeef, &mut id), 1);
+    assert_eq!(id, u32::MAX);
+    assert_eq!(
+        librustzcash_mmr_combine_rule_id(BranchId::Nu5.into(), std::ptr::null_mut()),
+        1
+    );
+}
+
+#[test]
+fn total_nodes() {
+    for t_len in 1..5000 {
+        let leaves = librustzcash_mmr_leaf_count(t_len);
+        if leaves != 0 {
+            assert_eq!(librustzcash_mmr_total_nodes(leaves), t_len);
+        }
+    }
+
+    for leaves in 0..2500 {
+        assert_eq!(
+            librustzcash_mmr_leaf_count(librustzcash_mmr_total_nodes(leaves)),
+            leaves
+        );
+    }
+
+    assert_eq!(librustzcash_mmr_leaf_count(0), 0);
+    assert_eq!(librustzcash_mmr_leaf_count(2), 0);
+    // The largest tree that fits is a single peak of altitude 31.
+    assert_eq!(librustzcash_mmr_total_nodes(1 << 31), u32::MAX);
+    assert_eq!(librustzcash_mmr_leaf_count(u32::MAX), 1 << 31);
+    assert_eq!(librustzcash_mmr_total_nodes((1 << 31) + 1), 0);
+    assert_eq!(librustzcash_mmr_total_nodes(u32::MAX), 0);
+}
+
+#[test]
+fn node_is_canonical() {
+    let heartwood: u32 = BranchId::Heartwood.into();
+    let is_canonical = |bytes: &[u8]| {
+        let mut result = u32::MAX;
+        assert_eq!(
+            librustzcash_mmr_node_is_canonical(heartwood, bytes.as_ptr(), bytes.len(), &mut result),
+            0
+        );
+        result
+    };
+
+    let node = V1::to_bytes(&load_nodes(NODE_DATA_16L)[0]);
+    assert_eq!(is_canonical(&node), 1);
+    // Zero padding, as in the node buffers that zcashd passes in, is allowed.
+    let mut padded = [0; zcash_history::MAX_NODE_DATA_SIZE];
+    padded[..node.len()].copy_from_slice(&node);
+    assert_eq!(is_canonical(&padded), 1);
+
+    // Trailing garbage would be ignored by the decoder.
+    padded[zcash_history::MAX_NODE_DATA_SIZE - 1] = 1;
+    assert_eq!(is_canonical(&padded), 0);
+    let mut extended = node.clone();
+    extended.push(0xff);
+    assert_eq!(is_canonical(&extended), 0);
+
+    // The start height, re-encoded as a three-byte CompactSize, decodes to the same
+    // node.
+    let start_height = 32 + 4 + 4 + 4 + 4 + 32 + 32 + 32;
+    assert!(node[start_height] < 0xfd);
+    let mut widened = node.clone();
+    widened.splice(
+        start_height..start_height + 1,
+        [0xfd, node[start_height], 0],
+    );
+    assert_eq!(
+        V1::to_bytes(&V1::from_bytes(heartwood, &widened).unwrap()),
+        node
+    );
+    assert_eq!(is_canonical(&widened), 0);
+
+    // An encoding that can't be decoded is not canonical.
+    assert_eq!(is_canonical(&node[..100]), 0);
+    assert_eq!(is_canonical(&[]), 0);
+
+    let mut result = u32::MAX;
+    assert_eq!(
+        librustzcash_mmr_node_is_canonical(0xdeadbeef, node.as_ptr(), node.len(), &mut result),
+        1
+    );
+    let oversize = [0; zcash_history::MAX_NODE_DATA_SIZE + 1];
+    assert_eq!(
+        librustzcash_mmr_node_is_canonical(
+            heartwood,
+            oversize.as_ptr(),
+            oversize.len(),
+            &mut result
+        ),
+        1
+    );
+    assert_eq!(result, u32::MAX);
+    assert_eq!(
+        librustzcash_mmr_node_is_canonical(
+            heartwood,
+            node.as_ptr(),
+            node.len(),
+            std::ptr::null_mut()
+        ),
+        1
+    );
+}
+
+#[test]
+fn hash_personalization() {
+    let personalization = |cbranch: BranchId, cap| {
+        let mut out = vec![0; cap];
+        let mut len = 0;
+        let result =
+            librustzcash_mmr_hash_personalization(cbranch.into(), out.as_mut_ptr(), cap, &mut len);
+        (result, out, len)
+    };
+
+    let (result, v1, len) = personalization(BranchId::Heartwood, 16);
+    assert_eq!((result, len), (0, 16));
+    assert_eq!(&v1[..], b"ZcashHistory\x0b\x23\xb9\xf5");
+    let (result, v2, len) = personalization(BranchId::Nu5, 20);
+    assert_eq!((result, len), (0, 16));
+    assert_eq!(&v2[..16], b"ZcashHistory\xb4\xd0\xd6\xc2");
+    assert_ne!(v1[..], v2[..16]);
+
+    // The hashes of each node use the personalization.
+    let nodes = load_nodes(NODE_DATA_16L);
+    let mut node = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+    nodes[0].write(&mut &mut node[..]).unwrap();
+    let mut hash = [0u8; 32];
+    assert_eq!(librustzcash_mmr_hash_node(0, &node, &mut hash), 0);
+    let expected = blake2b_simd::Params::new()
+        .hash_length(32)
+        .personal(&personalization(BranchId::Sprout, 16).1)
+        .hash(&V1::to_bytes(&nodes[0]));
+    assert_eq!(hash[..], expected.as_bytes()[..]);
+
+    assert_eq!(personalization(BranchId::Canopy, 15), (2, vec![0; 15], 16));
+    let mut len = 0;
+    assert_eq!(
+        librustzcash_mmr_hash_personalization(0x1234_5678, std::ptr::null_mut(), 0, &mut len),
+        1
+    );
+}
+
+#[test]
+fn storage_bytes() {
+    let storage = |branch: BranchId, leaves| {
+        let mut bytes = u64::MAX;
+        assert_eq!(
+            librustzcash_mmr_storage_bytes(branch.into(), leaves, &mut bytes),
+            0
+        );
+        bytes
+    };
+
+    // `CCoinsViewDB` writes a 9-byte key and a 244-byte node for each node, padding V1
+    // nodes to the length of V2 nodes. So a V2 tree intentionally takes the same space
+    // as a V1 tree of the same length, rather than more: this is the size on disk, not
+    // the size of the nodes' encodings.
+    assert_eq!(storage(BranchId::Heartwood, 0), 0);
+    assert_eq!(storage(BranchId::Heartwood, 1), 253);
+    assert_eq!(storage(BranchId::Nu5, 1), 253);
+    for leaves in [2, 3, 16, 1023, 1 << 20, 1 << 31] {
+        let t_len = u64::from(librustzcash_mmr_total_nodes(leaves));
+        assert_eq!(storage(BranchId::Canopy, leaves), t_len * 253);
+        assert_eq!(storage(BranchId::Nu5, leaves), t_len * 253);
+    }
+
+    // The serialized nodes of a V1 tree fit in the space they are stored in.
+    let t_len = load_nodes(NODE_DATA_1023L).len() as u32;
+    let leaves = librustzcash_mmr_leaf_count(t_len);
+    assert_ne!(leaves, 0);
+    assert!(NODE_DATA_1023L.len() as u64 <= storage(BranchId::Heartwood, leaves));
+
+    let mut bytes = u64::MAX;
+    assert_eq!(librustzcash_mmr_storage_bytes(0xdeadbeef, 1, &mut bytes), 1);
+    assert_eq!(
+        librustzcash_mmr_storage_bytes(BranchId::Nu5.into(), u32::MAX, &mut bytes),
+        1
+    );
+    assert_eq!(bytes, u64::MAX);
+    assert_eq!(
+        librustzcash_mmr_storage_bytes(BranchId::Nu5.into(), 1, std::ptr::null_mut()),
+        1
+    );
+}
+
+#[test]
+fn sibling_index() {
+    let sibling = |t_len, index| {
+        let (mut sibling, mut has_sibling) = (0, 2);
+        match librustzcash_mmr_sibling_index(t_len, index, &mut sibling, &mut has_sibling) {
+            0 => Some((has_sibling == 1).then(|| sibling)),
+            _ => None,
+        }
+    };
+
+    // A tree with seven leaves has peaks at 6, 9 and 10.
+    let t_len = librustzcash_mmr_total_nodes(7);
+    for (a, b) in [(0, 1), (2, 5), (3, 4), (7, 8)] {
+        assert_eq!(sibling(t_len, a), Some(Some(b)));
+        assert_eq!(sibling(t_len, b), Some(Some(a)));
+    }
+    for peak in [6, 9, 10] {
+        assert_eq!(sibling(t_len, peak), Some(None));
+    }
+    let (mut sibling_ret, mut has_sibling) = (0, 1);
+    assert_eq!(
+        librustzcash_mmr_sibling_index(t_len, 6, &mut sibling_ret, &mut has_sibling),
+        0
+    );
+    assert_eq!((sibling_ret, has_sibling), (u32::MAX, 0));
+
+    // Each leaf's path is made of the siblings of its ancestors.
+    for t_len in (1..300).filter(|&t_len| librustzcash_mmr_leaf_count(t_len) != 0) {
+        for leaf_index in (0..t_len).filter(|&index| node_altitude(index) == 0) {
+            let (_, path) = leaf_path(t_len, leaf_index).unwrap();
+            let mut node = leaf_index;
+            for (index, is_right) in path {
+                assert_eq!(sibling(t_len, node), Some(Some(index)));
+                node = if is_right { index } else { node } + 1;
+            }
+            assert_eq!(sibling(t_len, node), Some(None));
+        }
+    }
+
+    // The largest tree has a single peak, whose children are siblings.
+    assert_eq!(sibling(u32::MAX, u32::MAX - 2), Some(Some((1 << 31) - 2)));
+    assert_eq!(sibling(u32::MAX, u32::MAX - 1), Some(None));
+
+    assert_eq!(sibling(2, 0), None);
+    assert_eq!(sibling(t_len, t_len), None);
+    assert_eq!(
+        librustzcash_mmr_sibling_index(t_len, 0, std::ptr::null_mut(), &mut has_sibling),
+        1
+    );
+}
+
+#[test]
+fn append_sizes() {
+    let sizes = |t_len| {
+        let (mut exact, mut max) = (u32::MAX, u32::MAX);
+        let res = librustzcash_mmr_append_sizes(t_len, &mut exact, &mut max);
+        (res, exact, max)
+    };
+
+    let mut last_max = 0;
+    for t_len in 0..5000 {
+        let (res, exact, max) = sizes(t_len);
+        if librustzcash_mmr_leaf_count(t_len) == 0 && t_len != 0 {
+            assert_eq!((res, exact, max), (1, u32::MAX, u32::MAX));
+            continue;
+        }
+        assert_eq!(res, 0);
+        assert!(1 <= exact && exact <= max, "t_len {}", t_len);
+        assert!(max >= last_max);
+        last_max = max;
+    }
+
+    // Two leaves gain a third without a merge; three leaves merge twice into a peak of four.
+    assert_eq!(sizes(3), (0, 1, 2));
+    assert_eq!(sizes(4), (0, 3, 3));
+    assert_eq!(sizes(u32::MAX), (1, u32::MAX, u32::MAX));
+    assert_eq!(
+        librustzcash_mmr_append_sizes(1, std::ptr::null_mut(), &mut 0),
+        1
+    );
+}
+
+#[test]
+fn append_write_plan() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let plan = |t_len| {
+        let mut created = [u32::MAX; 16];
+        let (mut created_len, mut overwritten_len) = (usize::MAX, usize::MAX);
+        let res = librustzcash_mmr_append_write_plan(
+            t_len,
+            created.as_mut_ptr(),
+            created.len(),
+            &mut created_len,
+            std::ptr::null_mut(),
+            0,
+            &mut overwritten_len,
+        );
+        assert_eq!(res, 0);
+        assert_eq!(overwritten_len, 0);
+        created[..created_len].to_vec()
+    };
+
+    // Append each leaf in turn, checking the plan against the nodes the append stores.
+    let mut leaves = (0..nodes.len() as u32)
+        .filter(|&index| node_altitude(index) == 0)
+        .map(|index| nodes[index as usize].clone());
+    let first = Entry::new_leaf(leaves.next().unwrap());
+    let mut tree = Tree::<V1>::new(1, vec![(0, first)], vec![]);
+    for leaf in leaves.take(100) {
+        let t_len = tree.len();
+        let appended = tree.append_leaf(leaf).unwrap();
+        let stored: Vec<u32> = appended
+            .iter()
+            .map(|link| match link {
+                EntryLink::Stored(index) => *index,
+                EntryLink::Generated(_) => panic!("appended nodes are stored"),
+            })
+            .collect();
+        assert_eq!(plan(t_len), stored, "t_len {}", t_len);
+    }
+
+    // A buffer that is too small still learns the plan's size.
+    let (mut created_len, mut overwritten_len) = (0, usize::MAX);
+    assert_eq!(
+        librustzcash_mmr_append_write_plan(
+            4,
+            [0; 2].as_mut_ptr(),
+            2,
+            &mut created_len,
+            std::ptr::null_mut(),
+            0,
+            &mut overwritten_len,
+        ),
+        2
+    );
+    assert_eq!((created_len, overwritten_len), (3, 0));
+    assert_eq!(
+        librustzcash_mmr_append_write_plan(
+            2,
+            [0; 2].as_mut_ptr(),
+            2,
+            &mut created_len,
+            std::ptr::null_mut(),
+            0,
+            &mut overwritten_len,
+        ),
+        1
+    );
+}
+
+#[test]
+fn can_delete() {
+    // Empty tree.
+    assert_eq!(librustzcash_mmr_can_delete(0, std::ptr::null(), 0), 0);
+    // A single leaf, and a length that no tree has.
+    assert_eq!(librustzcash_mmr_can_delete(1, std::ptr::null(), 0), 3);
+    assert_eq!(librustzcash_mmr_can_delete(2, std::ptr::null(), 0), 3);
+
+    // Three leaves: the last peak is a leaf, so no extras are needed.
+    assert_eq!(librustzcash_mmr_can_delete(4, std::ptr::null(), 0), 1);
+
+    // Two leaves: both children of the only peak are needed.
+    assert_eq!(librustzcash_mmr_can_delete(3, std::ptr::null(), 0), 2);
+    assert_eq!(librustzcash_mmr_can_delete(3, [0].as_ptr(), 1), 2);
+    assert_eq!(librustzcash_mmr_can_delete(3, [1, 0].as_ptr(), 2), 1);
+
+    // The extras that zcashd preloads for a delete are sufficient, and all of them are needed.
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let (indices, _, peak_count) = preload_tree_delete(&nodes);
+    let extras = &indices[peak_count..];
+    assert_eq!(
+        librustzcash_mmr_can_delete(nodes.len() as u32, extras.as_ptr(), extras.len()),
+        1
+    );
+    assert_eq!(
+        librustzcash_mmr_can_delete(nodes.len() as u32, extras.as_ptr(), extras.len() - 1),
+        2
+    );
+}
+
+#[test]
+fn hash_block_leaf() {
+    let leaf = NodeData {
+        consensus_branch_id: 0,
+        subtree_commitment: [1u8; 32],
+        start_time: 101,
+        end_time: 101,
+        start_target: 190,
+        end_target: 190,
+        start_sapling_root: [2u8; 32],
+        end_sapling_root: [2u8; 32],
+        subtree_total_work: Default::default(),
+        start_height: 10,
+        end_height: 10,
+        sapling_tx: 13,
+    };
+    let mut leaf_data = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+    leaf.write(&mut &mut leaf_data[..])
+        .expect("Failed to write node data");
+
+    let mut expected = [0u8; 32];
+    assert_eq!(librustzcash_mmr_hash_node(0, &leaf_data, &mut expected), 0);
+
+    let mut h_ret = [0u8; 32];
+    let result = librustzcash_mmr_hash_block_leaf(
+        0,
+        &[1u8; 32],
+        101,
+        190,
+        &[2u8; 32],
+        std::ptr::null(),
+        &[0u8; 32],
+        10,
+        13,
+        0,
+        &mut h_ret,
+    );
+    assert_eq!(result, 0);
+    assert_eq!(h_ret, expected);
+
+    // V2 leaves additionally commit to the Orchard fields.
+    let nu5 = u32::from(BranchId::Nu5);
+    let mut v2_ret = [0u8; 32];
+    let result = librustzcash_mmr_hash_block_leaf(
+        nu5,
+        &[1u8; 32],
+        101,
+        190,
+        &[2u8; 32],
+        &[3u8; 32],
+        &[4u8; 32],
+        10,
+        13,
+        7,
+        &mut v2_ret,
+    );
+    assert_eq!(result, 0);
+
+    let v2_leaf = crate::history_ffi::BlockLeaf {
+        commitment: [1u8; 32],
+        time: 101,
+        target: 190,
+        sapling_root: [2u8; 32],
+        orchard_root: [3u8; 32],
+        total_work: [4u8; 32],
+        height: 10,
+        sapling_tx: 13,
+        orchard_tx: 7,
+    };
+    let v2_node = V2::from_bytes(nu5, &v2_leaf.to_bytes(nu5)).expect("Valid V2 leaf encoding");
+    assert_eq!(v2_ret, V2::hash(&v2_node));
+
+    // V2 leaves cannot be built without an Orchard root.
+    assert_eq!(
+        librustzcash_mmr_hash_block_leaf(
+            nu5,
+            &[1u8; 32],
+            101,
+            190,
+            &[2u8; 32],
+            std::ptr::null(),
+            &[4u8; 32],
+            10,
+            13,
+            7,
+            &mut v2_ret,
+        ),
+        1
+    );
+}
+
+#[test]
+fn root_needs_peak_node_data() {
+    // A tree of three leaves has two peaks, which are bagged into its root.
+    let nodes = load_nodes(NODE_DATA_16L);
+    let t_len = librustzcash_mmr_total_nodes(3);
+    let tree_view = prepare_tree(&nodes[..t_len as usize]);
+    let tree = Tree::<V1>::new(t_len, tree_view.peaks, vec![]);
+    let (left, right) = (&nodes[2], &nodes[3]);
+    let root = V1::combine(left, right);
+    assert_eq!(V1::hash(tree.root_node().unwrap().data()), V1::hash(&root));
+
+    // Bagging commits to the full encodings of the peaks, and takes the rest of the
+    // root's fields from theirs, so it can't be redone from the hashes of the peaks.
+    let hash = |bytes: &[u8]| {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(
+            blake2b_simd::Params::new()
+                .hash_length(32)
+                .personal(&crate::history::hash_personalization(0))
+                .hash(bytes)
+                .as_bytes(),
+        );
+        hash
+    };
+    assert_eq!(
+        root.subtree_commitment,
+        hash(&[V1::to_bytes(left), V1::to_bytes(right)].concat())
+    );
+    assert_ne!(
+        root.subtree_commitment,
+        hash(&[V1::hash(left), V1::hash(right)].concat())
+    );
+    assert_eq!(root.start_height, left.start_height);
+    assert_eq!(root.end_height, right.end_height);
+}
+
+#[test]
+fn peaks_commitment() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let commit = |t_len: u32, indices: &[u32], encoded: &[[u8; zcash_history::MAX_ENTRY_SIZE]]| {
+        let mut commitment = [0u8; 32];
+        match librustzcash_mmr_peaks_commitment(
+            0,
+            t_len,
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            indices.len(),
+            &mut commitment,
+        ) {
+            0 => Some(commitment),
+            _ => None,
+        }
+    };
+
+    // Identical peak sets have identical commitments.
+    let (indices, encoded) = preload_tree_append(&nodes[..11]);
+    assert_eq!(indices, vec![6, 9, 10]);
+    let commitment = commit(11, &indices, &encoded).unwrap();
+    let (indices_copy, encoded_copy) = preload_tree_append(&nodes[..11]);
+    assert_eq!(commit(11, &indices_copy, &encoded_copy), Some(commitment));
+
+    // A different set of peaks, or a different peak, changes the commitment.
+    let (other_indices, other_encoded) = preload_tree_append(&nodes[..10]);
+    assert_ne!(commit(10, &other_indices, &other_encoded), Some(commitment));
+    let mut tampered = encoded.clone();
+    // Skip the entry header to reach the subtree commitment.
+    tampered[1][9] ^= 1;
+    assert_ne!(commit(11, &indices, &tampered), Some(commitment));
+
+    // The nodes must be exactly the peaks of the tree, in order.
+    assert_eq!(commit(10, &indices, &encoded), None);
+    assert_eq!(commit(11, &indices[..2], &encoded[..2]), None);
+    assert_eq!(commit(11, &[6, 10, 9], &encoded), None);
+    assert_eq!(
+        librustzcash_mmr_peaks_commitment(
+            0,
+            11,
+            indices.as_ptr(),
+            encoded.as_ptr(),
+            indices.len(),
+            std::ptr::null_mut(),
+        ),
+        1
+    );
+}
+
+#[test]
+fn history_crate_versions() {
+    let required = librustzcash_history_crate_versions(std::ptr::null_mut(), 0);
+    let mut buf: Vec<libc::c_char> = vec![0x7f; required - 1];
+    assert_eq!(
+        librustzcash_history_crate_versions(buf.as_mut_ptr(), buf.len()),
+        required
+    );
+    assert!(buf.iter().all(|&c| c == 0x7f));
+
+    let mut buf: Vec<libc::c_char> = vec![0; required];
+    assert_eq!(
+        librustzcash_history_crate_versions(buf.as_mut_ptr(), buf.len()),
+        required
+    );
+    let versions = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) }
+        .to_str()
+        .unwrap();
+    let components: Vec<_> = versions.split(';').collect();
+    assert_eq!(components.len(), 2);
+    assert_eq!(
+        components[0],
+        format!("librustzcash={}", env!("CARGO_PKG_VERSION"))
+    );
+    let history_version = components[1].strip_prefix("zcash_history=").unwrap();
+    assert!(!history_version.is_empty());
+    assert_ne!(history_version, "unknown");
+}
+
+#[test]
+fn append_frontier() {
+    let nodes = load_nodes(NODE_DATA_1023L);
+    let frontier_of = |t_len: u32| {
+        let (indices, peaks) = if t_len == 0 {
+            (vec![], vec![])
+        } else {
+            preload_tree_append(&nodes[..t_len as usize])
+        };
+        let mut out = vec![0u8; 4096];
+        let mut len = 0;
+        assert_eq!(
+            librustzcash_mmr_frontier(
+                0,
+                t_len,
+                indices.as_ptr(),
+                peaks.as_ptr(),
+                peaks.len(),
+                out.as_mut_ptr(),
+                out.len(),
+                &mut len,
+            ),
+            0
+        );
+        out.truncate(len);
+        out
+    };
+
+    // An appender that only keeps the frontier, starting from the empty tree, has the
+    // same roots as appends given the full peaks.
+    let mut frontier = frontier_of(0);
+    assert_eq!(frontier, [0; 4]);
+    for leaves in 0..40 {
+        let t_len = librustzcash_mmr_total_nodes(leaves);
+        let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+        nodes[t_len as usize]
+            .write(&mut &mut leaf[..])
+            .expect("Failed to write node data");
+
+        let mut expected = [0u8; 32];
+        if t_len == 0 {
+            expected = V1::hash(&nodes[0]);
+        } else {
+            let (indices, peaks) = preload_tree_append(&nodes[..t_len as usize]);
+            let mut buf_ret = vec![[0u8; zcash_history::MAX_NODE_DATA_SIZE]; 32];
+            assert!(
+                librustzcash_mmr_append(
+                    0,
+                    t_len,
+                    indices.as_ptr(),
+                    peaks.as_ptr(),
+                    std::ptr::null(),
+                    peaks.len(),
+                    &leaf,
+                    &mut expected,
+                    buf_ret.as_mut_ptr(),
+                ) > 0
+            );
+        }
+
+        let mut root = [0u8; 32];
+        let mut out = vec![0u8; 4096];
+        let mut len = 0;
+        assert_eq!(
+            librustzcash_mmr_append_frontier(
+                0,
+                frontier.as_ptr(),
+                frontier.len(),
+                &leaf,
+                &mut root,
+                out.as_mut_ptr(),
+                out.len(),
+                &mut len,
+            ),
+            0,
+            "{} leaves",
+            leaves
+        );
+        out.truncate(len);
+        assert_eq!(root, expected, "{} leaves", leaves);
+
+        // The extended frontier is the frontier of the extended tree.
+        frontier = out;
+        assert_eq!(
+            frontier,
+            frontier_of(librustzcash_mmr_total_nodes(leaves + 1)),
+            "{} leaves",
+            leaves
+        );
+    }
+    // The frontier starts with the leaf count.
+    assert_eq!(&frontier[..4], &40u32.to_le_bytes());
+
+    // A buffer that is too small gets the length, and leaves the root as it was.
+    let mut leaf = [0u8; zcash_history::MAX_NODE_DATA_SIZE];
+    nodes[librustzcash_mmr_total_nodes(40) as usize]
+        .write(&mut &mut leaf[..])
+        .expect("Failed to write node data");
+    let mut root = [0u8; 32];
+    let mut len = 0;
+    assert_eq!(
+        librustzcash_mmr_append_frontier(
+            0,
+            frontier.as_ptr(),
+            frontier.len(),
+            &leaf,
+            &mut root,
+            std::ptr::null_mut(),
+            0,
+            &mut len,
+        ),
+        2
+    );
+    assert!(len > 0);
+    assert_eq!(root, [0; 32]);
+
+    // Truncated frontiers, and frontiers with trailing bytes, are rejected.
+    let mut out = vec![0u8; 4096];
+    for bad in [
+        &frontier[..frontier.len() - 1],
+        &[&frontier[..], &[0]].concat()[..],
+    ] {
+        assert_eq!(
+            librustzcash_mmr_append_frontier(
+                0,
+                bad.as_ptr(),
+                bad.len(),
+                &leaf,
+                &mut root,
+                out.as_mut_ptr(),
+                out.len(),
+                &mut len,
+            ),
+            1
+        );
+    }
+    // So are nodes that are not the peaks of the tree.
+    let (indices, peaks) = preload_tree_append(&nodes[..7]);
+    assert_eq!(
+        librustzcash_mmr_frontier(
+            0,
+            8,
+            indices.as_ptr(),
+            peaks.as_ptr(),
+            peaks.len(),
+            out.as_mut_ptr(),
+            out.len(),
+            &mut len,
+        ),
+        1
+    );
+}
+
+#[test]
+fn balanced_root() {
+    let leaves: Vec<[u8; 32]> = (0..7u8).map(|i| [i; 32]).collect();
+    let root = |cbranch: BranchId, leaves: &[[u8; 32]]| {
+        let mut root = [0u8; 32];
+        let result = librustzcash_mmr_balanced_root(
+            cbranch.into(),
+            leaves.as_ptr(),
+            leaves.len(),
+            &mut root,
+        );
+        assert_eq!(result, 0);
+        root
+    };
+    let combine = |cbranch: BranchId, left: [u8; 32], right: [u8; 32]| {
+        let hash = blake2b_simd::Params::new()
+            .hash_length(32)
+            .personal(&crate::history::hash_personalization(cbranch.into()))
+            .hash(&[left, right].concat());
+        let mut parent = [0u8; 32];
+        parent.copy_from_slice(hash.as_bytes());
+        parent
+    };
+
+    // A power-of-two count gives a perfect tree.
+    let c = |l, r| combine(BranchId::Nu5, l, r);
+    let l = &leaves;
+    assert_eq!(
+        root(BranchId::Nu5, &l[..4]),
+        c(c(l[0], l[1]), c(l[2], l[3]))
+    );
+
+    // Otherwise, a node without a sibling is carried up unchanged.
+    assert_eq!(
+        root(BranchId::Nu5, &l[..7]),
+        c(c(c(l[0], l[1]), c(l[2], l[3])), c(c(l[4], l[5]), l[6]))
+    );
+    assert_eq!(
+        root(BranchId::Nu5, &l[..5]),
+        c(c(c(l[0], l[1]), c(l[2], l[3])), l[4])
+    );
+    assert_eq!(root(BranchId::Nu5, &l[..1]), l[0]);
+
+    // Each branch hashes with its own personalization.
+    let c = |l, r| combine(BranchId::Heartwood, l, r);
+    assert_eq!(root(BranchId::Heartwood, &l[..3]), c(c(l[0], l[1]), l[2]));
+    assert_ne!(
+        root(BranchId::Heartwood, &l[..3]),
+        root(BranchId::Nu5, &l[..3])
+    );
+
+    // There is no root without leaves, or for an invalid branch.
+    let mut root = [0u8; 32];
+    assert_eq!(
+        librustzcash_mmr_balanced_root(BranchId::Nu5.into(), leaves.as_ptr(), 0, &mut root),
+        1
+    );
+    assert_eq!(
+        librustzcash_mmr_balanced_root(0x1234_5678, leaves.as_ptr(), 2, &mut root),
+        1
+    );
+    assert_eq!(
+        librustzcash_mmr_balanced_root(BranchId::Nu5.into(), std::ptr::null(), 2, &mut root),
+        1
+    );
+    assert_eq!(root, [0; 32]);
 }
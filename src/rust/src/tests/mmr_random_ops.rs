@@ -0,0 +1,68 @@
+use std::ffi::CStr;
+
+use zcash_primitives::consensus::BranchId;
+
+use crate::address_ffi::zcash_address_string_free;
+use crate::test_history_ffi::{
+    random_ops, zcash_test_harness_mmr_random_ops, zcash_test_harness_mmr_random_ops_diverged,
+    zcash_test_harness_mmr_random_ops_free, zcash_test_harness_mmr_random_ops_log, MmrOp,
+    MmrOpsReport,
+};
+
+fn assert_consistent(report: &MmrOpsReport) {
+    assert!(report.divergence.is_none(), "{}", report);
+}
+
+#[test]
+fn long_random_sequences_are_consistent() {
+    for (branch, seed) in [(BranchId::Heartwood, [1; 32]), (BranchId::Nu5, [2; 32])] {
+        let report = random_ops(seed, 5000, branch.into()).unwrap();
+        assert_consistent(&report);
+        assert_eq!(report.ops.len(), 5000);
+
+        // The sequences are mostly appends, but reorg often enough to delete through
+        // several peak merges.
+        let deletes = report.ops.iter().filter(|op| **op == MmrOp::Delete).count();
+        assert!(deletes > 100 && deletes < 1500, "{} deletes", deletes);
+    }
+}
+
+#[test]
+fn random_sequences_are_deterministic() {
+    let cbranch = BranchId::Canopy.into();
+    let ops = |seed| random_ops(seed, 200, cbranch).unwrap().ops;
+    assert_eq!(ops([3; 32]), ops([3; 32]));
+    assert_ne!(ops([3; 32]), ops([4; 32]));
+
+    // Branches without a history tree are rejected.
+    assert!(random_ops([3; 32], 200, BranchId::Sapling.into()).is_none());
+    assert!(random_ops([3; 32], 200, 0x1234_5678).is_none());
+}
+
+#[test]
+fn random_ops_ffi() {
+    let report = zcash_test_harness_mmr_random_ops(&[5; 32], 300, BranchId::Nu5.into());
+    assert!(!report.is_null());
+    let mut op_index = u32::MAX;
+    assert!(!zcash_test_harness_mmr_random_ops_diverged(
+        report,
+        &mut op_index
+    ));
+    assert_eq!(op_index, u32::MAX);
+
+    let log = zcash_test_harness_mmr_random_ops_log(report);
+    let lines: Vec<_> = unsafe { CStr::from_ptr(log) }
+        .to_str()
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect();
+    zcash_address_string_free(log);
+    // A header, a line per operation, and the outcome.
+    assert_eq!(lines.len(), 302);
+    assert_eq!(lines[1], "0: append 1");
+    assert_eq!(lines[301], "No divergence after 300 operations");
+    zcash_test_harness_mmr_random_ops_free(report);
+
+    assert!(zcash_test_harness_mmr_random_ops(&[5; 32], 300, BranchId::Blossom.into()).is_null());
+}
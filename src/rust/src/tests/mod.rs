@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use group::GroupEncoding;
 use zcash_primitives::constants::{
     NOTE_COMMITMENT_RANDOMNESS_GENERATOR, NULLIFIER_POSITION_GENERATOR,
@@ -5,13 +7,86 @@ use zcash_primitives::constants::{
     VALUE_COMMITMENT_VALUE_GENERATOR,
 };
 
+mod auth_data_tree;
+mod block_validation;
+mod fake_chain;
+mod fault_injection;
+mod fetch_params;
+mod groth16_params;
+mod history_invariants;
+mod history_tree;
 mod key_agreement;
 mod key_components;
+mod log_filter;
+mod merkle_frontier;
 mod mmr;
+mod mmr_random_ops;
+mod network;
 mod notes;
+mod orchard_bundles;
+mod orchard_proving;
+mod pedersen_hash;
+mod rng;
+mod sighash_legacy;
 mod signatures;
+mod sprout_batch;
+mod sprout_builder;
+mod sprout_migration;
+mod sprout_note_decryption;
+mod sprout_notes;
+mod sprout_prover;
+mod sprout_tree;
+mod test_vectors;
+mod thread_pool;
+mod transaction_digests;
+mod transaction_encoding;
+mod transaction_sniff;
+mod transaction_structure;
+mod transaction_timelocks;
+mod value_balances;
+mod wallet_scanner;
 mod zip339;
 
+/// The ZIP 244 test vectors.
+const ZIP_244_VECTORS: &str = include_str!("../../../test/data/zip0244.json");
+
+/// Decodes a hex string, ignoring surrounding whitespace.
+fn decode_hex(hex: &str) -> Vec<u8> {
+    let hex = hex.trim();
+    assert_eq!(hex.len() % 2, 0);
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+/// Decodes a digest displayed in the reversed byte order of `uint256::GetHex`.
+fn decode_digest(hex: &str) -> [u8; 32] {
+    let mut digest = [0; 32];
+    digest.copy_from_slice(&decode_hex(hex));
+    digest.reverse();
+    digest
+}
+
+/// Returns the hex strings of a JSON array of strings.
+fn json_hex_strings(json: &str) -> Vec<Vec<u8>> {
+    json.split('"').skip(1).step_by(2).map(decode_hex).collect()
+}
+
+/// Returns the first v5 transaction from the ZIP 244 test vectors, which is valid on
+/// NU5 mainnet and has one transparent input, a Sapling spend and output, and two
+/// Orchard actions.
+fn v5_transaction() -> Vec<u8> {
+    let start = ZIP_244_VECTORS.find("\"05000080").unwrap() + 1;
+    let end = start + ZIP_244_VECTORS[start..].find('"').unwrap();
+    decode_hex(&ZIP_244_VECTORS[start..end])
+}
+
+/// Returns a path in the temporary directory that is unique to this process.
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("zcashd-{}-{}", std::process::id(), name))
+}
+
 #[test]
 fn sapling_generators() {
     struct SaplingGenerators {
@@ -0,0 +1,90 @@
+use zcash_address::Network;
+use zcash_primitives::{
+    consensus::{BlockHeight, NetworkUpgrade, Parameters, ZIP212_GRACE_PERIOD},
+    sapling::note_encryption::plaintext_version_is_valid,
+};
+
+use crate::network::{network_params, params_for, select_params, ZcashdParams};
+
+/// Regtest with every upgrade active from height 1, except for the overridden NU5.
+const REGTEST_HEIGHTS: [i32; 6] = [1, 1, 1, 1, 1, 200];
+
+#[test]
+fn network_params_default_to_mainnet() {
+    // No test installs the global parameters, as they can only be installed once.
+    assert_eq!(network_params(), ZcashdParams::mainnet());
+    assert_eq!(params_for(Network::Main), Some(ZcashdParams::mainnet()));
+    assert_eq!(params_for(Network::Test), Some(ZcashdParams::testnet()));
+    assert_eq!(params_for(Network::Regtest), None);
+}
+
+#[test]
+fn zcashd_heights_are_parsed() {
+    assert_eq!(
+        ZcashdParams::from_zcashd_heights(Network::Regtest, &REGTEST_HEIGHTS[1..]),
+        None
+    );
+    assert_eq!(
+        ZcashdParams::from_zcashd_heights(Network::Regtest, &[1; 7]),
+        None
+    );
+
+    let params = ZcashdParams::from_zcashd_heights(Network::Regtest, &REGTEST_HEIGHTS).unwrap();
+    assert_eq!(params.network(), Network::Regtest);
+    assert_eq!(
+        params.activation_height(NetworkUpgrade::Nu5),
+        Some(BlockHeight::from_u32(200))
+    );
+    assert_eq!(params.hrp_sapling_payment_address(), "zregtestsapling");
+
+    // A negative height never activates.
+    let params = ZcashdParams::from_zcashd_heights(Network::Regtest, &[1, 1, 1, 1, 1, -1]).unwrap();
+    assert_eq!(params.activation_height(NetworkUpgrade::Nu5), None);
+}
+
+#[test]
+fn installed_params_are_only_used_for_their_network() {
+    let regtest = ZcashdParams::from_zcashd_heights(Network::Regtest, &REGTEST_HEIGHTS).unwrap();
+    assert_eq!(
+        select_params(Some(&regtest), Network::Regtest),
+        Some(regtest)
+    );
+    assert_eq!(
+        select_params(Some(&regtest), Network::Main),
+        Some(ZcashdParams::mainnet())
+    );
+    assert_eq!(
+        select_params(Some(&regtest), Network::Test),
+        Some(ZcashdParams::testnet())
+    );
+
+    let testnet = ZcashdParams::testnet();
+    assert_eq!(select_params(Some(&testnet), Network::Test), Some(testnet));
+    assert_eq!(select_params(Some(&testnet), Network::Regtest), None);
+    assert_eq!(select_params(None, Network::Regtest), None);
+}
+
+#[test]
+fn zip212_grace_period_follows_activation_heights() {
+    let canopy = 100;
+    let params = ZcashdParams::new(
+        Network::Regtest,
+        [1, 1, 1, 1, canopy, 150].map(|h| Some(BlockHeight::from_u32(h))),
+    );
+    let valid = |height: u32, leadbyte| {
+        plaintext_version_is_valid(&params, BlockHeight::from_u32(height), leadbyte)
+    };
+
+    assert!(valid(canopy - 1, 0x01));
+    assert!(!valid(canopy - 1, 0x02));
+    assert!(valid(canopy, 0x01));
+    assert!(valid(canopy, 0x02));
+    assert!(valid(canopy + ZIP212_GRACE_PERIOD - 1, 0x01));
+    assert!(!valid(canopy + ZIP212_GRACE_PERIOD, 0x01));
+    assert!(valid(canopy + ZIP212_GRACE_PERIOD, 0x02));
+
+    // Upgrades that never activate are reported as such.
+    assert!(params.is_nu_active(NetworkUpgrade::Nu5, BlockHeight::from_u32(150)));
+    let params = ZcashdParams::new(Network::Regtest, [None; 6]);
+    assert_eq!(params.activation_height(NetworkUpgrade::Nu5), None);
+}
@@ -0,0 +1,61 @@
+use orchard::circuit::VerifyingKey;
+use zcash_primitives::transaction::components::orchard as orchard_serialization;
+
+use crate::orchard_ffi::{
+    orchard_batch_add_bundle, orchard_batch_validate, orchard_batch_validation_free,
+    orchard_batch_validation_init,
+};
+use crate::test_orchard_ffi::generate_orchard_bundle;
+
+#[test]
+fn generated_bundles_round_trip() {
+    for num_actions in [1, 2, 5, 30] {
+        let bytes = generate_orchard_bundle([1; 32], num_actions, false).unwrap();
+        let bundle = orchard_serialization::read_v5_bundle(&bytes[..])
+            .unwrap()
+            .unwrap();
+        assert_eq!(bundle.actions().len(), num_actions);
+
+        let mut encoded = vec![];
+        orchard_serialization::write_v5_bundle(Some(&bundle), &mut encoded).unwrap();
+        assert_eq!(encoded, bytes);
+
+        assert_eq!(
+            generate_orchard_bundle([1; 32], num_actions, false),
+            Some(bytes.clone())
+        );
+        assert_ne!(
+            generate_orchard_bundle([2; 32], num_actions, false),
+            Some(bytes)
+        );
+    }
+
+    assert_eq!(generate_orchard_bundle([1; 32], 0, false), None);
+    assert_eq!(generate_orchard_bundle([1; 32], 1, true), None);
+}
+
+#[test]
+fn proven_bundles_validate() {
+    let seed = [3; 32];
+    let bytes = generate_orchard_bundle(seed, 2, true).unwrap();
+    let bundle = orchard_serialization::read_v5_bundle(&bytes[..])
+        .unwrap()
+        .unwrap();
+    assert_eq!(bundle.actions().len(), 2);
+    assert!(bundle.verify_proof(&VerifyingKey::build()).is_ok());
+
+    // The signatures are over the seed as the sighash.
+    for (sighash, valid) in [(seed, true), ([4; 32], false)] {
+        let batch = orchard_batch_validation_init();
+        orchard_batch_add_bundle(batch, &bundle, &sighash);
+        assert_eq!(orchard_batch_validate(batch), valid);
+        orchard_batch_validation_free(batch);
+    }
+
+    // The placeholder proofs and signatures of unproven bundles don't validate.
+    let bytes = generate_orchard_bundle(seed, 2, false).unwrap();
+    let bundle = orchard_serialization::read_v5_bundle(&bytes[..])
+        .unwrap()
+        .unwrap();
+    assert!(bundle.verify_proof(&VerifyingKey::build()).is_err());
+}
@@ -0,0 +1,48 @@
+use incrementalmerkletree::Hashable;
+use orchard::{
+    builder::Builder, bundle::Flags, circuit::VerifyingKey, tree::MerkleHashOrchard,
+    value::NoteValue,
+};
+use rand_core::OsRng;
+
+use crate::builder_ffi::{on_proving_pool, orchard_proving_threads, prove_and_sign};
+use crate::test_orchard_ffi::PROVING_KEY;
+use crate::test_wallet_ffi::random_address;
+
+#[test]
+fn proofs_are_created_on_sized_pools() {
+    let vk = VerifyingKey::build();
+
+    for num_threads in [1, 4] {
+        assert_eq!(
+            on_proving_pool(num_threads, rayon::current_num_threads),
+            num_threads
+        );
+
+        let mut builder = Builder::new(
+            Flags::from_parts(true, true),
+            MerkleHashOrchard::empty_root(32.into()).into(),
+        );
+        builder
+            .add_recipient(
+                None,
+                random_address([5; 32]),
+                NoteValue::from_raw(1000),
+                None,
+            )
+            .unwrap();
+        let bundle = builder.build(OsRng).unwrap();
+        assert_eq!(bundle.actions().len(), 2);
+
+        let bundle =
+            prove_and_sign(bundle, &PROVING_KEY, &[], [6; 32], num_threads, OsRng).unwrap();
+        assert!(bundle.verify_proof(&vk).is_ok());
+    }
+
+    // Builds that don't request a number of threads use the default pool.
+    assert_eq!(
+        on_proving_pool(0, rayon::current_num_threads),
+        orchard_proving_threads()
+    );
+    assert!(orchard_proving_threads() >= 1);
+}
@@ -0,0 +1,97 @@
+use group::Curve;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use zcash_primitives::{
+    merkle_tree::{HashSer, MerklePath},
+    sapling::{
+        self, merkle_hash,
+        pedersen_hash::{pedersen_hash, Personalization},
+    },
+};
+
+use crate::pedersen_hash::{merkle_hash_batch, merkle_path_roots, pedersen_hash_batch};
+
+/// Returns 32 random bytes that encode a BLS12-381 scalar, as note commitment tree
+/// nodes do.
+fn random_node(rng: &mut StdRng) -> [u8; 32] {
+    let mut node: [u8; 32] = rng.gen();
+    node[31] &= 0x3f;
+    node
+}
+
+fn node_bytes(node: &sapling::Node) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    node.write(&mut bytes[..]).unwrap();
+    bytes
+}
+
+#[test]
+fn pedersen_hash_batch_matches_scalar() {
+    let mut rng = StdRng::seed_from_u64(0);
+    let inputs: Vec<_> = (0..10_000)
+        .map(|_| {
+            let personalization = if rng.gen() {
+                Personalization::NoteCommitment
+            } else {
+                Personalization::MerkleTree(rng.gen_range(0..63))
+            };
+            let len = rng.gen_range(0..=600);
+            let bits: Vec<bool> = (0..len).map(|_| rng.gen()).collect();
+            (personalization, bits)
+        })
+        .collect();
+
+    let batched = pedersen_hash_batch(&inputs);
+    assert_eq!(batched.len(), inputs.len());
+    for ((personalization, bits), hash) in inputs.iter().zip(batched) {
+        let expected =
+            jubjub::ExtendedPoint::from(pedersen_hash(*personalization, bits.iter().copied()))
+                .to_affine()
+                .get_u()
+                .to_bytes();
+        assert_eq!(hash, expected);
+    }
+
+    // An empty batch has no hashes.
+    assert!(pedersen_hash_batch::<Vec<bool>>(&[]).is_empty());
+}
+
+#[test]
+fn merkle_hash_batch_matches_scalar() {
+    let mut rng = StdRng::seed_from_u64(1);
+    let inputs: Vec<_> = (0..1000)
+        .map(|_| {
+            (
+                rng.gen_range(0..63),
+                random_node(&mut rng),
+                random_node(&mut rng),
+            )
+        })
+        .collect();
+
+    for ((depth, lhs, rhs), hash) in inputs.iter().zip(merkle_hash_batch(&inputs)) {
+        assert_eq!(hash, merkle_hash(*depth, lhs, rhs));
+    }
+}
+
+#[test]
+fn merkle_path_roots_match_scalar() {
+    let mut rng = StdRng::seed_from_u64(2);
+    // Paths of different lengths are walked together.
+    let inputs: Vec<_> = (0..100)
+        .map(|_| {
+            let depth = rng.gen_range(0..=32);
+            let auth_path = (0..depth)
+                .map(|_| (sapling::Node::new(random_node(&mut rng)), rng.gen()))
+                .collect();
+            let position = rng.gen_range(0..(1u64 << 32));
+            (
+                random_node(&mut rng),
+                MerklePath::from_path(auth_path, position),
+            )
+        })
+        .collect();
+
+    for ((leaf, path), root) in inputs.iter().zip(merkle_path_roots(&inputs)) {
+        assert_eq!(root, node_bytes(&path.root(sapling::Node::new(*leaf))));
+    }
+}
@@ -0,0 +1,173 @@
+use std::num::NonZeroU32;
+use std::ptr;
+
+use orchard::{
+    keys::{FullViewingKey, Scope, SpendingKey},
+    Address,
+};
+use rand_chacha::ChaCha20Rng;
+use rand_core::{Error, RngCore, SeedableRng};
+use zcash_address::Network;
+
+use crate::{
+    builder_ffi::{orchard_builder_add_recipient, orchard_builder_build, orchard_builder_new},
+    ed25519::ed25519_generate_keypair,
+    fault_injection::test_inject_failure,
+    librustzcash_getrandom, librustzcash_rng_selfcheck, librustzcash_sapling_generate_r,
+    rng::{clear_deterministic_rng, set_deterministic_rng, RandomSource, RANDOM_SOURCE},
+};
+
+fn failing(_: &mut [u8]) -> Result<(), Error> {
+    Err(NonZeroU32::new(Error::CUSTOM_START).unwrap().into())
+}
+
+fn zeroes(buf: &mut [u8]) -> Result<(), Error> {
+    buf.fill(0);
+    Ok(())
+}
+
+fn constant(buf: &mut [u8]) -> Result<(), Error> {
+    buf.fill(0x42);
+    Ok(())
+}
+
+/// Runs `f` with the randomness on this thread drawn from `source`.
+fn with_source<T>(source: RandomSource, f: impl FnOnce() -> T) -> T {
+    RANDOM_SOURCE.with(|cell| cell.set(Some(source)));
+    let res = f();
+    RANDOM_SOURCE.with(|cell| cell.set(None));
+    res
+}
+
+#[test]
+fn rng_failures_are_reported() {
+    let mut buf = [0; 32];
+    assert!(librustzcash_getrandom(buf.as_mut_ptr(), buf.len()));
+    assert!(librustzcash_sapling_generate_r(&mut buf));
+    let (mut sk, mut vk) = ([0; 32], [0; 32]);
+    assert!(ed25519_generate_keypair(&mut sk, &mut vk));
+
+    with_source(failing, || {
+        assert!(!librustzcash_getrandom(buf.as_mut_ptr(), buf.len()));
+        assert!(!librustzcash_sapling_generate_r(&mut buf));
+        assert!(!ed25519_generate_keypair(&mut sk, &mut vk));
+    });
+
+    // The C++ tests simulate failures through the fault site instead.
+    assert!(test_inject_failure("randomness", 1));
+    assert!(!librustzcash_getrandom(buf.as_mut_ptr(), buf.len()));
+    assert!(librustzcash_getrandom(buf.as_mut_ptr(), buf.len()));
+}
+
+#[test]
+fn rng_selfcheck_detects_broken_randomness() {
+    assert!(librustzcash_rng_selfcheck());
+    assert!(!with_source(failing, librustzcash_rng_selfcheck));
+    assert!(!with_source(zeroes, librustzcash_rng_selfcheck));
+    assert!(!with_source(constant, librustzcash_rng_selfcheck));
+}
+
+/// Builds an Orchard bundle with one output to `recipient`, and returns the parts of
+/// each action that depend on the builder's randomness.
+fn build_orchard_bundle(recipient: &Address) -> Vec<Vec<u8>> {
+    let builder = orchard_builder_new(true, true, ptr::null());
+    assert!(orchard_builder_add_recipient(
+        builder,
+        ptr::null(),
+        recipient,
+        5000,
+        ptr::null(),
+    ));
+    let bundle = orchard_builder_build(builder);
+    assert!(!bundle.is_null());
+    let bundle = unsafe { Box::from_raw(bundle) };
+
+    bundle
+        .actions()
+        .iter()
+        .map(|action| {
+            let mut bytes = action.nullifier().to_bytes().to_vec();
+            bytes.extend_from_slice(&action.cmx().to_bytes());
+            bytes.extend_from_slice(&action.cv_net().to_bytes());
+            bytes.extend_from_slice(&action.encrypted_note().enc_ciphertext);
+            bytes
+        })
+        .collect()
+}
+
+/// Restores the operating system's randomness when dropped, even if the test panics.
+struct DeterministicRngGuard;
+
+impl Drop for DeterministicRngGuard {
+    fn drop(&mut self) {
+        clear_deterministic_rng();
+    }
+}
+
+fn getrandom_32() -> [u8; 32] {
+    let mut buf = [0; 32];
+    assert!(librustzcash_getrandom(buf.as_mut_ptr(), buf.len()));
+    buf
+}
+
+#[test]
+fn deterministic_rng_reproduces_transactions() {
+    let _guard = DeterministicRngGuard;
+    let sk = SpendingKey::from_bytes([7; 32]).unwrap();
+    let recipient = FullViewingKey::from(&sk).address_at(0u32, Scope::External);
+
+    assert!(!set_deterministic_rng(Network::Main, [1; 32]));
+    assert!(!set_deterministic_rng(Network::Test, [1; 32]));
+
+    // The calling thread draws the first ChaCha20 stream of the seed, which is the
+    // RFC 8439 keystream for the seed as a key with a zero nonce.
+    assert!(set_deterministic_rng(Network::Regtest, [1; 32]));
+    assert_eq!(
+        getrandom_32(),
+        [
+            0x02, 0x3f, 0x37, 0x20, 0x3a, 0x24, 0x76, 0xc4, 0x25, 0x66, 0xa6, 0x1c, 0xc5, 0x5c,
+            0x3c, 0xa8, 0x75, 0xdb, 0xb4, 0xcc, 0x41, 0xc0, 0xde, 0xb7, 0x89, 0xf8, 0xe7, 0xbf,
+            0x88, 0x18, 0x36, 0x38,
+        ]
+    );
+    assert_eq!(
+        getrandom_32(),
+        [
+            0x1e, 0xcc, 0x36, 0x86, 0xb6, 0x0e, 0xe3, 0xb8, 0x4b, 0x6c, 0x7d, 0x32, 0x1d, 0x70,
+            0xd5, 0xc0, 0x6e, 0x9d, 0xac, 0x63, 0xa4, 0xd0, 0xa7, 0x9d, 0x73, 0x1b, 0x17, 0xc0,
+            0xd0, 0x4d, 0x03, 0x0d,
+        ]
+    );
+
+    // Other threads, such as the tests running in parallel with this one, don't draw
+    // the seed's second stream but keep the operating system's randomness.
+    let mut second_stream = ChaCha20Rng::from_seed([1; 32]);
+    second_stream.set_stream(1);
+    let mut unexpected = [0; 32];
+    second_stream.fill_bytes(&mut unexpected);
+    assert_ne!(std::thread::spawn(getrandom_32).join().unwrap(), unexpected);
+
+    // Setting the seed restarts this thread's stream, so the same bundle is built.
+    assert!(set_deterministic_rng(Network::Regtest, [1; 32]));
+    let first = build_orchard_bundle(&recipient);
+    assert!(set_deterministic_rng(Network::Regtest, [1; 32]));
+    assert_eq!(build_orchard_bundle(&recipient), first);
+
+    assert!(set_deterministic_rng(Network::Regtest, [2; 32]));
+    assert_eq!(
+        getrandom_32(),
+        [
+            0xf6, 0xa1, 0x2c, 0xa8, 0xff, 0xc3, 0x0a, 0x66, 0xca, 0x14, 0x0c, 0xcc, 0x72, 0x76,
+            0x33, 0x61, 0x15, 0x81, 0x93, 0x61, 0x18, 0x6d, 0x3f, 0x53, 0x5d, 0xd9, 0x9f, 0x8e,
+            0xaa, 0xca, 0x8f, 0xce,
+        ]
+    );
+    assert_ne!(build_orchard_bundle(&recipient), first);
+
+    // Once cleared, the operating system's randomness is used again.
+    clear_deterministic_rng();
+    assert!(set_deterministic_rng(Network::Regtest, [1; 32]));
+    let seeded = getrandom_32();
+    clear_deterministic_rng();
+    assert_ne!(getrandom_32(), seeded);
+}
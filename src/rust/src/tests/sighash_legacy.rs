@@ -0,0 +1,227 @@
+use super::{decode_digest, decode_hex};
+use crate::sighash_legacy::{sighash_legacy, LegacySighashError, SIGHASH_ONE};
+use crate::transaction_encoding::{EncodingError, Violation};
+use crate::transaction_ffi::zcash_transaction_sprout_signature_digest;
+
+const SIGHASH_VECTORS: &str = include_str!("../../../test/data/sighash.json");
+const TX_VALID: &str = include_str!("../../../test/data/tx_valid.json");
+
+/// A coinbase transaction at height 621415.
+const V4_COINBASE: &str = include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt");
+
+/// Returns the transaction of the `index`th test case in `tx_valid.json`.
+fn tx_valid(index: usize) -> Vec<u8> {
+    TX_VALID
+        .split('"')
+        .filter(|s| s.starts_with("01000000"))
+        .nth(index)
+        .map(decode_hex)
+        .unwrap()
+}
+
+#[test]
+fn sighash_vectors() {
+    // These were generated by `sighash_tests`, which uses the original `SignatureHash`
+    // for transactions that are not overwintered. The transactions have random versions,
+    // and the vectors random script codes, inputs, and hash types.
+    let mut checked = 0;
+    for line in SIGHASH_VECTORS
+        .lines()
+        .filter(|line| line.trim_start().starts_with("[\""))
+        .skip(1)
+    {
+        let fields: Vec<_> = line
+            .trim()
+            .trim_start_matches('[')
+            .trim_end_matches(',')
+            .trim_end_matches(']')
+            .split(", ")
+            .map(|field| field.trim_matches('"'))
+            .collect();
+        let tx = decode_hex(fields[0]);
+        let script_code = decode_hex(fields[1]);
+        let input_index = fields[2].parse().unwrap();
+        let hash_type = fields[3].parse().unwrap();
+        let sighash = sighash_legacy(&tx, hash_type, input_index, &script_code);
+
+        if tx[3] >> 7 == 1 {
+            assert_eq!(sighash, Err(LegacySighashError::Overwintered));
+        } else {
+            assert_eq!(sighash, Ok(decode_digest(fields[5])));
+            checked += 1;
+        }
+    }
+    assert!(checked > 200);
+}
+
+#[test]
+fn historical_signatures() {
+    // Inputs of transactions from `tx_valid.json`, several of them from Bitcoin's
+    // mainnet, and the digests that their ECDSA signatures verify against.
+    let p2pk = "21035e7f0d4d0841bcd56c39337ed086b1a633ee770c1ffdd94ac552a95ac2ce0efcac";
+    let cases = [
+        // c99c49da, which is signed with hash type 0.
+        (
+            0,
+            0,
+            "76a914dc44b1164188067c3a32d4780f5996fa14a4f2d988ac",
+            0x00,
+            "11743b220e9e24e89abd4ff124a2740531fe7d7f9b4e26de14710a532fd543e2",
+        ),
+        // A script code ending in `OP_CHECKSIGVERIFY 1`.
+        (
+            1,
+            0,
+            "76a9145b6462475454710f3c22f5fdf0b40704c92f25c388ad51",
+            0x01,
+            "589ba1d00aa6704564845ab0646fb1e6d3274dae3a2fc9b8f06b78d731b473d1",
+        ),
+        // `SIGHASH_ALL`, then `SIGHASH_ALL | SIGHASH_ANYONECANPAY`.
+        (
+            8,
+            0,
+            p2pk,
+            0x01,
+            "f69b639c5d2ee6f886701efaf4616daa84793a48d851d19434bb6a13dd6225cc",
+        ),
+        (
+            8,
+            1,
+            p2pk,
+            0x81,
+            "57f5a54d548db73fa8ef7a43d011120f9935fe792f0a0630d28ee70b4c72a7e8",
+        ),
+        // The same transaction, with the first input's sequence number changed, which
+        // the second input's signature does not commit to.
+        (
+            9,
+            0,
+            p2pk,
+            0x01,
+            "64ad392ab03fdc78d2033df8ab09c7141dc655ef3cad46aeb8f343eca9e53486",
+        ),
+        (
+            9,
+            1,
+            p2pk,
+            0x81,
+            "57f5a54d548db73fa8ef7a43d011120f9935fe792f0a0630d28ee70b4c72a7e8",
+        ),
+        // afd9c17f, whose inputs are all signed with `SIGHASH_SINGLE`.
+        (
+            10,
+            0,
+            "76a914dcf72c4fd02f5a987cf9b02f2fabfcac3341a87d88ac",
+            0x03,
+            "465f0318f9801d56aeb1737d1e9af8e27d841a493b11494a9283477a1fdccc1e",
+        ),
+        (
+            10,
+            1,
+            "76a914dcf72c4fd02f5a987cf9b02f2fabfcac3341a87d88ac",
+            0x03,
+            "3b8eee7ff2be39a1d7a69cfdfda487985caa090f33858a60de0b4df67ae84319",
+        ),
+        (
+            10,
+            2,
+            "76a914dcf72c4fd02f5a987cf9b02f2fabfcac3341a87d88ac",
+            0x03,
+            "d694785005d3292d4771695034ee3f07b4dc8a3bacfdb0ecf221a7a68d061f1c",
+        ),
+    ];
+
+    for (tx, input_index, script_code, hash_type, expected) in cases.iter() {
+        let tx = tx_valid(*tx);
+        let script_code = decode_hex(script_code);
+        let expected = decode_hex(expected);
+        assert_eq!(
+            sighash_legacy(&tx, *hash_type, *input_index, &script_code).unwrap()[..],
+            expected[..]
+        );
+
+        let mut sighash = [0; 32];
+        assert!(zcash_transaction_sprout_signature_digest(
+            tx.as_ptr(),
+            tx.len(),
+            *hash_type,
+            *input_index,
+            script_code.as_ptr(),
+            script_code.len(),
+            &mut sighash,
+        ));
+        assert_eq!(sighash[..], expected[..]);
+    }
+}
+
+#[test]
+fn sighash_one() {
+    // A transaction with two inputs and one output.
+    let tx = tx_valid(8);
+    let script_code = decode_hex("51");
+    assert_ne!(sighash_legacy(&tx, 0x03, 0, &script_code), Ok(SIGHASH_ONE));
+    for hash_type in [0x03, 0x83, 0x23] {
+        assert_eq!(
+            sighash_legacy(&tx, hash_type, 1, &script_code),
+            Ok(SIGHASH_ONE)
+        );
+    }
+    for hash_type in [0x01, 0x02, 0x03, 0x81] {
+        assert_eq!(
+            sighash_legacy(&tx, hash_type, 2, &script_code),
+            Ok(SIGHASH_ONE)
+        );
+    }
+
+    let mut sighash = [0; 32];
+    assert!(zcash_transaction_sprout_signature_digest(
+        tx.as_ptr(),
+        tx.len(),
+        0x03,
+        1,
+        script_code.as_ptr(),
+        script_code.len(),
+        &mut sighash,
+    ));
+    assert_eq!(
+        sighash,
+        decode_digest("0000000000000000000000000000000000000000000000000000000000000001")
+    );
+}
+
+#[test]
+fn rejects_invalid_transactions() {
+    let coinbase = decode_hex(V4_COINBASE);
+    assert_eq!(
+        sighash_legacy(&coinbase, 0x01, 0, &[]),
+        Err(LegacySighashError::Overwintered)
+    );
+
+    let tx = tx_valid(0);
+    assert_eq!(
+        sighash_legacy(&tx[..100], 0x01, 0, &[]),
+        Err(LegacySighashError::NonCanonical(EncodingError {
+            offset: 42,
+            field: Some("vin[0].scriptSig".into()),
+            violation: Violation::Truncated,
+        }))
+    );
+    let mut extended = tx;
+    extended.push(0);
+    assert_eq!(
+        sighash_legacy(&extended, 0x01, 0, &[]),
+        Err(LegacySighashError::TrailingBytes(1))
+    );
+
+    let mut sighash = [0; 32];
+    assert!(!zcash_transaction_sprout_signature_digest(
+        coinbase.as_ptr(),
+        coinbase.len(),
+        0x01,
+        0,
+        std::ptr::null(),
+        0,
+        &mut sighash,
+    ));
+    assert_eq!(sighash, [0; 32]);
+}
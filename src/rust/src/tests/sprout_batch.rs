@@ -0,0 +1,115 @@
+use bellman::{
+    groth16::{create_random_proof, generate_random_parameters, Parameters},
+    Circuit, ConstraintSystem, SynthesisError,
+};
+use bls12_381::{Bls12, Scalar};
+use rand_core::OsRng;
+
+use crate::sprout_ffi::{
+    h_sig, sprout_batch_add_joinsplit, sprout_batch_first_failure, sprout_batch_validation_free,
+    sprout_batch_validation_init, JoinSplitInputs, SproutBatchValidator,
+};
+
+/// Proves knowledge of a square root of the public input.
+struct Square {
+    x: Option<Scalar>,
+}
+
+impl Circuit<Scalar> for Square {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let x = cs.alloc(|| "x", || self.x.ok_or(SynthesisError::AssignmentMissing))?;
+        let y = cs.alloc_input(
+            || "y",
+            || {
+                self.x
+                    .map(|x| x.square())
+                    .ok_or(SynthesisError::AssignmentMissing)
+            },
+        )?;
+        cs.enforce(|| "x * x = y", |lc| lc + x, |lc| lc + x, |lc| lc + y);
+        Ok(())
+    }
+}
+
+#[test]
+fn batch_attributes_invalid_proof() {
+    let params: Parameters<Bls12> =
+        generate_random_parameters(Square { x: None }, &mut OsRng).unwrap();
+
+    let mut batch = SproutBatchValidator::new();
+    assert!(batch.validate_with(&params.vk));
+    assert_eq!(batch.first_failure(), None);
+
+    for i in 0..7u64 {
+        let x = Scalar::from(i + 2);
+        let proof = create_random_proof(Square { x: Some(x) }, &params, &mut OsRng).unwrap();
+        // The proof at position 5 is checked against the wrong input.
+        let y = if i == 5 { x } else { x.square() };
+        batch.queue(proof, vec![y]);
+    }
+    assert!(!batch.validate_with(&params.vk));
+    assert_eq!(batch.first_failure(), Some(5));
+
+    let mut valid = SproutBatchValidator::new();
+    let x = Scalar::from(2);
+    let proof = create_random_proof(Square { x: Some(x) }, &params, &mut OsRng).unwrap();
+    valid.queue(proof, vec![x.square()]);
+    assert!(valid.validate_with(&params.vk));
+    assert_eq!(valid.first_failure(), None);
+}
+
+#[test]
+fn batch_rejects_invalid_proof_encoding() {
+    let batch = sprout_batch_validation_init();
+    let field = [0; 32];
+    let add = |proof: &[u8; 192]| {
+        sprout_batch_add_joinsplit(
+            batch, proof, &field, &field, &field, &field, &field, &field, &field, &field, 0, 0,
+            &field,
+        )
+    };
+
+    // The identity encodings of G1 and G2 are not valid proof elements.
+    let mut proof = [0; 192];
+    proof[0] = 0xc0;
+    proof[48] = 0xc0;
+    proof[144] = 0xc0;
+    assert!(!add(&proof));
+    assert!(!add(&[0xff; 192]));
+    assert_eq!(sprout_batch_first_failure(batch), u32::MAX);
+
+    sprout_batch_validation_free(batch);
+}
+
+#[test]
+fn joinsplit_public_inputs() {
+    // From the `h_sig` test vectors in `test_joinsplit.cpp`.
+    assert_eq!(
+        h_sig(&[0x61; 32], [&[0x62; 32], &[0x63; 32]], &[0x64; 32]),
+        [
+            0x13, 0x81, 0x5b, 0x0a, 0xb9, 0x58, 0xeb, 0x4c, 0x82, 0xa1, 0xb8, 0x4c, 0x4f, 0xe4,
+            0x61, 0x0b, 0xa0, 0xf8, 0x00, 0xf9, 0x4a, 0x6b, 0x75, 0x55, 0xc0, 0x29, 0xa3, 0x1f,
+            0x9f, 0xa6, 0xcb, 0xa8,
+        ]
+    );
+
+    let inputs = JoinSplitInputs {
+        rt: [0; 32],
+        h_sig: [0; 32],
+        nullifiers: [[0; 32]; 2],
+        macs: [[0; 32]; 2],
+        commitments: [[0; 32]; 2],
+        vpub_old: 0,
+        vpub_new: 1,
+    };
+    // The 2176 input bits pack into 254-bit field elements.
+    let public_inputs = inputs.public_inputs();
+    assert_eq!(public_inputs.len(), 9);
+    // The bytes are packed with the first bit of each byte as its most significant, so
+    // `vpub_new = 1` sets bit 2176 - 64 + 7 of the input, in the last element.
+    assert_eq!(public_inputs[..8], [Scalar::zero(); 8]);
+    assert_eq!(
+        public_inputs[8],
+        Scalar::from(2).pow_vartime(&[2176 - 64 + 7 - 8 * 254, 0, 0, 0])
+    );
+}
@@ -0,0 +1,313 @@
+use std::cell::RefCell;
+
+use rand_core::{OsRng, RngCore};
+use zcash_proofs::sprout::GROTH_PROOF_SIZE;
+
+use crate::sprout_builder::{
+    Error, JoinSplitProver, JsDescription, ProofWitness, SproutBuilder, SproutNote,
+    JS_DESCRIPTION_SIZE,
+};
+use crate::sprout_ffi::{
+    h_sig, mac, migration_amount, nullifier, output_rho, parse_plaintext, paying_key,
+    receiving_key, transmission_key, try_decrypt,
+};
+use crate::sprout_tree::{SproutTree, SproutWitness, SPROUT_TREE_DEPTH};
+
+const JOINSPLIT_PUB_KEY: [u8; 32] = [7; 32];
+
+/// Records the witness of each JoinSplit instead of proving it. Creating real
+/// proofs needs the Sprout parameters, so the tests check the statement of the
+/// JoinSplit circuit against each witness in `check_statement` instead.
+#[derive(Default)]
+struct RecordingProver(RefCell<Vec<ProofWitness>>);
+
+impl JoinSplitProver for RecordingProver {
+    fn prove(
+        &self,
+        witness: &ProofWitness,
+        _: &mut dyn RngCore,
+    ) -> Result<[u8; GROTH_PROOF_SIZE], Error> {
+        self.0.borrow_mut().push(witness.clone());
+        Ok([0; GROTH_PROOF_SIZE])
+    }
+}
+
+/// Checks that `witness` satisfies the JoinSplit circuit for the public inputs of
+/// `js`, as its proof would.
+fn check_statement(js: &JsDescription, witness: &ProofWitness) {
+    assert_eq!(witness.rt, js.anchor);
+    assert_eq!(witness.vpub_old, js.vpub_old);
+    assert_eq!(witness.vpub_new, js.vpub_new);
+    assert_eq!(
+        witness.h_sig,
+        h_sig(
+            &js.random_seed,
+            [&js.nullifiers[0], &js.nullifiers[1]],
+            &JOINSPLIT_PUB_KEY
+        )
+    );
+
+    let mut lhs = js.vpub_old;
+    for (i, input) in witness.inputs.iter().enumerate() {
+        assert_eq!(input.note.a_pk, paying_key(&input.a_sk));
+        // Only notes with value need to be in the tree.
+        if input.note.value != 0 {
+            assert_eq!(input.path.root(&input.note.cm()), js.anchor);
+        }
+        assert_eq!(js.nullifiers[i], nullifier(&input.a_sk, &input.note.rho));
+        assert_eq!(js.macs[i], mac(&input.a_sk, i as u8, &witness.h_sig));
+        lhs += input.note.value;
+    }
+
+    let mut rhs = js.vpub_new;
+    for (i, note) in witness.outputs.iter().enumerate() {
+        assert_eq!(note.rho, output_rho(&witness.phi, i as u8, &witness.h_sig));
+        assert_eq!(js.commitments[i], note.cm());
+        rhs += note.value;
+    }
+    assert_eq!(lhs, rhs);
+}
+
+/// Returns the values of the outputs of `js` that can be decrypted with `a_sk`.
+fn decrypt_outputs(js: &JsDescription, a_sk: &[u8; 32]) -> Vec<u64> {
+    let h_sig = h_sig(
+        &js.random_seed,
+        [&js.nullifiers[0], &js.nullifiers[1]],
+        &JOINSPLIT_PUB_KEY,
+    );
+    (0..2)
+        .filter_map(|i| {
+            try_decrypt(
+                &receiving_key(a_sk),
+                &js.ephemeral_key,
+                &h_sig,
+                &js.ciphertexts[i],
+                i as u8,
+            )
+        })
+        .map(|plaintext| parse_plaintext(&plaintext).unwrap().value)
+        .collect()
+}
+
+struct Spendable {
+    a_sk: [u8; 32],
+    note: SproutNote,
+    witness: SproutWitness<SPROUT_TREE_DEPTH>,
+}
+
+/// Returns notes with the given values, and the tree that they were appended to
+/// between other commitments, with each witness up to date with the tree.
+fn notes_in_tree(values: &[u64]) -> (Vec<Spendable>, SproutTree<SPROUT_TREE_DEPTH>) {
+    let mut rng = OsRng;
+    let mut random = || {
+        let mut bytes = [0; 32];
+        rng.fill_bytes(&mut bytes);
+        bytes
+    };
+
+    let mut tree = SproutTree::default();
+    let mut notes: Vec<Spendable> = vec![];
+    for &value in values {
+        let filler = random();
+        tree.append(filler);
+        for spendable in &mut notes {
+            spendable.witness.append(filler);
+        }
+
+        let mut a_sk = random();
+        a_sk[0] &= 0x0f;
+        let note = SproutNote {
+            a_pk: paying_key(&a_sk),
+            value,
+            rho: random(),
+            r: random(),
+        };
+        tree.append(note.cm());
+        for spendable in &mut notes {
+            spendable.witness.append(note.cm());
+        }
+        notes.push(Spendable {
+            a_sk,
+            note,
+            witness: tree.witness(),
+        });
+    }
+    (notes, tree)
+}
+
+/// Adds the notes and the change of a migration transaction of `amount` (including the
+/// fee) to a builder, as the migration's `TransactionBuilder` does.
+fn migration_builder(
+    notes: &[Spendable],
+    amount: u64,
+    anchor_tree: Option<SproutTree<SPROUT_TREE_DEPTH>>,
+) -> SproutBuilder {
+    let available: u64 = notes.iter().map(|spendable| spendable.note.value).sum();
+    let mut builder = SproutBuilder::new(JOINSPLIT_PUB_KEY, anchor_tree);
+    for spendable in notes {
+        builder
+            .add_sprout_input(
+                spendable.a_sk,
+                spendable.note.clone(),
+                spendable.witness.clone(),
+            )
+            .unwrap();
+    }
+    // The change goes back to the address of the first note.
+    if available > amount {
+        let a_sk = &notes[0].a_sk;
+        builder
+            .add_output(
+                paying_key(a_sk),
+                transmission_key(&receiving_key(a_sk)),
+                available - amount,
+                None,
+            )
+            .unwrap();
+    }
+    builder
+}
+
+#[test]
+fn migration_spends_two_notes_into_vpub_new() {
+    let (notes, tree) = notes_in_tree(&[60_000_000, 45_000_000]);
+    let amount = migration_amount(105_000_000, &mut OsRng).unwrap();
+    let builder = migration_builder(&notes, amount, Some(tree.clone()));
+
+    let prover = RecordingProver::default();
+    let joinsplits = builder.build(&prover, &mut OsRng).unwrap();
+    let witnesses = prover.0.into_inner();
+
+    // Both notes fit in a single JoinSplit, which releases the migrated amount to the
+    // transparent value pool for the Sapling output and fee.
+    assert_eq!(joinsplits.len(), 1);
+    let js = &joinsplits[0];
+    check_statement(js, &witnesses[0]);
+    assert_eq!(js.anchor, tree.root());
+    assert_eq!(js.vpub_old, 0);
+    assert_eq!(js.vpub_new, amount);
+
+    // ZIP 308 amounts are at most 1 ZEC, so there is always change, which can be
+    // spent by the first note's key.
+    assert_eq!(
+        decrypt_outputs(js, &notes[0].a_sk),
+        vec![105_000_000 - amount]
+    );
+
+    let mut encoded = vec![];
+    js.write(&mut encoded).unwrap();
+    assert_eq!(encoded.len(), JS_DESCRIPTION_SIZE);
+}
+
+#[test]
+fn migration_chains_change_into_the_next_joinsplit() {
+    let (notes, tree) = notes_in_tree(&[30_000_000, 25_000_000, 50_000_000, 1_000_000]);
+    let amount = 90_000_000;
+    let builder = migration_builder(&notes, amount, Some(tree.clone()));
+
+    let prover = RecordingProver::default();
+    let joinsplits = builder.build(&prover, &mut OsRng).unwrap();
+    let witnesses = prover.0.into_inner();
+
+    // The first JoinSplit spends two notes, and each one after it spends the change
+    // of the last along with the next note.
+    assert_eq!(joinsplits.len(), 3);
+    assert_eq!(joinsplits[0].anchor, tree.root());
+    // The first pays the change of the transaction, and keeps its own change for the
+    // next JoinSplit.
+    let mut paid = decrypt_outputs(&joinsplits[0], &notes[0].a_sk);
+    paid.sort_unstable();
+    assert_eq!(paid, vec![16_000_000, 39_000_000]);
+    let mut interstitial = tree;
+    for (i, (js, witness)) in joinsplits.iter().zip(witnesses.iter()).enumerate() {
+        check_statement(js, witness);
+        if i > 0 {
+            // Chained JoinSplits are anchored to the tree including the commitments
+            // of the JoinSplits before them in the transaction.
+            for cm in &joinsplits[i - 1].commitments {
+                interstitial.append(*cm);
+            }
+            assert_eq!(js.anchor, interstitial.root());
+            let change = witness
+                .inputs
+                .iter()
+                .find(|input| input.note.a_pk == paying_key(&notes[0].a_sk))
+                .unwrap();
+            assert!(joinsplits[i - 1].commitments.contains(&change.note.cm()));
+        }
+    }
+
+    // Only the last JoinSplit releases any value.
+    assert!(joinsplits.iter().all(|js| js.vpub_old == 0));
+    assert_eq!(joinsplits[0].vpub_new, 0);
+    assert_eq!(joinsplits[1].vpub_new, 0);
+    assert_eq!(joinsplits[2].vpub_new, amount);
+}
+
+#[test]
+fn chaining_needs_the_anchor_tree() {
+    let (notes, _) = notes_in_tree(&[30_000_000, 25_000_000, 50_000_000]);
+    let builder = migration_builder(&notes, 90_000_000, None);
+    assert!(matches!(
+        builder.build(&RecordingProver::default(), &mut OsRng),
+        Err(Error::MissingAnchorTree)
+    ));
+}
+
+#[test]
+fn add_sprout_input_rejects_mismatched_notes() {
+    let (notes, _) = notes_in_tree(&[10, 20]);
+    let (other_notes, _) = notes_in_tree(&[30]);
+    let mut builder = SproutBuilder::new(JOINSPLIT_PUB_KEY, None);
+
+    // The note must be spendable with the key.
+    assert!(matches!(
+        builder.add_sprout_input(
+            notes[1].a_sk,
+            notes[0].note.clone(),
+            notes[0].witness.clone()
+        ),
+        Err(Error::WrongKey)
+    ));
+    // The witness must be to the note.
+    assert!(matches!(
+        builder.add_sprout_input(
+            notes[0].a_sk,
+            notes[0].note.clone(),
+            notes[1].witness.clone()
+        ),
+        Err(Error::WrongWitness)
+    ));
+    assert!(matches!(
+        builder.add_sprout_input(
+            notes[0].a_sk,
+            notes[0].note.clone(),
+            SproutTree::default().witness()
+        ),
+        Err(Error::EmptyWitness)
+    ));
+
+    builder
+        .add_sprout_input(
+            notes[0].a_sk,
+            notes[0].note.clone(),
+            notes[0].witness.clone(),
+        )
+        .unwrap();
+    // Every note must have the same anchor.
+    assert!(matches!(
+        builder.add_sprout_input(
+            other_notes[0].a_sk,
+            other_notes[0].note.clone(),
+            other_notes[0].witness.clone()
+        ),
+        Err(Error::AnchorMismatch)
+    ));
+    builder
+        .add_sprout_input(
+            notes[1].a_sk,
+            notes[1].note.clone(),
+            notes[1].witness.clone(),
+        )
+        .unwrap();
+}
@@ -0,0 +1,36 @@
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::sprout_ffi::migration_amount;
+
+/// Returns the ZIP 308 mantissa and exponent of `amount`, if it has that form.
+fn zip308_form(amount: u64) -> Option<(u64, u32)> {
+    (6..=8)
+        .map(|exponent| (amount / 10u64.pow(exponent), exponent))
+        .find(|(mantissa, exponent)| {
+            (1..=99).contains(mantissa) && mantissa * 10u64.pow(*exponent) == amount
+        })
+}
+
+#[test]
+fn migration_amounts_follow_zip308() {
+    let mut rng = StdRng::seed_from_u64(308);
+
+    // Below 0.01 ZEC, no amount can be chosen.
+    assert_eq!(migration_amount(0, &mut rng), None);
+    assert_eq!(migration_amount(999_999, &mut rng), None);
+    // At exactly 0.01 ZEC, the only possible amount is all of it.
+    assert_eq!(migration_amount(1_000_000, &mut rng), Some(1_000_000));
+
+    let mut exponents = [false; 3];
+    for available in [1_234_567, 50_000_000, 10_000_000_000, u64::MAX] {
+        for _ in 0..200 {
+            let amount = migration_amount(available, &mut rng).unwrap();
+            assert!(amount <= available);
+            let (_, exponent) =
+                zip308_form(amount).unwrap_or_else(|| panic!("{} is not a ZIP 308 amount", amount));
+            exponents[exponent as usize - 6] = true;
+        }
+    }
+    // Every exponent is chosen when the funds allow it.
+    assert_eq!(exponents, [true; 3]);
+}
@@ -0,0 +1,129 @@
+use crate::sprout_ffi::{
+    librustzcash_sprout_parse_plaintext, librustzcash_sprout_try_decrypt, SPROUT_CIPHERTEXT_SIZE,
+    SPROUT_MEMO_SIZE, SPROUT_PLAINTEXT_SIZE,
+};
+
+/// The ciphertexts of both outputs of a JoinSplit, in order.
+const CIPHERTEXTS: &[u8] = include_bytes!("./res/sprout_ciphertexts.dat");
+
+const RECEIVING_KEY: [u8; 32] = [
+    0x38, 0x6c, 0x17, 0x7d, 0x47, 0x8e, 0xd2, 0xc4, 0x3b, 0xd8, 0xc3, 0xe3, 0x5a, 0x8d, 0x73, 0xa3,
+    0xb3, 0x99, 0x10, 0x08, 0xdb, 0x45, 0xa0, 0x15, 0x41, 0xa0, 0x2d, 0x6a, 0x7e, 0xac, 0xcf, 0x7f,
+];
+const EPK: [u8; 32] = [
+    0x1e, 0x71, 0xc1, 0xe7, 0x75, 0x12, 0x3b, 0x26, 0x9e, 0xf0, 0xdb, 0x4d, 0xa3, 0x01, 0x3b, 0x12,
+    0xe6, 0xe7, 0x4d, 0x01, 0x1e, 0x39, 0xf3, 0xa2, 0x6b, 0x2b, 0xf6, 0x04, 0x50, 0x7c, 0x9b, 0x71,
+];
+const H_SIG: [u8; 32] = [
+    0x17, 0xc0, 0x4b, 0x07, 0x45, 0x26, 0xfa, 0xe9, 0x36, 0x32, 0x0a, 0x7e, 0xe4, 0xd5, 0xce, 0xeb,
+    0x98, 0x10, 0x61, 0x64, 0x2e, 0xfd, 0x85, 0xeb, 0xc4, 0xd1, 0xd2, 0xb5, 0x12, 0xe7, 0x82, 0xf5,
+];
+
+struct TestVector {
+    nonce_index: u8,
+    value: u64,
+    rho: [u8; 32],
+    r: [u8; 32],
+    /// The memo, before its zero padding.
+    memo_prefix: &'static [u8],
+}
+
+fn ciphertext(nonce_index: u8) -> [u8; SPROUT_CIPHERTEXT_SIZE] {
+    let mut ciphertext = [0; SPROUT_CIPHERTEXT_SIZE];
+    let start = nonce_index as usize * SPROUT_CIPHERTEXT_SIZE;
+    ciphertext.copy_from_slice(&CIPHERTEXTS[start..start + SPROUT_CIPHERTEXT_SIZE]);
+    ciphertext
+}
+
+fn try_decrypt(
+    receiving_key: &[u8; 32],
+    ciphertext: &[u8; SPROUT_CIPHERTEXT_SIZE],
+    nonce_index: u8,
+) -> Option<[u8; SPROUT_PLAINTEXT_SIZE]> {
+    let mut plaintext = [0; SPROUT_PLAINTEXT_SIZE];
+    librustzcash_sprout_try_decrypt(
+        receiving_key,
+        &EPK,
+        &H_SIG,
+        ciphertext,
+        nonce_index,
+        &mut plaintext,
+    )
+    .then(|| plaintext)
+}
+
+#[test]
+fn sprout_note_decryption() {
+    // These fixed ciphertexts pin the decryption in the Rust test suite; the
+    // `NoteEncryption.RustSproutDecryption` gtest checks it against ciphertexts
+    // produced by `ZCNoteEncryption`.
+    let test_vectors = [
+        TestVector {
+            nonce_index: 0,
+            value: 100000000,
+            rho: [
+                0x06, 0xb5, 0xf0, 0x61, 0xbf, 0xda, 0x0d, 0x39, 0xf6, 0xdd, 0x2f, 0xa5, 0xa2, 0xb3,
+                0x5b, 0x68, 0x10, 0x3f, 0xa7, 0x8d, 0xc5, 0x37, 0x29, 0xf4, 0xec, 0x68, 0x7e, 0x38,
+                0x07, 0x5b, 0x2c, 0xe7,
+            ],
+            r: [
+                0x02, 0x4b, 0xf4, 0xf2, 0x61, 0x05, 0x32, 0xef, 0x04, 0xb3, 0x96, 0x6b, 0x32, 0x2c,
+                0x5e, 0x7f, 0x75, 0x77, 0x3e, 0x17, 0xaa, 0x39, 0x8b, 0x3a, 0x55, 0xa9, 0xf8, 0xdc,
+                0x7c, 0x0d, 0xa3, 0x69,
+            ],
+            memo_prefix: &[0xf6],
+        },
+        TestVector {
+            nonce_index: 1,
+            value: 2718281828,
+            rho: [
+                0x1e, 0xba, 0x62, 0xa0, 0xee, 0xdf, 0xfd, 0x5f, 0x37, 0x0d, 0xea, 0x53, 0x5b, 0x29,
+                0xb5, 0x0e, 0x71, 0x7f, 0xd1, 0x32, 0x91, 0xb0, 0x0f, 0xc5, 0xe9, 0x5b, 0x43, 0xf3,
+                0xc2, 0xc6, 0x9f, 0x5c,
+            ],
+            r: [
+                0x6d, 0x79, 0x13, 0xb6, 0xff, 0xb2, 0x2d, 0xb8, 0x9d, 0x20, 0xa0, 0xde, 0xc3, 0x7a,
+                0x9e, 0x0f, 0xcb, 0x68, 0x95, 0x97, 0x22, 0x78, 0x36, 0xda, 0x5c, 0xd6, 0xa5, 0xfa,
+                0xa6, 0x4d, 0x59, 0x32,
+            ],
+            memo_prefix: b"Sprout migration",
+        },
+    ];
+
+    for tv in &test_vectors {
+        let ciphertext = ciphertext(tv.nonce_index);
+        let plaintext = try_decrypt(&RECEIVING_KEY, &ciphertext, tv.nonce_index)
+            .expect("the note was encrypted to the receiving key");
+
+        let (mut value, mut rho, mut r, mut memo) = (0, [0; 32], [0; 32], [0; SPROUT_MEMO_SIZE]);
+        assert!(librustzcash_sprout_parse_plaintext(
+            &plaintext, &mut value, &mut rho, &mut r, &mut memo,
+        ));
+        assert_eq!(value, tv.value);
+        assert_eq!(rho, tv.rho);
+        assert_eq!(r, tv.r);
+        let (prefix, padding) = memo.split_at(tv.memo_prefix.len());
+        assert_eq!(prefix, tv.memo_prefix);
+        assert!(padding.iter().all(|b| *b == 0));
+
+        // Each output can only be decrypted with its own KDF index.
+        assert!(try_decrypt(&RECEIVING_KEY, &ciphertext, 1 - tv.nonce_index).is_none());
+        assert!(try_decrypt(&RECEIVING_KEY, &ciphertext, 2).is_none());
+
+        let mut other_key = RECEIVING_KEY;
+        other_key[1] ^= 1;
+        assert!(try_decrypt(&other_key, &ciphertext, tv.nonce_index).is_none());
+
+        let mut tampered = ciphertext;
+        tampered[SPROUT_PLAINTEXT_SIZE] ^= 1;
+        assert!(try_decrypt(&RECEIVING_KEY, &tampered, tv.nonce_index).is_none());
+    }
+
+    // Only lead byte 0x00 is defined for Sprout.
+    let mut plaintext = try_decrypt(&RECEIVING_KEY, &ciphertext(0), 0).unwrap();
+    plaintext[0] = 0x01;
+    let (mut value, mut rho, mut r, mut memo) = (0, [0; 32], [0; 32], [0; SPROUT_MEMO_SIZE]);
+    assert!(!librustzcash_sprout_parse_plaintext(
+        &plaintext, &mut value, &mut rho, &mut r, &mut memo,
+    ));
+}
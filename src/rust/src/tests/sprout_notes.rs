@@ -0,0 +1,75 @@
+use crate::sprout_ffi::{
+    librustzcash_sprout_note_commitment, librustzcash_sprout_nullifier, note_commitment, nullifier,
+    sha256_compress,
+};
+
+// The spending key from the `NoteClass` test in `test_joinsplit.cpp`, in its serialized
+// byte order. `RHO` and `R` are the SHA-256 hashes of "rho" and "r". The
+// `Joinsplit.RustNoteCommitmentAndNullifier` gtest checks `A_PK`, `CM` and `NF` against
+// `PRF_addr_a_pk`, `SproutNote::cm` and `SproutNote::nullifier`.
+const A_SK: [u8; 32] = [
+    0x0e, 0x84, 0x16, 0x45, 0xd9, 0x16, 0xb2, 0x55, 0xc0, 0xd5, 0x24, 0x4c, 0xaa, 0xb4, 0x26, 0x6a,
+    0xad, 0xfa, 0x87, 0x01, 0xbd, 0x16, 0xfc, 0x74, 0x0f, 0x60, 0x2d, 0x68, 0x16, 0x87, 0xda, 0xf6,
+];
+const A_PK: [u8; 32] = [
+    0xb0, 0x91, 0xb2, 0x95, 0xb6, 0x3b, 0xb4, 0xe6, 0x28, 0x99, 0xab, 0xe9, 0x34, 0xa0, 0xd1, 0x89,
+    0xbc, 0x7c, 0xed, 0xb5, 0x15, 0x77, 0xa5, 0x09, 0x96, 0x2c, 0xfc, 0x2b, 0xa8, 0x85, 0x3a, 0x3e,
+];
+const VALUE: u64 = 1945813;
+const RHO: [u8; 32] = [
+    0x0d, 0xdb, 0xc6, 0xc1, 0xd8, 0x1a, 0x07, 0x8b, 0x80, 0x30, 0x40, 0xa6, 0x15, 0xf9, 0x13, 0x62,
+    0x64, 0x2e, 0x98, 0x5d, 0x27, 0x37, 0xa9, 0x45, 0x53, 0x5b, 0x86, 0xda, 0x26, 0x81, 0x95, 0x2f,
+];
+const R: [u8; 32] = [
+    0x45, 0x43, 0x49, 0xe4, 0x22, 0xf0, 0x52, 0x97, 0x19, 0x1e, 0xad, 0x13, 0xe2, 0x1d, 0x3d, 0xb5,
+    0x20, 0xe5, 0xab, 0xef, 0x52, 0x05, 0x5e, 0x49, 0x64, 0xb8, 0x2f, 0xb2, 0x13, 0xf5, 0x93, 0xa1,
+];
+const CM: [u8; 32] = [
+    0xe7, 0xd4, 0xe7, 0x2f, 0x61, 0xb0, 0x0d, 0xd7, 0x25, 0xd9, 0xc1, 0x8c, 0x93, 0xe9, 0x13, 0x88,
+    0xf0, 0xe2, 0xf8, 0xf7, 0x01, 0x66, 0x01, 0x70, 0x97, 0x55, 0x2e, 0x9c, 0x94, 0x72, 0x46, 0xbb,
+];
+const NF: [u8; 32] = [
+    0x1d, 0x68, 0x2c, 0x0e, 0x9d, 0xdb, 0xaf, 0x73, 0xac, 0x88, 0x29, 0x2b, 0x74, 0x73, 0x12, 0xaf,
+    0xbc, 0x05, 0xaa, 0x89, 0x18, 0xd9, 0x15, 0x4c, 0xae, 0xa2, 0xd0, 0xeb, 0x5c, 0xfc, 0x29, 0xcb,
+];
+
+#[test]
+fn sha256_compress_vectors() {
+    // From `sha256compress_tests.cpp`, in serialized byte order.
+    assert_eq!(
+        sha256_compress(&[0; 64]),
+        [
+            0xda, 0x56, 0x98, 0xbe, 0x17, 0xb9, 0xb4, 0x69, 0x62, 0x33, 0x57, 0x99, 0x77, 0x9f,
+            0xbe, 0xca, 0x8c, 0xe5, 0xd4, 0x91, 0xc0, 0xd2, 0x62, 0x43, 0xba, 0xfe, 0xf9, 0xea,
+            0x18, 0x37, 0xa9, 0xd8,
+        ]
+    );
+
+    // PRF^addr, which derives the paying key from the spending key.
+    let mut block = [0; 64];
+    block[..32].copy_from_slice(&A_SK);
+    block[0] |= 0xc0;
+    assert_eq!(sha256_compress(&block), A_PK);
+}
+
+#[test]
+fn note_commitment_and_nullifier() {
+    assert_eq!(note_commitment(&A_PK, VALUE, &RHO, &R), CM);
+    assert_eq!(nullifier(&A_SK, &RHO), NF);
+
+    // Every input is bound by the commitment.
+    assert_ne!(note_commitment(&A_PK, VALUE + 1, &RHO, &R), CM);
+    assert_ne!(note_commitment(&A_PK, VALUE, &R, &RHO), CM);
+
+    // The high bits of the spending key are overwritten by the domain separator.
+    let mut a_sk = A_SK;
+    a_sk[0] |= 0xf0;
+    assert_eq!(nullifier(&a_sk, &RHO), NF);
+
+    let mut cm = [0; 32];
+    librustzcash_sprout_note_commitment(&A_PK, VALUE, &RHO, &R, &mut cm);
+    assert_eq!(cm, CM);
+    let mut nf = [0; 32];
+    librustzcash_sprout_nullifier(&A_SK, &RHO, &mut nf);
+    assert_eq!(nf, NF);
+}
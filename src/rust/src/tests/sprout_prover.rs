@@ -0,0 +1,124 @@
+use std::fs::{self, File};
+use std::path::PathBuf;
+
+use bellman::{
+    groth16::{generate_random_parameters, prepare_verifying_key, verify_proof, Parameters},
+    Circuit, ConstraintSystem, SynthesisError,
+};
+use bls12_381::{Bls12, Scalar};
+use rand_core::OsRng;
+
+use super::temp_path;
+use crate::proving_scratch;
+use crate::sprout_prover::{prove, ProverMode, StreamedParameters};
+
+/// Proves knowledge of an `x` whose 16th repeated square is the public input.
+struct RepeatedSquare {
+    x: Option<Scalar>,
+}
+
+const SQUARINGS: usize = 16;
+
+impl Circuit<Scalar> for RepeatedSquare {
+    fn synthesize<CS: ConstraintSystem<Scalar>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let mut value = self.x;
+        let mut var = cs.alloc(|| "x", || value.ok_or(SynthesisError::AssignmentMissing))?;
+        for i in 0..SQUARINGS {
+            let squared = value.map(|value| value.square());
+            let squared_var = if i == SQUARINGS - 1 {
+                cs.alloc_input(|| "y", || squared.ok_or(SynthesisError::AssignmentMissing))?
+            } else {
+                cs.alloc(
+                    || format!("x^2^{}", i + 1),
+                    || squared.ok_or(SynthesisError::AssignmentMissing),
+                )?
+            };
+            cs.enforce(
+                || format!("squaring {}", i),
+                |lc| lc + var,
+                |lc| lc + var,
+                |lc| lc + squared_var,
+            );
+            value = squared;
+            var = squared_var;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn streamed_and_in_memory_proofs_verify() {
+    let params: Parameters<Bls12> =
+        generate_random_parameters(RepeatedSquare { x: None }, &mut OsRng).unwrap();
+    let path = temp_path("repeated-square.params");
+    params.write(File::create(&path).unwrap()).unwrap();
+    let pvk = prepare_verifying_key(&params.vk);
+
+    let x = Scalar::from(3);
+    let y = (0..SQUARINGS).fold(x, |value, _| value.square());
+    let mut proofs = vec![];
+    // A budget of a single byte makes every point a separate read.
+    for (mode, budget) in [
+        (ProverMode::InMemory, 0),
+        (ProverMode::Streamed, 1),
+        (ProverMode::Streamed, 64 * 1024 * 1024),
+    ] {
+        let proof = prove(
+            RepeatedSquare { x: Some(x) },
+            &path,
+            mode,
+            budget,
+            &mut OsRng,
+        )
+        .unwrap_or_else(|e| panic!("{:?} proving failed: {}", mode, e));
+        assert!(verify_proof(&pvk, &proof, &[y]).is_ok(), "{:?}", mode);
+        assert!(verify_proof(&pvk, &proof, &[x]).is_err(), "{:?}", mode);
+        proofs.push(proof);
+    }
+    // The proofs are randomized, so they can't be compared directly.
+    assert_ne!(proofs[0], proofs[1]);
+
+    // A truncated file is rejected when it is opened.
+    let len = fs::metadata(&path).unwrap().len();
+    File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_len(len - 1)
+        .unwrap();
+    assert!(StreamedParameters::open(&path, 1024).is_err());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn streamed_proofs_reuse_scratch_buffers() {
+    let params: Parameters<Bls12> =
+        generate_random_parameters(RepeatedSquare { x: None }, &mut OsRng).unwrap();
+    let path = temp_path("repeated-square-scratch.params");
+    params.write(File::create(&path).unwrap()).unwrap();
+    let pvk = prepare_verifying_key(&params.vk);
+
+    let x = Scalar::from(5);
+    let y = (0..SQUARINGS).fold(x, |value, _| value.square());
+    let before = proving_scratch::stats();
+    for _ in 0..4 {
+        let proof = prove(
+            RepeatedSquare { x: Some(x) },
+            &path,
+            ProverMode::Streamed,
+            4096,
+            &mut OsRng,
+        )
+        .unwrap();
+        assert!(verify_proof(&pvk, &proof, &[y]).is_ok());
+    }
+
+    // Every multi-exponentiation takes a buffer, and the threads that perform them
+    // return their buffers to their pools for the next.
+    let after = proving_scratch::stats();
+    assert!(after.misses > before.misses);
+    assert!(after.hits > before.hits);
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,108 @@
+use super::json_hex_strings;
+use crate::sprout_tree::{empty_root, SproutTree, SproutWitness};
+
+// The Sprout test vectors that zcashd's `merkletree.vectors` gtest checks its testing
+// tree of depth 4 against.
+const COMMITMENTS: &str = include_str!("../../../test/data/merkle_commitments.json");
+const ROOTS: &str = include_str!("../../../test/data/merkle_roots.json");
+const TREES: &str = include_str!("../../../test/data/merkle_serialization.json");
+const WITNESSES: &str = include_str!("../../../test/data/merkle_witness_serialization.json");
+const PATHS: &str = include_str!("../../../test/data/merkle_path.json");
+
+const TESTING_DEPTH: usize = 4;
+
+fn to_node(bytes: &[u8]) -> [u8; 32] {
+    let mut node = [0; 32];
+    node.copy_from_slice(bytes);
+    node
+}
+
+#[test]
+fn sprout_tree_matches_zcashd_vectors() {
+    // The commitments are parsed with `uint256S`, which reverses the hex.
+    let commitments: Vec<_> = json_hex_strings(COMMITMENTS)
+        .into_iter()
+        .map(|mut cm| {
+            cm.reverse();
+            to_node(&cm)
+        })
+        .collect();
+    let roots = json_hex_strings(ROOTS);
+    let trees = json_hex_strings(TREES);
+    let mut witness_vectors = json_hex_strings(WITNESSES).into_iter();
+    let mut path_vectors = json_hex_strings(PATHS).into_iter();
+    assert_eq!(commitments.len(), 1 << TESTING_DEPTH);
+
+    let mut tree = SproutTree::<TESTING_DEPTH>::default();
+    assert_eq!(tree.root(), empty_root(TESTING_DEPTH));
+    assert_eq!(tree.last(), None);
+
+    let mut witnesses: Vec<SproutWitness<TESTING_DEPTH>> = vec![];
+    for (i, cm) in commitments.iter().enumerate() {
+        witnesses.push(tree.witness());
+        assert!(tree.append(*cm));
+        assert_eq!(tree.size(), i as u64 + 1);
+        assert_eq!(tree.last(), Some(*cm));
+        assert_eq!(&tree.root()[..], &roots[i][..]);
+
+        let mut encoded = vec![];
+        tree.write(&mut encoded).unwrap();
+        assert_eq!(encoded, trees[i]);
+        assert_eq!(SproutTree::read(&encoded[..]).unwrap(), tree);
+
+        for (j, witness) in witnesses.iter_mut().enumerate() {
+            assert!(witness.append(*cm));
+
+            // The first witness is to the empty tree, so it has no path.
+            if j == 0 {
+                assert!(witness.path().is_none());
+                assert!(witness.element().is_none());
+            } else {
+                let path = witness.path().unwrap();
+                let mut encoded = vec![];
+                path.write(&mut encoded).unwrap();
+                assert_eq!(encoded, path_vectors.next().unwrap());
+
+                assert_eq!(witness.position(), Some(j as u64 - 1));
+                assert_eq!(witness.element(), Some(commitments[j - 1]));
+                assert_eq!(path.root(&commitments[j - 1]), tree.root());
+            }
+
+            let mut encoded = vec![];
+            witness.write(&mut encoded).unwrap();
+            assert_eq!(encoded, witness_vectors.next().unwrap());
+            // `cursor_height` isn't encoded, and only matters while there is a cursor.
+            let read = SproutWitness::<TESTING_DEPTH>::read(&encoded[..]).unwrap();
+            let mut reencoded = vec![];
+            read.write(&mut reencoded).unwrap();
+            assert_eq!(reencoded, encoded);
+            assert_eq!(read.root(), witness.root());
+
+            assert_eq!(witness.root(), tree.root());
+        }
+    }
+    assert!(witness_vectors.next().is_none());
+    assert!(path_vectors.next().is_none());
+
+    // The tree is full.
+    assert!(!tree.append([0; 32]));
+    for witness in &mut witnesses {
+        assert!(!witness.append([0; 32]));
+    }
+}
+
+#[test]
+fn sprout_tree_rejects_non_canonical_encodings() {
+    // Parents without leaves.
+    assert!(SproutTree::<TESTING_DEPTH>::read(&[0, 0, 1, 0][..]).is_err());
+    // A right leaf without a left leaf.
+    let mut encoded = vec![0, 1];
+    encoded.extend_from_slice(&[7; 32]);
+    encoded.push(0);
+    assert!(SproutTree::<TESTING_DEPTH>::read(&encoded[..]).is_err());
+    // An empty last parent.
+    let mut encoded = vec![1];
+    encoded.extend_from_slice(&[7; 32]);
+    encoded.extend_from_slice(&[0, 1, 0]);
+    assert!(SproutTree::<TESTING_DEPTH>::read(&encoded[..]).is_err());
+}
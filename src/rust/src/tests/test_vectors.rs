@@ -0,0 +1,340 @@
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use super::{decode_digest, decode_hex, ZIP_244_VECTORS};
+use crate::address_ffi::zcash_address_string_free;
+use crate::history_ffi::{librustzcash_mmr_build_roots, librustzcash_mmr_hash_node};
+use crate::test_vectors::{
+    export_test_vectors, zcash_export_test_vectors, zip244_vector, ExportError,
+};
+use crate::transaction_ffi::calculate_wtxid;
+
+const DIGEST_NAMES: [&str; 9] = [
+    "header_digest",
+    "transparent_digest",
+    "sapling_digest",
+    "orchard_digest",
+    "txid",
+    "transparent_auth_digest",
+    "sapling_auth_digest",
+    "orchard_auth_digest",
+    "auth_digest",
+];
+
+/// The subset of JSON used by the exported documents and the test vector files.
+#[derive(Debug, PartialEq)]
+enum Json {
+    Null,
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(BTreeMap<String, Json>),
+}
+
+impl Json {
+    fn parse(input: &str) -> Self {
+        let mut chars = input.chars().peekable();
+        let json = Json::parse_value(&mut chars);
+        Json::skip_whitespace(&mut chars);
+        assert_eq!(chars.next(), None, "Trailing characters after JSON value");
+        json
+    }
+
+    fn skip_whitespace(input: &mut std::iter::Peekable<std::str::Chars>) {
+        while input.peek().map_or(false, |c| c.is_whitespace()) {
+            input.next();
+        }
+    }
+
+    /// Parses the items of an array or object up to `close`, which must be separated
+    /// by single commas.
+    fn parse_items<T>(
+        input: &mut std::iter::Peekable<std::str::Chars>,
+        close: char,
+        mut item: impl FnMut(&mut std::iter::Peekable<std::str::Chars>) -> T,
+    ) -> Vec<T> {
+        let mut items = vec![];
+        Json::skip_whitespace(input);
+        if input.peek() == Some(&close) {
+            input.next();
+            return items;
+        }
+        loop {
+            items.push(item(input));
+            Json::skip_whitespace(input);
+            match input.next() {
+                Some(',') => (),
+                Some(c) if c == close => return items,
+                c => panic!("Unexpected {:?} in JSON", c),
+            }
+        }
+    }
+
+    fn parse_value(input: &mut std::iter::Peekable<std::str::Chars>) -> Self {
+        Json::skip_whitespace(input);
+        match input.next().unwrap() {
+            '[' => Json::Array(Json::parse_items(input, ']', Json::parse_value)),
+            '{' => Json::Object(
+                Json::parse_items(input, '}', |input| {
+                    let key = match Json::parse_value(input) {
+                        Json::String(key) => key,
+                        key => panic!("Expected a string key, found {:?}", key),
+                    };
+                    Json::skip_whitespace(input);
+                    assert_eq!(input.next(), Some(':'));
+                    (key, Json::parse_value(input))
+                })
+                .into_iter()
+                .collect(),
+            ),
+            '"' => Json::String(input.by_ref().take_while(|&c| c != '"').collect()),
+            'n' => {
+                assert_eq!(input.by_ref().take(3).collect::<String>(), "ull");
+                Json::Null
+            }
+            c => {
+                let mut digits = c.to_string();
+                while let Some(d) = input.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    input.next();
+                }
+                Json::Number(digits.parse().unwrap())
+            }
+        }
+    }
+
+    fn array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => panic!("Expected an array"),
+        }
+    }
+
+    fn object(&self) -> &BTreeMap<String, Json> {
+        match self {
+            Json::Object(members) => members,
+            _ => panic!("Expected an object"),
+        }
+    }
+
+    fn number(&self) -> u64 {
+        match self {
+            Json::Number(n) => *n,
+            _ => panic!("Expected a number"),
+        }
+    }
+
+    fn string(&self) -> &str {
+        match self {
+            Json::String(s) => s,
+            _ => panic!("Expected a string"),
+        }
+    }
+
+    /// Returns the named member of an object.
+    fn get(&self, key: &str) -> &Json {
+        self.object()
+            .get(key)
+            .unwrap_or_else(|| panic!("Missing member {}", key))
+    }
+
+    /// Returns the members of an object, in sorted order.
+    fn keys(&self) -> Vec<&str> {
+        self.object().keys().map(|key| key.as_str()).collect()
+    }
+}
+
+/// Checks that `json` is the header of an export of `kind`, and returns its array of
+/// vectors.
+fn export_items<'a>(json: &'a Json, kind: &str, seed: u64, array: &str) -> &'a [Json] {
+    let mut keys = vec!["kind", "seed", array];
+    keys.sort_unstable();
+    assert_eq!(json.keys(), keys);
+    assert_eq!(json.get("kind").string(), kind);
+    assert_eq!(json.get("seed").number(), seed);
+    json.get(array).array()
+}
+
+fn check_zip244_vector(vector: &Json) {
+    let mut keys = DIGEST_NAMES.to_vec();
+    keys.push("tx");
+    keys.sort_unstable();
+    assert_eq!(vector.keys(), keys);
+    for name in DIGEST_NAMES.iter() {
+        assert_eq!(vector.get(name).string().len(), 64);
+    }
+
+    // The txid and authorizing data commitment are those that zcashd computes for the
+    // wtxid.
+    let tx = decode_hex(vector.get("tx").string());
+    let (txid, auth_digest) = calculate_wtxid(&tx).unwrap();
+    assert_eq!(decode_digest(vector.get("txid").string()), txid);
+    assert_eq!(
+        decode_digest(vector.get("auth_digest").string()),
+        auth_digest
+    );
+}
+
+#[test]
+fn zip244_export() {
+    let json = Json::parse(&export_test_vectors("zip244", "").unwrap());
+    let vectors = export_items(&json, "zip244", 0, "vectors");
+    assert_eq!(vectors.len(), 20);
+    for vector in vectors {
+        check_zip244_vector(vector);
+    }
+    // The transactions have only transparent bundles, so their shielded digests are
+    // those of empty bundles.
+    for name in ["sapling_digest", "orchard_digest", "sapling_auth_digest"].iter() {
+        let digest = vectors[0].get(name).string();
+        assert!(vectors.iter().all(|v| v.get(name).string() == digest));
+    }
+    let transparent_digest = vectors[0].get("transparent_digest").string();
+    assert!(vectors
+        .iter()
+        .any(|v| v.get("transparent_digest").string() != transparent_digest));
+
+    // Exports are determined by their parameters.
+    assert_eq!(
+        export_test_vectors("zip244", "{}").unwrap(),
+        export_test_vectors("zip244", "").unwrap()
+    );
+    let reseeded = export_test_vectors("zip244", r#"{"count": 3, "seed": 7}"#).unwrap();
+    assert_eq!(
+        reseeded,
+        export_test_vectors("zip244", r#"{ "seed": 7, "count": 3 }"#).unwrap()
+    );
+    let reseeded = Json::parse(&reseeded);
+    let vectors = export_items(&reseeded, "zip244", 7, "vectors");
+    assert_eq!(vectors.len(), 3);
+    assert_ne!(vectors[0], export_items(&json, "zip244", 0, "vectors")[0]);
+
+    let empty = Json::parse(&export_test_vectors("zip244", r#"{"count": 0}"#).unwrap());
+    assert!(export_items(&empty, "zip244", 0, "vectors").is_empty());
+}
+
+#[test]
+fn zip244_vectors_match_pinned_vectors() {
+    let pinned = Json::parse(ZIP_244_VECTORS);
+    let mut checked = 0;
+    // The first two rows are a source and a header.
+    for row in pinned.array().iter().skip(2) {
+        let row = row.array();
+        let tx = decode_hex(row[0].string());
+        let vector = Json::parse(&zip244_vector(&tx).unwrap());
+        check_zip244_vector(&vector);
+        assert_eq!(vector.get("tx").string(), row[0].string());
+        assert_eq!(vector.get("txid").string(), row[1].string());
+        assert_eq!(vector.get("auth_digest").string(), row[2].string());
+        checked += 1;
+    }
+    assert!(checked > 0);
+
+    // Pre-v5 transactions have no ZIP 244 digests.
+    let v4_coinbase = include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt");
+    assert_eq!(zip244_vector(&decode_hex(v4_coinbase.trim())), None);
+}
+
+#[test]
+fn history_tree_export() {
+    let json = Json::parse(&export_test_vectors("history_tree", "").unwrap());
+    let trees = export_items(&json, "history_tree", 0, "trees");
+    assert_eq!(trees.len(), 2);
+
+    for (tree, (version, cbranch)) in trees
+        .iter()
+        .zip([(1, 0xf5b9_230b_u32), (2, 0xc2d6_d0b4)].iter())
+    {
+        assert_eq!(tree.keys(), ["cbranch", "leaves", "roots", "version"]);
+        assert_eq!(tree.get("version").number(), *version);
+        assert_eq!(tree.get("cbranch").string(), format!("{:08x}", cbranch));
+
+        let leaves: Vec<_> = tree
+            .get("leaves")
+            .array()
+            .iter()
+            .map(|leaf| {
+                let leaf = decode_hex(leaf.string());
+                let mut padded = [0; zcash_history::MAX_NODE_DATA_SIZE];
+                padded[..leaf.len()].copy_from_slice(&leaf);
+                padded
+            })
+            .collect();
+        let roots: Vec<_> = tree
+            .get("roots")
+            .array()
+            .iter()
+            .map(|root| decode_digest(root.string()))
+            .collect();
+        assert_eq!(leaves.len(), 50);
+        assert_eq!(roots.len(), 50);
+
+        // The root of a single leaf is its hash, and every root is the one that
+        // zcashd's own batch FFI computes.
+        let mut leaf_hash = [0; 32];
+        assert_eq!(
+            librustzcash_mmr_hash_node(*cbranch, &leaves[0], &mut leaf_hash),
+            0
+        );
+        assert_eq!(roots[0], leaf_hash);
+        let mut expected = vec![[0; 32]; leaves.len()];
+        assert_eq!(
+            librustzcash_mmr_build_roots(
+                *cbranch,
+                leaves.as_ptr(),
+                leaves.len(),
+                expected.as_mut_ptr(),
+                expected.len(),
+            ),
+            leaves.len() as u32
+        );
+        assert_eq!(roots, expected);
+    }
+
+    let short = Json::parse(&export_test_vectors("history_tree", r#"{"count": 2}"#).unwrap());
+    for tree in export_items(&short, "history_tree", 0, "trees") {
+        assert_eq!(tree.get("leaves").array().len(), 2);
+        assert_eq!(tree.get("roots").array().len(), 2);
+    }
+}
+
+#[test]
+fn invalid_exports() {
+    assert_eq!(
+        export_test_vectors("sighash", ""),
+        Err(ExportError::UnknownKind("sighash".into()))
+    );
+    for params in [
+        "[]",
+        "{\"seed\"}",
+        "{\"seed\": -1}",
+        "{\"seed\": \"1\"}",
+        "{\"seed\": 1,}",
+        "{\"txs\": 1}",
+        "{\"count\": 10001}",
+    ]
+    .iter()
+    {
+        assert!(matches!(
+            export_test_vectors("zip244", params),
+            Err(ExportError::InvalidParams(_))
+        ));
+    }
+
+    let kind = CString::new("history_tree").unwrap();
+    let params = CString::new(r#"{"count": 1}"#).unwrap();
+    let json = zcash_export_test_vectors(kind.as_ptr(), params.as_ptr());
+    assert!(!json.is_null());
+    assert_eq!(
+        unsafe { CStr::from_ptr(json) }.to_str().unwrap(),
+        export_test_vectors("history_tree", r#"{"count": 1}"#).unwrap()
+    );
+    zcash_address_string_free(json);
+    let json = zcash_export_test_vectors(kind.as_ptr(), ptr::null());
+    assert!(!json.is_null());
+    zcash_address_string_free(json);
+
+    let kind = CString::new("zip243").unwrap();
+    assert!(zcash_export_test_vectors(kind.as_ptr(), ptr::null()).is_null());
+}
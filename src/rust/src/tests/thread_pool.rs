@@ -0,0 +1,20 @@
+use std::thread;
+
+use crate::init_ffi::rayon_pool_builder;
+
+#[test]
+fn rayon_pool_is_configured() {
+    let pool = rayon_pool_builder(3, "zc-test-rayon".to_owned(), true)
+        .build()
+        .unwrap();
+    assert_eq!(pool.install(rayon::current_num_threads), 3);
+
+    let name = pool.install(|| thread::current().name().map(str::to_owned));
+    assert!(matches!(name, Some(name) if name.starts_with("zc-test-rayon-")));
+
+    // Zero threads means one per core.
+    let pool = rayon_pool_builder(0, "zc-test-rayon".to_owned(), false)
+        .build()
+        .unwrap();
+    assert!(pool.install(rayon::current_num_threads) >= 1);
+}
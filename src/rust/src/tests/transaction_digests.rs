@@ -0,0 +1,835 @@
+use blake2b_simd::Params as Blake2bParams;
+
+use std::convert::TryInto;
+use std::{ptr, slice};
+
+use zcash_primitives::{
+    consensus::BranchId,
+    legacy::Script,
+    transaction::{components::Amount, sighash::SIGHASH_ALL, Transaction},
+};
+
+use super::{decode_digest, decode_hex, ZIP_244_VECTORS};
+use crate::transaction_ffi::{
+    calculate_txid, calculate_wtxid, check_wtxid, inspect_transaction, legacy_sig_digest,
+    transaction_auth_digest, transaction_digest_parts, transparent_sig_digest,
+    zcash_block_auth_data_root, zcash_transaction_check_wtxid, zcash_transaction_digest_parts,
+    zcash_transaction_inspect, zcash_transaction_legacy_signature_digest,
+    zcash_transaction_precomputed_free, zcash_transaction_precomputed_init,
+    zcash_transaction_zip244_signature_digest, zcash_tx_info_bytes, zcash_tx_info_free,
+    zcash_tx_info_hex, zcash_tx_info_summary, SigDigestError, TxDigestParts, TxSummary, TxidError,
+    WtxidMismatch,
+};
+
+/// A coinbase transaction at height 621415.
+const V4_COINBASE: &str = include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt");
+/// A v4 transaction with a single JoinSplit.
+const V4_SPROUT: &str = include_str!("../../../fuzzing/DecodeHexTx/input/sprout.txt");
+
+/// The subset of JSON used by the test vector files.
+#[derive(Debug)]
+enum Json {
+    Null,
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+}
+
+impl Json {
+    fn parse(input: &mut std::iter::Peekable<std::str::Chars>) -> Self {
+        while input
+            .peek()
+            .map_or(false, |c| c.is_whitespace() || *c == ',')
+        {
+            input.next();
+        }
+        match input.next().unwrap() {
+            '[' => {
+                let mut items = vec![];
+                loop {
+                    while input
+                        .peek()
+                        .map_or(false, |c| c.is_whitespace() || *c == ',')
+                    {
+                        input.next();
+                    }
+                    if input.peek() == Some(&']') {
+                        input.next();
+                        return Json::Array(items);
+                    }
+                    items.push(Json::parse(input));
+                }
+            }
+            '"' => Json::String(input.by_ref().take_while(|&c| c != '"').collect()),
+            'n' => {
+                assert_eq!(input.by_ref().take(3).collect::<String>(), "ull");
+                Json::Null
+            }
+            c => {
+                let mut digits = c.to_string();
+                while let Some(d) = input.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    input.next();
+                }
+                Json::Number(digits.parse().unwrap())
+            }
+        }
+    }
+
+    fn array(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => panic!("Expected an array"),
+        }
+    }
+
+    fn number(&self) -> Option<u64> {
+        match self {
+            Json::Number(n) => Some(*n),
+            Json::Null => None,
+            _ => panic!("Expected a number"),
+        }
+    }
+
+    fn string(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            Json::Null => None,
+            _ => panic!("Expected a string"),
+        }
+    }
+}
+
+struct Zip244Vector {
+    tx: Vec<u8>,
+    txid: [u8; 32],
+    auth_digest: [u8; 32],
+    amounts: Vec<i64>,
+    script_pubkeys: Vec<Vec<u8>>,
+    transparent_input: Option<usize>,
+    /// The transparent signature digests for `SIGHASH_ALL`, `SIGHASH_NONE`,
+    /// `SIGHASH_SINGLE`, and each of those with `SIGHASH_ANYONECANPAY`.
+    sighashes: Vec<Option<[u8; 32]>>,
+}
+
+fn zip_244_vectors() -> Vec<Zip244Vector> {
+    let json = Json::parse(&mut ZIP_244_VECTORS.chars().peekable());
+    // Skip over the comments at the start of the file.
+    json.array()[2..]
+        .iter()
+        .map(|vector| {
+            let fields = vector.array();
+            let string = |i: usize| fields[i].string().unwrap();
+            Zip244Vector {
+                tx: decode_hex(string(0)),
+                txid: decode_digest(string(1)),
+                auth_digest: decode_digest(string(2)),
+                amounts: fields[3]
+                    .array()
+                    .iter()
+                    .map(|amount| amount.number().unwrap() as i64)
+                    .collect(),
+                script_pubkeys: fields[4]
+                    .array()
+                    .iter()
+                    .map(|script| decode_hex(script.string().unwrap()))
+                    .collect(),
+                transparent_input: fields[5].number().map(|i| i as usize),
+                sighashes: fields[7..13]
+                    .iter()
+                    .map(|sighash| sighash.string().map(decode_digest))
+                    .collect(),
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn zip_244_txids() {
+    let vectors = zip_244_vectors();
+    assert_eq!(vectors.len(), 10);
+
+    for v in vectors {
+        assert_eq!(calculate_txid(&v.tx).unwrap(), v.txid);
+        assert_eq!(calculate_wtxid(&v.tx).unwrap(), (v.txid, v.auth_digest));
+    }
+}
+
+#[test]
+fn v4_txids() {
+    // The double-SHA-256 hashes of the transactions, computed independently.
+    for (tx, txid) in [
+        (
+            V4_COINBASE,
+            "d1e1d0b537f83c7c084b3435bc7d672615f136e53fa6077f0419425e526a41e3",
+        ),
+        (
+            V4_SPROUT,
+            "191fe038125545f8d8b7638059fb78300eae3f2b68f94bd20ede95bdd0fa3f67",
+        ),
+    ] {
+        let tx = decode_hex(tx);
+        assert_eq!(calculate_txid(&tx).unwrap(), decode_digest(txid));
+        // Pre-v5 transactions have no authorizing data commitment.
+        assert_eq!(
+            calculate_wtxid(&tx).unwrap(),
+            (decode_digest(txid), [0xff; 32])
+        );
+    }
+}
+
+#[test]
+fn check_claimed_wtxids() {
+    let flip = |digest: &[u8; 32]| {
+        let mut flipped = *digest;
+        flipped[31] ^= 1;
+        flipped
+    };
+    let check = |tx: &[u8], txid: &[u8; 32], auth_digest: &[u8; 32]| {
+        let result = check_wtxid(tx, txid, auth_digest).unwrap();
+        let code = zcash_transaction_check_wtxid(tx.as_ptr(), tx.len(), txid, auth_digest);
+        (result, code)
+    };
+
+    let v5 = zip_244_vectors().swap_remove(0);
+    let v4 = decode_hex(V4_SPROUT);
+    let v4_txid = decode_digest("191fe038125545f8d8b7638059fb78300eae3f2b68f94bd20ede95bdd0fa3f67");
+    for (tx, txid, auth_digest) in [
+        (&v5.tx, v5.txid, v5.auth_digest),
+        (&v4, v4_txid, [0xff; 32]),
+    ] {
+        assert_eq!(check(tx, &txid, &auth_digest), (None, 0));
+        assert_eq!(
+            check(tx, &flip(&txid), &auth_digest),
+            (Some(WtxidMismatch::Txid), 1)
+        );
+        assert_eq!(
+            check(tx, &txid, &flip(&auth_digest)),
+            (Some(WtxidMismatch::AuthDigest), 2)
+        );
+        assert_eq!(
+            check(tx, &flip(&txid), &flip(&auth_digest)),
+            (Some(WtxidMismatch::Both), 3)
+        );
+    }
+
+    // A pre-v5 transaction has no authorizing data commitment, so only the ZIP 239
+    // placeholder matches.
+    assert_eq!(
+        check(&v4, &v4_txid, &[0; 32]),
+        (Some(WtxidMismatch::AuthDigest), 2)
+    );
+
+    let truncated = &v5.tx[..v5.tx.len() - 1];
+    assert!(matches!(
+        check_wtxid(truncated, &v5.txid, &v5.auth_digest),
+        Err(TxidError::Invalid(_))
+    ));
+    assert_eq!(
+        zcash_transaction_check_wtxid(
+            truncated.as_ptr(),
+            truncated.len(),
+            &v5.txid,
+            &v5.auth_digest
+        ),
+        4
+    );
+}
+
+/// Returns the BLAKE2b-256 digest of `parts` with the given personalization.
+fn digest(personalization: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut state = Blake2bParams::new()
+        .hash_length(32)
+        .personal(personalization)
+        .to_state();
+    for part in parts {
+        state.update(part);
+    }
+    state.finalize().as_bytes().try_into().unwrap()
+}
+
+#[test]
+fn zip_244_digest_parts() {
+    let mut empty_bundles = [0; 3];
+    for v in zip_244_vectors() {
+        let parts = transaction_digest_parts(&v.tx).unwrap().unwrap();
+        assert_eq!(parts.txid, v.txid);
+        assert_eq!(parts.auth_digest, v.auth_digest);
+
+        // The header digest covers the first five fields, and the txid and auth digest
+        // are personalized with the consensus branch ID that follows the first two.
+        let branch_id = &v.tx[8..12];
+        assert_eq!(
+            parts.header_digest,
+            digest(b"ZTxIdHeadersHash", &[&v.tx[..20]])
+        );
+        assert_eq!(
+            digest(
+                &[&b"ZcashTxHash_"[..], branch_id].concat(),
+                &[
+                    &parts.header_digest,
+                    &parts.transparent_digest,
+                    &parts.sapling_digest,
+                    &parts.orchard_digest,
+                ],
+            ),
+            v.txid
+        );
+        assert_eq!(
+            digest(
+                &[&b"ZTxAuthHash_"[..], branch_id].concat(),
+                &[
+                    &parts.transparent_auth_digest,
+                    &parts.sapling_auth_digest,
+                    &parts.orchard_auth_digest,
+                ],
+            ),
+            v.auth_digest
+        );
+
+        // An empty bundle is committed to by the digest of nothing, with the bundle's
+        // personalization.
+        let summary = inspect_transaction(&v.tx, true).unwrap().summary;
+        let empty = [
+            (
+                summary.transparent_inputs == 0 && summary.transparent_outputs == 0,
+                (parts.transparent_digest, b"ZTxIdTranspaHash"),
+                (parts.transparent_auth_digest, b"ZTxAuthTransHash"),
+            ),
+            (
+                summary.sapling_spends == 0 && summary.sapling_outputs == 0,
+                (parts.sapling_digest, b"ZTxIdSaplingHash"),
+                (parts.sapling_auth_digest, b"ZTxAuthSapliHash"),
+            ),
+            (
+                summary.orchard_actions == 0,
+                (parts.orchard_digest, b"ZTxIdOrchardHash"),
+                (parts.orchard_auth_digest, b"ZTxAuthOrchaHash"),
+            ),
+        ];
+        for (count, (is_empty, (txid_part, txid_personal), (auth_part, auth_personal))) in
+            empty_bundles.iter_mut().zip(empty)
+        {
+            assert_eq!(txid_part == digest(txid_personal, &[]), is_empty);
+            if is_empty {
+                assert_eq!(auth_part, digest(auth_personal, &[]));
+                *count += 1;
+            }
+        }
+    }
+    // The vectors cover each kind of empty bundle.
+    assert!(empty_bundles.iter().all(|&count| count > 0));
+
+    // Pre-v5 transactions have no ZIP 244 digests.
+    assert!(transaction_digest_parts(&decode_hex(V4_SPROUT))
+        .unwrap()
+        .is_none());
+    let tx = decode_hex(V4_SPROUT);
+    let mut parts = TxDigestParts::default();
+    assert!(!zcash_transaction_digest_parts(
+        tx.as_ptr(),
+        tx.len(),
+        &mut parts
+    ));
+    assert_eq!(parts, TxDigestParts::default());
+}
+
+#[test]
+fn rejects_malformed_transactions() {
+    let mut tx = decode_hex(V4_COINBASE);
+
+    tx.push(0);
+    assert!(matches!(
+        calculate_txid(&tx),
+        Err(TxidError::TrailingBytes(1))
+    ));
+    tx.truncate(tx.len() - 2);
+    assert!(matches!(calculate_txid(&tx), Err(TxidError::Invalid(_))));
+    assert!(matches!(
+        calculate_txid(&tx[..2]),
+        Err(TxidError::Invalid(_))
+    ));
+
+    // An overwintered v4 header with an unknown version group ID.
+    let mut tx = decode_hex(V4_COINBASE);
+    tx[4] ^= 1;
+    assert!(matches!(
+        calculate_txid(&tx),
+        Err(TxidError::UnknownVersion(0x80000004))
+    ));
+
+    // Only versions 1 and 2 are valid without the overwintered flag.
+    for version in [0u32, 3, 5] {
+        assert!(matches!(
+            calculate_wtxid(&version.to_le_bytes()),
+            Err(TxidError::UnknownVersion(v)) if v == version
+        ));
+    }
+}
+
+#[test]
+fn block_auth_data_root_of_synthetic_block() {
+    let hash_pair = |left: &[u8; 32], right: &[u8; 32]| {
+        let mut node = [0; 32];
+        node.copy_from_slice(
+            Blake2bParams::new()
+                .hash_length(32)
+                .personal(b"ZcashAuthDatHash")
+                .to_state()
+                .update(left)
+                .update(right)
+                .finalize()
+                .as_bytes(),
+        );
+        node
+    };
+
+    // A block of a v4 coinbase followed by four v5 transactions, padded to eight leaves.
+    let vectors = zip_244_vectors();
+    let mut auth_digests = vec![transaction_auth_digest(&decode_hex(V4_COINBASE)).unwrap()];
+    assert_eq!(auth_digests[0], [0xff; 32]);
+    for v in &vectors[..4] {
+        assert_eq!(transaction_auth_digest(&v.tx).unwrap(), v.auth_digest);
+        auth_digests.push(v.auth_digest);
+    }
+
+    let d = &auth_digests;
+    let expected = hash_pair(
+        &hash_pair(&hash_pair(&d[0], &d[1]), &hash_pair(&d[2], &d[3])),
+        &hash_pair(&hash_pair(&d[4], &[0; 32]), &hash_pair(&[0; 32], &[0; 32])),
+    );
+    let mut root = [0; 32];
+    zcash_block_auth_data_root(auth_digests.as_ptr(), auth_digests.len(), &mut root);
+    assert_eq!(root, expected);
+
+    zcash_block_auth_data_root(std::ptr::null(), 0, &mut root);
+    assert_eq!(root, [0; 32]);
+}
+
+/// Serializes the previous outputs spent by a transaction, as passed to
+/// `zcash_transaction_precomputed_init`.
+fn all_prev_outputs(amounts: &[i64], script_pubkeys: &[Vec<u8>]) -> Vec<u8> {
+    let mut buf = vec![amounts.len() as u8];
+    for (amount, script) in amounts.iter().zip(script_pubkeys) {
+        buf.extend_from_slice(&amount.to_le_bytes());
+        buf.push(script.len() as u8);
+        buf.extend_from_slice(script);
+    }
+    buf
+}
+
+/// A 2-of-3 multisig redeem script.
+const P2SH_REDEEM_SCRIPT: [u8; 105] = [
+    0x52, 0x21, 0x02, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+    0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x11,
+    0x11, 0x11, 0x11, 0x21, 0x02, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+    0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22, 0x22,
+    0x22, 0x22, 0x22, 0x22, 0x22, 0x21, 0x02, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+    0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33,
+    0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x33, 0x53, 0xae,
+];
+
+#[test]
+fn zip_244_transparent_sig_digests() {
+    let hash_types = [0x01, 0x02, 0x03, 0x81, 0x82, 0x83];
+    let mut checked = 0;
+
+    for v in zip_244_vectors() {
+        let prev_outputs = all_prev_outputs(&v.amounts, &v.script_pubkeys);
+        let precomputed = zcash_transaction_precomputed_init(
+            v.tx.as_ptr(),
+            v.tx.len(),
+            prev_outputs.as_ptr(),
+            prev_outputs.len(),
+        );
+        let precomputed_tx = unsafe { precomputed.as_ref() }.unwrap();
+
+        let index = match v.transparent_input {
+            Some(index) => index,
+            None => {
+                // Coinbase transactions, and those without transparent inputs, have no
+                // transparent signatures.
+                assert_eq!(
+                    transparent_sig_digest(
+                        precomputed_tx,
+                        0,
+                        &Script(vec![]),
+                        &[],
+                        Amount::zero(),
+                        1
+                    ),
+                    Err(SigDigestError::NoTransparentInputs)
+                );
+                zcash_transaction_precomputed_free(precomputed);
+                continue;
+            }
+        };
+        let script_pubkey = Script(v.script_pubkeys[index].clone());
+        let value = Amount::from_i64(v.amounts[index]).unwrap();
+
+        for (&hash_type, expected) in hash_types.iter().zip(&v.sighashes) {
+            let sighash = transparent_sig_digest(
+                precomputed_tx,
+                index,
+                &script_pubkey,
+                &[],
+                value,
+                hash_type,
+            );
+            match expected {
+                Some(expected) => {
+                    assert_eq!(sighash, Ok(*expected));
+                    // The digest used when validating signatures is the same.
+                    let mut consensus = [0; 32];
+                    assert!(zcash_transaction_zip244_signature_digest(
+                        precomputed,
+                        hash_type.into(),
+                        index,
+                        ptr::null(),
+                        0,
+                        &mut consensus,
+                    ));
+                    assert_eq!(consensus, *expected);
+                    // ZIP 244 doesn't commit to the script code, so supplying one (such
+                    // as a redeem script) doesn't change the digest.
+                    assert_eq!(
+                        transparent_sig_digest(
+                            precomputed_tx,
+                            index,
+                            &script_pubkey,
+                            &P2SH_REDEEM_SCRIPT,
+                            value,
+                            hash_type
+                        ),
+                        Ok(*expected)
+                    );
+                    checked += 1;
+                }
+                // The vectors omit SIGHASH_SINGLE digests for inputs without a
+                // corresponding output.
+                None => assert_eq!(sighash, Err(SigDigestError::NoCorrespondingOutput)),
+            }
+        }
+
+        assert_eq!(
+            transparent_sig_digest(precomputed_tx, index, &script_pubkey, &[], value, 0x04),
+            Err(SigDigestError::InvalidHashType(0x04))
+        );
+        assert_eq!(
+            transparent_sig_digest(
+                precomputed_tx,
+                index,
+                &script_pubkey,
+                &[],
+                Amount::from_i64(v.amounts[index] + 1).unwrap(),
+                0x01
+            ),
+            Err(SigDigestError::PrevoutMismatch)
+        );
+        assert_eq!(
+            transparent_sig_digest(
+                precomputed_tx,
+                v.amounts.len(),
+                &script_pubkey,
+                &[],
+                value,
+                0x01
+            ),
+            Err(SigDigestError::InputOutOfRange(v.amounts.len()))
+        );
+
+        // A script code can't be supplied for a shielded signature digest.
+        let mut sighash = [0; 32];
+        assert!(!zcash_transaction_zip244_signature_digest(
+            precomputed,
+            SIGHASH_ALL.into(),
+            // NOT_AN_INPUT
+            0xffffffff,
+            P2SH_REDEEM_SCRIPT.as_ptr(),
+            P2SH_REDEEM_SCRIPT.len(),
+            &mut sighash,
+        ));
+        assert_eq!(sighash, [0; 32]);
+
+        zcash_transaction_precomputed_free(precomputed);
+    }
+
+    // Each hash type is covered by at least one vector.
+    assert!(checked >= hash_types.len());
+}
+
+#[test]
+fn legacy_p2sh_multisig_sig_digests() {
+    // A v4 transaction whose first input spends a P2SH output paying to
+    // `P2SH_REDEEM_SCRIPT`. The expected digests were computed with an independent
+    // implementation of ZIP 243, checked against the Sapling vectors in `sighash.json`
+    // that were produced by `SignatureHash`.
+    let tx_bytes = decode_hex(
+        "0400008085202f8902000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f\
+         0100000000feffffff202122232425262728292a2b2c2d2e2f303132333435363738393a3b3c3d3e3f\
+         0000000000ffffffff0180d1f008000000001976a914444444444444444444444444444444444444\
+         444488ac40420f0054420f000000000000000000000000",
+    );
+    let p2sh = Script(decode_hex("a9145cc52612d829ae9145a33d9ef5fe2f5c31e6062787"));
+    let value = Amount::from_i64(200_000_000).unwrap();
+    let sapling = u32::from(BranchId::Sapling);
+    let tx = Transaction::read(&tx_bytes[..], BranchId::Sapling).unwrap();
+
+    let digest = |hex: &str| -> [u8; 32] { decode_hex(hex).try_into().unwrap() };
+    for (hash_type, expected) in [
+        (
+            0x01,
+            "3b1972a50866d545af846a7b833d104f177dbd838077c2dadc57ea4bf132059d",
+        ),
+        (
+            0x02,
+            "5f54150b9ddeed8ee84340f6e3a3965d7a1de9f6d740efa4c16ce58aa23da175",
+        ),
+        (
+            0x03,
+            "54abd9ee3740ea782ba4a25bf65a6d2bbb174846e239b2e1690d54544a00e6fd",
+        ),
+        (
+            0x81,
+            "83adba21d5b2c562fef018c759f24cb4d7f8616586572f172b1e286a0d7a2033",
+        ),
+    ] {
+        let expected = digest(expected);
+        assert_eq!(
+            legacy_sig_digest(&tx, Some(0), &p2sh, &P2SH_REDEEM_SCRIPT, value, hash_type),
+            Ok(expected)
+        );
+
+        let mut sighash = [0; 32];
+        assert!(zcash_transaction_legacy_signature_digest(
+            tx_bytes.as_ptr(),
+            tx_bytes.len(),
+            sapling,
+            hash_type.into(),
+            0,
+            p2sh.0.as_ptr(),
+            p2sh.0.len(),
+            P2SH_REDEEM_SCRIPT.as_ptr(),
+            P2SH_REDEEM_SCRIPT.len(),
+            value.into(),
+            &mut sighash,
+        ));
+        assert_eq!(sighash, expected);
+    }
+
+    // Without a script code, the digest commits to the P2SH script itself, which is
+    // not what a P2SH signature must sign.
+    assert_eq!(
+        legacy_sig_digest(&tx, Some(0), &p2sh, &[], value, SIGHASH_ALL),
+        Ok(digest(
+            "acc4da76f10f9dde779bdd136ba360a661a85084f3a106ffabfd8240947bcfb7"
+        ))
+    );
+    // SIGHASH_SINGLE without a corresponding output commits to no outputs.
+    assert_eq!(
+        legacy_sig_digest(&tx, Some(1), &p2sh, &P2SH_REDEEM_SCRIPT, value, 0x03),
+        Ok(digest(
+            "ed0a6c221341e157b6a2dc8b61b03407f94236d745d1c9de460069d72acbcf14"
+        ))
+    );
+    // The digest commits to the consensus branch ID.
+    let mut sighash = [0; 32];
+    assert!(zcash_transaction_legacy_signature_digest(
+        tx_bytes.as_ptr(),
+        tx_bytes.len(),
+        BranchId::Canopy.into(),
+        SIGHASH_ALL.into(),
+        0,
+        p2sh.0.as_ptr(),
+        p2sh.0.len(),
+        P2SH_REDEEM_SCRIPT.as_ptr(),
+        P2SH_REDEEM_SCRIPT.len(),
+        value.into(),
+        &mut sighash,
+    ));
+    assert_eq!(
+        sighash,
+        digest("fd23c7485ba62001f71fb97e450a37d6b56e2894667b5a0d6a25962b39ea7eb9")
+    );
+
+    // Shielded signature digests take no script code.
+    assert_eq!(
+        legacy_sig_digest(&tx, None, &Script(vec![]), &[], Amount::zero(), SIGHASH_ALL),
+        Ok(digest(
+            "1ca91f7f2708a3815e26d4abe82f55d469157549d03951526293dcc925d58636"
+        ))
+    );
+    assert_eq!(
+        legacy_sig_digest(&tx, None, &p2sh, &P2SH_REDEEM_SCRIPT, value, SIGHASH_ALL),
+        Err(SigDigestError::ScriptCodeForShielded)
+    );
+    let mut sighash = [0; 32];
+    assert!(!zcash_transaction_legacy_signature_digest(
+        tx_bytes.as_ptr(),
+        tx_bytes.len(),
+        sapling,
+        SIGHASH_ALL.into(),
+        // NOT_AN_INPUT
+        0xffffffff,
+        ptr::null(),
+        0,
+        P2SH_REDEEM_SCRIPT.as_ptr(),
+        P2SH_REDEEM_SCRIPT.len(),
+        0,
+        &mut sighash,
+    ));
+    assert_eq!(sighash, [0; 32]);
+
+    assert_eq!(
+        legacy_sig_digest(&tx, Some(2), &p2sh, &P2SH_REDEEM_SCRIPT, value, SIGHASH_ALL),
+        Err(SigDigestError::InputOutOfRange(2))
+    );
+    let v5 = &zip_244_vectors()[0].tx;
+    let v5 = Transaction::read(&v5[..], BranchId::Nu5).unwrap();
+    assert_eq!(
+        legacy_sig_digest(&v5, Some(0), &p2sh, &P2SH_REDEEM_SCRIPT, value, SIGHASH_ALL),
+        Err(SigDigestError::NotLegacy)
+    );
+}
+
+#[test]
+fn inspect_mixed_pool_transaction() {
+    // The first vector has transparent, Sapling, and Orchard components. The `TxV5`
+    // btest checks the summary and the hex fields of every ZIP 244 vector against the
+    // C++ parse, and `rpc_decoderawtransaction_orchard` checks how they are rendered.
+    let tx = &zip_244_vectors()[0].tx;
+    let info = inspect_transaction(tx, true).unwrap();
+    assert_eq!(
+        info.summary,
+        TxSummary {
+            header: 0x80000005,
+            version_group_id: 0x26a7270a,
+            consensus_branch_id: 0xc2d6d0b4,
+            lock_time: 2591264634,
+            expiry_height: 36466477,
+            transparent_inputs: 1,
+            transparent_outputs: 0,
+            sprout_joinsplits: 0,
+            sapling_spends: 1,
+            sapling_outputs: 1,
+            orchard_actions: 2,
+            sapling_value_balance: 202285658676901,
+            orchard_value_balance: 614922616112471,
+            orchard_flags: 3,
+        }
+    );
+    assert_eq!(
+        info.sapling_anchors,
+        ["368e9c7e1fe01f6c54db9379a94c2941ef180c25b869bf8dcdb1cf014253b3c7"]
+    );
+    assert_eq!(
+        info.sapling_nullifiers,
+        ["d4f76a993d20c7e81393fdb51d55d7a8f5621d6ad566c13dce81632a9168694b"]
+    );
+    assert_eq!(
+        info.sapling_cmus,
+        ["12bdedff3959747e45905ebc9625e51124c0f0b9ec3222194b507265f2a6a475"]
+    );
+    assert_eq!(
+        info.orchard_anchor.as_deref(),
+        Some("1b3b3ab643fbbf6bbdeb757b72a775dabf8c6fc51fc0ae85aec92d45f211ade6")
+    );
+    assert_eq!(
+        info.orchard_nullifiers,
+        [
+            "3270732e942afb3be71ceaa0064216695deb69b42ef925e8953ecf6e564553ee",
+            "3379fc7bfce70e7c9c2107af8443015ceaa79c1a2e06676ac4f9d39aaf76eebc",
+        ]
+    );
+    assert_eq!(
+        info.orchard_cmxs,
+        [
+            "3dcc48283294a854b35c4f29b5a27db2bfd62930b7b91fcf55b00fbf95a87289",
+            "136a1ed10f1b7de4f37e65f2f2fedddbf0a838f7128ae0797d3b43dc8b9e7dc3",
+        ]
+    );
+
+    // The raw Orchard fields are laid out as in the v5 encoding: each action, then the
+    // proof, the spend authorization signatures, and the binding signature.
+    let tail = tx.len() - 64 * (info.orchard_actions.len() + 1);
+    assert_eq!(
+        &tx[tail + 64 * info.orchard_actions.len()..],
+        &info.orchard_binding_sig.unwrap()[..]
+    );
+    for (i, action) in info.orchard_actions.iter().enumerate() {
+        assert_eq!(
+            &tx[tail + 64 * i..tail + 64 * (i + 1)],
+            &action.spend_auth_sig[..]
+        );
+
+        let mut encoding = vec![];
+        encoding.extend_from_slice(&action.cv);
+        encoding.extend(decode_hex(&info.orchard_nullifiers[i]).iter().rev());
+        encoding.extend_from_slice(&action.rk);
+        encoding.extend(decode_hex(&info.orchard_cmxs[i]).iter().rev());
+        encoding.extend_from_slice(&action.ephemeral_key);
+        encoding.extend_from_slice(&action.enc_ciphertext);
+        encoding.extend_from_slice(&action.out_ciphertext);
+        assert_eq!(encoding.len(), 820);
+        assert!(tx.windows(encoding.len()).any(|w| w == &encoding[..]));
+    }
+    let proof = info.orchard_proof.as_ref().unwrap();
+    assert!(tx[..tail].ends_with(proof));
+
+    // The FFI returns the same fields.
+    let mut status = u32::MAX;
+    let handle = zcash_transaction_inspect(tx.as_ptr(), tx.len(), true, &mut status);
+    assert_eq!(status, 0);
+    let mut summary = TxSummary::default();
+    zcash_tx_info_summary(handle, &mut summary);
+    assert_eq!(summary, info.summary);
+    let mut hex = [0; 65];
+    assert!(zcash_tx_info_hex(handle, 4, 1, &mut hex));
+    assert_eq!(&hex[..64], info.orchard_nullifiers[1].as_bytes());
+    assert_eq!(hex[64], 0);
+    assert!(zcash_tx_info_hex(handle, 3, 0, &mut hex));
+    assert_eq!(&hex[..64], info.orchard_anchor.as_ref().unwrap().as_bytes());
+    assert!(!zcash_tx_info_hex(handle, 3, 1, &mut hex));
+    assert!(!zcash_tx_info_hex(handle, 6, 0, &mut hex));
+    let mut len = 0;
+    let bytes = zcash_tx_info_bytes(handle, 12, 0, &mut len);
+    assert_eq!(unsafe { slice::from_raw_parts(bytes, len) }, &proof[..]);
+    let bytes = zcash_tx_info_bytes(handle, 9, 1, &mut len);
+    assert_eq!(
+        unsafe { slice::from_raw_parts(bytes, len) },
+        &info.orchard_actions[1].enc_ciphertext[..]
+    );
+    len = 0;
+    assert!(zcash_tx_info_bytes(handle, 12, 1, &mut len).is_null());
+    assert!(zcash_tx_info_bytes(handle, 6, 2, &mut len).is_null());
+    assert!(zcash_tx_info_bytes(handle, 5, 0, &mut len).is_null());
+    assert_eq!(len, 0);
+    zcash_tx_info_free(handle);
+}
+
+#[test]
+fn inspect_pre_v5_and_unknown_transactions() {
+    let info = inspect_transaction(&decode_hex(V4_SPROUT), true).unwrap();
+    assert_eq!(
+        info.summary,
+        TxSummary {
+            header: 0x80000004,
+            version_group_id: 0x892f2085,
+            expiry_height: 625389,
+            sprout_joinsplits: 1,
+            ..Default::default()
+        }
+    );
+    assert!(info.orchard_anchor.is_none());
+
+    // A transaction from an unknown future version is rejected.
+    let mut tx = zip_244_vectors()[0].tx.clone();
+    tx[0] = 6;
+    assert!(matches!(
+        inspect_transaction(&tx, true),
+        Err(TxidError::UnknownVersion(0x80000006))
+    ));
+    let mut status = 0;
+    assert!(zcash_transaction_inspect(tx.as_ptr(), tx.len(), true, &mut status).is_null());
+    assert_eq!(status, 2);
+}
@@ -0,0 +1,233 @@
+use std::borrow::Cow;
+
+use super::{decode_hex, ZIP_244_VECTORS};
+use crate::transaction_encoding::{
+    canonical_length, check_reserialization, minimize_compact_sizes, EncodingError, Violation,
+};
+use crate::transaction_ffi::{
+    calculate_txid, inspect_transaction, zcash_transaction_calculate_wtxid,
+    zcash_transaction_inspect, zcash_tx_info_free, TxidError,
+};
+
+/// Mainnet transactions from the `DecodeHexTx` fuzzing corpus, and the transaction
+/// `394b54bb` used by the `CTransaction` tests.
+const MAINNET_TRANSACTIONS: [&str; 5] = [
+    include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt"),
+    include_str!("../../../fuzzing/DecodeHexTx/input/manyoutputs.txt"),
+    include_str!("../../../fuzzing/DecodeHexTx/input/sapling.txt"),
+    include_str!("../../../fuzzing/DecodeHexTx/input/sprout.txt"),
+    include_str!("../../../test/data/tx394b54bb.hex"),
+];
+const V4_COINBASE: usize = 0;
+const V4_SAPLING: usize = 2;
+const V4_SPROUT: usize = 3;
+
+fn mainnet_transaction(index: usize) -> Vec<u8> {
+    decode_hex(MAINNET_TRANSACTIONS[index])
+}
+
+/// Returns the v5 transactions from the ZIP 244 test vectors.
+fn v5_transactions() -> Vec<Vec<u8>> {
+    ZIP_244_VECTORS
+        .split('"')
+        .filter(|s| s.starts_with("050000800a27a726"))
+        .map(decode_hex)
+        .collect()
+}
+
+/// Re-encodes the CompactSize at `offset` with the given flag byte, and so in more bytes
+/// than it needs.
+fn widen(tx: &[u8], offset: usize, flag: u8) -> Vec<u8> {
+    let (size, len) = match tx[offset] {
+        0xfd => (
+            u16::from_le_bytes([tx[offset + 1], tx[offset + 2]]) as u64,
+            3,
+        ),
+        0xfe => (
+            u32::from_le_bytes([
+                tx[offset + 1],
+                tx[offset + 2],
+                tx[offset + 3],
+                tx[offset + 4],
+            ]) as u64,
+            5,
+        ),
+        n => (n as u64, 1),
+    };
+    let encoded = match flag {
+        0xfd => [&[0xfd][..], &(size as u16).to_le_bytes()].concat(),
+        0xfe => [&[0xfe][..], &(size as u32).to_le_bytes()].concat(),
+        _ => [&[0xff][..], &size.to_le_bytes()].concat(),
+    };
+    let mut widened = tx.to_vec();
+    widened.splice(offset..offset + len, encoded);
+    widened
+}
+
+fn non_canonical(offset: usize, field: &str, violation: Violation) -> EncodingError {
+    EncodingError {
+        offset,
+        field: Some(field.into()),
+        violation,
+    }
+}
+
+#[test]
+fn mainnet_transactions_are_canonical() {
+    let transactions = MAINNET_TRANSACTIONS
+        .iter()
+        .map(|hex| decode_hex(hex))
+        .chain(v5_transactions());
+    for tx in transactions {
+        assert_eq!(canonical_length(&tx), Ok(tx.len()));
+        assert!(matches!(minimize_compact_sizes(&tx), Cow::Borrowed(_)));
+        assert!(calculate_txid(&tx).is_ok());
+        assert_eq!(
+            inspect_transaction(&tx, true).unwrap().summary,
+            inspect_transaction(&tx, false).unwrap().summary
+        );
+    }
+}
+
+#[test]
+fn rejects_non_minimal_compact_sizes() {
+    // The second nullifier of the first v5 vector, which ends its last Orchard action.
+    let v5 = v5_transactions().swap_remove(0);
+    let mut last_nullifier =
+        decode_hex("3379fc7bfce70e7c9c2107af8443015ceaa79c1a2e06676ac4f9d39aaf76eebc");
+    last_nullifier.reverse();
+    let actions_end = v5
+        .windows(32)
+        .position(|w| w == &last_nullifier[..])
+        .unwrap()
+        - 32
+        + 820;
+
+    let cases = [
+        (mainnet_transaction(V4_COINBASE), 8, "vin"),
+        (mainnet_transaction(V4_COINBASE), 45, "vin[0].scriptSig"),
+        (mainnet_transaction(V4_COINBASE), 92, "vout"),
+        (mainnet_transaction(V4_SAPLING), 26, "vShieldedSpend"),
+        (mainnet_transaction(V4_SPROUT), 28, "vJoinSplit"),
+        // After flagsOrchard, valueBalanceOrchard, and anchorOrchard.
+        (v5, actions_end + 1 + 8 + 32, "proofsOrchard"),
+    ];
+    for (tx, offset, field) in cases.iter() {
+        let summary = inspect_transaction(tx, true).unwrap().summary;
+        let widths: &[u8] = if tx[*offset] < 0xfd {
+            &[0xfd, 0xfe, 0xff]
+        } else {
+            &[0xfe, 0xff]
+        };
+        for flag in widths {
+            let widened = widen(tx, *offset, *flag);
+            let expected = non_canonical(*offset, field, Violation::NonMinimalCompactSize);
+            assert_eq!(canonical_length(&widened), Err(expected.clone()));
+            assert!(matches!(
+                calculate_txid(&widened),
+                Err(TxidError::NonCanonical(e)) if e == expected
+            ));
+
+            // The debug decoder can still make sense of it.
+            assert_eq!(minimize_compact_sizes(&widened), &tx[..]);
+            assert_eq!(
+                inspect_transaction(&widened, false).unwrap().summary,
+                summary
+            );
+
+            let mut txid = [0; 32];
+            assert_eq!(
+                zcash_transaction_calculate_wtxid(
+                    widened.as_ptr(),
+                    widened.len(),
+                    &mut txid,
+                    std::ptr::null_mut(),
+                ),
+                4
+            );
+            let mut status = 0;
+            assert!(
+                zcash_transaction_inspect(widened.as_ptr(), widened.len(), true, &mut status)
+                    .is_null()
+            );
+            assert_eq!(status, 4);
+            let info =
+                zcash_transaction_inspect(widened.as_ptr(), widened.len(), false, &mut status);
+            assert!(!info.is_null());
+            assert_eq!(status, 0);
+            zcash_tx_info_free(info);
+        }
+    }
+
+    // Every non-minimal CompactSize is re-encoded.
+    let tx = mainnet_transaction(V4_COINBASE);
+    let widened = widen(&widen(&tx, 92, 0xfe), 8, 0xff);
+    assert_eq!(minimize_compact_sizes(&widened), &tx[..]);
+}
+
+#[test]
+fn rejects_oversize_compact_sizes() {
+    // A scriptSig longer than `MAX_SIZE`, which `ReadCompactSize` refuses to allocate.
+    let mut tx = mainnet_transaction(V4_COINBASE);
+    tx.splice(45..46, [0xfe, 0x01, 0x00, 0x00, 0x02]);
+    let expected = non_canonical(45, "vin[0].scriptSig", Violation::OversizeCompactSize);
+    assert_eq!(canonical_length(&tx), Err(expected.clone()));
+    assert!(matches!(
+        calculate_txid(&tx),
+        Err(TxidError::NonCanonical(e)) if e == expected
+    ));
+}
+
+#[test]
+fn reports_truncation_and_trailing_bytes() {
+    let tx = mainnet_transaction(V4_SAPLING);
+
+    // A transaction cut short within its first Sapling spend.
+    let truncated = &tx[..100];
+    assert_eq!(
+        canonical_length(truncated),
+        Err(non_canonical(27, "vShieldedSpend[0]", Violation::Truncated))
+    );
+    match calculate_txid(truncated) {
+        Err(TxidError::Invalid(e)) => {
+            assert_eq!(
+                e.to_string(),
+                "Transaction is truncated in vShieldedSpend[0] at byte 27"
+            )
+        }
+        _ => panic!("Truncated transaction was accepted"),
+    }
+
+    // Trailing bytes are rejected in either mode.
+    let mut extended = tx.clone();
+    extended.extend_from_slice(&[0; 3]);
+    assert_eq!(canonical_length(&extended), Ok(tx.len()));
+    assert!(matches!(
+        calculate_txid(&extended),
+        Err(TxidError::TrailingBytes(3))
+    ));
+    assert!(matches!(
+        inspect_transaction(&extended, false),
+        Err(TxidError::TrailingBytes(3))
+    ));
+}
+
+#[test]
+fn reserialization_must_match() {
+    let tx = mainnet_transaction(V4_COINBASE);
+    assert_eq!(check_reserialization(&tx, &tx), Ok(()));
+
+    let mut normalized = tx.clone();
+    normalized[50] ^= 1;
+    let differs_at = |offset| EncodingError {
+        offset,
+        field: None,
+        violation: Violation::NotReserializable,
+    };
+    assert_eq!(check_reserialization(&tx, &normalized), Err(differs_at(50)));
+    assert_eq!(
+        check_reserialization(&tx, &tx[..tx.len() - 1]),
+        Err(differs_at(tx.len() - 1))
+    );
+    assert_eq!(check_reserialization(&tx[..40], &tx), Err(differs_at(40)));
+}
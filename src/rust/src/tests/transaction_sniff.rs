@@ -0,0 +1,211 @@
+use zcash_primitives::consensus::BranchId;
+
+use super::{decode_hex, v5_transaction};
+use crate::transaction_ffi::{sniff_transaction, zcash_transaction_sniff, SniffError, SniffResult};
+
+const V4_COINBASE: &str = include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt");
+
+/// Returns a minimal transaction with the given header fields, padded to `len` bytes.
+fn transaction(fields: &[u32], len: usize) -> Vec<u8> {
+    let mut tx: Vec<u8> = fields.iter().flat_map(|f| f.to_le_bytes()).collect();
+    tx.resize(len, 0);
+    tx
+}
+
+const OVERWINTERED: u32 = 1 << 31;
+
+#[test]
+fn sniffs_every_historical_version() {
+    let sprout = BranchId::Sprout.into();
+    let overwinter = BranchId::Overwinter.into();
+    let nu5 = BranchId::Nu5.into();
+
+    for version in [1, 2] {
+        assert_eq!(
+            sniff_transaction(&transaction(&[version], 100), sprout),
+            Ok(SniffResult {
+                overwintered: false,
+                version,
+                version_group_id: 0,
+                consensus_branch_id: None,
+                min_size: 9 + version as usize,
+                max_size: 100_000,
+            })
+        );
+    }
+
+    assert_eq!(
+        sniff_transaction(
+            &transaction(&[OVERWINTERED | 3, 0x03C48270], 100),
+            overwinter
+        ),
+        Ok(SniffResult {
+            overwintered: true,
+            version: 3,
+            version_group_id: 0x03C48270,
+            consensus_branch_id: None,
+            min_size: 19,
+            max_size: 100_000,
+        })
+    );
+
+    let v4 = decode_hex(V4_COINBASE);
+    for branch in [
+        BranchId::Sapling,
+        BranchId::Blossom,
+        BranchId::Heartwood,
+        BranchId::Canopy,
+        BranchId::Nu5,
+    ] {
+        assert_eq!(
+            sniff_transaction(&v4, branch.into()),
+            Ok(SniffResult {
+                overwintered: true,
+                version: 4,
+                version_group_id: 0x892F2085,
+                consensus_branch_id: None,
+                min_size: 29,
+                max_size: 2_000_000,
+            })
+        );
+    }
+
+    assert_eq!(
+        sniff_transaction(&v5_transaction(), nu5),
+        Ok(SniffResult {
+            overwintered: true,
+            version: 5,
+            version_group_id: 0x26A7270A,
+            consensus_branch_id: Some(nu5),
+            min_size: 25,
+            max_size: 2_000_000,
+        })
+    );
+
+    let mut max_size = 0;
+    let v5 = v5_transaction();
+    assert_eq!(
+        zcash_transaction_sniff(v5.as_ptr(), v5.len(), nu5, &mut max_size),
+        0
+    );
+    assert_eq!(max_size, 2_000_000);
+}
+
+#[test]
+fn rejects_inactive_and_unknown_formats() {
+    let v4 = decode_hex(V4_COINBASE);
+    let v5 = v5_transaction();
+
+    // v5 transactions before NU5 activation, and v4 transactions before Sapling.
+    for branch in [BranchId::Sapling, BranchId::Canopy] {
+        assert_eq!(
+            sniff_transaction(&v5, branch.into()),
+            Err(SniffError::VersionGroupNotActive(0x26A7270A))
+        );
+    }
+    assert_eq!(
+        sniff_transaction(&v4, BranchId::Overwinter.into()),
+        Err(SniffError::VersionGroupNotActive(0x892F2085))
+    );
+    assert_eq!(
+        sniff_transaction(&v4, BranchId::Sprout.into()),
+        Err(SniffError::OverwinterNotActive)
+    );
+    assert_eq!(
+        sniff_transaction(&transaction(&[2], 100), BranchId::Nu5.into()),
+        Err(SniffError::NotOverwintered)
+    );
+
+    // A v5 transaction committing to another branch.
+    let mut other_branch = v5.clone();
+    other_branch[8..12].copy_from_slice(&u32::from(BranchId::Canopy).to_le_bytes());
+    assert_eq!(
+        sniff_transaction(&other_branch, BranchId::Nu5.into()),
+        Err(SniffError::BranchIdMismatch(BranchId::Canopy.into()))
+    );
+
+    // Versions that don't match their version group, and unknown version groups.
+    let nu5 = BranchId::Nu5.into();
+    for (fields, error) in [
+        (
+            [OVERWINTERED | 5, 0x892F2085, nu5],
+            SniffError::InvalidVersion(5),
+        ),
+        (
+            [OVERWINTERED | 4, 0x26A7270A, nu5],
+            SniffError::InvalidVersion(4),
+        ),
+        (
+            [OVERWINTERED | 6, 0x26A7270A, nu5],
+            SniffError::InvalidVersion(6),
+        ),
+        (
+            [OVERWINTERED | 6, 0xFFFFFFFF, nu5],
+            SniffError::UnknownVersionGroup(0xFFFFFFFF),
+        ),
+    ] {
+        assert_eq!(
+            sniff_transaction(&transaction(&fields, 100), nu5),
+            Err(error)
+        );
+    }
+    assert_eq!(
+        sniff_transaction(&transaction(&[0], 100), BranchId::Sprout.into()),
+        Err(SniffError::InvalidVersion(0))
+    );
+    assert_eq!(
+        sniff_transaction(&v5, 0x12345678),
+        Err(SniffError::UnknownBranch(0x12345678))
+    );
+
+    // Size limits.
+    let mut oversized = v4;
+    oversized.resize(2_000_001, 0);
+    assert_eq!(
+        sniff_transaction(&oversized, nu5),
+        Err(SniffError::Oversize(2_000_001))
+    );
+    assert_eq!(
+        sniff_transaction(&transaction(&[2], 100_001), BranchId::Sprout.into()),
+        Err(SniffError::Oversize(100_001))
+    );
+    assert_eq!(
+        zcash_transaction_sniff(
+            v5.as_ptr(),
+            v5.len(),
+            BranchId::Canopy.into(),
+            std::ptr::null_mut()
+        ),
+        7
+    );
+}
+
+#[test]
+fn rejects_truncated_headers() {
+    let nu5 = BranchId::Nu5.into();
+    let v5 = v5_transaction();
+
+    // Each header field must be present, and only the header is read.
+    for len in [0, 3, 4, 7, 8, 11] {
+        assert_eq!(
+            sniff_transaction(&v5[..len], nu5),
+            Err(SniffError::Truncated)
+        );
+    }
+    assert_eq!(
+        sniff_transaction(&v5[..24], nu5),
+        Err(SniffError::Truncated)
+    );
+    assert!(sniff_transaction(&v5[..25], nu5).is_ok());
+
+    let v4 = decode_hex(V4_COINBASE);
+    assert_eq!(
+        sniff_transaction(&v4[..28], nu5),
+        Err(SniffError::Truncated)
+    );
+    assert!(sniff_transaction(&v4[..29], nu5).is_ok());
+    assert_eq!(
+        sniff_transaction(&transaction(&[1], 9), BranchId::Sprout.into()),
+        Err(SniffError::Truncated)
+    );
+}
@@ -0,0 +1,504 @@
+use std::convert::TryInto;
+use std::ptr;
+
+use zcash_primitives::consensus::BranchId;
+
+use super::{decode_hex, v5_transaction};
+use crate::transaction_ffi::{
+    check_transaction_structure, zcash_transaction_check_structure, RuleViolation,
+};
+
+const V4_COINBASE: &str = include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt");
+const V4_SAPLING: &str = include_str!("../../../fuzzing/DecodeHexTx/input/sapling.txt");
+const V4_SPROUT: &str = include_str!("../../../fuzzing/DecodeHexTx/input/sprout.txt");
+
+const MAX_MONEY: i64 = 21_000_000 * 100_000_000;
+
+/// Returns the offset of the given 32-byte value, displayed as in `uint256::GetHex`.
+fn find(tx: &[u8], display_hex: &str) -> usize {
+    let mut bytes = decode_hex(display_hex);
+    bytes.reverse();
+    tx.windows(32).position(|w| w == &bytes[..]).unwrap()
+}
+
+/// The offsets of the two Orchard nullifiers in [`v5_transaction`].
+fn v5_orchard_nullifiers(tx: &[u8]) -> (usize, usize) {
+    (
+        find(
+            tx,
+            "3270732e942afb3be71ceaa0064216695deb69b42ef925e8953ecf6e564553ee",
+        ),
+        find(
+            tx,
+            "3379fc7bfce70e7c9c2107af8443015ceaa79c1a2e06676ac4f9d39aaf76eebc",
+        ),
+    )
+}
+
+/// The offset of the Orchard flags in [`v5_transaction`], which follow the 820-byte
+/// actions.
+fn v5_orchard_flags(tx: &[u8]) -> usize {
+    let (_, nf) = v5_orchard_nullifiers(tx);
+    nf - 32 + 820
+}
+
+/// The prevout of a coinbase input.
+fn null_prevout() -> [u8; 36] {
+    let mut prevout = [0; 36];
+    prevout[32..].copy_from_slice(&[0xff; 4]);
+    prevout
+}
+
+pub(super) fn prevout(n: u8) -> [u8; 36] {
+    [n; 36]
+}
+
+fn compact_size(n: usize) -> Vec<u8> {
+    match n {
+        0..=0xfc => vec![n as u8],
+        0xfd..=0xffff => [&[0xfd][..], &(n as u16).to_le_bytes()].concat(),
+        _ => [&[0xfe][..], &(n as u32).to_le_bytes()].concat(),
+    }
+}
+
+/// Returns the encoding of a transparent input spending `prevout`, with a scriptSig of
+/// `script_sig_len` bytes.
+fn txin(prevout: [u8; 36], script_sig_len: usize) -> Vec<u8> {
+    let mut txin = prevout.to_vec();
+    txin.extend(compact_size(script_sig_len));
+    txin.resize(txin.len() + script_sig_len, 0x51);
+    txin.extend_from_slice(&u32::MAX.to_le_bytes());
+    txin
+}
+
+/// Returns a v3 or v4 transaction with only the given transparent inputs, each a
+/// prevout and the length of its scriptSig, and outputs of the given values.
+pub(super) fn transparent_transaction(
+    version: u32,
+    inputs: &[([u8; 36], usize)],
+    outputs: &[i64],
+    expiry_height: u32,
+) -> Vec<u8> {
+    let version_group_id: u32 = if version == 3 { 0x03C48270 } else { 0x892F2085 };
+    let mut tx: Vec<u8> = [(1 << 31) | version, version_group_id]
+        .iter()
+        .flat_map(|f| f.to_le_bytes())
+        .collect();
+    tx.extend(compact_size(inputs.len()));
+    for (prevout, script_sig_len) in inputs {
+        tx.extend(txin(*prevout, *script_sig_len));
+    }
+    tx.extend(compact_size(outputs.len()));
+    for value in outputs {
+        tx.extend_from_slice(&value.to_le_bytes());
+        // An empty scriptPubKey.
+        tx.push(0);
+    }
+    // nLockTime and nExpiryHeight.
+    tx.extend_from_slice(&[0; 4]);
+    tx.extend_from_slice(&expiry_height.to_le_bytes());
+    if version == 4 {
+        // valueBalanceSapling, and no Sapling spends or outputs.
+        tx.extend_from_slice(&[0; 10]);
+    }
+    // No JoinSplits.
+    tx.push(0);
+    tx
+}
+
+/// Replaces the empty vin of a v4 transaction with a coinbase input.
+pub(super) fn make_coinbase(mut tx: Vec<u8>) -> Vec<u8> {
+    assert_eq!(tx[8], 0);
+    let coinbase = [&[1][..], &txin(null_prevout(), 4)].concat();
+    tx.splice(8..9, coinbase);
+    tx
+}
+
+fn check(tx: &[u8], height: u32, branch: BranchId) -> Result<(), Vec<RuleViolation>> {
+    check_transaction_structure(tx, height, branch.into())
+}
+
+#[test]
+fn accepts_valid_transactions() {
+    assert_eq!(
+        check(&decode_hex(V4_COINBASE), 621_415, BranchId::Blossom),
+        Ok(())
+    );
+    assert_eq!(
+        check(&decode_hex(V4_SAPLING), 625_388, BranchId::Canopy),
+        Ok(())
+    );
+    assert_eq!(
+        check(&decode_hex(V4_SPROUT), 625_389, BranchId::Canopy),
+        Ok(())
+    );
+    assert_eq!(check(&v5_transaction(), 1_687_104, BranchId::Nu5), Ok(()));
+
+    let tx = v5_transaction();
+    assert_eq!(
+        zcash_transaction_check_structure(
+            tx.as_ptr(),
+            tx.len(),
+            1_687_104,
+            BranchId::Nu5.into(),
+            ptr::null_mut(),
+            0,
+        ),
+        0
+    );
+}
+
+#[test]
+fn rejects_transactions_without_sources_or_sinks() {
+    let canopy = BranchId::Canopy;
+    assert_eq!(
+        check(&transparent_transaction(4, &[], &[], 0), 1, canopy),
+        Err(vec![
+            RuleViolation::NoSourceOfFunds,
+            RuleViolation::NoSinkOfFunds
+        ])
+    );
+    assert_eq!(
+        check(&transparent_transaction(4, &[], &[1], 0), 1, canopy),
+        Err(vec![RuleViolation::NoSourceOfFunds])
+    );
+    assert_eq!(
+        check(
+            &transparent_transaction(4, &[(prevout(1), 1)], &[], 0),
+            1,
+            canopy
+        ),
+        Err(vec![RuleViolation::NoSinkOfFunds])
+    );
+
+    // Orchard actions are only a source or sink of funds if the flags enable them.
+    let mut tx = v5_transaction();
+    // Drop the transparent input and the Sapling bundle, which lie between the vin and
+    // nSpendsSapling counts and between nSpendsSapling and nActionsOrchard.
+    let sapling_spends = find(
+        &tx,
+        "d4f76a993d20c7e81393fdb51d55d7a8f5621d6ad566c13dce81632a9168694b",
+    ) - 33;
+    let orchard_actions = v5_orchard_nullifiers(&tx).0 - 33;
+    assert_eq!((tx[sapling_spends], tx[orchard_actions]), (1, 2));
+    tx.splice(sapling_spends..orchard_actions, [0, 0]);
+    tx.splice(20..sapling_spends - 1, [0]);
+    assert_eq!(tx[20..25], [0, 0, 0, 0, 2]);
+    let flags = v5_orchard_flags(&tx);
+    assert_eq!(check(&tx, 1_687_104, BranchId::Nu5), Ok(()));
+    tx[flags] = 2;
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![RuleViolation::NoSourceOfFunds])
+    );
+    tx[flags] = 1;
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![RuleViolation::NoSinkOfFunds])
+    );
+}
+
+/// Returns a transaction of exactly `size` bytes, padded out by its scriptSig.
+fn transaction_of_size(version: u32, size: usize) -> Vec<u8> {
+    let padding = 0x10000;
+    let base = transparent_transaction(version, &[(prevout(1), padding)], &[1], 0).len();
+    let tx = transparent_transaction(version, &[(prevout(1), padding + size - base)], &[1], 0);
+    assert_eq!(tx.len(), size);
+    tx
+}
+
+#[test]
+fn rejects_oversize_transactions() {
+    assert_eq!(
+        check(&transaction_of_size(3, 100_000), 1, BranchId::Overwinter),
+        Ok(())
+    );
+    assert_eq!(
+        check(&transaction_of_size(3, 100_001), 1, BranchId::Overwinter),
+        Err(vec![RuleViolation::Oversize(100_001)])
+    );
+    assert_eq!(
+        check(&transaction_of_size(4, 100_001), 1, BranchId::Sapling),
+        Ok(())
+    );
+    assert_eq!(
+        check(&transaction_of_size(4, 2_000_000), 1, BranchId::Nu5),
+        Ok(())
+    );
+    assert_eq!(
+        check(&transaction_of_size(4, 2_000_001), 1, BranchId::Nu5),
+        Err(vec![RuleViolation::Oversize(2_000_001)])
+    );
+}
+
+#[test]
+fn rejects_value_totals_out_of_range() {
+    let heartwood = BranchId::Heartwood;
+    let tx = |outputs: &[i64]| transparent_transaction(4, &[(prevout(1), 1)], outputs, 0);
+    assert_eq!(check(&tx(&[MAX_MONEY]), 1, heartwood), Ok(()));
+    assert_eq!(
+        check(&tx(&[MAX_MONEY, 1]), 1, heartwood),
+        Err(vec![RuleViolation::TxOutTotalTooLarge])
+    );
+    assert_eq!(
+        check(&tx(&[MAX_MONEY; 3]), 1, heartwood),
+        Err(vec![RuleViolation::TxOutTotalTooLarge])
+    );
+
+    // The JoinSplit has vpub_new = 10000. A valueBalanceSapling without Sapling spends
+    // or outputs is invalid, but still counts towards the totals.
+    let sprout = decode_hex(V4_SPROUT);
+    let mut tx = sprout.clone();
+    tx[18..26].copy_from_slice(&(MAX_MONEY - 10_000).to_le_bytes());
+    assert_eq!(
+        check(&tx, 1, heartwood),
+        Err(vec![RuleViolation::ValueBalanceNonzero])
+    );
+    tx[18..26].copy_from_slice(&(MAX_MONEY - 9_999).to_le_bytes());
+    assert_eq!(
+        check(&tx, 1, heartwood),
+        Err(vec![
+            RuleViolation::ValueBalanceNonzero,
+            RuleViolation::TxInTotalTooLarge
+        ])
+    );
+    let mut tx = sprout;
+    tx[18..26].copy_from_slice(&(-MAX_MONEY).to_le_bytes());
+    tx[29..37].copy_from_slice(&1u64.to_le_bytes());
+    tx[37..45].copy_from_slice(&0u64.to_le_bytes());
+    assert_eq!(
+        check(&tx, 1, heartwood),
+        Err(vec![
+            RuleViolation::TxOutTotalTooLarge,
+            RuleViolation::ValueBalanceNonzero
+        ])
+    );
+
+    // The Sapling and Orchard value balances are both positive, and are added together.
+    let mut tx = v5_transaction();
+    let value_balance = find(
+        &tx,
+        "12bdedff3959747e45905ebc9625e51124c0f0b9ec3222194b507265f2a6a475",
+    ) - 32
+        + 756;
+    assert_eq!(
+        i64::from_le_bytes(tx[value_balance..value_balance + 8].try_into().unwrap()),
+        202285658676901
+    );
+    tx[value_balance..value_balance + 8].copy_from_slice(&MAX_MONEY.to_le_bytes());
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![RuleViolation::TxInTotalTooLarge])
+    );
+}
+
+#[test]
+fn rejects_invalid_joinsplits() {
+    let sprout = decode_hex(V4_SPROUT);
+    let mut tx = sprout.clone();
+    tx[29..37].copy_from_slice(&1u64.to_le_bytes());
+    assert_eq!(
+        check(&tx, 1, BranchId::Heartwood),
+        Err(vec![RuleViolation::VpubsBothNonzero])
+    );
+    assert_eq!(
+        check(&tx, 1, BranchId::Canopy),
+        Err(vec![
+            RuleViolation::VpubsBothNonzero,
+            RuleViolation::VpubOldNonzero
+        ])
+    );
+    tx[37..45].copy_from_slice(&0u64.to_le_bytes());
+    assert_eq!(check(&tx, 1, BranchId::Heartwood), Ok(()));
+    assert_eq!(
+        check(&tx, 1, BranchId::Canopy),
+        Err(vec![RuleViolation::VpubOldNonzero])
+    );
+
+    let mut tx = sprout;
+    tx.copy_within(77..109, 109);
+    assert_eq!(
+        check(&tx, 1, BranchId::Canopy),
+        Err(vec![RuleViolation::DuplicateSproutNullifiers])
+    );
+}
+
+#[test]
+fn rejects_duplicate_inputs_and_nullifiers() {
+    let canopy = BranchId::Canopy;
+    assert_eq!(
+        check(
+            &transparent_transaction(4, &[(prevout(1), 1), (prevout(2), 1)], &[1], 0),
+            1,
+            canopy
+        ),
+        Ok(())
+    );
+    assert_eq!(
+        check(
+            &transparent_transaction(4, &[(prevout(1), 1), (prevout(1), 2)], &[1], 0),
+            1,
+            canopy
+        ),
+        Err(vec![RuleViolation::DuplicateInputs])
+    );
+
+    let mut tx = decode_hex(V4_SAPLING);
+    assert_eq!(tx[26], 1);
+    let spend = tx[27..411].to_vec();
+    tx[26] = 2;
+    tx.splice(411..411, spend);
+    assert_eq!(
+        check(&tx, 1, canopy),
+        Err(vec![RuleViolation::DuplicateSaplingNullifiers])
+    );
+
+    let mut tx = v5_transaction();
+    let (nf0, nf1) = v5_orchard_nullifiers(&tx);
+    tx.copy_within(nf0..nf0 + 32, nf1);
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![RuleViolation::DuplicateOrchardNullifiers])
+    );
+}
+
+#[test]
+fn rejects_orchard_actions_with_no_enabled_flags() {
+    let mut tx = v5_transaction();
+    let flags = v5_orchard_flags(&tx);
+    assert_eq!(tx[flags], 3);
+    tx[flags] = 0;
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![RuleViolation::OrchardFlagsDisableActions])
+    );
+}
+
+#[test]
+fn checks_coinbase_rules() {
+    let canopy = BranchId::Canopy;
+    let coinbase =
+        |script_sig_len| transparent_transaction(4, &[(null_prevout(), script_sig_len)], &[1], 0);
+    for len in [2, 100] {
+        assert_eq!(check(&coinbase(len), 1, canopy), Ok(()));
+    }
+    for len in [0, 1, 101] {
+        assert_eq!(
+            check(&coinbase(len), 1, canopy),
+            Err(vec![RuleViolation::CoinbaseLength(len)])
+        );
+    }
+
+    // A null prevout is only allowed as the sole input.
+    assert_eq!(
+        check(
+            &transparent_transaction(4, &[(prevout(1), 1), (null_prevout(), 2)], &[1], 0),
+            1,
+            canopy
+        ),
+        Err(vec![RuleViolation::NullPrevout])
+    );
+
+    assert_eq!(
+        check(&make_coinbase(decode_hex(V4_SPROUT)), 1, canopy),
+        Err(vec![RuleViolation::CoinbaseHasJoinSplits])
+    );
+    // Sapling outputs are only allowed in coinbase transactions from Heartwood.
+    let tx = make_coinbase(decode_hex(V4_SAPLING));
+    assert_eq!(
+        check(&tx, 1, BranchId::Heartwood),
+        Err(vec![RuleViolation::CoinbaseHasSpendDescriptions])
+    );
+    assert_eq!(
+        check(&tx, 1, BranchId::Blossom),
+        Err(vec![
+            RuleViolation::CoinbaseHasSpendDescriptions,
+            RuleViolation::CoinbaseHasOutputDescriptions
+        ])
+    );
+
+    let mut tx = v5_transaction();
+    tx[21..57].copy_from_slice(&null_prevout());
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![
+            RuleViolation::CoinbaseHasSpendDescriptions,
+            RuleViolation::CoinbaseHasOrchardSpend
+        ])
+    );
+    let flags = v5_orchard_flags(&tx);
+    tx[flags] = 2;
+    assert_eq!(
+        check(&tx, 1_687_104, BranchId::Nu5),
+        Err(vec![RuleViolation::CoinbaseHasSpendDescriptions])
+    );
+}
+
+#[test]
+fn checks_expiry_heights() {
+    let canopy = BranchId::Canopy;
+    let nu5 = BranchId::Nu5;
+    let tx = |inputs: &[([u8; 36], usize)], expiry_height| {
+        transparent_transaction(4, inputs, &[1], expiry_height)
+    };
+    let spend = [(prevout(1), 1)];
+    let coinbase = [(null_prevout(), 2)];
+
+    assert_eq!(check(&tx(&spend, 10), 10, canopy), Ok(()));
+    assert_eq!(
+        check(&tx(&spend, 10), 11, canopy),
+        Err(vec![RuleViolation::Expired])
+    );
+    assert_eq!(check(&tx(&coinbase, 10), 11, canopy), Ok(()));
+    assert_eq!(check(&tx(&spend, 0), 1_000_000, canopy), Ok(()));
+
+    assert_eq!(check(&tx(&spend, 499_999_999), 1, canopy), Ok(()));
+    for branch in [canopy, nu5] {
+        assert_eq!(
+            check(&tx(&spend, 500_000_000), 1, branch),
+            Err(vec![RuleViolation::ExpiryHeightTooHigh(500_000_000)])
+        );
+    }
+    // ZIP 203 removes the limit for coinbase transactions from NU5.
+    assert_eq!(
+        check(&tx(&coinbase, 500_000_000), 1, canopy),
+        Err(vec![RuleViolation::ExpiryHeightTooHigh(500_000_000)])
+    );
+    assert_eq!(check(&tx(&coinbase, 500_000_000), 1, nu5), Ok(()));
+}
+
+#[test]
+fn reports_violations_through_ffi() {
+    let check_ffi = |tx: &[u8], branch_id: u32, violations_len: usize| {
+        let mut violations = vec![u32::MAX; violations_len];
+        let count = zcash_transaction_check_structure(
+            tx.as_ptr(),
+            tx.len(),
+            1,
+            branch_id,
+            violations.as_mut_ptr(),
+            violations_len,
+        );
+        (count, violations)
+    };
+    let canopy = BranchId::Canopy.into();
+
+    let empty = transparent_transaction(4, &[], &[], 0);
+    assert_eq!(check_ffi(&empty, canopy, 3), (2, vec![2, 3, u32::MAX]));
+    assert_eq!(check_ffi(&empty, canopy, 1), (2, vec![2]));
+    assert_eq!(check_ffi(&empty, canopy, 0), (2, vec![]));
+
+    assert_eq!(
+        check_transaction_structure(&empty, 1, 0xdeadbeef),
+        Err(vec![RuleViolation::UnknownBranch(0xdeadbeef)])
+    );
+    assert_eq!(check_ffi(&empty, 0xdeadbeef, 2), (1, vec![0, u32::MAX]));
+
+    let mut trailing = empty;
+    trailing.push(0);
+    assert_eq!(
+        check(&trailing, 1, BranchId::Canopy),
+        Err(vec![RuleViolation::Malformed])
+    );
+    assert_eq!(check_ffi(&trailing, canopy, 1), (1, vec![1]));
+}
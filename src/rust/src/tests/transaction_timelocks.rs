@@ -0,0 +1,144 @@
+use super::transaction_structure::{make_coinbase, prevout, transparent_transaction};
+use crate::transaction_ffi::{
+    evaluate_tx_timelocks, zcash_transaction_evaluate_timelocks, TimelockStatus,
+};
+
+const EXPIRY_HEIGHT: u32 = 1_000_000;
+const LOCK_TIME: u32 = 1_650_000_000;
+
+/// Returns a v4 transaction with an input for each of `sequences`, and the given
+/// nLockTime.
+fn locked_transaction(lock_time: u32, sequences: &[u32], expiry_height: u32) -> Vec<u8> {
+    let inputs = (1..=sequences.len())
+        .map(|n| (prevout(n as u8), 2))
+        .collect::<Vec<_>>();
+    let mut tx = transparent_transaction(4, &inputs, &[1000], expiry_height);
+    // Each input is a 36-byte prevout and a two-byte scriptSig with its length, followed
+    // by nSequence.
+    for (i, sequence) in sequences.iter().enumerate() {
+        let offset = 9 + 43 * i + 39;
+        tx[offset..offset + 4].copy_from_slice(&sequence.to_le_bytes());
+    }
+    // nLockTime is followed by nExpiryHeight, valueBalanceSapling, and the empty
+    // Sapling and JoinSplit fields.
+    let offset = tx.len() - 19;
+    tx[offset..offset + 4].copy_from_slice(&lock_time.to_le_bytes());
+    tx
+}
+
+fn evaluate(tx: &[u8], next_block_height: u32) -> TimelockStatus {
+    evaluate_tx_timelocks(tx, next_block_height, i64::from(LOCK_TIME), 0).unwrap()
+}
+
+#[test]
+fn evaluates_expiry_at_boundary_heights() {
+    let tx = locked_transaction(0, &[u32::MAX], EXPIRY_HEIGHT);
+    let expiry = |height| {
+        let status = evaluate(&tx, height);
+        (status.expired, status.expiring_soon)
+    };
+
+    assert_eq!(expiry(EXPIRY_HEIGHT - 3), (false, false));
+    assert_eq!(expiry(EXPIRY_HEIGHT - 2), (false, true));
+    // A transaction can still be mined at its expiry height.
+    assert_eq!(expiry(EXPIRY_HEIGHT), (false, true));
+    assert_eq!(expiry(EXPIRY_HEIGHT + 1), (true, true));
+    assert_eq!(expiry(u32::MAX), (true, true));
+
+    // Neither coinbase transactions nor an expiry height of 0 expire.
+    let coinbase = make_coinbase(transparent_transaction(4, &[], &[1000], EXPIRY_HEIGHT));
+    assert_eq!(
+        evaluate(&coinbase, u32::MAX),
+        TimelockStatus {
+            lock_time_by_height: true,
+            lock_time_by_time: true,
+            lock_time_by_median_time_past: true,
+            ..Default::default()
+        }
+    );
+    let tx = locked_transaction(0, &[u32::MAX], 0);
+    assert!(!evaluate(&tx, u32::MAX).expiring_soon);
+}
+
+#[test]
+fn evaluates_lock_times() {
+    // A lock time below the threshold is a height, which the next block must be above.
+    let tx = locked_transaction(EXPIRY_HEIGHT - 10, &[0, u32::MAX], EXPIRY_HEIGHT);
+    let by_height = |height| evaluate(&tx, height).lock_time_by_height;
+    assert!(!by_height(EXPIRY_HEIGHT - 10));
+    assert!(by_height(EXPIRY_HEIGHT - 9));
+    assert!(!evaluate(&tx, EXPIRY_HEIGHT).lock_time_by_time);
+
+    // Otherwise it is a time, checked against both cutoffs.
+    let tx = locked_transaction(LOCK_TIME, &[0, u32::MAX], EXPIRY_HEIGHT);
+    let at = |next_block_time: u32, median_time_past: u32| {
+        let status = evaluate_tx_timelocks(
+            &tx,
+            EXPIRY_HEIGHT,
+            next_block_time.into(),
+            median_time_past.into(),
+        )
+        .unwrap();
+        assert!(!status.lock_time_by_height);
+        (
+            status.lock_time_by_time,
+            status.lock_time_by_median_time_past,
+        )
+    };
+    assert_eq!(at(LOCK_TIME, LOCK_TIME), (false, false));
+    assert_eq!(at(LOCK_TIME + 1, LOCK_TIME), (true, false));
+    assert_eq!(at(LOCK_TIME + 1, LOCK_TIME + 1), (true, true));
+
+    // Inputs with final sequence numbers bypass the lock time, but only if all do.
+    let unlocked = TimelockStatus {
+        expiring_soon: true,
+        lock_time_by_height: true,
+        lock_time_by_time: true,
+        lock_time_by_median_time_past: true,
+        ..Default::default()
+    };
+    for lock_time in [EXPIRY_HEIGHT + 10, LOCK_TIME + 10] {
+        let tx = locked_transaction(lock_time, &[u32::MAX, u32::MAX], EXPIRY_HEIGHT);
+        assert_eq!(evaluate(&tx, EXPIRY_HEIGHT), unlocked);
+        let tx = locked_transaction(lock_time, &[u32::MAX, u32::MAX - 1], EXPIRY_HEIGHT);
+        assert_ne!(evaluate(&tx, EXPIRY_HEIGHT), unlocked);
+    }
+    // As does a lock time of 0.
+    let tx = locked_transaction(0, &[0], EXPIRY_HEIGHT);
+    assert_eq!(evaluate(&tx, EXPIRY_HEIGHT), unlocked);
+}
+
+#[test]
+fn evaluates_timelocks_through_ffi() {
+    let tx = locked_transaction(LOCK_TIME, &[0], EXPIRY_HEIGHT);
+    let mut status = TimelockStatus::default();
+    assert!(zcash_transaction_evaluate_timelocks(
+        tx.as_ptr(),
+        tx.len(),
+        EXPIRY_HEIGHT + 1,
+        i64::from(LOCK_TIME) + 1,
+        0,
+        &mut status,
+    ));
+    assert_eq!(
+        status,
+        TimelockStatus {
+            expired: true,
+            expiring_soon: true,
+            lock_time_by_time: true,
+            ..Default::default()
+        }
+    );
+
+    // A transaction that can't be parsed leaves the status alone.
+    let before = status;
+    assert!(!zcash_transaction_evaluate_timelocks(
+        tx.as_ptr(),
+        tx.len() - 1,
+        EXPIRY_HEIGHT,
+        0,
+        0,
+        &mut status,
+    ));
+    assert_eq!(status, before);
+}
@@ -0,0 +1,185 @@
+use super::{decode_hex, v5_transaction};
+use crate::transaction_ffi::{
+    block_pool_value_deltas, transaction_value_balances, zcash_block_pool_value_deltas,
+    PoolValueDeltas, TxidError, ValueBalanceError, ValueBalances,
+};
+
+const V4_COINBASE: &str = include_str!("../../../fuzzing/DecodeHexTx/input/coinbase.txt");
+const V4_MANY_OUTPUTS: &str = include_str!("../../../fuzzing/DecodeHexTx/input/manyoutputs.txt");
+const V4_SAPLING: &str = include_str!("../../../fuzzing/DecodeHexTx/input/sapling.txt");
+const V4_SPROUT: &str = include_str!("../../../fuzzing/DecodeHexTx/input/sprout.txt");
+
+/// Mainnet blocks from the `CheckBlock` fuzzing corpus: the genesis block, which has
+/// no JoinSplit field, and two Sapling-era blocks with only transparent transactions.
+const MAINNET_BLOCKS: [&[u8]; 3] = [
+    include_bytes!("../../../fuzzing/CheckBlock/input/0.bin"),
+    include_bytes!("../../../fuzzing/CheckBlock/input/476431.bin"),
+    include_bytes!("../../../fuzzing/CheckBlock/input/620000.bin"),
+];
+
+/// The length of a mainnet block header, including its 1344-byte Equihash solution.
+const BLOCK_HEADER_SIZE: usize = 140 + 3 + 1344;
+
+/// Returns a block with the header of block 620000, and the given transactions.
+fn block(transactions: &[Vec<u8>]) -> Vec<u8> {
+    let mut block = MAINNET_BLOCKS[2][..BLOCK_HEADER_SIZE].to_vec();
+    block.push(transactions.len() as u8);
+    for tx in transactions {
+        block.extend_from_slice(tx);
+    }
+    block
+}
+
+#[test]
+fn transaction_value_balances_by_pool() {
+    assert_eq!(
+        transaction_value_balances(&decode_hex(V4_COINBASE), None).unwrap(),
+        ValueBalances {
+            transparent_in: Some(0),
+            transparent_out: 1_250_021_567,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        transaction_value_balances(&decode_hex(V4_SPROUT), None).unwrap(),
+        ValueBalances {
+            transparent_in: Some(0),
+            sprout_vpub_new: 10_000,
+            ..Default::default()
+        }
+    );
+    assert_eq!(
+        transaction_value_balances(&decode_hex(V4_SAPLING), Some(&[])).unwrap(),
+        ValueBalances {
+            transparent_in: Some(0),
+            sapling_value_balance: 10_000,
+            ..Default::default()
+        }
+    );
+    // The first ZIP 244 vector spends a transparent input, and has both Sapling and
+    // Orchard bundles.
+    assert_eq!(
+        transaction_value_balances(&v5_transaction(), Some(&[5])).unwrap(),
+        ValueBalances {
+            transparent_in: Some(5),
+            transparent_out: 0,
+            sprout_vpub_old: 0,
+            sprout_vpub_new: 0,
+            sapling_value_balance: 202_285_658_676_901,
+            orchard_value_balance: 614_922_616_112_471,
+        }
+    );
+
+    // The values of spent outputs are only known if they are provided.
+    let many_outputs = decode_hex(V4_MANY_OUTPUTS);
+    let balances = transaction_value_balances(&many_outputs, None).unwrap();
+    assert_eq!(balances.transparent_in, None);
+    assert_eq!(balances.transparent_out, 66_138_883_215);
+    assert_eq!(
+        transaction_value_balances(&many_outputs, Some(&[66_000_000_000, 148_883_215]))
+            .unwrap()
+            .transparent_in,
+        Some(66_148_883_215)
+    );
+    assert!(matches!(
+        transaction_value_balances(&many_outputs, Some(&[1])),
+        Err(ValueBalanceError::SpentValuesMismatch {
+            inputs: 2,
+            values: 1
+        })
+    ));
+    assert!(matches!(
+        transaction_value_balances(&many_outputs, Some(&[i64::MAX, 1])),
+        Err(ValueBalanceError::Overflow)
+    ));
+}
+
+#[test]
+fn mainnet_blocks_have_no_shielded_value_flow() {
+    for block in MAINNET_BLOCKS.iter() {
+        assert_eq!(
+            block_pool_value_deltas(block).unwrap(),
+            PoolValueDeltas::default()
+        );
+    }
+}
+
+#[test]
+fn block_pool_value_deltas_sum_over_transactions() {
+    let block = block(&[
+        decode_hex(V4_COINBASE),
+        decode_hex(V4_SPROUT),
+        decode_hex(V4_SAPLING),
+        v5_transaction(),
+    ]);
+    let expected = PoolValueDeltas {
+        sprout_value: -10_000,
+        sapling_value: -10_000 - 202_285_658_676_901,
+        orchard_value: -614_922_616_112_471,
+    };
+    assert_eq!(block_pool_value_deltas(&block).unwrap(), expected);
+
+    let mut deltas = PoolValueDeltas::default();
+    assert!(zcash_block_pool_value_deltas(
+        block.as_ptr(),
+        block.len(),
+        &mut deltas
+    ));
+    assert_eq!(deltas, expected);
+
+    // A truncated block, or one with trailing bytes, is rejected.
+    let mut deltas = PoolValueDeltas::default();
+    assert!(!zcash_block_pool_value_deltas(
+        block.as_ptr(),
+        block.len() - 1,
+        &mut deltas
+    ));
+    assert_eq!(deltas, PoolValueDeltas::default());
+    assert!(matches!(
+        block_pool_value_deltas(&block[..block.len() - 1]),
+        Err(ValueBalanceError::InvalidBlockTransaction(
+            3,
+            TxidError::Invalid(_)
+        ))
+    ));
+    let mut extended = block;
+    extended.push(0);
+    assert!(matches!(
+        block_pool_value_deltas(&extended),
+        Err(ValueBalanceError::InvalidBlock(_))
+    ));
+    assert!(matches!(
+        block_pool_value_deltas(&extended[..100]),
+        Err(ValueBalanceError::InvalidBlock(_))
+    ));
+}
+
+#[test]
+fn pool_value_deltas_overflow() {
+    let sapling = transaction_value_balances(&decode_hex(V4_SAPLING), None).unwrap();
+    let deltas = PoolValueDeltas {
+        sapling_value: i64::MIN + 10_000,
+        ..Default::default()
+    };
+    assert_eq!(
+        deltas.checked_add(&sapling),
+        Some(PoolValueDeltas {
+            sapling_value: i64::MIN,
+            ..Default::default()
+        })
+    );
+    assert_eq!(
+        deltas.checked_add(&sapling).unwrap().checked_add(&sapling),
+        None
+    );
+
+    let sprout = ValueBalances {
+        sprout_vpub_old: i64::MAX,
+        ..Default::default()
+    };
+    let deltas = PoolValueDeltas {
+        sprout_value: 1,
+        ..Default::default()
+    };
+    assert_eq!(deltas.checked_add(&sprout), None);
+}
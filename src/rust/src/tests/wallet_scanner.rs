@@ -0,0 +1,242 @@
+use std::ptr::NonNull;
+use std::slice;
+
+use group::{ff::Field, Group};
+use libc::{c_long, size_t};
+use orchard::{
+    bundle::Authorized,
+    keys::{FullViewingKey, SpendingKey},
+    Bundle,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use zcash_primitives::{
+    consensus::BlockHeight,
+    sapling::note_encryption::sapling_ka_agree,
+    transaction::{components::Amount, TxId},
+};
+
+use crate::{
+    streams_ffi::StreamObj,
+    test_wallet_ffi::FakeNote,
+    wallet::{orchard_wallet_add_spending_key, orchard_wallet_write_note_commitment_tree, Wallet},
+    wallet_scanner::{rescan, sapling_ka_agree_ivks, PreparedKeyCache, RescanError, ScanBlock},
+};
+
+unsafe extern "C" fn write_to_vec(obj: Option<StreamObj>, pch: *const u8, size: size_t) -> c_long {
+    let buf = obj.unwrap().cast::<Vec<u8>>().as_mut();
+    buf.extend_from_slice(slice::from_raw_parts(pch, size));
+    size as c_long
+}
+
+/// Returns the serialized note commitment tree of `wallet`, which includes the tree
+/// position of each of the wallet's notes.
+fn note_commitment_tree(wallet: &Wallet) -> Vec<u8> {
+    let mut buf = vec![];
+    assert!(orchard_wallet_write_note_commitment_tree(
+        wallet,
+        Some(NonNull::from(&mut buf).cast()),
+        Some(write_to_vec),
+    ));
+    buf
+}
+
+fn wallet(keys: &[SpendingKey]) -> Wallet {
+    let mut wallet = Wallet::empty();
+    for sk in keys {
+        orchard_wallet_add_spending_key(&mut wallet, sk);
+    }
+    wallet
+}
+
+/// Scans `blocks` one transaction at a time, as `CWallet::ScanForWalletTransactions`
+/// does.
+fn scan_serially(wallet: &mut Wallet, blocks: &[ScanBlock<&Bundle<Authorized, Amount>>]) {
+    for block in blocks {
+        for (txid, bundle) in &block.transactions {
+            if let Some(bundle) = bundle {
+                wallet.add_notes_from_bundle(txid, bundle);
+            }
+        }
+        assert!(wallet.checkpoint(block.height));
+        for (tx_idx, (txid, bundle)) in block.transactions.iter().enumerate() {
+            if let Some(bundle) = bundle {
+                wallet
+                    .append_bundle_commitments(block.height, tx_idx, txid, bundle)
+                    .unwrap();
+            }
+        }
+    }
+}
+
+#[test]
+fn parallel_rescan_matches_serial_scan() {
+    let keys: Vec<_> = (1..=2)
+        .map(|i| SpendingKey::from_bytes([i; 32]).unwrap())
+        .collect();
+    let other_fvk = FullViewingKey::from(&SpendingKey::from_bytes([3; 32]).unwrap());
+
+    // Blocks of up to four transactions, a quarter of which send a note to one of the
+    // wallet's keys, and some of which have no Orchard bundle.
+    let mut rng = StdRng::seed_from_u64(0);
+    let notes: Vec<Vec<Option<FakeNote>>> = (0..200)
+        .map(|_| {
+            (0..rng.gen_range(0..=4))
+                .map(|_| {
+                    let fvk = match rng.gen_range(0..8) {
+                        0 => return None,
+                        1 => FullViewingKey::from(&keys[0]),
+                        2 => FullViewingKey::from(&keys[1]),
+                        _ => other_fvk.clone(),
+                    };
+                    FakeNote::new(&fvk, rng.gen_range(1..1_000_000), rng.gen())
+                })
+                .collect()
+        })
+        .collect();
+    let blocks = || {
+        notes.iter().enumerate().map(|(block_idx, txs)| ScanBlock {
+            height: BlockHeight::from(block_idx as u32 + 1),
+            transactions: txs
+                .iter()
+                .map(|note| match note {
+                    Some(note) => (note.txid(), Some(note.bundle())),
+                    None => (TxId::from_bytes([0; 32]), None),
+                })
+                .collect(),
+        })
+    };
+
+    let mut serial = wallet(&keys);
+    scan_serially(&mut serial, &blocks().collect::<Vec<_>>());
+    let serial_notes: Vec<_> = serial
+        .get_filtered_notes(None, false, false)
+        .into_iter()
+        .map(|(outpoint, _)| outpoint)
+        .collect();
+    assert!(serial_notes.len() > 50);
+
+    // Every transaction with a bundle is reported, in order.
+    let serial_involvement: Vec<_> = notes
+        .iter()
+        .enumerate()
+        .flat_map(|(block_idx, txs)| {
+            let serial = &serial;
+            txs.iter().enumerate().filter_map(move |(tx_idx, note)| {
+                note.as_ref()
+                    .map(|note| (block_idx, tx_idx, serial.tx_involves_my_notes(&note.txid())))
+            })
+        })
+        .collect();
+
+    for max_in_flight_blocks in [0, 1, 7, 64, 1000] {
+        let mut parallel = wallet(&keys);
+        let mut involvement = vec![];
+        rescan(
+            &mut parallel,
+            blocks(),
+            max_in_flight_blocks,
+            |block_idx, tx_idx, tx_involvement| {
+                involvement.push((block_idx, tx_idx, !tx_involvement.is_empty()))
+            },
+        )
+        .unwrap();
+        assert_eq!(involvement, serial_involvement);
+
+        // The wallet has the same notes, at the same positions in the same tree.
+        assert_eq!(
+            parallel
+                .get_filtered_notes(None, false, false)
+                .into_iter()
+                .map(|(outpoint, _)| outpoint)
+                .collect::<Vec<_>>(),
+            serial_notes,
+        );
+        assert_eq!(
+            note_commitment_tree(&parallel),
+            note_commitment_tree(&serial)
+        );
+        assert_eq!(
+            parallel.note_commitment_tree_root(0),
+            serial.note_commitment_tree_root(0)
+        );
+    }
+
+    // Blocks that do not follow the wallet's last checkpoint are rejected.
+    let mut scanned = wallet(&keys);
+    scan_serially(&mut scanned, &blocks().take(10).collect::<Vec<_>>());
+    assert!(matches!(
+        rescan(&mut scanned, blocks().skip(5), 4, |_, _, _| ()),
+        Err(RescanError::OutOfOrder(height)) if height == BlockHeight::from(6)
+    ));
+}
+
+#[test]
+fn orchard_keys_are_prepared_once() {
+    // Each spending key has an external and an internal incoming viewing key.
+    let keys: Vec<_> = (1..=25)
+        .map(|i| SpendingKey::from_bytes([i; 32]).unwrap())
+        .collect();
+    let mut wallet = wallet(&keys);
+
+    let mut rng = StdRng::seed_from_u64(1);
+    for height in 1..=100u32 {
+        let fvk = FullViewingKey::from(&keys[height as usize % keys.len()]);
+        let note = FakeNote::new(&fvk, rng.gen_range(1..1_000_000), rng.gen()).unwrap();
+        let block = ScanBlock {
+            height: height.into(),
+            transactions: vec![(note.txid(), Some(note.bundle()))],
+        };
+        rescan(&mut wallet, [block], 2, |_, _, _| ()).unwrap();
+    }
+    assert_eq!(wallet.get_filtered_notes(None, false, false).len(), 100);
+    assert_eq!(wallet.prepared_keys().preparations(), 50);
+
+    // Adding a key only prepares its own incoming viewing keys.
+    let sk = SpendingKey::from_bytes([26; 32]).unwrap();
+    orchard_wallet_add_spending_key(&mut wallet, &sk);
+    rescan(
+        &mut wallet,
+        Vec::<ScanBlock<&Bundle<Authorized, Amount>>>::new(),
+        2,
+        |_, _, _| (),
+    )
+    .unwrap();
+    assert_eq!(wallet.prepared_keys().preparations(), 50);
+    let note = FakeNote::new(&FullViewingKey::from(&sk), 1, [1; 32]).unwrap();
+    let block = ScanBlock {
+        height: 101.into(),
+        transactions: vec![(note.txid(), Some(note.bundle()))],
+    };
+    rescan(&mut wallet, [block], 2, |_, _, _| ()).unwrap();
+    assert!(wallet.tx_involves_my_notes(&note.txid()));
+    assert_eq!(wallet.prepared_keys().preparations(), 52);
+}
+
+#[test]
+fn sapling_keys_are_prepared_once() {
+    let mut rng = StdRng::seed_from_u64(2);
+    let ivks: Vec<_> = (0..50)
+        .map(|_| jubjub::Fr::random(&mut rng).to_bytes())
+        .collect();
+
+    // Trial-decrypt the outputs of 100 blocks with every key.
+    let cache = PreparedKeyCache::default();
+    for _ in 0..100 {
+        for _ in 0..rng.gen_range(1..=3) {
+            let epk = jubjub::ExtendedPoint::from(jubjub::SubgroupPoint::random(&mut rng));
+            let kas = sapling_ka_agree_ivks(&cache, &epk, &ivks).unwrap();
+            for (ivk, ka) in ivks.iter().zip(kas) {
+                assert_eq!(
+                    ka,
+                    sapling_ka_agree(&jubjub::Fr::from_bytes(ivk).unwrap(), &epk)
+                );
+            }
+        }
+    }
+    assert_eq!(cache.preparations(), 50);
+
+    // Invalid keys are rejected, and not cached.
+    let epk = jubjub::ExtendedPoint::from(jubjub::SubgroupPoint::random(&mut rng));
+    assert!(sapling_ka_agree_ivks(&cache, &epk, &[ivks[0], [0xff; 32]]).is_none());
+    assert_eq!(cache.preparations(), 50);
+}
@@ -1,5 +1,5 @@
 use libc::c_char;
-use std::ffi::CStr;
+use std::ffi::{CStr, CString};
 use std::fs::File;
 use std::path::Path;
 use std::slice;
@@ -54,6 +54,29 @@ where
 pub struct TracingHandle {
     _file_guard: Option<WorkerGuard>,
     reload_handle: Box<dyn ReloadHandle>,
+    /// The directives of the active filter.
+    ///
+    /// The lock is held across each reload, so that concurrent reloads and reads
+    /// (from different RPC threads) always see the directives of the active filter.
+    filter: Mutex<String>,
+}
+
+impl TracingHandle {
+    pub(crate) fn new<L, S>(
+        file_guard: Option<WorkerGuard>,
+        reload_handle: Handle<L, S>,
+        filter: &str,
+    ) -> Self
+    where
+        L: From<EnvFilter> + Layer<S> + 'static,
+        S: Subscriber + 'static,
+    {
+        TracingHandle {
+            _file_guard: file_guard,
+            reload_handle: Box::new(reload_handle),
+            filter: Mutex::new(filter.to_owned()),
+        }
+    }
 }
 
 #[no_mangle]
@@ -142,10 +165,11 @@ pub extern "C" fn tracing_init(
         .with(filter)
         .init();
 
-    Box::into_raw(Box::new(TracingHandle {
-        _file_guard: file_guard,
-        reload_handle: Box::new(reload_handle),
-    }))
+    Box::into_raw(Box::new(TracingHandle::new(
+        file_guard,
+        reload_handle,
+        initial_filter,
+    )))
 }
 
 #[no_mangle]
@@ -182,10 +206,11 @@ pub extern "C" fn tracing_init_test(
         .with(filter)
         .init();
 
-    Box::into_raw(Box::new(TracingHandle {
-        _file_guard: None,
-        reload_handle: Box::new(reload_handle),
-    }))
+    Box::into_raw(Box::new(TracingHandle::new(
+        None,
+        reload_handle,
+        initial_filter,
+    )))
 }
 
 #[no_mangle]
@@ -193,26 +218,59 @@ pub extern "C" fn tracing_free(handle: *mut TracingHandle) {
     drop(unsafe { Box::from_raw(handle) });
 }
 
+/// Replaces the active filter with `new_filter`.
+///
+/// If any of the directives in `new_filter` is invalid, the active filter is left
+/// unchanged and `false` is returned.
 #[no_mangle]
 pub extern "C" fn tracing_reload(handle: *mut TracingHandle, new_filter: *const c_char) -> bool {
-    let handle = unsafe { &mut *handle };
+    let handle = unsafe { &*handle };
 
-    match unsafe { CStr::from_ptr(new_filter) }
-        .to_str()
-        .map(EnvFilter::new)
-    {
+    let directives = match unsafe { CStr::from_ptr(new_filter) }.to_str() {
+        Ok(directives) => directives,
         Err(e) => {
             tracing::error!("New filter is not valid UTF-8: {}", e);
-            false
+            return false;
         }
-        Ok(new_filter) => {
-            if let Err(e) = handle.reload_handle.reload(new_filter) {
-                tracing::error!("Filter reload failed: {}", e);
-                false
-            } else {
-                true
-            }
+    };
+    // `EnvFilter::new` would silently drop invalid directives.
+    let new_filter = match EnvFilter::try_new(directives) {
+        Ok(new_filter) => new_filter,
+        Err(e) => {
+            tracing::error!("New filter is invalid: {}", e);
+            return false;
         }
+    };
+
+    let mut filter = handle.filter.lock().unwrap();
+    if let Err(e) = handle.reload_handle.reload(new_filter) {
+        tracing::error!("Filter reload failed: {}", e);
+        false
+    } else {
+        *filter = directives.to_owned();
+        true
+    }
+}
+
+/// Returns the directives of the active filter as a NUL-terminated string.
+///
+/// The returned string must be freed with `tracing_free_filter`.
+#[no_mangle]
+pub extern "C" fn tracing_get_filter(handle: *const TracingHandle) -> *mut c_char {
+    let filter = unsafe { &*handle }.filter.lock().unwrap();
+
+    // `EnvFilter::try_new` was given these directives from a C string, so they
+    // contain no interior NUL bytes.
+    CString::new(filter.as_str())
+        .expect("filter directives contain no NUL bytes")
+        .into_raw()
+}
+
+/// Frees a string returned from `tracing_get_filter`.
+#[no_mangle]
+pub extern "C" fn tracing_free_filter(filter: *mut c_char) {
+    if !filter.is_null() {
+        drop(unsafe { CString::from_raw(filter) });
     }
 }
 
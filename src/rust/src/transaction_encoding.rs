@@ -0,0 +1,367 @@
+//! Checks that serialized transactions use the encoding that `CTransaction`'s serializer
+//! would produce for them.
+//!
+//! The Rust parser accepts some encodings that `zcashd` would never produce, and
+//! normalizes them away, so that the txid it computes for a v1-v4 transaction (which is
+//! a hash of its re-serialization) may not be that of the bytes it was given. Walking the
+//! encoding before parsing it lets us reject these with the position and name of the
+//! offending field.
+
+use std::borrow::Cow;
+use std::fmt;
+
+/// The largest CompactSize that `ReadCompactSize` accepts.
+const MAX_SIZE: u64 = 0x02000000;
+
+const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C48270;
+const SAPLING_VERSION_GROUP_ID: u32 = 0x892F2085;
+const ZIP225_VERSION_GROUP_ID: u32 = 0x26A7270A;
+
+/// The size of a JoinSplit description, other than its proof.
+const JOINSPLIT_SIZE_WITHOUT_PROOF: usize =
+    8 + 8 + 32 + 2 * 32 + 2 * 32 + 32 + 32 + 2 * 32 + 2 * 601;
+const BCTV14_PROOF_SIZE: usize = 296;
+const GROTH_PROOF_SIZE: usize = 192;
+
+/// cv, anchor, nullifier, rk, zkproof, and spendAuthSig.
+const V4_SPEND_SIZE: usize = 32 + 32 + 32 + 32 + GROTH_PROOF_SIZE + 64;
+/// cv, cmu, ephemeralKey, encCiphertext, outCiphertext, and zkproof.
+const V4_OUTPUT_SIZE: usize = 32 + 32 + 32 + 580 + 80 + GROTH_PROOF_SIZE;
+/// cv, nullifier, and rk.
+const V5_SPEND_SIZE: usize = 32 + 32 + 32;
+/// cv, cmu, ephemeralKey, encCiphertext, and outCiphertext.
+const V5_OUTPUT_SIZE: usize = 32 + 32 + 32 + 580 + 80;
+/// cv, nullifier, rk, cmx, ephemeralKey, encCiphertext, and outCiphertext.
+const ORCHARD_ACTION_SIZE: usize = 32 + 32 + 32 + 32 + 32 + 580 + 80;
+
+/// Ways in which a serialized transaction can differ from the canonical encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Violation {
+    /// The transaction ends within the field.
+    Truncated,
+    /// The field's CompactSize is encoded in more bytes than its value needs.
+    NonMinimalCompactSize,
+    /// The field's CompactSize is larger than `ReadCompactSize` allows.
+    OversizeCompactSize,
+    /// The transaction parses, but is encoded differently when it is re-serialized.
+    NotReserializable,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Truncated => write!(f, "Transaction is truncated"),
+            Violation::NonMinimalCompactSize => write!(f, "Non-minimal CompactSize"),
+            Violation::OversizeCompactSize => write!(f, "CompactSize is too large"),
+            Violation::NotReserializable => {
+                write!(f, "Transaction re-serializes differently")
+            }
+        }
+    }
+}
+
+/// A violation of the canonical encoding, and where it occurs.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct EncodingError {
+    /// The offset of the start of the offending field, or for
+    /// [`Violation::NotReserializable`] of the first byte that differs.
+    pub(crate) offset: usize,
+    /// The name of the offending field, such as `vin[1].scriptSig`, if it is known.
+    pub(crate) field: Option<String>,
+    pub(crate) violation: Violation,
+}
+
+impl fmt::Display for EncodingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.field {
+            Some(field) => write!(f, "{} in {} at byte {}", self.violation, field, self.offset),
+            None => write!(f, "{} at byte {}", self.violation, self.offset),
+        }
+    }
+}
+
+impl std::error::Error for EncodingError {}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    /// Whether non-minimal CompactSizes are accepted, rather than rejected.
+    lenient: bool,
+    /// The offset, encoded length, and value of each non-minimal CompactSize that has
+    /// been accepted.
+    non_minimal: Vec<(usize, usize, usize)>,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8], lenient: bool) -> Self {
+        Reader {
+            bytes,
+            pos: 0,
+            lenient,
+            non_minimal: vec![],
+        }
+    }
+
+    fn error(&self, offset: usize, field: String, violation: Violation) -> EncodingError {
+        EncodingError {
+            offset,
+            field: Some(field),
+            violation,
+        }
+    }
+
+    fn read(
+        &mut self,
+        len: usize,
+        field: impl FnOnce() -> String,
+    ) -> Result<&'a [u8], EncodingError> {
+        match self
+            .pos
+            .checked_add(len)
+            .and_then(|end| self.bytes.get(self.pos..end))
+        {
+            Some(bytes) => {
+                self.pos += len;
+                Ok(bytes)
+            }
+            None => Err(self.error(self.pos, field(), Violation::Truncated)),
+        }
+    }
+
+    fn skip(&mut self, len: usize, field: impl FnOnce() -> String) -> Result<(), EncodingError> {
+        self.read(len, field).map(|_| ())
+    }
+
+    fn u32(&mut self, field: impl FnOnce() -> String) -> Result<u32, EncodingError> {
+        let bytes = self.read(4, field)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a CompactSize, which must be minimally encoded and at most `MAX_SIZE`.
+    fn compact_size(&mut self, field: impl Fn() -> String) -> Result<usize, EncodingError> {
+        let start = self.pos;
+        let flag = self.read(1, &field)?[0];
+        let (size, min) = match flag {
+            0..=0xfc => (u64::from(flag), 0),
+            0xfd => (self.le_bytes(2, &field)?, 0xfd),
+            0xfe => (self.le_bytes(4, &field)?, 0x10000),
+            0xff => (self.le_bytes(8, &field)?, 0x100000000),
+        };
+        if size > MAX_SIZE {
+            Err(self.error(start, field(), Violation::OversizeCompactSize))
+        } else if size < min && !self.lenient {
+            Err(self.error(start, field(), Violation::NonMinimalCompactSize))
+        } else {
+            if size < min {
+                self.non_minimal
+                    .push((start, self.pos - start, size as usize));
+            }
+            Ok(size as usize)
+        }
+    }
+
+    fn le_bytes(&mut self, len: usize, field: impl Fn() -> String) -> Result<u64, EncodingError> {
+        let bytes = self.read(len, &field)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, byte| (acc << 8) | u64::from(*byte)))
+    }
+
+    /// Skips a vector of `count` elements of `size` bytes each.
+    fn skip_each(&mut self, count: usize, size: usize, field: &str) -> Result<(), EncodingError> {
+        (0..count).try_for_each(|i| self.skip(size, || format!("{}[{}]", field, i)))
+    }
+
+    /// Skips a CompactSize-prefixed byte string.
+    fn skip_bytes(&mut self, field: impl Fn() -> String) -> Result<(), EncodingError> {
+        let len = self.compact_size(&field)?;
+        self.skip(len, field)
+    }
+
+    fn transparent(&mut self) -> Result<(), EncodingError> {
+        let inputs = self.compact_size(|| "vin".into())?;
+        for i in 0..inputs {
+            self.skip(36, || format!("vin[{}].prevout", i))?;
+            self.skip_bytes(|| format!("vin[{}].scriptSig", i))?;
+            self.skip(4, || format!("vin[{}].nSequence", i))?;
+        }
+        let outputs = self.compact_size(|| "vout".into())?;
+        for i in 0..outputs {
+            self.skip(8, || format!("vout[{}].nValue", i))?;
+            self.skip_bytes(|| format!("vout[{}].scriptPubKey", i))?;
+        }
+        Ok(())
+    }
+
+    fn joinsplits(&mut self, proof_size: usize) -> Result<(), EncodingError> {
+        let count = self.compact_size(|| "vJoinSplit".into())?;
+        self.skip_each(
+            count,
+            JOINSPLIT_SIZE_WITHOUT_PROOF + proof_size,
+            "vJoinSplit",
+        )?;
+        if count > 0 {
+            self.skip(32, || "joinSplitPubKey".into())?;
+            self.skip(64, || "joinSplitSig".into())?;
+        }
+        Ok(())
+    }
+
+    fn v4_sapling(&mut self) -> Result<(), EncodingError> {
+        self.skip(8, || "valueBalanceSapling".into())?;
+        let spends = self.compact_size(|| "vShieldedSpend".into())?;
+        self.skip_each(spends, V4_SPEND_SIZE, "vShieldedSpend")?;
+        let outputs = self.compact_size(|| "vShieldedOutput".into())?;
+        self.skip_each(outputs, V4_OUTPUT_SIZE, "vShieldedOutput")?;
+        self.joinsplits(GROTH_PROOF_SIZE)?;
+        if spends + outputs > 0 {
+            self.skip(64, || "bindingSigSapling".into())?;
+        }
+        Ok(())
+    }
+
+    fn v5_sapling(&mut self) -> Result<(), EncodingError> {
+        let spends = self.compact_size(|| "vSpendsSapling".into())?;
+        self.skip_each(spends, V5_SPEND_SIZE, "vSpendsSapling")?;
+        let outputs = self.compact_size(|| "vOutputsSapling".into())?;
+        self.skip_each(outputs, V5_OUTPUT_SIZE, "vOutputsSapling")?;
+        if spends + outputs > 0 {
+            self.skip(8, || "valueBalanceSapling".into())?;
+        }
+        if spends > 0 {
+            self.skip(32, || "anchorSapling".into())?;
+        }
+        self.skip_each(spends, GROTH_PROOF_SIZE, "vSpendProofsSapling")?;
+        self.skip_each(spends, 64, "vSpendAuthSigsSapling")?;
+        self.skip_each(outputs, GROTH_PROOF_SIZE, "vOutputProofsSapling")?;
+        if spends + outputs > 0 {
+            self.skip(64, || "bindingSigSapling".into())?;
+        }
+        Ok(())
+    }
+
+    fn orchard(&mut self) -> Result<(), EncodingError> {
+        let actions = self.compact_size(|| "vActionsOrchard".into())?;
+        self.skip_each(actions, ORCHARD_ACTION_SIZE, "vActionsOrchard")?;
+        if actions > 0 {
+            self.skip(1, || "flagsOrchard".into())?;
+            self.skip(8, || "valueBalanceOrchard".into())?;
+            self.skip(32, || "anchorOrchard".into())?;
+            self.skip_bytes(|| "proofsOrchard".into())?;
+            self.skip_each(actions, 64, "vSpendAuthSigsOrchard")?;
+            self.skip(64, || "bindingSigOrchard".into())?;
+        }
+        Ok(())
+    }
+}
+
+/// Walks the encoding of a transaction to its end.
+///
+/// The transaction format is chosen from the header in the same way as the parser does:
+/// by the version for transactions without the overwintered flag, and by the version
+/// group ID for those with it. Nothing is read past the header of a transaction in an
+/// unknown format, which the parser will reject.
+fn walk(reader: &mut Reader) -> Result<(), EncodingError> {
+    let header = reader.u32(|| "header".into())?;
+    let overwintered = header >> 31 == 1;
+    let version = header & 0x7fffffff;
+
+    if !overwintered {
+        reader.transparent()?;
+        reader.skip(4, || "nLockTime".into())?;
+        if version >= 2 {
+            reader.joinsplits(BCTV14_PROOF_SIZE)?;
+        }
+        return Ok(());
+    }
+
+    match reader.u32(|| "nVersionGroupId".into())? {
+        OVERWINTER_VERSION_GROUP_ID => {
+            reader.transparent()?;
+            reader.skip(4, || "nLockTime".into())?;
+            reader.skip(4, || "nExpiryHeight".into())?;
+            reader.joinsplits(BCTV14_PROOF_SIZE)?;
+        }
+        SAPLING_VERSION_GROUP_ID => {
+            reader.transparent()?;
+            reader.skip(4, || "nLockTime".into())?;
+            reader.skip(4, || "nExpiryHeight".into())?;
+            reader.v4_sapling()?;
+        }
+        ZIP225_VERSION_GROUP_ID => {
+            reader.skip(4, || "nConsensusBranchId".into())?;
+            reader.skip(4, || "nLockTime".into())?;
+            reader.skip(4, || "nExpiryHeight".into())?;
+            reader.transparent()?;
+            reader.v5_sapling()?;
+            reader.orchard()?;
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+/// Walks the encoding of a serialized transaction, checking that each of its
+/// CompactSizes is minimally encoded and within the limit that `ReadCompactSize`
+/// enforces, and returns the length of the transaction.
+pub(crate) fn canonical_length(tx_bytes: &[u8]) -> Result<usize, EncodingError> {
+    let mut reader = Reader::new(tx_bytes, false);
+    walk(&mut reader)?;
+    Ok(reader.pos)
+}
+
+/// Re-encodes each non-minimal CompactSize in a serialized transaction minimally, so
+/// that the parser can make a best effort at questionable data.
+///
+/// CompactSizes are only found up to the first other violation of the canonical
+/// encoding; the rest of the transaction is left as it is.
+pub(crate) fn minimize_compact_sizes(tx_bytes: &[u8]) -> Cow<[u8]> {
+    let mut reader = Reader::new(tx_bytes, true);
+    let _ = walk(&mut reader);
+    if reader.non_minimal.is_empty() {
+        return Cow::Borrowed(tx_bytes);
+    }
+
+    let mut minimized = Vec::with_capacity(tx_bytes.len());
+    let mut pos = 0;
+    for (offset, len, size) in reader.non_minimal {
+        minimized.extend_from_slice(&tx_bytes[pos..offset]);
+        match size {
+            0..=0xfc => minimized.push(size as u8),
+            0xfd..=0xffff => {
+                minimized.push(0xfd);
+                minimized.extend_from_slice(&(size as u16).to_le_bytes());
+            }
+            _ => {
+                minimized.push(0xfe);
+                minimized.extend_from_slice(&(size as u32).to_le_bytes());
+            }
+        }
+        pos = offset + len;
+    }
+    minimized.extend_from_slice(&tx_bytes[pos..]);
+    Cow::Owned(minimized)
+}
+
+/// Checks that `reserialized`, the re-serialization of the transaction parsed from
+/// `tx_bytes`, is identical to it.
+pub(crate) fn check_reserialization(
+    tx_bytes: &[u8],
+    reserialized: &[u8],
+) -> Result<(), EncodingError> {
+    let offset = tx_bytes
+        .iter()
+        .zip(reserialized)
+        .position(|(a, b)| a != b)
+        .unwrap_or_else(|| tx_bytes.len().min(reserialized.len()));
+    if offset == tx_bytes.len() && offset == reserialized.len() {
+        Ok(())
+    } else {
+        Err(EncodingError {
+            offset,
+            field: None,
+            violation: Violation::NotReserializable,
+        })
+    }
+}
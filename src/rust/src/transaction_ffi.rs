@@ -1,62 +1,1693 @@
-use std::convert::TryInto;
-use std::io::Cursor;
+use std::collections::BTreeSet;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::io::{self, Cursor, Read};
 use std::{ptr, slice};
 
-use blake2b_simd::Hash;
+use blake2b_simd::{Hash, Params as Blake2bParams};
+use group::ff::PrimeField;
 use libc::{c_uchar, size_t};
 use tracing::error;
-use zcash_encoding::Vector;
+use zcash_encoding::{CompactSize, Vector};
 use zcash_primitives::{
     consensus::BranchId,
     legacy::Script,
     transaction::{
-        components::{orchard as orchard_serialization, sapling, transparent, Amount},
-        sighash::{SignableInput, TransparentAuthorizingContext},
+        components::{
+            amount::MAX_MONEY, orchard as orchard_serialization, sapling, transparent, Amount,
+        },
+        sighash::{
+            SignableInput, TransparentAuthorizingContext, SIGHASH_ALL, SIGHASH_ANYONECANPAY,
+            SIGHASH_NONE, SIGHASH_SINGLE,
+        },
+        sighash_v4::v4_signature_hash,
         sighash_v5::v5_signature_hash,
-        txid::TxIdDigester,
-        Authorization, Transaction, TransactionData, TxDigests, TxVersion,
+        txid::{BlockTxCommitmentDigester, TxIdDigester},
+        Authorization, Transaction, TransactionData, TransactionDigest, TxDigests, TxVersion,
     },
 };
 
+use crate::auth_data_tree::{block_auth_data_root, AuthDataTree};
+use crate::sighash_legacy::sighash_legacy;
+use crate::transaction_encoding::{
+    canonical_length, check_reserialization, minimize_compact_sizes, EncodingError, Violation,
+};
+
 /// Calculates identifying and authorizing digests for the given transaction.
 ///
-/// If either `txid_ret` or `auth_digest_ret` is `nullptr`, the corresponding digest will
-/// not be calculated.
+/// If either `txid_ret` or `auth_digest_ret` is `nullptr`, the corresponding digest will
+/// not be calculated.
+///
+/// Returns `false` if the transaction is invalid.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_digests(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    txid_ret: *mut [u8; 32],
+    auth_digest_ret: *mut [u8; 32],
+) -> bool {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+
+    // We use a placeholder branch ID here, since it is not used for anything.
+    let tx = match Transaction::read(tx_bytes, BranchId::Canopy) {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to parse transaction: {}", e);
+            return false;
+        }
+    };
+
+    if let Some(txid_ret) = unsafe { txid_ret.as_mut() } {
+        *txid_ret = *tx.txid().as_ref();
+    }
+    if let Some(auth_digest_ret) = unsafe { auth_digest_ret.as_mut() } {
+        *auth_digest_ret = auth_digest(&tx);
+    }
+
+    true
+}
+
+/// Returns the authorizing data commitment of the transaction.
+fn auth_digest(tx: &Transaction) -> [u8; 32] {
+    match tx.version() {
+        // Pre-NU5 transaction formats don't have authorizing data commitments; when
+        // included in the authDataCommitment tree, they use the [0xff; 32] value.
+        TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => [0xff; 32],
+        _ => tx
+            .auth_commitment()
+            .as_bytes()
+            .try_into()
+            .expect("BLAKE2b-256 hashes are 32 bytes; qed"),
+    }
+}
+
+/// Errors that can occur when calculating the digests of a serialized transaction.
+#[derive(Debug)]
+pub(crate) enum TxidError {
+    /// The transaction header (with this value) is not that of a v1-v5 transaction.
+    UnknownVersion(u32),
+    /// The transaction could not be parsed.
+    Invalid(io::Error),
+    /// This many bytes follow the end of the transaction.
+    TrailingBytes(usize),
+    /// The transaction is not in the encoding that `zcashd` would serialize it to.
+    NonCanonical(EncodingError),
+}
+
+impl fmt::Display for TxidError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TxidError::UnknownVersion(header) => {
+                write!(f, "Unknown transaction format with header {:#010x}", header)
+            }
+            TxidError::Invalid(e) => write!(f, "Failed to parse transaction: {}", e),
+            TxidError::TrailingBytes(n) => {
+                write!(f, "Transaction is followed by {} trailing bytes", n)
+            }
+            TxidError::NonCanonical(e) => write!(f, "Non-canonical transaction: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TxidError {}
+
+/// Parses a serialized transaction that must take up all of `tx_bytes`.
+///
+/// If `strict` is set, the transaction must also be in the canonical encoding, that
+/// `zcashd` would serialize it to: its CompactSizes must be minimally encoded and within
+/// `MAX_SIZE`, and it must re-serialize to exactly `tx_bytes`. Otherwise, non-minimal
+/// CompactSizes are accepted, and the parser normalizes anything it can make sense of.
+fn read_exact_transaction(tx_bytes: &[u8], strict: bool) -> Result<Transaction, TxidError> {
+    let mut header = [0; 4];
+    let mut reader = tx_bytes;
+    reader.read_exact(&mut header).map_err(TxidError::Invalid)?;
+    let header = u32::from_le_bytes(header);
+    match TxVersion::read(tx_bytes) {
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Err(TxidError::Invalid(e)),
+        Err(_) => return Err(TxidError::UnknownVersion(header)),
+        // Only versions 1 and 2 predate the overwintered header format.
+        Ok(TxVersion::Sprout(version)) if !(1..=2).contains(&version) => {
+            return Err(TxidError::UnknownVersion(header))
+        }
+        Ok(_) => (),
+    }
+
+    let minimized;
+    let tx_bytes = if strict {
+        match canonical_length(tx_bytes) {
+            Ok(len) if len < tx_bytes.len() => {
+                return Err(TxidError::TrailingBytes(tx_bytes.len() - len))
+            }
+            Ok(_) => (),
+            Err(e) if e.violation == Violation::Truncated => {
+                return Err(TxidError::Invalid(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    e.to_string(),
+                )))
+            }
+            Err(e) => return Err(TxidError::NonCanonical(e)),
+        }
+        tx_bytes
+    } else {
+        minimized = minimize_compact_sizes(tx_bytes);
+        &minimized[..]
+    };
+
+    let mut cursor = Cursor::new(tx_bytes);
+    // We use a placeholder branch ID here, since it is not used for anything.
+    let tx = Transaction::read(&mut cursor, BranchId::Canopy).map_err(TxidError::Invalid)?;
+
+    match tx_bytes.len() - cursor.position() as usize {
+        0 => (),
+        n => return Err(TxidError::TrailingBytes(n)),
+    }
+
+    if strict {
+        // This catches any other encoding that the parser normalizes, which would give
+        // the transaction an identity other than that of `tx_bytes`.
+        let mut reserialized = Vec::with_capacity(tx_bytes.len());
+        tx.write(&mut reserialized).map_err(TxidError::Invalid)?;
+        check_reserialization(tx_bytes, &reserialized).map_err(TxidError::NonCanonical)?;
+    }
+    Ok(tx)
+}
+
+/// Calculates the txid of a serialized v1-v5 transaction: the double-SHA-256 hash of
+/// its encoding for v1-v4 transactions, and its ZIP 244 digest for v5 transactions.
+pub(crate) fn calculate_txid(tx_bytes: &[u8]) -> Result<[u8; 32], TxidError> {
+    read_exact_transaction(tx_bytes, true).map(|tx| *tx.txid().as_ref())
+}
+
+/// Calculates the txid and the authorizing data commitment of a serialized v1-v5
+/// transaction, which together make up its wtxid.
+pub(crate) fn calculate_wtxid(tx_bytes: &[u8]) -> Result<([u8; 32], [u8; 32]), TxidError> {
+    read_exact_transaction(tx_bytes, true).map(|tx| (*tx.txid().as_ref(), auth_digest(&tx)))
+}
+
+/// Calculates the ZIP 244 authorizing data commitment of a serialized v1-v5 transaction,
+/// which is `[0xff; 32]` for pre-v5 transactions.
+pub(crate) fn transaction_auth_digest(tx_bytes: &[u8]) -> Result<[u8; 32], TxidError> {
+    read_exact_transaction(tx_bytes, true).map(|tx| auth_digest(&tx))
+}
+
+const TXID_OK: u32 = 0;
+const TXID_INVALID: u32 = 1;
+const TXID_UNKNOWN_VERSION: u32 = 2;
+const TXID_TRAILING_BYTES: u32 = 3;
+const TXID_NON_CANONICAL: u32 = 4;
+
+/// Calculates the txid and authorizing data commitment of the serialized transaction,
+/// which must take up all of `tx_bytes`.
+///
+/// If `auth_digest_ret` is `nullptr`, the authorizing data commitment will not be
+/// returned.
+///
+/// Returns `TXID_OK` on success, or a code identifying why the transaction was
+/// rejected.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_calculate_wtxid(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    txid_ret: *mut [u8; 32],
+    auth_digest_ret: *mut [u8; 32],
+) -> u32 {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+
+    match calculate_wtxid(tx_bytes) {
+        Ok((txid, auth_digest)) => {
+            unsafe { *txid_ret = txid };
+            if let Some(auth_digest_ret) = unsafe { auth_digest_ret.as_mut() } {
+                *auth_digest_ret = auth_digest;
+            }
+            TXID_OK
+        }
+        Err(e) => {
+            error!("{}", e);
+            match e {
+                TxidError::Invalid(_) => TXID_INVALID,
+                TxidError::UnknownVersion(_) => TXID_UNKNOWN_VERSION,
+                TxidError::TrailingBytes(_) => TXID_TRAILING_BYTES,
+                TxidError::NonCanonical(_) => TXID_NON_CANONICAL,
+            }
+        }
+    }
+}
+
+/// The halves of a wtxid that differ from those of the transaction it was claimed for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum WtxidMismatch {
+    Txid,
+    AuthDigest,
+    Both,
+}
+
+/// Checks a wtxid, as announced by a peer for ZIP 239 relay, against the serialized
+/// transaction that must take up all of `tx_bytes`.
+///
+/// Returns `None` if both halves match, or which of them do not. The claimed
+/// `auth_digest` of a pre-v5 transaction must be `[0xff; 32]`, as for the
+/// transaction's own wtxid.
+pub(crate) fn check_wtxid(
+    tx_bytes: &[u8],
+    txid: &[u8; 32],
+    auth_digest: &[u8; 32],
+) -> Result<Option<WtxidMismatch>, TxidError> {
+    let (expected_txid, expected_auth_digest) = calculate_wtxid(tx_bytes)?;
+    Ok(
+        match (&expected_txid == txid, &expected_auth_digest == auth_digest) {
+            (true, true) => None,
+            (false, true) => Some(WtxidMismatch::Txid),
+            (true, false) => Some(WtxidMismatch::AuthDigest),
+            (false, false) => Some(WtxidMismatch::Both),
+        },
+    )
+}
+
+/// `zcash_transaction_check_wtxid` result: the wtxid is that of the transaction.
+const WTXID_MATCH: u32 = 0;
+/// `zcash_transaction_check_wtxid` result: only the txid differs.
+const WTXID_TXID_MISMATCH: u32 = 1;
+/// `zcash_transaction_check_wtxid` result: only the authorizing data commitment
+/// differs.
+const WTXID_AUTH_DIGEST_MISMATCH: u32 = 2;
+/// `zcash_transaction_check_wtxid` result: both halves differ.
+const WTXID_BOTH_MISMATCH: u32 = 3;
+/// `zcash_transaction_check_wtxid` result: the transaction could not be parsed, or is
+/// not in its canonical encoding.
+const WTXID_INVALID_TRANSACTION: u32 = 4;
+
+/// Checks that the wtxid made up of `txid` and `auth_digest` is that of the serialized
+/// transaction, which must take up all of `tx_bytes`, and returns a code identifying
+/// which half differs if not.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_check_wtxid(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    txid: *const [u8; 32],
+    auth_digest: *const [u8; 32],
+) -> u32 {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+    let txid = unsafe { txid.as_ref() }.expect("txid may not be null.");
+    let auth_digest = unsafe { auth_digest.as_ref() }.expect("Auth digest may not be null.");
+
+    match check_wtxid(tx_bytes, txid, auth_digest) {
+        Ok(None) => WTXID_MATCH,
+        Ok(Some(WtxidMismatch::Txid)) => WTXID_TXID_MISMATCH,
+        Ok(Some(WtxidMismatch::AuthDigest)) => WTXID_AUTH_DIGEST_MISMATCH,
+        Ok(Some(WtxidMismatch::Both)) => WTXID_BOTH_MISMATCH,
+        Err(e) => {
+            error!("{}", e);
+            WTXID_INVALID_TRANSACTION
+        }
+    }
+}
+
+/// The intermediate ZIP 244 digests of a v5 transaction, from which its txid and
+/// authorizing data commitment are computed.
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxDigestParts {
+    /// T.1: the digest of the header fields.
+    pub header_digest: [u8; 32],
+    /// T.2: the digest of the transparent bundle.
+    pub transparent_digest: [u8; 32],
+    /// T.3: the digest of the Sapling bundle.
+    pub sapling_digest: [u8; 32],
+    /// T.4: the digest of the Orchard bundle.
+    pub orchard_digest: [u8; 32],
+    /// The digest of T.1 to T.4.
+    pub txid: [u8; 32],
+    /// The digest of the transparent scriptSigs.
+    pub transparent_auth_digest: [u8; 32],
+    /// The digest of the Sapling proofs and signatures.
+    pub sapling_auth_digest: [u8; 32],
+    /// The digest of the Orchard proof and signatures.
+    pub orchard_auth_digest: [u8; 32],
+    /// The digest of the authorizing data digests.
+    pub auth_digest: [u8; 32],
+}
+
+fn digest_bytes(hash: Hash) -> [u8; 32] {
+    hash.as_bytes()
+        .try_into()
+        .expect("BLAKE2b-256 hashes are 32 bytes; qed")
+}
+
+/// Returns the BLAKE2b-256 digest of `parts` with the given personalization. An empty
+/// bundle is committed to by this digest with no parts.
+fn bundle_digest(personalization: &[u8; 16], parts: &[&Hash]) -> [u8; 32] {
+    let mut state = Blake2bParams::new()
+        .hash_length(32)
+        .personal(personalization)
+        .to_state();
+    for part in parts {
+        state.update(part.as_bytes());
+    }
+    digest_bytes(state.finalize())
+}
+
+/// Calculates the intermediate ZIP 244 digests of a serialized transaction, which must
+/// take up all of `tx_bytes`, so that a txid or wtxid that disagrees with another
+/// implementation can be narrowed down to a bundle.
+///
+/// Returns `Ok(None)` for v1-v4 transactions, whose txids are not ZIP 244 digests.
+pub(crate) fn transaction_digest_parts(
+    tx_bytes: &[u8],
+) -> Result<Option<TxDigestParts>, TxidError> {
+    let tx = read_exact_transaction(tx_bytes, true)?;
+    match tx.version() {
+        TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => return Ok(None),
+        _ => (),
+    }
+
+    // The bundle digests of the txid are only computed for bundles that are present,
+    // so the empty cases are filled in here as ZIP 244 specifies.
+    let txid_parts = tx.digest(TxIdDigester);
+    let transparent_parts = txid_parts.transparent_digests.as_ref().map_or(vec![], |d| {
+        vec![&d.prevouts_digest, &d.sequence_digest, &d.outputs_digest]
+    });
+    let bundle = |personalization, digest: &Option<Hash>| match digest {
+        Some(digest) => digest_bytes(*digest),
+        None => bundle_digest(personalization, &[]),
+    };
+    let auth = BlockTxCommitmentDigester;
+
+    Ok(Some(TxDigestParts {
+        header_digest: digest_bytes(txid_parts.header_digest),
+        transparent_digest: bundle_digest(b"ZTxIdTranspaHash", &transparent_parts),
+        sapling_digest: bundle(b"ZTxIdSaplingHash", &txid_parts.sapling_digest),
+        orchard_digest: bundle(b"ZTxIdOrchardHash", &txid_parts.orchard_digest),
+        txid: *tx.txid().as_ref(),
+        transparent_auth_digest: digest_bytes(auth.digest_transparent(tx.transparent_bundle())),
+        sapling_auth_digest: digest_bytes(auth.digest_sapling(tx.sapling_bundle())),
+        orchard_auth_digest: digest_bytes(auth.digest_orchard(tx.orchard_bundle())),
+        auth_digest: auth_digest(&tx),
+    }))
+}
+
+/// Writes the intermediate ZIP 244 digests of the serialized transaction, which must
+/// take up all of `tx_bytes`, to `parts_ret`.
+///
+/// Returns `false` if the transaction is invalid, or a v1-v4 transaction format, in
+/// which case `parts_ret` is unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_digest_parts(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    parts_ret: *mut TxDigestParts,
+) -> bool {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+    let parts_ret = match unsafe { parts_ret.as_mut() } {
+        Some(parts_ret) => parts_ret,
+        None => return false,
+    };
+
+    match transaction_digest_parts(tx_bytes) {
+        Ok(Some(parts)) => {
+            *parts_ret = parts;
+            true
+        }
+        Ok(None) => false,
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+/// The scalar fields of a parsed transaction, as rendered by `decoderawtransaction`.
+#[repr(C)]
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TxSummary {
+    /// The transaction header, including the `fOverwintered` flag.
+    pub header: u32,
+    pub version_group_id: u32,
+    /// The consensus branch ID, or 0 for pre-v5 transactions which don't encode one.
+    pub consensus_branch_id: u32,
+    pub lock_time: u32,
+    pub expiry_height: u32,
+    pub transparent_inputs: size_t,
+    pub transparent_outputs: size_t,
+    pub sprout_joinsplits: size_t,
+    pub sapling_spends: size_t,
+    pub sapling_outputs: size_t,
+    pub orchard_actions: size_t,
+    pub sapling_value_balance: i64,
+    pub orchard_value_balance: i64,
+    /// The Orchard flags byte: bit 0 enables spends and bit 1 enables outputs.
+    pub orchard_flags: u8,
+}
+
+/// The fields of a parsed transaction that `decoderawtransaction` renders, with each
+/// anchor, nullifier and note commitment as hex in the byte order of `uint256::GetHex`.
+#[derive(Debug)]
+pub struct TxInfo {
+    pub(crate) summary: TxSummary,
+    pub(crate) sapling_anchors: Vec<String>,
+    pub(crate) sapling_nullifiers: Vec<String>,
+    pub(crate) sapling_cmus: Vec<String>,
+    pub(crate) orchard_anchor: Option<String>,
+    pub(crate) orchard_nullifiers: Vec<String>,
+    pub(crate) orchard_cmxs: Vec<String>,
+    /// The fields of each Orchard action that are rendered as raw bytes.
+    pub(crate) orchard_actions: Vec<ActionBytes>,
+    pub(crate) orchard_proof: Option<Vec<u8>>,
+    pub(crate) orchard_binding_sig: Option<[u8; 64]>,
+}
+
+/// The fields of an Orchard action that `decoderawtransaction` renders as raw bytes, in
+/// their encoding in the transaction.
+#[derive(Debug)]
+pub(crate) struct ActionBytes {
+    pub(crate) cv: [u8; 32],
+    pub(crate) rk: [u8; 32],
+    pub(crate) ephemeral_key: [u8; 32],
+    pub(crate) enc_ciphertext: Vec<u8>,
+    pub(crate) out_ciphertext: Vec<u8>,
+    pub(crate) spend_auth_sig: [u8; 64],
+}
+
+fn display_hex(bytes: [u8; 32]) -> String {
+    bytes.iter().rev().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a serialized v1-v5 transaction, which must take up all of `tx_bytes`, into
+/// the fields needed to render it.
+///
+/// If `strict` is not set, a transaction in a non-canonical encoding is parsed as well
+/// as possible, so that questionable data can still be inspected.
+pub(crate) fn inspect_transaction(tx_bytes: &[u8], strict: bool) -> Result<TxInfo, TxidError> {
+    let tx = read_exact_transaction(tx_bytes, strict)?;
+
+    let transparent = tx.transparent_bundle();
+    let sapling = tx.sapling_bundle();
+    let orchard = tx.orchard_bundle();
+
+    let summary = TxSummary {
+        header: tx.version().header(),
+        version_group_id: tx.version().version_group_id(),
+        consensus_branch_id: match tx.version() {
+            TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => 0,
+            _ => u32::from(tx.consensus_branch_id()),
+        },
+        lock_time: tx.lock_time(),
+        expiry_height: u32::from(tx.expiry_height()),
+        transparent_inputs: transparent.map_or(0, |b| b.vin.len()),
+        transparent_outputs: transparent.map_or(0, |b| b.vout.len()),
+        sprout_joinsplits: tx.sprout_bundle().map_or(0, |b| b.joinsplits.len()),
+        sapling_spends: sapling.map_or(0, |b| b.shielded_spends.len()),
+        sapling_outputs: sapling.map_or(0, |b| b.shielded_outputs.len()),
+        orchard_actions: orchard.map_or(0, |b| b.actions().len()),
+        sapling_value_balance: sapling.map_or(0, |b| b.value_balance.into()),
+        orchard_value_balance: orchard.map_or(0, |b| (*b.value_balance()).into()),
+        orchard_flags: orchard.map_or(0, |b| {
+            (b.flags().spends_enabled() as u8) | ((b.flags().outputs_enabled() as u8) << 1)
+        }),
+    };
+
+    let spends = || sapling.into_iter().flat_map(|b| b.shielded_spends.iter());
+    let outputs = sapling.into_iter().flat_map(|b| b.shielded_outputs.iter());
+    let actions = || orchard.into_iter().flat_map(|b| b.actions().iter());
+    Ok(TxInfo {
+        summary,
+        sapling_anchors: spends()
+            .map(|spend| display_hex(spend.anchor.to_repr()))
+            .collect(),
+        sapling_nullifiers: spends()
+            .map(|spend| display_hex(spend.nullifier.0))
+            .collect(),
+        sapling_cmus: outputs
+            .map(|output| display_hex(output.cmu.to_repr()))
+            .collect(),
+        orchard_anchor: orchard.map(|b| display_hex(b.anchor().to_bytes())),
+        orchard_nullifiers: actions()
+            .map(|action| display_hex(action.nullifier().to_bytes()))
+            .collect(),
+        orchard_cmxs: actions()
+            .map(|action| display_hex(action.cmx().to_bytes()))
+            .collect(),
+        orchard_actions: actions()
+            .map(|action| ActionBytes {
+                cv: action.cv_net().to_bytes(),
+                rk: action.rk().into(),
+                ephemeral_key: action.encrypted_note().epk_bytes,
+                enc_ciphertext: action.encrypted_note().enc_ciphertext.to_vec(),
+                out_ciphertext: action.encrypted_note().out_ciphertext.to_vec(),
+                spend_auth_sig: action.authorization().into(),
+            })
+            .collect(),
+        orchard_proof: orchard.map(|b| b.authorization().proof().as_ref().to_vec()),
+        orchard_binding_sig: orchard.map(|b| b.authorization().binding_signature().into()),
+    })
+}
+
+/// Parses the serialized transaction, which must take up all of `tx_bytes`, for
+/// rendering by `decoderawtransaction`. If `strict` is false, a transaction that is not
+/// in the canonical encoding is accepted if it can be parsed at all.
+///
+/// Please free this with `zcash_tx_info_free` when you are done.
+///
+/// Returns `nullptr` if the transaction is rejected, setting `status_ret` to a code
+/// identifying why.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_inspect(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    strict: bool,
+    status_ret: *mut u32,
+) -> *mut TxInfo {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+    let status_ret = unsafe { &mut *status_ret };
+
+    match inspect_transaction(tx_bytes, strict) {
+        Ok(info) => {
+            *status_ret = TXID_OK;
+            Box::into_raw(Box::new(info))
+        }
+        Err(e) => {
+            error!("{}", e);
+            *status_ret = match e {
+                TxidError::Invalid(_) => TXID_INVALID,
+                TxidError::UnknownVersion(_) => TXID_UNKNOWN_VERSION,
+                TxidError::TrailingBytes(_) => TXID_TRAILING_BYTES,
+                TxidError::NonCanonical(_) => TXID_NON_CANONICAL,
+            };
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Frees a transaction summary from `zcash_transaction_inspect`.
+#[no_mangle]
+pub extern "C" fn zcash_tx_info_free(info: *mut TxInfo) {
+    if !info.is_null() {
+        drop(unsafe { Box::from_raw(info) });
+    }
+}
+
+/// Writes the scalar fields of the transaction to `summary_ret`.
+#[no_mangle]
+pub extern "C" fn zcash_tx_info_summary(info: *const TxInfo, summary_ret: *mut TxSummary) {
+    let info = unsafe { info.as_ref() }.expect("TxInfo may not be null");
+    unsafe { *summary_ret = info.summary.clone() };
+}
+
+const TX_INFO_SAPLING_ANCHOR: u32 = 0;
+const TX_INFO_SAPLING_NULLIFIER: u32 = 1;
+const TX_INFO_SAPLING_CMU: u32 = 2;
+const TX_INFO_ORCHARD_ANCHOR: u32 = 3;
+const TX_INFO_ORCHARD_NULLIFIER: u32 = 4;
+const TX_INFO_ORCHARD_CMX: u32 = 5;
+
+/// Writes the hex encoding of the 32-byte `field` of the shielded element at `index`
+/// to `hex_ret` as a NUL-terminated string.
+///
+/// Returns `false` if there is no such field; in this case, `hex_ret` will be
+/// unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_tx_info_hex(
+    info: *const TxInfo,
+    field: u32,
+    index: size_t,
+    hex_ret: *mut [u8; 65],
+) -> bool {
+    let info = unsafe { info.as_ref() }.expect("TxInfo may not be null");
+    let values = match field {
+        TX_INFO_SAPLING_ANCHOR => &info.sapling_anchors[..],
+        TX_INFO_SAPLING_NULLIFIER => &info.sapling_nullifiers[..],
+        TX_INFO_SAPLING_CMU => &info.sapling_cmus[..],
+        TX_INFO_ORCHARD_ANCHOR => info
+            .orchard_anchor
+            .as_ref()
+            .map_or(&[][..], slice::from_ref),
+        TX_INFO_ORCHARD_NULLIFIER => &info.orchard_nullifiers[..],
+        TX_INFO_ORCHARD_CMX => &info.orchard_cmxs[..],
+        _ => return false,
+    };
+
+    match (values.get(index), unsafe { hex_ret.as_mut() }) {
+        (Some(value), Some(hex_ret)) => {
+            hex_ret[..64].copy_from_slice(value.as_bytes());
+            hex_ret[64] = 0;
+            true
+        }
+        _ => false,
+    }
+}
+
+const TX_INFO_ORCHARD_CV: u32 = 6;
+const TX_INFO_ORCHARD_RK: u32 = 7;
+const TX_INFO_ORCHARD_EPHEMERAL_KEY: u32 = 8;
+const TX_INFO_ORCHARD_ENC_CIPHERTEXT: u32 = 9;
+const TX_INFO_ORCHARD_OUT_CIPHERTEXT: u32 = 10;
+const TX_INFO_ORCHARD_SPEND_AUTH_SIG: u32 = 11;
+const TX_INFO_ORCHARD_PROOF: u32 = 12;
+const TX_INFO_ORCHARD_BINDING_SIG: u32 = 13;
+
+/// Returns the raw bytes of `field` of the Orchard action at `index`, or of the bundle
+/// if `field` belongs to the bundle (in which case `index` must be 0), writing their
+/// length to `len_ret`.
+///
+/// The bytes are owned by `info`, and are valid until it is freed. Returns `nullptr`
+/// if there is no such field; in this case, `len_ret` will be unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_tx_info_bytes(
+    info: *const TxInfo,
+    field: u32,
+    index: size_t,
+    len_ret: *mut size_t,
+) -> *const c_uchar {
+    let info = unsafe { info.as_ref() }.expect("TxInfo may not be null");
+    let action = info.orchard_actions.get(index);
+    let bytes = match field {
+        TX_INFO_ORCHARD_CV => action.map(|a| &a.cv[..]),
+        TX_INFO_ORCHARD_RK => action.map(|a| &a.rk[..]),
+        TX_INFO_ORCHARD_EPHEMERAL_KEY => action.map(|a| &a.ephemeral_key[..]),
+        TX_INFO_ORCHARD_ENC_CIPHERTEXT => action.map(|a| &a.enc_ciphertext[..]),
+        TX_INFO_ORCHARD_OUT_CIPHERTEXT => action.map(|a| &a.out_ciphertext[..]),
+        TX_INFO_ORCHARD_SPEND_AUTH_SIG => action.map(|a| &a.spend_auth_sig[..]),
+        TX_INFO_ORCHARD_PROOF if index == 0 => info.orchard_proof.as_deref(),
+        TX_INFO_ORCHARD_BINDING_SIG if index == 0 => {
+            info.orchard_binding_sig.as_ref().map(|sig| &sig[..])
+        }
+        _ => None,
+    };
+
+    match (bytes, unsafe { len_ret.as_mut() }) {
+        (Some(bytes), Some(len_ret)) => {
+            *len_ret = bytes.len();
+            bytes.as_ptr()
+        }
+        _ => ptr::null(),
+    }
+}
+
+const OVERWINTER_VERSION_GROUP_ID: u32 = 0x03C48270;
+const SAPLING_VERSION_GROUP_ID: u32 = 0x892F2085;
+const ZIP225_VERSION_GROUP_ID: u32 = 0x26A7270A;
+
+const MAX_TX_SIZE_BEFORE_SAPLING: usize = 100_000;
+const MAX_TX_SIZE_AFTER_SAPLING: usize = 2_000_000;
+
+/// The header fields of a transaction that passed [`sniff_transaction`], along with
+/// the bounds on its size that the full parser and consensus rules enforce.
+#[derive(Debug, PartialEq)]
+pub(crate) struct SniffResult {
+    pub(crate) overwintered: bool,
+    pub(crate) version: u32,
+    /// The version group ID, or 0 for transactions without the overwintered flag.
+    pub(crate) version_group_id: u32,
+    /// The consensus branch ID encoded in v5 transactions.
+    pub(crate) consensus_branch_id: Option<u32>,
+    /// The size of the smallest transaction with this header.
+    pub(crate) min_size: usize,
+    /// The size of the largest transaction that is valid on the branch.
+    pub(crate) max_size: usize,
+}
+
+/// Reasons that [`sniff_transaction`] can reject a transaction.
+#[derive(Debug, PartialEq)]
+pub(crate) enum SniffError {
+    /// The given consensus branch ID is unknown.
+    UnknownBranch(u32),
+    /// The transaction is too short to hold its header, or to be a transaction with
+    /// that header.
+    Truncated,
+    /// The transaction (of this size) is larger than the branch allows.
+    Oversize(usize),
+    /// The overwintered flag is set before Overwinter activation.
+    OverwinterNotActive,
+    /// The overwintered flag is not set after Overwinter activation.
+    NotOverwintered,
+    /// The version group ID is not that of any transaction format.
+    UnknownVersionGroup(u32),
+    /// The version group ID is not valid on the branch.
+    VersionGroupNotActive(u32),
+    /// The version is outside the range allowed for the version group.
+    InvalidVersion(u32),
+    /// A v5 transaction commits to a consensus branch ID other than the active one.
+    BranchIdMismatch(u32),
+}
+
+impl fmt::Display for SniffError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SniffError::UnknownBranch(id) => write!(f, "Unknown consensus branch ID {:08x}", id),
+            SniffError::Truncated => write!(f, "Transaction is truncated"),
+            SniffError::Oversize(size) => write!(f, "Transaction size {} is too large", size),
+            SniffError::OverwinterNotActive => write!(f, "Overwinter is not active yet"),
+            SniffError::NotOverwintered => {
+                write!(
+                    f,
+                    "fOverwintered flag must be set when Overwinter is active"
+                )
+            }
+            SniffError::UnknownVersionGroup(id) => {
+                write!(f, "Unknown transaction version group ID {:08x}", id)
+            }
+            SniffError::VersionGroupNotActive(id) => write!(
+                f,
+                "Transaction version group ID {:08x} is not valid on this branch",
+                id
+            ),
+            SniffError::InvalidVersion(version) => {
+                write!(f, "Invalid version {} for the version group", version)
+            }
+            SniffError::BranchIdMismatch(id) => write!(
+                f,
+                "Transaction's consensus branch ID {:08x} does not match the current branch",
+                id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SniffError {}
+
+/// Checks the header of a serialized transaction against the consensus rules of the
+/// consensus branch `current_branch_id`, before it is fully parsed. This mirrors the
+/// version checks in `CheckTransaction` and `ContextualCheckTransaction`.
+///
+/// Only the header fields are read: the first 4 bytes of a transaction without the
+/// overwintered flag, the first 8 bytes of a v3 or v4 transaction, and the first 12
+/// bytes of a v5 transaction.
+pub(crate) fn sniff_transaction(
+    tx_bytes: &[u8],
+    current_branch_id: u32,
+) -> Result<SniffResult, SniffError> {
+    let branch = BranchId::try_from(current_branch_id)
+        .map_err(|_| SniffError::UnknownBranch(current_branch_id))?;
+    let field = |i: usize| {
+        tx_bytes
+            .get(4 * i..4 * (i + 1))
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .ok_or(SniffError::Truncated)
+    };
+
+    let header = field(0)?;
+    let overwintered = header >> 31 == 1;
+    let version = header & 0x7fffffff;
+    let before_sapling = matches!(branch, BranchId::Sprout | BranchId::Overwinter);
+
+    let (version_group_id, consensus_branch_id, min_size) = if !overwintered {
+        if branch != BranchId::Sprout {
+            return Err(SniffError::NotOverwintered);
+        }
+        if version < 1 {
+            return Err(SniffError::InvalidVersion(version));
+        }
+        // A header, empty vin and vout, nLockTime, and an empty vJoinSplit if v2+.
+        (0, None, if version == 1 { 10 } else { 11 })
+    } else {
+        if branch == BranchId::Sprout {
+            return Err(SniffError::OverwinterNotActive);
+        }
+        let version_group_id = field(1)?;
+        let (active, allowed_version, min_size) = match version_group_id {
+            // A header, empty vin, vout, and vJoinSplit, nLockTime, and nExpiryHeight.
+            OVERWINTER_VERSION_GROUP_ID => (branch == BranchId::Overwinter, 3, 19),
+            // As above, plus valueBalanceSapling and empty Sapling spends and outputs.
+            SAPLING_VERSION_GROUP_ID => (!before_sapling, 4, 29),
+            // A header, nLockTime, nExpiryHeight, and empty transparent, Sapling, and
+            // Orchard bundles.
+            ZIP225_VERSION_GROUP_ID => (branch == BranchId::Nu5, 5, 25),
+            _ => return Err(SniffError::UnknownVersionGroup(version_group_id)),
+        };
+        if !active {
+            return Err(SniffError::VersionGroupNotActive(version_group_id));
+        }
+        if version != allowed_version {
+            return Err(SniffError::InvalidVersion(version));
+        }
+
+        let consensus_branch_id = if version_group_id == ZIP225_VERSION_GROUP_ID {
+            let id = field(2)?;
+            if id != current_branch_id {
+                return Err(SniffError::BranchIdMismatch(id));
+            }
+            Some(id)
+        } else {
+            None
+        };
+        (version_group_id, consensus_branch_id, min_size)
+    };
+
+    let max_size = if before_sapling {
+        MAX_TX_SIZE_BEFORE_SAPLING
+    } else {
+        MAX_TX_SIZE_AFTER_SAPLING
+    };
+    if tx_bytes.len() < min_size {
+        return Err(SniffError::Truncated);
+    }
+    if tx_bytes.len() > max_size {
+        return Err(SniffError::Oversize(tx_bytes.len()));
+    }
+
+    Ok(SniffResult {
+        overwintered,
+        version,
+        version_group_id,
+        consensus_branch_id,
+        min_size,
+        max_size,
+    })
+}
+
+const TX_SNIFF_OK: u32 = 0;
+const TX_SNIFF_UNKNOWN_BRANCH: u32 = 1;
+const TX_SNIFF_TRUNCATED: u32 = 2;
+const TX_SNIFF_OVERSIZE: u32 = 3;
+const TX_SNIFF_OVERWINTER_NOT_ACTIVE: u32 = 4;
+const TX_SNIFF_NOT_OVERWINTERED: u32 = 5;
+const TX_SNIFF_UNKNOWN_VERSION_GROUP: u32 = 6;
+const TX_SNIFF_VERSION_GROUP_NOT_ACTIVE: u32 = 7;
+const TX_SNIFF_INVALID_VERSION: u32 = 8;
+const TX_SNIFF_BRANCH_ID_MISMATCH: u32 = 9;
+
+/// Checks the header of the serialized transaction against the consensus rules of the
+/// consensus branch `consensus_branch_id`, without parsing the rest of it.
+///
+/// If `max_size_ret` is not `nullptr` and the header is valid, the largest size of a
+/// transaction valid on the branch is written to it.
+///
+/// Returns `TX_SNIFF_OK` if the header is valid, or a code identifying why the
+/// transaction was rejected.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_sniff(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    consensus_branch_id: u32,
+    max_size_ret: *mut size_t,
+) -> u32 {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+
+    match sniff_transaction(tx_bytes, consensus_branch_id) {
+        Ok(result) => {
+            if let Some(max_size_ret) = unsafe { max_size_ret.as_mut() } {
+                *max_size_ret = result.max_size;
+            }
+            TX_SNIFF_OK
+        }
+        Err(e) => match e {
+            SniffError::UnknownBranch(_) => TX_SNIFF_UNKNOWN_BRANCH,
+            SniffError::Truncated => TX_SNIFF_TRUNCATED,
+            SniffError::Oversize(_) => TX_SNIFF_OVERSIZE,
+            SniffError::OverwinterNotActive => TX_SNIFF_OVERWINTER_NOT_ACTIVE,
+            SniffError::NotOverwintered => TX_SNIFF_NOT_OVERWINTERED,
+            SniffError::UnknownVersionGroup(_) => TX_SNIFF_UNKNOWN_VERSION_GROUP,
+            SniffError::VersionGroupNotActive(_) => TX_SNIFF_VERSION_GROUP_NOT_ACTIVE,
+            SniffError::InvalidVersion(_) => TX_SNIFF_INVALID_VERSION,
+            SniffError::BranchIdMismatch(_) => TX_SNIFF_BRANCH_ID_MISMATCH,
+        },
+    }
+}
+
+/// The nExpiryHeight at and above which transactions are rejected.
+const TX_EXPIRY_HEIGHT_THRESHOLD: u32 = 500_000_000;
+
+/// The largest number of Sapling spends, Sapling outputs, or Orchard actions in a
+/// transaction.
+const MAX_SHIELDED_ELEMENTS: usize = (1 << 16) - 1;
+
+/// Consensus rules of `CheckTransaction` and `ContextualCheckTransaction` that a
+/// transaction can violate, as found by [`check_transaction_structure`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum RuleViolation {
+    /// The given consensus branch ID is unknown.
+    UnknownBranch(u32),
+    /// The transaction could not be parsed.
+    Malformed,
+    /// The transaction has no transparent inputs, JoinSplits, Sapling spends, or
+    /// enabled Orchard spends (`bad-txns-no-source-of-funds`).
+    NoSourceOfFunds,
+    /// The transaction has no transparent outputs, JoinSplits, Sapling outputs, or
+    /// enabled Orchard outputs (`bad-txns-no-sink-of-funds`).
+    NoSinkOfFunds,
+    /// The transaction (of this size) is larger than the branch allows
+    /// (`bad-txns-oversize`).
+    Oversize(usize),
+    /// The values taken from the transparent pool exceed `MAX_MONEY`
+    /// (`bad-txns-txouttotal-toolarge`).
+    TxOutTotalTooLarge,
+    /// valueBalanceSapling is nonzero without Sapling spends or outputs
+    /// (`bad-txns-valuebalance-nonzero`).
+    ValueBalanceNonzero,
+    /// There are 2^16 or more Sapling spends (`bad-tx-too-many-sapling-spends`).
+    TooManySaplingSpends,
+    /// There are 2^16 or more Sapling outputs (`bad-tx-too-many-sapling-outputs`).
+    TooManySaplingOutputs,
+    /// There are 2^16 or more Orchard actions (`bad-tx-too-many-orchard-actions`).
+    TooManyOrchardActions,
+    /// Orchard actions are present while both spends and outputs are disabled
+    /// (`bad-tx-orchard-flags-disable-actions`).
+    OrchardFlagsDisableActions,
+    /// A JoinSplit has both vpub_old and vpub_new nonzero (`bad-txns-vpubs-both-nonzero`).
+    VpubsBothNonzero,
+    /// The values added to the transparent pool exceed `MAX_MONEY`
+    /// (`bad-txns-txintotal-toolarge`).
+    TxInTotalTooLarge,
+    /// Two transparent inputs spend the same outpoint (`bad-txns-inputs-duplicate`).
+    DuplicateInputs,
+    /// Two JoinSplit inputs reveal the same nullifier
+    /// (`bad-joinsplits-nullifiers-duplicate`).
+    DuplicateSproutNullifiers,
+    /// Two Sapling spends reveal the same nullifier
+    /// (`bad-spend-description-nullifiers-duplicate`).
+    DuplicateSaplingNullifiers,
+    /// Two Orchard actions reveal the same nullifier (`bad-orchard-nullifiers-duplicate`).
+    DuplicateOrchardNullifiers,
+    /// A coinbase transaction has JoinSplits (`bad-cb-has-joinsplits`).
+    CoinbaseHasJoinSplits,
+    /// A coinbase transaction has Sapling spends (`bad-cb-has-spend-description`).
+    CoinbaseHasSpendDescriptions,
+    /// A coinbase transaction enables Orchard spends (`bad-cb-has-orchard-spend`).
+    CoinbaseHasOrchardSpend,
+    /// A coinbase scriptSig (of this length) is shorter than 2 or longer than 100 bytes
+    /// (`bad-cb-length`).
+    CoinbaseLength(usize),
+    /// A transparent input of a non-coinbase transaction spends the null outpoint
+    /// (`bad-txns-prevout-null`).
+    NullPrevout,
+    /// The transaction expired before the given height (`tx-overwinter-expired`).
+    Expired,
+    /// A coinbase transaction has Sapling outputs before Heartwood
+    /// (`bad-cb-has-output-description`).
+    CoinbaseHasOutputDescriptions,
+    /// A JoinSplit has nonzero vpub_old from Canopy (`bad-txns-vpub_old-nonzero`).
+    VpubOldNonzero,
+    /// The nExpiryHeight (of this value) is 500000000 or more
+    /// (`bad-tx-expiry-height-too-high`).
+    ExpiryHeightTooHigh(u32),
+}
+
+impl fmt::Display for RuleViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuleViolation::UnknownBranch(id) => {
+                write!(f, "Unknown consensus branch ID {:08x}", id)
+            }
+            RuleViolation::Malformed => write!(f, "Transaction is malformed"),
+            RuleViolation::NoSourceOfFunds => write!(f, "Transaction has no source of funds"),
+            RuleViolation::NoSinkOfFunds => write!(f, "Transaction has no sink of funds"),
+            RuleViolation::Oversize(size) => write!(f, "Transaction size {} is too large", size),
+            RuleViolation::TxOutTotalTooLarge => write!(f, "txout total out of range"),
+            RuleViolation::ValueBalanceNonzero => {
+                write!(f, "valueBalanceSapling has no sources or sinks")
+            }
+            RuleViolation::TooManySaplingSpends => write!(f, "2^16 or more Sapling spends"),
+            RuleViolation::TooManySaplingOutputs => write!(f, "2^16 or more Sapling outputs"),
+            RuleViolation::TooManyOrchardActions => write!(f, "2^16 or more Orchard actions"),
+            RuleViolation::OrchardFlagsDisableActions => write!(
+                f,
+                "Orchard actions are present, but flags do not permit Orchard spends or outputs"
+            ),
+            RuleViolation::VpubsBothNonzero => {
+                write!(f, "joinsplit.vpub_new and joinsplit.vpub_old both nonzero")
+            }
+            RuleViolation::TxInTotalTooLarge => write!(f, "txin total out of range"),
+            RuleViolation::DuplicateInputs => write!(f, "Duplicate transparent inputs"),
+            RuleViolation::DuplicateSproutNullifiers => write!(f, "Duplicate Sprout nullifiers"),
+            RuleViolation::DuplicateSaplingNullifiers => {
+                write!(f, "Duplicate Sapling nullifiers")
+            }
+            RuleViolation::DuplicateOrchardNullifiers => {
+                write!(f, "Duplicate Orchard nullifiers")
+            }
+            RuleViolation::CoinbaseHasJoinSplits => write!(f, "Coinbase has joinsplits"),
+            RuleViolation::CoinbaseHasSpendDescriptions => {
+                write!(f, "Coinbase has spend descriptions")
+            }
+            RuleViolation::CoinbaseHasOrchardSpend => {
+                write!(f, "Coinbase has enableSpendsOrchard set")
+            }
+            RuleViolation::CoinbaseLength(len) => {
+                write!(f, "Coinbase scriptSig length {} is out of range", len)
+            }
+            RuleViolation::NullPrevout => write!(f, "Non-coinbase input spends a null prevout"),
+            RuleViolation::Expired => write!(f, "Transaction is expired"),
+            RuleViolation::CoinbaseHasOutputDescriptions => {
+                write!(f, "Coinbase has output descriptions before Heartwood")
+            }
+            RuleViolation::VpubOldNonzero => write!(f, "joinsplit.vpub_old nonzero"),
+            RuleViolation::ExpiryHeightTooHigh(expiry) => {
+                write!(f, "Expiry height {} is too high", expiry)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RuleViolation {}
+
+/// The number of blocks before a transaction's expiry height at which it is considered
+/// to be expiring soon, and is no longer accepted into the mempool.
+const TX_EXPIRING_SOON_THRESHOLD: u32 = 3;
+/// The nLockTime at and above which it is a Unix time rather than a block height.
+const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+fn prevout_bytes(txin: &transparent::TxIn<transparent::Authorized>) -> [u8; 36] {
+    let mut prevout = [0; 36];
+    txin.prevout.write(&mut prevout[..]).unwrap();
+    prevout
+}
+
+/// The prevout of a coinbase input.
+fn null_prevout() -> [u8; 36] {
+    let mut prevout = [0; 36];
+    prevout[32..].copy_from_slice(&u32::MAX.to_le_bytes());
+    prevout
+}
+
+/// Reads the fields of each JoinSplit that the rules need from its encoding: vpub_old,
+/// vpub_new, and the input nullifiers.
+fn sprout_joinsplits(tx: &Transaction) -> Vec<(i64, i64, [[u8; 32]; 2])> {
+    tx.sprout_bundle()
+        .into_iter()
+        .flat_map(|b| b.joinsplits.iter())
+        .map(|js| {
+            let mut bytes = vec![];
+            js.write(&mut bytes).unwrap();
+            let field =
+                |i: usize| i64::from_le_bytes(bytes[8 * i..8 * (i + 1)].try_into().unwrap());
+            let nullifier =
+                |i: usize| -> [u8; 32] { bytes[48 + 32 * i..80 + 32 * i].try_into().unwrap() };
+            (field(0), field(1), [nullifier(0), nullifier(1)])
+        })
+        .collect()
+}
+
+/// Returns whether a transaction with the given nExpiryHeight has expired by `height`,
+/// as in `IsExpiredTx`. Coinbase transactions and an nExpiryHeight of 0 never expire.
+fn is_expired(expiry_height: u32, is_coinbase: bool, height: u32) -> bool {
+    expiry_height != 0 && !is_coinbase && height > expiry_height
+}
+
+/// Adds `value` to a running total that must stay within `0..=MAX_MONEY`, returning
+/// `None` once it leaves that range.
+fn add_money(total: Option<i64>, value: i64) -> Option<i64> {
+    total
+        .map(|total| total + value)
+        .filter(|total| (0..=MAX_MONEY).contains(total))
+}
+
+/// Reads the valueBalanceSapling field of a v4 transaction, which the parser discards
+/// when the transaction has no Sapling spends or outputs.
+fn v4_value_balance(tx_bytes: &[u8], tx: &Transaction) -> i64 {
+    let (vin, vout) = tx
+        .transparent_bundle()
+        .map_or((&[][..], &[][..]), |b| (&b.vin[..], &b.vout[..]));
+    let mut transparent = vec![];
+    Vector::write(&mut transparent, vin, |w, txin| txin.write(w)).unwrap();
+    Vector::write(&mut transparent, vout, |w, txout| txout.write(w)).unwrap();
+
+    // The field follows the header, nVersionGroupId, nLockTime, and nExpiryHeight.
+    let offset = 8 + transparent.len() + 8;
+    i64::from_le_bytes(tx_bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// Checks a serialized transaction, which must take up all of `tx_bytes`, against the
+/// consensus rules of `CheckTransaction` and `ContextualCheckTransaction` that need no
+/// chain state, for inclusion at `height` on the consensus branch `branch_id`.
+///
+/// Every violated rule is returned, in the order that the C++ checks them. Rules that
+/// the parser already enforces (the ranges of individual values and value balances,
+/// and that v5 transactions cannot encode JoinSplits) and the header rules checked by
+/// [`sniff_transaction`] are not repeated, nor are the rules that need proofs, note
+/// decryption, or funding streams.
+pub(crate) fn check_transaction_structure(
+    tx_bytes: &[u8],
+    height: u32,
+    branch_id: u32,
+) -> Result<(), Vec<RuleViolation>> {
+    let branch =
+        BranchId::try_from(branch_id).map_err(|_| vec![RuleViolation::UnknownBranch(branch_id)])?;
+    let tx = read_exact_transaction(tx_bytes, true).map_err(|_| vec![RuleViolation::Malformed])?;
+
+    let overwinter_active = branch != BranchId::Sprout;
+    let before_sapling = matches!(branch, BranchId::Sprout | BranchId::Overwinter);
+    let heartwood_active = matches!(
+        branch,
+        BranchId::Heartwood | BranchId::Canopy | BranchId::Nu5
+    );
+    let canopy_active = matches!(branch, BranchId::Canopy | BranchId::Nu5);
+    let nu5_active = branch == BranchId::Nu5;
+
+    let (vin, vout) = tx
+        .transparent_bundle()
+        .map_or((&[][..], &[][..]), |b| (&b.vin[..], &b.vout[..]));
+    let prevouts: Vec<[u8; 36]> = vin.iter().map(prevout_bytes).collect();
+    let null_prevout = null_prevout();
+    let is_coinbase = prevouts.len() == 1 && prevouts[0] == null_prevout;
+
+    let joinsplits = sprout_joinsplits(&tx);
+
+    let sapling = tx.sapling_bundle();
+    let sapling_spends = sapling.map_or(0, |b| b.shielded_spends.len());
+    let sapling_outputs = sapling.map_or(0, |b| b.shielded_outputs.len());
+    let sapling_value_balance = match (sapling, tx.version()) {
+        (Some(b), _) => i64::from(b.value_balance),
+        (None, TxVersion::Sapling) => v4_value_balance(tx_bytes, &tx),
+        (None, _) => 0,
+    };
+
+    let orchard = tx.orchard_bundle();
+    let orchard_actions = orchard.map_or(0, |b| b.actions().len());
+    let orchard_spends_enabled = orchard.map_or(false, |b| b.flags().spends_enabled());
+    let orchard_outputs_enabled = orchard.map_or(false, |b| b.flags().outputs_enabled());
+    let orchard_value_balance = orchard.map_or(0, |b| i64::from(*b.value_balance()));
+
+    let mut violations = vec![];
+
+    if vin.is_empty() && joinsplits.is_empty() && sapling_spends == 0 && !orchard_spends_enabled {
+        violations.push(RuleViolation::NoSourceOfFunds);
+    }
+    if vout.is_empty() && joinsplits.is_empty() && sapling_outputs == 0 && !orchard_outputs_enabled
+    {
+        violations.push(RuleViolation::NoSinkOfFunds);
+    }
+
+    let max_size = if before_sapling {
+        MAX_TX_SIZE_BEFORE_SAPLING
+    } else {
+        MAX_TX_SIZE_AFTER_SAPLING
+    };
+    if tx_bytes.len() > max_size {
+        violations.push(RuleViolation::Oversize(tx_bytes.len()));
+    }
+
+    // Negative value balances take value from the transparent pool as outputs do, and
+    // positive ones add to it as inputs do.
+    let mut value_out = vout
+        .iter()
+        .fold(Some(0), |total, txout| add_money(total, txout.value.into()));
+    let mut value_in = Some(0);
+    for value_balance in [sapling_value_balance, orchard_value_balance] {
+        if value_balance <= 0 {
+            value_out = add_money(value_out, -value_balance);
+        }
+        if value_balance >= 0 {
+            value_in = add_money(value_in, value_balance);
+        }
+    }
+    for (vpub_old, vpub_new, _) in &joinsplits {
+        value_out = add_money(value_out, *vpub_old);
+        value_in = add_money(value_in, *vpub_new);
+    }
+
+    if value_out.is_none() {
+        violations.push(RuleViolation::TxOutTotalTooLarge);
+    }
+    if sapling_spends == 0 && sapling_outputs == 0 && sapling_value_balance != 0 {
+        violations.push(RuleViolation::ValueBalanceNonzero);
+    }
+    if sapling_spends > MAX_SHIELDED_ELEMENTS {
+        violations.push(RuleViolation::TooManySaplingSpends);
+    }
+    if sapling_outputs > MAX_SHIELDED_ELEMENTS {
+        violations.push(RuleViolation::TooManySaplingOutputs);
+    }
+    if orchard_actions > MAX_SHIELDED_ELEMENTS {
+        violations.push(RuleViolation::TooManyOrchardActions);
+    }
+    if orchard_actions > 0 && !orchard_spends_enabled && !orchard_outputs_enabled {
+        violations.push(RuleViolation::OrchardFlagsDisableActions);
+    }
+    if joinsplits
+        .iter()
+        .any(|(vpub_old, vpub_new, _)| *vpub_old != 0 && *vpub_new != 0)
+    {
+        violations.push(RuleViolation::VpubsBothNonzero);
+    }
+    if value_in.is_none() {
+        violations.push(RuleViolation::TxInTotalTooLarge);
+    }
+
+    let mut seen = BTreeSet::new();
+    if !prevouts.iter().all(|prevout| seen.insert(*prevout)) {
+        violations.push(RuleViolation::DuplicateInputs);
+    }
+    let mut seen = BTreeSet::new();
+    if !joinsplits
+        .iter()
+        .flat_map(|(_, _, nullifiers)| nullifiers.iter())
+        .all(|nf| seen.insert(*nf))
+    {
+        violations.push(RuleViolation::DuplicateSproutNullifiers);
+    }
+    let mut seen = BTreeSet::new();
+    if !sapling
+        .into_iter()
+        .flat_map(|b| b.shielded_spends.iter())
+        .all(|spend| seen.insert(spend.nullifier.0))
+    {
+        violations.push(RuleViolation::DuplicateSaplingNullifiers);
+    }
+    let mut seen = BTreeSet::new();
+    if !orchard
+        .into_iter()
+        .flat_map(|b| b.actions().iter())
+        .all(|action| seen.insert(action.nullifier().to_bytes()))
+    {
+        violations.push(RuleViolation::DuplicateOrchardNullifiers);
+    }
+
+    if is_coinbase {
+        if !joinsplits.is_empty() {
+            violations.push(RuleViolation::CoinbaseHasJoinSplits);
+        }
+        if sapling_spends > 0 {
+            violations.push(RuleViolation::CoinbaseHasSpendDescriptions);
+        }
+        if orchard_spends_enabled {
+            violations.push(RuleViolation::CoinbaseHasOrchardSpend);
+        }
+        let script_sig_len = vin[0].script_sig.0.len();
+        if !(2..=100).contains(&script_sig_len) {
+            violations.push(RuleViolation::CoinbaseLength(script_sig_len));
+        }
+    } else if prevouts.contains(&null_prevout) {
+        violations.push(RuleViolation::NullPrevout);
+    }
+
+    // The rules of ContextualCheckTransaction follow.
+    let expiry_height = u32::from(tx.expiry_height());
+    if overwinter_active && is_expired(expiry_height, is_coinbase, height) {
+        violations.push(RuleViolation::Expired);
+    }
+    if !heartwood_active && is_coinbase && sapling_outputs > 0 {
+        violations.push(RuleViolation::CoinbaseHasOutputDescriptions);
+    }
+    if canopy_active && joinsplits.iter().any(|(vpub_old, _, _)| *vpub_old != 0) {
+        violations.push(RuleViolation::VpubOldNonzero);
+    }
+    // ZIP 203: From NU5, the upper limit on nExpiryHeight is removed for coinbase
+    // transactions.
+    if expiry_height >= TX_EXPIRY_HEIGHT_THRESHOLD && !(nu5_active && is_coinbase) {
+        violations.push(RuleViolation::ExpiryHeightTooHigh(expiry_height));
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations)
+    }
+}
+
+const TX_RULE_UNKNOWN_BRANCH: u32 = 0;
+const TX_RULE_MALFORMED: u32 = 1;
+const TX_RULE_NO_SOURCE_OF_FUNDS: u32 = 2;
+const TX_RULE_NO_SINK_OF_FUNDS: u32 = 3;
+const TX_RULE_OVERSIZE: u32 = 4;
+const TX_RULE_TXOUT_TOTAL_TOO_LARGE: u32 = 5;
+const TX_RULE_VALUE_BALANCE_NONZERO: u32 = 6;
+const TX_RULE_TOO_MANY_SAPLING_SPENDS: u32 = 7;
+const TX_RULE_TOO_MANY_SAPLING_OUTPUTS: u32 = 8;
+const TX_RULE_TOO_MANY_ORCHARD_ACTIONS: u32 = 9;
+const TX_RULE_ORCHARD_FLAGS_DISABLE_ACTIONS: u32 = 10;
+const TX_RULE_VPUBS_BOTH_NONZERO: u32 = 11;
+const TX_RULE_TXIN_TOTAL_TOO_LARGE: u32 = 12;
+const TX_RULE_DUPLICATE_INPUTS: u32 = 13;
+const TX_RULE_DUPLICATE_SPROUT_NULLIFIERS: u32 = 14;
+const TX_RULE_DUPLICATE_SAPLING_NULLIFIERS: u32 = 15;
+const TX_RULE_DUPLICATE_ORCHARD_NULLIFIERS: u32 = 16;
+const TX_RULE_COINBASE_HAS_JOINSPLITS: u32 = 17;
+const TX_RULE_COINBASE_HAS_SPEND_DESCRIPTIONS: u32 = 18;
+const TX_RULE_COINBASE_HAS_ORCHARD_SPEND: u32 = 19;
+const TX_RULE_COINBASE_LENGTH: u32 = 20;
+const TX_RULE_NULL_PREVOUT: u32 = 21;
+const TX_RULE_EXPIRED: u32 = 22;
+const TX_RULE_COINBASE_HAS_OUTPUT_DESCRIPTIONS: u32 = 23;
+const TX_RULE_VPUB_OLD_NONZERO: u32 = 24;
+const TX_RULE_EXPIRY_HEIGHT_TOO_HIGH: u32 = 25;
+
+/// Checks the serialized transaction, which must take up all of `tx_bytes`, against
+/// the consensus rules that need no chain state, for inclusion at `height` on the
+/// consensus branch `consensus_branch_id`.
+///
+/// Up to `violations_len` codes identifying the violated rules are written to
+/// `violations_ret`, in the order that `CheckTransaction` and
+/// `ContextualCheckTransaction` check them.
+///
+/// Returns the number of violated rules, which is 0 if the transaction is valid.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_check_structure(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    height: u32,
+    consensus_branch_id: u32,
+    violations_ret: *mut u32,
+    violations_len: size_t,
+) -> size_t {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+
+    let violations = match check_transaction_structure(tx_bytes, height, consensus_branch_id) {
+        Ok(()) => return 0,
+        Err(violations) => violations,
+    };
+    if violations_len > 0 {
+        let violations_ret = unsafe { slice::from_raw_parts_mut(violations_ret, violations_len) };
+        for (code, violation) in violations_ret.iter_mut().zip(&violations) {
+            *code = match violation {
+                RuleViolation::UnknownBranch(_) => TX_RULE_UNKNOWN_BRANCH,
+                RuleViolation::Malformed => TX_RULE_MALFORMED,
+                RuleViolation::NoSourceOfFunds => TX_RULE_NO_SOURCE_OF_FUNDS,
+                RuleViolation::NoSinkOfFunds => TX_RULE_NO_SINK_OF_FUNDS,
+                RuleViolation::Oversize(_) => TX_RULE_OVERSIZE,
+                RuleViolation::TxOutTotalTooLarge => TX_RULE_TXOUT_TOTAL_TOO_LARGE,
+                RuleViolation::ValueBalanceNonzero => TX_RULE_VALUE_BALANCE_NONZERO,
+                RuleViolation::TooManySaplingSpends => TX_RULE_TOO_MANY_SAPLING_SPENDS,
+                RuleViolation::TooManySaplingOutputs => TX_RULE_TOO_MANY_SAPLING_OUTPUTS,
+                RuleViolation::TooManyOrchardActions => TX_RULE_TOO_MANY_ORCHARD_ACTIONS,
+                RuleViolation::OrchardFlagsDisableActions => TX_RULE_ORCHARD_FLAGS_DISABLE_ACTIONS,
+                RuleViolation::VpubsBothNonzero => TX_RULE_VPUBS_BOTH_NONZERO,
+                RuleViolation::TxInTotalTooLarge => TX_RULE_TXIN_TOTAL_TOO_LARGE,
+                RuleViolation::DuplicateInputs => TX_RULE_DUPLICATE_INPUTS,
+                RuleViolation::DuplicateSproutNullifiers => TX_RULE_DUPLICATE_SPROUT_NULLIFIERS,
+                RuleViolation::DuplicateSaplingNullifiers => TX_RULE_DUPLICATE_SAPLING_NULLIFIERS,
+                RuleViolation::DuplicateOrchardNullifiers => TX_RULE_DUPLICATE_ORCHARD_NULLIFIERS,
+                RuleViolation::CoinbaseHasJoinSplits => TX_RULE_COINBASE_HAS_JOINSPLITS,
+                RuleViolation::CoinbaseHasSpendDescriptions => {
+                    TX_RULE_COINBASE_HAS_SPEND_DESCRIPTIONS
+                }
+                RuleViolation::CoinbaseHasOrchardSpend => TX_RULE_COINBASE_HAS_ORCHARD_SPEND,
+                RuleViolation::CoinbaseLength(_) => TX_RULE_COINBASE_LENGTH,
+                RuleViolation::NullPrevout => TX_RULE_NULL_PREVOUT,
+                RuleViolation::Expired => TX_RULE_EXPIRED,
+                RuleViolation::CoinbaseHasOutputDescriptions => {
+                    TX_RULE_COINBASE_HAS_OUTPUT_DESCRIPTIONS
+                }
+                RuleViolation::VpubOldNonzero => TX_RULE_VPUB_OLD_NONZERO,
+                RuleViolation::ExpiryHeightTooHigh(_) => TX_RULE_EXPIRY_HEIGHT_TOO_HIGH,
+            };
+        }
+    }
+    violations.len()
+}
+
+/// Whether a transaction's expiry height and lock time allow it to be mined in the next
+/// block, as evaluated by [`evaluate_tx_timelocks`].
+///
+/// A transaction is final, as in `IsFinalTx`, if its lock time is satisfied by height
+/// or by the time cutoff in use: the block time for consensus, or the median time past
+/// for the mempool and the miner (BIP 113).
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TimelockStatus {
+    /// The transaction cannot be mined in the next block, as in `IsExpiredTx`.
+    pub expired: bool,
+    /// The transaction expires within `TX_EXPIRING_SOON_THRESHOLD` blocks of the next
+    /// block (or already has), as in `IsExpiringSoonTx`.
+    pub expiring_soon: bool,
+    /// nLockTime is a height that the next block is above.
+    pub lock_time_by_height: bool,
+    /// nLockTime is a time that the next block's time is after.
+    pub lock_time_by_time: bool,
+    /// nLockTime is a time that the median time past is after.
+    pub lock_time_by_median_time_past: bool,
+}
+
+/// Evaluates the expiry height and lock time of a serialized transaction, which must
+/// take up all of `tx_bytes`, for inclusion in the block at `next_block_height`.
 ///
-/// Returns `false` if the transaction is invalid.
+/// An nLockTime of 0, or inputs that all have a final nSequence, satisfy every lock
+/// time condition, as they make the lock time irrelevant.
+pub(crate) fn evaluate_tx_timelocks(
+    tx_bytes: &[u8],
+    next_block_height: u32,
+    next_block_time: i64,
+    median_time_past: i64,
+) -> Result<TimelockStatus, TxidError> {
+    let tx = read_exact_transaction(tx_bytes, true)?;
+    let vin = tx.transparent_bundle().map_or(&[][..], |b| &b.vin[..]);
+    let is_coinbase = vin.len() == 1 && prevout_bytes(&vin[0]) == null_prevout();
+
+    let expiry_height = u32::from(tx.expiry_height());
+    let lock_time = tx.lock_time();
+    let unlocked = lock_time == 0 || vin.iter().all(|txin| txin.sequence == u32::MAX);
+    let is_height = lock_time < LOCKTIME_THRESHOLD;
+    let before = |cutoff: i64| i64::from(lock_time) < cutoff;
+
+    Ok(TimelockStatus {
+        expired: is_expired(expiry_height, is_coinbase, next_block_height),
+        expiring_soon: is_expired(
+            expiry_height,
+            is_coinbase,
+            next_block_height.saturating_add(TX_EXPIRING_SOON_THRESHOLD),
+        ),
+        lock_time_by_height: unlocked || (is_height && before(next_block_height.into())),
+        lock_time_by_time: unlocked || (!is_height && before(next_block_time)),
+        lock_time_by_median_time_past: unlocked || (!is_height && before(median_time_past)),
+    })
+}
+
+/// Evaluates the expiry height and lock time of the serialized transaction, which must
+/// take up all of `tx_bytes`, for inclusion in the block at `next_block_height` with
+/// the time `next_block_time`, whose predecessor has the median time past
+/// `median_time_past`.
+///
+/// Returns `false` if the transaction could not be parsed, in which case `status_ret`
+/// is left unchanged.
 #[no_mangle]
-pub extern "C" fn zcash_transaction_digests(
+pub extern "C" fn zcash_transaction_evaluate_timelocks(
     tx_bytes: *const c_uchar,
     tx_bytes_len: size_t,
-    txid_ret: *mut [u8; 32],
-    auth_digest_ret: *mut [u8; 32],
+    next_block_height: u32,
+    next_block_time: i64,
+    median_time_past: i64,
+    status_ret: *mut TimelockStatus,
 ) -> bool {
     let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
 
-    // We use a placeholder branch ID here, since it is not used for anything.
-    let tx = match Transaction::read(tx_bytes, BranchId::Canopy) {
-        Ok(tx) => tx,
+    match evaluate_tx_timelocks(
+        tx_bytes,
+        next_block_height,
+        next_block_time,
+        median_time_past,
+    ) {
+        Ok(status) => {
+            unsafe { *status_ret = status };
+            true
+        }
         Err(e) => {
-            error!("Failed to parse transaction: {}", e);
-            return false;
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+/// The value that a transaction moves between the transparent and shielded pools, in
+/// zatoshis, with the signs that the protocol specification gives each field.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ValueBalances {
+    /// The total value of the outputs spent by the transparent inputs, if they were
+    /// provided; a transaction does not encode them itself.
+    pub transparent_in: Option<i64>,
+    pub transparent_out: i64,
+    /// The total vpub_old of the JoinSplits, moved into the Sprout pool.
+    pub sprout_vpub_old: i64,
+    /// The total vpub_new of the JoinSplits, moved out of the Sprout pool.
+    pub sprout_vpub_new: i64,
+    /// valueBalanceSapling, the net value moved out of the Sapling pool.
+    pub sapling_value_balance: i64,
+    /// valueBalanceOrchard, the net value moved out of the Orchard pool.
+    pub orchard_value_balance: i64,
+}
+
+/// Errors that can occur when accounting for the value moved by transactions.
+#[derive(Debug)]
+pub(crate) enum ValueBalanceError {
+    /// The transaction could not be parsed.
+    Invalid(TxidError),
+    /// The block's header or transaction count could not be parsed, or bytes follow
+    /// its last transaction.
+    InvalidBlock(io::Error),
+    /// The transaction at this position in the block could not be parsed.
+    InvalidBlockTransaction(usize, TxidError),
+    /// A different number of spent output values was provided than the transaction has
+    /// transparent inputs.
+    SpentValuesMismatch { inputs: usize, values: usize },
+    /// A total does not fit in an `i64`.
+    Overflow,
+}
+
+impl fmt::Display for ValueBalanceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValueBalanceError::Invalid(e) => write!(f, "{}", e),
+            ValueBalanceError::InvalidBlock(e) => write!(f, "Failed to parse block: {}", e),
+            ValueBalanceError::InvalidBlockTransaction(index, e) => {
+                write!(f, "Transaction {} of block: {}", index, e)
+            }
+            ValueBalanceError::SpentValuesMismatch { inputs, values } => write!(
+                f,
+                "{} spent output values provided for {} transparent inputs",
+                values, inputs
+            ),
+            ValueBalanceError::Overflow => write!(f, "Value total overflowed"),
+        }
+    }
+}
+
+impl std::error::Error for ValueBalanceError {}
+
+fn checked_sum(values: impl IntoIterator<Item = i64>) -> Result<i64, ValueBalanceError> {
+    values.into_iter().try_fold(0i64, |total, value| {
+        total.checked_add(value).ok_or(ValueBalanceError::Overflow)
+    })
+}
+
+/// Returns the value that a serialized transaction, which must take up all of
+/// `tx_bytes`, moves between pools.
+///
+/// `spent_values` are the values of the outputs spent by the transparent inputs, in
+/// order. If they are not provided, `transparent_in` is `None`, unless the transaction
+/// is a coinbase or has no transparent inputs, and so spends nothing.
+pub(crate) fn transaction_value_balances(
+    tx_bytes: &[u8],
+    spent_values: Option<&[i64]>,
+) -> Result<ValueBalances, ValueBalanceError> {
+    let tx = read_exact_transaction(tx_bytes, true).map_err(ValueBalanceError::Invalid)?;
+    let (vin, vout) = tx
+        .transparent_bundle()
+        .map_or((&[][..], &[][..]), |b| (&b.vin[..], &b.vout[..]));
+    let is_coinbase = vin.len() == 1 && prevout_bytes(&vin[0]) == null_prevout();
+
+    let transparent_in = match spent_values {
+        _ if is_coinbase || vin.is_empty() => Some(0),
+        Some(values) if values.len() == vin.len() => Some(checked_sum(values.iter().copied())?),
+        Some(values) => {
+            return Err(ValueBalanceError::SpentValuesMismatch {
+                inputs: vin.len(),
+                values: values.len(),
+            })
         }
+        None => None,
     };
+    let joinsplits = sprout_joinsplits(&tx);
 
-    if let Some(txid_ret) = unsafe { txid_ret.as_mut() } {
-        *txid_ret = *tx.txid().as_ref();
+    Ok(ValueBalances {
+        transparent_in,
+        transparent_out: checked_sum(vout.iter().map(|txout| i64::from(txout.value)))?,
+        sprout_vpub_old: checked_sum(joinsplits.iter().map(|(vpub_old, _, _)| *vpub_old))?,
+        sprout_vpub_new: checked_sum(joinsplits.iter().map(|(_, vpub_new, _)| *vpub_new))?,
+        sapling_value_balance: tx
+            .sapling_bundle()
+            .map_or(0, |b| i64::from(b.value_balance)),
+        orchard_value_balance: tx
+            .orchard_bundle()
+            .map_or(0, |b| i64::from(*b.value_balance())),
+    })
+}
+
+/// The net value that a block moves into each shielded pool, in zatoshis, which a
+/// block's transactions can make negative by moving value out of a pool.
+///
+/// A turnstile violation (ZIP 209) is a block that moves more out of a pool than the
+/// pool holds.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PoolValueDeltas {
+    pub sprout_value: i64,
+    pub sapling_value: i64,
+    pub orchard_value: i64,
+}
+
+impl PoolValueDeltas {
+    /// Adds the value that a transaction moves into each pool, or returns `None` if any
+    /// of the totals would overflow.
+    pub(crate) fn checked_add(&self, balances: &ValueBalances) -> Option<Self> {
+        let sprout = balances
+            .sprout_vpub_old
+            .checked_sub(balances.sprout_vpub_new)?;
+        Some(PoolValueDeltas {
+            sprout_value: self.sprout_value.checked_add(sprout)?,
+            sapling_value: self
+                .sapling_value
+                .checked_sub(balances.sapling_value_balance)?,
+            orchard_value: self
+                .orchard_value
+                .checked_sub(balances.orchard_value_balance)?,
+        })
     }
-    if let Some(auth_digest_ret) = unsafe { auth_digest_ret.as_mut() } {
-        match tx.version() {
-            // Pre-NU5 transaction formats don't have authorizing data commitments; when
-            // included in the authDataCommitment tree, they use the [0xff; 32] value.
-            TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling => {
-                *auth_digest_ret = [0xff; 32]
+}
+
+/// The length of a block header before its Equihash solution.
+const BLOCK_HEADER_SIZE_WITHOUT_SOLUTION: usize = 4 + 32 + 32 + 32 + 4 + 4 + 32;
+
+/// Returns the net value that a serialized block, which must take up all of
+/// `block_bytes`, moves into each shielded pool, as `ConnectBlock` accounts for it in
+/// the chain value pools.
+pub(crate) fn block_pool_value_deltas(
+    block_bytes: &[u8],
+) -> Result<PoolValueDeltas, ValueBalanceError> {
+    let mut reader = Cursor::new(block_bytes);
+    let tx_count = (|| {
+        let mut header = [0; BLOCK_HEADER_SIZE_WITHOUT_SOLUTION];
+        reader.read_exact(&mut header)?;
+        let solution_len = CompactSize::read(&mut reader)?;
+        io::copy(&mut (&mut reader).take(solution_len), &mut io::sink())?;
+        CompactSize::read(&mut reader)
+    })()
+    .map_err(ValueBalanceError::InvalidBlock)?;
+
+    let mut deltas = PoolValueDeltas::default();
+    let mut rest = &block_bytes[reader.position() as usize..];
+    for index in 0..tx_count as usize {
+        let invalid = |e| ValueBalanceError::InvalidBlockTransaction(index, e);
+        let len = match canonical_length(rest) {
+            Ok(len) => len,
+            Err(e) if e.violation == Violation::Truncated => {
+                return Err(invalid(TxidError::Invalid(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    e.to_string(),
+                ))))
             }
-            _ => auth_digest_ret.copy_from_slice(tx.auth_commitment().as_bytes()),
-        }
+            Err(e) => return Err(invalid(TxidError::NonCanonical(e))),
+        };
+        let balances = match transaction_value_balances(&rest[..len], None) {
+            Ok(balances) => balances,
+            Err(ValueBalanceError::Invalid(e)) => return Err(invalid(e)),
+            Err(e) => return Err(e),
+        };
+        deltas = deltas
+            .checked_add(&balances)
+            .ok_or(ValueBalanceError::Overflow)?;
+        rest = &rest[len..];
     }
 
-    true
+    if rest.is_empty() {
+        Ok(deltas)
+    } else {
+        Err(ValueBalanceError::InvalidBlock(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Block is followed by {} trailing bytes", rest.len()),
+        )))
+    }
+}
+
+/// Writes the net value that the serialized block, which must take up all of
+/// `block_bytes`, moves into each shielded pool to `deltas_ret`.
+///
+/// Returns `false` if the block could not be parsed, or a total overflowed; in this
+/// case, `deltas_ret` will be unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_block_pool_value_deltas(
+    block_bytes: *const c_uchar,
+    block_bytes_len: size_t,
+    deltas_ret: *mut PoolValueDeltas,
+) -> bool {
+    let block_bytes = unsafe { slice::from_raw_parts(block_bytes, block_bytes_len) };
+
+    match block_pool_value_deltas(block_bytes) {
+        Ok(deltas) => {
+            unsafe { *deltas_ret = deltas };
+            true
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -261,11 +1892,32 @@ pub extern "C" fn zcash_transaction_precomputed_free(precomputed_tx: *mut Precom
 /// This MUST match `NOT_AN_INPUT` in `src/script/interpreter.h`.
 const NOT_AN_INPUT: usize = 0xffffffff;
 
+/// Reads an optional script code from across the FFI, returning `None` if `index` is
+/// `NOT_AN_INPUT` and a script code was supplied anyway.
+fn script_code_for<'a>(
+    index: size_t,
+    script_code: *const c_uchar,
+    script_code_len: size_t,
+) -> Option<&'a [u8]> {
+    if script_code_len == 0 {
+        Some(&[])
+    } else if index == NOT_AN_INPUT {
+        None
+    } else {
+        Some(unsafe { slice::from_raw_parts(script_code, script_code_len) })
+    }
+}
+
 /// Calculates a ZIP 244 signature digest for the given transaction.
 ///
 /// `index` must be an index into the transaction's `vin`, or `NOT_AN_INPUT` for
 /// calculating the signature digest for shielded signatures.
 ///
+/// `script_code` may be empty, and must be empty if `index` is `NOT_AN_INPUT`. ZIP 244
+/// commits to the `script_pubkey` being spent rather than a script code, so it does not
+/// affect the digest; it is accepted so that callers can pass the same script code
+/// regardless of the transaction version.
+///
 /// `sighash_ret` must point to a 32-byte array.
 ///
 /// Returns `false` if any of the parameters are invalid; in this case, `sighash_ret`
@@ -275,6 +1927,8 @@ pub extern "C" fn zcash_transaction_zip244_signature_digest(
     precomputed_tx: *const PrecomputedTxParts,
     hash_type: u32,
     index: size_t,
+    script_code: *const c_uchar,
+    script_code_len: size_t,
     sighash_ret: *mut [u8; 32],
 ) -> bool {
     let precomputed_tx = if let Some(res) = unsafe { precomputed_tx.as_ref() } {
@@ -283,6 +1937,13 @@ pub extern "C" fn zcash_transaction_zip244_signature_digest(
         error!("Invalid precomputed transaction");
         return false;
     };
+    let script_code = match script_code_for(index, script_code, script_code_len) {
+        Some(script_code) => script_code,
+        None => {
+            error!("{}", SigDigestError::ScriptCodeForShielded);
+            return false;
+        }
+    };
     if matches!(
         precomputed_tx.tx.version(),
         TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling,
@@ -291,8 +1952,14 @@ pub extern "C" fn zcash_transaction_zip244_signature_digest(
         return false;
     }
 
-    let signable_input = if index == NOT_AN_INPUT {
-        SignableInput::Shielded
+    let sighash = if index == NOT_AN_INPUT {
+        let sighash = v5_signature_hash(
+            &precomputed_tx.tx,
+            &SignableInput::Shielded,
+            &precomputed_tx.txid_parts,
+        );
+        // `v5_signature_hash` output is always 32 bytes.
+        sighash.as_ref().try_into().unwrap()
     } else {
         let prevout = match precomputed_tx.tx.transparent_bundle() {
             Some(bundle) => match bundle.authorization.all_prev_outputs.get(index) {
@@ -308,31 +1975,499 @@ pub extern "C" fn zcash_transaction_zip244_signature_digest(
             }
         };
 
-        SignableInput::Transparent {
-            // This conversion to `u8` is always fine:
-            // - We only call this FFI method once we already know we are using ZIP 244.
-            // - Even if we weren't, `hash_type` is one byte tacked onto the end of a
-            //   signature, so it always fits into a `u8` (and TBH I don't know why we
-            //   ever set it to `u32`).
-            hash_type: hash_type.try_into().unwrap(),
+        // This conversion to `u8` is always fine:
+        // - We only call this FFI method once we already know we are using ZIP 244.
+        // - Even if we weren't, `hash_type` is one byte tacked onto the end of a
+        //   signature, so it always fits into a `u8` (and TBH I don't know why we
+        //   ever set it to `u32`).
+        match transparent_sig_digest(
+            precomputed_tx,
             index,
-            // `script_code` is unused by `v5_signature_hash`, so instead of passing the
-            // real `script_code` across the FFI (and paying the serialization and parsing
-            // cost for no benefit), we set it to the prevout's `script_pubkey`. This
-            // happens to be correct anyway for every output script kind except P2SH.
-            script_code: &prevout.script_pubkey,
-            script_pubkey: &prevout.script_pubkey,
-            value: prevout.value,
+            &prevout.script_pubkey,
+            script_code,
+            prevout.value,
+            hash_type.try_into().unwrap(),
+        ) {
+            Ok(sighash) => sighash,
+            Err(e) => {
+                error!("{}", e);
+                return false;
+            }
+        }
+    };
+
+    *unsafe { &mut *sighash_ret } = sighash;
+    true
+}
+
+/// Errors that can occur when calculating a transparent signature digest.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum SigDigestError {
+    /// The transaction is not a v5 transaction.
+    PreV5,
+    /// The transaction is not a v3 or v4 transaction.
+    NotLegacy,
+    /// A script code was supplied for a shielded signature digest.
+    ScriptCodeForShielded,
+    /// The transaction is a coinbase, or has no transparent inputs.
+    NoTransparentInputs,
+    /// The transaction has no transparent input with this index.
+    InputOutOfRange(usize),
+    /// The script or value does not match the precomputed previous output.
+    PrevoutMismatch,
+    /// This hash type is not defined by ZIP 244.
+    InvalidHashType(u8),
+    /// `SIGHASH_SINGLE` was used for an input with no corresponding output.
+    NoCorrespondingOutput,
+}
+
+impl fmt::Display for SigDigestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SigDigestError::PreV5 => {
+                write!(f, "Cannot calculate ZIP 244 digest for pre-v5 transaction")
+            }
+            SigDigestError::NotLegacy => write!(
+                f,
+                "Cannot calculate legacy digest for a transaction that is not v3 or v4"
+            ),
+            SigDigestError::ScriptCodeForShielded => {
+                write!(f, "Script code supplied for a shielded signature digest")
+            }
+            SigDigestError::NoTransparentInputs => {
+                write!(f, "Transaction has no transparent inputs to sign")
+            }
+            SigDigestError::InputOutOfRange(index) => {
+                write!(f, "Transparent input {} out of range", index)
+            }
+            SigDigestError::PrevoutMismatch => {
+                write!(f, "Input does not match the precomputed previous output")
+            }
+            SigDigestError::InvalidHashType(hash_type) => {
+                write!(f, "Hash type {:#04x} is not defined by ZIP 244", hash_type)
+            }
+            SigDigestError::NoCorrespondingOutput => {
+                write!(f, "Used SIGHASH_SINGLE without a corresponding output")
+            }
         }
+    }
+}
+
+impl std::error::Error for SigDigestError {}
+
+/// Calculates the ZIP 244 signature digest for the transparent input at `input_index`,
+/// which spends an output with the given `script_pubkey` and `value`.
+///
+/// The amounts and scripts of every input are committed to from the previous outputs
+/// that `precomputed_tx` was created with, which this input must match. In keeping
+/// with ZIP 244 (and unlike legacy sighash), the digest commits to the `script_pubkey`
+/// being spent rather than a script code, so for P2SH inputs this is the P2SH script
+/// and not the redeem script. A non-empty `script_code` (such as the redeem script) is
+/// therefore accepted but does not change the digest; see [`legacy_sig_digest`] for
+/// the v3 and v4 digests that do commit to it.
+pub(crate) fn transparent_sig_digest(
+    precomputed_tx: &PrecomputedTxParts,
+    input_index: usize,
+    script_pubkey: &Script,
+    script_code: &[u8],
+    value: Amount,
+    hash_type: u8,
+) -> Result<[u8; 32], SigDigestError> {
+    let tx = &precomputed_tx.tx;
+    if matches!(
+        tx.version(),
+        TxVersion::Sprout(_) | TxVersion::Overwinter | TxVersion::Sapling,
+    ) {
+        return Err(SigDigestError::PreV5);
+    }
+
+    let bundle = match tx.transparent_bundle() {
+        Some(bundle) if !bundle.vin.is_empty() && !bundle.is_coinbase() => bundle,
+        _ => return Err(SigDigestError::NoTransparentInputs),
     };
+    let prevout = bundle
+        .authorization
+        .all_prev_outputs
+        .get(input_index)
+        .ok_or(SigDigestError::InputOutOfRange(input_index))?;
+    if prevout.script_pubkey.0 != script_pubkey.0 || prevout.value != value {
+        return Err(SigDigestError::PrevoutMismatch);
+    }
+
+    match hash_type & !SIGHASH_ANYONECANPAY {
+        SIGHASH_ALL | SIGHASH_NONE => (),
+        SIGHASH_SINGLE if input_index < bundle.vout.len() => (),
+        SIGHASH_SINGLE => return Err(SigDigestError::NoCorrespondingOutput),
+        _ => return Err(SigDigestError::InvalidHashType(hash_type)),
+    }
 
+    // `script_code` is unused by `v5_signature_hash`.
+    let script_code = Script(script_code.to_vec());
     let sighash = v5_signature_hash(
-        &precomputed_tx.tx,
-        &signable_input,
+        tx,
+        &SignableInput::Transparent {
+            hash_type,
+            index: input_index,
+            script_code: if script_code.0.is_empty() {
+                script_pubkey
+            } else {
+                &script_code
+            },
+            script_pubkey,
+            value,
+        },
         &precomputed_tx.txid_parts,
     );
 
     // `v5_signature_hash` output is always 32 bytes.
-    *unsafe { &mut *sighash_ret } = sighash.as_ref().try_into().unwrap();
-    true
+    Ok(sighash.as_ref().try_into().unwrap())
+}
+
+/// Calculates the ZIP 244 signature digest for the transparent input at `index` of the
+/// given transaction, which spends an output with the given `script_pubkey` and
+/// `value`.
+///
+/// `script_code` may be empty; it does not affect ZIP 244 digests.
+///
+/// `sighash_ret` must point to a 32-byte array.
+///
+/// Returns `false` if any of the parameters are invalid; in this case, `sighash_ret`
+/// will be unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_transparent_sig_digest(
+    precomputed_tx: *const PrecomputedTxParts,
+    index: size_t,
+    script_pubkey: *const c_uchar,
+    script_pubkey_len: size_t,
+    script_code: *const c_uchar,
+    script_code_len: size_t,
+    value: i64,
+    hash_type: u32,
+    sighash_ret: *mut [u8; 32],
+) -> bool {
+    let precomputed_tx = if let Some(res) = unsafe { precomputed_tx.as_ref() } {
+        res
+    } else {
+        error!("Invalid precomputed transaction");
+        return false;
+    };
+    let script_pubkey = if script_pubkey_len == 0 {
+        Script(vec![])
+    } else {
+        Script(unsafe { slice::from_raw_parts(script_pubkey, script_pubkey_len) }.to_vec())
+    };
+    let value = match Amount::from_i64(value) {
+        Ok(value) => value,
+        Err(()) => {
+            error!("Invalid input value");
+            return false;
+        }
+    };
+    let hash_type = match hash_type.try_into() {
+        Ok(hash_type) => hash_type,
+        Err(_) => {
+            error!("Invalid hash type");
+            return false;
+        }
+    };
+
+    let script_code = match script_code_for(index, script_code, script_code_len) {
+        Some(script_code) => script_code,
+        None => {
+            error!("{}", SigDigestError::ScriptCodeForShielded);
+            return false;
+        }
+    };
+
+    match transparent_sig_digest(
+        precomputed_tx,
+        index,
+        &script_pubkey,
+        script_code,
+        value,
+        hash_type,
+    ) {
+        Ok(sighash) => {
+            *unsafe { &mut *sighash_ret } = sighash;
+            true
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+/// Calculates the ZIP 143 or ZIP 243 signature digest for a v3 or v4 transaction.
+///
+/// `tx` must have been parsed with the consensus branch ID that the digest is being
+/// calculated for. For a transparent input (`input_index` is `Some`), the digest commits
+/// to `script_code`, or to `script_pubkey` if `script_code` is empty; P2SH inputs must
+/// supply their redeem script here. For shielded signatures `script_code` must be
+/// empty, `hash_type` must be `SIGHASH_ALL`, and `script_pubkey` and `value` are
+/// ignored.
+pub(crate) fn legacy_sig_digest(
+    tx: &Transaction,
+    input_index: Option<usize>,
+    script_pubkey: &Script,
+    script_code: &[u8],
+    value: Amount,
+    hash_type: u8,
+) -> Result<[u8; 32], SigDigestError> {
+    if !matches!(tx.version(), TxVersion::Overwinter | TxVersion::Sapling) {
+        return Err(SigDigestError::NotLegacy);
+    }
+
+    let script_code = Script(script_code.to_vec());
+    let signable_input = match input_index {
+        None if !script_code.0.is_empty() => return Err(SigDigestError::ScriptCodeForShielded),
+        None if hash_type != SIGHASH_ALL => return Err(SigDigestError::InvalidHashType(hash_type)),
+        None => SignableInput::Shielded,
+        Some(index) => {
+            match tx.transparent_bundle() {
+                Some(bundle) if index < bundle.vin.len() => (),
+                Some(bundle) if !bundle.vin.is_empty() => {
+                    return Err(SigDigestError::InputOutOfRange(index))
+                }
+                _ => return Err(SigDigestError::NoTransparentInputs),
+            }
+            SignableInput::Transparent {
+                hash_type,
+                index,
+                script_code: if script_code.0.is_empty() {
+                    script_pubkey
+                } else {
+                    &script_code
+                },
+                script_pubkey,
+                value,
+            }
+        }
+    };
+
+    // `v4_signature_hash` output is always 32 bytes.
+    Ok(v4_signature_hash(&**tx, &signable_input)
+        .as_ref()
+        .try_into()
+        .unwrap())
+}
+
+/// Calculates the ZIP 143 or ZIP 243 signature digest for the given v3 or v4
+/// transaction, under the given consensus branch ID.
+///
+/// `index` must be an index into the transaction's `vin`, or `NOT_AN_INPUT` for
+/// calculating the signature digest for shielded signatures. For transparent inputs,
+/// the digest commits to `script_code` (the redeem script, for P2SH inputs), or to
+/// `script_pubkey` if `script_code` is empty. `script_code` must be empty if `index`
+/// is `NOT_AN_INPUT`.
+///
+/// `sighash_ret` must point to a 32-byte array.
+///
+/// Returns `false` if any of the parameters are invalid; in this case, `sighash_ret`
+/// will be unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_legacy_signature_digest(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    consensus_branch_id: u32,
+    hash_type: u32,
+    index: size_t,
+    script_pubkey: *const c_uchar,
+    script_pubkey_len: size_t,
+    script_code: *const c_uchar,
+    script_code_len: size_t,
+    value: i64,
+    sighash_ret: *mut [u8; 32],
+) -> bool {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+    let branch_id = match BranchId::try_from(consensus_branch_id) {
+        Ok(branch_id) => branch_id,
+        Err(_) => {
+            error!("Unknown consensus branch ID {:#010x}", consensus_branch_id);
+            return false;
+        }
+    };
+    let tx = match Transaction::read(tx_bytes, branch_id) {
+        Ok(tx) => tx,
+        Err(e) => {
+            error!("Failed to parse transaction: {}", e);
+            return false;
+        }
+    };
+    let script_code = match script_code_for(index, script_code, script_code_len) {
+        Some(script_code) => script_code,
+        None => {
+            error!("{}", SigDigestError::ScriptCodeForShielded);
+            return false;
+        }
+    };
+    let script_pubkey = if script_pubkey_len == 0 || index == NOT_AN_INPUT {
+        Script(vec![])
+    } else {
+        Script(unsafe { slice::from_raw_parts(script_pubkey, script_pubkey_len) }.to_vec())
+    };
+    let value = match Amount::from_i64(value) {
+        Ok(value) => value,
+        Err(()) => {
+            error!("Invalid input value");
+            return false;
+        }
+    };
+    let hash_type = match hash_type.try_into() {
+        Ok(hash_type) => hash_type,
+        Err(_) => {
+            error!("Invalid hash type");
+            return false;
+        }
+    };
+    let input_index = if index == NOT_AN_INPUT {
+        None
+    } else {
+        Some(index)
+    };
+
+    match legacy_sig_digest(
+        &tx,
+        input_index,
+        &script_pubkey,
+        script_code,
+        value,
+        hash_type,
+    ) {
+        Ok(sighash) => {
+            *unsafe { &mut *sighash_ret } = sighash;
+            true
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+/// Calculates the legacy signature digest of the transparent input at `index` of the
+/// given pre-Overwinter transaction, which must take up all of `tx_bytes`.
+///
+/// An `index` out of range, or `SIGHASH_SINGLE` on an input with no corresponding
+/// output, gives [`SIGHASH_ONE`](crate::sighash_legacy::SIGHASH_ONE).
+///
+/// `sighash_ret` must point to a 32-byte array.
+///
+/// Returns `false` if the transaction is overwintered or invalid; in this case,
+/// `sighash_ret` will be unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_transaction_sprout_signature_digest(
+    tx_bytes: *const c_uchar,
+    tx_bytes_len: size_t,
+    hash_type: i32,
+    index: size_t,
+    script_code: *const c_uchar,
+    script_code_len: size_t,
+    sighash_ret: *mut [u8; 32],
+) -> bool {
+    let tx_bytes = unsafe { slice::from_raw_parts(tx_bytes, tx_bytes_len) };
+    let script_code = if script_code_len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(script_code, script_code_len) }
+    };
+
+    match sighash_legacy(tx_bytes, hash_type, index, script_code) {
+        Ok(sighash) => {
+            *unsafe { &mut *sighash_ret } = sighash;
+            true
+        }
+        Err(e) => {
+            error!("{}", e);
+            false
+        }
+    }
+}
+
+/// Creates an empty incremental tree of transaction auth digests.
+///
+/// Please free this with `zcash_auth_data_tree_free` when you are done.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_new() -> *mut AuthDataTree {
+    Box::into_raw(Box::new(AuthDataTree::new()))
+}
+
+/// Frees an auth data tree from `zcash_auth_data_tree_new`.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_free(tree: *mut AuthDataTree) {
+    if !tree.is_null() {
+        drop(unsafe { Box::from_raw(tree) });
+    }
+}
+
+/// Returns the number of auth digests in the tree.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_len(tree: *const AuthDataTree) -> size_t {
+    unsafe { tree.as_ref() }
+        .expect("Auth data tree pointer may not be null.")
+        .len()
+}
+
+/// Appends a transaction's auth digest to the tree.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_append(
+    tree: *mut AuthDataTree,
+    auth_digest: *const [u8; 32],
+) {
+    let tree = unsafe { tree.as_mut() }.expect("Auth data tree pointer may not be null.");
+    let auth_digest = unsafe { auth_digest.as_ref() }.expect("Auth digest may not be null.");
+    tree.append(*auth_digest);
+}
+
+/// Replaces the auth digest at `index` in the tree.
+///
+/// Returns `false` if `index` is out of range; in this case, the tree will be
+/// unaltered.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_replace(
+    tree: *mut AuthDataTree,
+    index: size_t,
+    auth_digest: *const [u8; 32],
+) -> bool {
+    let tree = unsafe { tree.as_mut() }.expect("Auth data tree pointer may not be null.");
+    let auth_digest = unsafe { auth_digest.as_ref() }.expect("Auth digest may not be null.");
+    if tree.replace(index, *auth_digest) {
+        true
+    } else {
+        error!("Auth data tree index out of range");
+        false
+    }
+}
+
+/// Removes the most recently appended auth digest from the tree.
+///
+/// Returns `false` if the tree is empty.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_remove_last(tree: *mut AuthDataTree) -> bool {
+    let tree = unsafe { tree.as_mut() }.expect("Auth data tree pointer may not be null.");
+    tree.remove_last().is_some()
+}
+
+/// Writes the ZIP 244 `hashAuthDataRoot` of the tree to `root_ret`, which must point
+/// to a 32-byte array.
+#[no_mangle]
+pub extern "C" fn zcash_auth_data_tree_root(tree: *const AuthDataTree, root_ret: *mut [u8; 32]) {
+    let tree = unsafe { tree.as_ref() }.expect("Auth data tree pointer may not be null.");
+    *unsafe { &mut *root_ret } = tree.root();
+}
+
+/// Writes the ZIP 244 `hashAuthDataRoot` of a block whose transactions have the
+/// `auth_digests_len` auth digests at `auth_digests`, in block order, to `root_ret`.
+#[no_mangle]
+pub extern "C" fn zcash_block_auth_data_root(
+    auth_digests: *const [u8; 32],
+    auth_digests_len: size_t,
+    root_ret: *mut [u8; 32],
+) {
+    let auth_digests: &[[u8; 32]] = if auth_digests_len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(auth_digests, auth_digests_len) }
+    };
+    *unsafe { &mut *root_ret } = block_auth_data_root(auth_digests);
 }
@@ -6,6 +6,7 @@ use std::convert::TryInto;
 use std::io;
 use std::ptr;
 use std::slice;
+use std::sync::Arc;
 use tracing::error;
 
 use zcash_encoding::{Optional, Vector};
@@ -28,6 +29,7 @@ use crate::{
     incremental_merkle_tree::{read_tree, write_tree},
     incremental_merkle_tree_ffi::MERKLE_DEPTH,
     streams_ffi::{CppStreamReader, CppStreamWriter, ReadCb, StreamObj, WriteCb},
+    wallet_scanner::{PreparedKeyCache, PreparedOrchardIvk},
     zcashd_orchard::OrderedAddress,
 };
 
@@ -61,6 +63,10 @@ pub struct DecryptedNote {
     memo: [u8; 512],
 }
 
+/// A note decrypted from the action at the given index of a bundle, with the incoming
+/// viewing key that decrypted it, its recipient and its memo.
+pub(crate) type DecryptedOutput = (usize, IncomingViewingKey, Note, Address, [u8; 512]);
+
 /// A data structure tracking the note data that was decrypted from a single transaction.
 #[derive(Debug, Clone)]
 pub struct TxNotes {
@@ -164,6 +170,9 @@ pub struct Wallet {
     /// wallet has observed, the set of inpoints where those nullifiers were
     /// observed as as having been spent.
     potential_spends: BTreeMap<Nullifier, BTreeSet<InPoint>>,
+    /// The wallet's incoming viewing keys, as prepared for trial decryption by the
+    /// scans of this wallet.
+    prepared_keys: PreparedKeyCache,
 }
 
 #[derive(Debug, Clone)]
@@ -220,6 +229,11 @@ impl BundleWalletInvolvement {
             spend_action_metadata: Vec::new(),
         }
     }
+
+    /// Returns whether the bundle neither sends notes to nor spends notes of the wallet.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.receive_action_metadata.is_empty() && self.spend_action_metadata.is_empty()
+    }
 }
 
 impl Wallet {
@@ -234,6 +248,7 @@ impl Wallet {
             last_observed: None,
             mined_notes: BTreeMap::new(),
             potential_spends: BTreeMap::new(),
+            prepared_keys: PreparedKeyCache::default(),
         }
     }
 
@@ -382,20 +397,28 @@ impl Wallet {
         &mut self,
         txid: &TxId,
         bundle: &Bundle<Authorized, Amount>,
+    ) -> BundleWalletInvolvement {
+        let keys = self.incoming_viewing_keys();
+        self.add_decrypted_outputs(txid, bundle, bundle.decrypt_outputs_with_keys(&keys))
+    }
+
+    /// Adds notes that were decrypted from `bundle` with this wallet's incoming viewing
+    /// keys to the wallet, in the same way as `add_notes_from_bundle`. The notes must be
+    /// in action order, and must have been decrypted with the first key returned by
+    /// `incoming_viewing_keys` that decrypts them.
+    #[tracing::instrument(level = "trace", skip(self, bundle, decrypted))]
+    pub(crate) fn add_decrypted_outputs(
+        &mut self,
+        txid: &TxId,
+        bundle: &Bundle<Authorized, Amount>,
+        decrypted: Vec<DecryptedOutput>,
     ) -> BundleWalletInvolvement {
         let mut involvement = BundleWalletInvolvement::new();
         // If we recognize any of our notes as being consumed as inputs to actions
         // in this bundle, record them as potential spends.
         involvement.spend_action_metadata = self.add_potential_spends(txid, bundle);
 
-        let keys = self
-            .key_store
-            .viewing_keys
-            .keys()
-            .cloned()
-            .collect::<Vec<_>>();
-
-        for (action_idx, ivk, note, recipient, memo) in bundle.decrypt_outputs_with_keys(&keys) {
+        for (action_idx, ivk, note, recipient, memo) in decrypted {
             assert!(self.add_decrypted_note(txid, action_idx, ivk.clone(), note, recipient, memo));
             involvement.receive_action_metadata.insert(action_idx, ivk);
         }
@@ -403,6 +426,24 @@ impl Wallet {
         involvement
     }
 
+    /// Returns the incoming viewing keys that notes are decrypted with when scanning,
+    /// in the order in which they are tried.
+    pub(crate) fn incoming_viewing_keys(&self) -> Vec<IncomingViewingKey> {
+        self.key_store.viewing_keys.keys().cloned().collect()
+    }
+
+    /// Returns the wallet's incoming viewing keys prepared for trial decryption, in the
+    /// order of `incoming_viewing_keys`. Each key is only prepared the first time it is
+    /// scanned with.
+    pub(crate) fn prepared_incoming_viewing_keys(&self) -> Vec<Arc<PreparedOrchardIvk>> {
+        self.prepared_keys.orchard(&self.incoming_viewing_keys())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn prepared_keys(&self) -> &PreparedKeyCache {
+        &self.prepared_keys
+    }
+
     /// Restore note and potential spend data from a bundle using the provided
     /// metadata.
     ///
@@ -849,6 +890,30 @@ pub type ActionIvkPushCb =
 /// to a C++ callback receiver.
 pub type SpendIndexPushCb = unsafe extern "C" fn(obj: Option<FFICallbackReceiver>, value: u32);
 
+impl BundleWalletInvolvement {
+    /// Passes the actions of the bundle that involve the wallet to `cb_receiver`, and
+    /// returns whether there were any.
+    pub(crate) fn push_to(
+        self,
+        cb_receiver: Option<FFICallbackReceiver>,
+        action_ivk_push_cb: ActionIvkPushCb,
+        spend_idx_push_cb: SpendIndexPushCb,
+    ) -> bool {
+        let involved = !self.is_empty();
+        for (action_idx, ivk) in self.receive_action_metadata.into_iter() {
+            let action_ivk = FFIActionIvk {
+                action_idx: action_idx.try_into().unwrap(),
+                ivk_ptr: Box::into_raw(Box::new(ivk)),
+            };
+            unsafe { action_ivk_push_cb(cb_receiver, action_ivk) };
+        }
+        for action_idx in self.spend_action_metadata {
+            unsafe { spend_idx_push_cb(cb_receiver, action_idx.try_into().unwrap()) };
+        }
+        involved
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn orchard_wallet_add_notes_from_bundle(
     wallet: *mut Wallet,
@@ -861,20 +926,11 @@ pub extern "C" fn orchard_wallet_add_notes_from_bundle(
     let wallet = unsafe { wallet.as_mut() }.expect("Wallet pointer may not be null");
     let txid = TxId::from_bytes(*unsafe { txid.as_ref() }.expect("txid may not be null."));
     if let Some(bundle) = unsafe { bundle.as_ref() } {
-        let added = wallet.add_notes_from_bundle(&txid, bundle);
-        let involved =
-            !(added.receive_action_metadata.is_empty() && added.spend_action_metadata.is_empty());
-        for (action_idx, ivk) in added.receive_action_metadata.into_iter() {
-            let action_ivk = FFIActionIvk {
-                action_idx: action_idx.try_into().unwrap(),
-                ivk_ptr: Box::into_raw(Box::new(ivk.clone())),
-            };
-            unsafe { (action_ivk_push_cb.unwrap())(cb_receiver, action_ivk) };
-        }
-        for action_idx in added.spend_action_metadata {
-            unsafe { (spend_idx_push_cb.unwrap())(cb_receiver, action_idx.try_into().unwrap()) };
-        }
-        involved
+        wallet.add_notes_from_bundle(&txid, bundle).push_to(
+            cb_receiver,
+            action_ivk_push_cb.unwrap(),
+            spend_idx_push_cb.unwrap(),
+        )
     } else {
         false
     }
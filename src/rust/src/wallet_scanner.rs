@@ -0,0 +1,392 @@
+//! Parallel trial decryption for Orchard wallet rescans.
+//!
+//! Rescanning spends most of its time trial-decrypting outputs, which only needs the
+//! wallet's incoming viewing keys, while recording the decrypted notes and appending
+//! note commitments to the wallet's tree must happen in block order. `rescan` therefore
+//! splits a window of blocks into one work item per output and key, decrypts them on
+//! the rayon thread pool while the previous window is applied to the wallet, and then
+//! applies the decrypted notes block by block as `CWallet::ScanForWalletTransactions`
+//! does.
+//!
+//! Scans try every output with every key, so the keys are prepared for trial
+//! decryption once and kept in a `PreparedKeyCache` across scans.
+
+use std::borrow::Borrow;
+use std::collections::BTreeMap;
+use std::slice;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, RwLock,
+};
+
+use group::{cofactor::CofactorGroup, WnafBase, WnafScalar};
+use once_cell::sync::Lazy;
+use orchard::{bundle::Authorized, keys::IncomingViewingKey, Bundle};
+use rayon::prelude::*;
+use tracing::error;
+use zcash_primitives::{
+    consensus::BlockHeight,
+    transaction::{components::Amount, TxId},
+};
+
+use crate::{
+    de_ct,
+    wallet::{
+        ActionIvkPushCb, BundleWalletInvolvement, DecryptedOutput, FFICallbackReceiver,
+        SpendIndexPushCb, Wallet, WalletError,
+    },
+};
+
+/// The number of blocks that `orchard_wallet_rescan_blocks` holds at a time if the
+/// caller does not set a limit.
+pub const DEFAULT_MAX_IN_FLIGHT_BLOCKS: usize = 64;
+
+/// The window size of the wNAF form of prepared Sapling keys and ephemeral keys.
+const SAPLING_WNAF_WINDOW_SIZE: usize = 4;
+
+/// A Sapling incoming viewing key, with the wNAF form of its scalar precomputed so that
+/// it is only computed once for all the ephemeral keys it is agreed with.
+pub struct PreparedSaplingIvk(WnafScalar<jubjub::Fr, SAPLING_WNAF_WINDOW_SIZE>);
+
+impl PreparedSaplingIvk {
+    /// Returns \[ivk\] \[8\] epk, as `sapling_ka_agree` does.
+    fn ka_agree(
+        &self,
+        epk: &WnafBase<jubjub::ExtendedPoint, SAPLING_WNAF_WINDOW_SIZE>,
+    ) -> jubjub::SubgroupPoint {
+        (epk * &self.0).clear_cofactor()
+    }
+}
+
+/// An Orchard incoming viewing key prepared for trial decryption.
+///
+/// The `orchard` crate trial-decrypts with the key itself rather than a precomputed
+/// form of it, so preparing an Orchard key only shares it between scans.
+pub struct PreparedOrchardIvk(IncomingViewingKey);
+
+/// Incoming viewing keys prepared for trial decryption, keyed by their encodings.
+///
+/// A key is prepared the first time it is scanned with. As an entry only depends on its
+/// own key, adding keys does not invalidate the others; wallets never remove keys, so
+/// entries are never removed either. Concurrent scans share the cache, and only take
+/// its write lock to add keys.
+#[derive(Default)]
+pub struct PreparedKeyCache {
+    sapling: RwLock<BTreeMap<[u8; 32], Arc<PreparedSaplingIvk>>>,
+    orchard: RwLock<BTreeMap<[u8; 64], Arc<PreparedOrchardIvk>>>,
+    /// The number of keys that have been prepared.
+    preparations: AtomicUsize,
+}
+
+impl PreparedKeyCache {
+    /// Returns the prepared key for `key`, preparing it if it is not in `keys` yet, or
+    /// `None` if `prepare` fails.
+    fn get_or_prepare<K: Ord, V>(
+        &self,
+        keys: &RwLock<BTreeMap<K, Arc<V>>>,
+        key: K,
+        prepare: impl FnOnce() -> Option<V>,
+    ) -> Option<Arc<V>> {
+        if let Some(prepared) = keys.read().unwrap().get(&key) {
+            return Some(prepared.clone());
+        }
+
+        let mut keys = keys.write().unwrap();
+        // Another scan may have prepared the key in the meantime.
+        if let Some(prepared) = keys.get(&key) {
+            return Some(prepared.clone());
+        }
+        let prepared = Arc::new(prepare()?);
+        self.preparations.fetch_add(1, Ordering::Relaxed);
+        keys.insert(key, prepared.clone());
+        Some(prepared)
+    }
+
+    /// Returns the prepared form of the Sapling incoming viewing key encoded as `ivk`,
+    /// or `None` if it is not a valid scalar.
+    pub(crate) fn sapling(&self, ivk: &[u8; 32]) -> Option<Arc<PreparedSaplingIvk>> {
+        self.get_or_prepare(&self.sapling, *ivk, || {
+            de_ct(jubjub::Fr::from_bytes(ivk)).map(|ivk| PreparedSaplingIvk(WnafScalar::new(&ivk)))
+        })
+    }
+
+    /// Returns the prepared forms of the Orchard incoming viewing keys `ivks`, in order.
+    pub(crate) fn orchard(&self, ivks: &[IncomingViewingKey]) -> Vec<Arc<PreparedOrchardIvk>> {
+        ivks.iter()
+            .map(|ivk| {
+                self.get_or_prepare(&self.orchard, ivk.to_bytes(), || {
+                    Some(PreparedOrchardIvk(ivk.clone()))
+                })
+                .expect("Orchard keys are always prepared; qed")
+            })
+            .collect()
+    }
+
+    /// Returns the number of keys that have been prepared.
+    #[cfg(test)]
+    pub(crate) fn preparations(&self) -> usize {
+        self.preparations.load(Ordering::Relaxed)
+    }
+}
+
+/// The cache of the Sapling keys that zcashd trial-decrypts with.
+pub(crate) static SAPLING_KEYS: Lazy<PreparedKeyCache> = Lazy::new(PreparedKeyCache::default);
+
+/// Returns \[ivk\] \[8\] epk for each of `ivks`, or `None` if any of them is not a valid
+/// scalar.
+///
+/// Trial decryption agrees each output's ephemeral key with every incoming viewing key,
+/// so the wNAF form of `epk` is also only computed once.
+pub(crate) fn sapling_ka_agree_ivks(
+    cache: &PreparedKeyCache,
+    epk: &jubjub::ExtendedPoint,
+    ivks: &[[u8; 32]],
+) -> Option<Vec<jubjub::SubgroupPoint>> {
+    let epk = WnafBase::new(*epk);
+    ivks.iter()
+        .map(|ivk| cache.sapling(ivk).map(|ivk| ivk.ka_agree(&epk)))
+        .collect()
+}
+
+/// The transactions of a block to be rescanned, in block order, with their Orchard
+/// bundles if they have one.
+pub struct ScanBlock<B> {
+    pub height: BlockHeight,
+    pub transactions: Vec<(TxId, Option<B>)>,
+}
+
+#[derive(Debug, Clone)]
+pub enum RescanError {
+    /// The block at this height does not follow the last block that the wallet
+    /// checkpointed.
+    OutOfOrder(BlockHeight),
+    /// The commitments of a block could not be appended to the wallet's note
+    /// commitment tree.
+    Wallet(WalletError),
+}
+
+fn orchard_bundle<B>(tx: &(TxId, Option<B>)) -> Option<&Bundle<Authorized, Amount>>
+where
+    B: Borrow<Bundle<Authorized, Amount>>,
+{
+    tx.1.as_ref().map(|bundle| bundle.borrow())
+}
+
+/// Trial-decrypts the outputs of `blocks` with `keys`, and returns the notes decrypted
+/// from each transaction of each block.
+///
+/// Each output is decrypted with the first of `keys` that can decrypt it, as
+/// `Bundle::decrypt_outputs_with_keys` does.
+fn decrypt_blocks<B>(
+    blocks: &[ScanBlock<B>],
+    keys: &[Arc<PreparedOrchardIvk>],
+) -> Vec<Vec<Vec<DecryptedOutput>>>
+where
+    B: Borrow<Bundle<Authorized, Amount>> + Sync,
+{
+    let bundle =
+        |block_idx: usize, tx_idx: usize| orchard_bundle(&blocks[block_idx].transactions[tx_idx]);
+
+    // The block, transaction and action index of every output in the window.
+    let outputs: Vec<(usize, usize, usize)> = blocks
+        .iter()
+        .enumerate()
+        .flat_map(|(block_idx, block)| {
+            (0..block.transactions.len()).flat_map(move |tx_idx| {
+                let actions = bundle(block_idx, tx_idx).map_or(0, |b| b.actions().len());
+                (0..actions).map(move |action_idx| (block_idx, tx_idx, action_idx))
+            })
+        })
+        .collect();
+
+    // Each work item trial-decrypts one output with one key, so that the outputs of a
+    // large transaction are spread across threads as well as those of many small ones.
+    // The collected items stay in output and then key order.
+    let mut decrypted: Vec<_> = (0..outputs.len() * keys.len())
+        .into_par_iter()
+        .filter_map(|item| {
+            let (output, key) = (item / keys.len(), item % keys.len());
+            let (block_idx, tx_idx, action_idx) = outputs[output];
+            bundle(block_idx, tx_idx)
+                .expect("only the outputs of bundles are listed; qed")
+                .decrypt_output_with_key(action_idx, &keys[key].0)
+                .map(|note| (output, key, note))
+        })
+        .collect();
+    decrypted.dedup_by_key(|(output, _, _)| *output);
+
+    let mut results: Vec<Vec<Vec<DecryptedOutput>>> = blocks
+        .iter()
+        .map(|block| block.transactions.iter().map(|_| vec![]).collect())
+        .collect();
+    for (output, key, (note, recipient, memo)) in decrypted {
+        let (block_idx, tx_idx, action_idx) = outputs[output];
+        results[block_idx][tx_idx].push((action_idx, keys[key].0.clone(), note, recipient, memo));
+    }
+    results
+}
+
+/// Adds the notes decrypted from `blocks` to `wallet`, then checkpoints each block and
+/// appends its note commitments.
+fn apply_blocks<B, F>(
+    wallet: &mut Wallet,
+    blocks: &[ScanBlock<B>],
+    first_block_idx: usize,
+    decrypted: Vec<Vec<Vec<DecryptedOutput>>>,
+    on_tx: &mut F,
+) -> Result<(), RescanError>
+where
+    B: Borrow<Bundle<Authorized, Amount>>,
+    F: FnMut(usize, usize, BundleWalletInvolvement),
+{
+    for (block_idx, (block, block_decrypted)) in blocks.iter().zip(decrypted).enumerate() {
+        for ((tx_idx, tx), tx_decrypted) in
+            block.transactions.iter().enumerate().zip(block_decrypted)
+        {
+            if let Some(bundle) = orchard_bundle(tx) {
+                let involvement = wallet.add_decrypted_outputs(&tx.0, bundle, tx_decrypted);
+                on_tx(first_block_idx + block_idx, tx_idx, involvement);
+            }
+        }
+
+        if !wallet.checkpoint(block.height) {
+            return Err(RescanError::OutOfOrder(block.height));
+        }
+        for (tx_idx, tx) in block.transactions.iter().enumerate() {
+            if let Some(bundle) = orchard_bundle(tx) {
+                wallet
+                    .append_bundle_commitments(block.height, tx_idx, &tx.0, bundle)
+                    .map_err(RescanError::Wallet)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Scans `blocks` for notes belonging to `wallet`, with the same result as adding the
+/// notes of each of their transactions with `Wallet::add_notes_from_bundle`, then
+/// checkpointing each block and appending its commitments.
+///
+/// `on_tx` is called with the index of the block among `blocks`, the index of the
+/// transaction within it and the transaction's involvement with the wallet, for each
+/// transaction with an Orchard bundle, in order.
+///
+/// At most `max_in_flight_blocks` blocks (and at least two) are taken from `blocks` at a
+/// time: half of them are decrypted while the notes of the other half are added to the
+/// wallet. Scanning stops at the first block that cannot be applied.
+pub fn rescan<I, B, F>(
+    wallet: &mut Wallet,
+    blocks: I,
+    max_in_flight_blocks: usize,
+    mut on_tx: F,
+) -> Result<(), RescanError>
+where
+    I: IntoIterator<Item = ScanBlock<B>>,
+    B: Borrow<Bundle<Authorized, Amount>> + Sync,
+    F: FnMut(usize, usize, BundleWalletInvolvement),
+{
+    let window_len = (max_in_flight_blocks / 2).max(1);
+    // The wallet's keys do not change while it is being scanned.
+    let keys = wallet.prepared_incoming_viewing_keys();
+    let mut blocks = blocks.into_iter();
+    let mut next_window = || blocks.by_ref().take(window_len).collect::<Vec<_>>();
+
+    let mut window = next_window();
+    let mut decrypted = decrypt_blocks(&window, &keys);
+    let mut first_block_idx = 0;
+    while !window.is_empty() {
+        let following = next_window();
+        let mut following_decrypted = vec![];
+        rayon::in_place_scope(|s| {
+            s.spawn(|_| following_decrypted = decrypt_blocks(&following, &keys));
+            apply_blocks(wallet, &window, first_block_idx, decrypted, &mut on_tx)
+        })?;
+
+        first_block_idx += window.len();
+        window = following;
+        decrypted = following_decrypted;
+    }
+
+    Ok(())
+}
+
+//
+// FFI
+//
+
+/// A transaction of a block to be rescanned. This must have the same representation as
+/// `struct RawOrchardScanTx` in `rust/include/rust/orchard/wallet.h`.
+#[repr(C)]
+pub struct FFIScanTx {
+    txid: [u8; 32],
+    bundle: *const Bundle<Authorized, Amount>,
+    cb_receiver: Option<FFICallbackReceiver>,
+}
+
+/// A block to be rescanned. This must have the same representation as
+/// `struct RawOrchardScanBlock` in `rust/include/rust/orchard/wallet.h`.
+#[repr(C)]
+pub struct FFIScanBlock {
+    height: u32,
+    txs: *const FFIScanTx,
+    txs_len: usize,
+}
+
+impl FFIScanBlock {
+    fn txs(&self) -> &[FFIScanTx] {
+        if self.txs_len == 0 {
+            &[]
+        } else {
+            unsafe { slice::from_raw_parts(self.txs, self.txs_len) }
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn orchard_wallet_rescan_blocks(
+    wallet: *mut Wallet,
+    blocks: *const FFIScanBlock,
+    blocks_len: usize,
+    max_in_flight_blocks: usize,
+    action_ivk_push_cb: Option<ActionIvkPushCb>,
+    spend_idx_push_cb: Option<SpendIndexPushCb>,
+) -> bool {
+    let wallet = unsafe { wallet.as_mut() }.expect("Wallet pointer may not be null");
+    let blocks = if blocks_len == 0 {
+        &[]
+    } else {
+        unsafe { slice::from_raw_parts(blocks, blocks_len) }
+    };
+    let action_ivk_push_cb = action_ivk_push_cb.unwrap();
+    let spend_idx_push_cb = spend_idx_push_cb.unwrap();
+    let max_in_flight_blocks = match max_in_flight_blocks {
+        0 => DEFAULT_MAX_IN_FLIGHT_BLOCKS,
+        n => n,
+    };
+
+    let scan_blocks = blocks.iter().map(|block| ScanBlock {
+        height: block.height.into(),
+        transactions: block
+            .txs()
+            .iter()
+            .map(|tx| (TxId::from_bytes(tx.txid), unsafe { tx.bundle.as_ref() }))
+            .collect(),
+    });
+
+    match rescan(
+        wallet,
+        scan_blocks,
+        max_in_flight_blocks,
+        |block_idx, tx_idx, involvement| {
+            let cb_receiver = blocks[block_idx].txs()[tx_idx].cb_receiver;
+            involvement.push_to(cb_receiver, action_ivk_push_cb, spend_idx_push_cb);
+        },
+    ) {
+        Ok(()) => true,
+        Err(e) => {
+            error!("Failed to rescan Orchard blocks: {:?}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Measures the allocations made by `librustzcash_mmr_append`, called through the same
+//! `extern` declaration as the C++ side.
+//!
+//! This is its own test binary because it replaces the global allocator, which would
+//! otherwise count (and slow down) the allocations of every unit test.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use libc::{c_uchar, size_t};
+use zcash_history::{
+    Entry, EntryLink, NodeData, Tree, Version, MAX_ENTRY_SIZE, MAX_NODE_DATA_SIZE, V1,
+};
+
+// Links the bridge functions declared below.
+extern crate rustzcash;
+
+extern "system" {
+    fn librustzcash_mmr_total_nodes(leaf_count: u32) -> u32;
+
+    fn librustzcash_mmr_append(
+        cbranch: u32,
+        t_len: u32,
+        ni_ptr: *const u32,
+        n_ptr: *const [c_uchar; MAX_ENTRY_SIZE],
+        nl_ptr: *const size_t,
+        p_len: size_t,
+        nn_ptr: *const [u8; MAX_NODE_DATA_SIZE],
+        rt_ret: *mut [u8; 32],
+        buf_ret: *mut [c_uchar; MAX_NODE_DATA_SIZE],
+    ) -> u32;
+}
+
+/// Counts the allocations made on each thread, so that a test can measure just its own.
+struct CountingAllocator;
+
+thread_local! {
+    /// The number of allocations made on this thread, and their total size.
+    static ALLOCATED: Cell<(usize, usize)> = Cell::new((0, 0));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        // The counter is unavailable while the thread is being torn down.
+        let _ = ALLOCATED.try_with(|allocated| {
+            let (count, bytes) = allocated.get();
+            allocated.set((count + 1, bytes + layout.size()));
+        });
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Returns the number and total size of the allocations that `f` makes.
+fn allocations<T>(f: impl FnOnce() -> T) -> ((usize, usize), T) {
+    let (count, bytes) = ALLOCATED.with(Cell::get);
+    let ret = f();
+    let (count_after, bytes_after) = ALLOCATED.with(Cell::get);
+    ((count_after - count, bytes_after - bytes), ret)
+}
+
+/// The peaks of a simulated V1 tree, as `CCoinsViewDB` passes them.
+struct SimulatedTree {
+    t_len: u32,
+    indices: Vec<u32>,
+    entries: Vec<[u8; MAX_ENTRY_SIZE]>,
+    lengths: Vec<size_t>,
+    altitudes: Vec<u32>,
+    new_leaf: [u8; MAX_NODE_DATA_SIZE],
+}
+
+/// Returns the peaks of a tree of `leaves` leaves, along with the data of the next
+/// leaf. Their contents are made up rather than combined from real leaves, which the
+/// append does not check.
+fn simulated_tree(leaves: u32) -> SimulatedTree {
+    let node_data = |first_leaf: u32, alt: u32| NodeData {
+        consensus_branch_id: 0,
+        subtree_commitment: [alt as u8; 32],
+        start_time: first_leaf,
+        end_time: first_leaf + (1 << alt),
+        start_target: 190,
+        end_target: 200,
+        start_sapling_root: [0; 32],
+        end_sapling_root: [0; 32],
+        subtree_total_work: Default::default(),
+        start_height: u64::from(first_leaf),
+        end_height: u64::from(first_leaf) + (1 << alt) - 1,
+        sapling_tx: 1 << alt,
+    };
+
+    // Each peak is the root of a perfect subtree, one for each bit set in `leaves`.
+    let (mut indices, mut entries, mut lengths, mut altitudes) = (vec![], vec![], vec![], vec![]);
+    let (mut t_len, mut first_leaf) = (0, 1);
+    for alt in (0..32).rev().filter(|alt| leaves & (1 << alt) != 0) {
+        t_len += (2 << alt) - 1;
+        let index = t_len - 1;
+        let data = node_data(first_leaf, alt);
+        let entry: Entry<V1> = if alt == 0 {
+            Entry::new_leaf(data)
+        } else {
+            Entry::new(
+                data,
+                EntryLink::Stored(index - (1 << alt)),
+                EntryLink::Stored(index - 1),
+            )
+        };
+        let mut buf = [0; MAX_ENTRY_SIZE];
+        let mut unwritten = &mut buf[..];
+        entry
+            .write(&mut unwritten)
+            .expect("Cannot fail if enough buffer length");
+        lengths.push(MAX_ENTRY_SIZE - unwritten.len());
+        indices.push(index);
+        entries.push(buf);
+        altitudes.push(alt);
+        first_leaf += 1 << alt;
+    }
+    assert_eq!(t_len, unsafe { librustzcash_mmr_total_nodes(leaves) });
+
+    let mut new_leaf = [0; MAX_NODE_DATA_SIZE];
+    node_data(first_leaf, 0)
+        .write(&mut &mut new_leaf[..])
+        .expect("Cannot fail if enough buffer length");
+    SimulatedTree {
+        t_len,
+        indices,
+        entries,
+        lengths,
+        altitudes,
+        new_leaf,
+    }
+}
+
+impl SimulatedTree {
+    /// Appends the new leaf with `librustzcash_mmr_append`, returning the root.
+    fn append(&self) -> [u8; 32] {
+        let mut rt_ret = [0; 32];
+        let mut buf_ret = [[0; MAX_NODE_DATA_SIZE]; 32];
+        let appended = unsafe {
+            librustzcash_mmr_append(
+                0,
+                self.t_len,
+                self.indices.as_ptr(),
+                self.entries.as_ptr(),
+                self.lengths.as_ptr(),
+                self.indices.len(),
+                &self.new_leaf,
+                &mut rt_ret,
+                buf_ret.as_mut_ptr(),
+            )
+        };
+        assert_ne!(appended, 0);
+        rt_ret
+    }
+
+    /// Appends the new leaf with `zcash_history` directly, from peaks that are already
+    /// decoded, returning the root.
+    fn append_decoded(&self, peaks: Vec<(u32, Entry<V1>)>) -> [u8; 32] {
+        let mut tree = Tree::<V1>::new(self.t_len, peaks, vec![]);
+        let new_leaf = V1::from_bytes(0, &self.new_leaf[..]).unwrap();
+        tree.append_leaf(new_leaf).unwrap();
+        V1::hash(tree.root_node().unwrap().data())
+    }
+
+    /// Appends the new leaf by combining it with the peaks directly, as the FFI does
+    /// for trees of at most 16 peaks, returning the root.
+    fn append_combined(&self) -> [u8; 32] {
+        let peak = |i: usize| {
+            // The FFI checks each entry, then decodes its data from after the kind byte,
+            // and the links of a non-leaf.
+            let entry = &self.entries[i][..self.lengths[i]];
+            Entry::<V1>::from_bytes(0, entry).unwrap();
+            let data = if self.altitudes[i] == 0 { 1 } else { 9 };
+            V1::from_bytes(0, &entry[data..]).unwrap()
+        };
+        let peak_count = self.indices.len();
+
+        // Merge the new leaf with the peaks of the same altitude, from right to left,
+        // then bag the rest of the peaks and the merged node from left to right.
+        let mut merged = V1::from_bytes(0, &self.new_leaf[..]).unwrap();
+        let mut rest = peak_count;
+        while rest > 0 && self.altitudes[rest - 1] == (peak_count - rest) as u32 {
+            rest -= 1;
+            merged = V1::combine(&peak(rest), &merged);
+        }
+        let root = (0..rest)
+            .map(peak)
+            .chain(Some(merged))
+            .reduce(|root, node| V1::combine(&root, &node))
+            .unwrap();
+        V1::hash(&root)
+    }
+
+    /// Decodes the peaks into a list allocated at its final size, as the FFI does.
+    fn decode_peaks(&self) -> Vec<(u32, Entry<V1>)> {
+        let mut peaks = Vec::with_capacity(self.indices.len());
+        for ((index, entry), length) in self
+            .indices
+            .iter()
+            .zip(self.entries.iter())
+            .zip(self.lengths.iter())
+        {
+            peaks.push((*index, Entry::from_bytes(0, &entry[..*length]).unwrap()));
+        }
+        peaks
+    }
+}
+
+#[test]
+fn append_allocations_do_not_scale_with_peaks() {
+    // Trees of at most 16 peaks are appended to without a `Tree`, with the peaks
+    // decoded onto the stack, so nothing is allocated beyond what combining and hashing
+    // the nodes allocates.
+    for (leaves, peak_count) in [(1_000_000, 7), ((1 << 16) - 1, 16)] {
+        let tree = simulated_tree(leaves);
+        assert_eq!(tree.indices.len(), peak_count);
+
+        // Sizes the reused buffers before measuring.
+        let root = tree.append();
+
+        let (ffi_allocated, ffi_root) = allocations(|| tree.append());
+        let (allocated, combined_root) = allocations(|| tree.append_combined());
+        assert_eq!(ffi_root, root);
+        assert_eq!(combined_root, root);
+        assert_eq!(ffi_allocated, allocated, "{} leaves", leaves);
+    }
+
+    // Larger trees are appended to with a `Tree`, which takes its peaks in a `Vec` of
+    // decoded entries, so an append allocates at least that and whatever the tree
+    // itself allocates. Everything the FFI allocates on top of that must be independent
+    // of the size of the tree: entries are decoded straight from the caller's buffers,
+    // and nodes are encoded into a buffer reused across calls, rather than each being
+    // copied into its own `MAX_ENTRY_SIZE` buffer.
+    let overhead = |leaves: u32| {
+        let tree = simulated_tree(leaves);
+        let root = tree.append();
+
+        let ((ffi_count, ffi_bytes), ffi_root) = allocations(|| tree.append());
+        let ((count, bytes), decoded_root) =
+            allocations(|| tree.append_decoded(tree.decode_peaks()));
+        assert_eq!(ffi_root, root);
+        assert_eq!(decoded_root, root);
+        (
+            tree.indices.len(),
+            ffi_count.checked_sub(count).unwrap(),
+            ffi_bytes.checked_sub(bytes).unwrap(),
+        )
+    };
+
+    // 2^20 - 1 leaves make 20 peaks, the most of any tree of fewer than 2^20 leaves.
+    let (small_peaks, small_count, small_bytes) = overhead((1 << 17) - 1);
+    let (large_peaks, large_count, large_bytes) = overhead((1 << 20) - 1);
+    assert_eq!(small_peaks, 17);
+    assert_eq!(large_peaks, 20);
+    assert_eq!(large_count, small_count);
+    assert_eq!(large_bytes, small_bytes);
+}
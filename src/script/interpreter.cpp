@@ -1262,11 +1262,20 @@ uint256 SignatureHash(
             //   is updated to know about it!
             // The consensusBranchId parameter is ignored; we use the value stored
             // in the transaction itself.
+            // The scriptCode parameter does not affect the digest, as ZIP 244
+            // commits to the scriptPubKey being spent; it is only passed for
+            // transparent inputs.
+            std::vector<unsigned char> scriptCodeBytes;
+            if (nIn != NOT_AN_INPUT) {
+                scriptCodeBytes.assign(scriptCode.begin(), scriptCode.end());
+            }
             uint256 hash;
             if (!zcash_transaction_zip244_signature_digest(
                 txdata.preTx.get(),
                 nHashType,
                 nIn,
+                scriptCodeBytes.data(),
+                scriptCodeBytes.size(),
                 hash.begin()))
             {
                 throw std::logic_error("We should not reach here.");
@@ -6,12 +6,15 @@
 #include "rpc/server.h"
 #include "rpc/client.h"
 
+#include "core_io.h"
 #include "experimental_features.h"
 #include "key_io.h"
 #include "main.h"
 #include "netbase.h"
+#include "primitives/transaction.h"
 #include "utilstrencodings.h"
 
+#include "test/data/zip0244.json.h"
 #include "test/test_bitcoin.h"
 #include "test/test_util.h"
 
@@ -69,6 +72,51 @@ BOOST_AUTO_TEST_CASE(rpc_rawparams)
     BOOST_CHECK_THROW(CallRPC(string("sendrawtransaction ")+rawtx+" extra"), runtime_error);
 }
 
+BOOST_AUTO_TEST_CASE(rpc_decoderawtransaction_orchard)
+{
+    UniValue tests = read_json(std::string(json_tests::zip0244, json_tests::zip0244 + sizeof(json_tests::zip0244)));
+
+    // Skipping over comments in zip0244.json file
+    size_t orchardVectors = 0;
+    for (size_t idx = 2; idx < tests.size(); idx++) {
+        std::string transaction = tests[idx][0].get_str();
+        CTransaction tx;
+        BOOST_REQUIRE(DecodeHexTx(tx, transaction));
+        const auto& orchardBundle = tx.GetOrchardBundle();
+
+        UniValue r;
+        BOOST_CHECK_NO_THROW(r = CallRPC(string("decoderawtransaction ") + transaction));
+        UniValue orchard = find_value(r.get_obj(), "orchard");
+        BOOST_REQUIRE(orchard.isObject());
+        BOOST_CHECK_EQUAL(find_value(orchard, "valueBalanceZat").get_int64(), orchardBundle.GetValueBalance());
+
+        UniValue actions = find_value(orchard, "actions");
+        BOOST_REQUIRE_EQUAL(actions.size(), orchardBundle.GetNumActions());
+        if (actions.empty()) {
+            BOOST_CHECK(find_value(orchard, "anchor").isNull());
+            continue;
+        }
+        orchardVectors++;
+
+        // The fields that the C++ parse exposes are rendered as it would render
+        // them, and every other field is found in the encoding.
+        auto nullifiers = orchardBundle.GetNullifiers();
+        for (size_t i = 0; i < actions.size(); i++) {
+            BOOST_CHECK_EQUAL(find_value(actions[i], "nullifier").get_str(), nullifiers[i].GetHex());
+            for (auto field : {"cv", "rk", "ephemeralKey", "encCiphertext", "outCiphertext", "spendAuthSig"}) {
+                BOOST_CHECK(transaction.find(find_value(actions[i], field).get_str()) != std::string::npos);
+            }
+        }
+        UniValue flags = find_value(orchard, "flags");
+        BOOST_CHECK_EQUAL(find_value(flags, "enableSpends").get_bool(), orchardBundle.SpendsEnabled());
+        BOOST_CHECK_EQUAL(find_value(flags, "enableOutputs").get_bool(), orchardBundle.OutputsEnabled());
+        BOOST_CHECK_EQUAL(find_value(orchard, "anchor").get_str(), orchardBundle.GetAnchor().value().GetHex());
+        BOOST_CHECK(transaction.find(find_value(orchard, "proof").get_str()) != std::string::npos);
+        BOOST_CHECK_EQUAL(find_value(orchard, "bindingSig").get_str(), transaction.substr(transaction.size() - 128));
+    }
+    BOOST_CHECK(orchardVectors > 0);
+}
+
 BOOST_AUTO_TEST_CASE(rpc_rawsign)
 {
     UniValue r;
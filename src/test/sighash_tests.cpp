@@ -5,11 +5,13 @@
 
 #include "consensus/upgrades.h"
 #include "consensus/validation.h"
+#include "key.h"
 #include "test/data/sighash.json.h"
 #include "main.h"
 #include "test_random.h"
 #include "script/interpreter.h"
 #include "script/script.h"
+#include "script/standard.h"
 #include "serialize.h"
 #include "test/test_bitcoin.h"
 #include "test/test_util.h"
@@ -23,6 +25,7 @@
 
 #include <rust/ed25519.h>
 #include <rust/test_harness.h>
+#include <rust/transaction.h>
 
 #include <univalue.h>
 
@@ -344,4 +347,72 @@ BOOST_AUTO_TEST_CASE(sighash_from_data)
         BOOST_CHECK_MESSAGE(sh.GetHex() == sigHashHex, strTest);
     }
 }
+// Goal: check that the Rust legacy signature digest commits to the redeem script of a
+// P2SH input, exactly as SignatureHash does
+BOOST_AUTO_TEST_CASE(legacy_signature_digest_p2sh_multisig)
+{
+    uint32_t saplingBranchId = NetworkUpgradeInfo[Consensus::UPGRADE_SAPLING].nBranchId;
+
+    std::vector<CPubKey> keys;
+    for (int i = 0; i < 3; i++) {
+        keys.push_back(CKey::TestOnlyRandomKey(true).GetPubKey());
+    }
+    CScript redeemScript = GetScriptForMultisig(2, keys);
+    CScript scriptPubKey = GetScriptForDestination(CScriptID(redeemScript));
+    CAmount value = 10 * COIN;
+
+    CMutableTransaction mtx;
+    mtx.fOverwintered = true;
+    mtx.nVersionGroupId = SAPLING_VERSION_GROUP_ID;
+    mtx.nVersion = SAPLING_TX_VERSION;
+    mtx.vin.resize(2);
+    mtx.vin[0].prevout = COutPoint(GetRandHash(), 0);
+    mtx.vin[1].prevout = COutPoint(GetRandHash(), 1);
+    // An output for each input, so that SIGHASH_SINGLE commits to one.
+    mtx.vout.resize(2);
+    mtx.vout[0].nValue = 4 * COIN;
+    mtx.vout[0].scriptPubKey = GetScriptForDestination(keys[0].GetID());
+    mtx.vout[1].nValue = 5 * COIN;
+    mtx.vout[1].scriptPubKey = scriptPubKey;
+    CTransaction tx(mtx);
+
+    CDataStream ss(SER_NETWORK, PROTOCOL_VERSION);
+    ss << tx;
+    std::vector<unsigned char> txBytes(ss.begin(), ss.end());
+    std::vector<unsigned char> scriptPubKeyBytes(scriptPubKey.begin(), scriptPubKey.end());
+    std::vector<unsigned char> redeemScriptBytes(redeemScript.begin(), redeemScript.end());
+    const PrecomputedTransactionData txdata(tx, {});
+
+    for (int nHashType : {SIGHASH_ALL, SIGHASH_NONE, SIGHASH_SINGLE,
+                          SIGHASH_ALL | SIGHASH_ANYONECANPAY}) {
+        for (size_t nIn = 0; nIn < tx.vin.size(); nIn++) {
+            uint256 expected = SignatureHash(redeemScript, tx, nIn, nHashType, value, saplingBranchId, txdata);
+
+            uint256 sighash;
+            BOOST_CHECK(zcash_transaction_legacy_signature_digest(
+                txBytes.data(), txBytes.size(),
+                saplingBranchId,
+                nHashType,
+                nIn,
+                scriptPubKeyBytes.data(), scriptPubKeyBytes.size(),
+                redeemScriptBytes.data(), redeemScriptBytes.size(),
+                value,
+                sighash.begin()));
+            BOOST_CHECK_EQUAL(sighash.GetHex(), expected.GetHex());
+
+            // Committing to the P2SH script instead gives a different digest.
+            uint256 p2shSighash;
+            BOOST_CHECK(zcash_transaction_legacy_signature_digest(
+                txBytes.data(), txBytes.size(),
+                saplingBranchId,
+                nHashType,
+                nIn,
+                scriptPubKeyBytes.data(), scriptPubKeyBytes.size(),
+                nullptr, 0,
+                value,
+                p2shSighash.begin()));
+            BOOST_CHECK(p2shSighash != expected);
+        }
+    }
+}
 BOOST_AUTO_TEST_SUITE_END()
@@ -36,6 +36,7 @@
 #include <boost/test/data/test_case.hpp>
 
 #include <rust/ed25519.h>
+#include <rust/transaction.h>
 #include <rust/orchard.h>
 
 #include <univalue.h>
@@ -840,6 +841,58 @@ BOOST_AUTO_TEST_CASE(TxV5)
         BOOST_CHECK_EQUAL(tx.GetHash().GetHex(), test[1].getValStr());
         BOOST_CHECK_EQUAL(tx.GetAuthDigest().GetHex(), test[2].getValStr());
 
+        // The Rust inspection that decoderawtransaction renders Orchard bundles
+        // from agrees with the C++ parse.
+        auto txBytes = ParseHex(transaction);
+        uint32_t status;
+        TxInfo* info = zcash_transaction_inspect(txBytes.data(), txBytes.size(), true, &status);
+        BOOST_REQUIRE(info != nullptr);
+        TxSummary summary;
+        zcash_tx_info_summary(info, &summary);
+        BOOST_CHECK_EQUAL(summary.versionGroupId, tx.nVersionGroupId);
+        BOOST_CHECK_EQUAL(summary.consensusBranchId, tx.GetConsensusBranchId().value());
+        BOOST_CHECK_EQUAL(summary.lockTime, tx.nLockTime);
+        BOOST_CHECK_EQUAL(summary.expiryHeight, tx.nExpiryHeight);
+        BOOST_CHECK_EQUAL(summary.transparentInputs, tx.vin.size());
+        BOOST_CHECK_EQUAL(summary.transparentOutputs, tx.vout.size());
+        BOOST_CHECK_EQUAL(summary.saplingSpends, tx.vShieldedSpend.size());
+        BOOST_CHECK_EQUAL(summary.saplingOutputs, tx.vShieldedOutput.size());
+        BOOST_CHECK_EQUAL(summary.saplingValueBalance, tx.GetValueBalanceSapling());
+        const auto& orchardBundle = tx.GetOrchardBundle();
+        BOOST_CHECK_EQUAL(summary.orchardActions, orchardBundle.GetNumActions());
+        BOOST_CHECK_EQUAL(summary.orchardValueBalance, orchardBundle.GetValueBalance());
+        BOOST_CHECK_EQUAL((summary.orchardFlags & 1) != 0, orchardBundle.SpendsEnabled());
+        BOOST_CHECK_EQUAL((summary.orchardFlags & 2) != 0, orchardBundle.OutputsEnabled());
+
+        char hex[65];
+        for (size_t i = 0; i < tx.vShieldedSpend.size(); i++) {
+            BOOST_CHECK(zcash_tx_info_hex(info, TX_INFO_SAPLING_ANCHOR, i, hex));
+            BOOST_CHECK_EQUAL(hex, tx.vShieldedSpend[i].anchor.GetHex());
+            BOOST_CHECK(zcash_tx_info_hex(info, TX_INFO_SAPLING_NULLIFIER, i, hex));
+            BOOST_CHECK_EQUAL(hex, tx.vShieldedSpend[i].nullifier.GetHex());
+        }
+        for (size_t i = 0; i < tx.vShieldedOutput.size(); i++) {
+            BOOST_CHECK(zcash_tx_info_hex(info, TX_INFO_SAPLING_CMU, i, hex));
+            BOOST_CHECK_EQUAL(hex, tx.vShieldedOutput[i].cmu.GetHex());
+        }
+        auto orchardNullifiers = orchardBundle.GetNullifiers();
+        for (size_t i = 0; i < orchardNullifiers.size(); i++) {
+            BOOST_CHECK(zcash_tx_info_hex(info, TX_INFO_ORCHARD_NULLIFIER, i, hex));
+            BOOST_CHECK_EQUAL(hex, orchardNullifiers[i].GetHex());
+        }
+        auto orchardAnchor = orchardBundle.GetAnchor();
+        BOOST_CHECK_EQUAL(zcash_tx_info_hex(info, TX_INFO_ORCHARD_ANCHOR, 0, hex), orchardAnchor.has_value());
+        if (orchardAnchor.has_value()) {
+            BOOST_CHECK_EQUAL(hex, orchardAnchor.value().GetHex());
+
+            // The bundle's binding signature ends the transaction.
+            size_t len;
+            const unsigned char* bindingSig = zcash_tx_info_bytes(info, TX_INFO_ORCHARD_BINDING_SIG, 0, &len);
+            BOOST_REQUIRE(bindingSig != nullptr);
+            BOOST_CHECK_EQUAL(HexStr(bindingSig, bindingSig + len), transaction.substr(transaction.size() - 128));
+        }
+        zcash_tx_info_free(info);
+
         UniValue amountsArr = test[3].get_array();
         UniValue scriptCodesArr = test[4].get_array();
         std::vector<CAmount> amounts;
@@ -7,6 +7,7 @@
 #include "main.h"
 #include "proof_verifier.h"
 #include "pubkey.h"
+#include "random.h"
 #include "rpc/protocol.h"
 #include "script/sign.h"
 #include "utilmoneystr.h"
@@ -14,6 +15,7 @@
 
 #include <librustzcash.h>
 #include <rust/ed25519.h>
+#include <rust/sprout.h>
 
 uint256 ProduceZip244SignatureHash(
     const CTransaction& tx,
@@ -94,7 +96,8 @@ std::optional<UnauthorizedBundle> Builder::Build() {
 
 std::optional<OrchardBundle> UnauthorizedBundle::ProveAndSign(
     const std::vector<libzcash::OrchardSpendingKey>& keys,
-    uint256 sighash)
+    uint256 sighash,
+    size_t numThreads)
 {
     if (!inner) {
         throw std::logic_error("orchard::UnauthorizedBundle has already been used");
@@ -106,7 +109,7 @@ std::optional<OrchardBundle> UnauthorizedBundle::ProveAndSign(
     }
 
     auto authorizedBundle = orchard_unauthorized_bundle_prove_and_sign(
-        inner.release(), pKeys.data(), pKeys.size(), sighash.begin());
+        inner.release(), pKeys.data(), pKeys.size(), sighash.begin(), numThreads);
     if (authorizedBundle == nullptr) {
         return std::nullopt;
     } else {
@@ -122,7 +125,9 @@ SpendDescriptionInfo::SpendDescriptionInfo(
     uint256 anchor,
     SaplingWitness witness) : expsk(expsk), note(note), anchor(anchor), witness(witness)
 {
-    librustzcash_sapling_generate_r(alpha.begin());
+    if (!librustzcash_sapling_generate_r(alpha.begin())) {
+        RandFailure();
+    }
 }
 
 std::optional<OutputDescription> OutputDescriptionInfo::Build(void* ctx) {
@@ -444,6 +449,11 @@ void TransactionBuilder::SetFee(CAmount fee)
     this->fee = fee;
 }
 
+void TransactionBuilder::SetOrchardProvingThreads(size_t numThreads)
+{
+    this->orchardProvingThreads = numThreads;
+}
+
 // TODO: remove support for transparent change?
 void TransactionBuilder::SendChangeTo(
         const libzcash::RecipientAddress& changeAddr,
@@ -614,7 +624,9 @@ TransactionBuilderResult TransactionBuilder::Build()
     //
 
     Ed25519SigningKey joinSplitPrivKey;
-    ed25519_generate_keypair(&joinSplitPrivKey, &mtx.joinSplitPubKey);
+    if (!ed25519_generate_keypair(&joinSplitPrivKey, &mtx.joinSplitPubKey)) {
+        RandFailure();
+    }
 
     // Create Sprout JSDescriptions
     if (!jsInputs.empty() || !jsOutputs.empty()) {
@@ -656,7 +668,7 @@ TransactionBuilderResult TransactionBuilder::Build()
 
     if (orchardBundle.has_value()) {
         auto authorizedBundle = orchardBundle.value().ProveAndSign(
-            orchardSpendingKeys, dataToBeSigned);
+            orchardSpendingKeys, dataToBeSigned, orchardProvingThreads);
         if (authorizedBundle.has_value()) {
             mtx.orchardBundle = authorizedBundle.value();
         } else {
@@ -734,289 +746,85 @@ void TransactionBuilder::CheckOrSetUsingSprout()
 
 void TransactionBuilder::CreateJSDescriptions()
 {
-    // Copy jsInputs and jsOutputs to more flexible containers
-    std::deque<libzcash::JSInput> jsInputsDeque;
-    for (auto jsInput : jsInputs) {
-        jsInputsDeque.push_back(jsInput);
-    }
-    std::deque<libzcash::JSOutput> jsOutputsDeque;
-    for (auto jsOutput : jsOutputs) {
-        jsOutputsDeque.push_back(jsOutput);
-    }
-
-    // If we have no Sprout shielded inputs, then we do the simpler more-leaky
-    // process where we just create outputs directly. We save the chaining logic,
-    // at the expense of leaking the sums of pairs of output values in vpub_old.
-    if (jsInputs.empty()) {
-        // Create joinsplits, where each output represents a zaddr recipient.
-        while (jsOutputsDeque.size() > 0) {
-            // Default array entries are dummy inputs and outputs
-            std::array<libzcash::JSInput, ZC_NUM_JS_INPUTS> vjsin;
-            std::array<libzcash::JSOutput, ZC_NUM_JS_OUTPUTS> vjsout;
-            uint64_t vpub_old = 0;
-
-            for (int n = 0; n < ZC_NUM_JS_OUTPUTS && jsOutputsDeque.size() > 0; n++) {
-                vjsout[n] = jsOutputsDeque.front();
-                jsOutputsDeque.pop_front();
-
-                // Funds are removed from the value pool and enter the private pool
-                vpub_old += vjsout[n].value;
-            }
-
-            std::array<size_t, ZC_NUM_JS_INPUTS> inputMap;
-            std::array<size_t, ZC_NUM_JS_OUTPUTS> outputMap;
-            CreateJSDescription(vpub_old, 0, vjsin, vjsout, inputMap, outputMap);
+    // Chained JoinSplits are anchored to the tree as of the anchor of the notes
+    // being spent, extended with the commitments of the JoinSplits before them.
+    std::optional<std::vector<unsigned char>> anchorTree;
+    if (!jsInputs.empty()) {
+        // assert that coinsView is not null
+        assert(coinsView);
+        // We do not check cs_coinView because we do not set this in testing
+        // assert(cs_coinsView);
+        LOCK(cs_coinsView);
+        SproutMerkleTree tree;
+        if (coinsView->GetSproutAnchorAt(jsInputs[0].witness.root(), tree)) {
+            CDataStream ss(SER_NETWORK, PROTOCOL_VERSION);
+            ss << tree;
+            anchorTree = std::vector<unsigned char>(ss.begin(), ss.end());
         }
-        return;
     }
 
-    // At this point, we are guaranteed to have at least one input note.
-    // Use address of first input note as the temporary change address.
-    auto changeKey = jsInputsDeque.front().key;
-    auto changeAddress = changeKey.address();
-
-    CAmount jsChange = 0;          // this is updated after each joinsplit
-    int changeOutputIndex = -1;    // this is updated after each joinsplit if jsChange > 0
-    bool vpubOldProcessed = false; // updated when vpub_old for taddr inputs is set in first joinsplit
-    bool vpubNewProcessed = false; // updated when vpub_new for miner fee and taddr outputs is set in last joinsplit
-
-    CAmount valueOut = 0;
-    for (auto jsInput : jsInputs) {
-        valueOut += jsInput.note.value();
+    std::unique_ptr<SproutBuilderPtr, decltype(&sprout_builder_free)> builder(
+        sprout_builder_new(
+            mtx.joinSplitPubKey.bytes,
+            anchorTree.has_value() ? anchorTree->data() : nullptr,
+            anchorTree.has_value() ? anchorTree->size() : 0),
+        sprout_builder_free);
+    if (builder == nullptr) {
+        throw JSDescException("Could not parse the Sprout anchor tree");
     }
-    for (auto jsOutput : jsOutputs) {
-        valueOut -= jsOutput.value;
-    }
-    CAmount vpubOldTarget = valueOut < 0 ? -valueOut : 0;
-    CAmount vpubNewTarget = valueOut > 0 ? valueOut : 0;
-
-    // Keep track of treestate within this transaction
-    boost::unordered_map<uint256, SproutMerkleTree, SaltedTxidHasher> intermediates;
-    std::vector<uint256> previousCommitments;
-
-    while (!vpubNewProcessed) {
-        // Default array entries are dummy inputs and outputs
-        std::array<libzcash::JSInput, ZC_NUM_JS_INPUTS> vjsin;
-        std::array<libzcash::JSOutput, ZC_NUM_JS_OUTPUTS> vjsout;
-        uint64_t vpub_old = 0;
-        uint64_t vpub_new = 0;
-
-        // Set vpub_old in the first joinsplit
-        if (!vpubOldProcessed) {
-            vpub_old += vpubOldTarget; // funds flowing from public pool
-            vpubOldProcessed = true;
-        }
-
-        CAmount jsInputValue = 0;
-        uint256 jsAnchor;
-
-        JSDescription prevJoinSplit;
-
-        // Keep track of previous JoinSplit and its commitments
-        if (mtx.vJoinSplit.size() > 0) {
-            prevJoinSplit = mtx.vJoinSplit.back();
-        }
-
-        // If there is no change, the chain has terminated so we can reset the tracked treestate.
-        if (jsChange == 0 && mtx.vJoinSplit.size() > 0) {
-            intermediates.clear();
-            previousCommitments.clear();
-        }
-
-        //
-        // Consume change as the first input of the JoinSplit.
-        //
-        if (jsChange > 0) {
-            // Update tree state with previous joinsplit
-            SproutMerkleTree tree;
-            {
-                // assert that coinsView is not null
-                assert(coinsView);
-                // We do not check cs_coinView because we do not set this in testing
-                // assert(cs_coinsView);
-                LOCK(cs_coinsView);
-                auto it = intermediates.find(prevJoinSplit.anchor);
-                if (it != intermediates.end()) {
-                    tree = it->second;
-                } else if (!coinsView->GetSproutAnchorAt(prevJoinSplit.anchor, tree)) {
-                    throw JSDescException("Could not find previous JoinSplit anchor");
-                }
-            }
-
-            assert(changeOutputIndex != -1);
-            assert(changeOutputIndex < prevJoinSplit.commitments.size());
-            std::optional<SproutWitness> changeWitness;
-            int n = 0;
-            for (const uint256& commitment : prevJoinSplit.commitments) {
-                tree.append(commitment);
-                previousCommitments.push_back(commitment);
-                if (!changeWitness && changeOutputIndex == n++) {
-                    changeWitness = tree.witness();
-                } else if (changeWitness) {
-                    changeWitness.value().append(commitment);
-                }
-            }
-            assert(changeWitness.has_value());
-            jsAnchor = tree.root();
-            intermediates.insert(std::make_pair(tree.root(), tree)); // chained js are interstitial (found in between block boundaries)
-
-            // Decrypt the change note's ciphertext to retrieve some data we need
-            ZCNoteDecryption decryptor(changeKey.receiving_key());
-            auto hSig = ZCJoinSplit::h_sig(
-                prevJoinSplit.randomSeed,
-                prevJoinSplit.nullifiers,
-                mtx.joinSplitPubKey);
-            try {
-                auto plaintext = libzcash::SproutNotePlaintext::decrypt(
-                    decryptor,
-                    prevJoinSplit.ciphertexts[changeOutputIndex],
-                    prevJoinSplit.ephemeralKey,
-                    hSig,
-                    (unsigned char)changeOutputIndex);
-
-                auto note = plaintext.note(changeAddress);
-                vjsin[0] = libzcash::JSInput(changeWitness.value(), note, changeKey);
-
-                jsInputValue += plaintext.value();
-
-                LogPrint("zrpcunsafe", "spending change (amount=%s)\n", FormatMoney(plaintext.value()));
-
-            } catch (const std::exception& e) {
-                throw JSDescException("Error decrypting output note of previous JoinSplit");
-            }
-        }
 
-        //
-        // Consume spendable non-change notes
-        //
-        for (int n = (jsChange > 0) ? 1 : 0; n < ZC_NUM_JS_INPUTS && jsInputsDeque.size() > 0; n++) {
-            auto jsInput = jsInputsDeque.front();
-            jsInputsDeque.pop_front();
-
-            // Add history of previous commitments to witness
-            if (jsChange > 0) {
-                for (const uint256& commitment : previousCommitments) {
-                    jsInput.witness.append(commitment);
-                }
-                if (jsAnchor != jsInput.witness.root()) {
-                    throw JSDescException("Witness for spendable note does not have same anchor as change input");
-                }
-            }
-
-            // The jsAnchor is null if this JoinSplit is at the start of a new chain
-            if (jsAnchor.IsNull()) {
-                jsAnchor = jsInput.witness.root();
-            }
-
-            jsInputValue += jsInput.note.value();
-            vjsin[n] = jsInput;
-        }
-
-        // Find recipient to transfer funds to
-        libzcash::JSOutput recipient;
-        if (jsOutputsDeque.size() > 0) {
-            recipient = jsOutputsDeque.front();
-            jsOutputsDeque.pop_front();
-        }
-        // `recipient` is now either a valid recipient, or a dummy output with value = 0
-
-        // Reset change
-        jsChange = 0;
-        CAmount outAmount = recipient.value;
-
-        // Set vpub_new in the last joinsplit (when there are no more notes to spend or zaddr outputs to satisfy)
-        if (jsOutputsDeque.empty() && jsInputsDeque.empty()) {
-            assert(!vpubNewProcessed);
-            if (jsInputValue < vpubNewTarget) {
-                throw JSDescException(strprintf("Insufficient funds for vpub_new %s", FormatMoney(vpubNewTarget)));
-            }
-            outAmount += vpubNewTarget;
-            vpub_new += vpubNewTarget; // funds flowing back to public pool
-            vpubNewProcessed = true;
-            jsChange = jsInputValue - outAmount;
-            assert(jsChange >= 0);
-        } else {
-            // This is not the last joinsplit, so compute change and any amount still due to the recipient
-            if (jsInputValue > outAmount) {
-                jsChange = jsInputValue - outAmount;
-            } else if (outAmount > jsInputValue) {
-                // Any amount due is owed to the recipient.  Let the miners fee get paid first.
-                CAmount due = outAmount - jsInputValue;
-                libzcash::JSOutput recipientDue(recipient.addr, due);
-                recipientDue.memo = recipient.memo;
-                jsOutputsDeque.push_front(recipientDue);
-
-                // reduce the amount being sent right now to the value of all inputs
-                recipient.value = jsInputValue;
-            }
-        }
-
-        // create output for recipient
-        assert(ZC_NUM_JS_OUTPUTS == 2); // If this changes, the logic here will need to be adjusted
-        vjsout[0] = recipient;
-
-        // create output for any change
-        if (jsChange > 0) {
-            vjsout[1] = libzcash::JSOutput(changeAddress, jsChange);
-
-            LogPrint("zrpcunsafe", "generating note for change (amount=%s)\n", FormatMoney(jsChange));
+    for (const auto& jsInput : jsInputs) {
+        CDataStream ss(SER_NETWORK, PROTOCOL_VERSION);
+        ss << jsInput.witness;
+        std::vector<unsigned char> witness(ss.begin(), ss.end());
+        if (!sprout_builder_add_sprout_input(
+                builder.get(),
+                jsInput.key.begin(),
+                jsInput.note.value(),
+                jsInput.note.rho.begin(),
+                jsInput.note.r.begin(),
+                witness.data(),
+                witness.size())) {
+            throw JSDescException("Sprout input is invalid");
         }
-
-        std::array<size_t, ZC_NUM_JS_INPUTS> inputMap;
-        std::array<size_t, ZC_NUM_JS_OUTPUTS> outputMap;
-        CreateJSDescription(vpub_old, vpub_new, vjsin, vjsout, inputMap, outputMap);
-
-        if (jsChange > 0) {
-            changeOutputIndex = -1;
-            for (size_t i = 0; i < outputMap.size(); i++) {
-                if (outputMap[i] == 1) {
-                    changeOutputIndex = i;
-                }
-            }
-            assert(changeOutputIndex != -1);
+    }
+    for (const auto& jsOutput : jsOutputs) {
+        if (!sprout_builder_add_output(
+                builder.get(),
+                jsOutput.addr.a_pk.begin(),
+                jsOutput.addr.pk_enc.begin(),
+                jsOutput.value,
+                jsOutput.memo.data())) {
+            throw JSDescException("Sprout output is invalid");
         }
     }
-}
-
-void TransactionBuilder::CreateJSDescription(
-    uint64_t vpub_old,
-    uint64_t vpub_new,
-    std::array<libzcash::JSInput, ZC_NUM_JS_INPUTS> vjsin,
-    std::array<libzcash::JSOutput, ZC_NUM_JS_OUTPUTS> vjsout,
-    std::array<size_t, ZC_NUM_JS_INPUTS>& inputMap,
-    std::array<size_t, ZC_NUM_JS_OUTPUTS>& outputMap)
-{
-    LogPrint("zrpcunsafe", "CreateJSDescription: creating joinsplit at index %d (vpub_old=%s, vpub_new=%s, in[0]=%s, in[1]=%s, out[0]=%s, out[1]=%s)\n",
-        mtx.vJoinSplit.size(),
-        FormatMoney(vpub_old), FormatMoney(vpub_new),
-        FormatMoney(vjsin[0].note.value()), FormatMoney(vjsin[1].note.value()),
-        FormatMoney(vjsout[0].value), FormatMoney(vjsout[1].value));
 
-    uint256 esk; // payment disclosure - secret
+    LogPrint("zrpcunsafe", "CreateJSDescriptions: creating joinsplits for %d inputs and %d outputs\n",
+        jsInputs.size(), jsOutputs.size());
 
-    // Generate the proof, this can take over a minute.
+    // Generate the proofs, this can take over a minute for each JoinSplit.
     assert(mtx.fOverwintered && (mtx.nVersion >= SAPLING_TX_VERSION));
-    JSDescription jsdesc = JSDescriptionInfo(
-            mtx.joinSplitPubKey,
-            vjsin[0].witness.root(),
-            vjsin,
-            vjsout,
-            vpub_old,
-            vpub_new
-    ).BuildRandomized(
-            inputMap,
-            outputMap,
-            true, //!this->testmode,
-            &esk); // parameter expects pointer to esk, so pass in address
+    std::unique_ptr<SproutJoinSplitsPtr, decltype(&sprout_joinsplits_free)> joinSplits(
+        sprout_builder_build(builder.release()),
+        sprout_joinsplits_free);
+    if (joinSplits == nullptr) {
+        throw JSDescException("Failed to create Sprout JoinSplits");
+    }
+
+    auto verifier = ProofVerifier::Strict();
+    for (size_t i = 0; i < sprout_joinsplits_len(joinSplits.get()); i++) {
+        std::vector<unsigned char> encoded(SPROUT_JS_DESCRIPTION_SIZE);
+        sprout_joinsplits_get(joinSplits.get(), i, encoded.data());
+        CDataStream ss(encoded, SER_NETWORK, SAPLING_TX_VERSION | (1 << 31));
+        JSDescription jsdesc;
+        ss >> jsdesc;
 
-    {
-        auto verifier = ProofVerifier::Strict();
         if (!verifier.VerifySprout(jsdesc, mtx.joinSplitPubKey)) {
             throw std::runtime_error("error verifying joinsplit");
         }
-    }
 
-    mtx.vJoinSplit.push_back(jsdesc);
+        mtx.vJoinSplit.push_back(jsdesc);
+    }
 
     // TODO: Sprout payment disclosure
 }
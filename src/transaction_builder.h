@@ -172,8 +172,13 @@ public:
     /// this bundle must be discarded and a new bundle built. Subsequent usage of this
     /// object in any way will cause an exception. This emulates Rust's compile-time
     /// move semantics at runtime.
+    ///
+    /// The proof is created with `numThreads` threads, or with the default number
+    /// (`-par`, unless overridden at startup) if it is 0.
     std::optional<OrchardBundle> ProveAndSign(
-        const std::vector<libzcash::OrchardSpendingKey>& keys, uint256 sighash);
+        const std::vector<libzcash::OrchardSpendingKey>& keys,
+        uint256 sighash,
+        size_t numThreads = 0);
 };
 
 } // namespace orchard
@@ -260,6 +265,7 @@ private:
     CCriticalSection* cs_coinsView;
     CMutableTransaction mtx;
     CAmount fee = 10000;
+    size_t orchardProvingThreads = 0;
     std::optional<uint256> orchardAnchor;
     std::optional<orchard::Builder> orchardBuilder;
     CAmount valueBalanceOrchard = 0;
@@ -298,6 +304,7 @@ public:
         cs_coinsView(std::move(builder.cs_coinsView)),
         mtx(std::move(builder.mtx)),
         fee(std::move(builder.fee)),
+        orchardProvingThreads(std::move(builder.orchardProvingThreads)),
         orchardAnchor(std::move(builder.orchardAnchor)),
         orchardBuilder(std::move(builder.orchardBuilder)),
         valueBalanceOrchard(std::move(builder.valueBalanceOrchard)),
@@ -319,6 +326,7 @@ public:
             cs_coinsView = std::move(builder.cs_coinsView);
             mtx = std::move(builder.mtx);
             fee = std::move(builder.fee);
+            orchardProvingThreads = std::move(builder.orchardProvingThreads);
             orchardBuilder = std::move(builder.orchardBuilder);
             valueBalanceOrchard = std::move(builder.valueBalanceOrchard);
             spends = std::move(builder.spends);
@@ -337,6 +345,10 @@ public:
 
     void SetFee(CAmount fee);
 
+    // Sets the number of threads that the Orchard proof is created with (or 0
+    // for the default number).
+    void SetOrchardProvingThreads(size_t numThreads);
+
     bool SupportsOrchard() const;
 
     std::optional<uint256> GetOrchardAnchor() const;
@@ -391,14 +403,6 @@ private:
     void CheckOrSetUsingSprout();
 
     void CreateJSDescriptions();
-
-    void CreateJSDescription(
-        uint64_t vpub_old,
-        uint64_t vpub_new,
-        std::array<libzcash::JSInput, ZC_NUM_JS_INPUTS> vjsin,
-        std::array<libzcash::JSOutput, ZC_NUM_JS_OUTPUTS> vjsout,
-        std::array<size_t, ZC_NUM_JS_INPUTS>& inputMap,
-        std::array<size_t, ZC_NUM_JS_OUTPUTS>& outputMap);
 };
 
 #endif // ZCASH_TRANSACTION_BUILDER_H
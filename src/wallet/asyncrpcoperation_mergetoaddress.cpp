@@ -16,6 +16,7 @@
 #include "net.h"
 #include "netbase.h"
 #include "proof_verifier.h"
+#include "random.h"
 #include "rpc/protocol.h"
 #include "rpc/server.h"
 #include "script/interpreter.h"
@@ -401,7 +402,9 @@ bool AsyncRPCOperation_mergetoaddress::main_impl()
 
     // Prepare raw transaction to handle JoinSplits
     CMutableTransaction mtx(tx_);
-    ed25519_generate_keypair(&joinSplitPrivKey_, &joinSplitPubKey_);
+    if (!ed25519_generate_keypair(&joinSplitPrivKey_, &joinSplitPubKey_)) {
+        RandFailure();
+    }
     mtx.joinSplitPubKey = joinSplitPubKey_;
     tx_ = CTransaction(mtx);
 
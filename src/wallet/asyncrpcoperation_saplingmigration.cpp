@@ -3,7 +3,6 @@
 #include "init.h"
 #include "key_io.h"
 #include "rpc/protocol.h"
-#include "random.h"
 #include "sync.h"
 #include "tinyformat.h"
 #include "transaction_builder.h"
@@ -11,6 +10,8 @@
 #include "utilmoneystr.h"
 #include "wallet.h"
 
+#include <librustzcash.h>
+
 #include <optional>
 #include <variant>
 
@@ -188,17 +189,12 @@ void AsyncRPCOperation_saplingmigration::setMigrationResult(int numTxCreated, co
 }
 
 CAmount AsyncRPCOperation_saplingmigration::chooseAmount(const CAmount& availableFunds) {
-    CAmount amount = 0;
-    do {
-        // 1. Choose an integer exponent uniformly in the range 6 to 8 inclusive.
-        int exponent = GetRand(3) + 6;
-        // 2. Choose an integer mantissa uniformly in the range 1 to 99 inclusive.
-        uint64_t mantissa = GetRand(99) + 1;
-        // 3. Calculate amount := (mantissa * 10^exponent) zatoshi.
-        int pow = std::pow(10, exponent);
-        amount = mantissa * pow;
-        // 4. If amount is greater than the amount remaining to send, repeat from step 1.
-    } while (amount > availableFunds);
+    // The amount is chosen as specified in ZIP 308. We only migrate when at least
+    // 0.01 ZEC is available, so this can only fail if there is no randomness.
+    uint64_t amount;
+    if (!librustzcash_sprout_migration_amount(availableFunds, &amount)) {
+        throw JSONRPCError(RPC_WALLET_ERROR, "Could not choose a migration amount.");
+    }
     return amount;
 }
 
@@ -16,6 +16,7 @@
 #include "net.h"
 #include "netbase.h"
 #include "proof_verifier.h"
+#include "random.h"
 #include "rpc/protocol.h"
 #include "rpc/server.h"
 #include "transaction_builder.h"
@@ -255,7 +256,9 @@ bool ShieldToAddress::operator()(const libzcash::SproutPaymentAddress &zaddr) co
 
     // Prepare raw transaction to handle JoinSplits
     CMutableTransaction mtx(m_op->tx_);
-    ed25519_generate_keypair(&m_op->joinSplitPrivKey_, &m_op->joinSplitPubKey_);
+    if (!ed25519_generate_keypair(&m_op->joinSplitPrivKey_, &m_op->joinSplitPubKey_)) {
+        RandFailure();
+    }
     mtx.joinSplitPubKey = m_op->joinSplitPubKey_;
     m_op->tx_ = CTransaction(mtx);
 
@@ -16,6 +16,7 @@
 #include "net.h"
 #include "netbase.h"
 #include "proof_verifier.h"
+#include "random.h"
 #include "rpc/server.h"
 #include "timedata.h"
 #include "tinyformat.h"
@@ -3162,7 +3163,9 @@ UniValue zc_raw_joinsplit(const UniValue& params, bool fHelp)
 
     Ed25519VerificationKey joinSplitPubKey;
     Ed25519SigningKey joinSplitPrivKey;
-    ed25519_generate_keypair(&joinSplitPrivKey, &joinSplitPubKey);
+    if (!ed25519_generate_keypair(&joinSplitPrivKey, &joinSplitPubKey)) {
+        RandFailure();
+    }
 
     CMutableTransaction mtx(tx);
     mtx.nVersion = 4;
@@ -5295,6 +5298,7 @@ UniValue z_sendmany(const UniValue& params, bool fHelp)
         }
     }
     TransactionBuilder builder(chainparams.GetConsensus(), nextBlockHeight, orchardAnchor, pwalletMain);
+    builder.SetOrchardProvingThreads(std::max<int64_t>(GetArg("-orchardprovingthreads", DEFAULT_ORCHARD_PROVING_THREADS), 0));
 
     // Create operation and add to global queue
     std::shared_ptr<AsyncRPCQueue> q = getAsyncRPCQueue();
@@ -5662,6 +5666,7 @@ UniValue z_shieldcoinbase(const UniValue& params, bool fHelp)
     }
     TransactionBuilder builder = TransactionBuilder(
         Params().GetConsensus(), nextBlockHeight, orchardAnchor, pwalletMain);
+    builder.SetOrchardProvingThreads(std::max<int64_t>(GetArg("-orchardprovingthreads", DEFAULT_ORCHARD_PROVING_THREADS), 0));
 
     // Contextual transaction we will build on
     // (used if no Sapling addresses are involved)
@@ -6140,6 +6145,7 @@ UniValue z_mergetoaddress(const UniValue& params, bool fHelp)
             }
         }
         builder = TransactionBuilder(Params().GetConsensus(), nextBlockHeight, orchardAnchor, pwalletMain);
+        builder->SetOrchardProvingThreads(std::max<int64_t>(GetArg("-orchardprovingthreads", DEFAULT_ORCHARD_PROVING_THREADS), 0));
     }
     // Create operation and add to global queue
     std::shared_ptr<AsyncRPCQueue> q = getAsyncRPCQueue();
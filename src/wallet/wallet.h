@@ -88,6 +88,8 @@ static const unsigned int DEFAULT_ANCHOR_CONFIRMATIONS = 3;
 static const unsigned int DEFAULT_NOTE_CONFIRMATIONS = 10;
 //! -orchardactionlimit default
 static const unsigned int DEFAULT_ORCHARD_ACTION_LIMIT = 50;
+//! -orchardprovingthreads default, which uses the -par value
+static const unsigned int DEFAULT_ORCHARD_PROVING_THREADS = 0;
 
 extern const char * DEFAULT_WALLET_DAT;
 
@@ -176,6 +176,39 @@ HistoryEntry LeafToEntry(const HistoryNode node) {
     return result;
 }
 
+// Returns the length of the CompactSize that starts with the byte `first`.
+static size_t CompactSizeLength(unsigned char first) {
+    switch (first) {
+        case 253: return 3;
+        case 254: return 5;
+        case 255: return 9;
+        default: return 1;
+    }
+}
+
+size_t EntryLength(const HistoryEntry& entry, uint32_t epochId) {
+    // A leaf's entry starts with its kind, and any other node's also with the
+    // indices of its children.
+    size_t len = entry.bytes[0] == 1 ? 1 : 9;
+
+    // The node data has 144 bytes of fixed-length fields, followed by the
+    // start and end heights and the Sapling transaction count.
+    len += 144;
+    for (int i = 0; i < 3; i++) {
+        len += CompactSizeLength(entry.bytes[len]);
+    }
+
+    // V2 nodes then have the start and end Orchard roots and the Orchard
+    // transaction count. This can't run past the end of the entry, which has
+    // room for the longest encoding.
+    if (!IsV1HistoryTree(epochId)) {
+        len += 64;
+        len += CompactSizeLength(entry.bytes[len]);
+    }
+
+    return len;
+}
+
 bool IsV1HistoryTree(uint32_t epochId) {
     return (
         epochId == NetworkUpgradeInfo[Consensus::BASE_SPROUT].nBranchId ||
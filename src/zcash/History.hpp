@@ -73,6 +73,10 @@ HistoryEntry NodeToEntry(const HistoryNode node, uint32_t left, uint32_t right);
 // Convert history node to leaf node (end nodes without children)
 HistoryEntry LeafToEntry(const HistoryNode node);
 
+// Returns the length of the encoding in the entry, which is padded to fill its
+// HistoryEntry.
+size_t EntryLength(const HistoryEntry& entry, uint32_t epochId);
+
 // Returns true if this epoch used the V1 history tree format.
 bool IsV1HistoryTree(uint32_t epochId);
 
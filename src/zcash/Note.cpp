@@ -58,7 +58,9 @@ SaplingNote::SaplingNote(
         // Per ZIP 212, the rseed field is 32 random bytes.
         rseed = random_uint256();
     } else {
-        librustzcash_sapling_generate_r(rseed.begin());
+        if (!librustzcash_sapling_generate_r(rseed.begin())) {
+            RandFailure();
+        }
     }
 }
 
@@ -497,7 +499,9 @@ uint256 SaplingNotePlaintext::generate_or_derive_esk() const {
     } else {
         uint256 esk;
         // Pick random esk
-        librustzcash_sapling_generate_r(esk.begin());
+        if (!librustzcash_sapling_generate_r(esk.begin())) {
+            RandFailure();
+        }
         return esk;
     }
 }
@@ -0,0 +1,58 @@
+#!/usr/bin/env python3
+"""
+Extract the Sapling Spend and Output verifying keys from the parameter files,
+after checking their hashes, so that they can be embedded in librustzcash.
+
+Usage: extract-sapling-vks.py PARAMS_DIR OUT_DIR
+"""
+
+import hashlib
+import os
+import struct
+import sys
+
+# The BLAKE2b-512 hashes of the parameter files. These MUST match the hashes in
+# src/rust/src/groth16_params.rs.
+PARAMS = [
+    ('sapling-spend', '8270785a1a0d0bc77196f000ee6d221c9c9894f55307bd9357c3f0105d31ca63991ab91324160d8f53e2bbd3c2633a6eb8bdf5205d822e7f3f73edac51b2b70c'),
+    ('sapling-output', '657e3d38dbb5cb5e7dd2970e8b03d69b4787dd907285b5a7f0790dcc8072f60bf593b32cc2d1c030e00ff5ae64bf84c5c3beb84ddc841d48264b4a171744d028'),
+]
+
+# The length of the fixed-size prefix of an uncompressed Groth16 verifying key:
+# alpha_g1, beta_g1, beta_g2, gamma_g2, delta_g1 and delta_g2.
+VK_PREFIX_LEN = 96 + 96 + 192 + 192 + 96 + 192
+
+# The length of an uncompressed G1 element of ic.
+IC_ELEMENT_LEN = 96
+
+
+def extract_verifying_key(path, expected_hash):
+    with open(path, 'rb') as f:
+        params = f.read()
+
+    if hashlib.blake2b(params, digest_size=64).hexdigest() != expected_hash:
+        sys.exit('%s has an unexpected hash' % path)
+
+    if len(params) < VK_PREFIX_LEN + 4:
+        sys.exit('%s is truncated' % path)
+    (ic_len,) = struct.unpack('>I', params[VK_PREFIX_LEN:VK_PREFIX_LEN + 4])
+    vk_len = VK_PREFIX_LEN + 4 + ic_len * IC_ELEMENT_LEN
+    if len(params) < vk_len:
+        sys.exit('%s is truncated' % path)
+    return params[:vk_len]
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__.strip())
+    (params_dir, out_dir) = sys.argv[1:]
+
+    for (name, expected_hash) in PARAMS:
+        vk = extract_verifying_key(
+            os.path.join(params_dir, '%s.params' % name), expected_hash)
+        with open(os.path.join(out_dir, '%s.vk' % name), 'wb') as f:
+            f.write(vk)
+
+
+if __name__ == '__main__':
+    main()